@@ -0,0 +1,52 @@
+//! Cost of authenticating one request against 1,000 API keys
+//!
+//! Compares the key store with the linear scan of the configured keys it
+//! replaced, for a token matching the last key and for an unknown one.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use mcp_server_as_http_core::auth::KeyStore;
+use mcp_server_as_http_core::config::AuthConfig;
+use mcp_server_as_http_core::priority::RequestPriority;
+
+const KEYS: usize = 1_000;
+
+fn auth_config() -> AuthConfig {
+    AuthConfig {
+        api_key: None,
+        enabled: true,
+        default_priority: RequestPriority::Normal,
+        named_keys: (0..KEYS)
+            .map(|i| (format!("key-{}", i), format!("mcp-live-{:040}", i)))
+            .collect(),
+    }
+}
+
+/// The scan every request used to do
+fn linear_scan<'a>(auth_config: &'a AuthConfig, token: &str) -> Option<&'a str> {
+    auth_config
+        .named_keys
+        .iter()
+        .find(|(_, key)| key == token)
+        .map(|(name, _)| name.as_str())
+}
+
+fn bench_auth(c: &mut Criterion) {
+    let auth_config = auth_config();
+    let store = KeyStore::new(&auth_config).unwrap();
+    let last = format!("mcp-live-{:040}", KEYS - 1);
+    let unknown = format!("mcp-live-{:040}", KEYS);
+
+    let mut group = c.benchmark_group("auth_1k_keys");
+    for (label, token) in [("last_key", &last), ("unknown_key", &unknown)] {
+        group.bench_function(format!("linear_scan/{}", label), |b| {
+            b.iter(|| linear_scan(black_box(&auth_config), black_box(token)))
+        });
+        group.bench_function(format!("key_store/{}", label), |b| {
+            b.iter(|| store.identify(black_box(token)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_auth);
+criterion_main!(benches);
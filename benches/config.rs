@@ -0,0 +1,39 @@
+//! Cost of loading and validating a configuration of 5,000 servers
+//!
+//! The file is machine-generated, as for thousands of tenants; expanding
+//! the templates of every server is what `--print-config` does on top.
+
+#[path = "../tests/common/generated_config.rs"]
+mod generated_config;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use generated_config::generated_config;
+use mcp_server_as_http_core::config::McpServersConfig;
+
+fn bench_config(c: &mut Criterion) {
+    let path = std::env::temp_dir().join(format!("mcp-bench-config-{}.json", std::process::id()));
+    std::fs::write(&path, serde_json::to_vec(&generated_config()).unwrap()).unwrap();
+    let path = path.to_str().unwrap().to_string();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let config = runtime
+        .block_on(McpServersConfig::load_from_file(&path))
+        .unwrap();
+
+    let mut group = c.benchmark_group("config_5k_servers");
+    group.sample_size(10);
+    group.bench_function("load", |b| {
+        b.iter(|| {
+            runtime
+                .block_on(McpServersConfig::load_from_file(&path))
+                .unwrap()
+        })
+    });
+    group.bench_function("expand_templates", |b| {
+        b.iter(|| config.expand_templates(Some(8080)).unwrap())
+    });
+    group.finish();
+    let _ = std::fs::remove_file(&path);
+}
+
+criterion_group!(benches, bench_config);
+criterion_main!(benches);
@@ -0,0 +1,21 @@
+//! Embeds `git describe` of the gateway's own source tree as
+//! `MCP_GATEWAY_GIT_DESCRIBE`, or `unknown` outside a git checkout
+
+use std::process::Command;
+
+fn main() {
+    let describe = Command::new("git")
+        .args(["describe", "--tags", "--always", "--dirty"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|describe| !describe.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=MCP_GATEWAY_GIT_DESCRIBE={}", describe);
+
+    // Describe again when HEAD moves or the index changes
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
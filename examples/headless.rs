@@ -0,0 +1,51 @@
+//! Embedding example: run an MCP server without the HTTP gateway
+//!
+//! Builds without the `http-server` feature, as a desktop app would:
+//!
+//! ```bash
+//! MCP_CONFIG_FILE=mcp_servers.config.json MCP_SERVER_NAME=redmine \
+//!     cargo run --example headless --no-default-features
+//! ```
+
+use mcp_server_as_http_core::{
+    config::McpServersConfig, error::McpCoreResult, manager::ProcessManager,
+};
+
+#[tokio::main]
+async fn main() -> McpCoreResult<()> {
+    tracing_subscriber::fmt().init();
+
+    let config_file =
+        std::env::var("MCP_CONFIG_FILE").unwrap_or_else(|_| "mcp_servers.config.json".to_string());
+    let server_name = std::env::var("MCP_SERVER_NAME").unwrap_or_else(|_| "redmine".to_string());
+
+    let manager = ProcessManager::new(McpServersConfig::load_from_file(&config_file).await?);
+
+    // Report what happens to the server, restarts included
+    let mut events = manager.subscribe_events();
+    tokio::spawn(async move {
+        while let Ok(event) = events.recv().await {
+            println!(
+                "event: {}",
+                serde_json::to_string(&event).unwrap_or_default()
+            );
+        }
+    });
+
+    manager.start(&server_name).await?;
+    let tools = manager
+        .query(
+            &server_name,
+            r#"{"jsonrpc":"2.0","id":1,"method":"tools/list"}"#,
+        )
+        .await?;
+    println!("tools: {}", tools);
+
+    let status = manager.status(&server_name).await?;
+    println!(
+        "status: {}",
+        serde_json::to_string_pretty(&status).unwrap_or_default()
+    );
+
+    manager.stop(&server_name).await
+}
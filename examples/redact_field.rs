@@ -0,0 +1,49 @@
+//! Embedding example: strip an internal field from every MCP response
+//!
+//! Run with the same environment variables as the binary:
+//!
+//! ```bash
+//! MCP_CONFIG_FILE=mcp_servers.config.json MCP_SERVER_NAME=redmine \
+//!     cargo run --example redact_field
+//! ```
+
+use axum::http::StatusCode;
+use mcp_server_as_http_core::{
+    error::McpCoreResult,
+    hooks::{HookError, RequestContext},
+    http_server::McpHttpServer,
+};
+use serde_json::Value;
+
+#[tokio::main]
+async fn main() -> McpCoreResult<()> {
+    tracing_subscriber::fmt().init();
+
+    let config_file =
+        std::env::var("MCP_CONFIG_FILE").unwrap_or_else(|_| "mcp_servers.config.json".to_string());
+    let server_name = std::env::var("MCP_SERVER_NAME").unwrap_or_else(|_| "redmine".to_string());
+
+    let server = McpHttpServer::builder(&config_file, &server_name)
+        // Refuse tool calls from unauthenticated clients
+        .on_request(|message: Value, context: RequestContext| async move {
+            if context.method.as_deref() == Some("tools/call") && context.api_key_name.is_none() {
+                return Err(HookError::new(
+                    StatusCode::FORBIDDEN,
+                    "tools/call requires an API key",
+                ));
+            }
+            tracing::debug!("Forwarding request {:?}", message.get("id"));
+            Ok(message)
+        })
+        // Remove the internal `_meta` field from results
+        .on_response(|mut message: Value, _context: RequestContext| async move {
+            if let Some(result) = message.get_mut("result").and_then(Value::as_object_mut) {
+                result.remove("_meta");
+            }
+            message
+        })
+        .build()
+        .await?;
+
+    server.serve(3000).await
+}
@@ -0,0 +1,79 @@
+//! Embedding example: share application state with hooks and custom routes
+//!
+//! A directory of tool owners stands in for a database pool. The response
+//! hook joins every `tools/list` result with it, and a route of the app
+//! looks owners up from the same value.
+//!
+//! ```bash
+//! MCP_CONFIG_FILE=mcp_servers.config.json MCP_SERVER_NAME=redmine \
+//!     cargo run --example shared_state
+//! ```
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::{extract::Path, routing::get, Extension, Json, Router};
+use mcp_server_as_http_core::{
+    error::McpCoreResult, hooks::RequestContext, http_server::McpHttpServer,
+};
+use serde_json::Value;
+
+/// Owners by tool name; a real app would hold its database pool here
+#[derive(Clone, Default)]
+struct Owners(Arc<HashMap<String, String>>);
+
+impl Owners {
+    fn lookup(&self, tool: &str) -> Option<&str> {
+        self.0.get(tool).map(String::as_str)
+    }
+}
+
+#[tokio::main]
+async fn main() -> McpCoreResult<()> {
+    tracing_subscriber::fmt().init();
+
+    let config_file =
+        std::env::var("MCP_CONFIG_FILE").unwrap_or_else(|_| "mcp_servers.config.json".to_string());
+    let server_name = std::env::var("MCP_SERVER_NAME").unwrap_or_else(|_| "redmine".to_string());
+
+    let owners = Owners(Arc::new(HashMap::from([(
+        "search".to_string(),
+        "data-team".to_string(),
+    )])));
+
+    let server = McpHttpServer::builder(&config_file, &server_name)
+        .extension(owners)
+        // Add the owner of each listed tool
+        .on_response(|mut message: Value, context: RequestContext| async move {
+            let owners = context.extension::<Owners>();
+            let tools = message
+                .pointer_mut("/result/tools")
+                .and_then(Value::as_array_mut);
+            if let (Some(owners), Some(tools)) = (owners, tools) {
+                for tool in tools {
+                    let owner = tool["name"].as_str().and_then(|name| owners.lookup(name));
+                    if let Some(owner) = owner {
+                        tool["owner"] = Value::from(owner);
+                    }
+                }
+            }
+            message
+        })
+        .build()
+        .await?;
+
+    // Routes of the app take the same value from the server
+    let owners = server.extension::<Owners>().unwrap_or_default();
+    let app = Router::new()
+        .route("/app/owners/{tool}", get(owner_of))
+        .layer(Extension(owners))
+        .merge(server.create_router());
+
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn owner_of(Extension(owners): Extension<Owners>, Path(tool): Path<String>) -> Json<Value> {
+    Json(serde_json::json!({ "tool": tool, "owner": owners.lookup(&tool) }))
+}
@@ -0,0 +1,441 @@
+//! Access log written separately from the application log
+//!
+//! Every HTTP request is recorded once it completes, as a JSON line or a
+//! Common Log Format line, to stdout or a size-rotated file. Records are
+//! handed to a dedicated writer thread through a bounded channel, so a slow
+//! sink never blocks request handling; records are dropped (and counted)
+//! when the channel is full. The access log does not go through `tracing`
+//! and is unaffected by `RUST_LOG`.
+
+use crate::auth::ApiKeyName;
+use crate::error::{McpCoreError, McpCoreResult};
+use crate::rotating_file::RotatingFile;
+use axum::{
+    body::{Body, HttpBody},
+    extract::{Request, State},
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+/// Default size at which the access log file is rotated
+pub const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Default number of rotated files kept besides the current one
+pub const DEFAULT_MAX_FILES: usize = 5;
+
+/// Capacity of the live record channel; slower subscribers lag
+const EVENT_CAPACITY: usize = 256;
+
+/// Records buffered for the writer before new ones are dropped
+const CHANNEL_CAPACITY: usize = 4096;
+
+/// Header carrying the request id, accepted from clients and echoed back
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Fields of a JSON access log record
+pub const FIELDS: &[&str] = &[
+    "timestamp",
+    "request_id",
+    "method",
+    "path",
+    "status",
+    "latency_ms",
+    "bytes_out",
+    "api_key_name",
+    "rpc_method",
+    "rpc_id",
+    "error",
+];
+
+/// Where access log lines are written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessLogTarget {
+    Stdout,
+    /// A file rotated to `<path>.1` ... `<path>.<max_files>` at `max_bytes`
+    File {
+        path: PathBuf,
+        max_bytes: u64,
+        max_files: usize,
+    },
+}
+
+/// Line format of the access log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    /// One JSON object per line
+    Json,
+    /// Common Log Format followed by latency and request id
+    Clf,
+}
+
+/// Access log configuration
+#[derive(Debug, Clone)]
+pub struct AccessLogConfig {
+    pub target: AccessLogTarget,
+    pub format: AccessLogFormat,
+
+    /// JSON fields to include, all of [`FIELDS`] when `None`
+    pub fields: Option<Vec<String>>,
+}
+
+impl AccessLogConfig {
+    /// Read the configuration from `ACCESS_LOG*` environment variables
+    ///
+    /// Returns `None` when `ACCESS_LOG` is unset.
+    pub fn from_env() -> McpCoreResult<Option<Self>> {
+        let Ok(target) = std::env::var("ACCESS_LOG") else {
+            return Ok(None);
+        };
+        let invalid = |name: &str, value: &str| McpCoreError::ConfigurationError {
+            message: format!("Invalid {} '{}'", name, value),
+        };
+
+        let target = match target.as_str() {
+            "stdout" => AccessLogTarget::Stdout,
+            path => AccessLogTarget::File {
+                path: PathBuf::from(path),
+                max_bytes: match std::env::var("ACCESS_LOG_MAX_BYTES") {
+                    Ok(value) => value
+                        .parse()
+                        .map_err(|_| invalid("ACCESS_LOG_MAX_BYTES", &value))?,
+                    Err(_) => DEFAULT_MAX_BYTES,
+                },
+                max_files: match std::env::var("ACCESS_LOG_MAX_FILES") {
+                    Ok(value) => value
+                        .parse()
+                        .map_err(|_| invalid("ACCESS_LOG_MAX_FILES", &value))?,
+                    Err(_) => DEFAULT_MAX_FILES,
+                },
+            },
+        };
+        let format = match std::env::var("ACCESS_LOG_FORMAT").as_deref() {
+            Ok("json") | Err(_) => AccessLogFormat::Json,
+            Ok("clf") => AccessLogFormat::Clf,
+            Ok(other) => return Err(invalid("ACCESS_LOG_FORMAT", other)),
+        };
+        let fields = match std::env::var("ACCESS_LOG_FIELDS") {
+            Ok(value) => {
+                let fields: Vec<String> = value.split(',').map(|f| f.trim().to_string()).collect();
+                if let Some(unknown) = fields.iter().find(|f| !FIELDS.contains(&f.as_str())) {
+                    return Err(invalid("ACCESS_LOG_FIELDS entry", unknown));
+                }
+                Some(fields)
+            }
+            Err(_) => None,
+        };
+
+        Ok(Some(Self {
+            target,
+            format,
+            fields,
+        }))
+    }
+}
+
+/// Error message attached to error responses for the access log
+#[derive(Debug, Clone)]
+pub struct ErrorMessage(pub String);
+
+/// JSON-RPC method and id of the command, attached to responses for the
+/// access log
+#[derive(Debug, Clone)]
+pub struct RpcLabels {
+    pub method: String,
+    pub id: Option<String>,
+}
+
+/// One completed request
+#[derive(Debug, Clone)]
+pub struct AccessRecord {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub request_id: String,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub latency_ms: f64,
+    pub bytes_out: Option<u64>,
+    pub api_key_name: Option<String>,
+
+    /// JSON-RPC method of the command, [`crate::peek::UNPARSED`] if it could
+    /// not be read; unset for other endpoints
+    pub rpc_method: Option<String>,
+    pub rpc_id: Option<String>,
+    pub error: Option<String>,
+}
+
+impl AccessRecord {
+    /// JSON line with the selected fields
+    pub fn to_json(&self, fields: Option<&[String]>) -> String {
+        let record = self.to_value();
+        match fields {
+            Some(fields) => {
+                let selected: serde_json::Map<String, serde_json::Value> = fields
+                    .iter()
+                    .filter_map(|field| Some((field.clone(), record.get(field)?.clone())))
+                    .collect();
+                serde_json::Value::Object(selected).to_string()
+            }
+            None => record.to_string(),
+        }
+    }
+
+    /// All fields as a JSON object
+    pub fn to_value(&self) -> serde_json::Value {
+        serde_json::json!({
+            "timestamp": self.timestamp.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            "request_id": self.request_id,
+            "method": self.method,
+            "path": self.path,
+            "status": self.status,
+            "latency_ms": self.latency_ms,
+            "bytes_out": self.bytes_out,
+            "api_key_name": self.api_key_name,
+            "rpc_method": self.rpc_method,
+            "rpc_id": self.rpc_id,
+            "error": self.error,
+        })
+    }
+
+    /// Common Log Format line, followed by latency and request id
+    pub fn to_clf(&self) -> String {
+        format!(
+            "- - {} [{}] \"{} {} HTTP/1.1\" {} {} {:.1} {}",
+            self.api_key_name.as_deref().unwrap_or("-"),
+            self.timestamp.format("%d/%b/%Y:%H:%M:%S %z"),
+            self.method,
+            self.path,
+            self.status,
+            self.bytes_out
+                .map(|bytes| bytes.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            self.latency_ms,
+            self.request_id
+        )
+    }
+}
+
+/// Message to the writer thread
+enum WriterMessage {
+    Line(String),
+
+    /// Acknowledged once every earlier line is written
+    Flush(tokio::sync::oneshot::Sender<()>),
+}
+
+/// Handle for writing access log records
+#[derive(Clone)]
+pub struct AccessLog {
+    sender: SyncSender<WriterMessage>,
+    format: AccessLogFormat,
+    fields: Option<Arc<[String]>>,
+    next_request_id: Arc<AtomicU64>,
+    dropped: Arc<AtomicU64>,
+    events: broadcast::Sender<AccessRecord>,
+}
+
+impl AccessLog {
+    /// Open the target and start the writer thread
+    pub fn start(config: &AccessLogConfig) -> McpCoreResult<Self> {
+        let mut sink: Box<dyn Write + Send> = match &config.target {
+            AccessLogTarget::Stdout => Box::new(io::stdout()),
+            AccessLogTarget::File {
+                path,
+                max_bytes,
+                max_files,
+            } => Box::new(
+                RotatingFile::open(path, *max_bytes, *max_files).map_err(|e| {
+                    McpCoreError::ConfigurationError {
+                        message: format!("Failed to open access log '{}': {}", path.display(), e),
+                    }
+                })?,
+            ),
+        };
+
+        let (sender, receiver) = mpsc::sync_channel::<WriterMessage>(CHANNEL_CAPACITY);
+        std::thread::Builder::new()
+            .name("access-log".to_string())
+            .spawn(move || {
+                for message in receiver {
+                    let mut line = match message {
+                        WriterMessage::Line(line) => line,
+                        WriterMessage::Flush(done) => {
+                            let _ = done.send(());
+                            continue;
+                        }
+                    };
+                    // One write per line so rotation never splits a line
+                    line.push('\n');
+                    let written = sink.write_all(line.as_bytes()).and_then(|()| sink.flush());
+                    if let Err(e) = written {
+                        eprintln!("Failed to write access log: {}", e);
+                    }
+                }
+            })
+            .map_err(|e| McpCoreError::RuntimeError {
+                message: format!("Failed to start access log writer: {}", e),
+            })?;
+
+        Ok(Self {
+            sender,
+            format: config.format,
+            fields: config.fields.clone().map(Arc::from),
+            next_request_id: Arc::new(AtomicU64::new(1)),
+            dropped: Arc::new(AtomicU64::new(0)),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+        })
+    }
+
+    /// Queue a record without blocking; dropped if the writer is behind
+    pub fn log(&self, record: &AccessRecord) {
+        if self.events.receiver_count() > 0 {
+            let _ = self.events.send(record.clone());
+        }
+        let line = match self.format {
+            AccessLogFormat::Json => record.to_json(self.fields.as_deref()),
+            AccessLogFormat::Clf => record.to_clf(),
+        };
+        if let Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) =
+            self.sender.try_send(WriterMessage::Line(line))
+        {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            if dropped.is_power_of_two() {
+                tracing::warn!("Access log writer is behind, {} records dropped", dropped);
+            }
+        }
+    }
+
+    /// Wait until every record logged so far is written
+    pub async fn flush(&self) {
+        let (done, written) = tokio::sync::oneshot::channel();
+        let sender = self.sender.clone();
+        let queued =
+            tokio::task::spawn_blocking(move || sender.send(WriterMessage::Flush(done)).is_ok());
+        if matches!(queued.await, Ok(true)) {
+            let _ = written.await;
+        }
+    }
+
+    /// Receive every record logged from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<AccessRecord> {
+        self.events.subscribe()
+    }
+
+    /// Records dropped because the writer was behind
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn request_id(&self) -> String {
+        format!(
+            "req-{}",
+            self.next_request_id.fetch_add(1, Ordering::Relaxed)
+        )
+    }
+}
+
+/// Record every request in the access log
+///
+/// The request id is taken from `x-request-id` when the client sends one and
+/// echoed in the response.
+pub async fn access_log_middleware(
+    State(access_log): State<AccessLog>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let started = std::time::Instant::now();
+    let timestamp = chrono::Utc::now();
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(|| access_log.request_id());
+
+    let mut response = next.run(request).await;
+
+    let rpc_labels = response.extensions().get::<RpcLabels>();
+    access_log.log(&AccessRecord {
+        timestamp,
+        request_id: request_id.clone(),
+        method,
+        path,
+        status: response.status().as_u16(),
+        latency_ms: (started.elapsed().as_secs_f64() * 10_000.0).round() / 10.0,
+        bytes_out: response.body().size_hint().exact(),
+        api_key_name: response
+            .extensions()
+            .get::<ApiKeyName>()
+            .map(|name| name.0.clone()),
+        rpc_method: rpc_labels.map(|labels| labels.method.clone()),
+        rpc_id: rpc_labels.and_then(|labels| labels.id.clone()),
+        error: response
+            .extensions()
+            .get::<ErrorMessage>()
+            .map(|error| error.0.clone()),
+    });
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> AccessRecord {
+        AccessRecord {
+            timestamp: chrono::DateTime::parse_from_rfc3339("2025-01-02T03:04:05Z")
+                .unwrap()
+                .into(),
+            request_id: "req-7".to_string(),
+            method: "POST".to_string(),
+            path: "/api/v1".to_string(),
+            status: 504,
+            latency_ms: 30001.5,
+            bytes_out: Some(120),
+            api_key_name: Some("default".to_string()),
+            rpc_method: Some("tools/call".to_string()),
+            rpc_id: Some("3".to_string()),
+            error: Some("MCP server response timeout".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_json_lines_with_field_selection() {
+        let line: serde_json::Value = serde_json::from_str(&record().to_json(None)).unwrap();
+        assert_eq!(line["timestamp"], "2025-01-02T03:04:05.000Z");
+        assert_eq!(line["status"], 504);
+        assert_eq!(line["api_key_name"], "default");
+        assert_eq!(line["rpc_method"], "tools/call");
+        assert_eq!(line["rpc_id"], "3");
+        assert_eq!(line["error"], "MCP server response timeout");
+
+        let fields = ["method".to_string(), "status".to_string()];
+        let line: serde_json::Value =
+            serde_json::from_str(&record().to_json(Some(&fields))).unwrap();
+        assert_eq!(line, serde_json::json!({ "method": "POST", "status": 504 }));
+    }
+
+    #[test]
+    fn test_clf_line() {
+        let line = record().to_clf();
+        let (prefix, rest) = line.split_once(" [").unwrap();
+        assert_eq!(prefix, "- - default");
+        let (timestamp, rest) = rest.split_once("] ").unwrap();
+        assert_eq!(timestamp, "02/Jan/2025:03:04:05 +0000");
+        let (request, rest) = rest.trim_start_matches('"').split_once("\" ").unwrap();
+        assert_eq!(request, "POST /api/v1 HTTP/1.1");
+        let fields: Vec<&str> = rest.split(' ').collect();
+        assert_eq!(fields, ["504", "120", "30001.5", "req-7"]);
+    }
+}
@@ -0,0 +1,852 @@
+//! Administrative endpoints for inspecting the running MCP server
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{
+        sse::{Event, Sse},
+        Json,
+    },
+    Router,
+};
+use futures_util::Stream;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, OwnedSemaphorePermit};
+
+use crate::{
+    access_log::AccessRecord,
+    balance::Balance,
+    build_cache,
+    capture::{CaptureReport, CaptureRule, CaptureSummary},
+    error::{McpCoreError, McpCoreResult},
+    history::Trigger,
+    http_server::{self, ServerState},
+    listener::RouteGroup,
+    provision::RestartStrategy,
+    restart_budget::{RestartBudget, RestartBudgetConfig, RestartBudgetSnapshot},
+    routes::{Route, RouteTable},
+    stderr::StderrLine,
+    streaming::CloseReason,
+    workdir::{self, CleanupOptions, CleanupReport},
+};
+
+/// Concurrent `logs/stream` connections allowed
+pub const MAX_LOG_STREAMS: usize = 8;
+
+/// Query parameters for `POST /admin/cleanup`
+#[derive(Debug, Deserialize)]
+struct CleanupParams {
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(default)]
+    force: bool,
+    retention_days: Option<i64>,
+}
+
+/// Query parameters for `POST /admin/servers/{name}/drain`
+#[derive(Debug, Deserialize)]
+struct DrainParams {
+    /// Shown to clients whose requests are refused
+    message: Option<String>,
+}
+
+/// Query parameters for `POST /admin/servers/{name}/restart`
+#[derive(Debug, Deserialize)]
+struct RestartParams {
+    #[serde(default)]
+    strategy: RestartStrategy,
+}
+
+/// Query parameters for `GET /admin/servers/{name}/build-log`
+#[derive(Debug, Deserialize)]
+struct BuildLogParams {
+    /// Runs before the latest to show, below [`crate::build_log::RUNS_KEPT`]
+    #[serde(default)]
+    previous: usize,
+}
+
+/// Query parameters for `POST /admin/servers/{name}/canary/weight`
+#[derive(Debug, Deserialize)]
+struct WeightParams {
+    /// Percentage of requests sent to the canary
+    weight: u8,
+}
+
+/// Query parameters for `POST /admin/servers/{name}/replicas/balance`
+#[derive(Debug, Deserialize)]
+struct BalanceParams {
+    /// Strategy spreading requests over the replicas
+    balance: Balance,
+}
+
+/// Query parameters for `GET /admin/servers/{name}/logs/stream`
+#[derive(Debug, Deserialize)]
+struct LogStreamParams {
+    /// Comma-separated extra sources; only `access` is supported
+    include: Option<String>,
+
+    /// Replay captured stderr from this RFC 3339 time or duration ago (`30s`, `5m`, `1h`)
+    since: Option<String>,
+}
+
+/// Body of `POST /admin/restart-budget`
+#[derive(Debug, Deserialize)]
+struct RaiseBudget {
+    /// Limits during the raise; those left out stay as configured
+    per_minute: Option<u32>,
+    burst: Option<u32>,
+    duration_secs: u64,
+}
+
+/// Routes under `/admin`, sharing the server state and auth of the API
+pub fn admin_routes() -> Router<ServerState> {
+    let mut table = RouteTable::default();
+    table.add(RouteGroup::Admin, routes());
+    let (public, authenticated) = table.into_routers();
+    public.merge(authenticated)
+}
+
+/// Routes of the `admin` group
+pub(crate) fn routes() -> Vec<Route> {
+    vec![
+        Route::get(
+            "/admin/servers/{name}/inflight",
+            "List in-flight requests",
+            list_inflight,
+        ),
+        Route::post(
+            "/admin/servers/{name}/inflight/{id}/abort",
+            "Abort an in-flight request",
+            abort_inflight,
+        ),
+        Route::get(
+            "/admin/servers/{name}/stats",
+            "Show the server's rolling request statistics",
+            server_stats,
+        ),
+        Route::post(
+            "/admin/servers/{name}/rebuild",
+            "Invalidate the cached build so the next start rebuilds",
+            invalidate_build,
+        ),
+        Route::get(
+            "/admin/servers/{name}/logs/stream",
+            "Stream stderr and access log events as server-sent events",
+            stream_logs,
+        )
+        .streaming(),
+        Route::post(
+            "/admin/servers/{name}/drain",
+            "Refuse new requests for maintenance while in-flight ones finish",
+            drain_server,
+        ),
+        Route::post(
+            "/admin/servers/{name}/resume",
+            "Accept requests again after a drain",
+            resume_server,
+        ),
+        Route::post(
+            "/admin/servers/{name}/restart",
+            "Replace the server's child process, in place or blue-green",
+            restart_server,
+        ),
+        Route::get(
+            "/admin/servers/{name}/history",
+            "Show the last lifecycle transitions of the server's children",
+            server_history,
+        ),
+        Route::post(
+            "/admin/servers/{name}/provision",
+            "Clone, build, and start a server whose setup was deferred",
+            provision_server,
+        ),
+        Route::delete(
+            "/admin/servers/{name}/provision",
+            "Cancel the server's queued or running setup jobs",
+            abort_provisioning,
+        ),
+        Route::get(
+            "/admin/servers/{name}/provision/{job}",
+            "Show the progress of a provisioning job",
+            provision_job,
+        ),
+        Route::get(
+            "/admin/servers/{name}/build-log",
+            "Show the output of the server's latest clone and build",
+            build_log,
+        ),
+        Route::get(
+            "/admin/servers/{name}/canary",
+            "Show the canary's state, weight, and error rates per variant",
+            canary_status,
+        ),
+        Route::post(
+            "/admin/servers/{name}/canary/weight",
+            "Change the share of requests sent to the canary",
+            set_canary_weight,
+        ),
+        Route::post(
+            "/admin/servers/{name}/canary/promote",
+            "Make the canary the primary and retire the old child",
+            promote_canary,
+        ),
+        Route::post(
+            "/admin/servers/{name}/canary/abort",
+            "Stop routing to the canary and shut it down",
+            abort_canary,
+        ),
+        Route::get(
+            "/admin/servers/{name}/replicas",
+            "Show the server's replicas, their state and load, and the balancing strategy",
+            replicas_status,
+        ),
+        Route::post(
+            "/admin/servers/{name}/replicas/balance",
+            "Change how requests are spread over the server's replicas",
+            set_replica_balance,
+        ),
+        Route::post(
+            "/admin/cleanup",
+            "Remove orphaned work directories",
+            cleanup_work_dirs,
+        ),
+        Route::get(
+            "/admin/usage",
+            "List each API key's quota consumption and limits",
+            list_usage,
+        ),
+        Route::get(
+            "/admin/middleware",
+            "List the middleware in the order requests pass through it",
+            list_middleware,
+        ),
+        Route::get(
+            "/admin/restart-budget",
+            "Show the restart budget's limits, tokens, and waiting restarts",
+            restart_budget,
+        ),
+        Route::post(
+            "/admin/restart-budget",
+            "Raise the restart budget for a while",
+            raise_restart_budget,
+        ),
+        Route::post(
+            "/admin/capture",
+            "Capture the bodies of one method's exchanges for a while",
+            start_capture,
+        ),
+        Route::get(
+            "/admin/capture/{id}",
+            "Read a capture's redacted bodies",
+            read_capture,
+        ),
+        Route::delete("/admin/capture/{id}", "End a capture early", stop_capture),
+    ]
+}
+
+/// Ensure the path refers to the server managed by this gateway
+fn check_server_name(server_state: &ServerState, name: &str) -> McpCoreResult<()> {
+    if server_state.server_name == name {
+        Ok(())
+    } else {
+        Err(McpCoreError::NotFound {
+            message: format!("Server '{}' is not managed by this gateway", name),
+        })
+    }
+}
+
+/// List in-flight requests, longest-waiting first
+async fn list_inflight(
+    State(server_state): State<ServerState>,
+    Path(name): Path<String>,
+) -> McpCoreResult<Json<Value>> {
+    check_server_name(&server_state, &name)?;
+
+    let requests = server_state.inflight.snapshot();
+    let longest_waiting_ms = requests.first().map(|request| request.age_ms);
+    Ok(Json(serde_json::json!({
+        "server": name,
+        "count": requests.len(),
+        "longest_waiting_ms": longest_waiting_ms,
+        "requests": requests,
+    })))
+}
+
+/// Rolling request statistics of the named server
+async fn server_stats(
+    State(server_state): State<ServerState>,
+    Path(name): Path<String>,
+) -> McpCoreResult<Json<Value>> {
+    check_server_name(&server_state, &name)?;
+    Ok(Json(http_server::stats_body(&server_state)))
+}
+
+/// Abort a stuck in-flight request; its client receives 504
+async fn abort_inflight(
+    State(server_state): State<ServerState>,
+    Path((name, id)): Path<(String, u64)>,
+) -> McpCoreResult<Json<Value>> {
+    check_server_name(&server_state, &name)?;
+
+    if !server_state.inflight.abort(id) {
+        return Err(McpCoreError::NotFound {
+            message: format!("No abortable in-flight request with id {}", id),
+        });
+    }
+
+    tracing::warn!("Abort requested for in-flight request {}", id);
+    Ok(Json(serde_json::json!({
+        "server": name,
+        "id": id,
+        "aborted": true,
+    })))
+}
+
+/// Refuse new requests with `503` while admitted ones run to completion
+async fn drain_server(
+    State(server_state): State<ServerState>,
+    Path(name): Path<String>,
+    Query(params): Query<DrainParams>,
+) -> McpCoreResult<Json<Value>> {
+    check_server_name(&server_state, &name)?;
+
+    let mode = server_state.maintenance.drain(params.message).await;
+    Ok(Json(serde_json::json!({
+        "server": name,
+        "maintenance": mode,
+        "inflight": server_state.inflight.len(),
+    })))
+}
+
+/// Admit requests again after a drain
+async fn resume_server(
+    State(server_state): State<ServerState>,
+    Path(name): Path<String>,
+) -> McpCoreResult<Json<Value>> {
+    check_server_name(&server_state, &name)?;
+
+    let ended = server_state.maintenance.resume().await;
+    Ok(Json(serde_json::json!({
+        "server": name,
+        "resumed": ended.is_some(),
+    })))
+}
+
+/// Replace the server's child with a new one
+///
+/// With `?strategy=blue-green` the replacement is started while the old child
+/// serves; a replacement that fails to start leaves the old child running.
+async fn restart_server(
+    State(server_state): State<ServerState>,
+    Path(name): Path<String>,
+    Query(params): Query<RestartParams>,
+) -> McpCoreResult<Json<Value>> {
+    check_server_name(&server_state, &name)?;
+
+    let restart = server_state
+        .provisioner
+        .restart(params.strategy, Trigger::Admin, "admin")
+        .await?;
+    let pid = server_state
+        .provisioner
+        .provisioned()
+        .and_then(|provisioned| provisioned.pid);
+    Ok(Json(serde_json::json!({
+        "server": name,
+        "restart": restart,
+        "pid": pid,
+    })))
+}
+
+/// Recent lifecycle transitions of the server's children, oldest first
+async fn server_history(
+    State(server_state): State<ServerState>,
+    Path(name): Path<String>,
+) -> McpCoreResult<Json<Value>> {
+    check_server_name(&server_state, &name)?;
+    Ok(Json(serde_json::json!({
+        "server": name,
+        "history": server_state.provisioner.history().records(),
+    })))
+}
+
+/// Start cloning, building, and spawning a server that is not set up yet
+///
+/// Answers `202` with the job doing it, which may have been started by an
+/// earlier call, or `200` once the server is provisioned.
+async fn provision_server(
+    State(server_state): State<ServerState>,
+    Path(name): Path<String>,
+) -> McpCoreResult<(StatusCode, Json<Value>)> {
+    check_server_name(&server_state, &name)?;
+
+    let (job, started) = server_state.provisioner.provision(Trigger::Admin);
+    let status = server_state.provisioner.status();
+    let code = if status.state == "provisioned" {
+        StatusCode::OK
+    } else {
+        StatusCode::ACCEPTED
+    };
+    Ok((
+        code,
+        Json(serde_json::json!({
+            "server": name,
+            "state": status.state,
+            "started": started,
+            "job": job,
+        })),
+    ))
+}
+
+/// Cancel the setup jobs of a server, queued or running
+///
+/// A cancelled provisioning job fails and can be started again.
+async fn abort_provisioning(
+    State(server_state): State<ServerState>,
+    Path(name): Path<String>,
+) -> McpCoreResult<Json<Value>> {
+    check_server_name(&server_state, &name)?;
+
+    let cancelled = server_state.setup.cancel(&name);
+    Ok(Json(serde_json::json!({
+        "server": name,
+        "cancelled": cancelled,
+    })))
+}
+
+/// State, weight, and per-variant error rates of the server's canary
+async fn canary_status(
+    State(server_state): State<ServerState>,
+    Path(name): Path<String>,
+) -> McpCoreResult<Json<Value>> {
+    check_server_name(&server_state, &name)?;
+
+    let canary = server_state
+        .canary
+        .snapshot()
+        .ok_or_else(|| McpCoreError::NotFound {
+            message: format!("Server '{}' has no canary configured", name),
+        })?;
+    Ok(Json(serde_json::json!({
+        "server": name,
+        "canary": canary,
+    })))
+}
+
+/// Change the share of requests sent to the canary, effective immediately
+async fn set_canary_weight(
+    State(server_state): State<ServerState>,
+    Path(name): Path<String>,
+    Query(params): Query<WeightParams>,
+) -> McpCoreResult<Json<Value>> {
+    check_server_name(&server_state, &name)?;
+
+    server_state.canary.set_weight(params.weight)?;
+    Ok(Json(serde_json::json!({
+        "server": name,
+        "canary": server_state.canary.snapshot(),
+    })))
+}
+
+/// Make the canary the primary and shut down the old primary child
+async fn promote_canary(
+    State(server_state): State<ServerState>,
+    Path(name): Path<String>,
+) -> McpCoreResult<Json<Value>> {
+    check_server_name(&server_state, &name)?;
+
+    server_state
+        .canary
+        .promote(&server_state.transport, server_state.provisioner.history())
+        .await?;
+    Ok(Json(serde_json::json!({
+        "server": name,
+        "canary": server_state.canary.snapshot(),
+    })))
+}
+
+/// Send every request to the primary again and shut the canary down
+async fn abort_canary(
+    State(server_state): State<ServerState>,
+    Path(name): Path<String>,
+) -> McpCoreResult<Json<Value>> {
+    check_server_name(&server_state, &name)?;
+
+    server_state.canary.abort().await?;
+    Ok(Json(serde_json::json!({
+        "server": name,
+        "canary": server_state.canary.snapshot(),
+    })))
+}
+
+/// Replicas of the server and the strategy spreading requests over them
+async fn replicas_status(
+    State(server_state): State<ServerState>,
+    Path(name): Path<String>,
+) -> McpCoreResult<Json<Value>> {
+    check_server_name(&server_state, &name)?;
+
+    let replicas = server_state
+        .replicas
+        .snapshot()
+        .ok_or_else(|| no_replicas(&name))?;
+    Ok(Json(serde_json::json!({
+        "server": name,
+        "replicas": replicas,
+    })))
+}
+
+/// Change how requests are spread over the replicas, effective immediately
+async fn set_replica_balance(
+    State(server_state): State<ServerState>,
+    Path(name): Path<String>,
+    Query(params): Query<BalanceParams>,
+) -> McpCoreResult<Json<Value>> {
+    check_server_name(&server_state, &name)?;
+    if !server_state.replicas.is_replicated() {
+        return Err(no_replicas(&name));
+    }
+
+    server_state.replicas.set_balance(params.balance);
+    Ok(Json(serde_json::json!({
+        "server": name,
+        "replicas": server_state.replicas.snapshot(),
+    })))
+}
+
+fn no_replicas(name: &str) -> McpCoreError {
+    McpCoreError::NotFound {
+        message: format!("Server '{}' has no replicas configured", name),
+    }
+}
+
+/// Progress of a provisioning job
+async fn provision_job(
+    State(server_state): State<ServerState>,
+    Path((name, job)): Path<(String, String)>,
+) -> McpCoreResult<Json<Value>> {
+    check_server_name(&server_state, &name)?;
+
+    let job = server_state
+        .provisioner
+        .job(&job)
+        .ok_or_else(|| McpCoreError::NotFound {
+            message: format!("No provisioning job '{}'", job),
+        })?;
+    Ok(Json(serde_json::json!({
+        "server": name,
+        "state": server_state.provisioner.status().state,
+        "job": job,
+    })))
+}
+
+/// Commands, exit codes, and output of the server's latest clone and build
+async fn build_log(
+    State(server_state): State<ServerState>,
+    Path(name): Path<String>,
+    Query(params): Query<BuildLogParams>,
+) -> McpCoreResult<Json<Value>> {
+    check_server_name(&server_state, &name)?;
+
+    let run = server_state
+        .build_logs
+        .get(params.previous)
+        .await
+        .ok_or_else(|| McpCoreError::NotFound {
+            message: format!("Server '{}' has no build log", name),
+        })?;
+    Ok(Json(serde_json::json!({
+        "server": name,
+        "previous": params.previous,
+        "run": run,
+    })))
+}
+
+/// Invalidate the cached build so the next start runs the build command
+async fn invalidate_build(
+    State(server_state): State<ServerState>,
+    Path(name): Path<String>,
+) -> McpCoreResult<Json<Value>> {
+    check_server_name(&server_state, &name)?;
+
+    let work_dir = server_state.work_dir_base.join(&name);
+    let invalidated = build_cache::invalidate(&work_dir).await?;
+    tracing::info!("Build cache for '{}' invalidated", name);
+    Ok(Json(serde_json::json!({
+        "server": name,
+        "invalidated": invalidated,
+    })))
+}
+
+/// Remove work directories of unconfigured or expired servers
+async fn cleanup_work_dirs(
+    State(server_state): State<ServerState>,
+    Query(params): Query<CleanupParams>,
+) -> McpCoreResult<Json<CleanupReport>> {
+    let options = CleanupOptions {
+        retention: params.retention_days.map(chrono::Duration::days),
+        dry_run: params.dry_run,
+        force: params.force,
+    };
+
+    let report = workdir::cleanup(
+        &server_state.work_dir_base,
+        &server_state.configured_servers,
+        Some(&server_state.server_name),
+        &options,
+    )
+    .await?;
+    Ok(Json(report))
+}
+
+/// Quota consumption of every API key, by key name
+async fn list_usage(State(server_state): State<ServerState>) -> Json<Value> {
+    Json(serde_json::json!({
+        "server": server_state.server_name,
+        "keys": server_state.quotas.snapshot(chrono::Utc::now()),
+    }))
+}
+
+/// Body of `POST /admin/capture`
+#[derive(Debug, Deserialize)]
+struct StartCapture {
+    server: String,
+    #[serde(flatten)]
+    rule: CaptureRule,
+}
+
+/// Start capturing the bodies of a server's matching exchanges
+async fn start_capture(
+    State(server_state): State<ServerState>,
+    Json(body): Json<StartCapture>,
+) -> McpCoreResult<(StatusCode, Json<CaptureSummary>)> {
+    check_server_name(&server_state, &body.server)?;
+    let capture = server_state.captures.start(body.rule, chrono::Utc::now())?;
+    Ok((StatusCode::CREATED, Json(capture)))
+}
+
+/// A running capture with its redacted bodies
+async fn read_capture(
+    State(server_state): State<ServerState>,
+    Path(id): Path<String>,
+) -> McpCoreResult<Json<CaptureReport>> {
+    Ok(Json(server_state.captures.get(&id, chrono::Utc::now())?))
+}
+
+/// End a capture before it expires, dropping its bodies
+async fn stop_capture(
+    State(server_state): State<ServerState>,
+    Path(id): Path<String>,
+) -> McpCoreResult<Json<CaptureSummary>> {
+    Ok(Json(server_state.captures.stop(&id)?))
+}
+
+/// Restart budget shared across servers, see [`crate::restart_budget`]
+async fn restart_budget(
+    State(server_state): State<ServerState>,
+) -> McpCoreResult<Json<RestartBudgetSnapshot>> {
+    Ok(Json(configured_budget(&server_state)?.snapshot()))
+}
+
+/// Raise the restart budget for `duration_secs`
+async fn raise_restart_budget(
+    State(server_state): State<ServerState>,
+    Json(body): Json<RaiseBudget>,
+) -> McpCoreResult<Json<RestartBudgetSnapshot>> {
+    let budget = configured_budget(&server_state)?;
+    let configured = budget.configured();
+    let limits = RestartBudgetConfig {
+        per_minute: body.per_minute.unwrap_or(configured.per_minute),
+        burst: body.burst.unwrap_or(configured.burst),
+    };
+    let snapshot = budget
+        .raise(limits, Duration::from_secs(body.duration_secs))
+        .map_err(|reason| McpCoreError::RequestError {
+            message: format!("Restart budget {}", reason),
+        })?;
+    Ok(Json(snapshot))
+}
+
+fn configured_budget(server_state: &ServerState) -> McpCoreResult<&Arc<RestartBudget>> {
+    server_state
+        .provisioner
+        .restart_budget()
+        .ok_or_else(|| McpCoreError::NotFound {
+            message: "No restart_budget is configured".to_string(),
+        })
+}
+
+/// Middleware of the pipeline, outermost first
+async fn list_middleware(State(server_state): State<ServerState>) -> Json<Value> {
+    Json(serde_json::json!({
+        "server": server_state.server_name,
+        "configured": server_state.pipeline.is_configured(),
+        "layers": server_state
+            .pipeline
+            .describe(|layer| http_server::layer_applies(&server_state, layer)),
+    }))
+}
+
+/// Stream the server's stderr, and optionally its access log, as server-sent events
+///
+/// Each event carries its `type` and `timestamp`. A subscriber too slow to
+/// keep up receives a final `disconnected` event and the stream ends; the
+/// stderr reader never waits for it.
+async fn stream_logs(
+    State(server_state): State<ServerState>,
+    Path(name): Path<String>,
+    Query(params): Query<LogStreamParams>,
+) -> McpCoreResult<Sse<impl Stream<Item = Result<Event, std::convert::Infallible>>>> {
+    check_server_name(&server_state, &name)?;
+
+    let mut include_access = false;
+    for source in params.include.iter().flat_map(|include| include.split(',')) {
+        match source.trim() {
+            "access" => include_access = true,
+            "stderr" | "" => {}
+            other => {
+                return Err(McpCoreError::RequestError {
+                    message: format!("Unknown log source '{}'; expected 'access'", other),
+                })
+            }
+        }
+    }
+    let since = params.since.as_deref().map(parse_since).transpose()?;
+
+    let access = match (&server_state.access_log, include_access) {
+        (Some(access_log), true) => Some(access_log.subscribe()),
+        (None, true) => {
+            return Err(McpCoreError::RequestError {
+                message: "The access log is not enabled; set ACCESS_LOG to stream it".to_string(),
+            })
+        }
+        (_, false) => None,
+    };
+
+    let permit = server_state
+        .log_streams
+        .clone()
+        .try_acquire_owned()
+        .map_err(|_| McpCoreError::Overloaded {
+            message: format!("Too many log streams (limit {})", MAX_LOG_STREAMS),
+            retry_after_secs: 5,
+        })?;
+
+    let provisioned = server_state.provisioner.provisioned();
+    let (replay, stderr) = match provisioned
+        .as_ref()
+        .and_then(|provisioned| provisioned.stderr.as_ref())
+    {
+        Some(tail) => {
+            let (replay, receiver) = match since {
+                Some(since) => tail.subscribe_since(since),
+                None => (Vec::new(), tail.subscribe()),
+            };
+            (replay.iter().map(stderr_event).collect(), Some(receiver))
+        }
+        None => (VecDeque::new(), None),
+    };
+
+    let stream = LogStream {
+        replay,
+        stderr,
+        access,
+        finished: false,
+        _permit: permit,
+    };
+    let events = futures_util::stream::unfold(stream, |mut stream| async move {
+        if stream.finished {
+            return Some((Err(CloseReason::SlowConsumer), stream));
+        }
+        if let Some(event) = stream.replay.pop_front() {
+            return Some((Ok(event), stream));
+        }
+
+        let event = tokio::select! {
+            line = recv(&mut stream.stderr) => line.map(|line| stderr_event(&line)),
+            record = recv(&mut stream.access) => record.map(|record| access_event(&record)),
+        };
+        let event = match event {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                tracing::warn!("Disconnecting log stream that missed {} events", missed);
+                stream.finished = true;
+                Event::default().event("disconnected").data(
+                    serde_json::json!({
+                        "type": "disconnected",
+                        "timestamp": chrono::Utc::now(),
+                        "reason": "slow consumer",
+                        "missed": missed,
+                    })
+                    .to_string(),
+                )
+            }
+            Err(broadcast::error::RecvError::Closed) => return None,
+        };
+        Some((Ok(event), stream))
+    });
+
+    Ok(server_state.streams.sse(events))
+}
+
+/// State of one `logs/stream` connection
+struct LogStream {
+    replay: VecDeque<Event>,
+    stderr: Option<broadcast::Receiver<StderrLine>>,
+    access: Option<broadcast::Receiver<AccessRecord>>,
+    finished: bool,
+
+    /// Held for the life of the connection
+    _permit: OwnedSemaphorePermit,
+}
+
+/// Next value of an optional subscription; never resolves without one
+async fn recv<T: Clone>(
+    receiver: &mut Option<broadcast::Receiver<T>>,
+) -> Result<T, broadcast::error::RecvError> {
+    match receiver {
+        Some(receiver) => receiver.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+fn stderr_event(line: &StderrLine) -> Event {
+    let mut data = serde_json::json!(line);
+    data["type"] = "stderr".into();
+    Event::default().event("stderr").data(data.to_string())
+}
+
+fn access_event(record: &AccessRecord) -> Event {
+    let mut data = record.to_value();
+    data["type"] = "access".into();
+    Event::default().event("access").data(data.to_string())
+}
+
+/// Parse `since` as an RFC 3339 time or a duration ago such as `30s`, `5m`, `1h`
+fn parse_since(since: &str) -> McpCoreResult<chrono::DateTime<chrono::Utc>> {
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(since) {
+        return Ok(time.with_timezone(&chrono::Utc));
+    }
+
+    let invalid = || McpCoreError::RequestError {
+        message: format!(
+            "Invalid since '{}': expected an RFC 3339 time or a duration like 30s, 5m, 1h",
+            since
+        ),
+    };
+    let split = since.len().checked_sub(1).ok_or_else(invalid)?;
+    let (amount, unit) = since.split_at(split);
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+    let ago = match unit {
+        "s" => chrono::Duration::try_seconds(amount),
+        "m" => chrono::Duration::try_minutes(amount),
+        "h" => chrono::Duration::try_hours(amount),
+        _ => None,
+    }
+    .ok_or_else(invalid)?;
+    Ok(chrono::Utc::now() - ago)
+}
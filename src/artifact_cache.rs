@@ -0,0 +1,1217 @@
+//! Shared cache of cloned and built work directories
+//!
+//! Autoscaled instances would otherwise each clone and build the same
+//! repository. After a fresh clone or build the work directory is packed into
+//! a zstd-compressed tarball keyed by repository URL, commit, and build
+//! command, and stored with its SHA-256 checksum in a shared directory or an
+//! S3-compatible bucket. A start without a clone downloads and unpacks a
+//! matching artifact before falling back to clone and build.
+//!
+//! The cache is best-effort: failed downloads, uploads, and checksum
+//! mismatches are logged and counted, and never fail startup. Packing and
+//! unpacking require the `artifact-cache` feature; S3 also needs `reqwest`.
+
+use crate::child_env::ChildEnv;
+use crate::error::{McpCoreError, McpCoreResult};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Mutex;
+
+#[cfg(feature = "artifact-cache")]
+use crate::setup;
+#[cfg(feature = "artifact-cache")]
+use std::path::PathBuf;
+
+/// Paths left out of artifacts when `exclude` is not set
+const DEFAULT_EXCLUDE: &[&str] = &[
+    ".git/logs",
+    ".git/hooks",
+    ".DS_Store",
+    "__pycache__",
+    "node_modules/.cache",
+];
+
+/// Largest artifact uploaded when `max_size_mb` is not set, in MiB
+#[cfg(feature = "artifact-cache")]
+const DEFAULT_MAX_SIZE_MB: u64 = 1024;
+
+/// zstd level used when `compression_level` is not set
+#[cfg(feature = "artifact-cache")]
+const DEFAULT_COMPRESSION_LEVEL: i32 = 3;
+
+/// Artifact cache settings, top-level or per server
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ArtifactCacheConfig {
+    /// `false` opts a server out of the top-level cache
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+
+    /// Where artifacts are kept; required when enabled
+    #[serde(default)]
+    pub store: Option<ArtifactStoreConfig>,
+
+    /// Paths left out of artifacts: a name matches at any depth, a path with
+    /// `/` matches from the work directory root
+    #[serde(default = "default_exclude")]
+    pub exclude: Vec<String>,
+
+    /// Largest compressed artifact uploaded, in MiB (default 1024)
+    #[serde(default)]
+    pub max_size_mb: Option<u64>,
+
+    /// zstd compression level (default 3)
+    #[serde(default)]
+    pub compression_level: Option<i32>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_exclude() -> Vec<String> {
+    DEFAULT_EXCLUDE
+        .iter()
+        .map(|path| path.to_string())
+        .collect()
+}
+
+/// Storage shared between gateway instances
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ArtifactStoreConfig {
+    /// Directory every instance mounts, such as an NFS share
+    Directory { path: String },
+
+    /// S3-compatible bucket, addressed path-style
+    ///
+    /// Credentials come from `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`,
+    /// and optionally `AWS_SESSION_TOKEN`.
+    S3 {
+        endpoint: String,
+        bucket: String,
+        #[serde(default = "default_region")]
+        region: String,
+        /// Prepended to every object key, e.g. `mcp-artifacts/`
+        #[serde(default)]
+        prefix: String,
+    },
+}
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+impl ArtifactCacheConfig {
+    /// Reason the settings are unusable, if they are
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.store {
+            None if self.enabled => Err("artifact_cache is enabled but has no store".to_string()),
+            Some(ArtifactStoreConfig::Directory { path }) if path.is_empty() => {
+                Err("artifact_cache directory store has an empty path".to_string())
+            }
+            Some(ArtifactStoreConfig::S3 {
+                endpoint, bucket, ..
+            }) => {
+                if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
+                    Err(format!(
+                        "artifact_cache S3 endpoint '{}' must be an http or https URL",
+                        endpoint
+                    ))
+                } else if bucket.is_empty() {
+                    Err("artifact_cache S3 store has an empty bucket".to_string())
+                } else {
+                    Ok(())
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Cache outcomes since the gateway started
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ArtifactCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub uploads: u64,
+
+    /// Failed or oversized downloads and uploads
+    pub errors: u64,
+    pub bytes_downloaded: u64,
+    pub bytes_uploaded: u64,
+}
+
+/// Artifact cache of one server
+pub struct ArtifactCache {
+    #[cfg(feature = "artifact-cache")]
+    store: Box<dyn store::ArtifactStore>,
+    #[cfg(feature = "artifact-cache")]
+    exclude: Vec<String>,
+    #[cfg(feature = "artifact-cache")]
+    max_bytes: u64,
+    #[cfg(feature = "artifact-cache")]
+    compression_level: i32,
+    stats: Mutex<ArtifactCacheStats>,
+}
+
+impl ArtifactCache {
+    /// Open the configured store
+    #[cfg(feature = "artifact-cache")]
+    pub fn new(config: &ArtifactCacheConfig) -> McpCoreResult<Self> {
+        let store = config
+            .store
+            .as_ref()
+            .ok_or_else(|| McpCoreError::ConfigurationError {
+                message: "artifact_cache has no store".to_string(),
+            })?;
+        Ok(Self {
+            store: store::open(store)?,
+            exclude: config.exclude.clone(),
+            max_bytes: config.max_size_mb.unwrap_or(DEFAULT_MAX_SIZE_MB) * 1024 * 1024,
+            compression_level: config
+                .compression_level
+                .unwrap_or(DEFAULT_COMPRESSION_LEVEL),
+            stats: Mutex::new(ArtifactCacheStats::default()),
+        })
+    }
+
+    /// Open the configured store
+    ///
+    /// Unavailable without the `artifact-cache` feature.
+    #[cfg(not(feature = "artifact-cache"))]
+    pub fn new(_config: &ArtifactCacheConfig) -> McpCoreResult<Self> {
+        Err(McpCoreError::ConfigurationError {
+            message: "artifact_cache requires the 'artifact-cache' feature".to_string(),
+        })
+    }
+
+    /// Cache outcomes so far
+    pub fn stats(&self) -> ArtifactCacheStats {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ArtifactCacheStats> {
+        self.stats.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(feature = "artifact-cache")]
+impl ArtifactCache {
+    /// Unpack the artifact of the repository's commit into the empty `work_dir`
+    ///
+    /// The commit is `pinned_commit` or the remote's `HEAD`. Returns whether
+    /// an artifact was restored; on failure `work_dir` is emptied again.
+    pub async fn restore(
+        &self,
+        repository_url: &str,
+        pinned_commit: Option<&str>,
+        build_command: Option<&str>,
+        work_dir: &Path,
+        env: &ChildEnv,
+    ) -> bool {
+        let commit = match pinned_commit {
+            Some(commit) => commit.to_string(),
+            None => match remote_head(repository_url, env).await {
+                Some(commit) => commit,
+                None => {
+                    tracing::warn!("Could not resolve the remote HEAD, skipping artifact cache");
+                    self.lock().misses += 1;
+                    return false;
+                }
+            },
+        };
+        let key = artifact_key(repository_url, &commit, build_command);
+        let partial = temp_path(&key, "download");
+
+        let fetched = self.fetch(&key, &partial, work_dir).await;
+        remove_temp_files(&partial).await;
+        match fetched {
+            Ok(Some(bytes)) => {
+                tracing::info!(
+                    "Restored work dir from cached artifact {} ({} bytes from {})",
+                    key,
+                    bytes,
+                    self.store.describe()
+                );
+                let mut stats = self.lock();
+                stats.hits += 1;
+                stats.bytes_downloaded += bytes;
+                true
+            }
+            Ok(None) => {
+                tracing::info!("No cached artifact {}, cloning", key);
+                self.lock().misses += 1;
+                false
+            }
+            Err(e) => {
+                tracing::warn!("Failed to restore cached artifact {}, cloning: {}", key, e);
+                if let Err(e) = empty_dir(work_dir).await {
+                    tracing::warn!("Failed to clear '{}': {}", work_dir.display(), e);
+                }
+                let mut stats = self.lock();
+                stats.misses += 1;
+                stats.errors += 1;
+                false
+            }
+        }
+    }
+
+    /// Pack `work_dir` at its checked-out commit and upload it
+    pub async fn save(&self, repository_url: &str, build_command: Option<&str>, work_dir: &Path) {
+        let Some(commit) = crate::workdir::current_commit(work_dir).await else {
+            tracing::warn!("Work dir has no commit, not caching an artifact");
+            return;
+        };
+        let key = artifact_key(repository_url, &commit, build_command);
+        let partial = temp_path(&key, "upload");
+
+        let uploaded = self.upload(&key, &partial, work_dir).await;
+        remove_temp_files(&partial).await;
+        match uploaded {
+            Ok(bytes) => {
+                tracing::info!(
+                    "Cached artifact {} ({} bytes to {})",
+                    key,
+                    bytes,
+                    self.store.describe()
+                );
+                let mut stats = self.lock();
+                stats.uploads += 1;
+                stats.bytes_uploaded += bytes;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to cache artifact {}: {}", key, e);
+                self.lock().errors += 1;
+            }
+        }
+    }
+
+    /// Download and unpack an artifact, `None` if the store has none
+    async fn fetch(
+        &self,
+        key: &str,
+        partial: &Path,
+        work_dir: &Path,
+    ) -> McpCoreResult<Option<u64>> {
+        let checksum_path = partial.with_extension("sha256");
+        let Some(checksum_bytes) = self.store.get(&checksum_name(key), &checksum_path).await?
+        else {
+            return Ok(None);
+        };
+        let expected = read_file(&checksum_path).await?;
+        let Some(bytes) = self.store.get(&archive_name(key), partial).await? else {
+            return Ok(None);
+        };
+
+        let archive = partial.to_path_buf();
+        let work_dir = work_dir.to_path_buf();
+        let expected = expected.trim().to_string();
+        setup::blocking(move || {
+            let actual = archive::sha256_file(&archive)?;
+            if actual != expected {
+                return Err(runtime_error(format!(
+                    "checksum mismatch (expected {}, got {})",
+                    expected, actual
+                )));
+            }
+            archive::unpack(&archive, &work_dir)
+        })
+        .await?;
+        Ok(Some(bytes + checksum_bytes))
+    }
+
+    /// Pack and upload an artifact with its checksum, returning bytes sent
+    async fn upload(&self, key: &str, partial: &Path, work_dir: &Path) -> McpCoreResult<u64> {
+        let (archive, source) = (partial.to_path_buf(), work_dir.to_path_buf());
+        let (exclude, max_bytes, level) =
+            (self.exclude.clone(), self.max_bytes, self.compression_level);
+        let checksum =
+            setup::blocking(move || archive::pack(&source, &archive, &exclude, level, max_bytes))
+                .await?;
+
+        let checksum_path = partial.with_extension("sha256");
+        tokio::fs::write(&checksum_path, &checksum)
+            .await
+            .map_err(|e| runtime_error(format!("Failed to write checksum: {}", e)))?;
+        // The checksum goes last: readers look for it first
+        let archive_bytes = self.store.put(&archive_name(key), partial).await?;
+        let checksum_bytes = self.store.put(&checksum_name(key), &checksum_path).await?;
+        Ok(archive_bytes + checksum_bytes)
+    }
+}
+
+// Without the feature no cache can be opened, so these are never reached
+#[cfg(not(feature = "artifact-cache"))]
+impl ArtifactCache {
+    pub async fn restore(
+        &self,
+        _repository_url: &str,
+        _pinned_commit: Option<&str>,
+        _build_command: Option<&str>,
+        _work_dir: &Path,
+        _env: &ChildEnv,
+    ) -> bool {
+        false
+    }
+
+    pub async fn save(
+        &self,
+        _repository_url: &str,
+        _build_command: Option<&str>,
+        _work_dir: &Path,
+    ) {
+    }
+}
+
+/// Key of the artifact for `commit` of `repository_url` built with `build_command`
+pub fn artifact_key(repository_url: &str, commit: &str, build_command: Option<&str>) -> String {
+    let inputs = format!("{}\n{}", repository_url, build_command.unwrap_or_default());
+    format!(
+        "{}-{:016x}",
+        commit,
+        crate::build_cache::fnv1a(inputs.as_bytes())
+    )
+}
+
+/// Whether `path`, relative to the work directory, is left out of artifacts
+pub fn is_excluded(path: &Path, exclude: &[String]) -> bool {
+    let relative = path.to_string_lossy().replace('\\', "/");
+    // Files describing this instance, not the build
+    if relative == crate::workdir::META_FILE_NAME
+        || relative == crate::lifecycle::LIFECYCLE_FILE_NAME
+        || crate::build_log::is_log_file(path)
+    {
+        return true;
+    }
+    exclude.iter().any(|pattern| {
+        let pattern = pattern.trim_matches('/');
+        if pattern.contains('/') {
+            relative == pattern || relative.starts_with(&format!("{}/", pattern))
+        } else {
+            relative.split('/').any(|component| component == pattern)
+        }
+    })
+}
+
+#[cfg(feature = "artifact-cache")]
+fn archive_name(key: &str) -> String {
+    format!("{}.tar.zst", key)
+}
+
+#[cfg(feature = "artifact-cache")]
+fn checksum_name(key: &str) -> String {
+    format!("{}.tar.zst.sha256", key)
+}
+
+#[cfg(feature = "artifact-cache")]
+fn temp_path(key: &str, purpose: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "mcp-artifact-{}-{}-{}.partial",
+        key,
+        purpose,
+        std::process::id()
+    ))
+}
+
+/// Remove a partial archive and its checksum, if present
+#[cfg(feature = "artifact-cache")]
+async fn remove_temp_files(partial: &Path) {
+    let _ = tokio::fs::remove_file(partial).await;
+    let _ = tokio::fs::remove_file(partial.with_extension("sha256")).await;
+}
+
+/// Remove everything inside `dir`, keeping the directory
+#[cfg(feature = "artifact-cache")]
+async fn empty_dir(dir: &Path) -> std::io::Result<()> {
+    tokio::fs::remove_dir_all(dir).await?;
+    tokio::fs::create_dir_all(dir).await
+}
+
+#[cfg(feature = "artifact-cache")]
+async fn read_file(path: &Path) -> McpCoreResult<String> {
+    tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| runtime_error(format!("Failed to read '{}': {}", path.display(), e)))
+}
+
+/// Commit of the remote's `HEAD`, without cloning
+#[cfg(feature = "artifact-cache")]
+async fn remote_head(repository_url: &str, env: &ChildEnv) -> Option<String> {
+    let mut command = tokio::process::Command::new("git");
+    command.args(["ls-remote", repository_url, "HEAD"]);
+    env.apply(&mut command);
+    command
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(std::process::Stdio::null());
+    let output = command.output().await.ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .next()
+        .map(str::to_string)
+}
+
+#[cfg(feature = "artifact-cache")]
+fn runtime_error(message: String) -> McpCoreError {
+    McpCoreError::RuntimeError { message }
+}
+
+/// Packing and unpacking of zstd-compressed tarballs
+#[cfg(feature = "artifact-cache")]
+mod archive {
+    use super::{is_excluded, runtime_error};
+    use crate::error::McpCoreResult;
+    use sha2::{Digest, Sha256};
+    use std::io::{Read, Write};
+    use std::path::Path;
+
+    /// Pack `source` into `dest`, returning the archive's SHA-256
+    ///
+    /// Fails once the compressed archive grows past `max_bytes`.
+    pub fn pack(
+        source: &Path,
+        dest: &Path,
+        exclude: &[String],
+        level: i32,
+        max_bytes: u64,
+    ) -> McpCoreResult<String> {
+        let io_error = |e: std::io::Error| runtime_error(format!("Failed to pack artifact: {}", e));
+        let file = std::fs::File::create(dest).map_err(io_error)?;
+        let writer = HashingWriter {
+            inner: file,
+            hasher: Sha256::new(),
+            written: 0,
+            max_bytes,
+        };
+        let encoder = zstd::Encoder::new(writer, level).map_err(io_error)?;
+        let mut builder = tar::Builder::new(encoder);
+        builder.follow_symlinks(false);
+        append_dir(&mut builder, source, Path::new(""), exclude).map_err(io_error)?;
+        let writer = builder
+            .into_inner()
+            .and_then(|encoder| encoder.finish())
+            .map_err(io_error)?;
+        let HashingWriter {
+            mut inner, hasher, ..
+        } = writer;
+        inner.flush().map_err(io_error)?;
+        Ok(hex(&hasher.finalize()))
+    }
+
+    /// Append the entries below `root/relative`, sorted by name
+    fn append_dir<W: Write>(
+        builder: &mut tar::Builder<W>,
+        root: &Path,
+        relative: &Path,
+        exclude: &[String],
+    ) -> std::io::Result<()> {
+        let mut entries =
+            std::fs::read_dir(root.join(relative))?.collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let path = relative.join(entry.file_name());
+            if is_excluded(&path, exclude) {
+                continue;
+            }
+            if entry.file_type()?.is_dir() {
+                builder.append_dir(&path, entry.path())?;
+                append_dir(builder, root, &path, exclude)?;
+            } else {
+                builder.append_path_with_name(entry.path(), &path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Unpack `archive` into `dest`; entries escaping `dest` are refused
+    pub fn unpack(archive: &Path, dest: &Path) -> McpCoreResult<()> {
+        let io_error =
+            |e: std::io::Error| runtime_error(format!("Failed to unpack artifact: {}", e));
+        let file = std::fs::File::open(archive).map_err(io_error)?;
+        let decoder = zstd::Decoder::new(file).map_err(io_error)?;
+        let mut archive = tar::Archive::new(decoder);
+        archive.set_preserve_permissions(true);
+        archive.set_preserve_mtime(true);
+        archive.unpack(dest).map_err(io_error)
+    }
+
+    /// SHA-256 of a file, hex-encoded
+    pub fn sha256_file(path: &Path) -> McpCoreResult<String> {
+        let io_error = |e: std::io::Error| {
+            runtime_error(format!("Failed to hash '{}': {}", path.display(), e))
+        };
+        let mut file = std::fs::File::open(path).map_err(io_error)?;
+        let mut hasher = Sha256::new();
+        let mut buffer = [0u8; 64 * 1024];
+        loop {
+            let read = file.read(&mut buffer).map_err(io_error)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+        Ok(hex(&hasher.finalize()))
+    }
+
+    pub fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// Writer hashing and counting what passes through, up to a limit
+    struct HashingWriter<W> {
+        inner: W,
+        hasher: Sha256,
+        written: u64,
+        max_bytes: u64,
+    }
+
+    impl<W: Write> Write for HashingWriter<W> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let written = self.inner.write(buf)?;
+            self.written += written as u64;
+            if self.written > self.max_bytes {
+                return Err(std::io::Error::other(format!(
+                    "artifact exceeds {} MiB",
+                    self.max_bytes / (1024 * 1024)
+                )));
+            }
+            self.hasher.update(&buf[..written]);
+            Ok(written)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.inner.flush()
+        }
+    }
+}
+
+/// Backends holding the artifacts
+#[cfg(feature = "artifact-cache")]
+mod store {
+    use super::{runtime_error, ArtifactStoreConfig};
+    use crate::error::McpCoreResult;
+    use async_trait::async_trait;
+    use std::path::{Path, PathBuf};
+
+    /// Object storage addressed by name
+    #[async_trait]
+    pub trait ArtifactStore: Send + Sync {
+        /// Download `name` into `dest`, returning its size, `None` if absent
+        async fn get(&self, name: &str, dest: &Path) -> McpCoreResult<Option<u64>>;
+
+        /// Upload `source` as `name`, returning its size
+        async fn put(&self, name: &str, source: &Path) -> McpCoreResult<u64>;
+
+        /// Location shown in logs
+        fn describe(&self) -> String;
+    }
+
+    pub fn open(config: &ArtifactStoreConfig) -> McpCoreResult<Box<dyn ArtifactStore>> {
+        match config {
+            ArtifactStoreConfig::Directory { path } => Ok(Box::new(DirectoryStore {
+                root: PathBuf::from(path),
+            })),
+            #[cfg(feature = "reqwest")]
+            ArtifactStoreConfig::S3 {
+                endpoint,
+                bucket,
+                region,
+                prefix,
+            } => Ok(Box::new(super::s3::S3Store::new(
+                endpoint, bucket, region, prefix,
+            )?)),
+            #[cfg(not(feature = "reqwest"))]
+            ArtifactStoreConfig::S3 { .. } => Err(crate::error::McpCoreError::ConfigurationError {
+                message: "The S3 artifact store requires the 'reqwest' feature".to_string(),
+            }),
+        }
+    }
+
+    /// Directory shared between instances
+    ///
+    /// Uploads are copied under a temporary name and renamed, so readers
+    /// never see a partial artifact.
+    pub struct DirectoryStore {
+        pub root: PathBuf,
+    }
+
+    #[async_trait]
+    impl ArtifactStore for DirectoryStore {
+        async fn get(&self, name: &str, dest: &Path) -> McpCoreResult<Option<u64>> {
+            let path = self.root.join(name);
+            match tokio::fs::copy(&path, dest).await {
+                Ok(bytes) => Ok(Some(bytes)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(runtime_error(format!(
+                    "Failed to copy '{}': {}",
+                    path.display(),
+                    e
+                ))),
+            }
+        }
+
+        async fn put(&self, name: &str, source: &Path) -> McpCoreResult<u64> {
+            let path = self.root.join(name);
+            let partial = self
+                .root
+                .join(format!("{}.partial-{}", name, std::process::id()));
+            let copied = async {
+                tokio::fs::create_dir_all(&self.root).await?;
+                let bytes = tokio::fs::copy(source, &partial).await?;
+                tokio::fs::rename(&partial, &path).await?;
+                Ok::<_, std::io::Error>(bytes)
+            }
+            .await;
+            copied.map_err(|e| {
+                let _ = std::fs::remove_file(&partial);
+                runtime_error(format!("Failed to store '{}': {}", path.display(), e))
+            })
+        }
+
+        fn describe(&self) -> String {
+            self.root.display().to_string()
+        }
+    }
+}
+
+/// S3-compatible object store with Signature Version 4 authentication
+#[cfg(all(feature = "artifact-cache", feature = "reqwest"))]
+mod s3 {
+    use super::archive::hex;
+    use super::runtime_error;
+    use super::store::ArtifactStore;
+    use crate::error::{McpCoreError, McpCoreResult};
+    use async_trait::async_trait;
+    use chrono::{DateTime, Utc};
+    use sha2::{Digest, Sha256};
+    use std::path::Path;
+    use tokio::io::AsyncWriteExt;
+
+    /// Payload hash sent instead of hashing the body
+    const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+    #[derive(Clone)]
+    pub struct Credentials {
+        pub access_key_id: String,
+        pub secret_access_key: String,
+        pub session_token: Option<String>,
+    }
+
+    pub struct S3Store {
+        client: reqwest::Client,
+        endpoint: reqwest::Url,
+        bucket: String,
+        region: String,
+        prefix: String,
+        credentials: Credentials,
+    }
+
+    impl S3Store {
+        pub fn new(
+            endpoint: &str,
+            bucket: &str,
+            region: &str,
+            prefix: &str,
+        ) -> McpCoreResult<Self> {
+            let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+            let (Some(access_key_id), Some(secret_access_key)) =
+                (var("AWS_ACCESS_KEY_ID"), var("AWS_SECRET_ACCESS_KEY"))
+            else {
+                return Err(McpCoreError::ConfigurationError {
+                    message:
+                        "The S3 artifact store needs AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY"
+                            .to_string(),
+                });
+            };
+            let endpoint =
+                reqwest::Url::parse(endpoint).map_err(|e| McpCoreError::ConfigurationError {
+                    message: format!("Invalid S3 endpoint '{}': {}", endpoint, e),
+                })?;
+            Ok(Self {
+                client: reqwest::Client::new(),
+                endpoint,
+                bucket: bucket.to_string(),
+                region: region.to_string(),
+                prefix: prefix.to_string(),
+                credentials: Credentials {
+                    access_key_id,
+                    secret_access_key,
+                    session_token: var("AWS_SESSION_TOKEN"),
+                },
+            })
+        }
+
+        /// Signed request for the object `name`
+        fn request(&self, method: reqwest::Method, name: &str) -> reqwest::RequestBuilder {
+            let path = format!(
+                "{}/{}/{}{}",
+                self.endpoint.path().trim_end_matches('/'),
+                self.bucket,
+                self.prefix,
+                name
+            );
+            let path = uri_encode(&path);
+            let mut url = self.endpoint.clone();
+            url.set_path(&path);
+            let host = match url.port() {
+                Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+                None => url.host_str().unwrap_or_default().to_string(),
+            };
+            let headers = sign(
+                &self.credentials,
+                &self.region,
+                method.as_str(),
+                &host,
+                &path,
+                Utc::now(),
+            );
+            headers.into_iter().fold(
+                self.client.request(method, url),
+                |request, (name, value)| request.header(name, value),
+            )
+        }
+    }
+
+    #[async_trait]
+    impl ArtifactStore for S3Store {
+        async fn get(&self, name: &str, dest: &Path) -> McpCoreResult<Option<u64>> {
+            let request_error = |e: reqwest::Error| {
+                runtime_error(format!("S3 download of '{}' failed: {}", name, e))
+            };
+            let mut response = self
+                .request(reqwest::Method::GET, name)
+                .send()
+                .await
+                .map_err(request_error)?;
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
+                return Ok(None);
+            }
+            if !response.status().is_success() {
+                return Err(runtime_error(format!(
+                    "S3 download of '{}' returned HTTP {}",
+                    name,
+                    response.status()
+                )));
+            }
+
+            let io_error = |e: std::io::Error| {
+                runtime_error(format!("Failed to write '{}': {}", dest.display(), e))
+            };
+            let mut file = tokio::fs::File::create(dest).await.map_err(io_error)?;
+            let mut bytes = 0;
+            while let Some(chunk) = response.chunk().await.map_err(request_error)? {
+                file.write_all(&chunk).await.map_err(io_error)?;
+                bytes += chunk.len() as u64;
+            }
+            file.flush().await.map_err(io_error)?;
+            Ok(Some(bytes))
+        }
+
+        async fn put(&self, name: &str, source: &Path) -> McpCoreResult<u64> {
+            let io_error = |e: std::io::Error| {
+                runtime_error(format!("Failed to read '{}': {}", source.display(), e))
+            };
+            let file = tokio::fs::File::open(source).await.map_err(io_error)?;
+            let bytes = file.metadata().await.map_err(io_error)?.len();
+            let response = self
+                .request(reqwest::Method::PUT, name)
+                .header(reqwest::header::CONTENT_LENGTH, bytes)
+                .body(reqwest::Body::from(file))
+                .send()
+                .await
+                .map_err(|e| runtime_error(format!("S3 upload of '{}' failed: {}", name, e)))?;
+            if !response.status().is_success() {
+                return Err(runtime_error(format!(
+                    "S3 upload of '{}' returned HTTP {}",
+                    name,
+                    response.status()
+                )));
+            }
+            Ok(bytes)
+        }
+
+        fn describe(&self) -> String {
+            format!("s3://{}/{}", self.bucket, self.prefix)
+        }
+    }
+
+    /// Headers authenticating a request with an unsigned payload
+    pub fn sign(
+        credentials: &Credentials,
+        region: &str,
+        method: &str,
+        host: &str,
+        path: &str,
+        now: DateTime<Utc>,
+    ) -> Vec<(&'static str, String)> {
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+
+        let mut headers = vec![
+            ("host", host.to_string()),
+            ("x-amz-content-sha256", UNSIGNED_PAYLOAD.to_string()),
+            ("x-amz-date", amz_date.clone()),
+        ];
+        if let Some(token) = &credentials.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+            .collect();
+        let canonical_request = format!(
+            "{}\n{}\n\n{}\n{}\n{}",
+            method, path, canonical_headers, signed_headers, UNSIGNED_PAYLOAD
+        );
+
+        let scope = format!("{}/{}/s3/aws4_request", date, region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = signing_key(&credentials.secret_access_key, &date, region, "s3");
+        let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+
+        headers.retain(|(name, _)| *name != "host");
+        headers.push((
+            "authorization",
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                credentials.access_key_id, scope, signed_headers, signature
+            ),
+        ));
+        headers
+    }
+
+    /// Key for signing requests to `service` in `region` on `date`
+    pub fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+        let key = hmac_sha256(format!("AWS4{}", secret).as_bytes(), date.as_bytes());
+        let key = hmac_sha256(&key, region.as_bytes());
+        let key = hmac_sha256(&key, service.as_bytes());
+        hmac_sha256(&key, b"aws4_request")
+    }
+
+    /// HMAC-SHA256 (RFC 2104)
+    pub fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+        const BLOCK_SIZE: usize = 64;
+        let mut block = [0u8; BLOCK_SIZE];
+        if key.len() > BLOCK_SIZE {
+            block[..32].copy_from_slice(&Sha256::digest(key));
+        } else {
+            block[..key.len()].copy_from_slice(key);
+        }
+        let pad = |byte: u8| block.iter().map(|b| b ^ byte).collect::<Vec<_>>();
+        let inner = Sha256::new()
+            .chain_update(pad(0x36))
+            .chain_update(message)
+            .finalize();
+        Sha256::new()
+            .chain_update(pad(0x5c))
+            .chain_update(inner)
+            .finalize()
+            .to_vec()
+    }
+
+    /// Percent-encode a path, keeping unreserved characters and `/`
+    fn uri_encode(path: &str) -> String {
+        path.bytes()
+            .map(|byte| match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                    (byte as char).to_string()
+                }
+                _ => format!("%{:02X}", byte),
+            })
+            .collect()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_hmac_sha256() {
+            // RFC 4231, test case 2
+            let mac = hmac_sha256(b"Jefe", b"what do ya want for nothing?");
+            assert_eq!(
+                hex(&mac),
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+            );
+        }
+
+        #[test]
+        fn test_signing_key() {
+            // Example from the AWS Signature Version 4 documentation
+            let key = signing_key(
+                "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+                "20120215",
+                "us-east-1",
+                "iam",
+            );
+            assert_eq!(
+                hex(&key),
+                "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+            );
+        }
+
+        #[test]
+        fn test_sign_headers() {
+            let credentials = Credentials {
+                access_key_id: "AKIDEXAMPLE".to_string(),
+                secret_access_key: "secret".to_string(),
+                session_token: Some("token".to_string()),
+            };
+            let now = DateTime::parse_from_rfc3339("2025-01-02T03:04:05Z")
+                .unwrap()
+                .with_timezone(&Utc);
+            let headers = sign(
+                &credentials,
+                "eu-west-1",
+                "GET",
+                "s3.local:9000",
+                "/b/k",
+                now,
+            );
+            let header = |name: &str| {
+                headers
+                    .iter()
+                    .find(|(n, _)| *n == name)
+                    .map(|(_, v)| v.clone())
+                    .unwrap()
+            };
+            assert_eq!(header("x-amz-date"), "20250102T030405Z");
+            assert_eq!(header("x-amz-security-token"), "token");
+            let authorization = header("authorization");
+            assert!(authorization.starts_with(
+                "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20250102/eu-west-1/s3/aws4_request, \
+                 SignedHeaders=host;x-amz-content-sha256;x-amz-date;x-amz-security-token, Signature="
+            ));
+            assert!(!headers.iter().any(|(name, _)| *name == "host"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exclude_patterns() {
+        let exclude = default_exclude();
+        assert!(is_excluded(Path::new(".git/logs/HEAD"), &exclude));
+        assert!(is_excluded(Path::new("src/__pycache__/a.pyc"), &exclude));
+        assert!(is_excluded(Path::new(".mcp-meta.json"), &exclude));
+        assert!(!is_excluded(Path::new(".git/HEAD"), &exclude));
+        assert!(!is_excluded(Path::new("logs/app.log"), &exclude));
+        assert!(!is_excluded(Path::new(".mcp-build-stamp.json"), &exclude));
+    }
+
+    #[test]
+    fn test_artifact_key() {
+        let key = artifact_key("https://example.com/repo.git", "abc123", Some("npm ci"));
+        assert!(key.starts_with("abc123-"));
+        assert_ne!(
+            key,
+            artifact_key(
+                "https://example.com/repo.git",
+                "abc123",
+                Some("npm install")
+            )
+        );
+        assert_ne!(
+            key,
+            artifact_key("https://example.com/fork.git", "abc123", Some("npm ci"))
+        );
+    }
+
+    #[test]
+    fn test_store_required_when_enabled() {
+        let config: ArtifactCacheConfig = serde_json::from_str(r#"{"enabled": false}"#).unwrap();
+        assert!(config.validate().is_ok());
+        let config: ArtifactCacheConfig = serde_json::from_str("{}").unwrap();
+        assert!(config.validate().unwrap_err().contains("no store"));
+        let config: ArtifactCacheConfig = serde_json::from_str(
+            r#"{"store": {"type": "s3", "endpoint": "minio:9000", "bucket": "b"}}"#,
+        )
+        .unwrap();
+        assert!(config.validate().unwrap_err().contains("http or https"));
+    }
+
+    #[cfg(feature = "artifact-cache")]
+    fn cache(root: &Path) -> ArtifactCache {
+        let config: ArtifactCacheConfig = serde_json::from_value(serde_json::json!({
+            "store": { "type": "directory", "path": root.join("store") }
+        }))
+        .unwrap();
+        ArtifactCache::new(&config).unwrap()
+    }
+
+    #[cfg(feature = "artifact-cache")]
+    async fn git(dir: &Path, args: &[&str]) {
+        let status = tokio::process::Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .stdout(std::process::Stdio::null())
+            .status()
+            .await
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[cfg(feature = "artifact-cache")]
+    #[tokio::test]
+    async fn test_save_and_restore_through_directory_store() {
+        let root = std::env::temp_dir().join(format!("mcp-artifact-cache-{}", std::process::id()));
+        let _ = tokio::fs::remove_dir_all(&root).await;
+        let built = root.join("built");
+        tokio::fs::create_dir_all(built.join("dist")).await.unwrap();
+        tokio::fs::create_dir_all(built.join("__pycache__"))
+            .await
+            .unwrap();
+        tokio::fs::write(built.join("dist/index.js"), "built")
+            .await
+            .unwrap();
+        tokio::fs::write(built.join("__pycache__/x.pyc"), "junk")
+            .await
+            .unwrap();
+        tokio::fs::write(built.join(".mcp-meta.json"), "{}")
+            .await
+            .unwrap();
+        git(&built, &["init", "--quiet"]).await;
+        git(
+            &built,
+            &[
+                "-c",
+                "user.name=t",
+                "-c",
+                "user.email=t@t",
+                "commit",
+                "--quiet",
+                "--allow-empty",
+                "-m",
+                "init",
+            ],
+        )
+        .await;
+        let commit = crate::workdir::current_commit(&built).await.unwrap();
+
+        let cache = cache(&root);
+        let url = "https://example.com/repo.git";
+        cache.save(url, Some("make"), &built).await;
+        let stats = cache.stats();
+        assert_eq!(stats.uploads, 1);
+        assert!(stats.bytes_uploaded > 0);
+
+        let fresh = root.join("fresh");
+        tokio::fs::create_dir_all(&fresh).await.unwrap();
+        let env = crate::child_env::ChildEnv::default();
+        assert!(
+            cache
+                .restore(url, Some(&commit), Some("make"), &fresh, &env)
+                .await
+        );
+        assert_eq!(
+            tokio::fs::read_to_string(fresh.join("dist/index.js"))
+                .await
+                .unwrap(),
+            "built"
+        );
+        assert!(!fresh.join("__pycache__").exists());
+        assert!(!fresh.join(".mcp-meta.json").exists());
+        assert_eq!(
+            crate::workdir::current_commit(&fresh).await,
+            Some(commit.clone())
+        );
+
+        // Another build command misses
+        let other = root.join("other");
+        tokio::fs::create_dir_all(&other).await.unwrap();
+        assert!(
+            !cache
+                .restore(url, Some(&commit), Some("make all"), &other, &env)
+                .await
+        );
+
+        // A corrupted artifact is refused and the work dir emptied
+        let key = artifact_key(url, &commit, Some("make"));
+        tokio::fs::write(root.join("store").join(archive_name(&key)), "garbage")
+            .await
+            .unwrap();
+        let corrupt = root.join("corrupt");
+        tokio::fs::create_dir_all(&corrupt).await.unwrap();
+        assert!(
+            !cache
+                .restore(url, Some(&commit), Some("make"), &corrupt, &env)
+                .await
+        );
+        assert_eq!(std::fs::read_dir(&corrupt).unwrap().count(), 0);
+        assert!(!temp_path(&key, "download").exists());
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.errors), (1, 2, 1));
+        assert!(stats.bytes_downloaded > 0);
+        let _ = tokio::fs::remove_dir_all(&root).await;
+    }
+
+    #[cfg(feature = "artifact-cache")]
+    #[tokio::test]
+    async fn test_oversized_artifact_not_uploaded() {
+        let root = std::env::temp_dir().join(format!("mcp-artifact-size-{}", std::process::id()));
+        let _ = tokio::fs::remove_dir_all(&root).await;
+        let built = root.join("built");
+        tokio::fs::create_dir_all(&built).await.unwrap();
+        git(&built, &["init", "--quiet"]).await;
+        git(
+            &built,
+            &[
+                "-c",
+                "user.name=t",
+                "-c",
+                "user.email=t@t",
+                "commit",
+                "--quiet",
+                "--allow-empty",
+                "-m",
+                "init",
+            ],
+        )
+        .await;
+        // Incompressible content past the limit
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let noise: Vec<u8> = (0..2 * 1024 * 1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        tokio::fs::write(built.join("blob"), noise).await.unwrap();
+
+        let config: ArtifactCacheConfig = serde_json::from_value(serde_json::json!({
+            "store": { "type": "directory", "path": root.join("store") },
+            "max_size_mb": 1
+        }))
+        .unwrap();
+        let cache = ArtifactCache::new(&config).unwrap();
+        cache
+            .save("https://example.com/repo.git", None, &built)
+            .await;
+
+        let stats = cache.stats();
+        assert_eq!((stats.uploads, stats.errors), (0, 1));
+        assert!(
+            !root.join("store").exists()
+                || std::fs::read_dir(root.join("store")).unwrap().count() == 0
+        );
+        let _ = tokio::fs::remove_dir_all(&root).await;
+    }
+}
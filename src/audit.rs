@@ -0,0 +1,605 @@
+//! Dependency audit of cloned code before it runs
+//!
+//! With `audit` enabled, the dependencies of a server are audited after the
+//! build with the tool of its ecosystem: `npm audit`, `pip-audit`, or
+//! `govulncheck`. Findings are counted by severity and startup fails when any
+//! finding reaches `fail_on`; below it they are only logged. The report is
+//! cached next to the build stamp and reused until the stamp changes, so
+//! restarts do not audit again.
+//!
+//! `pip-audit` and `govulncheck` report no severities; their findings count
+//! as `unknown`, which is gated like `high`.
+
+use crate::build_cache::BuildStamp;
+use crate::child_env::ChildEnv;
+use crate::config::RuntimeConfig;
+use crate::diagnostics::find_executable;
+use crate::error::{McpCoreError, McpCoreResult};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::Duration;
+
+/// Name of the audit report written into each work directory
+pub const REPORT_FILE_NAME: &str = ".mcp-audit.json";
+
+/// Time an audit tool may run before it counts as failed
+const AUDIT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Per-server audit settings
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AuditConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+
+    /// Lowest severity that fails startup; `null` only logs findings
+    #[serde(default = "default_fail_on")]
+    pub fail_on: Option<Severity>,
+
+    /// Fail startup instead of warning when the audit tool is missing or fails
+    #[serde(default)]
+    pub require_audit_tool: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_fail_on() -> Option<Severity> {
+    Some(Severity::High)
+}
+
+/// Severity of a finding, ordered from least to most severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Low,
+    #[serde(alias = "medium")]
+    Moderate,
+    High,
+    Critical,
+}
+
+/// Number of findings per severity
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SeverityCounts {
+    pub critical: u64,
+    pub high: u64,
+    pub moderate: u64,
+    pub low: u64,
+    pub info: u64,
+
+    /// Findings the tool reported without a severity
+    pub unknown: u64,
+}
+
+impl SeverityCounts {
+    pub fn total(&self) -> u64 {
+        self.critical + self.high + self.moderate + self.low + self.info + self.unknown
+    }
+
+    /// Findings at or above `threshold`, counting unknown severities as high
+    pub fn at_or_above(&self, threshold: Severity) -> u64 {
+        [
+            (Severity::Critical, self.critical),
+            (Severity::High, self.high + self.unknown),
+            (Severity::Moderate, self.moderate),
+            (Severity::Low, self.low),
+            (Severity::Info, self.info),
+        ]
+        .into_iter()
+        .filter(|(severity, _)| *severity >= threshold)
+        .map(|(_, count)| count)
+        .sum()
+    }
+}
+
+/// Package ecosystem of a work directory and its audit tool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ecosystem {
+    Npm,
+    Python,
+    Go,
+}
+
+impl Ecosystem {
+    /// Ecosystem of `work_dir` by its manifests, else by the runtime configuration
+    pub fn detect(work_dir: &Path, runtime: &RuntimeConfig) -> Option<Self> {
+        let has = |name: &str| work_dir.join(name).is_file();
+        if has("package.json") {
+            Some(Self::Npm)
+        } else if has("go.mod") {
+            Some(Self::Go)
+        } else if has("requirements.txt") || has("pyproject.toml") || has("setup.py") {
+            Some(Self::Python)
+        } else if runtime.node.is_some() {
+            Some(Self::Npm)
+        } else if runtime.python.is_some() {
+            Some(Self::Python)
+        } else if runtime.go.is_some() {
+            Some(Self::Go)
+        } else {
+            None
+        }
+    }
+
+    /// Executable of the audit tool
+    pub fn program(&self) -> &'static str {
+        match self {
+            Self::Npm => "npm",
+            Self::Python => "pip-audit",
+            Self::Go => "govulncheck",
+        }
+    }
+
+    fn args(&self, work_dir: &Path) -> Vec<&'static str> {
+        match self {
+            Self::Npm => vec!["audit", "--json"],
+            Self::Python if work_dir.join("requirements.txt").is_file() => {
+                vec!["--format", "json", "-r", "requirements.txt"]
+            }
+            Self::Python => vec!["--format", "json", "."],
+            Self::Go => vec!["-json", "./..."],
+        }
+    }
+
+    /// Count the findings in the tool's JSON output
+    pub fn parse(&self, output: &str) -> Result<SeverityCounts, String> {
+        match self {
+            Self::Npm => parse_npm(output),
+            Self::Python => parse_pip_audit(output),
+            Self::Go => parse_govulncheck(output),
+        }
+    }
+}
+
+/// Outcome of the last audit of a work directory
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AuditReport {
+    /// Audit tool, or `none` when no ecosystem was recognized
+    pub tool: String,
+    pub audited_at: DateTime<Utc>,
+    pub counts: SeverityCounts,
+
+    /// Why no audit ran, if none did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<String>,
+}
+
+impl AuditReport {
+    fn skipped(tool: &str, reason: String) -> Self {
+        Self {
+            tool: tool.to_string(),
+            audited_at: Utc::now(),
+            counts: SeverityCounts::default(),
+            skipped: Some(reason),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct CachedReport {
+    stamp: BuildStamp,
+    report: AuditReport,
+}
+
+/// Audit `work_dir` unless its cached report is current, then apply the gate
+pub async fn run(
+    config: &AuditConfig,
+    work_dir: &Path,
+    build_command: Option<&str>,
+    runtime: &RuntimeConfig,
+    env: &ChildEnv,
+) -> McpCoreResult<AuditReport> {
+    let Some(ecosystem) = Ecosystem::detect(work_dir, runtime) else {
+        tracing::warn!("Skipping audit, no npm, Python, or Go project found");
+        let report = AuditReport::skipped("none", "no supported ecosystem".to_string());
+        write_report(work_dir, None, &report).await;
+        return Ok(report);
+    };
+    let program = ecosystem.program();
+
+    if find_executable(program).is_none() {
+        let reason = format!("'{}' not found on PATH", program);
+        if config.require_audit_tool {
+            return Err(McpCoreError::ProcessError {
+                message: format!("Audit required but {}", reason),
+            });
+        }
+        tracing::warn!("Skipping audit, {}", reason);
+        let report = AuditReport::skipped(program, reason);
+        write_report(work_dir, None, &report).await;
+        return Ok(report);
+    }
+
+    let stamp = BuildStamp::compute(work_dir, build_command.unwrap_or_default(), &[program]).await;
+    let report = match read_cached(work_dir).await {
+        Some(cached) if cached.report.skipped.is_none() && cached.stamp == stamp => {
+            tracing::info!("Reusing audit report, stamp matches the previous audit");
+            cached.report
+        }
+        _ => {
+            let report = match audit(ecosystem, work_dir, env).await {
+                Ok(counts) => AuditReport {
+                    tool: program.to_string(),
+                    audited_at: Utc::now(),
+                    counts,
+                    skipped: None,
+                },
+                Err(reason) if config.require_audit_tool => {
+                    return Err(McpCoreError::ProcessError {
+                        message: format!("Audit with '{}' failed: {}", program, reason),
+                    })
+                }
+                Err(reason) => {
+                    tracing::warn!("Audit with '{}' failed: {}", program, reason);
+                    AuditReport::skipped(program, reason)
+                }
+            };
+            write_report(work_dir, Some(stamp), &report).await;
+            report
+        }
+    };
+
+    check(config, &report)?;
+    Ok(report)
+}
+
+/// Fail when findings reach the threshold, else log them
+pub fn check(config: &AuditConfig, report: &AuditReport) -> McpCoreResult<()> {
+    let counts = &report.counts;
+    if report.skipped.is_some() {
+        return Ok(());
+    }
+    let summary = format!(
+        "{} critical, {} high, {} moderate, {} low, {} info, {} unknown",
+        counts.critical, counts.high, counts.moderate, counts.low, counts.info, counts.unknown
+    );
+    if let Some(threshold) = config.fail_on {
+        let failing = counts.at_or_above(threshold);
+        if failing > 0 {
+            return Err(McpCoreError::ProcessError {
+                message: format!(
+                    "Audit with '{}' found {} vulnerabilities at or above {:?} ({})",
+                    report.tool, failing, threshold, summary
+                ),
+            });
+        }
+    }
+    if counts.total() > 0 {
+        tracing::warn!(
+            "Audit with '{}' found vulnerabilities: {}",
+            report.tool,
+            summary
+        );
+    } else {
+        tracing::info!("Audit with '{}' found no vulnerabilities", report.tool);
+    }
+    Ok(())
+}
+
+/// The last report written into `work_dir`, if any
+pub async fn read_report(work_dir: &Path) -> Option<AuditReport> {
+    read_cached(work_dir).await.map(|cached| cached.report)
+}
+
+async fn read_cached(work_dir: &Path) -> Option<CachedReport> {
+    let content = tokio::fs::read_to_string(work_dir.join(REPORT_FILE_NAME))
+        .await
+        .ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Record `report`; a report without a stamp is never reused
+async fn write_report(work_dir: &Path, stamp: Option<BuildStamp>, report: &AuditReport) {
+    let cached = CachedReport {
+        stamp: stamp.unwrap_or(BuildStamp {
+            commit: None,
+            build_command_hash: String::new(),
+            runtime_version: None,
+            lockfile_hash: None,
+        }),
+        report: report.clone(),
+    };
+    let path = work_dir.join(REPORT_FILE_NAME);
+    let written = match serde_json::to_string_pretty(&cached) {
+        Ok(json) => tokio::fs::write(&path, json)
+            .await
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    if let Err(e) = written {
+        tracing::warn!("Failed to write audit report '{}': {}", path.display(), e);
+    }
+}
+
+/// Run the audit tool and count its findings
+///
+/// The tools exit non-zero when they find vulnerabilities, so the exit status
+/// is ignored as long as the output parses.
+async fn audit(
+    ecosystem: Ecosystem,
+    work_dir: &Path,
+    env: &ChildEnv,
+) -> Result<SeverityCounts, String> {
+    let mut command = tokio::process::Command::new(ecosystem.program());
+    command.args(ecosystem.args(work_dir));
+    env.apply(&mut command);
+    command
+        .current_dir(work_dir)
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true);
+
+    tracing::info!("Auditing dependencies with '{}'", ecosystem.program());
+    let output = tokio::time::timeout(AUDIT_TIMEOUT, command.output())
+        .await
+        .map_err(|_| format!("timed out after {:?}", AUDIT_TIMEOUT))?
+        .map_err(|e| e.to_string())?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    ecosystem.parse(&stdout).map_err(|e| {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        match stderr.trim() {
+            "" => e,
+            stderr => format!("{} ({})", e, env.redact(stderr)),
+        }
+    })
+}
+
+/// `npm audit --json`: counts under `metadata.vulnerabilities`
+fn parse_npm(output: &str) -> Result<SeverityCounts, String> {
+    let value: Value =
+        serde_json::from_str(output).map_err(|e| format!("invalid npm audit output: {}", e))?;
+    if let Some(error) = value.get("error") {
+        let summary = error["summary"].as_str().or(error["code"].as_str());
+        return Err(format!(
+            "npm audit failed: {}",
+            summary.unwrap_or("unknown error")
+        ));
+    }
+    let counts = value
+        .pointer("/metadata/vulnerabilities")
+        .ok_or("npm audit output has no metadata.vulnerabilities")?;
+    let count = |severity: &str| counts[severity].as_u64().unwrap_or(0);
+    Ok(SeverityCounts {
+        critical: count("critical"),
+        high: count("high"),
+        moderate: count("moderate"),
+        low: count("low"),
+        info: count("info"),
+        unknown: 0,
+    })
+}
+
+/// `pip-audit --format json`: one finding per entry in `dependencies[].vulns`
+fn parse_pip_audit(output: &str) -> Result<SeverityCounts, String> {
+    let value: Value =
+        serde_json::from_str(output).map_err(|e| format!("invalid pip-audit output: {}", e))?;
+    // Older releases print the dependency list without the surrounding object
+    let dependencies = value
+        .get("dependencies")
+        .unwrap_or(&value)
+        .as_array()
+        .ok_or("pip-audit output has no dependencies")?;
+    let unknown = dependencies
+        .iter()
+        .filter_map(|dependency| dependency["vulns"].as_array())
+        .map(|vulns| vulns.len() as u64)
+        .sum();
+    Ok(SeverityCounts {
+        unknown,
+        ..SeverityCounts::default()
+    })
+}
+
+/// `govulncheck -json`: one finding per vulnerability whose code is called
+///
+/// The output is a stream of JSON objects. Findings whose trace stops at the
+/// module or package are imported but unreachable and are not counted.
+fn parse_govulncheck(output: &str) -> Result<SeverityCounts, String> {
+    let mut called = HashSet::new();
+    for message in serde_json::Deserializer::from_str(output).into_iter::<Value>() {
+        let message = message.map_err(|e| format!("invalid govulncheck output: {}", e))?;
+        let Some(finding) = message.get("finding") else {
+            continue;
+        };
+        let reaches_function = finding["trace"][0]["function"].is_string();
+        if let (Some(id), true) = (finding["osv"].as_str(), reaches_function) {
+            called.insert(id.to_string());
+        }
+    }
+    Ok(SeverityCounts {
+        unknown: called.len() as u64,
+        ..SeverityCounts::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_npm_audit() {
+        let output = r#"{
+            "auditReportVersion": 2,
+            "vulnerabilities": {},
+            "metadata": {
+                "vulnerabilities": {
+                    "info": 0, "low": 2, "moderate": 1, "high": 1, "critical": 0, "total": 4
+                },
+                "dependencies": {"prod": 120, "total": 300}
+            }
+        }"#;
+        let counts = Ecosystem::Npm.parse(output).unwrap();
+        assert_eq!(counts.low, 2);
+        assert_eq!(counts.moderate, 1);
+        assert_eq!(counts.high, 1);
+        assert_eq!(counts.total(), 4);
+        assert_eq!(counts.at_or_above(Severity::High), 1);
+        assert_eq!(counts.at_or_above(Severity::Moderate), 2);
+        assert_eq!(counts.at_or_above(Severity::Critical), 0);
+
+        let error = r#"{"error": {"code": "ENOLOCK", "summary": "This command requires an existing lockfile."}}"#;
+        assert!(Ecosystem::Npm
+            .parse(error)
+            .unwrap_err()
+            .contains("lockfile"));
+    }
+
+    #[test]
+    fn test_parse_pip_audit() {
+        let output = r#"{
+            "dependencies": [
+                {"name": "requests", "version": "2.19.0", "vulns": [
+                    {"id": "PYSEC-2018-28", "fix_versions": ["2.20.0"], "aliases": ["CVE-2018-18074"]},
+                    {"id": "PYSEC-2023-74", "fix_versions": ["2.31.0"], "aliases": []}
+                ]},
+                {"name": "idna", "version": "3.7", "vulns": []},
+                {"name": "local-package", "skip_reason": "not on PyPI"}
+            ],
+            "fixes": []
+        }"#;
+        let counts = Ecosystem::Python.parse(output).unwrap();
+        assert_eq!(counts.unknown, 2);
+        assert_eq!(counts.at_or_above(Severity::High), 2);
+        assert_eq!(counts.at_or_above(Severity::Critical), 0);
+
+        let legacy =
+            r#"[{"name": "jinja2", "version": "2.10", "vulns": [{"id": "PYSEC-2019-217"}]}]"#;
+        assert_eq!(Ecosystem::Python.parse(legacy).unwrap().unknown, 1);
+        assert!(Ecosystem::Python.parse("not json").is_err());
+    }
+
+    #[test]
+    fn test_parse_govulncheck() {
+        let output = r#"
+            {"config": {"protocol_version": "v1.0.0", "scanner_name": "govulncheck"}}
+            {"progress": {"message": "Scanning your code and 42 packages across 3 dependent modules for known vulnerabilities..."}}
+            {"osv": {"id": "GO-2023-1571", "summary": "Denial of service in net/http"}}
+            {"osv": {"id": "GO-2022-0969", "summary": "Unbounded memory growth"}}
+            {"finding": {"osv": "GO-2023-1571", "fixed_version": "v0.7.0",
+                "trace": [{"module": "golang.org/x/net", "version": "v0.1.0"}]}}
+            {"finding": {"osv": "GO-2023-1571", "fixed_version": "v0.7.0",
+                "trace": [{"module": "golang.org/x/net", "package": "golang.org/x/net/http2", "function": "ServeConn"},
+                          {"module": "example.com/server", "function": "main"}]}}
+            {"finding": {"osv": "GO-2023-1571",
+                "trace": [{"module": "golang.org/x/net", "package": "golang.org/x/net/http2", "function": "Serve"}]}}
+            {"finding": {"osv": "GO-2022-0969",
+                "trace": [{"module": "golang.org/x/text", "package": "golang.org/x/text/language"}]}}
+        "#;
+        let counts = Ecosystem::Go.parse(output).unwrap();
+        assert_eq!(counts.unknown, 1);
+        assert!(Ecosystem::Go.parse(r#"{"finding": "#).is_err());
+    }
+
+    #[test]
+    fn test_gate_and_ecosystem_detection() {
+        let report = AuditReport {
+            tool: "npm".to_string(),
+            audited_at: Utc::now(),
+            counts: SeverityCounts {
+                moderate: 3,
+                ..SeverityCounts::default()
+            },
+            skipped: None,
+        };
+        let config: AuditConfig = serde_json::from_str(r#"{"fail_on": "medium"}"#).unwrap();
+        assert!(config.enabled);
+        assert!(check(&config, &report).is_err());
+        let config: AuditConfig = serde_json::from_str(r#"{}"#).unwrap();
+        assert_eq!(config.fail_on, Some(Severity::High));
+        assert!(check(&config, &report).is_ok());
+        let config: AuditConfig = serde_json::from_str(r#"{"fail_on": null}"#).unwrap();
+        assert!(check(&config, &report).is_ok());
+
+        let dir = std::env::temp_dir().join(format!("mcp-audit-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let runtime = RuntimeConfig::default();
+        assert_eq!(Ecosystem::detect(&dir, &runtime), None);
+        std::fs::write(dir.join("requirements.txt"), "requests==2.19.0\n").unwrap();
+        assert_eq!(Ecosystem::detect(&dir, &runtime), Some(Ecosystem::Python));
+        assert_eq!(
+            Ecosystem::Python.args(&dir),
+            ["--format", "json", "-r", "requirements.txt"]
+        );
+        std::fs::write(dir.join("package.json"), "{}").unwrap();
+        assert_eq!(Ecosystem::detect(&dir, &runtime), Some(Ecosystem::Npm));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_cached_report_reused_until_stamp_changes() {
+        if find_executable("npm").is_none() {
+            return;
+        }
+        let dir = std::env::temp_dir().join(format!("mcp-audit-cache-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("package.json"), "{}").unwrap();
+        let config: AuditConfig = serde_json::from_str("{}").unwrap();
+        let runtime = RuntimeConfig::default();
+
+        // A cached finding fails startup without running npm again
+        let stamp = BuildStamp::compute(&dir, "npm ci", &["npm"]).await;
+        let cached = AuditReport {
+            tool: "npm".to_string(),
+            audited_at: Utc::now(),
+            counts: SeverityCounts {
+                critical: 1,
+                ..SeverityCounts::default()
+            },
+            skipped: None,
+        };
+        write_report(&dir, Some(stamp), &cached).await;
+        let error = run(
+            &config,
+            &dir,
+            Some("npm ci"),
+            &runtime,
+            &ChildEnv::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(error.to_string().contains("1 critical"));
+
+        // A different build command makes the cached report stale
+        let _ = run(
+            &config,
+            &dir,
+            Some("npm install"),
+            &runtime,
+            &ChildEnv::default(),
+        )
+        .await;
+        assert_ne!(read_report(&dir).await, Some(cached));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_missing_tool_degrades_unless_required() {
+        let dir = std::env::temp_dir().join(format!("mcp-audit-missing-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("go.mod"), "module example.com/server\n").unwrap();
+        let env = ChildEnv::default();
+        let runtime = RuntimeConfig::default();
+        let mut config: AuditConfig = serde_json::from_str("{}").unwrap();
+
+        // The test environment has no govulncheck unless PATH says otherwise
+        if find_executable("govulncheck").is_none() {
+            let report = run(&config, &dir, None, &runtime, &env).await.unwrap();
+            assert_eq!(
+                report.skipped.as_deref(),
+                Some("'govulncheck' not found on PATH")
+            );
+            assert_eq!(read_report(&dir).await, Some(report));
+
+            config.require_audit_tool = true;
+            assert!(run(&config, &dir, None, &runtime, &env).await.is_err());
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,6 +1,20 @@
 //! Authentication module for MCP HTTP Core
+//!
+//! The keys in force live in a [`SharedAuth`] that every request loads
+//! once, so replacing them while serving is atomic: each request is checked
+//! against either the old or the new keys in full, never a mix or neither.
+//!
+//! A key is configured either as the token itself or as `sha256:` followed
+//! by the hex SHA-256 digest of the token, so the configuration need not
+//! hold it. Both are kept as digests in a [`KeyStore`], indexed by the first
+//! bytes of the digest: a presented token is hashed once, its few candidates
+//! are found without going through the other keys, and each is compared in
+//! constant time. How long the check takes therefore does not depend on how
+//! many keys there are or how much of one a token matches.
 
 use crate::config::AuthConfig;
+use crate::error::{McpCoreError, McpCoreResult};
+use arc_swap::ArcSwap;
 use axum::{
     body::Body,
     extract::State,
@@ -10,6 +24,11 @@ use axum::{
     Json,
 };
 use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
 
 /// Authentication error response
 #[derive(Serialize)]
@@ -18,13 +37,257 @@ pub struct AuthError {
     pub message: String,
 }
 
+/// Name of the API key that authenticated a request
+///
+/// Inserted into request extensions by [`bearer_auth_middleware`] on success.
+#[derive(Debug, Clone)]
+pub struct ApiKeyName(pub String);
+
+/// Name reported for the single key configured via `HTTP_API_KEY`
+pub const DEFAULT_API_KEY_NAME: &str = "default";
+
+/// Prefix of a key configured by the SHA-256 digest of its token
+pub const SHA256_PREFIX: &str = "sha256:";
+
+/// SHA-256 digest of a token
+type KeyDigest = [u8; 32];
+
+/// Accepted keys by the digest of their token
+///
+/// Keys are found by the first eight bytes of the digest, which say nothing
+/// usable about the tokens. When two keys have the same token, the first
+/// configured wins, the `HTTP_API_KEY` one before the named ones.
+#[derive(Debug, Default)]
+pub struct KeyStore {
+    by_fingerprint: HashMap<u64, Vec<(KeyDigest, String)>>,
+    len: usize,
+}
+
+impl KeyStore {
+    /// Keys of `auth_config`; fails on a malformed `sha256:` key
+    pub fn new(auth_config: &AuthConfig) -> McpCoreResult<Self> {
+        let mut store = Self::default();
+        for (name, key) in key_names(auth_config) {
+            let digest = stored_digest(key).ok_or_else(|| McpCoreError::ConfigurationError {
+                message: format!(
+                    "API key '{}' starts with {} but is not followed by 64 hex digits",
+                    name, SHA256_PREFIX
+                ),
+            })?;
+            let candidates = store
+                .by_fingerprint
+                .entry(fingerprint(&digest))
+                .or_default();
+            if !candidates.iter().any(|(known, _)| *known == digest) {
+                candidates.push((digest, name.to_string()));
+                store.len += 1;
+            }
+        }
+        Ok(store)
+    }
+
+    /// Name of the key whose token is `token`
+    pub fn identify(&self, token: &str) -> Option<&str> {
+        let digest: KeyDigest = Sha256::digest(token.as_bytes()).into();
+        let candidates = self.by_fingerprint.get(&fingerprint(&digest))?;
+        // Every candidate is compared, so a match costs the same wherever it is
+        let mut found = None;
+        for (known, name) in candidates {
+            if bool::from(known.ct_eq(&digest)) && found.is_none() {
+                found = Some(name.as_str());
+            }
+        }
+        found
+    }
+
+    /// Number of distinct tokens accepted
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Digest a key is stored as: decoded from a `sha256:` key, computed from
+/// any other
+fn stored_digest(key: &str) -> Option<KeyDigest> {
+    let Some(hex) = key.strip_prefix(SHA256_PREFIX) else {
+        return Some(Sha256::digest(key.as_bytes()).into());
+    };
+    if hex.len() != 64 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut digest = [0; 32];
+    for (byte, pair) in digest.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        let pair = std::str::from_utf8(pair).ok()?;
+        *byte = u8::from_str_radix(pair, 16).ok()?;
+    }
+    Some(digest)
+}
+
+fn fingerprint(digest: &KeyDigest) -> u64 {
+    let mut head = [0; 8];
+    head.copy_from_slice(&digest[..8]);
+    u64::from_be_bytes(head)
+}
+
+/// Keys in force, with the store they are looked up in
+#[derive(Debug)]
+struct ActiveAuth {
+    config: Arc<AuthConfig>,
+    keys: KeyStore,
+}
+
+impl ActiveAuth {
+    fn new(auth_config: AuthConfig) -> McpCoreResult<Self> {
+        Ok(Self {
+            keys: KeyStore::new(&auth_config)?,
+            config: Arc::new(auth_config),
+        })
+    }
+}
+
+/// Authentication in force, replaceable while the server runs
+#[derive(Debug)]
+pub struct SharedAuth {
+    current: ArcSwap<ActiveAuth>,
+
+    /// Addresses serving authenticated routes, checked on every replacement
+    hosts: Vec<IpAddr>,
+    allow_unauthenticated_public: bool,
+}
+
+/// Key names affected by a replacement of the authentication
+///
+/// Only names are reported, never the keys themselves.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AuthChange {
+    /// Whether authentication is enforced after the change
+    pub enabled: bool,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+
+    /// Names kept with a different key
+    pub rotated: Vec<String>,
+}
+
+impl SharedAuth {
+    /// Authentication with `auth_config` on `hosts`
+    ///
+    /// Fails as [`check_exposure`] does for any of the hosts, or on a
+    /// malformed `sha256:` key.
+    pub fn new(
+        auth_config: AuthConfig,
+        hosts: Vec<IpAddr>,
+        allow_unauthenticated_public: bool,
+    ) -> McpCoreResult<Self> {
+        for host in &hosts {
+            check_exposure(&auth_config, *host, allow_unauthenticated_public)?;
+        }
+        Ok(Self {
+            current: ArcSwap::from_pointee(ActiveAuth::new(auth_config)?),
+            hosts,
+            allow_unauthenticated_public,
+        })
+    }
+
+    /// Authentication in force
+    pub fn load(&self) -> Arc<AuthConfig> {
+        Arc::clone(&self.current.load().config)
+    }
+
+    /// Replace the authentication in force, as a config reload would
+    ///
+    /// A replacement leaving a public address unauthenticated, or with a
+    /// malformed key, is refused as it would be at startup, and the current
+    /// authentication stays. An accepted one is logged with the names of the
+    /// keys it changes.
+    pub fn replace(&self, auth_config: AuthConfig) -> McpCoreResult<AuthChange> {
+        let active = ActiveAuth::new(auth_config).inspect_err(|e| {
+            tracing::warn!(
+                "Rejected authentication change, keeping the current keys: {}",
+                e
+            );
+        })?;
+        let auth_config = &active.config;
+        for host in &self.hosts {
+            if let Err(e) = check_exposure(auth_config, *host, self.allow_unauthenticated_public) {
+                tracing::warn!(
+                    "Rejected authentication change, keeping the current keys: {}",
+                    e
+                );
+                return Err(e);
+            }
+        }
+
+        let previous = Arc::clone(&self.current.swap(Arc::new(active)).config);
+        let current = self.load();
+        let before = key_names(&previous);
+        let after = key_names(&current);
+        let change = AuthChange {
+            enabled: is_enforced(&current),
+            added: after
+                .iter()
+                .filter(|(name, _)| !before.iter().any(|(old, _)| old == name))
+                .map(|(name, _)| name.to_string())
+                .collect(),
+            removed: before
+                .iter()
+                .filter(|(name, _)| !after.iter().any(|(new, _)| new == name))
+                .map(|(name, _)| name.to_string())
+                .collect(),
+            rotated: after
+                .iter()
+                .filter(|(name, key)| before.iter().any(|(old, k)| old == name && k != key))
+                .map(|(name, _)| name.to_string())
+                .collect(),
+        };
+        tracing::warn!(
+            enabled_before = is_enforced(&previous),
+            enabled = change.enabled,
+            added = ?change.added,
+            removed = ?change.removed,
+            rotated = ?change.rotated,
+            "Security: authentication changed"
+        );
+        Ok(change)
+    }
+}
+
+/// Accepted keys by name, the `HTTP_API_KEY` one as [`DEFAULT_API_KEY_NAME`]
+fn key_names(auth_config: &AuthConfig) -> Vec<(&str, &str)> {
+    auth_config
+        .api_key
+        .as_deref()
+        .map(|key| (DEFAULT_API_KEY_NAME, key))
+        .into_iter()
+        .chain(
+            auth_config
+                .named_keys
+                .iter()
+                .map(|(name, key)| (name.as_str(), key.as_str())),
+        )
+        .collect()
+}
+
+/// Whether requests must present a key: auth is enabled and keys are set
+fn is_enforced(auth_config: &AuthConfig) -> bool {
+    auth_config.enabled && (auth_config.api_key.is_some() || !auth_config.named_keys.is_empty())
+}
+
 /// Bearer token authentication middleware
 pub async fn bearer_auth_middleware(
-    State(auth_config): State<AuthConfig>,
+    State(auth): State<Arc<SharedAuth>>,
     headers: HeaderMap,
-    request: Request<Body>,
+    mut request: Request<Body>,
     next: Next,
 ) -> Result<Response, impl IntoResponse> {
+    // One load per request, so a concurrent replacement is seen whole
+    let active = auth.current.load_full();
+    let auth_config = &active.config;
+
     // Skip authentication if disabled
     if !auth_config.enabled {
         tracing::debug!("Authentication disabled, proceeding without check");
@@ -32,13 +295,10 @@ pub async fn bearer_auth_middleware(
     }
 
     // Skip if no API key is configured
-    let expected_api_key = match &auth_config.api_key {
-        Some(key) => key,
-        None => {
-            tracing::debug!("No API key configured, proceeding without check");
-            return Ok(next.run(request).await);
-        }
-    };
+    if auth_config.api_key.is_none() && auth_config.named_keys.is_empty() {
+        tracing::debug!("No API key configured, proceeding without check");
+        return Ok(next.run(request).await);
+    }
 
     // Extract Authorization header
     let auth_header = match headers.get("authorization") {
@@ -76,7 +336,7 @@ pub async fn bearer_auth_middleware(
     let provided_token = &auth_header[7..]; // Skip "Bearer "
 
     // Validate API key
-    if provided_token != expected_api_key {
+    let Some(key_name) = active.keys.identify(provided_token) else {
         tracing::debug!(
             "Invalid API key provided (length: {})",
             provided_token.len()
@@ -86,17 +346,284 @@ pub async fn bearer_auth_middleware(
             message: "Invalid API key".to_string(),
         };
         return Err((StatusCode::UNAUTHORIZED, Json(error_response)));
-    }
+    };
 
     tracing::debug!("Authentication successful");
-    Ok(next.run(request).await)
+    let api_key_name = ApiKeyName(key_name.to_string());
+    request.extensions_mut().insert(api_key_name.clone());
+    request
+        .extensions_mut()
+        .insert(auth_config.default_priority);
+    // Also on the response, for the access log
+    let mut response = next.run(request).await;
+    response.extensions_mut().insert(api_key_name);
+    Ok(response)
+}
+
+/// Refuse to serve unauthenticated on a non-loopback address
+///
+/// Disabled auth on a public bind is only allowed when explicitly opted into
+/// with `allow_unauthenticated_public`, and is then logged as a warning.
+pub fn check_exposure(
+    auth_config: &AuthConfig,
+    host: IpAddr,
+    allow_unauthenticated_public: bool,
+) -> McpCoreResult<()> {
+    if is_enforced(auth_config) || host.is_loopback() {
+        return Ok(());
+    }
+
+    if allow_unauthenticated_public {
+        tracing::warn!(
+            "AUTHENTICATION IS DISABLED on public address {}; anyone who can reach it can use the MCP server",
+            host
+        );
+        return Ok(());
+    }
+
+    Err(McpCoreError::ConfigurationError {
+        message: format!(
+            "Refusing to listen on {} without authentication. Set HTTP_API_KEY (and leave DISABLE_AUTH unset), \
+             bind to a loopback address with BIND_ADDRESS=127.0.0.1, \
+             or set ALLOW_UNAUTHENTICATED_PUBLIC=true to accept the exposure",
+            host
+        ),
+    })
+}
+
+/// `"enabled"` or `"disabled"`, as reported by the index and health endpoints
+pub fn auth_status(auth_config: &AuthConfig) -> &'static str {
+    if auth_config.enabled {
+        "enabled"
+    } else {
+        "disabled"
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use axum::{body::Body, http::Request};
-    use std::collections::HashMap;
+    use crate::priority::RequestPriority;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_check_exposure() {
+        let enabled = AuthConfig {
+            api_key: Some("key".to_string()),
+            enabled: true,
+            default_priority: RequestPriority::Normal,
+            named_keys: Vec::new(),
+        };
+        let disabled = AuthConfig {
+            api_key: None,
+            enabled: false,
+            default_priority: RequestPriority::Normal,
+            named_keys: Vec::new(),
+        };
+        let loopback = IpAddr::from(Ipv4Addr::LOCALHOST);
+        let public = IpAddr::from(Ipv4Addr::UNSPECIFIED);
+
+        assert!(check_exposure(&enabled, loopback, false).is_ok());
+        assert!(check_exposure(&enabled, public, false).is_ok());
+        assert!(check_exposure(&disabled, loopback, false).is_ok());
+
+        let error = check_exposure(&disabled, public, false).unwrap_err();
+        assert!(matches!(error, McpCoreError::ConfigurationError { .. }));
+        assert!(error.to_string().contains("ALLOW_UNAUTHENTICATED_PUBLIC"));
+        assert!(check_exposure(&disabled, public, true).is_ok());
+    }
+
+    fn keys(api_key: Option<&str>, named_keys: &[(&str, &str)]) -> AuthConfig {
+        AuthConfig {
+            api_key: api_key.map(str::to_string),
+            enabled: true,
+            default_priority: RequestPriority::Normal,
+            named_keys: named_keys
+                .iter()
+                .map(|(name, key)| (name.to_string(), key.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_replace_reports_key_names() {
+        let public = IpAddr::from(Ipv4Addr::UNSPECIFIED);
+        let auth = SharedAuth::new(
+            keys(Some("k0"), &[("ci", "k1"), ("ops", "k2")]),
+            vec![public],
+            false,
+        )
+        .unwrap();
+
+        let change = auth
+            .replace(keys(
+                None,
+                &[("ci", "k1-next"), ("ops", "k2"), ("bot", "k3")],
+            ))
+            .unwrap();
+        assert_eq!(
+            change,
+            AuthChange {
+                enabled: true,
+                added: vec!["bot".to_string()],
+                removed: vec![DEFAULT_API_KEY_NAME.to_string()],
+                rotated: vec!["ci".to_string()],
+            }
+        );
+        let current = auth.load();
+        assert_eq!(current.api_key, None);
+        assert_eq!(current.named_keys.len(), 3);
+    }
+
+    #[test]
+    fn test_replace_refuses_unauthenticated_public_bind() {
+        let public = IpAddr::from(Ipv4Addr::UNSPECIFIED);
+        let auth = SharedAuth::new(keys(Some("k0"), &[]), vec![public], false).unwrap();
+
+        let mut disabled = keys(None, &[]);
+        let error = auth.replace(disabled.clone()).unwrap_err();
+        assert!(error.to_string().contains("ALLOW_UNAUTHENTICATED_PUBLIC"));
+        disabled.enabled = false;
+        assert!(auth.replace(disabled.clone()).is_err());
+        // The keys in force are kept
+        assert_eq!(auth.load().api_key.as_deref(), Some("k0"));
+
+        let allowed = SharedAuth::new(keys(Some("k0"), &[]), vec![public], true).unwrap();
+        let change = allowed.replace(disabled.clone()).unwrap();
+        assert!(!change.enabled);
+        assert_eq!(change.removed, [DEFAULT_API_KEY_NAME]);
+
+        let loopback = SharedAuth::new(
+            keys(Some("k0"), &[]),
+            vec![IpAddr::from(Ipv4Addr::LOCALHOST)],
+            false,
+        )
+        .unwrap();
+        assert!(loopback.replace(disabled).is_ok());
+    }
+
+    fn sha256_key(token: &str) -> String {
+        let digest = Sha256::digest(token.as_bytes());
+        let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+        format!("{}{}", SHA256_PREFIX, hex)
+    }
+
+    /// Linear scan the key store must agree with
+    fn naive_identify<'a>(auth_config: &'a AuthConfig, token: &str) -> Option<&'a str> {
+        let hashed = sha256_key(token);
+        key_names(auth_config)
+            .into_iter()
+            .find(|(_, key)| match key.starts_with(SHA256_PREFIX) {
+                true => key.eq_ignore_ascii_case(&hashed),
+                false => *key == token,
+            })
+            .map(|(name, _)| name)
+    }
+
+    #[test]
+    fn test_key_store_agrees_with_a_linear_scan() {
+        let uppercase = sha256_key("upper-token").to_uppercase().replacen(
+            &SHA256_PREFIX.to_uppercase(),
+            SHA256_PREFIX,
+            1,
+        );
+        let mut named_keys = vec![
+            ("ci", "abcdefgh-1".to_string()),
+            ("ops", "abcdefgh-2".to_string()),
+            ("ci-copy", "abcdefgh-1".to_string()),
+            ("shadowed", "k0".to_string()),
+            ("hashed", sha256_key("hashed-token")),
+            ("hashed-twin", "hashed-token".to_string()),
+            ("upper", uppercase),
+            ("literal", "SHA256:not-a-digest".to_string()),
+            ("empty", String::new()),
+        ];
+        named_keys.extend((0..1000).map(|i| ("bulk", format!("bulk-{:04}", i))));
+        let named_keys: Vec<(&str, &str)> = named_keys
+            .iter()
+            .map(|(name, key)| (*name, key.as_str()))
+            .collect();
+
+        let with_empty = keys(Some("k0"), &named_keys);
+        let without_empty = keys(
+            Some("k0"),
+            &named_keys
+                .iter()
+                .copied()
+                .filter(|(name, _)| *name != "empty")
+                .collect::<Vec<_>>(),
+        );
+        let hashed = sha256_key("hashed-token");
+        let mut tokens = vec![
+            "",
+            " ",
+            "k0",
+            "k",
+            "k00",
+            "K0",
+            "abcdefgh",
+            "abcdefgh-1",
+            "abcdefgh-2",
+            "abcdefgh-3",
+            "ABCDEFGH-1",
+            " abcdefgh-1",
+            "abcdefgh-1 ",
+            "hashed-token",
+            "hashed-toke",
+            &hashed,
+            "upper-token",
+            "SHA256:not-a-digest",
+            "sha256:",
+            "bulk-",
+            "bulk-0999",
+            "bulk-1000",
+            "bulk-0999\0",
+            "é",
+        ];
+        let bulk: Vec<String> = (0..1000).map(|i| format!("bulk-{:04}", i)).collect();
+        tokens.extend(bulk.iter().map(String::as_str));
+
+        for auth_config in [&with_empty, &without_empty] {
+            let store = KeyStore::new(auth_config).unwrap();
+            for token in &tokens {
+                assert_eq!(
+                    store.identify(token),
+                    naive_identify(auth_config, token),
+                    "{:?}",
+                    token
+                );
+            }
+        }
+
+        let store = KeyStore::new(&with_empty).unwrap();
+        assert_eq!(store.identify("k0"), Some(DEFAULT_API_KEY_NAME));
+        assert_eq!(store.identify("abcdefgh-1"), Some("ci"));
+        assert_eq!(store.identify("hashed-token"), Some("hashed"));
+        assert_eq!(store.identify("upper-token"), Some("upper"));
+        assert_eq!(store.identify(""), Some("empty"));
+        assert_eq!(store.identify(&hashed), None);
+        assert_eq!(KeyStore::new(&without_empty).unwrap().identify(""), None);
+        // Duplicates of a token are only counted once
+        assert_eq!(store.len(), 7 + 1000);
+    }
+
+    #[test]
+    fn test_malformed_hashed_keys_are_refused() {
+        let loopback = IpAddr::from(Ipv4Addr::LOCALHOST);
+        let too_short = format!("{}abc", SHA256_PREFIX);
+        let not_hex = format!("{}{}", SHA256_PREFIX, "+f".repeat(32));
+        for key in [too_short.as_str(), not_hex.as_str(), SHA256_PREFIX] {
+            let error = KeyStore::new(&keys(None, &[("ci", key)])).unwrap_err();
+            assert!(matches!(error, McpCoreError::ConfigurationError { .. }));
+            assert!(error.to_string().contains("'ci'"), "{}", error);
+            assert!(SharedAuth::new(keys(None, &[("ci", key)]), vec![loopback], false).is_err());
+        }
+
+        let auth = SharedAuth::new(keys(Some("k0"), &[]), vec![loopback], false).unwrap();
+        assert!(auth.replace(keys(None, &[("ci", &not_hex)])).is_err());
+        // The keys in force are kept
+        assert_eq!(auth.load().api_key.as_deref(), Some("k0"));
+    }
 
     #[test]
     fn test_auth_error_serialization() {
@@ -0,0 +1,612 @@
+//! Replicas of a server and choosing which one answers a request
+//!
+//! A server entry's `replicas` block runs `count` children of the server:
+//! the primary and `count - 1` more, `<server>-replica-1` and on, started
+//! in the background in the primary's work directory once it is set up.
+//! Each replica takes its own turns, so they serve requests side by side.
+//!
+//! `least-busy` picks the replica with the fewest requests in flight,
+//! `round-robin` takes them in turn, and `sticky-key` keeps a client on one
+//! replica so its caches stay warm. A client is known by its
+//! `X-MCP-Affinity` header or, without one, the name of its API key. Every
+//! replica scores that key by a stable hash of both, and the highest score
+//! wins (rendezvous hashing): removing a replica only moves the clients it
+//! had, and adding one only takes its share from the others. A client whose
+//! replica is unavailable goes to the replica it scores next highest, so
+//! those clients spread over the rest instead of piling onto one. Requests
+//! without a key are served least-busy.
+//!
+//! A replica other than the primary whose child exits is taken out of the
+//! pool and started again. The strategy is held atomically and can be
+//! changed while serving.
+
+use crate::build_cache::fnv1a;
+use crate::canary::SharedTransport;
+use crate::priority::{RequestQueue, RequestQueueConfig};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+
+/// Request header whose value keeps a client on one replica
+pub const AFFINITY_HEADER: &str = "x-mcp-affinity";
+
+/// How requests are spread over replicas
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Balance {
+    #[default]
+    LeastBusy,
+    RoundRobin,
+    StickyKey,
+}
+
+impl Balance {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::RoundRobin,
+            2 => Self::StickyKey,
+            _ => Self::LeastBusy,
+        }
+    }
+}
+
+/// Most children a server's replicas may run
+pub const MAX_REPLICAS: usize = 32;
+
+/// Replicas of a server sharing its requests
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReplicaConfig {
+    /// Children serving the server, the primary included
+    pub count: usize,
+
+    /// How requests are spread over them
+    #[serde(default)]
+    pub balance: Balance,
+}
+
+impl ReplicaConfig {
+    /// Check the count
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=MAX_REPLICAS).contains(&self.count) {
+            return Err(format!(
+                "count {} is not between 1 and {}",
+                self.count, MAX_REPLICAS
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Name replica `index` of `server_name` runs under; the primary, index 0,
+/// keeps the server's
+pub fn replica_name(server_name: &str, index: usize) -> String {
+    match index {
+        0 => server_name.to_string(),
+        _ => format!("{}-replica-{}", server_name, index),
+    }
+}
+
+/// A replica as the balancer sees it
+#[derive(Debug, Clone, Copy)]
+pub struct Replica<'a> {
+    /// Name the replica is hashed by, stable across its restarts
+    pub name: &'a str,
+    pub in_flight: usize,
+
+    /// Whether it takes requests; one restarting or failed does not
+    pub available: bool,
+}
+
+/// Picks replicas with the strategy in force
+#[derive(Debug, Default)]
+pub struct Balancer {
+    balance: AtomicU8,
+    next: AtomicUsize,
+}
+
+impl Balancer {
+    pub fn new(balance: Balance) -> Self {
+        Self {
+            balance: AtomicU8::new(balance as u8),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Strategy in force
+    pub fn balance(&self) -> Balance {
+        Balance::from_u8(self.balance.load(Ordering::Relaxed))
+    }
+
+    /// Change the strategy, effective from the next pick
+    pub fn set_balance(&self, balance: Balance) {
+        let previous = Balance::from_u8(self.balance.swap(balance as u8, Ordering::Relaxed));
+        if previous != balance {
+            tracing::info!(
+                "Replica balancing changed from {:?} to {:?}",
+                previous,
+                balance
+            );
+        }
+    }
+
+    /// Index in `replicas` of the one to serve a request from the client
+    /// known by `affinity`, or `None` if none is available
+    pub fn pick(&self, replicas: &[Replica], affinity: Option<&str>) -> Option<usize> {
+        match (self.balance(), affinity) {
+            (Balance::StickyKey, Some(key)) => sticky(replicas, key),
+            (Balance::RoundRobin, _) => {
+                let start = self.next.fetch_add(1, Ordering::Relaxed);
+                (0..replicas.len())
+                    .map(|offset| (start + offset) % replicas.len())
+                    .find(|index| replicas[*index].available)
+            }
+            (Balance::LeastBusy | Balance::StickyKey, _) => available(replicas)
+                .min_by_key(|(_, replica)| replica.in_flight)
+                .map(|(index, _)| index),
+        }
+    }
+}
+
+/// Where a replica other than the primary is in its life
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplicaState {
+    /// Being spawned or restarted; its clients go to the others
+    Starting,
+    Running,
+
+    /// Its start failed; its clients go to the others
+    Failed,
+}
+
+/// One replica, served by the stats and admin endpoints
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplicaSnapshot {
+    pub name: String,
+    pub state: ReplicaState,
+    pub in_flight: usize,
+
+    /// Why its start failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure: Option<String>,
+}
+
+/// Replicas of a server, served by the stats and admin endpoints
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolSnapshot {
+    pub balance: Balance,
+    pub replicas: Vec<ReplicaSnapshot>,
+}
+
+/// Replica after the primary
+struct Extra {
+    name: String,
+    status: RwLock<ExtraStatus>,
+
+    /// Its own turns, so it serves next to the others
+    queue: Arc<RequestQueue>,
+    in_flight: Arc<AtomicUsize>,
+}
+
+struct ExtraStatus {
+    state: ReplicaState,
+    failure: Option<String>,
+    transport: Option<SharedTransport>,
+}
+
+/// Starts replica `index` again, with the pool it belongs to
+type RestartFn = Box<dyn Fn(usize) + Send + Sync>;
+
+/// A server's replicas and the strategy picking among them
+///
+/// The primary is replica 0 and is always available; its transport and turns
+/// are the server's own.
+#[derive(Default)]
+pub struct ReplicaPool {
+    server_name: String,
+    balancer: Balancer,
+    primary_in_flight: Arc<AtomicUsize>,
+    extras: Vec<Extra>,
+    restart: OnceLock<RestartFn>,
+}
+
+impl std::fmt::Debug for ReplicaPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReplicaPool")
+            .field("server_name", &self.server_name)
+            .field("balancer", &self.balancer)
+            .field("replicas", &(self.extras.len() + 1))
+            .finish_non_exhaustive()
+    }
+}
+
+/// Replica picked to serve a request, counted in flight until dropped
+pub struct PickedReplica {
+    index: usize,
+
+    /// Transport and turns of a replica other than the primary
+    target: Option<(SharedTransport, Arc<RequestQueue>)>,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl PickedReplica {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Transport and turns of the replica, or `None` for the primary
+    pub fn target(&self) -> Option<&(SharedTransport, Arc<RequestQueue>)> {
+        self.target.as_ref()
+    }
+}
+
+impl Drop for PickedReplica {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl ReplicaPool {
+    /// Pool of `server_name` for `config`, with the replicas after the
+    /// primary starting and taking turns configured by `queue`
+    pub fn new(server_name: &str, config: &ReplicaConfig, queue: &RequestQueueConfig) -> Self {
+        Self {
+            server_name: server_name.to_string(),
+            balancer: Balancer::new(config.balance),
+            primary_in_flight: Arc::default(),
+            extras: (1..config.count.max(1))
+                .map(|index| Extra {
+                    name: replica_name(server_name, index),
+                    status: RwLock::new(ExtraStatus {
+                        state: ReplicaState::Starting,
+                        failure: None,
+                        transport: None,
+                    }),
+                    queue: Arc::new(RequestQueue::new(queue.clone())),
+                    in_flight: Arc::default(),
+                })
+                .collect(),
+            restart: OnceLock::new(),
+        }
+    }
+
+    /// Whether the server runs more than one child
+    pub fn is_replicated(&self) -> bool {
+        !self.extras.is_empty()
+    }
+
+    /// Indexes of the replicas after the primary
+    pub fn extra_indexes(&self) -> std::ops::RangeInclusive<usize> {
+        1..=self.extras.len()
+    }
+
+    /// Start a replica whose child exited with `restart`
+    pub fn on_exit(&self, restart: impl Fn(usize) + Send + Sync + 'static) {
+        let _ = self.restart.set(Box::new(restart));
+    }
+
+    /// Send requests to replica `index`, now running on `transport`
+    pub fn start(&self, index: usize, transport: SharedTransport) {
+        if let Some(extra) = index.checked_sub(1).and_then(|i| self.extras.get(i)) {
+            let mut status = write(&extra.status);
+            status.state = ReplicaState::Running;
+            status.failure = None;
+            status.transport = Some(transport);
+        }
+    }
+
+    /// Record that replica `index` could not be started
+    pub fn fail(&self, index: usize, reason: String) {
+        if let Some(extra) = index.checked_sub(1).and_then(|i| self.extras.get(i)) {
+            let mut status = write(&extra.status);
+            status.state = ReplicaState::Failed;
+            status.failure = Some(reason);
+            status.transport = None;
+        }
+    }
+
+    /// Strategy in force
+    pub fn balance(&self) -> Balance {
+        self.balancer.balance()
+    }
+
+    /// Change the strategy, effective from the next request
+    pub fn set_balance(&self, balance: Balance) {
+        self.balancer.set_balance(balance);
+    }
+
+    /// Replica to serve a request from the client known by `affinity`, or
+    /// `None` if the server runs a single child
+    pub fn pick(&self, affinity: Option<&str>) -> Option<PickedReplica> {
+        if !self.is_replicated() {
+            return None;
+        }
+        let names: Vec<&str> = std::iter::once(self.server_name.as_str())
+            .chain(self.extras.iter().map(|extra| extra.name.as_str()))
+            .collect();
+        let mut replicas = vec![Replica {
+            name: names[0],
+            in_flight: self.primary_in_flight.load(Ordering::Relaxed),
+            available: true,
+        }];
+        for (index, extra) in self.extras.iter().enumerate() {
+            replicas.push(Replica {
+                name: names[index + 1],
+                in_flight: extra.in_flight.load(Ordering::Relaxed),
+                available: self.check_running(index + 1, extra),
+            });
+        }
+
+        // The primary is always available
+        let index = self.balancer.pick(&replicas, affinity).unwrap_or(0);
+        let picked = match index {
+            0 => PickedReplica {
+                index,
+                target: None,
+                in_flight: Arc::clone(&self.primary_in_flight),
+            },
+            _ => {
+                let extra = &self.extras[index - 1];
+                let transport = read(&extra.status).transport.clone();
+                match transport {
+                    Some(transport) => PickedReplica {
+                        index,
+                        target: Some((transport, Arc::clone(&extra.queue))),
+                        in_flight: Arc::clone(&extra.in_flight),
+                    },
+                    // Taken out since it was checked
+                    None => PickedReplica {
+                        index: 0,
+                        target: None,
+                        in_flight: Arc::clone(&self.primary_in_flight),
+                    },
+                }
+            }
+        };
+        picked.in_flight.fetch_add(1, Ordering::Relaxed);
+        Some(picked)
+    }
+
+    /// Whether replica `index` takes requests, taking it out of the pool and
+    /// starting it again if its child exited
+    ///
+    /// A replica busy with a request is taken to be running.
+    fn check_running(&self, index: usize, extra: &Extra) -> bool {
+        let Some(transport) = read(&extra.status).transport.clone() else {
+            return false;
+        };
+        let alive = match transport.try_lock() {
+            Ok(mut transport) => transport.is_alive(),
+            Err(_) => true,
+        };
+        if alive {
+            return true;
+        }
+
+        let mut status = write(&extra.status);
+        if !status
+            .transport
+            .as_ref()
+            .is_some_and(|current| Arc::ptr_eq(current, &transport))
+        {
+            return false;
+        }
+        status.state = ReplicaState::Starting;
+        status.transport = None;
+        drop(status);
+        tracing::warn!(
+            "Replica '{}' exited; its clients go to the other replicas while it restarts",
+            extra.name
+        );
+        if let Some(restart) = self.restart.get() {
+            restart(index);
+        }
+        false
+    }
+
+    /// Transports of the running replicas after the primary
+    pub fn transports(&self) -> Vec<SharedTransport> {
+        self.extras
+            .iter()
+            .filter_map(|extra| read(&extra.status).transport.clone())
+            .collect()
+    }
+
+    /// Stop the turns of the replicas after the primary, returning how many
+    /// waiting requests failed
+    pub fn close(&self) -> usize {
+        self.extras.iter().map(|extra| extra.queue.close()).sum()
+    }
+
+    /// The replicas, or `None` if the server runs a single child
+    pub fn snapshot(&self) -> Option<PoolSnapshot> {
+        if !self.is_replicated() {
+            return None;
+        }
+        let primary = ReplicaSnapshot {
+            name: self.server_name.clone(),
+            state: ReplicaState::Running,
+            in_flight: self.primary_in_flight.load(Ordering::Relaxed),
+            failure: None,
+        };
+        let extras = self.extras.iter().map(|extra| {
+            let status = read(&extra.status);
+            ReplicaSnapshot {
+                name: extra.name.clone(),
+                state: status.state,
+                in_flight: extra.in_flight.load(Ordering::Relaxed),
+                failure: status.failure.clone(),
+            }
+        });
+        Some(PoolSnapshot {
+            balance: self.balance(),
+            replicas: std::iter::once(primary).chain(extras).collect(),
+        })
+    }
+}
+
+fn read(status: &RwLock<ExtraStatus>) -> std::sync::RwLockReadGuard<'_, ExtraStatus> {
+    status.read().unwrap_or_else(|e| e.into_inner())
+}
+
+fn write(status: &RwLock<ExtraStatus>) -> std::sync::RwLockWriteGuard<'_, ExtraStatus> {
+    status.write().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Key a client is kept on a replica by: its `X-MCP-Affinity` header, or
+/// the name of its API key
+pub fn affinity_key<'a>(header: Option<&'a str>, api_key_name: Option<&'a str>) -> Option<&'a str> {
+    header.filter(|value| !value.is_empty()).or(api_key_name)
+}
+
+fn available<'r, 'a>(
+    replicas: &'r [Replica<'a>],
+) -> impl Iterator<Item = (usize, &'r Replica<'a>)> {
+    replicas
+        .iter()
+        .enumerate()
+        .filter(|(_, replica)| replica.available)
+}
+
+fn sticky(replicas: &[Replica], key: &str) -> Option<usize> {
+    let best = |candidates: &mut dyn Iterator<Item = (usize, &Replica)>| {
+        candidates
+            .max_by_key(|(_, replica)| score(key, replica.name))
+            .map(|(index, _)| index)
+    };
+    let chosen = best(&mut available(replicas))?;
+    if let Some(preferred) = best(&mut replicas.iter().enumerate()) {
+        if preferred != chosen {
+            tracing::info!(
+                "Replica '{}' is unavailable, sending its client to '{}'",
+                replicas[preferred].name,
+                replicas[chosen].name
+            );
+        }
+    }
+    Some(chosen)
+}
+
+/// Rendezvous score of `replica` for `key`
+fn score(key: &str, replica: &str) -> u64 {
+    let mut bytes = Vec::with_capacity(key.len() + 1 + replica.len());
+    bytes.extend_from_slice(key.as_bytes());
+    bytes.push(0);
+    bytes.extend_from_slice(replica.as_bytes());
+    mix(fnv1a(&bytes))
+}
+
+/// SplitMix64 finalizer, so scores differ in every bit and not only in the
+/// low ones FNV-1a varies most
+fn mix(mut hash: u64) -> u64 {
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAMES: [&str; 5] = ["r0", "r1", "r2", "r3", "r4"];
+
+    fn replicas(names: &[&'static str]) -> Vec<Replica<'static>> {
+        names
+            .iter()
+            .map(|name| Replica {
+                name,
+                in_flight: 0,
+                available: true,
+            })
+            .collect()
+    }
+
+    fn clients() -> Vec<String> {
+        (0..5000).map(|i| format!("client-{}", i)).collect()
+    }
+
+    #[test]
+    fn test_sticky_key_keeps_clients_on_one_replica_and_spreads_them() {
+        let balancer = Balancer::new(Balance::StickyKey);
+        let mut pool = replicas(&NAMES);
+        let mut per_replica = [0; NAMES.len()];
+        for client in clients() {
+            let first = balancer.pick(&pool, Some(&client)).unwrap();
+            for round in 0..20 {
+                // Load does not move a client
+                pool[round % NAMES.len()].in_flight += 1;
+                assert_eq!(balancer.pick(&pool, Some(&client)), Some(first));
+            }
+            per_replica[first] += 1;
+        }
+        // 1,000 each on average
+        for count in per_replica {
+            assert!((850..1150).contains(&count), "{:?}", per_replica);
+        }
+    }
+
+    #[test]
+    fn test_removing_a_replica_only_moves_its_clients() {
+        let balancer = Balancer::new(Balance::StickyKey);
+        let all = replicas(&NAMES);
+        let fewer = replicas(&["r0", "r1", "r3", "r4"]);
+        let mut down = replicas(&NAMES);
+        down[2].available = false;
+
+        let mut moved_to = Vec::new();
+        for client in clients() {
+            let before = all[balancer.pick(&all, Some(&client)).unwrap()].name;
+            let after = fewer[balancer.pick(&fewer, Some(&client)).unwrap()].name;
+            // An unavailable replica is treated like a removed one
+            assert_eq!(
+                down[balancer.pick(&down, Some(&client)).unwrap()].name,
+                after
+            );
+            if before == "r2" {
+                moved_to.push(after);
+            } else {
+                assert_eq!(before, after, "{}", client);
+            }
+        }
+        // Its clients are shared out, not all sent to one replica
+        for name in ["r0", "r1", "r3", "r4"] {
+            let count = moved_to.iter().filter(|moved| **moved == name).count();
+            assert!(count > moved_to.len() / 8, "{} got {}", name, count);
+        }
+    }
+
+    #[test]
+    fn test_other_strategies_and_switching() {
+        let balancer = Balancer::default();
+        assert_eq!(balancer.balance(), Balance::LeastBusy);
+        let mut pool = replicas(&["a", "b", "c"]);
+        pool[0].in_flight = 2;
+        pool[1].in_flight = 1;
+        pool[2].in_flight = 1;
+        assert_eq!(balancer.pick(&pool, Some("client")), Some(1));
+        pool[1].available = false;
+        assert_eq!(balancer.pick(&pool, None), Some(2));
+
+        balancer.set_balance(Balance::RoundRobin);
+        let picks: Vec<_> = (0..4).map(|_| balancer.pick(&pool, None)).collect();
+        assert_eq!(picks, [Some(0), Some(2), Some(2), Some(0)]);
+
+        // Without a key, sticky-key falls back to least-busy
+        balancer.set_balance(Balance::StickyKey);
+        assert_eq!(balancer.pick(&pool, None), Some(2));
+
+        for replica in &mut pool {
+            replica.available = false;
+        }
+        assert_eq!(balancer.pick(&pool, Some("client")), None);
+        assert_eq!(balancer.pick(&[], None), None);
+
+        assert_eq!(
+            serde_json::from_str::<Balance>(r#""sticky-key""#).unwrap(),
+            Balance::StickyKey
+        );
+        assert_eq!(affinity_key(Some("tab-1"), Some("ci")), Some("tab-1"));
+        assert_eq!(affinity_key(Some(""), Some("ci")), Some("ci"));
+        assert_eq!(affinity_key(None, None), None);
+    }
+}
@@ -0,0 +1,92 @@
+//! Breaker for a child that stopped reading its stdin
+//!
+//! A child whose event loop is stuck stops reading stdin; once the pipe
+//! buffer is full, writes to it block. Writes are therefore given their own
+//! timeout (`stdin_write_timeout_secs`, default 5). The first write that times
+//! out opens the breaker: requests to the child fail at once with
+//! `child_unresponsive` instead of each waiting out the write timeout, and
+//! the child is restarted blue-green. The breaker closes when the restart
+//! ends, so a failed restart is retried on the next write that times out.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::error::{McpCoreError, McpCoreResult};
+use crate::history::Trigger;
+use crate::provision::{Provisioner, RestartStrategy};
+
+/// Reason recorded for restarts of an unresponsive child
+pub const RESTART_REASON: &str = "child unresponsive: stdin write timed out";
+
+/// Stdin write timeouts of the server's child
+#[derive(Debug, Default)]
+pub struct StdinBreaker {
+    open: AtomicBool,
+
+    /// Write timeouts since the last request that succeeded
+    consecutive: AtomicU64,
+    total: AtomicU64,
+}
+
+/// Breaker state, for the stats endpoints
+#[derive(Debug, Clone, Serialize)]
+pub struct BreakerSnapshot {
+    pub open: bool,
+    pub consecutive_write_timeouts: u64,
+    pub write_timeouts: u64,
+}
+
+impl StdinBreaker {
+    /// Refuse a request while the child is being replaced
+    pub fn check(&self) -> McpCoreResult<()> {
+        if self.open.load(Ordering::Acquire) {
+            return Err(McpCoreError::ChildUnresponsive {
+                message: "MCP server stopped reading its stdin and is being restarted".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Note a request the child answered
+    pub fn record_success(&self) {
+        self.consecutive.store(0, Ordering::Relaxed);
+    }
+
+    /// Note a write that timed out, restarting the child unless a restart
+    /// is already underway
+    pub fn trip(self: &Arc<Self>, provisioner: &Arc<Provisioner>) {
+        let consecutive = self.consecutive.fetch_add(1, Ordering::Relaxed) + 1;
+        self.total.fetch_add(1, Ordering::Relaxed);
+        if self.open.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        tracing::error!(
+            "MCP server stopped reading its stdin ({} consecutive write timeouts); restarting it",
+            consecutive
+        );
+        let (breaker, provisioner) = (Arc::clone(self), Arc::clone(provisioner));
+        tokio::spawn(async move {
+            if let Err(e) = provisioner
+                .restart(
+                    RestartStrategy::BlueGreen,
+                    Trigger::Unresponsive,
+                    RESTART_REASON,
+                )
+                .await
+            {
+                tracing::error!("Restarting the unresponsive MCP server failed: {}", e);
+            }
+            breaker.open.store(false, Ordering::Release);
+        });
+    }
+
+    pub fn snapshot(&self) -> BreakerSnapshot {
+        BreakerSnapshot {
+            open: self.open.load(Ordering::Acquire),
+            consecutive_write_timeouts: self.consecutive.load(Ordering::Relaxed),
+            write_timeouts: self.total.load(Ordering::Relaxed),
+        }
+    }
+}
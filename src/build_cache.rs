@@ -0,0 +1,242 @@
+//! Build artifact caching across restarts
+//!
+//! After a successful build a stamp file is written into the work directory.
+//! It records what the build depended on: the checked-out commit, the build
+//! command, the runtime version, and the lockfiles. A later start skips the
+//! build when a freshly computed stamp matches the stored one.
+
+use crate::diagnostics::{find_executable, probe_version};
+use crate::error::{McpCoreError, McpCoreResult};
+use crate::setup;
+use crate::workdir::current_commit;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Name of the stamp file written into each work directory
+pub const STAMP_FILE_NAME: &str = ".mcp-build-stamp.json";
+
+/// Lockfiles whose contents invalidate the build when changed
+const LOCKFILES: &[&str] = &[
+    "package-lock.json",
+    "npm-shrinkwrap.json",
+    "yarn.lock",
+    "pnpm-lock.yaml",
+    "bun.lockb",
+    "requirements.txt",
+    "poetry.lock",
+    "uv.lock",
+    "Pipfile.lock",
+    "go.sum",
+    "Cargo.lock",
+];
+
+/// Inputs a build depended on
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BuildStamp {
+    pub commit: Option<String>,
+    pub build_command_hash: String,
+    pub runtime_version: Option<String>,
+    pub lockfile_hash: Option<String>,
+}
+
+impl BuildStamp {
+    /// Compute the stamp for building `work_dir` with `build_command`
+    ///
+    /// The runtime is the first of `runtime_candidates` found on the PATH.
+    pub async fn compute(
+        work_dir: &Path,
+        build_command: &str,
+        runtime_candidates: &[&str],
+    ) -> Self {
+        let runtime = runtime_candidates
+            .iter()
+            .find_map(|command| find_executable(command));
+        let runtime_version = match runtime {
+            Some(path) => probe_version(&path).await,
+            None => None,
+        };
+
+        Self {
+            commit: current_commit(work_dir).await,
+            build_command_hash: format!("{:016x}", fnv1a(build_command.as_bytes())),
+            runtime_version,
+            lockfile_hash: lockfile_hash(work_dir).await,
+        }
+    }
+
+    /// Why a build with this stamp differs from `previous`, if it does
+    pub fn stale_reason(&self, previous: Option<&BuildStamp>) -> Option<String> {
+        let Some(previous) = previous else {
+            return Some("no build stamp".to_string());
+        };
+
+        if self.commit != previous.commit {
+            Some(format!(
+                "commit changed ({} -> {})",
+                previous.commit.as_deref().unwrap_or("none"),
+                self.commit.as_deref().unwrap_or("none")
+            ))
+        } else if self.build_command_hash != previous.build_command_hash {
+            Some("build command changed".to_string())
+        } else if self.runtime_version != previous.runtime_version {
+            Some(format!(
+                "runtime version changed ({} -> {})",
+                previous.runtime_version.as_deref().unwrap_or("unknown"),
+                self.runtime_version.as_deref().unwrap_or("unknown")
+            ))
+        } else if self.lockfile_hash != previous.lockfile_hash {
+            Some("lockfiles changed".to_string())
+        } else {
+            None
+        }
+    }
+}
+
+/// Read the stamp of the last successful build, if present and valid
+pub async fn read_stamp(work_dir: &Path) -> Option<BuildStamp> {
+    let content = tokio::fs::read_to_string(work_dir.join(STAMP_FILE_NAME))
+        .await
+        .ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Record a successful build
+pub async fn write_stamp(work_dir: &Path, stamp: &BuildStamp) -> McpCoreResult<()> {
+    let path = work_dir.join(STAMP_FILE_NAME);
+    tokio::fs::write(&path, serde_json::to_string_pretty(stamp)?)
+        .await
+        .map_err(|e| McpCoreError::ProcessError {
+            message: format!("Failed to write build stamp '{}': {}", path.display(), e),
+        })
+}
+
+/// Remove the stamp so the next start rebuilds, returning whether one existed
+pub async fn invalidate(work_dir: &Path) -> McpCoreResult<bool> {
+    let path = work_dir.join(STAMP_FILE_NAME);
+    match tokio::fs::remove_file(&path).await {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(McpCoreError::ProcessError {
+            message: format!("Failed to remove build stamp '{}': {}", path.display(), e),
+        }),
+    }
+}
+
+/// Combined hash of the lockfiles present in `work_dir`
+///
+/// Lockfiles can be large, so they are hashed on the blocking thread pool.
+async fn lockfile_hash(work_dir: &Path) -> Option<String> {
+    let work_dir = work_dir.to_path_buf();
+    setup::blocking(move || {
+        let mut hash = FNV_OFFSET;
+        let mut found = false;
+        for name in LOCKFILES {
+            if let Ok(content) = std::fs::read(work_dir.join(name)) {
+                found = true;
+                hash = fnv1a_extend(hash, name.as_bytes());
+                hash = fnv1a_extend(hash, &content);
+            }
+        }
+        Ok(found.then(|| format!("{:016x}", hash)))
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// FNV-1a hash, stable across Rust releases unlike `DefaultHasher`
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    fnv1a_extend(FNV_OFFSET, bytes)
+}
+
+fn fnv1a_extend(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamp() -> BuildStamp {
+        BuildStamp {
+            commit: Some("abc".to_string()),
+            build_command_hash: format!("{:016x}", fnv1a(b"npm install")),
+            runtime_version: Some("v20.0.0".to_string()),
+            lockfile_hash: Some("0123".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_stale_stamp_detection() {
+        let current = stamp();
+        assert_eq!(current.stale_reason(Some(&stamp())), None);
+        assert_eq!(
+            current.stale_reason(None).as_deref(),
+            Some("no build stamp")
+        );
+
+        let previous = BuildStamp {
+            commit: Some("old".to_string()),
+            ..stamp()
+        };
+        assert!(current
+            .stale_reason(Some(&previous))
+            .unwrap()
+            .contains("commit changed"));
+
+        let previous = BuildStamp {
+            build_command_hash: format!("{:016x}", fnv1a(b"npm ci")),
+            ..stamp()
+        };
+        assert!(current.stale_reason(Some(&previous)).is_some());
+
+        let previous = BuildStamp {
+            runtime_version: Some("v18.0.0".to_string()),
+            ..stamp()
+        };
+        assert!(current
+            .stale_reason(Some(&previous))
+            .unwrap()
+            .contains("v18.0.0 -> v20.0.0"));
+
+        let previous = BuildStamp {
+            lockfile_hash: None,
+            ..stamp()
+        };
+        assert_eq!(
+            current.stale_reason(Some(&previous)).as_deref(),
+            Some("lockfiles changed")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stamp_roundtrip_and_lockfile_changes() {
+        let dir = std::env::temp_dir().join(format!("mcp-build-cache-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let first = BuildStamp::compute(&dir, "npm install", &[]).await;
+        assert_eq!(first.lockfile_hash, None);
+        write_stamp(&dir, &first).await.unwrap();
+        assert_eq!(read_stamp(&dir).await, Some(first.clone()));
+
+        std::fs::write(dir.join("package-lock.json"), "{}").unwrap();
+        let second = BuildStamp::compute(&dir, "npm install", &[]).await;
+        assert!(second
+            .stale_reason(read_stamp(&dir).await.as_ref())
+            .is_some());
+
+        assert!(invalidate(&dir).await.unwrap());
+        assert!(!invalidate(&dir).await.unwrap());
+        assert_eq!(read_stamp(&dir).await, None);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
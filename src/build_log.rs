@@ -0,0 +1,342 @@
+//! Output of the latest clone and build of a server
+//!
+//! The clone and build commands and `pre_start` hooks of a setup run record
+//! their output while running inside [`BuildRecorder::scope`]. When the run ends, the steps are
+//! written to [`LOG_FILE_NAME`] in the work directory, the previous runs
+//! rotated to `.mcp-build-log.1.json` and `.mcp-build-log.2.json`, and a
+//! smaller copy is kept in memory for `GET /admin/servers/{name}/build-log`.
+//!
+//! Output over the cap keeps its head and tail around a marker naming the
+//! bytes left out. Runs that neither cloned, built, nor ran a hook, such as
+//! restarts reusing both, leave the logs alone.
+
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::child_env::ChildEnv;
+
+/// File in the work directory holding the latest run
+pub const LOG_FILE_NAME: &str = ".mcp-build-log.json";
+
+/// Runs kept, the latest included
+pub const RUNS_KEPT: usize = 3;
+
+/// Output kept per stream of a step in the log files
+pub const MAX_FILE_OUTPUT_BYTES: usize = 1024 * 1024;
+
+/// Output kept per stream of a step in memory
+pub const MAX_MEMORY_OUTPUT_BYTES: usize = 64 * 1024;
+
+/// Command a step ran
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepKind {
+    Clone,
+    Build,
+    /// A `pre_start` hook, see [`crate::server_hooks`]
+    PreStart,
+    /// A `post_stop` hook, outside any run and so only logged
+    PostStop,
+}
+
+/// One command of a run
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BuildStep {
+    pub kind: StepKind,
+
+    /// Command line, secrets redacted
+    pub command: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+
+    /// `None` if the command was killed by a signal
+    pub exit_code: Option<i32>,
+    pub success: bool,
+
+    /// Output, secrets redacted and truncated
+    pub stdout: String,
+    pub stderr: String,
+
+    /// Size of the whole output of both streams
+    pub output_bytes: usize,
+    pub truncated: bool,
+}
+
+impl BuildStep {
+    /// Step of `kind` that ran `command` from `started_at` and produced `output`
+    pub fn new(
+        kind: StepKind,
+        command: &str,
+        started_at: DateTime<Utc>,
+        output: &std::process::Output,
+        env: &ChildEnv,
+    ) -> Self {
+        let stdout = env.redact(&String::from_utf8_lossy(&output.stdout));
+        let stderr = env.redact(&String::from_utf8_lossy(&output.stderr));
+        Self {
+            kind,
+            command: env.redact(command),
+            started_at,
+            finished_at: Utc::now(),
+            exit_code: output.status.code(),
+            success: output.status.success(),
+            output_bytes: output.stdout.len() + output.stderr.len(),
+            truncated: false,
+            stdout,
+            stderr,
+        }
+        .truncated_to(MAX_FILE_OUTPUT_BYTES)
+    }
+
+    fn truncated_to(mut self, max_bytes: usize) -> Self {
+        let (stdout, stdout_truncated) = truncate(&self.stdout, max_bytes);
+        let (stderr, stderr_truncated) = truncate(&self.stderr, max_bytes);
+        self.stdout = stdout;
+        self.stderr = stderr;
+        self.truncated |= stdout_truncated || stderr_truncated;
+        self
+    }
+}
+
+/// Clone and build steps of one setup run
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BuildRun {
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub steps: Vec<BuildStep>,
+
+    /// Why the setup failed, if it did
+    pub error: Option<String>,
+}
+
+impl BuildRun {
+    fn truncated_to(mut self, max_bytes: usize) -> Self {
+        self.steps = self
+            .steps
+            .into_iter()
+            .map(|step| step.truncated_to(max_bytes))
+            .collect();
+        self
+    }
+}
+
+tokio::task_local! {
+    static RECORDER: BuildRecorder;
+}
+
+/// Collects the steps of the run it scopes
+#[derive(Debug, Clone, Default)]
+pub struct BuildRecorder {
+    steps: Arc<Mutex<Vec<BuildStep>>>,
+}
+
+impl BuildRecorder {
+    /// Run `future`, recording the steps it runs
+    ///
+    /// Steps are recorded as they finish, so a cancelled run keeps those.
+    pub async fn scope<F: Future>(&self, future: F) -> F::Output {
+        RECORDER.scope(self.clone(), future).await
+    }
+
+    /// Steps recorded so far
+    pub fn steps(&self) -> Vec<BuildStep> {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<BuildStep>> {
+        self.steps
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Record `step` in the run being scoped, if any
+pub fn record_step(step: BuildStep) {
+    let _ = RECORDER.try_with(|recorder| recorder.lock().push(step));
+}
+
+/// Build logs of a server
+#[derive(Debug)]
+pub struct BuildLogs {
+    work_dir: PathBuf,
+    latest: Mutex<Option<BuildRun>>,
+}
+
+impl BuildLogs {
+    /// Logs kept in `work_dir`
+    pub fn new(work_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            work_dir: work_dir.into(),
+            latest: Mutex::new(None),
+        }
+    }
+
+    /// Keep `run` as the latest, rotating the older ones
+    ///
+    /// Runs without steps are not kept. Failing to write the files is
+    /// logged; the copy in memory is kept regardless.
+    pub async fn save(&self, run: BuildRun) {
+        if run.steps.is_empty() {
+            return;
+        }
+        if let Err(e) = self.write(&run).await {
+            tracing::warn!(
+                "Failed to write the build log in '{}': {}",
+                self.work_dir.display(),
+                e
+            );
+        }
+        *self.lock() = Some(run.truncated_to(MAX_MEMORY_OUTPUT_BYTES));
+    }
+
+    /// Run `previous` runs before the latest, `0` being the latest
+    ///
+    /// The latest run comes from memory, or from its file after the gateway
+    /// restarted; older ones always come from their files.
+    pub async fn get(&self, previous: usize) -> Option<BuildRun> {
+        if previous == 0 {
+            if let Some(run) = self.lock().clone() {
+                return Some(run);
+            }
+        }
+        if previous >= RUNS_KEPT {
+            return None;
+        }
+        let contents = tokio::fs::read(self.path(previous)).await.ok()?;
+        match serde_json::from_slice::<BuildRun>(&contents) {
+            Ok(run) => Some(run.truncated_to(MAX_MEMORY_OUTPUT_BYTES)),
+            Err(e) => {
+                tracing::warn!("Ignoring unreadable build log: {}", e);
+                None
+            }
+        }
+    }
+
+    async fn write(&self, run: &BuildRun) -> std::io::Result<()> {
+        for index in (1..RUNS_KEPT).rev() {
+            match tokio::fs::rename(self.path(index - 1), self.path(index)).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        let contents = serde_json::to_vec_pretty(run).map_err(std::io::Error::other)?;
+        tokio::fs::create_dir_all(&self.work_dir).await?;
+        tokio::fs::write(self.path(0), contents).await
+    }
+
+    fn path(&self, index: usize) -> PathBuf {
+        match index {
+            0 => self.work_dir.join(LOG_FILE_NAME),
+            index => self.work_dir.join(format!(".mcp-build-log.{}.json", index)),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<BuildRun>> {
+        self.latest
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Whether `path`, relative to a work directory, is one of the log files
+pub fn is_log_file(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|name| name.starts_with(".mcp-build-log.") && name.ends_with(".json"))
+}
+
+/// `text` cut to about `max_bytes`, keeping its head and tail, and whether
+/// it was cut
+fn truncate(text: &str, max_bytes: usize) -> (String, bool) {
+    if text.len() <= max_bytes {
+        return (text.to_string(), false);
+    }
+    let mut head = max_bytes / 2;
+    while !text.is_char_boundary(head) {
+        head -= 1;
+    }
+    let mut tail = text.len() - max_bytes / 2;
+    while !text.is_char_boundary(tail) {
+        tail += 1;
+    }
+    let marker = format!("\n[... {} bytes truncated ...]\n", tail - head);
+    (
+        format!("{}{}{}", &text[..head], marker, &text[tail..]),
+        true,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(marker: &str) -> BuildRun {
+        BuildRun {
+            started_at: Utc::now(),
+            finished_at: Utc::now(),
+            steps: vec![BuildStep {
+                kind: StepKind::Build,
+                command: "npm run build".to_string(),
+                started_at: Utc::now(),
+                finished_at: Utc::now(),
+                exit_code: Some(0),
+                success: true,
+                stdout: marker.to_string(),
+                stderr: String::new(),
+                output_bytes: marker.len(),
+                truncated: false,
+            }],
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_truncate_keeps_head_and_tail() {
+        assert_eq!(truncate("short", 10), ("short".to_string(), false));
+
+        let text = format!("HEAD{}TAIL", "x".repeat(1000));
+        let (truncated, cut) = truncate(&text, 100);
+        assert!(cut);
+        assert!(truncated.starts_with("HEAD"));
+        assert!(truncated.ends_with("TAIL"));
+        assert!(truncated.contains("[... 908 bytes truncated ...]"));
+
+        // Cuts fall on character boundaries
+        let (truncated, _) = truncate(&"é".repeat(100), 11);
+        assert!(truncated.contains("bytes truncated"));
+    }
+
+    #[tokio::test]
+    async fn test_runs_rotate() {
+        let work_dir = std::env::temp_dir().join(format!("mcp-build-log-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&work_dir);
+        let logs = BuildLogs::new(&work_dir);
+        assert!(logs.get(0).await.is_none());
+
+        for index in 0..4 {
+            logs.save(run(&format!("run {}", index))).await;
+        }
+        // A run without steps changes nothing
+        logs.save(BuildRun {
+            steps: Vec::new(),
+            ..run("empty")
+        })
+        .await;
+        assert_eq!(logs.get(0).await.unwrap().steps[0].stdout, "run 3");
+        assert_eq!(logs.get(2).await.unwrap().steps[0].stdout, "run 1");
+        assert!(logs.get(3).await.is_none());
+        assert!(!work_dir.join(".mcp-build-log.3.json").exists());
+
+        // After a gateway restart the latest run is read back from its file
+        let reloaded = BuildLogs::new(&work_dir);
+        assert_eq!(reloaded.get(0).await.unwrap().steps[0].stdout, "run 3");
+        assert!(is_log_file(Path::new(LOG_FILE_NAME)));
+        assert!(is_log_file(Path::new(".mcp-build-log.2.json")));
+        assert!(!is_log_file(Path::new(".mcp-build-stamp.json")));
+
+        let _ = std::fs::remove_dir_all(&work_dir);
+    }
+}
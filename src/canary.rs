@@ -0,0 +1,456 @@
+//! Canary routing between a server and a second configuration of it
+//!
+//! A server entry's `canary` block describes an alternate configuration: a
+//! repository ref to check out, or a command, arguments, and environment
+//! pinning another package version. The gateway runs it as a second child in
+//! its own work directory, `WORK_DIR_BASE/<server>-canary`, and sends
+//! `weight` percent of requests to it. Requests are assigned by a stable hash
+//! of their `Mcp-Session-Id` header or API key name, so a client stays on one
+//! variant while the weight is unchanged; requests with neither are spread
+//! evenly. Responses carry the variant in `X-MCP-Variant`, and requests and
+//! errors are counted per variant. The admin API changes the weight, promotes
+//! the canary to primary, or aborts it.
+
+use crate::error::{McpCoreError, McpCoreResult};
+use crate::history::{LifecycleHistory, LifecycleRecord, Transition, Trigger};
+use crate::transport::McpTransport;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, RwLock};
+use tokio::sync::Mutex;
+
+/// Response header naming the variant that answered
+pub const VARIANT_HEADER: &str = "x-mcp-variant";
+
+/// Request header whose value keeps a client on one variant
+pub const SESSION_HEADER: &str = "mcp-session-id";
+
+/// A shared transport to one child
+pub type SharedTransport = Arc<Mutex<Box<dyn McpTransport>>>;
+
+/// Alternate configuration of a server receiving part of its traffic
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CanaryConfig {
+    /// Percentage of requests sent to the canary, 0 to 100
+    pub weight: u8,
+
+    /// Branch, tag, or commit of `repository` the canary runs
+    #[serde(default, rename = "ref", skip_serializing_if = "Option::is_none")]
+    pub git_ref: Option<String>,
+
+    /// Command replacing the server's `command`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+
+    /// Arguments replacing the server's `args`, e.g. to pin a package version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub args: Option<Vec<String>>,
+
+    /// Variables added to or replacing the server's `env`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+}
+
+impl CanaryConfig {
+    /// Check the weight
+    pub fn validate(&self) -> Result<(), String> {
+        check_weight(self.weight)
+    }
+}
+
+fn check_weight(weight: u8) -> Result<(), String> {
+    if weight > 100 {
+        return Err(format!("weight {} is above 100", weight));
+    }
+    Ok(())
+}
+
+/// Name the canary of `server_name` runs under, which is also its work directory
+pub fn canary_name(server_name: &str) -> String {
+    format!("{}-canary", server_name)
+}
+
+/// Which configuration answered a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Variant {
+    Primary,
+    Canary,
+}
+
+impl Variant {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Variant::Primary => "primary",
+            Variant::Canary => "canary",
+        }
+    }
+}
+
+/// Where the canary is in its life
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CanaryState {
+    /// Being cloned, built, or spawned; requests go to the primary
+    Starting,
+    Running,
+
+    /// Setup failed; requests go to the primary
+    Failed,
+
+    /// Made the primary, whose old child was retired
+    Promoted,
+    Aborted,
+}
+
+/// Requests and errors of one variant
+#[derive(Debug, Clone, Serialize)]
+pub struct VariantSnapshot {
+    pub requests: u64,
+    pub errors: u64,
+
+    /// Errors per request, or `None` before the first request
+    pub error_rate: Option<f64>,
+}
+
+/// Canary of a server, served by the stats and admin endpoints
+#[derive(Debug, Clone, Serialize)]
+pub struct CanarySnapshot {
+    pub state: CanaryState,
+    pub weight: u8,
+
+    /// Why setup failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure: Option<String>,
+    pub primary: VariantSnapshot,
+    pub canary: VariantSnapshot,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    requests: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl Counts {
+    fn snapshot(&self) -> VariantSnapshot {
+        let requests = self.requests.load(Ordering::Relaxed);
+        let errors = self.errors.load(Ordering::Relaxed);
+        VariantSnapshot {
+            requests,
+            errors,
+            error_rate: (requests > 0).then(|| errors as f64 / requests as f64),
+        }
+    }
+}
+
+struct Status {
+    state: CanaryState,
+    failure: Option<String>,
+
+    /// Transport of the running canary
+    transport: Option<SharedTransport>,
+}
+
+/// Routes a server's requests between its primary and its canary
+pub struct CanaryRouter {
+    /// Whether the server has a canary at all; responses are tagged only then
+    configured: bool,
+    weight: AtomicU8,
+    status: RwLock<Status>,
+    counts: [Counts; 2],
+
+    /// Spreads requests without a session or API key
+    unkeyed: AtomicU64,
+}
+
+impl std::fmt::Debug for CanaryRouter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CanaryRouter")
+            .field("configured", &self.configured)
+            .field("weight", &self.weight)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for CanaryRouter {
+    /// Router of a server without a canary
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl CanaryRouter {
+    /// Router for `config`, with the canary starting
+    pub fn new(config: Option<&CanaryConfig>) -> Self {
+        Self {
+            configured: config.is_some(),
+            weight: AtomicU8::new(config.map_or(0, |config| config.weight)),
+            status: RwLock::new(Status {
+                state: CanaryState::Starting,
+                failure: None,
+                transport: None,
+            }),
+            counts: Default::default(),
+            unkeyed: AtomicU64::new(0),
+        }
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.configured
+    }
+
+    /// Send traffic to the canary set up on `transport`
+    pub fn start(&self, transport: SharedTransport) {
+        let mut status = self.write();
+        if status.state == CanaryState::Starting {
+            status.state = CanaryState::Running;
+            status.transport = Some(transport);
+        }
+    }
+
+    /// Record that the canary could not be set up
+    pub fn fail(&self, reason: String) {
+        let mut status = self.write();
+        if status.state == CanaryState::Starting {
+            status.state = CanaryState::Failed;
+            status.failure = Some(reason);
+        }
+    }
+
+    /// Pick the variant for a request keyed by `sticky_key`, with the
+    /// canary's transport if it is picked
+    pub fn route(&self, sticky_key: Option<&str>) -> (Variant, Option<SharedTransport>) {
+        let Some(transport) = self.read().transport.clone() else {
+            return (Variant::Primary, None);
+        };
+        let bucket = match sticky_key {
+            Some(key) => fnv1a(key.as_bytes()) % 100,
+            None => self.unkeyed.fetch_add(1, Ordering::Relaxed) % 100,
+        };
+        if bucket < u64::from(self.weight.load(Ordering::Relaxed)) {
+            (Variant::Canary, Some(transport))
+        } else {
+            (Variant::Primary, None)
+        }
+    }
+
+    /// Transport of the running canary, if any
+    pub fn transport(&self) -> Option<SharedTransport> {
+        self.read().transport.clone()
+    }
+
+    /// Count a request answered by `variant`
+    pub fn record(&self, variant: Variant, failed: bool) {
+        if !self.configured {
+            return;
+        }
+        let counts = &self.counts[variant as usize];
+        counts.requests.fetch_add(1, Ordering::Relaxed);
+        if failed {
+            counts.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Change the share of requests sent to the canary
+    pub fn set_weight(&self, weight: u8) -> McpCoreResult<()> {
+        self.check_configured()?;
+        check_weight(weight).map_err(|reason| McpCoreError::RequestError {
+            message: format!("Canary {}", reason),
+        })?;
+        self.weight.store(weight, Ordering::Relaxed);
+        tracing::info!("Canary weight set to {}%", weight);
+        Ok(())
+    }
+
+    /// Make the canary the primary and shut down the old primary child,
+    /// recording both in the primary's `history`
+    pub async fn promote(
+        &self,
+        primary: &SharedTransport,
+        history: &LifecycleHistory,
+    ) -> McpCoreResult<()> {
+        let canary = self.take_running(CanaryState::Promoted)?;
+        let mut primary = primary.lock().await;
+        let mut canary = canary.lock().await;
+        std::mem::swap(&mut *primary, &mut *canary);
+        history.record(
+            LifecycleRecord::new(Transition::Initialized, Trigger::Admin)
+                .with_pid(primary.pid())
+                .with_detail("promoted canary"),
+        );
+        drop(primary);
+        let retired = canary.pid();
+        if let Err(e) = canary.shutdown().await {
+            tracing::warn!("Failed to shut down the retired primary: {}", e);
+        }
+        history.record(
+            LifecycleRecord::new(Transition::Exited, Trigger::Admin)
+                .with_pid(retired)
+                .with_exit(canary.exit_status())
+                .with_detail("replaced by the promoted canary"),
+        );
+        tracing::info!("Canary promoted to primary");
+        Ok(())
+    }
+
+    /// Stop routing to the canary and shut its child down
+    pub async fn abort(&self) -> McpCoreResult<()> {
+        let canary = self.take_running(CanaryState::Aborted)?;
+        if let Err(e) = canary.lock().await.shutdown().await {
+            tracing::warn!("Failed to shut down the canary: {}", e);
+        }
+        tracing::info!("Canary aborted");
+        Ok(())
+    }
+
+    /// The canary, or `None` if the server has none
+    pub fn snapshot(&self) -> Option<CanarySnapshot> {
+        if !self.configured {
+            return None;
+        }
+        let status = self.read();
+        Some(CanarySnapshot {
+            state: status.state,
+            weight: self.weight.load(Ordering::Relaxed),
+            failure: status.failure.clone(),
+            primary: self.counts[Variant::Primary as usize].snapshot(),
+            canary: self.counts[Variant::Canary as usize].snapshot(),
+        })
+    }
+
+    /// End the running canary in `state`, returning its transport
+    fn take_running(&self, state: CanaryState) -> McpCoreResult<SharedTransport> {
+        self.check_configured()?;
+        let mut status = self.write();
+        let transport = status
+            .transport
+            .take()
+            .ok_or_else(|| McpCoreError::RequestError {
+                message: format!(
+                    "Canary is not running (state {})",
+                    serde_json::to_value(status.state).unwrap_or_default()
+                ),
+            })?;
+        status.state = state;
+        self.weight.store(0, Ordering::Relaxed);
+        Ok(transport)
+    }
+
+    fn check_configured(&self) -> McpCoreResult<()> {
+        if self.configured {
+            Ok(())
+        } else {
+            Err(McpCoreError::NotFound {
+                message: "Server has no canary configured".to_string(),
+            })
+        }
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Status> {
+        self.status.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Status> {
+        self.status.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// FNV-1a, stable across processes and Rust versions unlike `DefaultHasher`
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provision::Unprovisioned;
+
+    fn running(weight: u8) -> CanaryRouter {
+        let router = CanaryRouter::new(Some(&CanaryConfig {
+            weight,
+            ..CanaryConfig::default()
+        }));
+        router.start(Arc::new(Mutex::new(Box::new(Unprovisioned))));
+        router
+    }
+
+    #[test]
+    fn test_split_follows_weight() {
+        let router = running(20);
+        let canary = (0..10_000)
+            .filter(|i| router.route(Some(&format!("key-{}", i))).0 == Variant::Canary)
+            .count();
+        // 20% of 10,000 with a generous margin for the hash's spread
+        assert!(
+            (1_700..2_300).contains(&canary),
+            "{} went to the canary",
+            canary
+        );
+
+        let unkeyed = (0..1_000)
+            .filter(|_| router.route(None).0 == Variant::Canary)
+            .count();
+        assert_eq!(unkeyed, 200);
+
+        router.set_weight(0).unwrap();
+        assert_eq!(router.route(Some("key-1")).0, Variant::Primary);
+        assert!(router.set_weight(101).is_err());
+    }
+
+    #[test]
+    fn test_keys_stick_to_one_variant() {
+        let router = running(50);
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            let first = router.route(Some(&key)).0;
+            assert!((0..10).all(|_| router.route(Some(&key)).0 == first));
+        }
+
+        // Raising the weight only moves primary keys to the canary
+        let before: Vec<Variant> = (0..100)
+            .map(|i| router.route(Some(&format!("key-{}", i))).0)
+            .collect();
+        router.set_weight(80).unwrap();
+        for (i, variant) in before.into_iter().enumerate() {
+            if variant == Variant::Canary {
+                assert_eq!(router.route(Some(&format!("key-{}", i))).0, variant);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_abort_and_promote_end_routing() {
+        let router = running(100);
+        router.record(Variant::Canary, true);
+        router.record(Variant::Canary, false);
+        router.record(Variant::Primary, false);
+        let snapshot = router.snapshot().unwrap();
+        assert_eq!(snapshot.state, CanaryState::Running);
+        assert_eq!(snapshot.canary.error_rate, Some(0.5));
+        assert_eq!(snapshot.primary.error_rate, Some(0.0));
+
+        router.abort().await.unwrap();
+        assert_eq!(router.route(Some("key")).0, Variant::Primary);
+        assert_eq!(router.snapshot().unwrap().state, CanaryState::Aborted);
+        let primary: SharedTransport = Arc::new(Mutex::new(Box::new(Unprovisioned)));
+        assert!(router
+            .promote(&primary, &LifecycleHistory::default())
+            .await
+            .is_err());
+
+        // Setup failing leaves traffic on the primary
+        let failed = CanaryRouter::new(Some(&CanaryConfig::default()));
+        failed.fail("clone failed".to_string());
+        failed.start(Arc::new(Mutex::new(Box::new(Unprovisioned))));
+        assert_eq!(failed.route(None).0, Variant::Primary);
+        assert_eq!(
+            failed.snapshot().unwrap().failure.as_deref(),
+            Some("clone failed")
+        );
+        assert!(CanaryRouter::default().snapshot().is_none());
+    }
+}
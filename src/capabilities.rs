@@ -0,0 +1,252 @@
+//! External binaries a server needs from the execution environment
+//!
+//! Minimal images often lack `git` or a shell, and a server needing them
+//! would only fail once setup reaches the clone or build, with a spawn
+//! error. Before a server is set up, the gateway works out which binaries
+//! its configuration needs, looks each one up in PATH, and refuses to start
+//! with one report of everything missing, with a hint where a setting
+//! avoids the need:
+//!
+//! - `git` for `repository`
+//! - the shell of `shell` (`sh` by default) for `build_command`, or the
+//!   build's own program with `shell: "none"`
+//! - `command`, when it is a bare name and `skip_command_check` is unset
+//!
+//! Servers reached over a remote transport need none of them. Each binary
+//! is looked up once and the result kept for the life of the process; the
+//! results appear in `--doctor` and as `capabilities` in the stats.
+
+use crate::config::McpServerConfig;
+use crate::diagnostics;
+use crate::error::{McpCoreError, McpCoreResult};
+use crate::platform;
+use crate::transport::TransportConfig;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+/// A binary a server needs, and where it was found
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Requirement {
+    pub binary: String,
+
+    /// Setting that needs the binary
+    pub needed_for: &'static str,
+
+    /// Where the binary was found, or `None` if it is missing
+    pub path: Option<PathBuf>,
+
+    /// How to do without the binary, if a setting allows it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<&'static str>,
+}
+
+/// Results of looking binaries up in one PATH
+#[derive(Debug)]
+pub struct Capabilities {
+    path_var: Option<OsString>,
+    found: Mutex<HashMap<String, Option<PathBuf>>>,
+}
+
+impl Capabilities {
+    /// Capabilities of the directories in `path_var`
+    pub fn new(path_var: Option<OsString>) -> Self {
+        Self {
+            path_var,
+            found: Mutex::default(),
+        }
+    }
+
+    /// Capabilities of the process, by the PATH it started with
+    pub fn shared() -> &'static Self {
+        static SHARED: OnceLock<Capabilities> = OnceLock::new();
+        SHARED.get_or_init(|| Self::new(std::env::var_os("PATH")))
+    }
+
+    /// Where `binary` is, looking it up on first use only
+    pub fn find(&self, binary: &str) -> Option<PathBuf> {
+        let mut found = self
+            .found
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        found
+            .entry(binary.to_string())
+            .or_insert_with(|| diagnostics::find_executable_in(binary, self.path_var.as_deref()))
+            .clone()
+    }
+
+    /// Binaries `config` needs, with where each was found
+    pub fn requirements(&self, config: &McpServerConfig) -> Vec<Requirement> {
+        if !matches!(config.transport, TransportConfig::Stdio) {
+            return Vec::new();
+        }
+        let mut needed = Vec::new();
+        if config.repository.is_some() {
+            needed.push((
+                "git".to_string(),
+                "repository",
+                Some("install git, or remove `repository` and install the server in the image"),
+            ));
+        }
+        if let Some(build_command) = &config.build_command {
+            match config.shell.program() {
+                Some(shell) => needed.push((
+                    shell.to_string(),
+                    "build_command",
+                    Some("set `shell` to \"none\" if the build command needs no shell features"),
+                )),
+                None => {
+                    let program = platform::split(build_command)
+                        .ok()
+                        .and_then(|words| words.into_iter().next());
+                    needed.extend(program.map(|program| (program, "build_command", None)));
+                }
+            }
+        }
+        if !config.skip_command_check && Path::new(&config.command).components().count() == 1 {
+            needed.push((config.command.clone(), "command", None));
+        }
+
+        needed
+            .into_iter()
+            .map(|(binary, needed_for, hint)| Requirement {
+                path: self.find(&binary),
+                binary,
+                needed_for,
+                hint,
+            })
+            .collect()
+    }
+
+    /// Fail with every binary missing for `servers`, by server
+    pub fn check<'a>(
+        &self,
+        servers: impl IntoIterator<Item = (&'a str, &'a McpServerConfig)>,
+    ) -> McpCoreResult<()> {
+        let mut reports: Vec<String> = servers
+            .into_iter()
+            .filter_map(|(name, config)| {
+                let missing = missing(&self.requirements(config))?;
+                Some(format!("server '{}' needs {}", name, missing))
+            })
+            .collect();
+        if reports.is_empty() {
+            return Ok(());
+        }
+        reports.sort();
+        Err(McpCoreError::ConfigurationError {
+            message: format!("Binaries missing from PATH: {}", reports.join("; ")),
+        })
+    }
+}
+
+/// The missing ones of `requirements`, described, or `None` if all were found
+pub fn missing(requirements: &[Requirement]) -> Option<String> {
+    let missing: Vec<String> = requirements
+        .iter()
+        .filter(|requirement| requirement.path.is_none())
+        .map(|requirement| match requirement.hint {
+            Some(hint) => format!(
+                "{} for {} ({})",
+                requirement.binary, requirement.needed_for, hint
+            ),
+            None => format!("{} for {}", requirement.binary, requirement.needed_for),
+        })
+        .collect();
+    (!missing.is_empty()).then(|| missing.join(", "))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::config::McpServersConfig;
+
+    /// A PATH directory holding only `binaries`
+    fn stub_path(name: &str, binaries: &[&str]) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir =
+            std::env::temp_dir().join(format!("mcp-capabilities-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for binary in binaries {
+            let path = dir.join(binary);
+            std::fs::write(&path, "#!/bin/sh\n").unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        dir
+    }
+
+    fn config() -> McpServersConfig {
+        McpServersConfig::from_value(
+            serde_json::json!({
+                "servers": {
+                    "cloned": {
+                        "command": "node",
+                        "repository": "https://github.com/example/server.git",
+                        "build_command": "npm ci && npm run build"
+                    },
+                    "unquoted": {
+                        "command": "./bin/server",
+                        "build_command": "make build",
+                        "shell": "none"
+                    },
+                    "remote": {
+                        "command": "unused",
+                        "transport": { "kind": "http", "url": "http://127.0.0.1:1/mcp" }
+                    }
+                }
+            }),
+            "test configuration",
+        )
+        .unwrap()
+    }
+
+    fn servers(config: &McpServersConfig) -> Vec<(&str, &McpServerConfig)> {
+        config
+            .servers
+            .iter()
+            .map(|(name, server)| (name.as_str(), server))
+            .collect()
+    }
+
+    #[test]
+    fn test_missing_binaries_reported_per_server() {
+        let config = config();
+        let dir = stub_path("missing", &["node"]);
+        let capabilities = Capabilities::new(Some(dir.clone().into_os_string()));
+
+        let message = capabilities
+            .check(servers(&config))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            message.contains("server 'cloned' needs git for repository (install git"),
+            "{}",
+            message
+        );
+        assert!(message.contains("sh for build_command (set `shell` to \"none\""));
+        assert!(message.contains("server 'unquoted' needs make for build_command"));
+        assert!(!message.contains("node for command"));
+        assert!(!message.contains("remote"));
+        assert!(capabilities
+            .requirements(&config.servers["remote"])
+            .is_empty());
+
+        // Relative commands come from the work directory, not PATH
+        let unquoted = capabilities.requirements(&config.servers["unquoted"]);
+        assert_eq!(unquoted.len(), 1);
+
+        // Lookups are kept: binaries added later are not seen
+        let later = stub_path("missing", &["node", "git", "sh", "make"]);
+        assert_eq!(later, dir);
+        assert!(capabilities.check(servers(&config)).is_err());
+        let fresh = Capabilities::new(Some(dir.into_os_string()));
+        assert!(fresh.check(servers(&config)).is_ok());
+        let node = &fresh.requirements(&config.servers["cloned"])[2];
+        assert_eq!(node.binary, "node");
+        assert!(node.path.is_some());
+    }
+}
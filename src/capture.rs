@@ -0,0 +1,558 @@
+//! Targeted capture of request and response bodies
+//!
+//! A capture records the exchanges of one method, optionally of one tool,
+//! in memory while a misbehaving server is debugged. It is started by a
+//! server's `capture` or by `POST /admin/capture`, and read with
+//! `GET /admin/capture/{id}`. Bodies are stored with the values of
+//! secret-looking keys and secret patterns in strings redacted.
+//!
+//! A capture stops recording after `max_bodies` exchanges and is dropped,
+//! bodies and all, `ttl_secs` after it started. A server runs at most one
+//! capture at a time, and the captures of the process share a budget of
+//! [`MAX_TOTAL_BYTES`]; exchanges beyond it are counted but not stored. As
+//! captures hold client data, starting and ending one is logged and a running
+//! one is reported under `capture` in the server's info and stats.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use crate::child_env::{is_secret_key, redact_command_line};
+use crate::error::{McpCoreError, McpCoreResult};
+use crate::injection::REDACTED;
+
+/// Bytes of bodies held by all captures of the process
+pub const MAX_TOTAL_BYTES: usize = 32 * 1024 * 1024;
+
+/// Bodies larger than this are stored as a note of their size
+pub const MAX_BODY_BYTES: usize = 256 * 1024;
+
+/// Upper bound of `max_bodies`
+pub const MAX_BODIES: usize = 1000;
+
+/// Upper bound of `ttl_secs`, one day
+pub const MAX_TTL_SECS: u64 = 24 * 60 * 60;
+
+/// Which exchanges to capture, and for how long
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CaptureRule {
+    /// JSON-RPC method of the captured requests
+    pub method: String,
+
+    /// Only requests whose `params.name` is this tool
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool: Option<String>,
+
+    /// Exchanges recorded before the capture stops (default 50)
+    #[serde(default = "default_max_bodies")]
+    pub max_bodies: usize,
+
+    /// Seconds until the capture and its bodies are dropped (default 600)
+    #[serde(default = "default_ttl_secs")]
+    pub ttl_secs: u64,
+}
+
+fn default_max_bodies() -> usize {
+    50
+}
+
+fn default_ttl_secs() -> u64 {
+    600
+}
+
+impl CaptureRule {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.method.is_empty() {
+            return Err("capture method must not be empty".to_string());
+        }
+        if self.max_bodies == 0 || self.max_bodies > MAX_BODIES {
+            return Err(format!(
+                "capture max_bodies must be between 1 and {}",
+                MAX_BODIES
+            ));
+        }
+        if self.ttl_secs == 0 || self.ttl_secs > MAX_TTL_SECS {
+            return Err(format!(
+                "capture ttl_secs must be between 1 and {}",
+                MAX_TTL_SECS
+            ));
+        }
+        Ok(())
+    }
+
+    fn matches(&self, method: Option<&str>, tool: Option<&str>) -> bool {
+        method == Some(self.method.as_str())
+            && self
+                .tool
+                .as_deref()
+                .is_none_or(|wanted| tool == Some(wanted))
+    }
+}
+
+/// Memory held by captures, shared across servers
+#[derive(Debug)]
+pub struct CaptureBudget {
+    max_bytes: usize,
+    used: AtomicUsize,
+}
+
+impl CaptureBudget {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    /// The budget shared by every server in the process
+    pub fn shared() -> Arc<Self> {
+        static SHARED: OnceLock<Arc<CaptureBudget>> = OnceLock::new();
+        Arc::clone(SHARED.get_or_init(|| Arc::new(Self::new(MAX_TOTAL_BYTES))))
+    }
+
+    fn reserve(&self, bytes: usize) -> bool {
+        self.used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                used.checked_add(bytes)
+                    .filter(|total| *total <= self.max_bytes)
+            })
+            .is_ok()
+    }
+
+    fn release(&self, bytes: usize) {
+        self.used.fetch_sub(bytes, Ordering::AcqRel);
+    }
+
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Acquire)
+    }
+}
+
+/// One recorded exchange
+#[derive(Debug, Clone, Serialize)]
+pub struct CapturedExchange {
+    pub at: DateTime<Utc>,
+    pub duration_ms: u64,
+    pub request: Value,
+
+    /// Response the client received
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<Value>,
+
+    /// Error the client received instead of a response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// State of a capture, as reported by the status endpoints
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureSummary {
+    pub id: String,
+    pub server: String,
+    #[serde(flatten)]
+    pub rule: CaptureRule,
+    pub started_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub captured: usize,
+
+    /// Matching exchanges not stored because the memory budget was spent
+    pub dropped: u64,
+    pub bytes: usize,
+
+    /// Whether `max_bodies` exchanges were recorded
+    pub full: bool,
+}
+
+/// A capture with its bodies, served by `GET /admin/capture/{id}`
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureReport {
+    #[serde(flatten)]
+    pub summary: CaptureSummary,
+    pub exchanges: Vec<CapturedExchange>,
+}
+
+#[derive(Debug)]
+struct Capture {
+    id: String,
+    rule: CaptureRule,
+    started_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    exchanges: Vec<CapturedExchange>,
+    bytes: usize,
+    dropped: u64,
+    budget: Arc<CaptureBudget>,
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        self.budget.release(self.bytes);
+    }
+}
+
+/// The capture of a server, if one is running
+#[derive(Debug)]
+pub struct Captures {
+    server_name: String,
+    budget: Arc<CaptureBudget>,
+    active: Mutex<Option<Capture>>,
+    next_id: AtomicU64,
+}
+
+impl Captures {
+    /// Captures of `server_name`, drawing on the process-wide budget
+    pub fn new(server_name: &str) -> Self {
+        Self::with_budget(server_name, CaptureBudget::shared())
+    }
+
+    pub fn with_budget(server_name: &str, budget: Arc<CaptureBudget>) -> Self {
+        Self {
+            server_name: server_name.to_string(),
+            budget,
+            active: Mutex::new(None),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<Capture>> {
+        self.active
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Start capturing, unless a capture is already running
+    ///
+    /// The capture is dropped once its ttl has passed.
+    pub fn start(
+        self: &Arc<Self>,
+        rule: CaptureRule,
+        now: DateTime<Utc>,
+    ) -> McpCoreResult<CaptureSummary> {
+        rule.validate()
+            .map_err(|message| McpCoreError::RequestError { message })?;
+        let summary = {
+            let mut active = self.lock();
+            self.expire_locked(&mut active, now);
+            if let Some(capture) = active.as_ref() {
+                return Err(McpCoreError::RequestError {
+                    message: format!(
+                        "Server '{}' is already capturing as '{}'",
+                        self.server_name, capture.id
+                    ),
+                });
+            }
+            let ttl = Duration::from_secs(rule.ttl_secs);
+            let capture = Capture {
+                id: format!("capture-{}", self.next_id.fetch_add(1, Ordering::Relaxed)),
+                rule,
+                started_at: now,
+                expires_at: now + ttl,
+                exchanges: Vec::new(),
+                bytes: 0,
+                dropped: 0,
+                budget: Arc::clone(&self.budget),
+            };
+            let summary = self.summarize(&capture);
+            *active = Some(capture);
+            summary
+        };
+        tracing::warn!(
+            "Capturing request and response bodies of server '{}' as '{}' ({}{}) until {}",
+            self.server_name,
+            summary.id,
+            summary.rule.method,
+            summary
+                .rule
+                .tool
+                .as_deref()
+                .map(|tool| format!(" {}", tool))
+                .unwrap_or_default(),
+            summary.expires_at.to_rfc3339()
+        );
+
+        let (captures, id) = (Arc::downgrade(self), summary.id.clone());
+        let ttl = Duration::from_secs(summary.rule.ttl_secs);
+        tokio::spawn(async move {
+            tokio::time::sleep(ttl).await;
+            if let Some(captures) = captures.upgrade() {
+                let _ = captures.stop(&id);
+            }
+        });
+        Ok(summary)
+    }
+
+    /// End capture `id` and drop its bodies
+    pub fn stop(&self, id: &str) -> McpCoreResult<CaptureSummary> {
+        let mut active = self.lock();
+        match active.take_if(|capture| capture.id == id) {
+            Some(capture) => {
+                tracing::warn!(
+                    "Capture '{}' of server '{}' ended",
+                    capture.id,
+                    self.server_name
+                );
+                Ok(self.summarize(&capture))
+            }
+            None => Err(self.not_found(id)),
+        }
+    }
+
+    /// Capture `id` with its bodies
+    pub fn get(&self, id: &str, now: DateTime<Utc>) -> McpCoreResult<CaptureReport> {
+        let mut active = self.lock();
+        self.expire_locked(&mut active, now);
+        match active.as_ref().filter(|capture| capture.id == id) {
+            Some(capture) => Ok(CaptureReport {
+                summary: self.summarize(capture),
+                exchanges: capture.exchanges.clone(),
+            }),
+            None => Err(self.not_found(id)),
+        }
+    }
+
+    /// The running capture, if any
+    pub fn snapshot(&self, now: DateTime<Utc>) -> Option<CaptureSummary> {
+        let mut active = self.lock();
+        self.expire_locked(&mut active, now);
+        active.as_ref().map(|capture| self.summarize(capture))
+    }
+
+    /// Record an exchange if the running capture matches it
+    ///
+    /// `request` is the command the client sent; `outcome` what it received.
+    pub fn record(
+        &self,
+        request: &str,
+        outcome: Result<&str, &McpCoreError>,
+        duration: Duration,
+        now: DateTime<Utc>,
+    ) {
+        if self.lock().is_none() {
+            return;
+        }
+        let Ok(request) = serde_json::from_str::<Value>(request) else {
+            return;
+        };
+        let method = request.get("method").and_then(Value::as_str);
+        let tool = request.pointer("/params/name").and_then(Value::as_str);
+
+        let mut active = self.lock();
+        self.expire_locked(&mut active, now);
+        let Some(capture) = active
+            .as_mut()
+            .filter(|capture| capture.rule.matches(method, tool))
+        else {
+            return;
+        };
+        if capture.exchanges.len() >= capture.rule.max_bodies {
+            return;
+        }
+
+        let (response, error) = match outcome {
+            Ok(response) => (
+                Some(
+                    serde_json::from_str(response)
+                        .unwrap_or_else(|_| Value::String(response.to_string())),
+                ),
+                None,
+            ),
+            Err(e) => (None, Some(redact_command_line(&e.to_string()))),
+        };
+        let exchange = CapturedExchange {
+            at: now,
+            duration_ms: duration.as_millis() as u64,
+            request: bounded(redact(&request)),
+            response: response.map(|response| bounded(redact(&response))),
+            error,
+        };
+        let bytes = serde_json::to_string(&exchange).map_or(0, |json| json.len());
+        if !self.budget.reserve(bytes) {
+            capture.dropped += 1;
+            return;
+        }
+        capture.bytes += bytes;
+        capture.exchanges.push(exchange);
+        if capture.exchanges.len() == capture.rule.max_bodies {
+            tracing::info!(
+                "Capture '{}' of server '{}' is full",
+                capture.id,
+                self.server_name
+            );
+        }
+    }
+
+    fn expire_locked(&self, active: &mut Option<Capture>, now: DateTime<Utc>) {
+        if let Some(capture) = active.take_if(|capture| capture.expires_at <= now) {
+            tracing::warn!(
+                "Capture '{}' of server '{}' expired",
+                capture.id,
+                self.server_name
+            );
+        }
+    }
+
+    fn summarize(&self, capture: &Capture) -> CaptureSummary {
+        CaptureSummary {
+            id: capture.id.clone(),
+            server: self.server_name.clone(),
+            rule: capture.rule.clone(),
+            started_at: capture.started_at,
+            expires_at: capture.expires_at,
+            captured: capture.exchanges.len(),
+            dropped: capture.dropped,
+            bytes: capture.bytes,
+            full: capture.exchanges.len() >= capture.rule.max_bodies,
+        }
+    }
+
+    fn not_found(&self, id: &str) -> McpCoreError {
+        McpCoreError::NotFound {
+            message: format!(
+                "No capture '{}' of server '{}'; it may have expired",
+                id, self.server_name
+            ),
+        }
+    }
+}
+
+/// Copy of `value` with the values of secret-looking keys replaced and
+/// secret patterns in strings hidden
+fn redact(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => map
+            .iter()
+            .map(|(key, value)| {
+                let value = if is_secret_key(key) {
+                    Value::String(REDACTED.to_string())
+                } else {
+                    redact(value)
+                };
+                (key.clone(), value)
+            })
+            .collect(),
+        Value::Array(items) => items.iter().map(redact).collect(),
+        Value::String(text) => Value::String(redact_command_line(text)),
+        other => other.clone(),
+    }
+}
+
+/// `value`, or a note of its size if it is over [`MAX_BODY_BYTES`]
+fn bounded(value: Value) -> Value {
+    let bytes = value.to_string().len();
+    if bytes > MAX_BODY_BYTES {
+        Value::String(format!("[{} bytes, not captured]", bytes))
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(tool: Option<&str>, max_bodies: usize) -> CaptureRule {
+        CaptureRule {
+            method: "tools/call".to_string(),
+            tool: tool.map(str::to_string),
+            max_bodies,
+            ttl_secs: 60,
+        }
+    }
+
+    fn call(tool: &str) -> String {
+        serde_json::json!({
+            "jsonrpc": "2.0", "id": 1, "method": "tools/call",
+            "params": { "name": tool, "arguments": { "query": "rust", "api_key": "sk-1234" } }
+        })
+        .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_records_matching_exchanges_redacted() {
+        let captures = Arc::new(Captures::with_budget(
+            "fs",
+            Arc::new(CaptureBudget::new(MAX_TOTAL_BYTES)),
+        ));
+        let now = Utc::now();
+        let id = captures.start(rule(Some("search"), 2), now).unwrap().id;
+        assert!(captures.start(rule(None, 1), now).is_err());
+
+        let response = r#"{"jsonrpc":"2.0","id":1,"result":{"content":[{"type":"text","text":"Authorization: Bearer abc123"}]}}"#;
+        let record = |request: &str, outcome| {
+            captures.record(request, outcome, Duration::from_millis(5), now)
+        };
+        record(&call("search"), Ok(response));
+        record(&call("fetch"), Ok(response));
+        record(
+            r#"{"jsonrpc":"2.0","id":2,"method":"tools/list"}"#,
+            Ok(response),
+        );
+        let error = McpCoreError::RequestTimeout {
+            message: "took too long".to_string(),
+            timeout_secs: 1,
+            limit: "default".to_string(),
+        };
+        record(&call("search"), Err(&error));
+        // max_bodies reached: no longer recorded
+        record(&call("search"), Ok(response));
+
+        let report = captures.get(&id, now).unwrap();
+        assert_eq!(report.summary.captured, 2);
+        assert!(report.summary.full);
+        let first = &report.exchanges[0];
+        assert_eq!(first.request["params"]["arguments"]["api_key"], REDACTED);
+        assert_eq!(first.request["params"]["arguments"]["query"], "rust");
+        assert_eq!(
+            first.response.as_ref().unwrap()["result"]["content"][0]["text"],
+            format!("Authorization: {}", REDACTED)
+        );
+        assert_eq!(
+            report.exchanges[1].error.as_deref(),
+            Some("Request timed out: took too long")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expiry_and_budget() {
+        let budget = Arc::new(CaptureBudget::new(600));
+        let captures = Arc::new(Captures::with_budget("fs", Arc::clone(&budget)));
+        let now = Utc::now();
+        let id = captures.start(rule(None, 10), now).unwrap().id;
+
+        let response = r#"{"jsonrpc":"2.0","id":1,"result":{}}"#;
+        for _ in 0..5 {
+            captures.record(&call("search"), Ok(response), Duration::ZERO, now);
+        }
+        let summary = captures.snapshot(now).unwrap();
+        assert!(summary.captured >= 1 && summary.captured < 5);
+        assert_eq!(summary.captured as u64 + summary.dropped, 5);
+        assert_eq!(budget.used(), summary.bytes);
+
+        // Past the ttl the capture and its bodies are gone
+        let later = now + Duration::from_secs(60);
+        assert!(captures.snapshot(later).is_none());
+        assert!(matches!(
+            captures.get(&id, later),
+            Err(McpCoreError::NotFound { .. })
+        ));
+        assert_eq!(budget.used(), 0);
+
+        // A new capture can start once the old one is gone
+        let id = captures.start(rule(None, 1), later).unwrap().id;
+        assert_eq!(id, "capture-2");
+        assert!(captures.stop(&id).is_ok());
+        assert!(captures.stop(&id).is_err());
+    }
+
+    #[test]
+    fn test_rule_validation() {
+        assert!(rule(None, 50).validate().is_ok());
+        assert!(rule(None, 0).validate().unwrap_err().contains("max_bodies"));
+        assert!(rule(None, MAX_BODIES + 1).validate().is_err());
+        let mut forever = rule(None, 1);
+        forever.ttl_secs = MAX_TTL_SECS + 1;
+        assert!(forever.validate().unwrap_err().contains("ttl_secs"));
+    }
+}
@@ -0,0 +1,510 @@
+//! Client for calling a gateway over HTTP
+//!
+//! [`McpHttpClient`] sends commands to `POST /api/v1`, decodes structured
+//! error bodies into [`ClientError`], and retries requests the gateway shed
+//! before forwarding them. Requests and responses use the same types as the
+//! server, so their shapes cannot drift apart. Elicitations published by the
+//! gateway can be followed with [`McpHttpClient::elicitations`].
+
+use crate::elicitation::Elicitation;
+use crate::error::ErrorBody;
+use crate::process::{McpRequest, McpResponse};
+use crate::tool_schema::SchemaViolation;
+use chrono::{DateTime, Utc};
+use futures_util::Stream;
+use reqwest::header::{ACCEPT, CONTENT_TYPE, RETRY_AFTER};
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use thiserror::Error;
+
+/// Default time a request may take, including the MCP server's work
+pub const DEFAULT_CLIENT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Errors returned by [`McpHttpClient`]
+#[derive(Error, Debug)]
+pub enum ClientError {
+    /// The gateway rejected the API key
+    #[error("{message}")]
+    Unauthorized { message: String },
+
+    /// The command failed a gateway check; `code` names the check
+    #[error("{message}")]
+    InvalidCommand { code: String, message: String },
+
+    /// Tool arguments did not match the tool's input schema
+    #[error("{message}")]
+    InvalidArguments {
+        message: String,
+        errors: Vec<SchemaViolation>,
+    },
+
+    /// The gateway shed the request before forwarding it
+    #[error("{message}")]
+    Overloaded {
+        message: String,
+        retry_after: Option<Duration>,
+    },
+
+    /// The API key used up a quota; `quota` names it
+    #[error("{message}")]
+    QuotaExceeded {
+        message: String,
+        quota: Option<String>,
+        resets_at: Option<DateTime<Utc>>,
+    },
+
+    /// The server was drained for maintenance
+    #[error("{message}")]
+    Maintenance {
+        message: String,
+        maintenance_message: Option<String>,
+    },
+
+    /// Any other error response from the gateway
+    #[error("{message} (HTTP {status})")]
+    Api {
+        status: u16,
+        code: Option<String>,
+        message: String,
+    },
+
+    /// The MCP server answered with a JSON-RPC error
+    #[error("JSON-RPC error {code}: {message}")]
+    JsonRpc {
+        code: i64,
+        message: String,
+        data: Option<Value>,
+    },
+
+    /// The gateway could not be reached or the request timed out
+    #[error("HTTP request failed: {0}")]
+    Transport(#[from] reqwest::Error),
+
+    /// The gateway's answer could not be decoded
+    #[error("Invalid response: {message}")]
+    InvalidResponse { message: String },
+}
+
+/// Convenient Result type for client operations
+pub type ClientResult<T> = Result<T, ClientError>;
+
+impl ClientError {
+    /// Decode an error response of the gateway
+    fn from_response(status: StatusCode, retry_after: Option<Duration>, body: &[u8]) -> Self {
+        let body = serde_json::from_slice::<ErrorBody>(body).unwrap_or_else(|_| ErrorBody {
+            error: status.canonical_reason().unwrap_or("Error").to_string(),
+            message: String::from_utf8_lossy(body).into_owned(),
+            code: None,
+            maintenance_message: None,
+            quota: None,
+            resets_at: None,
+            timeout_secs: None,
+            timeout_limit: None,
+            errors: Vec::new(),
+            content_type: None,
+            line: None,
+            column: None,
+        });
+        match (status, body.code) {
+            (StatusCode::UNAUTHORIZED, _) => ClientError::Unauthorized {
+                message: body.message,
+            },
+            (StatusCode::SERVICE_UNAVAILABLE, Some(code)) if code == "overloaded" => {
+                ClientError::Overloaded {
+                    message: body.message,
+                    retry_after,
+                }
+            }
+            (StatusCode::TOO_MANY_REQUESTS, Some(code)) if code == "quota_exceeded" => {
+                ClientError::QuotaExceeded {
+                    message: body.message,
+                    quota: body.quota,
+                    resets_at: body.resets_at,
+                }
+            }
+            (StatusCode::SERVICE_UNAVAILABLE, Some(code)) if code == "maintenance" => {
+                ClientError::Maintenance {
+                    message: body.message,
+                    maintenance_message: body.maintenance_message,
+                }
+            }
+            (StatusCode::UNPROCESSABLE_ENTITY, _) => ClientError::InvalidArguments {
+                message: body.message,
+                errors: body.errors,
+            },
+            (StatusCode::BAD_REQUEST, Some(code)) => ClientError::InvalidCommand {
+                code,
+                message: body.message,
+            },
+            (status, code) => ClientError::Api {
+                status: status.as_u16(),
+                code,
+                message: body.message,
+            },
+        }
+    }
+
+    /// Whether the request is known not to have reached the MCP server
+    fn is_retryable(&self) -> bool {
+        match self {
+            ClientError::Overloaded { .. } => true,
+            ClientError::Transport(e) => e.is_connect(),
+            _ => false,
+        }
+    }
+}
+
+/// When requests are retried
+///
+/// Only requests that never reached the MCP server are retried: those shed
+/// as overloaded and those whose connection could not be established.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Attempts including the first; 1 disables retries
+    pub max_attempts: u32,
+
+    /// Wait before the first retry, doubled for each one after it
+    pub initial_backoff: Duration,
+
+    /// Longest wait, also capping the gateway's `Retry-After`
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retry
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Wait before retry number `retry` (from 0) after `error`
+    fn backoff(&self, retry: u32, error: &ClientError) -> Duration {
+        let exponential = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry));
+        let wait = match error {
+            ClientError::Overloaded {
+                retry_after: Some(retry_after),
+                ..
+            } => *retry_after,
+            _ => exponential,
+        };
+        wait.min(self.max_backoff)
+    }
+}
+
+/// Client of a gateway's HTTP API
+#[derive(Debug, Clone)]
+pub struct McpHttpClient {
+    client: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+    timeout: Duration,
+    retry: RetryPolicy,
+    next_id: std::sync::Arc<AtomicU64>,
+}
+
+impl McpHttpClient {
+    /// Client of the gateway at `base_url`, e.g. `http://localhost:3000`
+    pub fn new(base_url: &str, api_key: Option<&str>) -> ClientResult<Self> {
+        Ok(Self {
+            client: reqwest::Client::builder().build()?,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.map(str::to_string),
+            timeout: DEFAULT_CLIENT_TIMEOUT,
+            retry: RetryPolicy::default(),
+            next_id: Default::default(),
+        })
+    }
+
+    /// Time each attempt of a request may take
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// When failed requests are retried
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Send a raw command and return the MCP server's raw response
+    pub async fn query(&self, command: &str) -> ClientResult<String> {
+        let request = serde_json::to_vec(&McpRequest {
+            command: command.to_string(),
+        })
+        .unwrap_or_default();
+        let body = self
+            .with_retries(|| async {
+                let response = self
+                    .request(reqwest::Method::POST, "/api/v1")
+                    .header(ACCEPT, "application/json")
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(request.clone())
+                    .timeout(self.timeout)
+                    .send()
+                    .await?;
+                Self::body(response).await
+            })
+            .await?;
+        let response: McpResponse =
+            serde_json::from_slice(&body).map_err(|e| ClientError::InvalidResponse {
+                message: format!("Failed to decode query response: {}", e),
+            })?;
+        Ok(response.result)
+    }
+
+    /// Tools offered by the MCP server
+    pub async fn list_tools(&self) -> ClientResult<Vec<Value>> {
+        let result = self.call("tools/list", None).await?;
+        match result.get("tools") {
+            Some(Value::Array(tools)) => Ok(tools.clone()),
+            _ => Err(ClientError::InvalidResponse {
+                message: "tools/list result has no 'tools' array".to_string(),
+            }),
+        }
+    }
+
+    /// Call a tool and return the `result` of the call
+    pub async fn call_tool(&self, name: &str, arguments: Value) -> ClientResult<Value> {
+        self.call(
+            "tools/call",
+            Some(serde_json::json!({ "name": name, "arguments": arguments })),
+        )
+        .await
+    }
+
+    /// Follow the elicitations the gateway publishes from now on
+    ///
+    /// The stream ends when the gateway closes the connection.
+    pub async fn elicitations(
+        &self,
+    ) -> ClientResult<impl Stream<Item = ClientResult<Elicitation>>> {
+        let response = self
+            .request(reqwest::Method::GET, "/api/v1/elicitations/events")
+            .header(ACCEPT, "text/event-stream")
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(Self::body(response).await.unwrap_err());
+        }
+        Ok(futures_util::stream::unfold(
+            (response, String::new()),
+            |(mut response, mut buffer)| async move {
+                loop {
+                    if let Some(end) = buffer.find("\n\n") {
+                        let event: String = buffer.drain(..end + 2).collect();
+                        match parse_elicitation_event(&event) {
+                            Some(elicitation) => {
+                                return Some((elicitation, (response, buffer)));
+                            }
+                            None => continue,
+                        }
+                    }
+                    match response.chunk().await {
+                        Ok(Some(chunk)) => {
+                            buffer.push_str(&String::from_utf8_lossy(&chunk).replace('\r', ""))
+                        }
+                        Ok(None) => return None,
+                        Err(e) => return Some((Err(e.into()), (response, String::new()))),
+                    }
+                }
+            },
+        ))
+    }
+
+    /// Answer a pending elicitation with its `result`
+    pub async fn answer_elicitation(&self, id: &str, result: Value) -> ClientResult<()> {
+        let response = self
+            .request(
+                reqwest::Method::POST,
+                &format!("/api/v1/elicitations/{}", id),
+            )
+            .header(CONTENT_TYPE, "application/json")
+            .body(result.to_string())
+            .timeout(self.timeout)
+            .send()
+            .await?;
+        Self::body(response).await.map(|_| ())
+    }
+
+    /// Send a JSON-RPC request and return its `result`
+    async fn call(&self, method: &str, params: Option<Value>) -> ClientResult<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut request = serde_json::json!({ "jsonrpc": "2.0", "id": id, "method": method });
+        if let Some(params) = params {
+            request["params"] = params;
+        }
+        let raw = self.query(&request.to_string()).await?;
+        let mut response: Value =
+            serde_json::from_str(&raw).map_err(|e| ClientError::InvalidResponse {
+                message: format!("MCP server response is not JSON: {}", e),
+            })?;
+        if let Some(error) = response.get("error") {
+            return Err(ClientError::JsonRpc {
+                code: error.get("code").and_then(Value::as_i64).unwrap_or(0),
+                message: error
+                    .get("message")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string(),
+                data: error.get("data").cloned(),
+            });
+        }
+        match response.get_mut("result") {
+            Some(result) => Ok(result.take()),
+            None => Err(ClientError::InvalidResponse {
+                message: format!("Response to {} has neither result nor error", method),
+            }),
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let request = self
+            .client
+            .request(method, format!("{}{}", self.base_url, path));
+        match &self.api_key {
+            Some(api_key) => request.bearer_auth(api_key),
+            None => request,
+        }
+    }
+
+    /// Body of a successful response, or the decoded error
+    async fn body(response: reqwest::Response) -> ClientResult<Vec<u8>> {
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs);
+        let body = response.bytes().await?;
+        if status.is_success() {
+            Ok(body.to_vec())
+        } else {
+            Err(ClientError::from_response(status, retry_after, &body))
+        }
+    }
+
+    async fn with_retries<F, Fut, T>(&self, attempt: F) -> ClientResult<T>
+    where
+        F: Fn() -> Fut,
+        Fut: std::future::Future<Output = ClientResult<T>>,
+    {
+        let mut retry = 0;
+        loop {
+            match attempt().await {
+                Err(e) if e.is_retryable() && retry + 1 < self.retry.max_attempts => {
+                    let wait = self.retry.backoff(retry, &e);
+                    tracing::debug!("Retrying in {:?} after: {}", wait, e);
+                    tokio::time::sleep(wait).await;
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// The elicitation in one server-sent event, if it carries one
+fn parse_elicitation_event(event: &str) -> Option<ClientResult<Elicitation>> {
+    let mut name = None;
+    let mut data = Vec::new();
+    for line in event.lines() {
+        if let Some(value) = line.strip_prefix("event:") {
+            name = Some(value.trim());
+        } else if let Some(value) = line.strip_prefix("data:") {
+            data.push(value.strip_prefix(' ').unwrap_or(value));
+        }
+    }
+    if name != Some("elicitation") {
+        return None;
+    }
+    Some(
+        serde_json::from_str(&data.join("\n")).map_err(|e| ClientError::InvalidResponse {
+            message: format!("Invalid elicitation event: {}", e),
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_bodies_are_typed() {
+        let body = |code: Option<&str>| {
+            serde_json::to_vec(&ErrorBody {
+                error: "Error".to_string(),
+                message: "refused".to_string(),
+                code: code.map(str::to_string),
+                maintenance_message: None,
+                quota: None,
+                resets_at: None,
+                timeout_secs: None,
+                timeout_limit: None,
+                errors: Vec::new(),
+                content_type: None,
+                line: None,
+                column: None,
+            })
+            .unwrap()
+        };
+        assert!(matches!(
+            ClientError::from_response(StatusCode::UNAUTHORIZED, None, &body(None)),
+            ClientError::Unauthorized { .. }
+        ));
+        assert!(matches!(
+            ClientError::from_response(StatusCode::BAD_REQUEST, None, &body(Some("too_large"))),
+            ClientError::InvalidCommand { code, .. } if code == "too_large"
+        ));
+        let overloaded = ClientError::from_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            Some(Duration::from_secs(2)),
+            &body(Some("overloaded")),
+        );
+        assert!(overloaded.is_retryable());
+        let quota = ClientError::from_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            None,
+            &body(Some("quota_exceeded")),
+        );
+        assert!(matches!(quota, ClientError::QuotaExceeded { .. }));
+        assert!(!quota.is_retryable());
+        assert!(matches!(
+            ClientError::from_response(StatusCode::GATEWAY_TIMEOUT, None, b"not json"),
+            ClientError::Api { status: 504, message, .. } if message == "not json"
+        ));
+
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(0, &overloaded), Duration::from_secs(2));
+        let other = ClientError::InvalidResponse {
+            message: String::new(),
+        };
+        assert_eq!(policy.backoff(1, &other), Duration::from_millis(400));
+        assert_eq!(policy.backoff(10, &other), policy.max_backoff);
+    }
+
+    #[test]
+    fn test_elicitation_events_are_parsed() {
+        let event = "event: elicitation\nid: 7\ndata: {\"id\":\"7\",\"params\":{},\"expires_at\":\"2025-01-01T00:00:00Z\"}\n\n";
+        let elicitation = parse_elicitation_event(event).unwrap().unwrap();
+        assert_eq!(elicitation.id, "7");
+        assert!(parse_elicitation_event(": keep-alive\n\n").is_none());
+    }
+}
@@ -0,0 +1,117 @@
+//! Notifications from HTTP clients to the MCP server
+//!
+//! A command without an `id` is a JSON-RPC notification: nothing answers it,
+//! so `POST /api/v1` answers `202` at once instead of waiting for a response
+//! line. Only methods in the server's `client_notifications` are accepted;
+//! others are refused with `403`. The list holds method names or prefixes
+//! ending in `*` and defaults to [`DEFAULT_CLIENT_NOTIFICATIONS`].
+//! Accepted notifications are written by one task per server, in the order
+//! they were accepted.
+//!
+//! `notifications/cancelled` is not forwarded as is: the request ids the
+//! server sees are the gateway's, so the gateway cancels the client's
+//! in-flight request with that id itself, telling the server as it does for
+//! aborted requests.
+
+use serde_json::Value;
+
+use crate::error::{McpCoreError, McpCoreResult};
+use crate::method_timeout::NamePattern;
+
+/// Notification cancelling an in-flight request
+pub const CANCELLED: &str = "notifications/cancelled";
+
+/// Notifications accepted when a server does not configure its own
+pub const DEFAULT_CLIENT_NOTIFICATIONS: &[&str] = &[CANCELLED];
+
+/// Whether `command` is a notification: an object with a `method` and no `id`
+pub fn is_notification(command: &str) -> bool {
+    serde_json::from_str::<Value>(command).is_ok_and(|message| {
+        message.get("method").is_some_and(Value::is_string) && message.get("id").is_none()
+    })
+}
+
+/// Notification methods clients may send
+#[derive(Debug, Clone)]
+pub struct NotificationAllowlist {
+    patterns: Vec<NamePattern>,
+}
+
+impl Default for NotificationAllowlist {
+    fn default() -> Self {
+        Self {
+            patterns: DEFAULT_CLIENT_NOTIFICATIONS
+                .iter()
+                .map(|method| NamePattern::parse(method).expect("default is valid"))
+                .collect(),
+        }
+    }
+}
+
+impl NotificationAllowlist {
+    /// Allowlist of `methods`, or the default one
+    pub fn new(methods: Option<&[String]>) -> Result<Self, String> {
+        let Some(methods) = methods else {
+            return Ok(Self::default());
+        };
+        let patterns = methods
+            .iter()
+            .map(|method| {
+                NamePattern::parse(method)
+                    .map_err(|reason| format!("client_notifications entry '{}' {}", method, reason))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { patterns })
+    }
+
+    /// Refuse notification methods not in the list
+    pub fn check(&self, method: &str) -> McpCoreResult<()> {
+        if self.patterns.iter().any(|pattern| pattern.matches(method)) {
+            return Ok(());
+        }
+        Err(McpCoreError::NotificationNotAllowed {
+            message: format!("Clients may not send '{}'", method),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_notifications() {
+        assert!(is_notification(
+            r#"{"jsonrpc":"2.0","method":"notifications/cancelled"}"#
+        ));
+        assert!(!is_notification(
+            r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#
+        ));
+        // A null id is still an id; the server answers it
+        assert!(!is_notification(
+            r#"{"jsonrpc":"2.0","id":null,"method":"ping"}"#
+        ));
+        assert!(!is_notification(r#"{"jsonrpc":"2.0","result":{}}"#));
+        assert!(!is_notification("not json"));
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let default = NotificationAllowlist::default();
+        assert!(default.check(CANCELLED).is_ok());
+        let error = default.check("notifications/initialized").unwrap_err();
+        assert_eq!(error.error_code(), Some("notification_not_allowed"));
+
+        let custom = NotificationAllowlist::new(Some(&[
+            "notifications/progress".to_string(),
+            "custom/*".to_string(),
+        ]))
+        .unwrap();
+        assert!(custom.check("custom/refresh").is_ok());
+        assert!(custom.check("notifications/progress").is_ok());
+        assert!(custom.check(CANCELLED).is_err());
+
+        let error = NotificationAllowlist::new(Some(&["a b".to_string()])).unwrap_err();
+        assert!(error.contains("client_notifications entry 'a b'"));
+    }
+}
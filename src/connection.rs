@@ -0,0 +1,227 @@
+//! Serving accepted connections with the options of [`HttpConfig`]
+//!
+//! Connections are handed to hyper directly rather than through
+//! `axum::serve`, which cannot be tuned. Without `http2` a connection speaks
+//! HTTP/1.1 only, as before; with it, hyper tells HTTP/2 apart by its
+//! connection preface. A connection with no request in flight for
+//! `keep_alive_idle_timeout_secs` is shut down gracefully: an HTTP/1.1 one
+//! is closed, an HTTP/2 one is sent a GOAWAY frame. A request counts as in
+//! flight until its response body has been sent, so event streams keep
+//! their connection open.
+
+use crate::listener::{HttpConfig, PeerAddr};
+use axum::body::{Body, Bytes, HttpBody};
+use axum::extract::ConnectInfo;
+use axum::Router;
+use hyper::body::{Frame, Incoming};
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto::Builder;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tower_service::Service;
+
+/// Serve `app` on `listener` until `shutdown` resolves and its connections
+/// end
+pub(crate) async fn serve<L>(mut listener: L, app: Router, http: &HttpConfig, shutdown: impl Future)
+where
+    L: axum::serve::Listener<Addr = SocketAddr>,
+{
+    let builder = builder(http);
+    let idle_timeout = http.keep_alive_idle_timeout_secs.map(Duration::from_secs);
+    let (stop, stopping) = watch::channel(());
+    let (closed, connections) = watch::channel(());
+    let mut shutdown = std::pin::pin!(shutdown);
+    loop {
+        let (io, peer) = tokio::select! {
+            connection = listener.accept() => connection,
+            _ = &mut shutdown => break,
+        };
+        let connection = Connection {
+            builder: builder.clone(),
+            app: app.clone(),
+            peer,
+            idle_timeout,
+            stopping: stopping.clone(),
+            _open: connections.clone(),
+        };
+        tokio::spawn(connection.serve(io));
+    }
+
+    drop(listener);
+    drop(connections);
+    // Open connections shut down gracefully once the last sender is gone
+    drop(stop);
+    closed.closed().await;
+}
+
+/// Connection builder applying `http`
+fn builder(http: &HttpConfig) -> Builder<TokioExecutor> {
+    let mut builder = Builder::new(TokioExecutor::new());
+    match http.header_read_timeout_secs {
+        Some(secs) => {
+            builder
+                .http1()
+                .timer(TokioTimer::new())
+                .header_read_timeout(Duration::from_secs(secs));
+        }
+        // hyper's default timeout only takes effect with a timer
+        None => {
+            builder.http1().header_read_timeout(None);
+        }
+    }
+    if !http.http2 {
+        return builder.http1_only();
+    }
+    if let Some(max) = http.max_concurrent_streams {
+        builder.http2().max_concurrent_streams(max);
+    }
+    builder
+}
+
+/// One accepted connection and what serving it needs
+struct Connection {
+    builder: Builder<TokioExecutor>,
+    app: Router,
+    peer: SocketAddr,
+    idle_timeout: Option<Duration>,
+
+    /// Changes when the listener stops
+    stopping: watch::Receiver<()>,
+
+    /// Held until the connection ends, so the listener can wait for it
+    _open: watch::Receiver<()>,
+}
+
+impl Connection {
+    async fn serve<I>(mut self, io: I)
+    where
+        I: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+    {
+        let activity = Arc::new(Activity::new());
+        let (app, peer, tracked) = (self.app, self.peer, Arc::clone(&activity));
+        let service = hyper::service::service_fn(move |mut request: hyper::Request<Incoming>| {
+            request.extensions_mut().insert(ConnectInfo(PeerAddr(peer)));
+            let in_flight = InFlight::start(&tracked);
+            let mut app = app.clone();
+            async move {
+                let response = app.call(request.map(Body::new)).await?;
+                Ok::<_, std::convert::Infallible>(response.map(|body| {
+                    Body::new(TrackedBody {
+                        body,
+                        _in_flight: in_flight,
+                    })
+                }))
+            }
+        });
+
+        let connection = self
+            .builder
+            .serve_connection_with_upgrades(TokioIo::new(io), service);
+        let mut connection = std::pin::pin!(connection);
+        let reason = tokio::select! {
+            result = connection.as_mut() => {
+                if let Err(e) = result {
+                    tracing::debug!("Connection from {} failed: {}", peer, e);
+                }
+                return;
+            }
+            _ = self.stopping.changed() => "listener stopping",
+            () = activity.idle(self.idle_timeout) => "idle timeout",
+        };
+        tracing::debug!("Closing connection from {}: {}", peer, reason);
+        connection.as_mut().graceful_shutdown();
+        if let Err(e) = connection.await {
+            tracing::debug!("Connection from {} failed: {}", peer, e);
+        }
+    }
+}
+
+/// Requests in flight on a connection and when the last one started or
+/// ended
+struct Activity {
+    in_flight: AtomicUsize,
+    last: Mutex<Instant>,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            in_flight: AtomicUsize::new(0),
+            last: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn touch(&self) {
+        *self.last.lock().unwrap() = Instant::now();
+    }
+
+    /// Resolve once no request has been in flight for `timeout`; never
+    /// without one
+    async fn idle(&self, timeout: Option<Duration>) {
+        let Some(timeout) = timeout else {
+            return std::future::pending().await;
+        };
+        loop {
+            let idle_until = *self.last.lock().unwrap() + timeout;
+            if Instant::now() < idle_until {
+                tokio::time::sleep_until(idle_until).await;
+            } else if self.in_flight.load(Ordering::SeqCst) == 0 {
+                return;
+            } else {
+                // A request running since before the deadline; look again later
+                tokio::time::sleep(timeout).await;
+            }
+        }
+    }
+}
+
+/// A request counted in flight on its connection until dropped
+struct InFlight(Arc<Activity>);
+
+impl InFlight {
+    fn start(activity: &Arc<Activity>) -> Self {
+        activity.in_flight.fetch_add(1, Ordering::SeqCst);
+        activity.touch();
+        Self(Arc::clone(activity))
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.touch();
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Response body keeping its request in flight until it is sent or dropped
+struct TrackedBody {
+    body: Body,
+    _in_flight: InFlight,
+}
+
+impl HttpBody for TrackedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        Pin::new(&mut self.body).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> hyper::body::SizeHint {
+        self.body.size_hint()
+    }
+}
@@ -0,0 +1,205 @@
+//! Caller context passed to MCP servers in `_meta`
+//!
+//! Stdio servers never see the HTTP request, so with `context_meta` set on a
+//! server the gateway adds who is asking to every forwarded message, as
+//! `params._meta.gateway`. A `_meta` the client sent is kept and merged into;
+//! a `gateway` entry in it is replaced so clients cannot forge one. Only the
+//! configured fields are added, and none of them can carry a secret: the API
+//! key is named, never included. Servers echoing the context back have it
+//! removed from their responses.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::net::IpAddr;
+
+/// Key of the gateway's entry in `_meta`
+pub const META_KEY: &str = "gateway";
+
+/// A piece of context the gateway can pass on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextField {
+    /// Name of the API key that authenticated the request
+    ApiKeyName,
+
+    /// JSON-RPC id the client sent, before it is rewritten
+    RequestId,
+
+    /// Address of the connecting client
+    ClientIp,
+}
+
+/// Which context a server receives in `_meta`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ContextMetaConfig {
+    /// Fields to pass on; all of them by default
+    #[serde(default = "default_fields")]
+    pub fields: Vec<ContextField>,
+}
+
+fn default_fields() -> Vec<ContextField> {
+    vec![
+        ContextField::ApiKeyName,
+        ContextField::RequestId,
+        ContextField::ClientIp,
+    ]
+}
+
+impl Default for ContextMetaConfig {
+    fn default() -> Self {
+        Self {
+            fields: default_fields(),
+        }
+    }
+}
+
+/// What the gateway knows about the caller of a request
+#[derive(Debug, Clone, Default)]
+pub struct CallerContext<'a> {
+    pub api_key_name: Option<&'a str>,
+    pub request_id: Option<&'a Value>,
+    pub client_ip: Option<IpAddr>,
+}
+
+impl ContextMetaConfig {
+    /// The configured fields of `caller` that are known
+    pub fn context(&self, caller: &CallerContext<'_>) -> Map<String, Value> {
+        let mut context = Map::new();
+        for field in &self.fields {
+            let (key, value) = match field {
+                ContextField::ApiKeyName => ("api_key_name", caller.api_key_name.map(Value::from)),
+                ContextField::RequestId => ("request_id", caller.request_id.cloned()),
+                ContextField::ClientIp => (
+                    "client_ip",
+                    caller.client_ip.map(|ip| Value::from(ip.to_string())),
+                ),
+            };
+            if let Some(value) = value {
+                context.insert(key.to_string(), value);
+            }
+        }
+        context
+    }
+}
+
+/// Add `context` to the message's `params._meta`, returning what was added
+///
+/// Messages with positional params or a `_meta` that is not an object are
+/// left alone, as is everything when `context` is empty.
+pub fn inject(message: &mut Value, context: Map<String, Value>) -> Option<Value> {
+    if context.is_empty() || message.get("method").is_none() {
+        return None;
+    }
+    let params = message
+        .as_object_mut()?
+        .entry("params")
+        .or_insert_with(|| Value::Object(Map::new()))
+        .as_object_mut()?;
+    let meta = params
+        .entry("_meta")
+        .or_insert_with(|| Value::Object(Map::new()))
+        .as_object_mut()?;
+    let context = Value::Object(context);
+    meta.insert(META_KEY.to_string(), context.clone());
+    Some(context)
+}
+
+/// Remove echoes of the `injected` context from a response, returning
+/// whether any were found
+///
+/// A `_meta` left empty is removed as well.
+pub fn strip(message: &mut Value, injected: &Value) -> bool {
+    let mut stripped = false;
+    match message {
+        Value::Object(object) => {
+            if let Some(Value::Object(meta)) = object.get_mut("_meta") {
+                if meta.get(META_KEY) == Some(injected) {
+                    meta.shift_remove(META_KEY);
+                    stripped = true;
+                    if meta.is_empty() {
+                        object.shift_remove("_meta");
+                    }
+                }
+            }
+            for value in object.values_mut() {
+                stripped |= strip(value, injected);
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                stripped |= strip(value, injected);
+            }
+        }
+        _ => {}
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn caller() -> CallerContext<'static> {
+        static ID: Value = Value::Null;
+        CallerContext {
+            api_key_name: Some("default"),
+            request_id: Some(&ID),
+            client_ip: Some("203.0.113.7".parse().unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_context_merged_into_existing_meta() {
+        let config = ContextMetaConfig::default();
+        let mut message = json!({
+            "jsonrpc": "2.0", "id": 1, "method": "tools/call",
+            "params": { "name": "echo", "_meta": { "progressToken": 5, "gateway": "forged" } }
+        });
+        let injected = inject(&mut message, config.context(&caller())).unwrap();
+        assert_eq!(
+            message["params"]["_meta"],
+            json!({
+                "progressToken": 5,
+                "gateway": { "api_key_name": "default", "request_id": null, "client_ip": "203.0.113.7" }
+            })
+        );
+        assert_eq!(message["params"]["name"], "echo");
+
+        // Messages without params get them; positional params are left alone
+        let mut bare = json!({ "jsonrpc": "2.0", "id": 2, "method": "ping" });
+        assert!(inject(&mut bare, config.context(&caller())).is_some());
+        assert_eq!(bare["params"]["_meta"]["gateway"], injected);
+        let mut positional = json!({ "jsonrpc": "2.0", "id": 3, "method": "m", "params": [1] });
+        assert!(inject(&mut positional, config.context(&caller())).is_none());
+        assert_eq!(positional["params"], json!([1]));
+    }
+
+    #[test]
+    fn test_fields_limited_and_echoes_stripped() {
+        let config = ContextMetaConfig {
+            fields: vec![ContextField::ApiKeyName],
+        };
+        let context = config.context(&caller());
+        assert_eq!(
+            Value::Object(context.clone()),
+            json!({ "api_key_name": "default" })
+        );
+        assert!(config.context(&CallerContext::default()).is_empty());
+
+        let mut message =
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/call", "params": {} });
+        let injected = inject(&mut message, context).unwrap();
+        let mut response = json!({
+            "jsonrpc": "2.0", "id": 1,
+            "result": { "echo": message.clone(), "_meta": { "gateway": injected.clone(), "other": 1 } }
+        });
+        assert!(strip(&mut response, &injected));
+        assert_eq!(response["result"]["_meta"], json!({ "other": 1 }));
+        assert_eq!(response["result"]["echo"]["params"], json!({}));
+
+        // Content that merely looks alike is kept
+        let mut unrelated = json!({ "_meta": { "gateway": { "api_key_name": "other" } } });
+        assert!(!strip(&mut unrelated, &injected));
+    }
+}
@@ -0,0 +1,450 @@
+//! Allowlisting forward proxy for sandboxed servers
+//!
+//! A server whose sandbox sets `network: "allowlist"` gets its own
+//! [`EgressProxy`] listening on a loopback port. The server's `HTTP_PROXY`
+//! and `HTTPS_PROXY` point at it, and it only opens connections to
+//! destinations matching one of the configured `host:port` patterns: `CONNECT`
+//! tunnels for HTTPS, and absolute-form requests for plain HTTP. Anything
+//! else is answered with `403` and logged. Traffic that ignores the proxy
+//! variables is not covered; only `network: "none"` cuts it off.
+
+use crate::error::{McpCoreError, McpCoreResult};
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::{JoinHandle, JoinSet};
+
+/// Time a client has to send its request head
+const HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time allowed to connect to the destination
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest request head accepted
+const MAX_HEAD_BYTES: usize = 16 * 1024;
+
+/// Destination pattern: `host:port`, where the host may be `*` or start with
+/// `*.` and the port may be `*`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostPattern {
+    host: String,
+    port: Option<u16>,
+}
+
+impl HostPattern {
+    pub fn parse(pattern: &str) -> Result<Self, String> {
+        let (host, port) =
+            split_host_port(pattern).ok_or_else(|| format!("'{}' must be host:port", pattern))?;
+        let port = match port {
+            "*" => None,
+            port => Some(
+                port.parse()
+                    .map_err(|_| format!("'{}' has an invalid port", pattern))?,
+            ),
+        };
+        let wildcard = host.strip_prefix("*.").unwrap_or(host);
+        if host.is_empty() || (host != "*" && wildcard.contains(['*', '/', ' '])) {
+            return Err(format!("'{}' has an invalid host", pattern));
+        }
+        Ok(Self {
+            host: host.to_ascii_lowercase(),
+            port,
+        })
+    }
+
+    /// Whether `host:port` is allowed; `*.example.com` also matches
+    /// `example.com`
+    pub fn matches(&self, host: &str, port: u16) -> bool {
+        if self.port.is_some_and(|allowed| allowed != port) {
+            return false;
+        }
+        let host = host.to_ascii_lowercase();
+        match self.host.strip_prefix("*.") {
+            _ if self.host == "*" => true,
+            Some(domain) => host == domain || host.ends_with(&format!(".{}", domain)),
+            None => host == self.host,
+        }
+    }
+}
+
+/// Connections handled by an egress proxy since it started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct EgressStats {
+    pub allowed: u64,
+    pub denied: u64,
+
+    /// Allowed connections whose destination could not be reached
+    pub failed: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    allowed: AtomicU64,
+    denied: AtomicU64,
+    failed: AtomicU64,
+}
+
+/// Forward proxy on a loopback port; stops when dropped
+pub struct EgressProxy {
+    address: SocketAddr,
+    counters: Arc<Counters>,
+    task: JoinHandle<()>,
+}
+
+impl std::fmt::Debug for EgressProxy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EgressProxy")
+            .field("address", &self.address)
+            .finish()
+    }
+}
+
+impl EgressProxy {
+    /// Listen on `127.0.0.1` and forward connections allowed by `allowed`
+    pub async fn start(server_name: &str, allowed: Vec<HostPattern>) -> McpCoreResult<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.map_err(|e| {
+            McpCoreError::ConfigurationError {
+                message: format!("Failed to start the egress proxy: {}", e),
+            }
+        })?;
+        let address = listener
+            .local_addr()
+            .map_err(|e| McpCoreError::ConfigurationError {
+                message: format!("Failed to start the egress proxy: {}", e),
+            })?;
+        let counters = Arc::new(Counters::default());
+        let allowed = Arc::new(allowed);
+        let span = tracing::info_span!("egress_proxy", server = %server_name);
+        let task = {
+            let counters = Arc::clone(&counters);
+            tokio::spawn(tracing::Instrument::instrument(
+                async move {
+                    // Aborting the accept loop drops the set, closing every tunnel
+                    let mut connections = JoinSet::new();
+                    loop {
+                        tokio::select! {
+                            accepted = listener.accept() => match accepted {
+                                Ok((stream, _)) => {
+                                    connections.spawn(handle(
+                                        stream,
+                                        Arc::clone(&allowed),
+                                        Arc::clone(&counters),
+                                    ));
+                                }
+                                Err(e) => tracing::warn!("Egress proxy accept failed: {}", e),
+                            },
+                            Some(_) = connections.join_next() => {}
+                        }
+                    }
+                },
+                span,
+            ))
+        };
+        tracing::info!("Egress proxy listening on {}", address);
+        Ok(Self {
+            address,
+            counters,
+            task,
+        })
+    }
+
+    /// URL clients use to reach the proxy
+    pub fn url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    /// Proxy variables for the child, in upper and lower case
+    pub fn env_vars(&self) -> Vec<(String, String)> {
+        let url = self.url();
+        ["HTTP_PROXY", "HTTPS_PROXY"]
+            .into_iter()
+            .flat_map(|name| {
+                [
+                    (name.to_string(), url.clone()),
+                    (name.to_ascii_lowercase(), url.clone()),
+                ]
+            })
+            .collect()
+    }
+
+    pub fn stats(&self) -> EgressStats {
+        EgressStats {
+            allowed: self.counters.allowed.load(Ordering::Relaxed),
+            denied: self.counters.denied.load(Ordering::Relaxed),
+            failed: self.counters.failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Drop for EgressProxy {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Serve one client connection
+async fn handle(mut client: TcpStream, allowed: Arc<Vec<HostPattern>>, counters: Arc<Counters>) {
+    let head = match tokio::time::timeout(HEADER_TIMEOUT, read_head(&mut client)).await {
+        Ok(Ok(head)) => head,
+        Ok(Err(status)) => {
+            let _ = respond(&mut client, status, "malformed request").await;
+            return;
+        }
+        Err(_) => {
+            let _ = respond(&mut client, "408 Request Timeout", "request head timed out").await;
+            return;
+        }
+    };
+    let Some((method, host, port)) = destination(&head.bytes[..head.len]) else {
+        let _ = respond(
+            &mut client,
+            "400 Bad Request",
+            "expected CONNECT or an absolute http:// URL",
+        )
+        .await;
+        return;
+    };
+
+    if !allowed.iter().any(|pattern| pattern.matches(&host, port)) {
+        counters.denied.fetch_add(1, Ordering::Relaxed);
+        tracing::warn!("Denied egress {} {}:{}", method, host, port);
+        let body = format!("egress to {}:{} is not allowed", host, port);
+        let _ = respond(&mut client, "403 Forbidden", &body).await;
+        return;
+    }
+
+    let upstream = match tokio::time::timeout(
+        CONNECT_TIMEOUT,
+        TcpStream::connect((host.as_str(), port)),
+    )
+    .await
+    {
+        Ok(Ok(upstream)) => upstream,
+        Ok(Err(e)) => {
+            counters.failed.fetch_add(1, Ordering::Relaxed);
+            tracing::warn!("Egress to {}:{} failed: {}", host, port, e);
+            let _ = respond(&mut client, "502 Bad Gateway", &e.to_string()).await;
+            return;
+        }
+        Err(_) => {
+            counters.failed.fetch_add(1, Ordering::Relaxed);
+            tracing::warn!("Egress to {}:{} timed out", host, port);
+            let _ = respond(&mut client, "504 Gateway Timeout", "connect timed out").await;
+            return;
+        }
+    };
+    counters.allowed.fetch_add(1, Ordering::Relaxed);
+    tracing::debug!("Allowed egress {} {}:{}", method, host, port);
+
+    let mut upstream = upstream;
+    let forwarded = if method == "CONNECT" {
+        // Bytes sent after the head already belong to the tunnel
+        let written = client
+            .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+            .await;
+        match written {
+            Ok(()) => upstream.write_all(&head.bytes[head.len..]).await,
+            Err(e) => Err(e),
+        }
+    } else {
+        upstream.write_all(&head.bytes).await
+    };
+    if forwarded.is_ok() {
+        let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+    }
+}
+
+/// Request head and whatever followed it in the same reads
+struct Head {
+    bytes: Vec<u8>,
+
+    /// Length of the head, including the blank line
+    len: usize,
+}
+
+async fn read_head(client: &mut TcpStream) -> Result<Head, &'static str> {
+    let mut bytes = Vec::new();
+    let mut buffer = [0u8; 4096];
+    loop {
+        let read = client
+            .read(&mut buffer)
+            .await
+            .map_err(|_| "400 Bad Request")?;
+        if read == 0 {
+            return Err("400 Bad Request");
+        }
+        bytes.extend_from_slice(&buffer[..read]);
+        if let Some(end) = bytes.windows(4).position(|window| window == b"\r\n\r\n") {
+            return Ok(Head {
+                bytes,
+                len: end + 4,
+            });
+        }
+        if bytes.len() > MAX_HEAD_BYTES {
+            return Err("431 Request Header Fields Too Large");
+        }
+    }
+}
+
+/// Method and destination of a request head
+fn destination(head: &[u8]) -> Option<(String, String, u16)> {
+    let head = std::str::from_utf8(head).ok()?;
+    let mut request_line = head.lines().next()?.split_whitespace();
+    let method = request_line.next()?;
+    let target = request_line.next()?;
+    if method == "CONNECT" {
+        let (host, port) = split_host_port(target)?;
+        return Some((method.to_string(), host.to_string(), port.parse().ok()?));
+    }
+    let rest = target.strip_prefix("http://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let (host, port) = match split_host_port(authority) {
+        Some((host, port)) => (host, port.parse().ok()?),
+        None => (authority.trim_start_matches('[').trim_end_matches(']'), 80),
+    };
+    (!host.is_empty()).then(|| (method.to_string(), host.to_string(), port))
+}
+
+/// Split `host:port` or `[v6]:port`, without the brackets
+fn split_host_port(value: &str) -> Option<(&str, &str)> {
+    let (host, port) = value.rsplit_once(':')?;
+    if host.starts_with('[') {
+        return Some((host.strip_prefix('[')?.strip_suffix(']')?, port));
+    }
+    (!host.contains(':')).then_some((host, port))
+}
+
+async fn respond(client: &mut TcpStream, status: &str, body: &str) -> std::io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}\n",
+        status,
+        body.len() + 1,
+        body
+    );
+    client.write_all(response.as_bytes()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_patterns() {
+        let exact = HostPattern::parse("api.github.com:443").unwrap();
+        assert!(exact.matches("API.github.com", 443));
+        assert!(!exact.matches("api.github.com", 80));
+        assert!(!exact.matches("evil-api.github.com", 443));
+
+        let wildcard = HostPattern::parse("*.example.com:*").unwrap();
+        assert!(wildcard.matches("example.com", 8080));
+        assert!(wildcard.matches("a.b.example.com", 443));
+        assert!(!wildcard.matches("notexample.com", 443));
+        assert!(HostPattern::parse("*:443")
+            .unwrap()
+            .matches("anything", 443));
+        assert!(HostPattern::parse("[::1]:80").unwrap().matches("::1", 80));
+
+        assert!(HostPattern::parse("example.com")
+            .unwrap_err()
+            .contains("host:port"));
+        assert!(HostPattern::parse("example.com:https")
+            .unwrap_err()
+            .contains("invalid port"));
+        assert!(HostPattern::parse("a.*.com:443")
+            .unwrap_err()
+            .contains("invalid host"));
+    }
+
+    #[test]
+    fn test_destination_parsing() {
+        assert_eq!(
+            destination(b"CONNECT example.com:443 HTTP/1.1\r\n\r\n"),
+            Some(("CONNECT".to_string(), "example.com".to_string(), 443))
+        );
+        assert_eq!(
+            destination(b"GET http://example.com/a?b HTTP/1.1\r\n\r\n"),
+            Some(("GET".to_string(), "example.com".to_string(), 80))
+        );
+        assert_eq!(
+            destination(b"GET http://[::1]:8080/ HTTP/1.1\r\n\r\n"),
+            Some(("GET".to_string(), "::1".to_string(), 8080))
+        );
+        assert_eq!(destination(b"GET /relative HTTP/1.1\r\n\r\n"), None);
+    }
+
+    /// Origin answering every connection with a fixed response
+    async fn origin() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut request = [0u8; 4096];
+                    let _ = stream.read(&mut request).await;
+                    let _ = stream
+                        .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\nConnection: close\r\n\r\norigin")
+                        .await;
+                });
+            }
+        });
+        port
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_child_curl_through_proxy() {
+        if crate::diagnostics::find_executable("curl").is_none() {
+            eprintln!("Skipping egress proxy test: curl not found");
+            return;
+        }
+        let port = origin().await;
+        let proxy = EgressProxy::start(
+            "test",
+            vec![HostPattern::parse(&format!("127.0.0.1:{}", port)).unwrap()],
+        )
+        .await
+        .unwrap();
+
+        let curl = |args: Vec<String>| {
+            let mut command = tokio::process::Command::new("curl");
+            command
+                .args(["-s", "-o", "-", "-w", " %{http_code}"])
+                .args(args)
+                .env_remove("NO_PROXY")
+                .env_remove("no_proxy")
+                .envs(proxy.env_vars());
+            command
+        };
+        let output = |mut command: tokio::process::Command| async move {
+            let output = command.output().await.unwrap();
+            String::from_utf8_lossy(&output.stdout).to_string()
+        };
+
+        // Plain HTTP and a CONNECT tunnel to the allowed origin
+        let allowed = format!("http://127.0.0.1:{}/", port);
+        assert_eq!(output(curl(vec![allowed.clone()])).await, "origin 200");
+        assert_eq!(
+            output(curl(vec!["-p".to_string(), allowed])).await,
+            "origin 200"
+        );
+
+        // Same origin under another name is not on the list
+        let denied = format!("http://localhost:{}/", port);
+        let body = output(curl(vec![denied])).await;
+        assert!(body.ends_with(" 403"), "{}", body);
+        assert!(body.contains("is not allowed"));
+
+        assert_eq!(
+            proxy.stats(),
+            EgressStats {
+                allowed: 2,
+                denied: 1,
+                failed: 0
+            }
+        );
+    }
+}
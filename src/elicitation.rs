@@ -0,0 +1,232 @@
+//! Relaying `elicitation/create` requests from MCP servers to HTTP clients
+//!
+//! An elicitation asks the user a question in the middle of a tool call. The
+//! gateway cannot answer it itself, so the request is parked under an id,
+//! published to subscribers of `GET /api/v1/elicitations/events`, and answered
+//! through `POST /api/v1/elicitations/{id}`. The tool call stays blocked until
+//! then. Elicitations left unanswered expire and are declined.
+
+use crate::error::{McpCoreError, McpCoreResult};
+use crate::server_requests::ServerRequestHandler;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, oneshot};
+
+/// Default time an elicitation waits for an answer
+pub const DEFAULT_ELICITATION_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Elicitation results accepted from clients
+const ACTIONS: &[&str] = &["accept", "decline", "cancel"];
+
+/// Capacity of the subscriber channel; slow subscribers miss older events
+const EVENT_CAPACITY: usize = 64;
+
+/// An elicitation waiting for an answer
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Elicitation {
+    pub id: String,
+
+    /// `params` of the server's `elicitation/create` request
+    pub params: Value,
+
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+struct PendingElicitation {
+    elicitation: Elicitation,
+    reply: oneshot::Sender<Value>,
+}
+
+/// Elicitations parked until a client answers them
+pub struct ElicitationRegistry {
+    timeout: Duration,
+    next_id: AtomicU64,
+    pending: Mutex<HashMap<String, PendingElicitation>>,
+    events: broadcast::Sender<Elicitation>,
+}
+
+impl Default for ElicitationRegistry {
+    fn default() -> Self {
+        Self::new(DEFAULT_ELICITATION_TIMEOUT)
+    }
+}
+
+impl ElicitationRegistry {
+    /// Create a registry whose elicitations expire after `timeout`
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            next_id: AtomicU64::new(0),
+            pending: Mutex::new(HashMap::new()),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+
+    /// Handler for `elicitation/create` that waits for a client's answer
+    pub fn handler(self: &Arc<Self>) -> ServerRequestHandler {
+        let registry = self.clone();
+        Arc::new(move |params: Value| {
+            let registry = registry.clone();
+            Box::pin(async move { Ok(registry.elicit(params).await) })
+        })
+    }
+
+    /// Park an elicitation and wait for its answer or expiry
+    async fn elicit(&self, params: Value) -> Value {
+        let sequence = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let id = elicitation_id(sequence);
+        let expires_at = chrono::Utc::now()
+            + chrono::Duration::from_std(self.timeout).unwrap_or(chrono::Duration::MAX);
+        let elicitation = Elicitation {
+            id: id.clone(),
+            params,
+            expires_at,
+        };
+
+        let (reply, answer) = oneshot::channel();
+        // Removes the entry on expiry, and when the tool call is aborted
+        let _guard = PendingGuard {
+            registry: self,
+            id: &id,
+        };
+        self.lock().insert(
+            id.clone(),
+            PendingElicitation {
+                elicitation: elicitation.clone(),
+                reply,
+            },
+        );
+        tracing::info!("MCP server requested elicitation {}", id);
+        // No subscribers is fine; the elicitation is still listed and answerable
+        let _ = self.events.send(elicitation);
+
+        match tokio::time::timeout(self.timeout, answer).await {
+            Ok(Ok(result)) => result,
+            _ => {
+                tracing::warn!("Elicitation {} expired unanswered, declining", id);
+                serde_json::json!({ "action": "decline" })
+            }
+        }
+    }
+
+    /// Relay a client's answer to the waiting elicitation
+    ///
+    /// `result` must be an elicitation result with an `action` of `accept`,
+    /// `decline`, or `cancel`.
+    pub fn answer(&self, id: &str, result: Value) -> McpCoreResult<()> {
+        let action = result.get("action").and_then(Value::as_str);
+        if !action.is_some_and(|action| ACTIONS.contains(&action)) {
+            return Err(McpCoreError::RequestError {
+                message: format!(
+                    "Elicitation answer must have an action of {}",
+                    ACTIONS.join(", ")
+                ),
+            });
+        }
+
+        let pending = self
+            .lock()
+            .remove(id)
+            .ok_or_else(|| McpCoreError::NotFound {
+                message: format!("No pending elicitation '{}'", id),
+            })?;
+        pending
+            .reply
+            .send(result)
+            .map_err(|_| McpCoreError::NotFound {
+                message: format!("Elicitation '{}' is no longer waiting", id),
+            })
+    }
+
+    /// Elicitations waiting for an answer, oldest first
+    pub fn pending(&self) -> Vec<Elicitation> {
+        let mut pending: Vec<Elicitation> = self
+            .lock()
+            .values()
+            .map(|pending| pending.elicitation.clone())
+            .collect();
+        pending.sort_by_key(|elicitation| elicitation.expires_at);
+        pending
+    }
+
+    /// Receive every elicitation created from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<Elicitation> {
+        self.events.subscribe()
+    }
+
+    /// Sequence number of the most recent elicitation
+    pub fn last_sequence(&self) -> u64 {
+        self.next_id.load(Ordering::Relaxed)
+    }
+
+    /// Ids of the elicitations created after `sequence`
+    pub fn ids_since(&self, sequence: u64) -> Vec<String> {
+        (sequence + 1..=self.last_sequence())
+            .map(elicitation_id)
+            .collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, PendingElicitation>> {
+        self.pending
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+struct PendingGuard<'a> {
+    registry: &'a ElicitationRegistry,
+    id: &'a str,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.registry.lock().remove(self.id);
+    }
+}
+
+fn elicitation_id(sequence: u64) -> String {
+    format!("elicit-{}", sequence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_answer_is_relayed() {
+        let registry = Arc::new(ElicitationRegistry::default());
+        let mut events = registry.subscribe();
+        let handler = registry.handler();
+        let waiting = tokio::spawn(handler(serde_json::json!({ "message": "Name?" })));
+
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.id, "elicit-1");
+        assert_eq!(registry.pending().len(), 1);
+
+        let invalid = registry.answer(&event.id, serde_json::json!({ "name": "x" }));
+        assert!(matches!(invalid, Err(McpCoreError::RequestError { .. })));
+
+        let answer = serde_json::json!({ "action": "accept", "content": { "name": "Ada" } });
+        registry.answer(&event.id, answer.clone()).unwrap();
+        assert_eq!(waiting.await.unwrap().unwrap(), answer);
+        assert!(registry.pending().is_empty());
+        assert!(matches!(
+            registry.answer(&event.id, answer),
+            Err(McpCoreError::NotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unanswered_elicitation_is_declined() {
+        let registry = Arc::new(ElicitationRegistry::new(Duration::from_millis(20)));
+        let before = registry.last_sequence();
+        let result = registry.handler()(serde_json::json!({})).await.unwrap();
+        assert_eq!(result, serde_json::json!({ "action": "decline" }));
+        assert!(registry.pending().is_empty());
+        assert_eq!(registry.ids_since(before), ["elicit-1"]);
+    }
+}
@@ -1,5 +1,16 @@
 //! Error types for MCP HTTP Core
 
+use crate::tool_schema::SchemaViolation;
+use crate::transport::HandshakeFailure;
+#[cfg(feature = "http-server")]
+use axum::{
+    http::header,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 /// Core error types for MCP HTTP operations
@@ -20,6 +31,124 @@ pub enum McpCoreError {
     #[error("HTTP server error: {message}")]
     HttpServerError { message: String },
 
+    #[error("Invalid request: {message}")]
+    RequestError { message: String },
+
+    #[error("Invalid command: {message}")]
+    InvalidCommand { code: &'static str, message: String },
+
+    #[error("Not found: {message}")]
+    NotFound { message: String },
+
+    #[error("Request aborted: {message}")]
+    RequestAborted { message: String },
+
+    /// Ended by the client's own `notifications/cancelled`
+    #[error("Request cancelled: {message}")]
+    RequestCancelled { message: String },
+
+    #[error("Request timed out: {message}")]
+    RequestTimeout {
+        message: String,
+        timeout_secs: u64,
+        limit: String,
+    },
+
+    #[error("Overloaded: {message}")]
+    Overloaded {
+        message: String,
+        retry_after_secs: u64,
+    },
+
+    #[error("Rate limited: {message}")]
+    RateLimited {
+        message: String,
+        retry_after_secs: u64,
+    },
+
+    #[error("Quota exceeded: {message}")]
+    QuotaExceeded {
+        message: String,
+        quota: &'static str,
+        resets_at: DateTime<Utc>,
+        retry_after_secs: u64,
+    },
+
+    #[error("Maintenance: {message}")]
+    Maintenance {
+        message: String,
+        maintenance_message: Option<String>,
+    },
+
+    #[error("Shutting down: {message}")]
+    ShuttingDown { message: String },
+
+    #[error("Not provisioned: {message}")]
+    NotProvisioned { message: String },
+
+    #[error("Work directory locked: {message}")]
+    WorkDirLocked { message: String },
+
+    #[error("MCP server unresponsive: {message}")]
+    ChildUnresponsive { message: String },
+
+    #[error("MCP server exited early: {message}")]
+    ChildExitedEarly {
+        message: String,
+        exit_code: Option<i32>,
+
+        /// First lines the child wrote to stdout and stderr
+        output: Vec<String>,
+    },
+
+    #[error("MCP handshake failed: {message}")]
+    HandshakeFailed {
+        message: String,
+        failure: HandshakeFailure,
+
+        /// Initialize attempts made before giving up
+        attempts: u32,
+    },
+
+    #[error("Notification not allowed: {message}")]
+    NotificationNotAllowed { message: String },
+
+    #[error("Invalid tool arguments: {message}")]
+    InvalidToolArguments {
+        message: String,
+        errors: Vec<SchemaViolation>,
+    },
+
+    #[error("Tool error: {message}")]
+    ToolError { message: String },
+
+    #[error("Response too large: {message}")]
+    ResponseTooLarge { message: String },
+
+    #[error("Invalid upstream response: {message}")]
+    InvalidUpstreamResponse {
+        message: String,
+        errors: Vec<SchemaViolation>,
+    },
+
+    #[error("Unsupported media type: {message}")]
+    UnsupportedMediaType {
+        message: String,
+
+        /// Content-Type the request was sent with, if any
+        content_type: Option<String>,
+    },
+
+    #[error("Malformed request body: {message}")]
+    MalformedBody {
+        message: String,
+        line: usize,
+        column: usize,
+    },
+
+    #[error("Request rejected: {message}")]
+    HookRejected { status: StatusCode, message: String },
+
     #[error("Serialization error: {0}")]
     SerializationError(#[from] serde_json::Error),
 
@@ -27,5 +156,202 @@ pub enum McpCoreError {
     IoError(#[from] std::io::Error),
 }
 
+impl From<crate::hooks::HookError> for McpCoreError {
+    fn from(error: crate::hooks::HookError) -> Self {
+        McpCoreError::HookRejected {
+            status: error.status,
+            message: error.message,
+        }
+    }
+}
+
+/// Body of an HTTP error response
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ErrorBody {
+    /// Reason phrase of the status code
+    pub error: String,
+    pub message: String,
+
+    /// Machine-readable code, see [`McpCoreError::error_code`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+
+    /// Operator's message about a server drained for maintenance
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maintenance_message: Option<String>,
+
+    /// Quota an API key used up
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota: Option<String>,
+
+    /// When the used up quota starts over
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resets_at: Option<DateTime<Utc>>,
+
+    /// Timeout a request ran past, in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+
+    /// Limit that set the timeout: `header`, `method:<key>`, `server`, or
+    /// `default`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_limit: Option<String>,
+
+    /// Schema violations of rejected tool arguments or server results
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<SchemaViolation>,
+
+    /// Content-Type of a request refused for it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+
+    /// Where in a malformed request body parsing failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+}
+
+/// Status of a request the client cancelled, as nginx logs it
+pub const CLIENT_CLOSED_REQUEST: u16 = 499;
+
 /// Convenient Result type for MCP Core operations
 pub type McpCoreResult<T> = Result<T, McpCoreError>;
+
+impl McpCoreError {
+    /// HTTP status code corresponding to this error
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            McpCoreError::AuthenticationError { .. } => StatusCode::UNAUTHORIZED,
+            McpCoreError::RequestError { .. } => StatusCode::BAD_REQUEST,
+            McpCoreError::InvalidCommand { .. } => StatusCode::BAD_REQUEST,
+            McpCoreError::NotFound { .. } => StatusCode::NOT_FOUND,
+            McpCoreError::RequestAborted { .. } => StatusCode::GATEWAY_TIMEOUT,
+            McpCoreError::RequestCancelled { .. } => {
+                StatusCode::from_u16(CLIENT_CLOSED_REQUEST).expect("valid status code")
+            }
+            McpCoreError::RequestTimeout { .. } => StatusCode::GATEWAY_TIMEOUT,
+            McpCoreError::Overloaded { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            McpCoreError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            McpCoreError::QuotaExceeded { .. } => StatusCode::TOO_MANY_REQUESTS,
+            McpCoreError::Maintenance { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            McpCoreError::ShuttingDown { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            McpCoreError::NotProvisioned { .. } => StatusCode::CONFLICT,
+            McpCoreError::WorkDirLocked { .. } => StatusCode::CONFLICT,
+            McpCoreError::ChildUnresponsive { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            McpCoreError::ChildExitedEarly { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            McpCoreError::HandshakeFailed { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            McpCoreError::NotificationNotAllowed { .. } => StatusCode::FORBIDDEN,
+            McpCoreError::InvalidToolArguments { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            McpCoreError::ToolError { .. } => StatusCode::BAD_GATEWAY,
+            McpCoreError::ResponseTooLarge { .. } => StatusCode::BAD_GATEWAY,
+            McpCoreError::InvalidUpstreamResponse { .. } => StatusCode::BAD_GATEWAY,
+            McpCoreError::UnsupportedMediaType { .. } => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            McpCoreError::MalformedBody { .. } => StatusCode::BAD_REQUEST,
+            McpCoreError::HookRejected { status, .. } => *status,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Machine-readable error code included in HTTP error responses
+    pub fn error_code(&self) -> Option<&'static str> {
+        match self {
+            McpCoreError::InvalidCommand { code, .. } => Some(code),
+            McpCoreError::RequestTimeout { .. } => Some("timeout"),
+            McpCoreError::RequestCancelled { .. } => Some("request_cancelled"),
+            McpCoreError::Overloaded { .. } => Some("overloaded"),
+            McpCoreError::RateLimited { .. } => Some("rate_limited"),
+            McpCoreError::QuotaExceeded { .. } => Some("quota_exceeded"),
+            McpCoreError::Maintenance { .. } => Some("maintenance"),
+            McpCoreError::ShuttingDown { .. } => Some("shutting_down"),
+            McpCoreError::NotProvisioned { .. } => Some("not_provisioned"),
+            McpCoreError::WorkDirLocked { .. } => Some("work_dir_locked"),
+            McpCoreError::ChildUnresponsive { .. } => Some("child_unresponsive"),
+            McpCoreError::ChildExitedEarly { .. } => Some("child_exited_early"),
+            McpCoreError::HandshakeFailed { .. } => Some("handshake_failed"),
+            McpCoreError::NotificationNotAllowed { .. } => Some("notification_not_allowed"),
+            McpCoreError::InvalidToolArguments { .. } => Some("invalid_arguments"),
+            McpCoreError::ToolError { .. } => Some("tool_error"),
+            McpCoreError::ResponseTooLarge { .. } => Some("response_too_large"),
+            McpCoreError::InvalidUpstreamResponse { .. } => Some("invalid_upstream_response"),
+            McpCoreError::UnsupportedMediaType { .. } => Some("unsupported_media_type"),
+            McpCoreError::MalformedBody { .. } => Some("malformed_body"),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "http-server")]
+impl IntoResponse for McpCoreError {
+    fn into_response(self) -> Response {
+        let status = self.status_code();
+        let body = ErrorBody {
+            error: match &self {
+                McpCoreError::RequestCancelled { .. } => "Client Closed Request",
+                _ => status.canonical_reason().unwrap_or("Error"),
+            }
+            .to_string(),
+            message: self.to_string(),
+            code: self.error_code().map(str::to_string),
+            maintenance_message: match &self {
+                McpCoreError::Maintenance {
+                    maintenance_message,
+                    ..
+                } => maintenance_message.clone(),
+                _ => None,
+            },
+            quota: match &self {
+                McpCoreError::QuotaExceeded { quota, .. } => Some(quota.to_string()),
+                _ => None,
+            },
+            resets_at: match &self {
+                McpCoreError::QuotaExceeded { resets_at, .. } => Some(*resets_at),
+                _ => None,
+            },
+            timeout_secs: match &self {
+                McpCoreError::RequestTimeout { timeout_secs, .. } => Some(*timeout_secs),
+                _ => None,
+            },
+            timeout_limit: match &self {
+                McpCoreError::RequestTimeout { limit, .. } => Some(limit.clone()),
+                _ => None,
+            },
+            errors: match &self {
+                McpCoreError::InvalidToolArguments { errors, .. }
+                | McpCoreError::InvalidUpstreamResponse { errors, .. } => errors.clone(),
+                _ => Vec::new(),
+            },
+            content_type: match &self {
+                McpCoreError::UnsupportedMediaType { content_type, .. } => content_type.clone(),
+                _ => None,
+            },
+            line: match &self {
+                McpCoreError::MalformedBody { line, .. } => Some(*line),
+                _ => None,
+            },
+            column: match &self {
+                McpCoreError::MalformedBody { column, .. } => Some(*column),
+                _ => None,
+            },
+        };
+        let mut response = (status, Json(body)).into_response();
+        if let McpCoreError::Overloaded {
+            retry_after_secs, ..
+        }
+        | McpCoreError::RateLimited {
+            retry_after_secs, ..
+        }
+        | McpCoreError::QuotaExceeded {
+            retry_after_secs, ..
+        } = self
+        {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, retry_after_secs.into());
+        }
+        response
+            .extensions_mut()
+            .insert(crate::access_log::ErrorMessage(self.to_string()));
+        response
+    }
+}
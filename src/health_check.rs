@@ -0,0 +1,595 @@
+//! Health checks calling a method of the server and checking its result
+//!
+//! A child can keep answering while every real call fails, for instance once
+//! the credential it uses upstream expired. A server's `health_check` sends
+//! `method` with `params` every `interval_secs` through the same request path
+//! as clients and checks the response: it must be a result rather than a
+//! JSON-RPC error, must not be flagged `isError` unless `allow_is_error` is
+//! set, and, with `expect`, must have a value at the JSONPath `path`, equal
+//! to `equals` if given. Once `failure_threshold` checks in a row failed, the
+//! server is unhealthy and its child is restarted blue-green, like an
+//! unresponsive one.
+//!
+//! Health checks are internal traffic: they go to the serving child only,
+//! are exempt from load shedding, quotas, the request statistics, and the
+//! canary comparison, and wait for their turn with the transport at low
+//! priority so client requests go first. No check is sent while the server
+//! is not provisioned, drained, or replacing an unresponsive child, and a
+//! check refused by the gateway itself is not counted.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+use crate::error::{McpCoreError, McpCoreResult};
+use crate::history::Trigger;
+use crate::provision::{Provisioner, RestartStrategy};
+
+/// Time between checks unless `interval_secs` is set
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Time a check may take unless `timeout_secs` is set
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Checks failing in a row before the server is unhealthy, unless
+/// `failure_threshold` is set
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+
+/// A server's health check
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HealthCheckConfig {
+    /// JSON-RPC method called, e.g. `tools/call`
+    pub method: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<Value>,
+
+    /// Value the result must have
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect: Option<HealthExpectation>,
+
+    /// Count a result with `isError: true` as passing
+    #[serde(default)]
+    pub allow_is_error: bool,
+
+    /// Seconds between checks (default 30)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval_secs: Option<u64>,
+
+    /// Seconds a check may take (default 10)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+
+    /// Checks failing in a row before the child is restarted (default 3)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_threshold: Option<u32>,
+}
+
+/// Where the response must have a value, and which
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HealthExpectation {
+    /// JSONPath into the whole response, e.g. `$.result.content[0].text`
+    pub path: String,
+
+    /// Value required at `path`; without it any value passes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub equals: Option<Value>,
+}
+
+impl HealthCheckConfig {
+    /// Check the method, the JSONPath, and the numbers
+    pub fn validate(&self) -> Result<(), String> {
+        if self.method.is_empty() {
+            return Err("health_check has an empty method".to_string());
+        }
+        if self.interval_secs == Some(0)
+            || self.timeout_secs == Some(0)
+            || self.failure_threshold == Some(0)
+        {
+            return Err(
+                "health_check interval_secs, timeout_secs, and failure_threshold must be positive"
+                    .to_string(),
+            );
+        }
+        if let Some(expect) = &self.expect {
+            JsonPath::parse(&expect.path)?;
+        }
+        Ok(())
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval_secs
+            .map_or(DEFAULT_INTERVAL, Duration::from_secs)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout_secs
+            .map_or(DEFAULT_TIMEOUT, Duration::from_secs)
+    }
+
+    fn failure_threshold(&self) -> u32 {
+        self.failure_threshold.unwrap_or(DEFAULT_FAILURE_THRESHOLD)
+    }
+}
+
+/// Step of a JSONPath
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// The JSONPath subset of member and index steps: `$.a.b[0]['c d']`
+#[derive(Debug, Clone, PartialEq)]
+struct JsonPath(Vec<Segment>);
+
+impl JsonPath {
+    fn parse(path: &str) -> Result<Self, String> {
+        let invalid = |why: &str| format!("health_check path '{}' {}", path, why);
+        let mut rest = path
+            .strip_prefix('$')
+            .ok_or_else(|| invalid("does not start with $"))?;
+        let mut segments = Vec::new();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('.') {
+                let end = after.find(['.', '[']).unwrap_or(after.len());
+                if end == 0 {
+                    return Err(invalid("has an empty member name"));
+                }
+                segments.push(Segment::Key(after[..end].to_string()));
+                rest = &after[end..];
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let end = after
+                    .find(']')
+                    .ok_or_else(|| invalid("has an unclosed ["))?;
+                let inside = &after[..end];
+                let quoted = inside
+                    .strip_prefix('\'')
+                    .and_then(|inside| inside.strip_suffix('\''))
+                    .or_else(|| {
+                        inside
+                            .strip_prefix('"')
+                            .and_then(|inside| inside.strip_suffix('"'))
+                    });
+                segments.push(match quoted {
+                    Some(key) => Segment::Key(key.to_string()),
+                    None => Segment::Index(
+                        inside
+                            .parse()
+                            .map_err(|_| invalid("has an index that is not a number"))?,
+                    ),
+                });
+                rest = &after[end + 1..];
+            } else {
+                return Err(invalid("is not made of .member and [index] steps"));
+            }
+        }
+        Ok(Self(segments))
+    }
+
+    fn find<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+        self.0
+            .iter()
+            .try_fold(value, |value, segment| match segment {
+                Segment::Key(key) => value.get(key),
+                Segment::Index(index) => value.get(index),
+            })
+    }
+}
+
+/// Outcome of the latest check
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckOutcome {
+    pub at: DateTime<Utc>,
+    pub passed: bool,
+    pub latency_ms: u64,
+
+    /// Why the check failed
+    pub error: Option<String>,
+}
+
+/// Health of the server, for `GET /ready`
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthSnapshot {
+    pub healthy: bool,
+    pub consecutive_failures: u32,
+    pub failure_threshold: u32,
+    pub checks: u64,
+    pub failures: u64,
+
+    /// Restarts of the child after it became unhealthy
+    pub restarts: u64,
+    pub last: Option<CheckOutcome>,
+}
+
+#[derive(Debug, Default)]
+struct HealthState {
+    consecutive_failures: u32,
+    checks: u64,
+    failures: u64,
+    restarts: u64,
+    last: Option<CheckOutcome>,
+}
+
+/// Runs a server's health check and restarts the child it finds unhealthy
+#[derive(Debug)]
+pub struct HealthChecker {
+    config: HealthCheckConfig,
+    path: Option<JsonPath>,
+    provisioner: Arc<Provisioner>,
+    sent: AtomicU64,
+    state: Mutex<HealthState>,
+}
+
+impl HealthChecker {
+    pub fn new(config: HealthCheckConfig, provisioner: Arc<Provisioner>) -> Result<Self, String> {
+        config.validate()?;
+        let path = config
+            .expect
+            .as_ref()
+            .map(|expect| JsonPath::parse(&expect.path))
+            .transpose()?;
+        Ok(Self {
+            config,
+            path,
+            provisioner,
+            sent: AtomicU64::new(0),
+            state: Mutex::new(HealthState::default()),
+        })
+    }
+
+    pub fn config(&self) -> &HealthCheckConfig {
+        &self.config
+    }
+
+    /// Whether fewer than `failure_threshold` checks in a row failed
+    pub fn is_healthy(&self) -> bool {
+        self.lock().consecutive_failures < self.config.failure_threshold()
+    }
+
+    /// The request of the next check, with an id of its own
+    pub fn command(&self) -> String {
+        let sent = self.sent.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut message = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": format!("health-check-{}", sent),
+            "method": self.config.method,
+        });
+        if let Some(params) = &self.config.params {
+            message["params"] = params.clone();
+        }
+        message.to_string()
+    }
+
+    /// Check `response` against the expectations
+    pub fn evaluate(&self, response: &str) -> Result<(), String> {
+        let message: Value =
+            serde_json::from_str(response).map_err(|e| format!("response is not JSON: {}", e))?;
+        if let Some(error) = message.get("error") {
+            return Err(format!("answered with an error: {}", error));
+        }
+        let Some(result) = message.get("result") else {
+            return Err("response has no result".to_string());
+        };
+        if !self.config.allow_is_error && result.get("isError") == Some(&Value::Bool(true)) {
+            let text = result
+                .pointer("/content/0/text")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            return Err(format!("result has isError set: {}", text));
+        }
+        let (Some(path), Some(expect)) = (&self.path, &self.config.expect) else {
+            return Ok(());
+        };
+        match (path.find(&message), &expect.equals) {
+            (None, _) => Err(format!("response has no value at {}", expect.path)),
+            (Some(found), Some(equals)) if found != equals => Err(format!(
+                "response has {} at {} instead of {}",
+                found, expect.path, equals
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Send a check with `send` and record its outcome, restarting the
+    /// child once the failures reach the threshold
+    ///
+    /// `send` returns `None` when the server cannot be checked now. Returns
+    /// the outcome of a check that was counted.
+    pub async fn run_once<F, Fut>(&self, send: F) -> Option<CheckOutcome>
+    where
+        F: FnOnce(String) -> Fut,
+        Fut: Future<Output = Option<McpCoreResult<String>>>,
+    {
+        let started = Instant::now();
+        let checked = match send(self.command()).await? {
+            Ok(response) => self.evaluate(&response),
+            Err(e) if is_refusal(&e) => {
+                tracing::debug!("Health check not sent: {}", e);
+                return None;
+            }
+            Err(e) => Err(e.to_string()),
+        };
+        let outcome = CheckOutcome {
+            at: Utc::now(),
+            passed: checked.is_ok(),
+            latency_ms: started.elapsed().as_millis() as u64,
+            error: checked.err(),
+        };
+
+        let consecutive_failures = {
+            let mut state = self.lock();
+            state.checks += 1;
+            state.last = Some(outcome.clone());
+            if outcome.passed {
+                state.consecutive_failures = 0;
+                return Some(outcome);
+            }
+            state.failures += 1;
+            state.consecutive_failures += 1;
+            state.consecutive_failures
+        };
+        let error = outcome.error.as_deref().unwrap_or_default();
+        tracing::warn!(
+            "Health check failed ({} in a row): {}",
+            consecutive_failures,
+            error
+        );
+        if consecutive_failures >= self.config.failure_threshold() {
+            let reason = format!(
+                "health check failed {} times in a row: {}",
+                consecutive_failures, error
+            );
+            match self
+                .provisioner
+                .restart(RestartStrategy::BlueGreen, Trigger::HealthCheck, &reason)
+                .await
+            {
+                Ok(_) => {
+                    let mut state = self.lock();
+                    state.consecutive_failures = 0;
+                    state.restarts += 1;
+                }
+                Err(e) => tracing::error!("Restarting the unhealthy MCP server failed: {}", e),
+            }
+        }
+        Some(outcome)
+    }
+
+    /// Run a check every `interval_secs` with `send` until the task is
+    /// aborted
+    pub fn spawn<F, Fut>(self: Arc<Self>, send: F) -> JoinHandle<()>
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<McpCoreResult<String>>> + Send,
+    {
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(self.config.interval());
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                self.run_once(&send).await;
+            }
+        })
+    }
+
+    pub fn snapshot(&self) -> HealthSnapshot {
+        let state = self.lock();
+        HealthSnapshot {
+            healthy: state.consecutive_failures < self.config.failure_threshold(),
+            consecutive_failures: state.consecutive_failures,
+            failure_threshold: self.config.failure_threshold(),
+            checks: state.checks,
+            failures: state.failures,
+            restarts: state.restarts,
+            last: state.last.clone(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HealthState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Whether the gateway refused to send the check, which says nothing about
+/// the server
+fn is_refusal(error: &McpCoreError) -> bool {
+    matches!(
+        error,
+        McpCoreError::Overloaded { .. }
+            | McpCoreError::RateLimited { .. }
+            | McpCoreError::QuotaExceeded { .. }
+            | McpCoreError::Maintenance { .. }
+            | McpCoreError::ShuttingDown { .. }
+            | McpCoreError::NotProvisioned { .. }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provision::{ProvisionFn, Provisioned, Unprovisioned};
+    use crate::timing::{PhaseTimer, PhaseTimings};
+    use crate::transport::McpTransport;
+
+    fn config(json: Value) -> HealthCheckConfig {
+        serde_json::from_value(json).unwrap()
+    }
+
+    /// Checker over a server whose restarts always succeed
+    fn checker(json: Value) -> HealthChecker {
+        let provisioned = || Provisioned {
+            protocol_version: "2025-06-18".to_string(),
+            pid: None,
+            stderr: None,
+            startup: PhaseTimings::default(),
+            audit: None,
+            commit: None,
+            package_version: None,
+            artifact_cache: None,
+            sandbox: None,
+            egress: None,
+        };
+        let pipeline: ProvisionFn = Arc::new(move |_timer: PhaseTimer, _trigger: Trigger| {
+            Box::pin(async move {
+                let transport: Box<dyn McpTransport> = Box::new(Unprovisioned);
+                Ok::<_, McpCoreError>((transport, provisioned()))
+            })
+        });
+        let transport: Box<dyn McpTransport> = Box::new(Unprovisioned);
+        let provisioner = Provisioner::ready(
+            "health",
+            Arc::new(tokio::sync::Mutex::new(transport)),
+            provisioned(),
+        )
+        .with_pipeline(pipeline);
+        HealthChecker::new(config(json), Arc::new(provisioner)).unwrap()
+    }
+
+    #[test]
+    fn test_config_and_path_validation() {
+        let error = |json| config(json).validate().unwrap_err();
+        assert!(error(serde_json::json!({ "method": "" })).contains("empty method"));
+        assert!(
+            error(serde_json::json!({ "method": "ping", "interval_secs": 0 })).contains("positive")
+        );
+        for (path, reason) in [
+            ("result", "does not start with $"),
+            ("$.result..text", "empty member name"),
+            ("$.content[0", "unclosed ["),
+            ("$.content[first]", "not a number"),
+            ("$result", "steps"),
+        ] {
+            let json = serde_json::json!({ "method": "ping", "expect": { "path": path } });
+            assert!(error(json).contains(reason), "{}", path);
+        }
+
+        let path = JsonPath::parse("$.result.content[0]['text value']").unwrap();
+        assert_eq!(
+            path,
+            JsonPath(vec![
+                Segment::Key("result".to_string()),
+                Segment::Key("content".to_string()),
+                Segment::Index(0),
+                Segment::Key("text value".to_string()),
+            ])
+        );
+        assert_eq!(JsonPath::parse("$").unwrap(), JsonPath(Vec::new()));
+    }
+
+    #[test]
+    fn test_responses_are_checked_against_the_expectations() {
+        let response = |result: Value| {
+            serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": result }).to_string()
+        };
+        let ok = response(serde_json::json!({
+            "content": [{ "type": "text", "text": "valid" }],
+            "isError": false
+        }));
+        let failed = response(serde_json::json!({
+            "content": [{ "type": "text", "text": "credential expired" }],
+            "isError": true
+        }));
+
+        let plain = checker(serde_json::json!({ "method": "tools/call" }));
+        assert_eq!(plain.evaluate(&ok), Ok(()));
+        assert_eq!(
+            plain.evaluate(&failed).unwrap_err(),
+            "result has isError set: credential expired"
+        );
+        let error = r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32601,"message":"nope"}}"#;
+        assert!(plain.evaluate(error).unwrap_err().contains("-32601"));
+        assert!(plain.evaluate("server ready").is_err());
+
+        let lenient =
+            checker(serde_json::json!({ "method": "tools/call", "allow_is_error": true }));
+        assert_eq!(lenient.evaluate(&failed), Ok(()));
+
+        let exists = checker(serde_json::json!({
+            "method": "tools/call",
+            "expect": { "path": "$.result.content[0].text" }
+        }));
+        assert_eq!(exists.evaluate(&ok), Ok(()));
+        assert_eq!(
+            exists.evaluate(&response(serde_json::json!({ "content": [] }))),
+            Err("response has no value at $.result.content[0].text".to_string())
+        );
+
+        let equals = checker(serde_json::json!({
+            "method": "tools/call",
+            "expect": { "path": "$.result.content[0].text", "equals": "valid" },
+            "allow_is_error": true
+        }));
+        assert_eq!(equals.evaluate(&ok), Ok(()));
+        assert_eq!(
+            equals.evaluate(&failed),
+            Err(
+                r#"response has "credential expired" at $.result.content[0].text instead of "valid""#
+                    .to_string()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failures_in_a_row_restart_the_child() {
+        let checker = checker(serde_json::json!({
+            "method": "tools/call",
+            "failure_threshold": 2
+        }));
+        let passing = |_| async {
+            Some(Ok(
+                r#"{"jsonrpc":"2.0","id":1,"result":{"content":[],"isError":false}}"#.to_string(),
+            ))
+        };
+        let failing = |_| async {
+            Some(Err(McpCoreError::RequestTimeout {
+                message: "no answer".to_string(),
+                timeout_secs: 10,
+                limit: "server".to_string(),
+            }))
+        };
+
+        assert!(checker.run_once(failing).await.is_some());
+        assert!(checker.run_once(passing).await.unwrap().passed);
+        assert!(!checker.run_once(failing).await.unwrap().passed);
+        assert!(checker.is_healthy());
+        assert_eq!(checker.provisioner.restarts().restarts, 0);
+
+        // Refusals and unsent checks are not counted
+        assert!(checker.run_once(|_| async { None }).await.is_none());
+        let refused = |_| async {
+            Some(Err(McpCoreError::Maintenance {
+                message: "drained".to_string(),
+                maintenance_message: None,
+            }))
+        };
+        assert!(checker.run_once(refused).await.is_none());
+        assert_eq!(checker.snapshot().consecutive_failures, 1);
+
+        let outcome = checker.run_once(failing).await.unwrap();
+        assert!(!outcome.passed);
+        let restarts = checker.provisioner.restarts();
+        assert_eq!(restarts.restarts, 1);
+        let last = restarts.last.unwrap();
+        assert_eq!(last.strategy, RestartStrategy::BlueGreen);
+        assert!(last
+            .reason
+            .starts_with("health check failed 2 times in a row: "));
+
+        let snapshot = checker.snapshot();
+        assert!(snapshot.healthy);
+        assert_eq!(
+            (snapshot.checks, snapshot.failures, snapshot.restarts),
+            (4, 3, 1)
+        );
+        assert_eq!(snapshot.last.unwrap().error, outcome.error);
+    }
+}
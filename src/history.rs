@@ -0,0 +1,231 @@
+//! Recent lifecycle transitions of each server, kept in memory
+//!
+//! Every path that spawns, initializes, restarts, or stops a server's child
+//! appends a [`LifecycleRecord`] saying what happened, what caused it, and,
+//! for an exit, the child's exit code or signal. The last `history_size`
+//! records (default [`DEFAULT_HISTORY_SIZE`]) are kept per server and served
+//! by [`crate::manager::ProcessManager::history`] and
+//! `GET /admin/servers/{name}/history`, so an embedding application can say
+//! "crashed with exit code 1 at 14:02, restarted twice" without reading logs.
+//!
+//! An exit is recorded once per child, by whichever path notices it first:
+//! a failed request finding the child gone records a crash, and a restart
+//! or stop shutting down a child that already exited adds nothing.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// Records kept per server unless `history_size` says otherwise
+pub const DEFAULT_HISTORY_SIZE: usize = 50;
+
+/// What happened to the child
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transition {
+    /// The child process was spawned
+    Spawned,
+    /// The handshake succeeded and the child serves requests
+    Initialized,
+    /// The child exited, or was stopped
+    Exited,
+    /// A restart began replacing the child
+    Restarting,
+    /// A setup failed; the error says where
+    Failed,
+}
+
+/// What caused a transition
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    /// Setting the server up at start, or on a request after a failed setup
+    Start,
+    /// An operator, through the admin API
+    Admin,
+    /// The embedding application, through the process manager
+    Manager,
+    /// The child exiting on its own
+    Crash,
+    /// A threshold of the server's `recycle` policy
+    Recycle,
+    /// The child no longer reading its stdin
+    Unresponsive,
+    /// The server's `health_check` failing too many times in a row
+    HealthCheck,
+    /// The gateway shutting down
+    Shutdown,
+}
+
+/// How a child exited
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ChildExit {
+    pub code: Option<i32>,
+
+    /// Signal that ended the child, on Unix
+    pub signal: Option<i32>,
+}
+
+impl From<std::process::ExitStatus> for ChildExit {
+    fn from(status: std::process::ExitStatus) -> Self {
+        #[cfg(unix)]
+        let signal = std::os::unix::process::ExitStatusExt::signal(&status);
+        #[cfg(not(unix))]
+        let signal = None;
+        Self {
+            code: status.code(),
+            signal,
+        }
+    }
+}
+
+/// One lifecycle transition of a server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LifecycleRecord {
+    pub at: DateTime<Utc>,
+    pub transition: Transition,
+    pub trigger: Trigger,
+
+    /// Child the transition happened to, for transports that own one
+    pub pid: Option<u32>,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+
+    /// Error of a failure, or why a restart was asked for
+    pub detail: Option<String>,
+}
+
+impl LifecycleRecord {
+    pub fn new(transition: Transition, trigger: Trigger) -> Self {
+        Self {
+            at: Utc::now(),
+            transition,
+            trigger,
+            pid: None,
+            exit_code: None,
+            signal: None,
+            detail: None,
+        }
+    }
+
+    pub fn with_pid(mut self, pid: Option<u32>) -> Self {
+        self.pid = pid;
+        self
+    }
+
+    pub fn with_exit(mut self, exit: Option<ChildExit>) -> Self {
+        self.exit_code = exit.and_then(|exit| exit.code);
+        self.signal = exit.and_then(|exit| exit.signal);
+        self
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+}
+
+/// The last lifecycle records of one server, oldest first
+#[derive(Debug)]
+pub struct LifecycleHistory {
+    capacity: usize,
+    records: Mutex<VecDeque<LifecycleRecord>>,
+}
+
+impl Default for LifecycleHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_SIZE)
+    }
+}
+
+impl LifecycleHistory {
+    /// History keeping the last `capacity` records
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Append `record`, dropping the oldest beyond the capacity
+    ///
+    /// A second exit of the same child is ignored.
+    pub fn record(&self, record: LifecycleRecord) {
+        let mut records = self.lock();
+        if record.transition == Transition::Exited && record.pid.is_some() {
+            let exited = records
+                .iter()
+                .rev()
+                .filter(|earlier| earlier.pid == record.pid)
+                .take_while(|earlier| earlier.transition != Transition::Spawned)
+                .any(|earlier| earlier.transition == Transition::Exited);
+            if exited {
+                return;
+            }
+        }
+        records.push_back(record);
+        while records.len() > self.capacity {
+            records.pop_front();
+        }
+    }
+
+    /// The kept records, oldest first
+    pub fn records(&self) -> Vec<LifecycleRecord> {
+        self.lock().iter().cloned().collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<LifecycleRecord>> {
+        self.records
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_keeps_last_records_and_one_exit_per_child() {
+        let history = LifecycleHistory::new(3);
+        let exited = |trigger| LifecycleRecord::new(Transition::Exited, trigger).with_pid(Some(7));
+        history.record(LifecycleRecord::new(Transition::Spawned, Trigger::Start).with_pid(Some(7)));
+        history.record(exited(Trigger::Crash).with_exit(Some(ChildExit {
+            code: Some(1),
+            signal: None,
+        })));
+        history.record(LifecycleRecord::new(Transition::Restarting, Trigger::Admin));
+        history.record(exited(Trigger::Admin));
+
+        let records = history.records();
+        let transitions: Vec<_> = records.iter().map(|record| record.transition).collect();
+        assert_eq!(
+            transitions,
+            [
+                Transition::Spawned,
+                Transition::Exited,
+                Transition::Restarting
+            ]
+        );
+        assert_eq!(records[1].trigger, Trigger::Crash);
+        assert_eq!(records[1].exit_code, Some(1));
+
+        // A new child with the same pid exits again; the oldest record goes
+        history.record(LifecycleRecord::new(Transition::Spawned, Trigger::Admin).with_pid(Some(7)));
+        history.record(exited(Trigger::Manager));
+        let transitions: Vec<_> = history
+            .records()
+            .iter()
+            .map(|record| record.transition)
+            .collect();
+        assert_eq!(
+            transitions,
+            [
+                Transition::Restarting,
+                Transition::Spawned,
+                Transition::Exited
+            ]
+        );
+    }
+}
@@ -0,0 +1,137 @@
+//! Request and response transformation hooks for embedders
+//!
+//! Hooks are registered on [`crate::http_server::McpHttpServerBuilder`] and
+//! run in `handle_mcp_request` around the call to the MCP server. Hooks are
+//! async, so they may await a lookup or a remote policy check; each gets the
+//! message and returns it, changed or not. Request hooks may reject a
+//! request; response hooks may only change the response.
+//!
+//! Values registered with
+//! [`McpHttpServerBuilder::extension`](crate::http_server::McpHttpServerBuilder::extension),
+//! such as a database pool, reach the hooks through
+//! [`RequestContext::extension`].
+
+use http::{Extensions, StatusCode};
+use serde_json::Value;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Per-request information passed to hooks
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    /// Name of the MCP server handling the request
+    pub server_name: String,
+
+    /// JSON-RPC method of the request, if present
+    pub method: Option<String>,
+
+    /// Name of the API key that authenticated the request, if any
+    pub api_key_name: Option<String>,
+
+    /// JSON-RPC id of the request, if present
+    pub request_id: Option<Value>,
+
+    /// Values the embedder registered on the builder, by type
+    pub extensions: Arc<Extensions>,
+}
+
+impl RequestContext {
+    /// Value of type `T` the embedder registered, if any
+    pub fn extension<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.extensions.get::<T>()
+    }
+}
+
+/// Error returned by a request hook to reject the request
+#[derive(Debug, Clone)]
+pub struct HookError {
+    /// HTTP status returned to the client
+    pub status: StatusCode,
+
+    /// Message returned to the client
+    pub message: String,
+}
+
+impl HookError {
+    /// Create a hook error with the given status and message
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+/// Future returned by a hook
+pub type HookFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// Hook invoked with the outgoing JSON-RPC message before it is sent,
+/// resolving to the message to send
+pub type RequestHook =
+    Arc<dyn Fn(Value, RequestContext) -> HookFuture<Result<Value, HookError>> + Send + Sync>;
+
+/// Hook invoked with the JSON-RPC response before it is returned, resolving
+/// to the response to return
+pub type ResponseHook = Arc<dyn Fn(Value, RequestContext) -> HookFuture<Value> + Send + Sync>;
+
+/// Box an async closure as a [`RequestHook`]
+pub fn request_hook<F, Fut>(hook: F) -> RequestHook
+where
+    F: Fn(Value, RequestContext) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Value, HookError>> + Send + 'static,
+{
+    Arc::new(move |message, context| Box::pin(hook(message, context)))
+}
+
+/// Box an async closure as a [`ResponseHook`]
+pub fn response_hook<F, Fut>(hook: F) -> ResponseHook
+where
+    F: Fn(Value, RequestContext) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Value> + Send + 'static,
+{
+    Arc::new(move |message, context| Box::pin(hook(message, context)))
+}
+
+/// Registered hooks, run in registration order
+#[derive(Clone, Default)]
+pub struct Hooks {
+    pub on_request: Vec<RequestHook>,
+    pub on_response: Vec<ResponseHook>,
+}
+
+impl Hooks {
+    /// Whether any hook is registered
+    pub fn is_empty(&self) -> bool {
+        self.on_request.is_empty() && self.on_response.is_empty()
+    }
+
+    /// Run request hooks in turn, stopping at the first error
+    pub async fn run_request(
+        &self,
+        mut message: Value,
+        context: &RequestContext,
+    ) -> Result<Value, HookError> {
+        for hook in &self.on_request {
+            message = hook(message, context.clone()).await?;
+        }
+        Ok(message)
+    }
+
+    /// Run response hooks in turn
+    pub async fn run_response(&self, mut message: Value, context: &RequestContext) -> Value {
+        for hook in &self.on_response {
+            message = hook(message, context.clone()).await;
+        }
+        message
+    }
+}
+
+impl std::fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Hooks")
+            .field("on_request", &self.on_request.len())
+            .field("on_response", &self.on_response.len())
+            .finish()
+    }
+}
@@ -1,352 +1,7356 @@
 //! HTTP server module for MCP Core
 
-use axum::{extract::State, http::StatusCode, middleware, response::Json, routing::post, Router};
-use std::sync::Arc;
-use tokio::sync::Mutex;
-use tracing;
+use axum::{
+    body::{Body, HttpBody},
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header, Extensions, HeaderMap, HeaderValue, StatusCode, Uri},
+    middleware,
+    response::{
+        sse::{Event, Sse},
+        IntoResponse, Json, Response,
+    },
+    routing::get,
+    Extension, Router,
+};
+use futures_util::Stream;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex, Semaphore};
+use tokio::task::{JoinHandle, JoinSet};
+use tracing::Instrument;
 
 use crate::{
-    auth::bearer_auth_middleware,
+    access_log::{self, AccessLog, AccessLogConfig, RpcLabels},
+    admin,
+    auth::{self, bearer_auth_middleware, ApiKeyName, SharedAuth},
+    balance::{self, PickedReplica, ReplicaPool},
+    breaker::StdinBreaker,
+    build_log::BuildLogs,
+    canary::{self, CanaryRouter, SharedTransport, Variant},
+    capabilities::{Capabilities, Requirement},
+    capture::Captures,
+    client_notifications::{self, NotificationAllowlist},
     config::{AuthConfig, McpServersConfig},
+    connection,
+    context_meta::{self, CallerContext, ContextMetaConfig},
+    diagnostics::{self, DiagnosticsOptions},
+    elicitation::{ElicitationRegistry, DEFAULT_ELICITATION_TIMEOUT},
     error::{McpCoreError, McpCoreResult},
-    process::{McpProcess, McpRequest, McpResponse},
+    health_check::HealthChecker,
+    history::{self, LifecycleHistory, Trigger},
+    hooks::{self, HookError, Hooks, RequestContext},
+    id_rewrite::IdRewriter,
+    inflight::{self, AbortReason, InflightGuard, InflightPhase, InflightRegistry},
+    injection::{apply_injection_rules, ParamInjectionRule},
+    lifecycle::LifecycleState,
+    listener::{self, HttpConfig, ListenerConfig, PeerAddr, RouteGroup},
+    maintenance::Maintenance,
+    manager,
+    method_timeout::{self, MethodTimeouts, ResolvedTimeout},
+    notifications::{
+        NotificationPage, NotificationRing, DEFAULT_MAX_NOTIFICATION_WAIT,
+        DEFAULT_NOTIFICATION_BUFFER,
+    },
+    peek::CommandPeek,
+    pipeline::{self, Layer, Pipeline},
+    presets::Presets,
+    priority::{RequestPriority, RequestQueue},
+    process::{CommandPolicy, McpRequest, McpResponse},
+    provision::{JobState, Provisioner},
+    quota::{QuotaCharge, Quotas},
+    recycle::Recycler,
+    render::{self, ResponseFormat},
+    response_headers::ResponseHeaders,
+    response_schema::ResponseValidator,
+    response_stream::{self, ResponseSink},
+    restart_budget::RestartBudget,
+    rewrite::RewriteRules,
+    routes::{self, ManifestConfig, Route, RouteInfo, RouteTable, MANIFEST_PATH},
+    server_hooks::ServerHooks,
+    server_requests::{ServerRequestError, ServerRequestHandlers},
+    setup::{SetupExecutor, DEFAULT_MAX_SETUP_JOBS},
+    shedding::LoadShedder,
+    shutdown::{self, BackgroundTasks, ShutdownConfig, ShutdownCoordinator, ShutdownPhase},
+    simple,
+    stats::{ErrorClass, RequestStats},
+    streaming::Streams,
+    strict,
+    template::TemplateValues,
+    tenant::{self, TenantGateway, TenantScope},
+    timing::PhaseTimer,
+    tool_schema::ToolSchemas,
+    tool_stats::ToolStats,
+    transport::{self, McpTransport},
+    workdir::{self, CleanupOptions},
 };
 
-/// HTTP server state containing the MCP process
+pub use crate::manager::WORK_DIR_BASE;
+
+/// HTTP server state containing the MCP transport
 #[derive(Clone)]
 pub struct ServerState {
-    pub mcp_process: Arc<Mutex<McpProcess>>,
+    pub server_name: String,
+    pub transport: Arc<Mutex<Box<dyn McpTransport>>>,
+    pub param_injection: Arc<Vec<ParamInjectionRule>>,
+
+    /// Aliases, moves, and defaults applied to client requests, if configured
+    pub rewrite: Option<Arc<RewriteRules>>,
+
+    /// Binaries the server needs, and where they were found
+    pub capabilities: Arc<Vec<Requirement>>,
+
+    /// Caller context added to forwarded messages in `_meta`, if enabled
+    pub context_meta: Option<Arc<ContextMetaConfig>>,
+
+    /// Headers added to responses, if any are configured
+    pub response_headers: Option<Arc<ResponseHeaders>>,
+
+    /// Timeouts of forwarded requests by method
+    pub timeouts: Arc<MethodTimeouts>,
+    pub command_policy: CommandPolicy,
+
+    /// Largest response passed to a client, if limited
+    pub max_response_bytes: Option<usize>,
+    pub hooks: Hooks,
+
+    /// Values registered with [`McpHttpServerBuilder::extension`]
+    pub extensions: Arc<Extensions>,
+    pub server_requests: ServerRequestHandlers,
+    pub inflight: Arc<InflightRegistry>,
+    pub stats: Arc<RequestStats>,
+
+    /// Calls, errors, and latency of each tool
+    pub tool_stats: Arc<ToolStats>,
+    pub elicitations: Arc<ElicitationRegistry>,
+
+    /// Recent notifications from the MCP server, for long-polling clients
+    pub notifications: Arc<NotificationRing>,
+
+    /// Longest wait a notifications long-poll may ask for
+    pub max_notification_wait: std::time::Duration,
+    pub load_shedder: Option<Arc<LoadShedder>>,
+
+    /// Priority queue for turns with the transport
+    pub request_queue: Arc<RequestQueue>,
+
+    /// Replaces client ids with unique ones; `None` forwards them unchanged
+    pub id_rewriter: Option<Arc<IdRewriter>>,
+
+    /// Whether the server was drained for maintenance
+    pub maintenance: Arc<Maintenance>,
+
+    /// Input schemas of the server's tools, if `tools/call` arguments are validated
+    pub tool_schemas: Option<Arc<ToolSchemas>>,
+
+    /// Checks results against the MCP specification, if `validate_responses` is set
+    pub response_validator: Option<Arc<ResponseValidator>>,
+
+    /// Lifecycle metadata as of startup, if persisted
+    pub lifecycle: Option<Arc<LifecycleState>>,
+
+    /// Setup of the MCP server, and its startup timings, PID, stderr, and
+    /// audit once it is set up
+    pub provisioner: Arc<Provisioner>,
+
+    /// Restarts the child when it reaches a recycling threshold
+    pub recycler: Option<Arc<Recycler>>,
+
+    /// Runs the server's `health_check`, if it has one
+    pub health_checker: Option<Arc<HealthChecker>>,
+
+    /// Fails requests fast while a child that stopped reading stdin is replaced
+    pub stdin_breaker: Arc<StdinBreaker>,
+
+    /// Capture of request and response bodies, if one is running
+    pub captures: Arc<Captures>,
+
+    /// Notification methods clients may send
+    pub client_notifications: Arc<NotificationAllowlist>,
+
+    /// Accepted client notifications, written in order by one task started
+    /// with the first
+    notification_queue: Arc<OnceLock<mpsc::Sender<QueuedNotification>>>,
+    pub configured_servers: Arc<HashSet<String>>,
+
+    /// Directory holding the work directories, the tenant's in tenant mode
+    pub work_dir_base: PathBuf,
+    pub access_log: Option<AccessLog>,
+
+    /// Permits for concurrent `logs/stream` connections
+    pub log_streams: Arc<Semaphore>,
+
+    /// Whether responses carry the gateway and child versions in headers
+    pub version_headers: bool,
+
+    /// Whether `/api/v1/simple/{tool}` is served
+    pub simple_mode: bool,
+
+    /// Whether and with what the route manifest is served
+    pub manifest: Arc<ManifestConfig>,
+
+    /// Named requests served under `/api/v1/presets`
+    pub presets: Arc<Presets>,
+
+    /// Order and selection of the middleware, with the rate limiter it shares
+    pub pipeline: Arc<Pipeline>,
+
+    /// Whether request bodies with unknown keys are rejected rather than logged
+    pub strict: bool,
+
+    /// Keep-alives, idle timeout, and shutdown of event streams
+    pub streams: Arc<Streams>,
+
+    /// Executor running the clone, build, and provisioning jobs
+    pub setup: Arc<SetupExecutor>,
+
+    /// Output of the latest clone and build runs
+    pub build_logs: Arc<BuildLogs>,
+
+    /// `pre_start` and `post_stop` hooks of the server
+    pub server_hooks: Arc<ServerHooks>,
+
+    /// Usage quotas and consumption per API key
+    pub quotas: Arc<Quotas>,
+
+    /// Split of requests between the server and its canary
+    pub canary: Arc<CanaryRouter>,
+
+    /// Children running the server side by side, if it has replicas
+    pub replicas: Arc<ReplicaPool>,
+
+    /// Time budget of a graceful shutdown
+    pub shutdown: ShutdownConfig,
+
+    /// Tasks running next to the server, stopped during shutdown
+    pub background: Arc<BackgroundTasks>,
 }
 
 /// HTTP server for MCP Core
 pub struct McpHttpServer {
-    auth_config: AuthConfig,
+    auth: Arc<SharedAuth>,
     server_state: ServerState,
+    bind_host: IpAddr,
+    port_fallback: bool,
+    local_addr: Arc<OnceLock<SocketAddr>>,
+
+    /// Where the routes are mounted, `/t/{tenant}` for a tenant's server
+    path_prefix: String,
+
+    /// Listeners served by [`McpHttpServer::serve_all`]
+    listeners: Vec<ListenerConfig>,
+
+    /// Protocol options of every listener
+    http: HttpConfig,
 }
 
-impl McpHttpServer {
-    /// Create a new MCP HTTP server
-    pub async fn new(
-        config_file_path: &str,
-        server_name: &str,
-    ) -> McpCoreResult<Self> {
-        tracing::info!("Initializing MCP HTTP server...");
-        tracing::info!(
-            "Config file: '{}', Server: '{}'",
-            config_file_path,
-            server_name
-        );
+/// Handle to a server started with [`McpHttpServer::serve_background`]
+///
+/// Dropping the handle leaves the server running detached, unless
+/// [`ServerHandle::abort_on_drop`] is set.
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    scope: ShutdownScope,
+    shutdown: Option<oneshot::Sender<()>>,
+    task: Option<JoinHandle<McpCoreResult<()>>>,
+    abort_on_drop: bool,
+}
 
-        // Load configuration
-        let servers_config = McpServersConfig::load_from_file(config_file_path).await?;
-        let server_config = servers_config.get_server(server_name)?.clone();
+impl ServerHandle {
+    /// Address the server is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
 
-        // Start MCP server process directly
-        let mcp_process = Self::start_mcp_process(&server_config, server_name).await?;
+    /// Stop the server when the handle is dropped
+    pub fn abort_on_drop(mut self, enabled: bool) -> Self {
+        self.abort_on_drop = enabled;
+        self
+    }
 
-        // Create auth config
-        let auth_config = AuthConfig::from_env();
+    /// Stop the server and wait for it to terminate
+    ///
+    /// A graceful shutdown runs the phases described in [`crate::shutdown`],
+    /// letting in-flight requests finish within the grace period; otherwise
+    /// the server task is aborted immediately.
+    pub async fn shutdown(mut self, graceful: bool) -> McpCoreResult<()> {
+        let Some(mut task) = self.task.take() else {
+            return Ok(());
+        };
+        if !graceful {
+            task.abort();
+            return join_server_task(task.await);
+        }
+        let scope = self.scope.clone();
+        let stop = self.shutdown.take().into_iter().collect();
+        match shutdown_in_order(&scope, stop, &mut task).await {
+            Some(joined) => join_server_task(joined),
+            None => {
+                task.abort();
+                Ok(())
+            }
+        }
+    }
 
-        tracing::info!("MCP HTTP server initialized successfully");
+    /// Wait until the server terminates
+    pub async fn await_terminated(mut self) -> McpCoreResult<()> {
+        let Some(task) = self.task.take() else {
+            return Ok(());
+        };
+        join_server_task(task.await)
+    }
 
-        Ok(Self {
-            auth_config,
-            server_state: ServerState {
-                mcp_process: Arc::new(Mutex::new(mcp_process)),
-            },
-        })
+    /// Wait until the server terminates, shutting it down gracefully on
+    /// SIGINT or SIGTERM
+    pub async fn await_signal(mut self) -> McpCoreResult<()> {
+        let Some(task) = self.task.as_mut() else {
+            return Ok(());
+        };
+        tokio::select! {
+            joined = task => {
+                self.task = None;
+                join_server_task(joined)
+            }
+            _ = shutdown::signal() => self.shutdown(true).await,
+        }
     }
+}
 
-    /// Start MCP server process with optional repository clone and build command execution
-    async fn start_mcp_process(
-        config: &crate::config::McpServerConfig,
-        server_name: &str,
-    ) -> McpCoreResult<McpProcess> {
-        tracing::info!(
-            "Starting MCP server '{}': {} {:?}",
-            server_name,
-            config.command,
-            config.args
-        );
+fn join_server_task(
+    joined: Result<McpCoreResult<()>, tokio::task::JoinError>,
+) -> McpCoreResult<()> {
+    match joined {
+        Ok(result) => result,
+        Err(e) if e.is_cancelled() => Ok(()),
+        Err(e) => Err(McpCoreError::HttpServerError {
+            message: format!("Server task failed: {}", e),
+        }),
+    }
+}
 
-        // Get server-specific working directory
-        let work_dir = Self::get_server_work_dir(server_name);
-        tokio::fs::create_dir_all(&work_dir).await.map_err(|e| McpCoreError::ProcessError {
-            message: format!("Failed to create work directory '{}': {}", work_dir, e),
-        })?;
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        if let (true, Some(task)) = (self.abort_on_drop, &self.task) {
+            task.abort();
+        }
+    }
+}
+
+/// Address a listener was bound to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoundListener {
+    pub name: String,
+    pub local_addr: SocketAddr,
+    pub tls: bool,
+    pub routes: Vec<RouteGroup>,
+}
+
+/// Handle to the listeners started with [`McpHttpServer::serve_all`]
+///
+/// When one listener stops with an error the others are shut down too.
+/// Dropping the handle leaves the listeners running detached, unless
+/// [`ListenersHandle::abort_on_drop`] is set.
+pub struct ListenersHandle {
+    listeners: Vec<BoundListener>,
+    scope: ShutdownScope,
+    shutdown: Vec<oneshot::Sender<()>>,
+    tasks: JoinSet<(String, McpCoreResult<()>)>,
+    abort_on_drop: bool,
+}
+
+impl ListenersHandle {
+    /// Every listener with its resolved address
+    pub fn listeners(&self) -> &[BoundListener] {
+        &self.listeners
+    }
 
-        // Clone repository if specified and not already exists
-        if let Some(repository_url) = &config.repository {
-            Self::clone_repository_if_needed(repository_url, &work_dir).await?;
+    /// Resolved address of the listener called `name`
+    pub fn local_addr(&self, name: &str) -> Option<SocketAddr> {
+        self.listeners
+            .iter()
+            .find(|listener| listener.name == name)
+            .map(|listener| listener.local_addr)
+    }
+
+    /// Stop the listeners when the handle is dropped
+    pub fn abort_on_drop(mut self, enabled: bool) -> Self {
+        self.abort_on_drop = enabled;
+        self
+    }
+
+    /// Stop every listener and wait for them to terminate
+    ///
+    /// See [`ServerHandle::shutdown`] for what `graceful` means.
+    pub async fn shutdown(mut self, graceful: bool) -> McpCoreResult<()> {
+        if !graceful {
+            self.tasks.abort_all();
+            return self.await_terminated().await;
         }
+        let scope = self.scope.clone();
+        let stop = std::mem::take(&mut self.shutdown);
+        match shutdown_in_order(&scope, stop, self.join_listeners()).await {
+            Some(result) => result,
+            None => {
+                self.tasks.abort_all();
+                Ok(())
+            }
+        }
+    }
+
+    /// Wait until every listener terminates
+    ///
+    /// Returns the error of the first listener that failed.
+    pub async fn await_terminated(mut self) -> McpCoreResult<()> {
+        self.join_listeners().await
+    }
 
-        // Execute build command if present
-        if let Some(build_cmd) = &config.build_command {
-            tracing::info!("Executing build command: {}", build_cmd);
-            Self::execute_build_command(build_cmd, &work_dir, &config.env).await?;
+    /// Wait until every listener terminates, shutting them down gracefully
+    /// on SIGINT or SIGTERM
+    pub async fn await_signal(mut self) -> McpCoreResult<()> {
+        tokio::select! {
+            result = self.join_listeners() => return result,
+            _ = shutdown::signal() => {}
         }
+        self.shutdown(true).await
+    }
 
-        let mut command_builder = tokio::process::Command::new(&config.command);
-        command_builder.args(&config.args);
-        command_builder.envs(&config.env);
+    async fn join_listeners(&mut self) -> McpCoreResult<()> {
+        let mut result = Ok(());
+        while let Some(joined) = self.tasks.join_next().await {
+            let message = match joined {
+                Ok((_, Ok(()))) => continue,
+                Ok((name, Err(e))) => format!("Listener '{}' failed: {}", name, e),
+                Err(e) if e.is_cancelled() => continue,
+                Err(e) => format!("Listener task failed: {}", e),
+            };
+            if result.is_ok() {
+                tracing::error!("{}, stopping the other listeners", message);
+                for shutdown in self.shutdown.drain(..) {
+                    let _ = shutdown.send(());
+                }
+                result = Err(McpCoreError::HttpServerError { message });
+            }
+        }
+        result
+    }
+}
 
-        // Inherit parent environment variables
-        for (key, value) in std::env::vars() {
-            command_builder.env(key, value);
+impl Drop for ListenersHandle {
+    fn drop(&mut self) {
+        if !self.abort_on_drop {
+            self.tasks.detach_all();
         }
+    }
+}
 
-        command_builder.current_dir(&work_dir);
-        
-        command_builder
-            .stdin(std::process::Stdio::piped())
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped());
+/// Builder for [`McpHttpServer`] allowing embedders to register hooks
+pub struct McpHttpServerBuilder {
+    config_file_path: String,
+    config_profile: Option<String>,
+    config: Option<McpServersConfig>,
+    server_name: String,
+    hooks: Hooks,
+    extensions: Extensions,
+    server_requests: ServerRequestHandlers,
+    preflight: Option<DiagnosticsOptions>,
+    cleanup: Option<CleanupOptions>,
+    bind_host: IpAddr,
+    port_fallback: bool,
+    http2: bool,
+    port: Option<u16>,
+    allow_unauthenticated_public: bool,
+    access_log: Option<AccessLogConfig>,
+    listeners: Option<Vec<ListenerConfig>>,
+
+    /// Authentication in place of the one `HTTP_API_KEY` and `DISABLE_AUTH` set
+    auth_config: Option<AuthConfig>,
 
-        let mut mcp_process = McpProcess::spawn(command_builder).await?;
-        
-        // Initialize MCP connection
-        mcp_process.initialize().await?;
-        
-        Ok(mcp_process)
+    /// Tenant the server is built for, by [`McpHttpServerBuilder::build_tenants`]
+    tenant: Option<TenantScope>,
+}
+
+impl McpHttpServerBuilder {
+    /// Merge the overlay for the given profile over the base configuration
+    pub fn config_profile(mut self, profile: impl Into<String>) -> Self {
+        self.config_profile = Some(profile.into());
+        self
     }
 
-    /// Get server-specific working directory path
-    fn get_server_work_dir(server_name: &str) -> String {
-        format!("/tmp/mcp-servers/{}", server_name)
+    /// Serve this configuration instead of loading the config file
+    pub fn config(mut self, config: McpServersConfig) -> Self {
+        self.config = Some(config);
+        self
     }
 
-    /// Clone repository if it doesn't already exist
-    async fn clone_repository_if_needed(
-        repository_url: &str,
-        work_dir: &str,
-    ) -> McpCoreResult<()> {
-        tracing::info!("Checking repository: {}", repository_url);
+    /// Run preflight diagnostics before starting and fail if any check fails
+    pub fn strict_preflight(mut self, options: DiagnosticsOptions) -> Self {
+        self.preflight = Some(options);
+        self
+    }
 
-        // Check if directory already contains a git repository
-        let git_dir = format!("{}/.git", work_dir);
-        if tokio::fs::metadata(&git_dir).await.is_ok() {
-            tracing::info!("Repository already exists in '{}', skipping clone", work_dir);
-            return Ok(());
+    /// Clean up orphaned work directories after the server has started
+    pub fn startup_cleanup(mut self, options: CleanupOptions) -> Self {
+        self.cleanup = Some(options);
+        self
+    }
+
+    /// Address to listen on, `0.0.0.0` by default
+    pub fn bind_host(mut self, host: IpAddr) -> Self {
+        self.bind_host = host;
+        self
+    }
+
+    /// Authenticate with `config` instead of reading `HTTP_API_KEY`,
+    /// `DISABLE_AUTH`, and `HTTP_API_KEY_PRIORITY`
+    pub fn auth_config(mut self, config: AuthConfig) -> Self {
+        self.auth_config = Some(config);
+        self
+    }
+
+    /// Allow serving without authentication on a non-loopback address
+    ///
+    /// Without this, starting such a server fails with a configuration error.
+    pub fn allow_unauthenticated_public(mut self, allowed: bool) -> Self {
+        self.allow_unauthenticated_public = allowed;
+        self
+    }
+
+    /// Write an access log of every HTTP request
+    pub fn access_log(mut self, config: AccessLogConfig) -> Self {
+        self.access_log = Some(config);
+        self
+    }
+
+    /// Listeners for [`McpHttpServer::serve_all`], replacing the configured ones
+    pub fn listeners(mut self, listeners: Vec<ListenerConfig>) -> Self {
+        self.listeners = Some(listeners);
+        self
+    }
+
+    /// Listen on the next free port when the requested one is in use
+    ///
+    /// Up to [`listener::PORT_FALLBACK_RANGE`] ports above it are tried.
+    pub fn port_fallback(mut self, enabled: bool) -> Self {
+        self.port_fallback = enabled;
+        self
+    }
+
+    /// Accept HTTP/2 even if the configuration's `http` options do not
+    pub fn http2(mut self, enabled: bool) -> Self {
+        self.http2 = enabled;
+        self
+    }
+
+    /// Port substituted for `{{port}}` in the server configuration
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Register an async hook run on every JSON-RPC message before it is
+    /// sent, resolving to the message to send
+    ///
+    /// Returning an error short-circuits the request with the error's status.
+    pub fn on_request<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(Value, RequestContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Value, HookError>> + Send + 'static,
+    {
+        self.hooks.on_request.push(hooks::request_hook(hook));
+        self
+    }
+
+    /// Register an async hook run on every JSON-RPC response before it is
+    /// returned, resolving to the response to return
+    pub fn on_response<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(Value, RequestContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Value> + Send + 'static,
+    {
+        self.hooks.on_response.push(hooks::response_hook(hook));
+        self
+    }
+
+    /// Make `value` available to hooks through [`RequestContext::extension`]
+    /// and to the embedder through [`McpHttpServer::extension`]
+    ///
+    /// Values are kept by type, so registering a second value of a type
+    /// replaces the first.
+    pub fn extension<T: Clone + Send + Sync + 'static>(mut self, value: T) -> Self {
+        self.extensions.insert(value);
+        self
+    }
+
+    /// Register a handler for `sampling/createMessage` requests from the server
+    ///
+    /// The handler receives the request `params` and returns its `result`.
+    /// Sampling is only advertised to the server when a handler is registered.
+    pub fn on_sampling_request<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(Value) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Value, ServerRequestError>> + Send + 'static,
+    {
+        self.server_requests.sampling = Some(Arc::new(move |params| Box::pin(handler(params))));
+        self
+    }
+
+    /// Register a handler for `roots/list` requests from the server
+    ///
+    /// Takes precedence over the server's configured `roots`. Without either,
+    /// an empty list of roots is returned.
+    pub fn on_roots_request<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(Value) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Value, ServerRequestError>> + Send + 'static,
+    {
+        self.server_requests.roots = Some(Arc::new(move |params| Box::pin(handler(params))));
+        self
+    }
+
+    /// Load the configuration and start the MCP server process
+    pub async fn build(self) -> McpCoreResult<McpHttpServer> {
+        tracing::info!("Initializing MCP HTTP server...");
+        let timer = PhaseTimer::default();
+        tracing::info!(
+            "Config file: '{}', Server: '{}'",
+            self.config_file_path,
+            self.server_name
+        );
+        if let Some(tenant) = &self.tenant {
+            tracing::info!("Tenant: '{}'", tenant.id);
         }
 
-        tracing::info!("Cloning repository '{}' to '{}'", repository_url, work_dir);
+        // Load configuration
+        let servers_config = match self.config {
+            Some(config) => config,
+            None => {
+                McpServersConfig::load_with_profile(
+                    &self.config_file_path,
+                    self.config_profile.as_deref(),
+                )
+                .await?
+            }
+        };
+        let mut http = servers_config.http.clone();
+        http.http2 |= self.http2;
+        let listeners = match self.listeners {
+            Some(listeners) => {
+                listener::validate_listeners(&listeners)?;
+                listeners
+            }
+            None => servers_config.listeners.clone(),
+        };
 
-        let start_time = std::time::Instant::now();
-        
-        // Use git clone command
-        let mut command_builder = tokio::process::Command::new("git");
-        command_builder.args(["clone", repository_url, "."]);
-        command_builder.current_dir(work_dir);
-        
-        // Capture output for logging
-        command_builder
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped());
+        // Refuse a public unauthenticated bind before doing any work
+        let auth_config = match (&self.tenant, &self.auth_config) {
+            (Some(tenant), _) => tenant.auth_config.clone(),
+            (None, Some(auth_config)) => auth_config.clone(),
+            (None, None) => AuthConfig::from_env(),
+        };
+        let hosts = if listeners.is_empty() {
+            vec![self.bind_host]
+        } else {
+            listeners
+                .iter()
+                .filter(|listener| listener.serves_authenticated_routes())
+                .map(|listener| listener.bind.ip())
+                .collect()
+        };
+        let auth = Arc::new(SharedAuth::new(
+            auth_config,
+            hosts,
+            self.allow_unauthenticated_public,
+        )?);
+        let access_log = self.access_log.as_ref().map(AccessLog::start).transpose()?;
+        let server_config = servers_config.get_server(&self.server_name)?;
+        let work_dir = server_config.work_dir(&self.server_name);
+        let work_dir_base = server_config.work_dir_base();
+        let server_config = Arc::new(server_config.expand_templates(&TemplateValues::new(
+            &self.server_name,
+            &work_dir,
+            self.port,
+        ))?);
 
-        tracing::debug!("Executing: git clone {} .", repository_url);
+        // Name every missing binary now rather than fail to spawn it mid-setup
+        let capabilities = Capabilities::shared();
+        capabilities.check([(self.server_name.as_str(), &*server_config)])?;
+        let requirements = capabilities.requirements(&server_config);
 
-        let output = command_builder
-            .output()
-            .await
-            .map_err(|e| McpCoreError::ProcessError {
-                message: format!("Failed to execute git clone: {}", e),
-            })?;
+        // Fail fast on missing prerequisites instead of failing mid-clone
+        if let Some(options) = &self.preflight {
+            let report =
+                diagnostics::run_for_server(&self.server_name, &server_config, options).await;
+            for check in &report.checks {
+                tracing::info!(
+                    "Preflight {} [{}] {}",
+                    check.name,
+                    check.status,
+                    check.detail
+                );
+            }
+            if !report.is_ok() {
+                let failures: Vec<String> = report
+                    .failures()
+                    .map(|check| format!("{}: {}", check.name, check.detail))
+                    .collect();
+                return Err(McpCoreError::ConfigurationError {
+                    message: format!("Preflight checks failed: {}", failures.join("; ")),
+                });
+            }
+        }
+
+        // Registered handlers take precedence over the server configuration
+        let mut server_requests =
+            manager::with_configured_handlers(self.server_requests, &server_config)?;
+        let elicitations = Arc::new(ElicitationRegistry::new(
+            server_config
+                .elicitation_timeout_secs
+                .map(std::time::Duration::from_secs)
+                .unwrap_or(DEFAULT_ELICITATION_TIMEOUT),
+        ));
+        if server_requests.elicitation.is_none() && server_config.elicitation_passthrough {
+            server_requests.elicitation = Some(elicitations.handler());
+        }
 
-        let duration = start_time.elapsed();
+        // Restore lifecycle metadata and count this start
+        let (lifecycle_file, mut lifecycle) =
+            manager::restore_lifecycle(&server_config, &self.server_name).await;
 
-        // Log the output
-        if !output.stdout.is_empty() {
-            let stdout_str = String::from_utf8_lossy(&output.stdout);
-            tracing::debug!("Git clone stdout: {}", stdout_str.trim());
+        // Start or connect to the MCP server now, or leave it to the provisioner;
+        // a tenant's setup jobs never wait behind another tenant's
+        let max_setup_jobs = servers_config
+            .max_concurrent_setup_jobs
+            .unwrap_or(DEFAULT_MAX_SETUP_JOBS);
+        let setup = match &self.tenant {
+            Some(_) => Arc::new(SetupExecutor::new(max_setup_jobs)),
+            None => SetupExecutor::shared(max_setup_jobs),
+        };
+        let restart_budget = servers_config.restart_budget.map(RestartBudget::shared);
+        let managed = manager::supervise(
+            Arc::clone(&server_config),
+            &self.server_name,
+            &server_requests,
+            lifecycle_file.as_ref(),
+            lifecycle.as_mut(),
+            &setup,
+            restart_budget.as_ref(),
+            Arc::new(LifecycleHistory::new(
+                server_config
+                    .history_size
+                    .unwrap_or(history::DEFAULT_HISTORY_SIZE),
+            )),
+            timer,
+        )
+        .await?;
+
+        // Run the canary next to the primary without holding up startup
+        let canary = Arc::new(CanaryRouter::new(server_config.canary.as_ref()));
+        if let Some(config) = servers_config
+            .get_server(&self.server_name)?
+            .canary_config()
+        {
+            let name = canary::canary_name(&self.server_name);
+            let config = config.expand_templates(&TemplateValues::new(
+                &name,
+                &config.work_dir(&name),
+                self.port,
+            ))?;
+            tokio::spawn(manager::start_canary(
+                config,
+                name,
+                server_config
+                    .canary
+                    .as_ref()
+                    .and_then(|canary| canary.git_ref.clone()),
+                server_requests.clone(),
+                Arc::clone(&setup),
+                Arc::clone(&canary),
+            ));
         }
 
-        if !output.stderr.is_empty() {
-            let stderr_str = String::from_utf8_lossy(&output.stderr);
-            if output.status.success() {
-                tracing::debug!("Git clone stderr: {}", stderr_str.trim());
-            } else {
-                tracing::error!("Git clone stderr: {}", stderr_str.trim());
+        // Start the other replicas in the primary's work directory, now set up
+        let replicas = Arc::new(match &server_config.replicas {
+            Some(config) => {
+                ReplicaPool::new(&self.server_name, config, &server_config.request_queue)
+            }
+            None => ReplicaPool::default(),
+        });
+        let start_replica = {
+            let config = Arc::clone(&server_config);
+            let server_name = self.server_name.clone();
+            let server_requests = server_requests.clone();
+            let setup = Arc::clone(&setup);
+            let pool = Arc::downgrade(&replicas);
+            move |index| {
+                if let Some(pool) = pool.upgrade() {
+                    tokio::spawn(manager::start_replica(
+                        Arc::clone(&config),
+                        server_name.clone(),
+                        index,
+                        server_requests.clone(),
+                        Arc::clone(&setup),
+                        pool,
+                    ));
+                }
             }
+        };
+        for index in replicas.extra_indexes() {
+            start_replica(index);
         }
+        replicas.on_exit(start_replica);
 
-        // Check if the command was successful
-        if output.status.success() {
-            tracing::info!(
-                "Repository cloned successfully in {:?}: {}",
-                duration,
-                repository_url
-            );
-            Ok(())
-        } else {
-            let error_msg = format!(
-                "Git clone failed with exit code {:?}: {}",
-                output.status.code(),
-                repository_url
+        // Remove work directories of servers that are gone or expired
+        let configured_servers = servers_config.work_dir_names();
+        if let Some(options) = &self.cleanup {
+            match workdir::cleanup(
+                &work_dir_base,
+                &configured_servers,
+                Some(&self.server_name),
+                options,
+            )
+            .await
+            {
+                Ok(report) => tracing::info!(
+                    "Work dir cleanup removed {} directories",
+                    report.removed.len()
+                ),
+                Err(e) => tracing::warn!("Work dir cleanup failed: {}", e),
+            }
+        }
+
+        let inflight = Arc::new(InflightRegistry::new(
+            server_config.max_inflight,
+            server_config.request_deadline_secs.map_or(
+                inflight::DEFAULT_REQUEST_DEADLINE,
+                std::time::Duration::from_secs,
+            ),
+        ));
+        let background = Arc::new(BackgroundTasks::default());
+        background.register("inflight sweeper", inflight.spawn_sweeper());
+
+        if let Some(recycling) = managed.recycling {
+            background.register("recycler", recycling);
+        }
+        let health_checker = server_config
+            .health_check
+            .clone()
+            .map(|config| HealthChecker::new(config, Arc::clone(&managed.provisioner)))
+            .transpose()
+            .map_err(|message| McpCoreError::ConfigurationError { message })?
+            .map(Arc::new);
+        let captures = Arc::new(Captures::new(&self.server_name));
+        if let Some(rule) = &server_config.capture {
+            captures.start(rule.clone(), chrono::Utc::now())?;
+        }
+
+        // Quota counts of the current day and month survive restarts the same way
+        let quotas = Arc::new(Quotas::new(
+            &self.server_name,
+            &servers_config.quotas,
+            lifecycle_file.clone(),
+        ));
+        if let Some(state) = &lifecycle {
+            quotas.restore(&state.usage, chrono::Utc::now());
+            background.register("quota flusher", quotas.spawn_flusher());
+        }
+
+        // A drained server stays drained across restarts when lifecycle state is persisted
+        let maintenance = Arc::new(Maintenance::new(
+            &self.server_name,
+            lifecycle
+                .as_ref()
+                .and_then(|state| state.maintenance.clone()),
+            lifecycle_file,
+        ));
+
+        let strict = servers_config.strict || strict::strict_from_env();
+        let tool_schemas = server_config
+            .validate_tool_arguments
+            .then(|| Arc::new(ToolSchemas::default()));
+
+        tracing::info!("MCP HTTP server initialized successfully");
+
+        let server = McpHttpServer {
+            auth,
+            server_state: ServerState {
+                server_name: self.server_name,
+                transport: managed.transport,
+                command_policy: server_config.command_policy(),
+                max_response_bytes: server_config.max_response_bytes,
+                param_injection: Arc::new(server_config.param_injection.clone()),
+                rewrite: server_config
+                    .rewrite
+                    .clone()
+                    .filter(|rules| !rules.is_empty())
+                    .map(Arc::new),
+                capabilities: Arc::new(requirements),
+                context_meta: server_config.context_meta.clone().map(Arc::new),
+                response_headers: ResponseHeaders::new(
+                    &server_config.response_headers,
+                    &server_config.header_from_meta,
+                )
+                .map(Arc::new),
+                timeouts: Arc::new(MethodTimeouts::new(
+                    &server_config.method_timeouts,
+                    server_config
+                        .request_deadline_secs
+                        .map(std::time::Duration::from_secs),
+                )),
+                hooks: self.hooks,
+                extensions: Arc::new(self.extensions),
+                server_requests,
+                inflight,
+                stats: Arc::new(RequestStats::default()),
+                tool_stats: Arc::new(ToolStats::new(tool_schemas.clone())),
+                elicitations,
+                notifications: Arc::new(NotificationRing::new(
+                    server_config
+                        .notification_buffer_size
+                        .unwrap_or(DEFAULT_NOTIFICATION_BUFFER),
+                )),
+                max_notification_wait: server_config
+                    .max_notification_wait_secs
+                    .map(std::time::Duration::from_secs)
+                    .unwrap_or(DEFAULT_MAX_NOTIFICATION_WAIT),
+                load_shedder: server_config
+                    .load_shedding
+                    .clone()
+                    .map(|config| Arc::new(LoadShedder::new(config))),
+                request_queue: Arc::new(RequestQueue::new(server_config.request_queue.clone())),
+                id_rewriter: (!server_config.preserve_request_ids)
+                    .then(|| Arc::new(IdRewriter::default())),
+                maintenance,
+                tool_schemas,
+                response_validator: match server_config.validate_responses {
+                    true => Some(Arc::new(
+                        ResponseValidator::new(&server_config.skip_response_validation, strict)
+                            .map_err(|message| McpCoreError::ConfigurationError { message })?,
+                    )),
+                    false => None,
+                },
+                lifecycle: lifecycle.map(Arc::new),
+                provisioner: managed.provisioner,
+                recycler: managed.recycler,
+                health_checker,
+                stdin_breaker: Arc::new(StdinBreaker::default()),
+                captures,
+                client_notifications: Arc::new(
+                    NotificationAllowlist::new(server_config.client_notifications.as_deref())
+                        .map_err(|message| McpCoreError::ConfigurationError { message })?,
+                ),
+                notification_queue: Arc::default(),
+                configured_servers: Arc::new(configured_servers),
+                work_dir_base,
+                access_log,
+                log_streams: Arc::new(Semaphore::new(admin::MAX_LOG_STREAMS)),
+                version_headers: !servers_config.hide_version_headers,
+                simple_mode: server_config.simple_mode,
+                manifest: Arc::new(servers_config.manifest.clone()),
+                presets: Arc::new(Presets::new(&server_config.presets)),
+                pipeline: Arc::new(
+                    Pipeline::new(servers_config.middleware.as_deref())
+                        .map_err(|reason| McpCoreError::ConfigurationError { message: reason })?,
+                ),
+                strict,
+                streams: Arc::new(Streams::new(servers_config.streaming.clone())),
+                setup,
+                build_logs: managed.build_logs,
+                server_hooks: managed.hooks,
+                quotas,
+                canary,
+                replicas,
+                shutdown: servers_config.shutdown.clone(),
+                background,
+            },
+            bind_host: self.bind_host,
+            port_fallback: self.port_fallback,
+            local_addr: Arc::new(OnceLock::new()),
+            path_prefix: self
+                .tenant
+                .as_ref()
+                .map_or_else(String::new, |tenant| tenant::path_prefix(&tenant.id)),
+            listeners,
+            http,
+        };
+        let server_state = &server.server_state;
+        if let Some(checker) = &server_state.health_checker {
+            server_state.background.register(
+                "health checker",
+                spawn_health_checks(Arc::clone(checker), server_state.clone()),
             );
-            tracing::error!("{}", error_msg);
-            Err(McpCoreError::ProcessError {
-                message: error_msg,
-            })
         }
+        Ok(server)
     }
 
-    /// Execute build command in the specified working directory
-    async fn execute_build_command(
-        build_cmd: &str,
-        work_dir: &str,
-        env_vars: &std::collections::HashMap<String, String>,
-    ) -> McpCoreResult<()> {
-        tracing::info!("Starting build process: {}", build_cmd);
-        
-        // Parse the build command (handle shell commands with &&, ||, etc.)
-        let mut command_builder = if cfg!(target_os = "windows") {
-            let mut cmd = tokio::process::Command::new("cmd");
-            cmd.args(["/C", build_cmd]);
-            cmd
+    /// Build one server per tenant of the configuration's `tenants`
+    ///
+    /// Each tenant's server is built as by [`McpHttpServerBuilder::build`]
+    /// from the tenant's own configuration, authenticated by the tenant's
+    /// API keys. Listeners, the access log, and `HTTP_API_KEY` belong to the
+    /// gateway.
+    pub async fn build_tenants(mut self) -> McpCoreResult<TenantGateway> {
+        let servers_config = match self.config.take() {
+            Some(config) => config,
+            None => {
+                McpServersConfig::load_with_profile(
+                    &self.config_file_path,
+                    self.config_profile.as_deref(),
+                )
+                .await?
+            }
+        };
+        if servers_config.tenants.is_empty() {
+            return Err(McpCoreError::ConfigurationError {
+                message: "Configuration defines no tenants".to_string(),
+            });
+        }
+        let mut http = servers_config.http.clone();
+        http.http2 |= self.http2;
+        let listeners = match &self.listeners {
+            Some(listeners) => {
+                listener::validate_listeners(listeners)?;
+                listeners.clone()
+            }
+            None => servers_config.listeners.clone(),
+        };
+        let auth_config = self
+            .auth_config
+            .clone()
+            .unwrap_or_else(AuthConfig::from_env);
+        let hosts = if listeners.is_empty() {
+            vec![self.bind_host]
         } else {
-            let mut cmd = tokio::process::Command::new("sh");
-            cmd.args(["-c", build_cmd]);
-            cmd
-        };
-
-        // Set environment variables
-        command_builder.envs(env_vars);
-        
-        // Inherit parent environment variables
-        for (key, value) in std::env::vars() {
-            command_builder.env(key, value);
-        }
-        
-        // Set working directory
-        command_builder.current_dir(work_dir);
-        
-        // Capture output for logging
-        command_builder
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped());
-
-        tracing::debug!("Executing build command in directory: {}", work_dir);
-        
-        let start_time = std::time::Instant::now();
-        let output = command_builder
-            .output()
-            .await
-            .map_err(|e| McpCoreError::ProcessError {
-                message: format!("Failed to execute build command '{}': {}", build_cmd, e),
-            })?;
+            Vec::new()
+        };
+        let auth = Arc::new(SharedAuth::new(
+            auth_config.clone(),
+            hosts,
+            self.allow_unauthenticated_public,
+        )?);
+        let access_log = self.access_log.as_ref().map(AccessLog::start).transpose()?;
 
-        let duration = start_time.elapsed();
-        
-        // Log the output
-        if !output.stdout.is_empty() {
-            let stdout_str = String::from_utf8_lossy(&output.stdout);
-            tracing::info!("Build stdout: {}", stdout_str.trim());
-        }
-        
-        if !output.stderr.is_empty() {
-            let stderr_str = String::from_utf8_lossy(&output.stderr);
-            if output.status.success() {
-                tracing::info!("Build stderr: {}", stderr_str.trim());
-            } else {
-                tracing::error!("Build stderr: {}", stderr_str.trim());
+        let mut ids: Vec<&String> = servers_config.tenants.keys().collect();
+        ids.sort();
+        let mut tenants = Vec::with_capacity(ids.len());
+        for id in ids {
+            let tenant = &servers_config.tenants[id];
+            let config = tenant
+                .resolve(id, &self.config_file_path, self.config_profile.as_deref())
+                .await?;
+            let keys = tenant.api_keys(id)?;
+            tracing::info!("Building server '{}' of tenant '{}'", self.server_name, id);
+            let server = McpHttpServerBuilder {
+                config_file_path: self.config_file_path.clone(),
+                config_profile: self.config_profile.clone(),
+                config: Some(config),
+                server_name: self.server_name.clone(),
+                hooks: self.hooks.clone(),
+                extensions: self.extensions.clone(),
+                server_requests: self.server_requests.clone(),
+                preflight: self.preflight.clone(),
+                cleanup: self.cleanup.clone(),
+                bind_host: self.bind_host,
+                port_fallback: self.port_fallback,
+                http2: self.http2,
+                port: self.port,
+                allow_unauthenticated_public: self.allow_unauthenticated_public,
+                access_log: None,
+                listeners: Some(listeners.clone()),
+                auth_config: None,
+                tenant: Some(TenantScope {
+                    id: id.clone(),
+                    auth_config: auth_config.with_named_keys(keys),
+                }),
             }
+            .build()
+            .await?;
+            tenants.push((id.clone(), server));
         }
-        
-        // Check if the command was successful
-        if output.status.success() {
-            tracing::info!(
-                "Build command completed successfully in {:?}: {}",
-                duration,
-                build_cmd
-            );
-            Ok(())
-        } else {
-            let error_msg = format!(
-                "Build command failed with exit code {:?}: {}",
-                output.status.code(),
-                build_cmd
-            );
-            tracing::error!("{}", error_msg);
-            Err(McpCoreError::ProcessError {
-                message: error_msg,
-            })
+
+        Ok(TenantGateway {
+            auth,
+            tenants,
+            access_log,
+            shutdown: servers_config.shutdown.clone(),
+            bind_host: self.bind_host,
+            port_fallback: self.port_fallback,
+            local_addr: Arc::new(OnceLock::new()),
+            listeners,
+            http,
+            manifest: Arc::new(servers_config.manifest.clone()),
+        })
+    }
+}
+
+impl McpHttpServer {
+    /// Create a new MCP HTTP server
+    pub async fn new(config_file_path: &str, server_name: &str) -> McpCoreResult<Self> {
+        Self::builder(config_file_path, server_name).build().await
+    }
+
+    /// Create a builder for an MCP HTTP server
+    pub fn builder(config_file_path: &str, server_name: &str) -> McpHttpServerBuilder {
+        McpHttpServerBuilder {
+            config_file_path: config_file_path.to_string(),
+            config_profile: None,
+            config: None,
+            server_name: server_name.to_string(),
+            hooks: Hooks::default(),
+            extensions: Extensions::new(),
+            server_requests: ServerRequestHandlers::default(),
+            preflight: None,
+            cleanup: None,
+            bind_host: Ipv4Addr::UNSPECIFIED.into(),
+            port_fallback: false,
+            http2: false,
+            port: None,
+            allow_unauthenticated_public: false,
+            access_log: None,
+            listeners: None,
+            auth_config: None,
+            tenant: None,
         }
     }
 
-    /// Create the Axum router
-    pub fn create_router(self) -> Router {
-        Router::new()
-            .route("/api/v1", post(handle_mcp_request))
-            .layer(middleware::from_fn_with_state(
-                self.auth_config.clone(),
-                bearer_auth_middleware,
-            ))
-            .with_state(self.server_state)
+    /// Authentication of the server, replaceable while it serves
+    pub fn auth(&self) -> Arc<SharedAuth> {
+        Arc::clone(&self.auth)
     }
 
-    /// Start the HTTP server
-    pub async fn serve(self, port: u16) -> McpCoreResult<()> {
-        let app = self.create_router();
+    /// Value of type `T` registered with [`McpHttpServerBuilder::extension`]
+    ///
+    /// Routes the embedder adds next to [`McpHttpServer::create_router`]
+    /// take their state from here.
+    pub fn extension<T: Clone + Send + Sync + 'static>(&self) -> Option<T> {
+        self.server_state.extensions.get::<T>().cloned()
+    }
 
-        let listener_addr = format!("0.0.0.0:{}", port);
-        tracing::info!("Starting HTTP server on {}", listener_addr);
+    /// State shared by the server's handlers
+    pub(crate) fn server_state(&self) -> &ServerState {
+        &self.server_state
+    }
 
-        let listener = tokio::net::TcpListener::bind(&listener_addr)
-            .await
-            .map_err(|e| McpCoreError::HttpServerError {
-                message: format!("Failed to bind to address {}: {}", listener_addr, e),
-            })?;
+    /// Create the Axum router serving every route group
+    pub fn create_router(self) -> Router {
+        let local_addr = Arc::clone(&self.local_addr);
+        self.router(&RouteGroup::ALL, local_addr)
+    }
 
-        tracing::info!(
-            "HTTP server listening on http://{}",
-            listener
-                .local_addr()
-                .map_err(|e| McpCoreError::HttpServerError {
-                    message: format!("Failed to get local address: {}", e),
-                })?
-        );
+    /// Create a router mounting only `groups`
+    ///
+    /// `local_addr` is the listener's address, reported by the index.
+    pub(crate) fn router(
+        &self,
+        groups: &[RouteGroup],
+        local_addr: Arc<OnceLock<SocketAddr>>,
+    ) -> Router {
+        let table = self.route_table(groups);
+        let listing = Listing {
+            auth: Arc::clone(&self.auth),
+            local_addr,
+            routes: Arc::new(table.infos(&self.path_prefix)),
+        };
+        let (public, authenticated) = table.into_routers();
+        // Layers are listed outermost first, so the innermost is applied first
+        let pipeline = Arc::clone(&self.server_state.pipeline);
+        let authenticated = pipeline
+            .authenticated()
+            .rev()
+            .fold(authenticated, |router, layer| {
+                self.apply_layer(router, layer)
+            });
 
-        axum::serve(listener, app.into_make_service())
-            .await
-            .map_err(|e| McpCoreError::HttpServerError {
-                message: format!("Server error: {}", e),
-            })?;
+        let app = Router::new()
+            .merge(authenticated)
+            .merge(public.layer(Extension(listing)))
+            .fallback(not_found)
+            .with_state(self.server_state.clone());
 
-        Ok(())
+        // Wrap the whole router so the middleware sees the `Allow` header axum adds
+        let router = Router::new()
+            .fallback_service(app)
+            .layer(middleware::map_response(json_method_not_allowed));
+        pipeline
+            .outer()
+            .rev()
+            .fold(router, |router, layer| self.apply_layer(router, layer))
     }
-}
 
-/// Handle MCP requests via HTTP
-async fn handle_mcp_request(
-    State(server_state): State<ServerState>,
-    Json(payload): Json<McpRequest>,
-) -> Result<Json<McpResponse>, StatusCode> {
-    tracing::debug!("Received HTTP request: {:?}", payload);
+    /// Routes of `groups` with this server's options
+    fn route_table(&self, groups: &[RouteGroup]) -> RouteTable {
+        route_table(
+            groups,
+            self.server_state.simple_mode,
+            self.server_state.manifest.enabled,
+        )
+    }
 
-    let mut mcp_process_guard = server_state.mcp_process.lock().await;
-    tracing::debug!("Acquired MCP process mutex lock");
+    /// Routes of `groups` as listed, under the server's path prefix
+    pub(crate) fn route_infos(&self, groups: &[RouteGroup]) -> Vec<RouteInfo> {
+        self.route_table(groups).infos(&self.path_prefix)
+    }
 
-    match mcp_process_guard.query(&payload).await {
-        Ok(response) => {
-            tracing::debug!("MCP query successful: {:?}", response);
-            Ok(Json(response))
+    /// Wrap `router` in the middleware of `layer`, unless the layer has
+    /// nothing to do, see [`layer_applies`]
+    fn apply_layer<S>(&self, router: Router<S>, layer: &Layer) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let state = &self.server_state;
+        match layer {
+            Layer::AccessLog => match &state.access_log {
+                Some(log) => router.layer(middleware::from_fn_with_state(
+                    log.clone(),
+                    access_log::access_log_middleware,
+                )),
+                None => router,
+            },
+            Layer::VersionHeaders if state.version_headers => router.layer(
+                middleware::map_response_with_state(state.clone(), add_version_headers),
+            ),
+            Layer::VersionHeaders => router,
+            Layer::ResponseHeaders => match &state.response_headers {
+                Some(headers) => router.layer(middleware::map_response_with_state(
+                    Arc::clone(headers),
+                    add_response_headers,
+                )),
+                None => router,
+            },
+            Layer::Auth => router.layer(middleware::from_fn_with_state(
+                Arc::clone(&self.auth),
+                bearer_auth_middleware,
+            )),
+            Layer::RateLimit { .. } => match state.pipeline.rate_limiter() {
+                Some(limiter) => router.layer(middleware::from_fn_with_state(
+                    limiter,
+                    pipeline::rate_limit_middleware,
+                )),
+                None => router,
+            },
         }
-        Err(e) => {
-            tracing::error!("MCP query failed: {}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    /// Start the HTTP server and run until it stops
+    ///
+    /// SIGINT and SIGTERM shut the server down gracefully. With listeners configured, `port` is ignored and every listener is
+    /// served as by [`McpHttpServer::serve_all`].
+    pub async fn serve(self, port: u16) -> McpCoreResult<()> {
+        if !self.listeners.is_empty() {
+            return self.serve_all().await?.await_signal().await;
         }
+        self.serve_background(port).await?.await_signal().await
     }
-}
 
-/// Create a simple health check endpoint
-pub fn create_health_router() -> Router {
-    Router::new().route("/health", axum::routing::get(health_check))
-}
+    /// Bind every configured listener and serve each one's route groups
+    ///
+    /// Listeners are bound concurrently. If any fails to bind, the others are
+    /// closed and the error names the listener that failed.
+    pub async fn serve_all(self) -> McpCoreResult<ListenersHandle> {
+        serve_listeners(
+            &self.listeners,
+            &self.http,
+            |groups, local_addr| self.router(groups, local_addr),
+            ShutdownScope::of(&self.server_state),
+        )
+        .await
+    }
 
-async fn health_check() -> Json<serde_json::Value> {
-    Json(serde_json::json!({
-        "status": "healthy",
-        "service": "mcp-http-core",
-        "timestamp": chrono::Utc::now().to_rfc3339()
+    /// Start the HTTP server in a background task
+    ///
+    /// Port 0 picks an ephemeral port; the handle reports the actual address.
+    /// See [`ServerHandle`] for what happens when the handle is dropped.
+    pub async fn serve_background(self, port: u16) -> McpCoreResult<ServerHandle> {
+        let scope = ShutdownScope::of(&self.server_state);
+        let (bind_host, port_fallback) = (self.bind_host, self.port_fallback);
+        let local_addr = Arc::clone(&self.local_addr);
+        let http = self.http.clone();
+        let app = self.create_router();
+        serve_in_background(
+            bind_host,
+            port,
+            port_fallback,
+            &local_addr,
+            &http,
+            app,
+            scope,
+        )
+        .await
+    }
+}
+
+/// Whether the middleware of `layer` is put in the router; a layer of a
+/// feature the server does not use is left out
+pub(crate) fn layer_applies(server_state: &ServerState, layer: &Layer) -> bool {
+    match layer {
+        Layer::AccessLog => server_state.access_log.is_some(),
+        Layer::VersionHeaders => server_state.version_headers,
+        Layer::ResponseHeaders => server_state.response_headers.is_some(),
+        Layer::Auth => true,
+        Layer::RateLimit { .. } => server_state.pipeline.rate_limiter().is_some(),
+    }
+}
+
+/// Server states stopped together by a graceful shutdown, with the time
+/// budget and access log of what serves them
+#[derive(Clone)]
+pub(crate) struct ShutdownScope {
+    pub config: ShutdownConfig,
+    pub states: Vec<ServerState>,
+    pub access_log: Option<AccessLog>,
+}
+
+impl ShutdownScope {
+    /// Scope of a gateway serving a single server
+    fn of(server_state: &ServerState) -> Self {
+        Self {
+            config: server_state.shutdown.clone(),
+            states: vec![server_state.clone()],
+            access_log: server_state.access_log.clone(),
+        }
+    }
+}
+
+/// Bind `listeners` concurrently and serve each one the router `router`
+/// builds for its route groups
+pub(crate) async fn serve_listeners(
+    listeners: &[ListenerConfig],
+    http: &HttpConfig,
+    router: impl Fn(&[RouteGroup], Arc<OnceLock<SocketAddr>>) -> Router,
+    scope: ShutdownScope,
+) -> McpCoreResult<ListenersHandle> {
+    if listeners.is_empty() {
+        return Err(McpCoreError::ConfigurationError {
+            message: "No listeners configured".to_string(),
+        });
+    }
+
+    let binds: Vec<_> = listeners
+        .iter()
+        .map(|config| {
+            let (config, http2) = (config.clone(), http.http2);
+            tokio::spawn(async move {
+                let bound = bind_listener(&config, http2).await;
+                (config, bound)
+            })
+        })
+        .collect();
+    let mut bound = Vec::new();
+    let mut failure = None;
+    for bind in binds {
+        let (config, result) = bind.await.map_err(|e| McpCoreError::HttpServerError {
+            message: format!("Listener bind task failed: {}", e),
+        })?;
+        match result {
+            Ok(listener) => bound.push((config, listener)),
+            Err(e) => {
+                failure.get_or_insert(McpCoreError::HttpServerError {
+                    message: format!("Listener '{}' failed to start: {}", config.name, e),
+                });
+            }
+        }
+    }
+    // Dropping the bound listeners closes them
+    if let Some(error) = failure {
+        return Err(error);
+    }
+
+    let mut handle = ListenersHandle {
+        listeners: Vec::new(),
+        scope,
+        shutdown: Vec::new(),
+        tasks: JoinSet::new(),
+        abort_on_drop: false,
+    };
+    for (config, listener) in bound {
+        let local_addr = listener.local_addr();
+        let scheme = if config.tls.is_some() {
+            "https"
+        } else {
+            "http"
+        };
+        tracing::info!(
+            "Listener '{}' serving {:?} on {}://{}",
+            config.name,
+            config.routes,
+            scheme,
+            local_addr
+        );
+        for guidance in http.guidance(config.tls.is_some()) {
+            tracing::warn!("Listener '{}': {}", config.name, guidance);
+        }
+        let app = router(&config.routes, Arc::new(OnceLock::from(local_addr)));
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let name = config.name.clone();
+        let serving = listener.serve(app, http.clone(), shutdown_rx);
+        handle.tasks.spawn(async move { (name, serving.await) });
+        handle.shutdown.push(shutdown);
+        handle.listeners.push(BoundListener {
+            name: config.name,
+            local_addr,
+            tls: config.tls.is_some(),
+            routes: config.routes,
+        });
+    }
+    Ok(handle)
+}
+
+/// Bind `bind_host:port` and serve `app` in a background task, recording the
+/// address in `local_addr`
+pub(crate) async fn serve_in_background(
+    bind_host: IpAddr,
+    port: u16,
+    port_fallback: bool,
+    local_addr: &OnceLock<SocketAddr>,
+    http: &HttpConfig,
+    app: Router,
+    scope: ShutdownScope,
+) -> McpCoreResult<ServerHandle> {
+    tracing::info!("Starting HTTP server on {}:{}", bind_host, port);
+    let listener = listener::bind(bind_host, port, port_fallback).await?;
+    let bound_addr = listener
+        .local_addr()
+        .map_err(|e| McpCoreError::HttpServerError {
+            message: format!("Failed to get local address: {}", e),
+        })?;
+    let _ = local_addr.set(bound_addr);
+    tracing::info!("HTTP server listening on http://{}", bound_addr);
+    for guidance in http.guidance(false) {
+        tracing::warn!("{}", guidance);
+    }
+
+    let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+    let task = tokio::spawn(serve_until(listener, app, http.clone(), shutdown_rx));
+
+    Ok(ServerHandle {
+        local_addr: bound_addr,
+        scope,
+        shutdown: Some(shutdown),
+        task: Some(task),
+        abort_on_drop: false,
+    })
+}
+
+/// A bound listener, plain or TLS
+enum BoundSocket {
+    Plain(tokio::net::TcpListener),
+    #[cfg(feature = "tls")]
+    Tls(listener::TlsListener),
+}
+
+impl BoundSocket {
+    fn local_addr(&self) -> SocketAddr {
+        let addr = match self {
+            BoundSocket::Plain(listener) => listener.local_addr(),
+            #[cfg(feature = "tls")]
+            BoundSocket::Tls(listener) => axum::serve::Listener::local_addr(listener),
+        };
+        addr.expect("bound listener has a local address")
+    }
+
+    /// Serve `app` until `shutdown` fires
+    async fn serve(
+        self,
+        app: Router,
+        http: HttpConfig,
+        shutdown: oneshot::Receiver<()>,
+    ) -> McpCoreResult<()> {
+        match self {
+            BoundSocket::Plain(listener) => serve_until(listener, app, http, shutdown).await,
+            #[cfg(feature = "tls")]
+            BoundSocket::Tls(listener) => serve_until(listener, app, http, shutdown).await,
+        }
+    }
+}
+
+/// Bind a configured listener, loading its certificate if it uses TLS
+async fn bind_listener(
+    config: &ListenerConfig,
+    #[cfg_attr(not(feature = "tls"), allow(unused_variables))] http2: bool,
+) -> McpCoreResult<BoundSocket> {
+    let listener = listener::bind(config.bind.ip(), config.bind.port(), false).await?;
+    match &config.tls {
+        None => Ok(BoundSocket::Plain(listener)),
+        #[cfg(feature = "tls")]
+        Some(tls) => {
+            let tls = listener::load_tls(tls, http2)?;
+            listener::TlsListener::new(listener, tls)
+                .map(BoundSocket::Tls)
+                .map_err(|e| McpCoreError::HttpServerError {
+                    message: format!("Failed to get local address: {}", e),
+                })
+        }
+        #[cfg(not(feature = "tls"))]
+        Some(_) => Err(McpCoreError::ConfigurationError {
+            message: "TLS listeners require the 'tls' feature".to_string(),
+        }),
+    }
+}
+
+/// Serve `app` on `listener` until `shutdown` fires and its connections end
+///
+/// The rest of a graceful shutdown is run by [`shutdown_in_order`].
+async fn serve_until<L>(
+    listener: L,
+    app: Router,
+    http: HttpConfig,
+    shutdown: oneshot::Receiver<()>,
+) -> McpCoreResult<()>
+where
+    L: axum::serve::Listener<Addr = SocketAddr>,
+{
+    connection::serve(listener, app, &http, async move {
+        // A dropped handle closes the channel without asking to stop
+        if shutdown.await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    })
+    .await;
+    Ok(())
+}
+
+/// Shut down gracefully in the order described in [`crate::shutdown`]
+///
+/// `stop` asks the listeners to stop accepting connections, and `serving`
+/// resolves once their open connections have ended. Returns its output, or
+/// `None` if the connections did not end in time.
+async fn shutdown_in_order<T>(
+    scope: &ShutdownScope,
+    stop: Vec<oneshot::Sender<()>>,
+    serving: impl Future<Output = T>,
+) -> Option<T> {
+    let states = &scope.states;
+    let mut coordinator = ShutdownCoordinator::new(&scope.config);
+    coordinator
+        .phase(ShutdownPhase::StopAccepting, async {
+            for stop in stop {
+                let _ = stop.send(());
+            }
+        })
+        .await;
+    // Event streams would otherwise keep their connections open until
+    // their clients leave
+    coordinator
+        .phase(ShutdownPhase::CloseStreams, async {
+            for server_state in states {
+                server_state.streams.close_all();
+            }
+        })
+        .await;
+    coordinator
+        .phase(ShutdownPhase::DrainQueue, async {
+            let failed: usize = states
+                .iter()
+                .map(|server_state| {
+                    server_state.request_queue.close() + server_state.replicas.close()
+                })
+                .sum();
+            if failed > 0 {
+                tracing::info!("Failed {} queued requests", failed);
+            }
+        })
+        .await;
+    coordinator
+        .phase(ShutdownPhase::WaitInflight, async {
+            for server_state in states {
+                server_state.inflight.wait_idle().await;
+            }
+        })
+        .await;
+    coordinator
+        .phase(ShutdownPhase::CancelInflight, async {
+            let cancelled: usize = states
+                .iter()
+                .map(|server_state| server_state.inflight.abort_all(AbortReason::Shutdown))
+                .sum();
+            if cancelled > 0 {
+                tracing::info!("Cancelled {} in-flight requests", cancelled);
+            }
+            for server_state in states {
+                server_state.inflight.wait_idle().await;
+            }
+        })
+        .await;
+    let served = coordinator
+        .phase(ShutdownPhase::CloseConnections, serving)
+        .await;
+    coordinator
+        .phase(ShutdownPhase::StopBackground, async {
+            for server_state in states {
+                server_state.setup.cancel(&server_state.server_name);
+                server_state
+                    .setup
+                    .cancel(&canary::canary_name(&server_state.server_name));
+                for index in server_state.replicas.extra_indexes() {
+                    server_state
+                        .setup
+                        .cancel(&balance::replica_name(&server_state.server_name, index));
+                }
+                server_state.background.stop().await;
+            }
+        })
+        .await;
+    coordinator
+        .phase(ShutdownPhase::TerminateChildren, async {
+            for server_state in states {
+                let started = server_state.provisioner.provisioned().is_some();
+                if let Err(e) = server_state.provisioner.shut_down(Trigger::Shutdown).await {
+                    tracing::warn!("{}", e);
+                }
+                if started {
+                    server_state.server_hooks.post_stop().await;
+                }
+            }
+            let children = states.iter().flat_map(|server_state| {
+                server_state
+                    .canary
+                    .transport()
+                    .into_iter()
+                    .chain(server_state.replicas.transports())
+            });
+            for transport in children {
+                if let Err(e) = transport.lock().await.shutdown().await {
+                    tracing::warn!("{}", e);
+                }
+            }
+        })
+        .await;
+    coordinator
+        .phase(ShutdownPhase::Flush, async {
+            for server_state in states {
+                server_state.quotas.flush().await;
+            }
+            if let Some(access_log) = &scope.access_log {
+                access_log.flush().await;
+            }
+        })
+        .await;
+    coordinator.finish();
+    served
+}
+
+/// Routes of `groups`, which [`McpHttpServer::router`] mounts and the index
+/// and manifest list
+///
+/// `/api/v1/simple/{tool}` is served with `simple_mode`, the manifest with
+/// `manifest`.
+pub(crate) fn route_table(groups: &[RouteGroup], simple_mode: bool, manifest: bool) -> RouteTable {
+    let mut table = RouteTable::default();
+    if groups.contains(&RouteGroup::Api) {
+        table.add(RouteGroup::Api, api_routes(simple_mode));
+        #[cfg(feature = "ui")]
+        table.add(RouteGroup::Api, crate::ui::routes());
+    }
+    if groups.contains(&RouteGroup::Metrics) {
+        table.add(RouteGroup::Metrics, metrics_routes());
+    }
+    if groups.contains(&RouteGroup::Admin) {
+        table.add(RouteGroup::Admin, admin::routes());
+    }
+    if groups.contains(&RouteGroup::Health) {
+        table.add(RouteGroup::Health, health_routes(manifest));
+    }
+    table
+}
+
+/// Routes of the `api` group, with `/api/v1/simple/{tool}` if `simple_mode` is set
+fn api_routes(simple_mode: bool) -> Vec<Route> {
+    let mut routes = vec![
+        Route::post(
+            "/api/v1",
+            "Forward a JSON-RPC command to the MCP server",
+            handle_mcp_request,
+        )
+        .streaming(),
+        Route::post("/api/v1/", "Same as /api/v1", handle_mcp_request).streaming(),
+        Route::post(
+            "/api/v1/validate",
+            "Check whether a command would be accepted, without forwarding it",
+            validate_request,
+        ),
+        Route::get(
+            "/api/v1/info",
+            "Show the MCP server name and negotiated protocol version",
+            server_info,
+        ),
+        Route::get(
+            "/api/v1/presets",
+            "List the configured request presets and the params they accept",
+            list_presets,
+        ),
+        Route::post(
+            "/api/v1/presets/{name}",
+            "Send a preset request, with the body merged over its params",
+            handle_preset_request,
+        ),
+        Route::get(
+            "/api/v1/elicitations",
+            "List elicitations waiting for an answer",
+            list_elicitations,
+        ),
+        Route::get(
+            "/api/v1/elicitations/events",
+            "Stream elicitation requests as server-sent events",
+            elicitation_events,
+        )
+        .streaming(),
+        Route::post(
+            "/api/v1/elicitations/{id}",
+            "Answer an elicitation with an accept, decline, or cancel result",
+            answer_elicitation,
+        ),
+        Route::get(
+            "/api/v1/notifications",
+            "Long-poll notifications from the MCP server after a cursor",
+            poll_notifications,
+        ),
+    ];
+    if simple_mode {
+        for route in [Route::get, Route::post] {
+            routes.push(route(
+                "/api/v1/simple/{tool}",
+                "Call a tool with flat JSON, form, or query parameters",
+                handle_simple_request,
+            ));
+        }
+    }
+    routes
+}
+
+/// Routes of the `metrics` group
+fn metrics_routes() -> Vec<Route> {
+    vec![
+        Route::get(
+            "/api/v1/stats",
+            "Show request counts, errors, and latency over rolling windows",
+            server_stats,
+        ),
+        Route::get(
+            "/api/v1/stats/tools",
+            "Show calls, errors, and latency of each tool",
+            server_tool_stats,
+        ),
+    ]
+}
+
+/// Routes of the `health` group, served without authentication, with the
+/// route manifest if `manifest` is set
+fn health_routes(manifest: bool) -> Vec<Route> {
+    let mut routes = vec![
+        Route::get("/", "Describe the service and list its endpoints", index).public(),
+        Route::get(
+            "/ready",
+            "Report whether the server accepts requests or is drained for maintenance",
+            readiness,
+        )
+        .public(),
+        Route::get(
+            "/version",
+            "Show the gateway build and the MCP server's commit and protocol version",
+            version,
+        )
+        .public(),
+    ];
+    if manifest {
+        routes.push(
+            Route::get(
+                MANIFEST_PATH,
+                "List the routes served here, with their authentication, for edge routers",
+                gateway_manifest,
+            )
+            .public(),
+        );
+    }
+    routes
+}
+
+/// What the index and manifest of a listener describe
+#[derive(Clone)]
+struct Listing {
+    auth: Arc<SharedAuth>,
+    local_addr: Arc<OnceLock<SocketAddr>>,
+    routes: Arc<Vec<RouteInfo>>,
+}
+
+/// Handle MCP requests via HTTP, recording request statistics
+async fn handle_mcp_request(
+    State(server_state): State<ServerState>,
+    api_key_name: Option<Extension<ApiKeyName>>,
+    key_priority: Option<Extension<RequestPriority>>,
+    connect_info: Option<Extension<ConnectInfo<PeerAddr>>>,
+    Query(query): Query<Vec<(String, String)>>,
+    headers: HeaderMap,
+    RequestBody { payload, untyped }: RequestBody,
+) -> Response {
+    let started = std::time::Instant::now();
+    let bytes_in = payload.command.len();
+    let stats = Arc::clone(&server_state.stats);
+    let peek = CommandPeek::of(&payload.command);
+    let rpc_labels = RpcLabels {
+        method: peek.method_label().to_string(),
+        id: peek.id_label(),
+    };
+    let span = tracing::info_span!(
+        "mcp_request",
+        server = %server_state.server_name,
+        pid = server_state
+            .provisioner
+            .provisioned()
+            .and_then(|provisioned| provisioned.pid),
+        rpc_method = %rpc_labels.method,
+        rpc_id = rpc_labels.id.as_deref(),
+        timeout_secs = tracing::field::Empty,
+        timeout_limit = tracing::field::Empty,
+        original_method = tracing::field::Empty,
+    );
+
+    if client_notifications::is_notification(&payload.command) {
+        let response = send_notification(
+            server_state,
+            api_key_name,
+            key_priority,
+            &headers,
+            &payload.command,
+        )
+        .instrument(span)
+        .await;
+        stats.record_notification(response.is_ok());
+        let mut response = response.into_response();
+        response.extensions_mut().insert(rpc_labels);
+        return warn_if_untyped(response, untyped);
+    }
+
+    let client_addr = connect_info.map(|Extension(ConnectInfo(PeerAddr(addr)))| addr);
+    let raw = render::raw_requested(&headers, &query);
+    let mut response = process_mcp_request(
+        server_state,
+        api_key_name,
+        key_priority,
+        client_addr,
+        headers,
+        raw,
+        payload,
+    )
+    .instrument(span)
+    .await
+    .into_response();
+    response.extensions_mut().insert(rpc_labels);
+
+    let bytes_out = response.body().size_hint().exact().unwrap_or(0) as usize;
+    stats.record(
+        started.elapsed(),
+        ErrorClass::from_status(response.status().as_u16()),
+        bytes_in,
+        bytes_out,
+    );
+    warn_if_untyped(response, untyped)
+}
+
+/// Pass a client's notification to the MCP server without waiting for it
+///
+/// The notification goes through the same checks and transformations as a
+/// request, then joins the server's notification queue; the client gets
+/// `202` right away. `notifications/cancelled` cancels the client's
+/// in-flight request instead, see [`crate::client_notifications`].
+async fn send_notification(
+    server_state: ServerState,
+    api_key_name: Option<Extension<ApiKeyName>>,
+    key_priority: Option<Extension<RequestPriority>>,
+    headers: &HeaderMap,
+    command: &str,
+) -> McpCoreResult<StatusCode> {
+    // Provisioning is left to the writer, so the client never waits for it
+    server_state.maintenance.check()?;
+    server_state.provisioner.check_ready()?;
+    server_state.stdin_breaker.check()?;
+
+    // The variant and replica the client's requests go to
+    let route = route_request(&server_state, headers, api_key_name.as_ref());
+    let PreparedRequest {
+        command,
+        context,
+        priority,
+        ..
+    } = prepare_request(
+        &server_state,
+        api_key_name,
+        key_priority,
+        headers,
+        command,
+        false,
+    )
+    .await?;
+    let method = context.method.as_deref().unwrap_or_default();
+    server_state.client_notifications.check(method)?;
+
+    if method == client_notifications::CANCELLED {
+        let message: Value = serde_json::from_str(&command)?;
+        if let Some(request_id) = message.pointer("/params/requestId") {
+            let cancelled = server_state.inflight.cancel(
+                request_id,
+                context.api_key_name.as_deref(),
+                session_id(headers),
+            );
+            tracing::debug!("Client cancelled {} request(s) {}", cancelled, request_id);
+        }
+        return Ok(StatusCode::ACCEPTED);
+    }
+
+    tracing::debug!("Queueing notification {}", method);
+    let notification = QueuedNotification {
+        command,
+        priority,
+        route,
+    };
+    notification_queue(&server_state)
+        .try_send(notification)
+        .map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => McpCoreError::Overloaded {
+                message: format!(
+                    "Too many notifications waiting for the MCP server (limit {})",
+                    NOTIFICATION_QUEUE_SIZE
+                ),
+                retry_after_secs: 1,
+            },
+            mpsc::error::TrySendError::Closed(_) => McpCoreError::ShuttingDown {
+                message: "Notifications are no longer forwarded".to_string(),
+            },
+        })?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Notifications that may wait for the MCP server at once
+const NOTIFICATION_QUEUE_SIZE: usize = 1024;
+
+/// A client notification waiting to be written to the MCP server
+struct QueuedNotification {
+    command: String,
+    priority: RequestPriority,
+
+    /// Canary or replica the client's requests go to
+    route: Destination,
+}
+
+/// Queue of the server's client notifications, starting its writer with the first
+///
+/// One task writes the notifications in the order they were accepted, each
+/// once the server is set up and after a turn in the request queue, like a
+/// request. It holds no server
+/// state, so it ends once the last clone of the state is dropped.
+fn notification_queue(server_state: &ServerState) -> &mpsc::Sender<QueuedNotification> {
+    server_state.notification_queue.get_or_init(|| {
+        let (sender, mut receiver) = mpsc::channel::<QueuedNotification>(NOTIFICATION_QUEUE_SIZE);
+        let transport = Arc::clone(&server_state.transport);
+        let provisioner = Arc::clone(&server_state.provisioner);
+        let request_queue = Arc::clone(&server_state.request_queue);
+        let stdin_breaker = Arc::clone(&server_state.stdin_breaker);
+        let writer = tokio::spawn(async move {
+            while let Some(notification) = receiver.recv().await {
+                if let Err(e) = provisioner.ensure_ready().await {
+                    tracing::warn!("Dropping notification: {}", e);
+                    continue;
+                }
+                let route = &notification.route;
+                let queue = route.queue.as_ref().unwrap_or(&request_queue);
+                let _turn = match queue.acquire(notification.priority).await {
+                    Ok(turn) => turn,
+                    Err(e) => {
+                        tracing::warn!("Dropping notification: {}", e);
+                        continue;
+                    }
+                };
+                let primary = route.is_primary();
+                let transport = route.transport.as_ref().unwrap_or(&transport);
+                let sent = transport.lock().await.send(&notification.command).await;
+                match sent {
+                    Ok(()) if primary => stdin_breaker.record_success(),
+                    Ok(()) => {}
+                    Err(e) => {
+                        tracing::warn!("Failed to forward notification: {}", e);
+                        if let (true, McpCoreError::ChildUnresponsive { .. }) = (primary, e) {
+                            stdin_breaker.trip(&provisioner);
+                        }
+                    }
+                }
+            }
+        });
+        server_state
+            .background
+            .register("notification writer", writer);
+        sender
+    })
+}
+
+/// Send a configured preset, with the body merged over its params
+#[allow(clippy::too_many_arguments)]
+async fn handle_preset_request(
+    State(server_state): State<ServerState>,
+    api_key_name: Option<Extension<ApiKeyName>>,
+    key_priority: Option<Extension<RequestPriority>>,
+    connect_info: Option<Extension<ConnectInfo<PeerAddr>>>,
+    Path(name): Path<String>,
+    query: Query<Vec<(String, String)>>,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> Response {
+    let command = match server_state.presets.command(&name, &body) {
+        Ok(command) => command,
+        Err(e) => return e.into_response(),
+    };
+    handle_mcp_request(
+        State(server_state),
+        api_key_name,
+        key_priority,
+        connect_info,
+        query,
+        headers,
+        RequestBody::new(McpRequest { command }),
+    )
+    .await
+}
+
+/// Presets of the MCP server and the params a request body can replace
+async fn list_presets(State(server_state): State<ServerState>) -> Json<Value> {
+    Json(serde_json::json!({
+        "server": server_state.server_name,
+        "presets": server_state.presets.list(),
     }))
 }
+
+/// Body of `POST /api/v1`, checked for keys [`McpRequest`] does not declare
+///
+/// The body must be `application/json` (or a `+json` type), in UTF-8 if a
+/// charset is given. A body without a Content-Type is still accepted if it
+/// parses, with a [`MISSING_CONTENT_TYPE_WARNING`] on the response.
+struct RequestBody {
+    payload: McpRequest,
+
+    /// Whether the request came without a Content-Type
+    untyped: bool,
+}
+
+impl RequestBody {
+    fn new(payload: McpRequest) -> Self {
+        Self {
+            payload,
+            untyped: false,
+        }
+    }
+}
+
+/// `Warning` header of responses to requests without a Content-Type
+const MISSING_CONTENT_TYPE_WARNING: &str =
+    "299 - \"Requests without Content-Type: application/json are deprecated\"";
+
+impl axum::extract::FromRequest<ServerState> for RequestBody {
+    type Rejection = Response;
+
+    async fn from_request(
+        request: axum::extract::Request,
+        state: &ServerState,
+    ) -> Result<Self, Self::Rejection> {
+        let content_type = request
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned());
+        if let Some(content_type) = &content_type {
+            check_json_content_type(content_type).map_err(IntoResponse::into_response)?;
+        }
+        let bytes = axum::body::Bytes::from_request(request, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let payload = match serde_json::from_slice::<McpRequest>(&bytes) {
+            Ok(payload) => payload,
+            // Only a body that is JSON excuses a missing Content-Type
+            Err(e) if content_type.is_none() && (e.is_syntax() || e.is_eof()) => {
+                return Err(McpCoreError::UnsupportedMediaType {
+                    message: "Missing Content-Type, expected application/json".to_string(),
+                    content_type: None,
+                }
+                .into_response())
+            }
+            Err(e) => return Err(malformed_body(&e).into_response()),
+        };
+
+        let body: Value = serde_json::from_slice(&bytes).unwrap_or_default();
+        let unknown = strict::unknown_keys::<McpRequest>(&body);
+        if !unknown.is_empty() && state.strict {
+            return Err(McpCoreError::RequestError {
+                message: format!(
+                    "Unknown keys in request body: {}",
+                    strict::describe(&unknown)
+                ),
+            }
+            .into_response());
+        }
+        strict::warn_once("request body", &unknown);
+        Ok(RequestBody {
+            payload,
+            untyped: content_type.is_none(),
+        })
+    }
+}
+
+/// `response` with the [`MISSING_CONTENT_TYPE_WARNING`] if `untyped`
+fn warn_if_untyped(mut response: Response, untyped: bool) -> Response {
+    if untyped {
+        response.headers_mut().insert(
+            header::WARNING,
+            HeaderValue::from_static(MISSING_CONTENT_TYPE_WARNING),
+        );
+    }
+    response
+}
+
+/// Check that `content_type` is JSON in UTF-8
+fn check_json_content_type(content_type: &str) -> McpCoreResult<()> {
+    let mut parts = content_type.split(';');
+    let essence = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+    let json = essence == "application/json"
+        || essence.starts_with("application/") && essence.ends_with("+json");
+    let utf8 = parts.all(|parameter| match parameter.split_once('=') {
+        Some((name, value)) if name.trim().eq_ignore_ascii_case("charset") => {
+            let charset = value.trim().trim_matches('"');
+            charset.eq_ignore_ascii_case("utf-8") || charset.eq_ignore_ascii_case("utf8")
+        }
+        _ => true,
+    });
+    if json && utf8 {
+        return Ok(());
+    }
+    Err(McpCoreError::UnsupportedMediaType {
+        message: format!(
+            "Content-Type '{}' is not supported, expected application/json",
+            content_type
+        ),
+        content_type: Some(content_type.to_string()),
+    })
+}
+
+/// Error for a request body that is not an [`McpRequest`]
+fn malformed_body(error: &serde_json::Error) -> McpCoreError {
+    McpCoreError::MalformedBody {
+        message: error.to_string(),
+        line: error.line(),
+        column: error.column(),
+    }
+}
+
+/// A request that passed every check, ready to be forwarded
+struct PreparedRequest {
+    /// Message to write to the MCP server
+    command: String,
+    context: RequestContext,
+    priority: RequestPriority,
+    timeout: ResolvedTimeout,
+}
+
+/// Run every check and transformation a request goes through before it is forwarded
+///
+/// Shared by the real handler and `POST /api/v1/validate`, so a dry run
+/// reports exactly what forwarding would do. Never touches the transport.
+/// A `health_check` is the gateway's own request: header injection and the
+/// embedder's request hooks, which are there for clients, are skipped.
+async fn prepare_request(
+    server_state: &ServerState,
+    api_key_name: Option<Extension<ApiKeyName>>,
+    key_priority: Option<Extension<RequestPriority>>,
+    headers: &HeaderMap,
+    command: &str,
+    health_check: bool,
+) -> McpCoreResult<PreparedRequest> {
+    // The header overrides the API key's default priority
+    let priority = RequestPriority::from_headers(headers)?
+        .or(key_priority.map(|Extension(priority)| priority))
+        .unwrap_or_default();
+
+    // Guarantee a single bounded JSON line so the MCP server cannot be desynchronized
+    let mut message = server_state.command_policy.validate(command)?;
+
+    // Rewrite before anything else looks at the method or params
+    if let Some(rewrite) = &server_state.rewrite {
+        let aliased = rewrite.apply(&mut message)?;
+        if !aliased.is_empty() {
+            tracing::Span::current().record("original_method", aliased.join(","));
+        }
+    }
+    let mut command = message.to_string();
+
+    let context = RequestContext {
+        server_name: server_state.server_name.clone(),
+        method: message
+            .get("method")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        api_key_name: api_key_name.map(|Extension(ApiKeyName(name))| name),
+        request_id: message.get("id").cloned(),
+        extensions: Arc::clone(&server_state.extensions),
+    };
+    let timeout = server_state.timeouts.resolve(&message, headers)?;
+
+    if health_check {
+        return Ok(PreparedRequest {
+            command,
+            context,
+            priority,
+            timeout,
+        });
+    }
+
+    // Inject header values into the command; only the redacted copy is logged
+    if let Some(injected) = apply_injection_rules(&server_state.param_injection, headers, &command)?
+    {
+        tracing::debug!("Command after injection: {}", injected.redacted);
+        command = injected.command;
+    }
+
+    // Run embedder request hooks on the parsed message
+    if !server_state.hooks.on_request.is_empty() {
+        let message: Value =
+            serde_json::from_str(&command).map_err(|e| McpCoreError::RequestError {
+                message: format!("Command is not valid JSON: {}", e),
+            })?;
+        command = server_state
+            .hooks
+            .run_request(message, &context)
+            .await?
+            .to_string();
+    }
+
+    Ok(PreparedRequest {
+        command,
+        context,
+        priority,
+        timeout,
+    })
+}
+
+/// Validate, transform, and forward a request, rendering the response as the client asked
+///
+/// With `raw` the response line is returned exactly as the server wrote it.
+async fn process_mcp_request(
+    server_state: ServerState,
+    api_key_name: Option<Extension<ApiKeyName>>,
+    key_priority: Option<Extension<RequestPriority>>,
+    client_addr: Option<SocketAddr>,
+    headers: HeaderMap,
+    raw: bool,
+    payload: McpRequest,
+) -> Result<Response, McpCoreError> {
+    tracing::debug!("Received HTTP request: {:?}", payload);
+    if render::stream_requested(&headers) {
+        return Ok(stream_mcp_request(
+            server_state,
+            api_key_name,
+            key_priority,
+            client_addr,
+            headers,
+            payload,
+        )
+        .await);
+    }
+    let canary = Arc::clone(&server_state.canary);
+    let captures = Arc::clone(&server_state.captures);
+    let tool_stats = Arc::clone(&server_state.tool_stats);
+    let response_headers = server_state.response_headers.clone();
+    let route = route_request(&server_state, &headers, api_key_name.as_ref());
+    let variant = route.variant;
+    let started = std::time::Instant::now();
+    let response = exchange(
+        server_state,
+        api_key_name,
+        key_priority,
+        client_addr,
+        &headers,
+        &payload.command,
+        route,
+        raw,
+        false,
+        None,
+    )
+    .await
+    .map(|response| response.expect("only a sink streams a response"));
+    captures.record(
+        &payload.command,
+        response.as_ref().map(|response| response.result.as_str()),
+        started.elapsed(),
+        chrono::Utc::now(),
+    );
+    tool_stats.record(
+        &payload.command,
+        response.as_ref().map(|response| response.result.as_str()),
+        started.elapsed(),
+    );
+    let meta_headers = meta_headers(response_headers.as_deref(), response.as_ref().ok());
+    let response = response.and_then(|response| match raw {
+        true => render::render_raw(response),
+        false => Ok(render::render(
+            ResponseFormat::from_headers(&headers),
+            response,
+        )),
+    });
+    let mut response = response.into_response();
+    response.headers_mut().extend(meta_headers);
+    Ok(tag_variant(response, &canary, variant))
+}
+
+/// Forward a request whose response is passed to the client as it is read
+///
+/// The exchange is raw and runs in its own task, so the body can start
+/// while the server is still writing. A response read whole, such as one
+/// whose `id` follows its `result`, is sent like any raw response. Streamed
+/// responses are not captured or counted in the tool statistics.
+async fn stream_mcp_request(
+    server_state: ServerState,
+    api_key_name: Option<Extension<ApiKeyName>>,
+    key_priority: Option<Extension<RequestPriority>>,
+    client_addr: Option<SocketAddr>,
+    headers: HeaderMap,
+    payload: McpRequest,
+) -> Response {
+    let canary = Arc::clone(&server_state.canary);
+    let route = route_request(&server_state, &headers, api_key_name.as_ref());
+    let variant = route.variant;
+    let (mut sink, chunks, mut started) =
+        ResponseSink::channel(server_state.max_response_bytes, transport::RESPONSE_TIMEOUT);
+    let mut exchanged = tokio::spawn(
+        async move {
+            exchange(
+                server_state,
+                api_key_name,
+                key_priority,
+                client_addr,
+                &headers,
+                &payload.command,
+                route,
+                true,
+                false,
+                Some(&mut sink),
+            )
+            .await
+        }
+        .instrument(tracing::Span::current()),
+    );
+    let response = tokio::select! {
+        biased;
+        Ok(()) = &mut started => Ok(render::render_streamed(chunks)),
+        exchanged = &mut exchanged => match exchanged {
+            // A streamed response has started the body first
+            Ok(Ok(response)) => {
+                render::render_raw(response.expect("a streamed response started the body"))
+            }
+            Ok(Err(e)) => Err(e),
+            Err(e) => Err(McpCoreError::ProcessError {
+                message: format!("Streamed exchange failed: {}", e),
+            }),
+        },
+    };
+    tag_variant(response.into_response(), &canary, variant)
+}
+
+/// Headers the server's configuration takes from its JSON-RPC response
+fn meta_headers(
+    response_headers: Option<&ResponseHeaders>,
+    response: Option<&McpResponse>,
+) -> HeaderMap {
+    match (response_headers, response) {
+        (Some(config), Some(response)) if config.reads_meta() => {
+            serde_json::from_str::<Value>(&response.result)
+                .map(|message| config.from_message(&message))
+                .unwrap_or_default()
+        }
+        _ => HeaderMap::new(),
+    }
+}
+
+/// Session the client sent the request in, if it named one
+fn session_id(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(canary::SESSION_HEADER)
+        .and_then(|value| value.to_str().ok())
+}
+
+/// Child a request is sent to, and the variant it runs
+struct Destination {
+    variant: Variant,
+
+    /// Transport of the canary or replica serving the request, unless it is
+    /// the primary
+    transport: Option<SharedTransport>,
+
+    /// Turns of the replica serving the request, unless they are the server's
+    queue: Option<Arc<RequestQueue>>,
+
+    /// Counts the request in flight at its replica until it is dropped
+    _replica: Option<PickedReplica>,
+}
+
+impl Destination {
+    /// The primary, bypassing the canary and the replicas
+    fn primary() -> Self {
+        Self {
+            variant: Variant::Primary,
+            transport: None,
+            queue: None,
+            _replica: None,
+        }
+    }
+
+    /// Whether the primary child serves the request
+    fn is_primary(&self) -> bool {
+        self.transport.is_none()
+    }
+}
+
+/// Pick the variant answering a request, keyed by its session or API key,
+/// and the replica serving it, keyed by its affinity header or API key
+fn route_request(
+    server_state: &ServerState,
+    headers: &HeaderMap,
+    api_key_name: Option<&Extension<ApiKeyName>>,
+) -> Destination {
+    let key = api_key_name.map(|Extension(ApiKeyName(name))| name.as_str());
+    let (variant, canary_transport) = server_state.canary.route(session_id(headers).or(key));
+    if canary_transport.is_some() {
+        return Destination {
+            variant,
+            transport: canary_transport,
+            queue: None,
+            _replica: None,
+        };
+    }
+
+    let affinity = headers
+        .get(balance::AFFINITY_HEADER)
+        .and_then(|value| value.to_str().ok());
+    let replica = server_state
+        .replicas
+        .pick(balance::affinity_key(affinity, key));
+    let (transport, queue) = replica
+        .as_ref()
+        .and_then(PickedReplica::target)
+        .cloned()
+        .unzip();
+    Destination {
+        variant,
+        transport,
+        queue,
+        _replica: replica,
+    }
+}
+
+/// Name the variant that answered in the response, if the server has a canary
+fn tag_variant(mut response: Response, canary: &CanaryRouter, variant: Variant) -> Response {
+    if canary.is_configured() {
+        response.headers_mut().insert(
+            canary::VARIANT_HEADER,
+            HeaderValue::from_static(variant.as_str()),
+        );
+    }
+    response
+}
+
+/// Validate, transform, and forward a command to the MCP server, or to the
+/// canary or replica `route` picked
+///
+/// A `raw` exchange leaves the response untouched: the request keeps the
+/// client's id and gets no `_meta` context, and response hooks are skipped.
+/// With a `sink`, a raw response is written to it as it is read and `None`
+/// returned. A `health_check` exchange is exempt from load shedding and is
+/// not counted toward recycling or the canary comparison.
+#[allow(clippy::too_many_arguments)]
+async fn exchange(
+    server_state: ServerState,
+    api_key_name: Option<Extension<ApiKeyName>>,
+    key_priority: Option<Extension<RequestPriority>>,
+    client_addr: Option<SocketAddr>,
+    headers: &HeaderMap,
+    command: &str,
+    route: Destination,
+    raw: bool,
+    health_check: bool,
+    sink: Option<&mut ResponseSink>,
+) -> McpCoreResult<Option<McpResponse>> {
+    server_state.maintenance.check()?;
+    server_state.provisioner.ensure_ready().await?;
+    let primary = route.is_primary();
+    if primary {
+        server_state.stdin_breaker.check()?;
+    }
+
+    let PreparedRequest {
+        mut command,
+        context,
+        priority,
+        timeout,
+    } = prepare_request(
+        &server_state,
+        api_key_name,
+        key_priority,
+        headers,
+        command,
+        health_check,
+    )
+    .await?;
+    let span = tracing::Span::current();
+    span.record("timeout_secs", timeout.duration.as_secs());
+    span.record("timeout_limit", tracing::field::display(&timeout.limit));
+
+    // Fail fast rather than queue behind an overloaded server
+    if let Some(shedder) = server_state.load_shedder.as_ref().filter(|_| !health_check) {
+        shedder.admit(context.method.as_deref(), server_state.inflight.queued())?;
+    }
+    // Counted once the request reaches the server; refused before, it is not
+    let quota = context
+        .api_key_name
+        .as_deref()
+        .map(|key| {
+            server_state
+                .quotas
+                .charge(key, context.method.as_deref(), chrono::Utc::now())
+        })
+        .transpose()?;
+
+    // Tell the server who is asking; echoes are stripped from the response
+    let injected_meta = match &server_state.context_meta {
+        Some(config) if !raw => {
+            let caller = CallerContext {
+                api_key_name: context.api_key_name.as_deref(),
+                request_id: context.request_id.as_ref(),
+                client_ip: client_addr.map(|addr| addr.ip()),
+            };
+            let mut message: Value = serde_json::from_str(&command)?;
+            let injected = context_meta::inject(&mut message, config.context(&caller));
+            if injected.is_some() {
+                command = message.to_string();
+            }
+            injected
+        }
+        _ => None,
+    };
+
+    let (inflight, abort) = server_state.inflight.register_with_deadline(
+        context.request_id.clone(),
+        context.method.clone(),
+        context.api_key_name.clone(),
+        session_id(headers).map(str::to_string),
+        timeout.duration,
+    )?;
+
+    // Give each request a gateway-unique id so clients reusing ids cannot collide
+    let mut request_id = context.request_id.clone();
+    let rewritten = match &server_state.id_rewriter {
+        Some(rewriter) if !raw => {
+            let mut message: Value = serde_json::from_str(&command)?;
+            let rewritten = rewriter.rewrite(&mut message);
+            if !rewritten.is_empty() {
+                command = message.to_string();
+                request_id = rewritten.request_id();
+            }
+            Some(rewritten)
+        }
+        _ => None,
+    };
+
+    if primary && !health_check {
+        server_state.provisioner.record_request();
+    }
+    let transport = route
+        .transport
+        .clone()
+        .unwrap_or_else(|| Arc::clone(&server_state.transport));
+    let queue = route
+        .queue
+        .clone()
+        .unwrap_or_else(|| Arc::clone(&server_state.request_queue));
+    let forwarded = forward_to_process(
+        &server_state,
+        (&transport, &queue),
+        &command,
+        priority,
+        request_id.as_ref(),
+        &inflight,
+        abort,
+        quota,
+        &timeout,
+        sink.filter(|_| raw),
+    )
+    .await;
+    if primary {
+        match &forwarded {
+            Err(McpCoreError::ChildUnresponsive { .. }) => {
+                server_state.stdin_breaker.trip(&server_state.provisioner)
+            }
+            Ok(_) => server_state.stdin_breaker.record_success(),
+            // Says nothing about the child, and may still be queued behind it
+            Err(McpCoreError::RequestAborted { .. } | McpCoreError::RequestCancelled { .. }) => {}
+            // A child found gone is recorded as crashed
+            Err(_) => {
+                server_state.provisioner.check_exited().await;
+            }
+        }
+    }
+    if server_state.canary.is_configured() && !health_check {
+        let failed = match &forwarded {
+            Ok(Some(response)) => serde_json::from_str::<Value>(&response.result)
+                .is_ok_and(|message| message.get("error").is_some()),
+            Ok(None) => false,
+            Err(_) => true,
+        };
+        server_state.canary.record(route.variant, failed);
+    }
+    let mut response = match forwarded {
+        Ok(Some(response)) => {
+            tracing::debug!("MCP query successful: {:?}", response);
+            response
+        }
+        Ok(None) => {
+            tracing::debug!("MCP query streamed");
+            return Ok(None);
+        }
+        Err(e) => {
+            tracing::error!("MCP query failed: {}", e);
+            return Err(e);
+        }
+    };
+    drop(inflight);
+
+    if let Some(rewritten) = rewritten.filter(|rewritten| !rewritten.is_empty()) {
+        if let Ok(mut message) = serde_json::from_str::<Value>(&response.result) {
+            rewritten.restore(&mut message);
+            response.result = message.to_string();
+        }
+    }
+
+    if let Some(injected) = &injected_meta {
+        if let Ok(mut message) = serde_json::from_str::<Value>(&response.result) {
+            if context_meta::strip(&mut message, injected) {
+                response.result = message.to_string();
+            }
+        }
+    }
+
+    // Run embedder response hooks; non-JSON responses are passed through untouched
+    if !raw && !server_state.hooks.on_response.is_empty() {
+        match serde_json::from_str::<Value>(&response.result) {
+            Ok(message) => {
+                response.result = server_state
+                    .hooks
+                    .run_response(message, &context)
+                    .await
+                    .to_string();
+            }
+            Err(e) => {
+                tracing::warn!("Skipping response hooks for non-JSON response: {}", e);
+            }
+        }
+    }
+
+    Ok(Some(response))
+}
+
+/// Send the server's health check through the request path every interval
+///
+/// A check waits at low priority for its turn and is given the check's
+/// timeout as if requested with `X-MCP-Timeout`. It is not sent unless the
+/// serving child is up and taking requests.
+fn spawn_health_checks(checker: Arc<HealthChecker>, server_state: ServerState) -> JoinHandle<()> {
+    let timeout = HeaderValue::from(checker.config().timeout().as_secs());
+    checker.spawn(move |command| {
+        let server_state = server_state.clone();
+        let mut headers = HeaderMap::new();
+        headers.insert(method_timeout::TIMEOUT_HEADER, timeout.clone());
+        async move {
+            if server_state.provisioner.provisioned().is_none()
+                || server_state.maintenance.current().is_some()
+                || server_state.stdin_breaker.snapshot().open
+            {
+                return None;
+            }
+            let exchanged = exchange(
+                server_state,
+                None,
+                Some(Extension(RequestPriority::Low)),
+                None,
+                &headers,
+                &command,
+                Destination::primary(),
+                false,
+                true,
+                None,
+            )
+            .await;
+            Some(exchanged.map(|response| response.expect("only a sink streams a response").result))
+        }
+        .instrument(tracing::info_span!("health_check"))
+    })
+}
+
+/// Call a tool with flat JSON, form, or query parameters, recording request statistics
+async fn handle_simple_request(
+    State(server_state): State<ServerState>,
+    api_key_name: Option<Extension<ApiKeyName>>,
+    key_priority: Option<Extension<RequestPriority>>,
+    Path(tool): Path<String>,
+    Query(query): Query<Vec<(String, String)>>,
+    request: axum::extract::Request,
+) -> Response {
+    let started = std::time::Instant::now();
+    let bytes_in = request.body().size_hint().exact().unwrap_or(0) as usize;
+    let stats = Arc::clone(&server_state.stats);
+    let span = tracing::info_span!(
+        "mcp_request",
+        server = %server_state.server_name,
+        tool = %tool,
+        rpc_method = "tools/call",
+        timeout_secs = tracing::field::Empty,
+        timeout_limit = tracing::field::Empty,
+        original_method = tracing::field::Empty,
+    );
+
+    let mut response = process_simple_request(
+        server_state,
+        api_key_name,
+        key_priority,
+        tool,
+        query,
+        request,
+    )
+    .instrument(span)
+    .await
+    .into_response();
+    response.extensions_mut().insert(RpcLabels {
+        method: "tools/call".to_string(),
+        id: None,
+    });
+
+    let bytes_out = response.body().size_hint().exact().unwrap_or(0) as usize;
+    stats.record(
+        started.elapsed(),
+        ErrorClass::from_status(response.status().as_u16()),
+        bytes_in,
+        bytes_out,
+    );
+    response
+}
+
+/// Turn the parameters of a simple request into a `tools/call` and unwrap its result
+async fn process_simple_request(
+    server_state: ServerState,
+    api_key_name: Option<Extension<ApiKeyName>>,
+    key_priority: Option<Extension<RequestPriority>>,
+    tool: String,
+    query: Vec<(String, String)>,
+    request: axum::extract::Request,
+) -> McpCoreResult<Response> {
+    server_state.maintenance.check()?;
+    server_state.provisioner.ensure_ready().await?;
+
+    let headers = request.headers().clone();
+    let client_addr = request
+        .extensions()
+        .get::<ConnectInfo<PeerAddr>>()
+        .map(|ConnectInfo(PeerAddr(addr))| *addr);
+    let mut pairs: Vec<(String, Value)> = query
+        .into_iter()
+        .map(|(key, value)| (key, Value::String(value)))
+        .collect();
+    pairs.extend(simple_body_params(request).await?);
+    let mut arguments = Value::Object(simple::expand(pairs)?);
+
+    // Query and form values arrive as strings; the schema says what they should be
+    if let Some(tool_schemas) = &server_state.tool_schemas {
+        let mut transport = server_state.transport.lock().await;
+        let schema = tool_schemas
+            .input_schema(transport.as_mut(), &server_state.server_requests, &tool)
+            .await?;
+        drop(transport);
+        if let Some(schema) = schema {
+            simple::coerce(&mut arguments, &schema);
+        }
+    }
+    let Value::Object(arguments) = arguments else {
+        unreachable!("expanded parameters are an object")
+    };
+
+    let command = simple::tool_call(&tool, arguments).to_string();
+    let canary = Arc::clone(&server_state.canary);
+    let tool_stats = Arc::clone(&server_state.tool_stats);
+    let response_headers = server_state.response_headers.clone();
+    let route = route_request(&server_state, &headers, api_key_name.as_ref());
+    let variant = route.variant;
+    let started = std::time::Instant::now();
+    let response = exchange(
+        server_state,
+        api_key_name,
+        key_priority,
+        client_addr,
+        &headers,
+        &command,
+        route,
+        false,
+        false,
+        None,
+    )
+    .await
+    .map(|response| response.expect("only a sink streams a response"));
+    tool_stats.record(
+        &command,
+        response.as_ref().map(|response| response.result.as_str()),
+        started.elapsed(),
+    );
+    let meta_headers = meta_headers(response_headers.as_deref(), response.as_ref().ok());
+    let mut response = response
+        .and_then(|response| simple::render(&response.result))
+        .into_response();
+    response.headers_mut().extend(meta_headers);
+    Ok(tag_variant(response, &canary, variant))
+}
+
+/// Parameters in the body of a simple request: a form, or a flat JSON object
+async fn simple_body_params(
+    request: axum::extract::Request,
+) -> McpCoreResult<Vec<(String, Value)>> {
+    use axum::extract::FromRequest;
+
+    let is_form = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+    if is_form {
+        let axum::Form(form) = axum::Form::<Vec<(String, String)>>::from_request(request, &())
+            .await
+            .map_err(|rejection| McpCoreError::RequestError {
+                message: rejection.body_text(),
+            })?;
+        return Ok(form
+            .into_iter()
+            .map(|(key, value)| (key, Value::String(value)))
+            .collect());
+    }
+
+    let body = axum::body::Bytes::from_request(request, &())
+        .await
+        .map_err(|rejection| McpCoreError::RequestError {
+            message: rejection.body_text(),
+        })?;
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(Vec::new());
+    }
+    match serde_json::from_slice(&body) {
+        Ok(Value::Object(object)) => Ok(object.into_iter().collect()),
+        Ok(_) => Err(McpCoreError::RequestError {
+            message: "Body must be a JSON object".to_string(),
+        }),
+        Err(e) => Err(McpCoreError::RequestError {
+            message: format!("Body is not valid JSON: {}", e),
+        }),
+    }
+}
+
+/// Report whether a request would be accepted, without forwarding it
+///
+/// Runs [`prepare_request`], the same checks as `POST /api/v1`, and answers
+/// `200` either way; rejections are listed as violations with the status the
+/// real request would receive.
+async fn validate_request(
+    State(server_state): State<ServerState>,
+    api_key_name: Option<Extension<ApiKeyName>>,
+    key_priority: Option<Extension<RequestPriority>>,
+    headers: HeaderMap,
+    RequestBody { payload, untyped }: RequestBody,
+) -> Response {
+    let report = match prepare_request(
+        &server_state,
+        api_key_name,
+        key_priority,
+        &headers,
+        &payload.command,
+        false,
+    )
+    .await
+    {
+        Ok(prepared) => Json(serde_json::json!({
+            "accepted": true,
+            "method": prepared.context.method,
+            "priority": prepared.priority,
+            "timeout": {
+                "secs": prepared.timeout.duration.as_secs(),
+                "limit": prepared.timeout.limit.to_string(),
+            },
+        })),
+        Err(e) => Json(serde_json::json!({
+            "accepted": false,
+            "violations": [{
+                "code": violation_code(&e),
+                "status": e.status_code().as_u16(),
+                "message": e.to_string(),
+            }],
+        })),
+    };
+    warn_if_untyped(report.into_response(), untyped)
+}
+
+/// Machine-readable code of a validation failure
+fn violation_code(error: &McpCoreError) -> &'static str {
+    match error {
+        McpCoreError::HookRejected { .. } => "rejected_by_hook",
+        McpCoreError::RequestError { .. } => "invalid_request",
+        _ => error.error_code().unwrap_or("invalid_request"),
+    }
+}
+
+/// Send a command to the MCP server and read its response
+///
+/// The request first waits for its turn in `priority`'s queue class.
+/// Resolving `abort` (an admin abort or the request's `timeout`) cancels the
+/// request: a queued request is dropped, and a sent request is followed by an
+/// MCP cancellation notification. The `quota` charge is kept once the request
+/// is written, and taken back if it never is.
+#[allow(clippy::too_many_arguments)]
+async fn forward_to_process(
+    server_state: &ServerState,
+    (transport, queue): (&SharedTransport, &Arc<RequestQueue>),
+    command: &str,
+    priority: RequestPriority,
+    request_id: Option<&Value>,
+    inflight: &InflightGuard,
+    abort: oneshot::Receiver<AbortReason>,
+    quota: Option<QuotaCharge>,
+    timeout: &ResolvedTimeout,
+    sink: Option<&mut ResponseSink>,
+) -> McpCoreResult<Option<McpResponse>> {
+    let abort = async move { abort.await.unwrap_or(AbortReason::Aborted) };
+    tokio::pin!(abort);
+    let aborted = |reason| match reason {
+        AbortReason::Aborted => McpCoreError::RequestAborted {
+            message: format!("In-flight request {} was aborted", inflight.id()),
+        },
+        AbortReason::Expired => timeout.exceeded(&format!("In-flight request {}", inflight.id())),
+        AbortReason::Shutdown => McpCoreError::ShuttingDown {
+            message: format!("In-flight request {} was cancelled", inflight.id()),
+        },
+        AbortReason::Cancelled => McpCoreError::RequestCancelled {
+            message: format!(
+                "In-flight request {} was cancelled by the client",
+                inflight.id()
+            ),
+        },
+    };
+
+    let _turn = tokio::select! {
+        turn = queue.acquire(priority) => turn?,
+        reason = &mut abort => return Err(aborted(reason)),
+    };
+    let mut transport_guard = tokio::select! {
+        guard = transport.lock() => guard,
+        reason = &mut abort => return Err(aborted(reason)),
+    };
+    tracing::debug!("Acquired MCP transport mutex lock");
+
+    // Reject tool calls whose arguments the server would refuse anyway
+    if let Some(tool_schemas) = &server_state.tool_schemas {
+        tool_schemas
+            .check(
+                transport_guard.as_mut(),
+                &server_state.server_requests,
+                command,
+            )
+            .await?;
+    }
+
+    inflight.set_phase(InflightPhase::Sent);
+    let sent = std::time::Instant::now();
+    transport_guard.send(command).await?;
+    inflight.set_phase(InflightPhase::AwaitingResponse);
+    if let Some(quota) = quota {
+        quota.keep();
+    }
+
+    let elicitations_before = server_state.elicitations.last_sequence();
+    let received = async {
+        match (sink, request_id) {
+            (Some(sink), Some(request_id)) => transport::stream_response(
+                transport_guard.as_mut(),
+                &server_state.server_requests,
+                request_id,
+                timeout.message_timeout(),
+                sink,
+            )
+            .await
+            .map(|()| None),
+            _ => transport::receive_response(
+                transport_guard.as_mut(),
+                &server_state.server_requests,
+                request_id,
+                timeout.message_timeout(),
+            )
+            .await
+            .map(Some),
+        }
+    };
+    let response = tokio::select! {
+        response = received => Ok(response),
+        reason = &mut abort => Err(reason),
+    };
+    if let (Ok(_), Some(shedder)) = (&response, &server_state.load_shedder) {
+        shedder.record_latency(sent.elapsed());
+    }
+    collect_notifications(server_state, transport_guard.as_mut());
+
+    match response {
+        Ok(Err(McpCoreError::ProcessError { message })) => {
+            // Point at elicitations the server was waiting on before it went quiet
+            let elicitations = server_state.elicitations.ids_since(elicitations_before);
+            let message = if elicitations.is_empty() {
+                message
+            } else {
+                format!("{} (elicitations: {})", message, elicitations.join(", "))
+            };
+            Err(McpCoreError::ProcessError { message })
+        }
+        Ok(Ok(None)) => Ok(None),
+        Ok(response) => response.and_then(|result| {
+            let result = result.unwrap_or_default();
+            if let Some(max_bytes) = server_state
+                .max_response_bytes
+                .filter(|max_bytes| result.len() > *max_bytes)
+            {
+                return Err(response_stream::too_large(max_bytes));
+            }
+            if let Some(validator) = &server_state.response_validator {
+                validator.check(command, &result)?;
+            }
+            if let Some(tool_schemas) = &server_state.tool_schemas {
+                tool_schemas.observe_response(command, &result);
+            }
+            Ok(Some(McpResponse { result }))
+        }),
+        Err(reason) => {
+            tracing::warn!("Aborting in-flight request {}", inflight.id());
+            if let Some(request_id) = request_id {
+                let notification = serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "method": "notifications/cancelled",
+                    "params": {
+                        "requestId": request_id,
+                        "reason": reason.description()
+                    }
+                });
+                if let Err(e) = transport_guard.send(&notification.to_string()).await {
+                    tracing::error!("Failed to send cancellation notification: {}", e);
+                }
+            }
+            Err(aborted(reason))
+        }
+    }
+}
+
+/// Move the notifications buffered on the transport into the notification ring
+fn collect_notifications(server_state: &ServerState, transport: &mut dyn McpTransport) {
+    let notifications = transport.drain_notifications();
+    if let Some(tool_schemas) = &server_state.tool_schemas {
+        tool_schemas.observe_notifications(&notifications);
+    }
+    for notification in &notifications {
+        server_state.notifications.push(notification);
+    }
+}
+
+/// Describe the service and its endpoints
+async fn index(Extension(listing): Extension<Listing>) -> Json<Value> {
+    let auth_config = listing.auth.load();
+    let endpoints: Vec<Value> = listing
+        .routes
+        .iter()
+        .map(|route| {
+            serde_json::json!({
+                "method": route.method,
+                "path": route.path,
+                "description": route.description,
+            })
+        })
+        .collect();
+
+    Json(serde_json::json!({
+        "service": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "auth_required": auth_config.enabled,
+        "auth": auth::auth_status(&auth_config),
+        "port": listing.local_addr.get().map(SocketAddr::port),
+        "endpoints": endpoints,
+    }))
+}
+
+/// Routes of the listener with their authentication and streaming, see
+/// [`crate::routes`]
+async fn gateway_manifest(
+    State(server_state): State<ServerState>,
+    Extension(listing): Extension<Listing>,
+) -> Json<Value> {
+    let auth_config = listing.auth.load();
+    let routes = routes::effective(&listing.routes, auth_config.enabled);
+    Json(routes::manifest(
+        &routes,
+        auth::auth_status(&auth_config),
+        &server_state.manifest,
+    ))
+}
+
+/// Describe the MCP server behind this gateway
+async fn server_info(State(server_state): State<ServerState>) -> Json<Value> {
+    let provisioned = server_state.provisioner.provisioned();
+    let provisioned = provisioned.as_deref();
+    Json(serde_json::json!({
+        "server_name": server_state.server_name,
+        "protocol_version": provisioned.map(|provisioned| &provisioned.protocol_version),
+        "supported_protocol_versions": transport::SUPPORTED_PROTOCOL_VERSIONS,
+        "startup": provisioned.map(|provisioned| &provisioned.startup),
+        "audit": provisioned.and_then(|provisioned| provisioned.audit.as_ref()),
+        "artifact_cache": provisioned.and_then(|provisioned| provisioned.artifact_cache.as_ref()),
+        "sandbox": provisioned.and_then(|provisioned| provisioned.sandbox),
+        "egress": provisioned
+            .and_then(|provisioned| provisioned.egress.as_ref())
+            .map(|egress| egress.stats()),
+        "provisioning": server_state.provisioner.status(),
+        "maintenance": server_state.maintenance.current(),
+        "capture": server_state.captures.snapshot(chrono::Utc::now()),
+    }))
+}
+
+/// Whether the server accepts requests; `503` while drained for maintenance,
+/// not provisioned, or failing its health check, with the error of a failed
+/// handshake and the latest health check
+async fn readiness(State(server_state): State<ServerState>) -> (StatusCode, Json<Value>) {
+    let maintenance = server_state.maintenance.current();
+    let provisioning = server_state.provisioner.status();
+    let health = server_state
+        .health_checker
+        .as_ref()
+        .map(|checker| checker.snapshot());
+    let ready = maintenance.is_none()
+        && provisioning.state == "provisioned"
+        && health.as_ref().is_none_or(|health| health.healthy);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let mut body = serde_json::json!({
+        "server": server_state.server_name,
+        "ready": ready,
+        "maintenance": maintenance,
+        "provisioning": provisioning.state,
+    });
+    if let Some(health) = health {
+        body["health_check"] = serde_json::json!(health);
+    }
+    // Say which handshake failed, or point at the output of a failed clone
+    // or build
+    if let Some(handshake) = provisioning.handshake {
+        body["code"] = serde_json::json!("handshake_failed");
+        body["message"] = serde_json::json!(format!(
+            "MCP handshake failed ({}, {} attempts): {}; restart with POST /admin/servers/{}/restart",
+            handshake.failure, handshake.attempts, handshake.error, server_state.server_name
+        ));
+    } else if let Some(job) = provisioning.job.filter(|job| job.state == JobState::Failed) {
+        let mut message = format!(
+            "Setup failed: {}",
+            job.error.as_deref().unwrap_or("unknown error")
+        );
+        if server_state.build_logs.get(0).await.is_some() {
+            message.push_str(&format!(
+                "; see GET /admin/servers/{}/build-log",
+                server_state.server_name
+            ));
+        }
+        body["message"] = serde_json::json!(message);
+    }
+    (status, Json(body))
+}
+
+/// Versions of the gateway and of the MCP server behind it
+async fn version(State(server_state): State<ServerState>) -> Json<Value> {
+    let provisioned = server_state.provisioner.provisioned();
+    let provisioned = provisioned.as_deref();
+    Json(serde_json::json!({
+        "gateway": {
+            "name": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+            "git_describe": env!("MCP_GATEWAY_GIT_DESCRIBE"),
+        },
+        "server": {
+            "name": server_state.server_name,
+            "commit": provisioned.and_then(|provisioned| provisioned.commit.as_ref()),
+            "package_version": provisioned.and_then(|provisioned| provisioned.package_version.as_ref()),
+            "protocol_version": provisioned.map(|provisioned| &provisioned.protocol_version),
+        },
+    }))
+}
+
+/// Name the gateway build, the server, and its commit on every response
+async fn add_version_headers(
+    State(server_state): State<ServerState>,
+    mut response: Response,
+) -> Response {
+    let headers = response.headers_mut();
+    headers.insert(
+        header::SERVER,
+        HeaderValue::from_static(concat!("mcp-http-core/", env!("CARGO_PKG_VERSION"))),
+    );
+    if let Ok(name) = HeaderValue::from_str(&server_state.server_name) {
+        headers.insert("x-mcp-server-name", name);
+    }
+    let commit = server_state
+        .provisioner
+        .provisioned()
+        .and_then(|provisioned| provisioned.commit.clone());
+    if let Some(Ok(commit)) = commit.as_deref().map(HeaderValue::from_str) {
+        headers.insert("x-mcp-child-commit", commit);
+    }
+    response
+}
+
+/// Add the server's configured static headers to a response
+async fn add_response_headers(
+    State(headers): State<Arc<ResponseHeaders>>,
+    mut response: Response,
+) -> Response {
+    headers.apply_fixed(response.headers_mut());
+    response
+}
+
+/// Elicitations waiting for an answer
+async fn list_elicitations(State(server_state): State<ServerState>) -> Json<Value> {
+    Json(serde_json::json!({ "elicitations": server_state.elicitations.pending() }))
+}
+
+/// Stream new elicitations as server-sent `elicitation` events
+async fn elicitation_events(
+    State(server_state): State<ServerState>,
+) -> Sse<impl Stream<Item = Result<Event, std::convert::Infallible>>> {
+    let receiver = server_state.elicitations.subscribe();
+    let events = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(elicitation) => {
+                    let event = Event::default()
+                        .event("elicitation")
+                        .id(elicitation.id.clone())
+                        .data(serde_json::json!(elicitation).to_string());
+                    return Some((Ok(event), receiver));
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    tracing::warn!("Elicitation subscriber missed {} events", missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+    server_state.streams.sse(events)
+}
+
+/// Relay a client's answer to a pending elicitation
+async fn answer_elicitation(
+    State(server_state): State<ServerState>,
+    Path(id): Path<String>,
+    Json(result): Json<Value>,
+) -> Result<Json<Value>, McpCoreError> {
+    server_state.elicitations.answer(&id, result)?;
+    Ok(Json(serde_json::json!({ "id": id, "answered": true })))
+}
+
+/// Query parameters for `GET /api/v1/notifications`
+#[derive(Debug, Deserialize)]
+struct NotificationParams {
+    /// Cursor of the last notification seen; 0 returns everything buffered
+    #[serde(default)]
+    since: u64,
+
+    /// Seconds to wait for a notification when there is none after `since`
+    #[serde(default)]
+    wait: u64,
+}
+
+/// Notifications after a cursor, long-polling for the next one
+async fn poll_notifications(
+    State(server_state): State<ServerState>,
+    Query(params): Query<NotificationParams>,
+) -> Json<NotificationPage> {
+    let wait = std::time::Duration::from_secs(params.wait).min(server_state.max_notification_wait);
+    Json(
+        server_state
+            .notifications
+            .wait_since(params.since, wait)
+            .await,
+    )
+}
+
+/// Rolling request statistics of the MCP server
+async fn server_stats(State(server_state): State<ServerState>) -> Json<Value> {
+    Json(stats_body(&server_state))
+}
+
+async fn server_tool_stats(State(server_state): State<ServerState>) -> Json<Value> {
+    Json(serde_json::json!({
+        "server": server_state.server_name,
+        "tools": server_state.tool_stats.snapshot(),
+    }))
+}
+
+/// Body shared by the stats endpoints
+pub(crate) fn stats_body(server_state: &ServerState) -> Value {
+    let windows: serde_json::Map<String, Value> = server_state
+        .stats
+        .snapshot()
+        .into_iter()
+        .map(|(name, stats)| (name.to_string(), serde_json::json!(stats)))
+        .collect();
+    let provisioned = server_state.provisioner.provisioned();
+    let provisioned = provisioned.as_deref();
+    serde_json::json!({
+        "server": server_state.server_name,
+        "windows": windows,
+        "tools": server_state.tool_stats.snapshot(),
+        "startup": provisioned.map(|provisioned| &provisioned.startup),
+        "load_shedding": server_state
+            .load_shedder
+            .as_ref()
+            .map(|shedder| shedder.snapshot(server_state.inflight.queued())),
+        "queue": server_state.request_queue.snapshot(),
+        "streams": server_state.streams.snapshot(),
+        "setup": server_state.setup.snapshot(),
+        "canary": server_state.canary.snapshot(),
+        "replicas": server_state.replicas.snapshot(),
+        "lifecycle": server_state.lifecycle.as_deref(),
+        "audit": provisioned.and_then(|provisioned| provisioned.audit.as_ref()),
+        "artifact_cache": provisioned.and_then(|provisioned| provisioned.artifact_cache.as_ref()),
+        "egress": provisioned
+            .and_then(|provisioned| provisioned.egress.as_ref())
+            .map(|egress| egress.stats()),
+        "provisioning": server_state.provisioner.status(),
+        "capabilities": server_state.capabilities.as_slice(),
+        "restarts": server_state.provisioner.restarts(),
+        "restart_budget": server_state
+            .provisioner
+            .restart_budget()
+            .map(|budget| budget.snapshot()),
+        "stdin_breaker": server_state.stdin_breaker.snapshot(),
+        "recycle": server_state
+            .recycler
+            .as_ref()
+            .map(|recycler| recycler.snapshot()),
+        "health_check": server_state
+            .health_checker
+            .as_ref()
+            .map(|checker| checker.snapshot()),
+        "capture": server_state.captures.snapshot(chrono::Utc::now()),
+        "maintenance": server_state.maintenance.current(),
+        "inflight": {
+            "pending": server_state.inflight.len(),
+            "expired": server_state.inflight.expired(),
+        },
+    })
+}
+
+/// Structured 404 for unknown paths
+pub(crate) async fn not_found(uri: Uri) -> McpCoreError {
+    McpCoreError::NotFound {
+        message: format!("No route for '{}'", uri.path()),
+    }
+}
+
+/// Replace axum's empty 405 responses with a structured JSON error
+pub(crate) async fn json_method_not_allowed(response: Response) -> Response {
+    if response.status() != StatusCode::METHOD_NOT_ALLOWED {
+        return response;
+    }
+
+    let allowed_methods: Vec<String> = response
+        .headers()
+        .get(header::ALLOW)
+        .and_then(|allow| allow.to_str().ok())
+        .map(|allow| {
+            allow
+                .split(',')
+                .map(|method| method.trim().to_string())
+                .filter(|method| !method.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let body = serde_json::json!({
+        "error": "Method Not Allowed",
+        "message": format!("Allowed methods: {}", allowed_methods.join(", ")),
+        "allowed_methods": allowed_methods,
+    });
+
+    let (mut parts, _) = response.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    Response::from_parts(parts, Body::from(body.to_string()))
+}
+
+/// Create a simple health check endpoint
+pub fn create_health_router() -> Router {
+    Router::new().route("/health", get(health_check))
+}
+
+pub(crate) async fn health_check() -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "status": "healthy",
+        "service": "mcp-http-core",
+        "auth": auth::auth_status(&AuthConfig::from_env()),
+        "timestamp": chrono::Utc::now().to_rfc3339()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::balance::{Balance, ReplicaConfig};
+    use crate::breaker;
+    use crate::build_log;
+    use crate::injection::REDACTED;
+    use crate::process::McpProcess;
+    use crate::provision::{ProvisionFn, Provisioned, SetupMode, Unprovisioned};
+    use crate::routes::RouteAuth;
+    use crate::server_requests;
+    use crate::timing::PhaseTimings;
+    use axum::http::Request;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    /// Server whose MCP process is `cat`, echoing every message back
+    async fn echo_server(hooks: Hooks) -> McpHttpServer {
+        test_server("cat", &[], hooks).await
+    }
+
+    /// Server whose MCP process is the given program
+    async fn test_server(program: &str, args: &[&str], hooks: Hooks) -> McpHttpServer {
+        let mut command = tokio::process::Command::new(program);
+        command
+            .args(args)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped());
+        let mcp_process = McpProcess::spawn(command).await.unwrap();
+        let provisioned = Provisioned {
+            protocol_version: transport::SUPPORTED_PROTOCOL_VERSIONS[0].to_string(),
+            pid: None,
+            stderr: mcp_process.stderr_tail(),
+            startup: PhaseTimings::default(),
+            audit: None,
+            commit: None,
+            package_version: None,
+            artifact_cache: None,
+            sandbox: None,
+            egress: None,
+        };
+        let transport: Arc<Mutex<Box<dyn McpTransport>>> =
+            Arc::new(Mutex::new(Box::new(mcp_process)));
+
+        let auth = SharedAuth::new(
+            AuthConfig {
+                api_key: None,
+                enabled: false,
+                default_priority: RequestPriority::Normal,
+                named_keys: Vec::new(),
+            },
+            vec![Ipv4Addr::LOCALHOST.into()],
+            false,
+        )
+        .unwrap();
+        McpHttpServer {
+            auth: Arc::new(auth),
+            server_state: ServerState {
+                server_name: "echo".to_string(),
+                transport: Arc::clone(&transport),
+                param_injection: Arc::new(Vec::new()),
+                rewrite: None,
+                capabilities: Arc::default(),
+                context_meta: None,
+                response_headers: None,
+                timeouts: Arc::new(MethodTimeouts::default()),
+                command_policy: CommandPolicy::default(),
+                max_response_bytes: None,
+                hooks,
+                extensions: Arc::new(Extensions::new()),
+                server_requests: ServerRequestHandlers::default(),
+                inflight: Arc::new(InflightRegistry::default()),
+                stats: Arc::new(RequestStats::default()),
+                tool_stats: Arc::new(ToolStats::default()),
+                elicitations: Arc::new(ElicitationRegistry::default()),
+                notifications: Arc::new(NotificationRing::default()),
+                max_notification_wait: DEFAULT_MAX_NOTIFICATION_WAIT,
+                load_shedder: None,
+                request_queue: Arc::new(RequestQueue::default()),
+                id_rewriter: Some(Arc::new(IdRewriter::default())),
+                maintenance: Arc::new(Maintenance::new("echo", None, None)),
+                tool_schemas: None,
+                response_validator: None,
+                lifecycle: None,
+                provisioner: Arc::new(Provisioner::ready("echo", transport, provisioned)),
+                recycler: None,
+                health_checker: None,
+                stdin_breaker: Arc::new(StdinBreaker::default()),
+                captures: Arc::new(Captures::new("echo")),
+                client_notifications: Arc::new(NotificationAllowlist::default()),
+                notification_queue: Arc::default(),
+                configured_servers: Arc::new(HashSet::from(["echo".to_string()])),
+                work_dir_base: PathBuf::from(WORK_DIR_BASE),
+                access_log: None,
+                log_streams: Arc::new(Semaphore::new(admin::MAX_LOG_STREAMS)),
+                version_headers: true,
+                simple_mode: false,
+                manifest: Arc::new(ManifestConfig::default()),
+                presets: Arc::new(Presets::default()),
+                pipeline: Arc::new(Pipeline::default()),
+                strict: false,
+                streams: Arc::new(Streams::default()),
+                setup: Arc::new(SetupExecutor::default()),
+                build_logs: Arc::new(BuildLogs::new(PathBuf::from(WORK_DIR_BASE).join("echo"))),
+                server_hooks: Arc::new(ServerHooks::default()),
+                quotas: Arc::new(Quotas::default()),
+                canary: Arc::new(CanaryRouter::default()),
+                replicas: Arc::new(ReplicaPool::default()),
+                shutdown: ShutdownConfig::default(),
+                background: Arc::new(BackgroundTasks::default()),
+            },
+            bind_host: Ipv4Addr::LOCALHOST.into(),
+            port_fallback: false,
+            local_addr: Arc::new(OnceLock::new()),
+            path_prefix: String::new(),
+            listeners: Vec::new(),
+            http: HttpConfig::default(),
+        }
+    }
+
+    /// Require `key` as the default API key
+    fn require_key(server: &McpHttpServer, key: &str) {
+        server
+            .auth()
+            .replace(AuthConfig {
+                api_key: Some(key.to_string()),
+                enabled: true,
+                default_priority: RequestPriority::Normal,
+                named_keys: Vec::new(),
+            })
+            .unwrap();
+    }
+
+    async fn post_command(router: Router, command: Value) -> (StatusCode, Value) {
+        post_raw_command(router, &command.to_string()).await
+    }
+
+    async fn post_raw_command(router: Router, command: &str) -> (StatusCode, Value) {
+        let body = serde_json::json!({ "command": command });
+        let request = Request::post("/api/v1")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        send(router, request).await
+    }
+
+    async fn send(router: Router, request: Request<Body>) -> (StatusCode, Value) {
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_response_hook_mutation_visible_to_client() {
+        let mut hooks = Hooks::default();
+        hooks.on_response.push(hooks::response_hook(
+            |mut message: Value, context: RequestContext| async move {
+                assert_eq!(context.server_name, "echo");
+                assert_eq!(context.method.as_deref(), Some("tools/list"));
+                // Hooks may await, e.g. a remote policy check
+                tokio::task::yield_now().await;
+                if let Some(params) = message.get_mut("params").and_then(Value::as_object_mut) {
+                    params.remove("internal");
+                }
+                message
+            },
+        ));
+        let router = echo_server(hooks).await.create_router();
+
+        let command = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "tools/list",
+            "params": { "internal": "secret", "visible": true }
+        });
+        let (status, body) = post_command(router, command).await;
+
+        assert_eq!(status, StatusCode::OK);
+        let result: Value = serde_json::from_str(body["result"].as_str().unwrap()).unwrap();
+        assert!(result["params"].get("internal").is_none());
+        assert_eq!(result["params"]["visible"], true);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_extensions_reach_hooks_and_embedder() {
+        #[derive(Clone)]
+        struct Owners(Arc<HashMap<&'static str, &'static str>>);
+
+        let mut hooks = Hooks::default();
+        hooks.on_response.push(hooks::response_hook(
+            |mut message: Value, context: RequestContext| async move {
+                let owners = context.extension::<Owners>().expect("registered");
+                let tool = message.pointer("/params/name").and_then(Value::as_str);
+                if let Some(owner) = tool.and_then(|tool| owners.0.get(tool)) {
+                    message["owner"] = Value::from(*owner);
+                }
+                message
+            },
+        ));
+        let mut server = echo_server(hooks).await;
+        let mut extensions = Extensions::new();
+        extensions.insert(Owners(Arc::new(HashMap::from([("search", "data-team")]))));
+        server.server_state.extensions = Arc::new(extensions);
+
+        assert!(server.extension::<Owners>().is_some());
+        assert!(server.extension::<String>().is_none());
+
+        let command = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "tools/call",
+            "params": { "name": "search", "arguments": {} }
+        });
+        let (status, body) = post_command(server.create_router(), command).await;
+        assert_eq!(status, StatusCode::OK);
+        let result: Value = serde_json::from_str(body["result"].as_str().unwrap()).unwrap();
+        assert_eq!(result["owner"], "data-team");
+    }
+
+    /// Server answering `fail` with an error and anything else with a result,
+    /// both carrying vendor fields
+    async fn vendor_server(hooks: Hooks) -> McpHttpServer {
+        let script = r#"while read request; do
+            id=$(echo "$request" | sed -n 's/.*"id":\("[^"]*"\|[0-9]*\).*/\1/p')
+            case "$request" in
+                *'"method":"fail"'*)
+                    printf '{"jsonrpc":"2.0","id":%s,"error":{"code":-32000,"message":"nope","x-detail":{"z":1,"a":2}},"x-vendor":"v"}\n' "$id" ;;
+                *)
+                    printf '{"jsonrpc":"2.0","id":%s,"result":{"z":1,"a":[true,null]},"x-vendor":{"b":1,"a":2},"_trace":"t1"}\n' "$id" ;;
+            esac
+        done"#;
+        test_server("sh", &["-c", script], hooks).await
+    }
+
+    /// Hooks that parse and re-serialize every response without changing it
+    fn passthrough_hooks() -> Hooks {
+        let mut hooks = Hooks::default();
+        hooks
+            .on_response
+            .push(hooks::response_hook(|message, _| async move { message }));
+        hooks
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_extension_fields_round_trip() {
+        // The echoed request keeps its fields and their order through id
+        // rewriting, context injection, and response hooks
+        let mut echo = echo_server(passthrough_hooks()).await;
+        echo.server_state.context_meta = Some(Arc::new(ContextMetaConfig::default()));
+        let command = r#"{"jsonrpc":"2.0","x-first":1,"id":7,"method":"tools/list","params":{"cursor":"c","_vendor":{"z":0,"a":1}},"vendor_ext":"v"}"#;
+        let pretty =
+            serde_json::to_string_pretty(&serde_json::from_str::<Value>(command).unwrap()).unwrap();
+        let (status, body) = post_raw_command(echo.create_router(), &pretty).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["result"], command);
+
+        let router = vendor_server(passthrough_hooks()).await.create_router();
+        let (status, body) =
+            post_raw_command(router.clone(), r#"{"jsonrpc":"2.0","id":3,"method":"ok"}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["result"],
+            r#"{"jsonrpc":"2.0","id":3,"result":{"z":1,"a":[true,null]},"x-vendor":{"b":1,"a":2},"_trace":"t1"}"#
+        );
+
+        let (status, body) =
+            post_raw_command(router, r#"{"jsonrpc":"2.0","id":"e","method":"fail"}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["result"],
+            r#"{"jsonrpc":"2.0","id":"e","error":{"code":-32000,"message":"nope","x-detail":{"z":1,"a":2}},"x-vendor":"v"}"#
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_random_extension_fields_survive() {
+        // xorshift, seeded for reproducible cases
+        let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+        let mut next = move |bound: u64| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state % bound
+        };
+        fn random_value(next: &mut impl FnMut(u64) -> u64, depth: u32) -> Value {
+            match next(if depth > 2 { 5 } else { 7 }) {
+                0 => Value::Null,
+                1 => Value::Bool(next(2) == 0),
+                2 => Value::from(next(1_000_000) as i64 - 500_000),
+                3 => Value::from(next(1000) as f64 / 8.0),
+                4 => Value::from(format!("s{}\u{e9}\"\\{}", next(100), next(100))),
+                5 => (0..next(4))
+                    .map(|_| random_value(next, depth + 1))
+                    .collect(),
+                _ => Value::Object(
+                    (0..next(4))
+                        .map(|i| (format!("k{}{}", next(50), i), random_value(next, depth + 1)))
+                        .collect(),
+                ),
+            }
+        }
+
+        let mut server = echo_server(passthrough_hooks()).await;
+        server.server_state.context_meta = Some(Arc::new(ContextMetaConfig::default()));
+        let router = server.create_router();
+        for case in 0..32 {
+            let mut message = serde_json::Map::new();
+            let mut fields = vec![
+                ("jsonrpc".to_string(), Value::from("2.0")),
+                ("id".to_string(), Value::from(case)),
+                ("method".to_string(), Value::from("vendor/call")),
+                // Present, so the injected context leaves nothing behind
+                ("params".to_string(), serde_json::json!({ "x-param": case })),
+            ];
+            for i in 0..1 + next(5) {
+                let key = format!("x-{}-{}", next(1000), i);
+                fields.insert(
+                    next(fields.len() as u64 + 1) as usize,
+                    (key, random_value(&mut next, 0)),
+                );
+            }
+            message.extend(fields);
+            let command = Value::Object(message).to_string();
+
+            let (status, body) = post_raw_command(router.clone(), &command).await;
+            assert_eq!(status, StatusCode::OK, "{}", command);
+            assert_eq!(body["result"], command);
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_request_hook_error_short_circuits() {
+        let mut hooks = Hooks::default();
+        hooks.on_request.push(hooks::request_hook(|_, _| async {
+            tokio::task::yield_now().await;
+            Err(HookError::new(StatusCode::FORBIDDEN, "Method not allowed"))
+        }));
+        let router = echo_server(hooks).await.create_router();
+
+        let command = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/list" });
+        let (status, body) = post_command(router, command).await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(body["message"]
+            .as_str()
+            .unwrap()
+            .contains("Method not allowed"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_rewrite_rules_apply_before_checks() {
+        let mut hooks = Hooks::default();
+        hooks
+            .on_request
+            .push(hooks::request_hook(|message: Value, _| async move {
+                if message["params"]["name"] == "drop_db" {
+                    return Err(HookError::new(StatusCode::FORBIDDEN, "Tool not allowed"));
+                }
+                Ok(message)
+            }));
+        let mut server = echo_server(hooks).await;
+        server.server_state.rewrite = Some(Arc::new(
+            serde_json::from_value(serde_json::json!({
+                "aliases": [
+                    { "from": "search", "to": "tools/call", "params": { "name": "search" } },
+                    { "from": "drop", "to": "tools/call", "params": { "name": "drop_db" } }
+                ],
+                "moves": [{ "methods": ["search"], "from": "/params/q", "to": "/params/arguments/query" }],
+                "defaults": [{ "methods": ["tools/call"], "params": { "arguments": { "limit": 10 } } }]
+            }))
+            .unwrap(),
+        ));
+        let router = server.create_router();
+
+        // The echo server answers with the message it was sent
+        let search = serde_json::json!({
+            "jsonrpc": "2.0", "id": 1, "method": "search", "params": { "q": "rust" }
+        });
+        let (status, body) = post_command(router.clone(), search).await;
+        assert_eq!(status, StatusCode::OK);
+        let forwarded: Value = serde_json::from_str(body["result"].as_str().unwrap()).unwrap();
+        assert_eq!(forwarded["method"], "tools/call");
+        assert_eq!(
+            forwarded["params"],
+            serde_json::json!({ "name": "search", "arguments": { "query": "rust", "limit": 10 } })
+        );
+
+        // Hooks see the rewritten request
+        let drop = serde_json::json!({ "jsonrpc": "2.0", "id": 2, "method": "drop" });
+        let (status, _) = post_command(router, drop).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_validate_matches_real_request_outcomes() {
+        let mut hooks = Hooks::default();
+        hooks
+            .on_request
+            .push(hooks::request_hook(|message: Value, _| async move {
+                if message["params"]["name"] == "drop_db" {
+                    return Err(HookError::new(StatusCode::FORBIDDEN, "Tool not allowed"));
+                }
+                Ok(message)
+            }));
+        let mut server = echo_server(hooks).await;
+        server.server_state.command_policy.max_bytes = 256;
+        server.server_state.param_injection =
+            Arc::new(vec![serde_json::from_value(serde_json::json!({
+                "header": "x-tenant",
+                "json_pointer": "/params/tenant",
+                "methods": ["tools/call"],
+            }))
+            .unwrap()]);
+        let router = server.create_router();
+
+        let call = |name: &str| {
+            serde_json::json!({
+                "jsonrpc": "2.0", "id": 1, "method": "tools/call", "params": { "name": name }
+            })
+            .to_string()
+        };
+        let cases = vec![
+            (
+                r#"{"jsonrpc":"2.0","id":1,"method":"tools/list"}"#.to_string(),
+                Vec::new(),
+                None,
+            ),
+            (call("search"), vec![("x-tenant", "acme")], None),
+            (call("search"), Vec::new(), Some("invalid_request")),
+            (
+                call("drop_db"),
+                vec![("x-tenant", "acme")],
+                Some("rejected_by_hook"),
+            ),
+            ("not json".to_string(), Vec::new(), Some("invalid_json")),
+            (r#"{"id":1}"#.to_string(), Vec::new(), Some("not_jsonrpc")),
+            (
+                call(&"x".repeat(300)),
+                Vec::new(),
+                Some("command_too_large"),
+            ),
+            (
+                r#"{"jsonrpc":"2.0","id":1,"method":"tools/list"}"#.to_string(),
+                vec![(crate::priority::PRIORITY_HEADER, "urgent")],
+                Some("invalid_request"),
+            ),
+            (
+                r#"{"jsonrpc":"2.0","id":1,"method":"tools/list"}"#.to_string(),
+                vec![(crate::method_timeout::TIMEOUT_HEADER, "0")],
+                Some("invalid_request"),
+            ),
+        ];
+
+        for (command, headers, expected_code) in cases {
+            let request = |path: &str| {
+                let mut request = Request::post(path).header("content-type", "application/json");
+                for (name, value) in &headers {
+                    request = request.header(*name, *value);
+                }
+                request
+                    .body(Body::from(
+                        serde_json::json!({ "command": command }).to_string(),
+                    ))
+                    .unwrap()
+            };
+            let (status, report) = send(router.clone(), request("/api/v1/validate")).await;
+            assert_eq!(status, StatusCode::OK);
+            let (real_status, real_body) = send(router.clone(), request("/api/v1")).await;
+
+            match expected_code {
+                None => {
+                    assert_eq!(report["accepted"], true, "{}", command);
+                    assert_eq!(real_status, StatusCode::OK, "{}", command);
+                }
+                Some(code) => {
+                    assert_eq!(report["accepted"], false, "{}", command);
+                    let violation = &report["violations"][0];
+                    assert_eq!(violation["code"], code);
+                    assert_eq!(violation["status"], real_status.as_u16(), "{}", command);
+                    assert_eq!(violation["message"], real_body["message"]);
+                }
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_clients_reusing_ids_get_their_own_responses() {
+        // A server that answers each request late: first the previous request
+        // again, then the current one
+        let script = r#"prev=""; while read request; do
+            id=$(echo "$request" | sed -n 's/.*"id":\([0-9]*\).*/\1/p')
+            client=$(echo "$request" | sed -n 's/.*"client":"\([a-z]*\)".*/\1/p')
+            [ -n "$prev" ] && echo "$prev"
+            prev="{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"client\":\"$client\"}}"
+            echo "$prev"
+        done"#;
+        let call = |client: &str| {
+            serde_json::json!({
+                "jsonrpc": "2.0", "id": 1, "method": "tools/call", "params": { "client": client }
+            })
+        };
+
+        let router = test_server("sh", &["-c", script], Hooks::default())
+            .await
+            .create_router();
+        let (first, second) = tokio::join!(
+            post_command(router.clone(), call("a")),
+            post_command(router.clone(), call("b")),
+        );
+        for ((status, body), client) in [(first, "a"), (second, "b")] {
+            assert_eq!(status, StatusCode::OK);
+            let response: Value = serde_json::from_str(body["result"].as_str().unwrap()).unwrap();
+            assert_eq!(response["id"], 1);
+            assert_eq!(response["result"]["client"], client);
+        }
+        for client in ["a", "b"] {
+            let (_, body) = post_command(router.clone(), call(client)).await;
+            let response: Value = serde_json::from_str(body["result"].as_str().unwrap()).unwrap();
+            assert_eq!(response["id"], 1);
+            assert_eq!(response["result"]["client"], client);
+        }
+
+        // Without rewriting the late answer to "a" is taken for "b"'s
+        let mut server = test_server("sh", &["-c", script], Hooks::default()).await;
+        server.server_state.id_rewriter = None;
+        let router = server.create_router();
+        post_command(router.clone(), call("a")).await;
+        let (_, body) = post_command(router, call("b")).await;
+        let response: Value = serde_json::from_str(body["result"].as_str().unwrap()).unwrap();
+        assert_eq!(response["result"]["client"], "a");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_batch_ids_round_trip() {
+        let router = echo_server(Hooks::default()).await.create_router();
+        let batch = serde_json::json!([
+            { "jsonrpc": "2.0", "id": "first", "method": "tools/list" },
+            { "jsonrpc": "2.0", "id": 7, "method": "prompts/list" },
+            { "jsonrpc": "2.0", "id": null, "method": "resources/list" },
+            { "jsonrpc": "2.0", "method": "notifications/initialized" },
+        ]);
+
+        let (status, body) = post_command(router, batch.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        let echoed: Value = serde_json::from_str(body["result"].as_str().unwrap()).unwrap();
+        assert_eq!(echoed, batch);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_presets_listed_and_sent_through_pipeline() {
+        let mut server = echo_server(Hooks::default()).await;
+        let presets: HashMap<String, crate::presets::Preset> =
+            serde_json::from_value(serde_json::json!({
+                "list-open-issues": {
+                    "method": "tools/call",
+                    "params": { "name": "search_issues", "arguments": { "status": "open" } }
+                }
+            }))
+            .unwrap();
+        server.server_state.presets = Arc::new(Presets::new(&presets));
+        let router = server.create_router();
+
+        let request = Request::get("/api/v1/presets").body(Body::empty()).unwrap();
+        let (status, body) = send(router.clone(), request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["presets"][0]["name"], "list-open-issues");
+        assert_eq!(
+            body["presets"][0]["slots"],
+            serde_json::json!(["/name", "/arguments/status"])
+        );
+
+        // The echo child shows the request the preset turned into
+        let request = Request::post("/api/v1/presets/list-open-issues")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                r#"{"arguments": {"status": "closed", "page": 2}}"#,
+            ))
+            .unwrap();
+        let (status, body) = send(router.clone(), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let echoed: Value = serde_json::from_str(body["result"].as_str().unwrap()).unwrap();
+        assert_eq!(echoed["method"], "tools/call");
+        assert_eq!(echoed["id"], "preset-1");
+        assert_eq!(
+            echoed["params"],
+            serde_json::json!({
+                "name": "search_issues",
+                "arguments": { "status": "closed", "page": 2 }
+            })
+        );
+
+        let request = Request::post("/api/v1/presets/list-open-issues")
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = send(router.clone(), request).await;
+        assert_eq!(status, StatusCode::OK);
+        let echoed: Value = serde_json::from_str(body["result"].as_str().unwrap()).unwrap();
+        assert_eq!(echoed["params"]["arguments"]["status"], "open");
+
+        let request = Request::post("/api/v1/presets/missing")
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = send(router.clone(), request).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body["message"]
+            .as_str()
+            .unwrap()
+            .contains("No preset 'missing'"));
+        let request = Request::post("/api/v1/presets/list-open-issues")
+            .body(Body::from("[1]"))
+            .unwrap();
+        let (status, _) = send(router, request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_embedded_newline_does_not_desync_later_requests() {
+        let router = echo_server(Hooks::default()).await.create_router();
+
+        // Two messages in one command would leave an extra response queued
+        let smuggled = concat!(
+            r#"{"jsonrpc":"2.0","id":1,"method":"tools/list"}"#,
+            "\n",
+            r#"{"jsonrpc":"2.0","id":2,"method":"tools/list"}"#
+        );
+        let (status, body) = post_raw_command(router.clone(), smuggled).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "invalid_json");
+
+        // A pretty-printed single message is forwarded as one line
+        let pretty = "{\n  \"jsonrpc\": \"2.0\",\n  \"id\": 3,\n  \"method\": \"ping\"\n}";
+        let (status, body) = post_raw_command(router.clone(), pretty).await;
+        assert_eq!(status, StatusCode::OK);
+        let result: Value = serde_json::from_str(body["result"].as_str().unwrap()).unwrap();
+        assert_eq!(result["id"], 3);
+
+        // The next unrelated request gets its own response
+        let command = serde_json::json!({ "jsonrpc": "2.0", "id": 4, "method": "tools/list" });
+        let (status, body) = post_command(router, command).await;
+        assert_eq!(status, StatusCode::OK);
+        let result: Value = serde_json::from_str(body["result"].as_str().unwrap()).unwrap();
+        assert_eq!(result["id"], 4);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_non_jsonrpc_command_rejected() {
+        let router = echo_server(Hooks::default()).await.create_router();
+
+        let (status, body) = post_raw_command(router, "not json").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "invalid_json");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_server_initiated_request_is_answered() {
+        // A server that asks for roots, then answers with the client's reply embedded
+        let script = r#"read request; echo '{"jsonrpc":"2.0","id":"srv-1","method":"roots/list"}'; read reply; echo "{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":{\"reply\":$reply}}""#;
+        let router = test_server("sh", &["-c", script], Hooks::default())
+            .await
+            .create_router();
+
+        let (status, body) = post_command(
+            router,
+            serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/list" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let response: Value = serde_json::from_str(body["result"].as_str().unwrap()).unwrap();
+        let reply = &response["result"]["reply"];
+        assert_eq!(reply["id"], "srv-1");
+        assert_eq!(reply["result"]["roots"], serde_json::json!([]));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_interleaved_notifications_do_not_shift_responses() {
+        let server = notifying_server().await;
+        let transport = server.server_state.transport.clone();
+        let notifications = server.server_state.notifications.clone();
+        let router = server.create_router();
+
+        for id in 1..=3 {
+            let (status, body) = post_command(
+                router.clone(),
+                serde_json::json!({ "jsonrpc": "2.0", "id": id, "method": "tools/list" }),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            let response: Value = serde_json::from_str(body["result"].as_str().unwrap()).unwrap();
+            assert_eq!(response["id"], id);
+        }
+
+        let page = notifications.since(0);
+        assert_eq!(page.notifications.len(), 3);
+        assert_eq!(
+            page.notifications[0].notification["method"],
+            "notifications/message"
+        );
+        assert!(transport.lock().await.drain_notifications().is_empty());
+    }
+
+    /// Server that emits a notification before every response
+    async fn notifying_server() -> McpHttpServer {
+        let script = r#"while read request; do
+            id=$(echo "$request" | sed -n 's/.*"id":\([0-9]*\).*/\1/p')
+            echo '{"jsonrpc":"2.0","method":"notifications/message","params":{"level":"info"}}'
+            echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{}}"
+        done"#;
+        test_server("sh", &["-c", script], Hooks::default()).await
+    }
+
+    async fn get_notifications(router: Router, query: &str) -> Value {
+        let request = Request::get(format!("/api/v1/notifications?{}", query))
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = send(router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        body
+    }
+
+    async fn list_tools(router: Router, id: u64) {
+        let command = serde_json::json!({ "jsonrpc": "2.0", "id": id, "method": "tools/list" });
+        let (status, _) = post_command(router, command).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_notifications_returned_immediately() {
+        let router = notifying_server().await.create_router();
+        list_tools(router.clone(), 1).await;
+
+        let started = std::time::Instant::now();
+        let body = get_notifications(router, "since=0&wait=25").await;
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        assert_eq!(body["cursor"], 1);
+        assert_eq!(body["gap"], false);
+        assert_eq!(body["notifications"][0]["seq"], 1);
+        assert_eq!(
+            body["notifications"][0]["notification"]["method"],
+            "notifications/message"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_notifications_wait_for_next_event() {
+        let router = notifying_server().await.create_router();
+        list_tools(router.clone(), 1).await;
+
+        let poll = tokio::spawn(get_notifications(router.clone(), "since=1&wait=25"));
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(!poll.is_finished());
+        list_tools(router, 2).await;
+
+        let body = tokio::time::timeout(std::time::Duration::from_secs(5), poll)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(body["cursor"], 2);
+        assert_eq!(body["notifications"].as_array().unwrap().len(), 1);
+        assert_eq!(body["notifications"][0]["seq"], 2);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_notifications_wait_capped_and_empty_on_timeout() {
+        let mut server = notifying_server().await;
+        server.server_state.max_notification_wait = std::time::Duration::from_millis(200);
+        let router = server.create_router();
+
+        let started = std::time::Instant::now();
+        let body = get_notifications(router, "since=0&wait=25").await;
+        assert!(started.elapsed() >= std::time::Duration::from_millis(200));
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        assert_eq!(body["cursor"], 0);
+        assert_eq!(body["gap"], false);
+        assert_eq!(body["notifications"], serde_json::json!([]));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_notifications_report_gap() {
+        let mut server = notifying_server().await;
+        server.server_state.notifications = Arc::new(NotificationRing::new(1));
+        let router = server.create_router();
+        list_tools(router.clone(), 1).await;
+        list_tools(router.clone(), 2).await;
+
+        let body = get_notifications(router, "since=0").await;
+        assert_eq!(body["gap"], true);
+        assert_eq!(body["cursor"], 2);
+        assert_eq!(body["notifications"].as_array().unwrap().len(), 1);
+        assert_eq!(body["notifications"][0]["seq"], 2);
+    }
+
+    /// Server that elicits before answering, embedding the client's reply
+    async fn eliciting_server(registry: ElicitationRegistry) -> McpHttpServer {
+        let script = r#"read request; echo '{"jsonrpc":"2.0","id":"srv-1","method":"elicitation/create","params":{"message":"Name?","requestedSchema":{"type":"object"}}}'; read reply; echo "{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":{\"reply\":$reply}}""#;
+        let mut server = test_server("sh", &["-c", script], Hooks::default()).await;
+        let registry = Arc::new(registry);
+        server.server_state.server_requests.elicitation = Some(registry.handler());
+        server.server_state.elicitations = registry;
+        server
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_elicitation_answered_over_http() {
+        let router = eliciting_server(ElicitationRegistry::default())
+            .await
+            .create_router();
+        let tool_call = tokio::spawn(post_command(
+            router.clone(),
+            serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/call" }),
+        ));
+
+        let pending = loop {
+            let request = Request::get("/api/v1/elicitations")
+                .body(Body::empty())
+                .unwrap();
+            let (_, body) = send(router.clone(), request).await;
+            if let Some(pending) = body["elicitations"].as_array().and_then(|p| p.first()) {
+                break pending.clone();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        assert_eq!(pending["params"]["message"], "Name?");
+
+        let answer = serde_json::json!({ "action": "accept", "content": { "name": "Ada" } });
+        let request = Request::post(format!(
+            "/api/v1/elicitations/{}",
+            pending["id"].as_str().unwrap()
+        ))
+        .header("content-type", "application/json")
+        .body(Body::from(answer.to_string()))
+        .unwrap();
+        let (status, _) = send(router.clone(), request).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = tool_call.await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        let response: Value = serde_json::from_str(body["result"].as_str().unwrap()).unwrap();
+        assert_eq!(response["result"]["reply"]["id"], "srv-1");
+        assert_eq!(response["result"]["reply"]["result"], answer);
+
+        let request = Request::post("/api/v1/elicitations/elicit-99")
+            .header("content-type", "application/json")
+            .body(Body::from(answer.to_string()))
+            .unwrap();
+        let (status, _) = send(router, request).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unanswered_elicitation_declined() {
+        let router = eliciting_server(ElicitationRegistry::new(Duration::from_millis(50)))
+            .await
+            .create_router();
+
+        let (status, body) = post_command(
+            router,
+            serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/call" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let response: Value = serde_json::from_str(body["result"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            response["result"]["reply"]["result"],
+            serde_json::json!({ "action": "decline" })
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_configured_roots_returned_to_server() {
+        let script = r#"read request; echo '{"jsonrpc":"2.0","id":"srv-1","method":"roots/list"}'; read reply; echo "{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":{\"reply\":$reply}}""#;
+        let mut server = test_server("sh", &["-c", script], Hooks::default()).await;
+        let roots: Vec<crate::config::RootConfig> = serde_json::from_value(serde_json::json!([
+            { "uri": "file:///data/projects", "name": "Projects" }
+        ]))
+        .unwrap();
+        server.server_state.server_requests.roots = Some(server_requests::static_roots(&roots));
+
+        let (status, body) = post_command(
+            server.create_router(),
+            serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/list" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let response: Value = serde_json::from_str(body["result"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            response["result"]["reply"]["result"]["roots"],
+            serde_json::json!([{ "uri": "file:///data/projects", "name": "Projects" }])
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unknown_request_keys_rejected_in_strict_mode() {
+        let body = serde_json::json!({
+            "command": r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#,
+            "comand": "typo"
+        });
+        let request = || {
+            Request::post("/api/v1")
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        let router = echo_server(Hooks::default()).await.create_router();
+        let (status, _) = send(router, request()).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let mut server = echo_server(Hooks::default()).await;
+        server.server_state.strict = true;
+        let router = server.create_router();
+        let (status, body) = send(router.clone(), request()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["message"]
+            .as_str()
+            .unwrap()
+            .contains("'comand' (did you mean 'command'?)"));
+
+        // A body without a command is malformed, strict or not
+        let request = Request::post("/api/v1")
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"comand": "typo"}"#))
+            .unwrap();
+        let (status, body) = send(router, request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "malformed_body");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_request_content_types() {
+        let router = echo_server(Hooks::default()).await.create_router();
+        let command = serde_json::json!({
+            "command": r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#
+        })
+        .to_string();
+        let request = |content_type: Option<&str>, body: &str| {
+            let mut request = Request::post("/api/v1");
+            if let Some(content_type) = content_type {
+                request = request.header("content-type", content_type);
+            }
+            request.body(Body::from(body.to_string())).unwrap()
+        };
+
+        for content_type in [
+            "application/json",
+            "application/json; charset=UTF-8",
+            "Application/JSON;charset=\"utf-8\"",
+            "application/vnd.mcp+json",
+        ] {
+            let response = router
+                .clone()
+                .oneshot(request(Some(content_type), &command))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{}", content_type);
+            assert!(!response.headers().contains_key(header::WARNING));
+        }
+
+        // A JSON body without a Content-Type passes with a warning
+        let response = router
+            .clone()
+            .oneshot(request(None, &command))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::WARNING],
+            MISSING_CONTENT_TYPE_WARNING
+        );
+        let (status, body) = send(router.clone(), request(None, "command=ping")).await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert!(body.get("content_type").is_none());
+
+        for content_type in ["text/plain", "application/json; charset=latin1"] {
+            let (status, body) = send(router.clone(), request(Some(content_type), &command)).await;
+            assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            assert_eq!(body["code"], "unsupported_media_type");
+            assert_eq!(body["content_type"], content_type);
+        }
+
+        let (status, body) = send(
+            router,
+            request(Some("application/json"), "{\n  \"command\": }"),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "malformed_body");
+        assert_eq!(
+            (body["line"].as_u64(), body["column"].as_u64()),
+            (Some(2), Some(14))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_info_reports_protocol_version() {
+        let router = echo_server(Hooks::default()).await.create_router();
+
+        let request = Request::get("/api/v1/info").body(Body::empty()).unwrap();
+        let (status, body) = send(router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["server_name"], "echo");
+        assert_eq!(
+            body["protocol_version"],
+            transport::SUPPORTED_PROTOCOL_VERSIONS[0]
+        );
+        assert!(body["startup"]["phases"].is_array());
+    }
+
+    #[tokio::test]
+    async fn test_version_endpoint_and_headers() {
+        let mut server = echo_server(Hooks::default()).await;
+        let mut provisioned = (*server.server_state.provisioner.provisioned().unwrap()).clone();
+        provisioned.commit = Some("0123456789abcdef0123456789abcdef01234567".to_string());
+        provisioned.package_version = Some("1.4.2".to_string());
+        server.server_state.provisioner = Arc::new(Provisioner::ready(
+            "echo",
+            Arc::clone(&server.server_state.transport),
+            provisioned,
+        ));
+        let state = server.server_state.clone();
+        let router = server.create_router();
+
+        let request = Request::get("/version").body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let headers = response.headers();
+        assert_eq!(
+            headers[header::SERVER],
+            format!("mcp-http-core/{}", env!("CARGO_PKG_VERSION"))
+        );
+        assert_eq!(headers["x-mcp-server-name"], "echo");
+        assert_eq!(
+            headers["x-mcp-child-commit"],
+            "0123456789abcdef0123456789abcdef01234567"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["gateway"]["version"], env!("CARGO_PKG_VERSION"));
+        assert!(!body["gateway"]["git_describe"].as_str().unwrap().is_empty());
+        assert_eq!(body["server"]["package_version"], "1.4.2");
+        assert_eq!(
+            body["server"]["protocol_version"],
+            transport::SUPPORTED_PROTOCOL_VERSIONS[0]
+        );
+
+        // Error responses carry the headers too, unless they are hidden
+        let request = Request::get("/missing").body(Body::empty()).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()["x-mcp-server-name"], "echo");
+
+        let mut hidden = echo_server(Hooks::default()).await;
+        hidden.server_state = ServerState {
+            version_headers: false,
+            ..state
+        };
+        let request = Request::get("/version").body(Body::empty()).unwrap();
+        let response = hidden.create_router().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(header::SERVER).is_none());
+        assert!(response.headers().get("x-mcp-child-commit").is_none());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_accept_text_plain_returns_tool_text() {
+        let script = r#"while read request; do
+            id=$(echo "$request" | sed -n 's/.*"id":\([0-9]*\).*/\1/p')
+            echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"content\":[{\"type\":\"text\",\"text\":\"hello\"},{\"type\":\"text\",\"text\":\"world\"}]}}"
+        done"#;
+        let router = test_server("sh", &["-c", script], Hooks::default())
+            .await
+            .create_router();
+        let command = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/call" });
+        let body = serde_json::json!({ "command": command.to_string() });
+
+        for (accept, content_type, expected) in [
+            ("text/plain", "text/plain; charset=utf-8", "hello\nworld"),
+            (
+                "application/x-ndjson",
+                "application/x-ndjson",
+                "{\"type\":\"text\",\"text\":\"hello\"}\n{\"type\":\"text\",\"text\":\"world\"}\n",
+            ),
+        ] {
+            let request = Request::post("/api/v1")
+                .header("content-type", "application/json")
+                .header("accept", accept)
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[header::CONTENT_TYPE], content_type);
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(String::from_utf8(bytes.to_vec()).unwrap(), expected);
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stats_count_requests_and_errors() {
+        let router = echo_server(Hooks::default()).await.create_router();
+
+        post_command(
+            router.clone(),
+            serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/list" }),
+        )
+        .await;
+        post_raw_command(router.clone(), "not json").await;
+
+        let request = Request::get("/api/v1/stats").body(Body::empty()).unwrap();
+        let (status, body) = send(router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        let minute = &body["windows"]["1m"];
+        assert_eq!(minute["requests"], 2);
+        assert_eq!(minute["errors"]["client"], 1);
+        assert!(minute["bytes_out"].as_u64().unwrap() > 0);
+        assert!(minute["latency_ms"]["p50"].is_number());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_higher_priority_requests_are_served_first() {
+        // A server answering each request after 100ms
+        let script = "while read line; do sleep 0.1; echo \"$line\"; done";
+        let router = test_server("sh", &["-c", script], Hooks::default())
+            .await
+            .create_router();
+
+        let (order_tx, mut order) = tokio::sync::mpsc::unbounded_channel();
+        for (id, priority) in [(1, "normal"), (2, "low"), (3, "normal"), (4, "high")] {
+            let command = serde_json::json!({ "jsonrpc": "2.0", "id": id, "method": "tools/list" });
+            let body = serde_json::json!({ "command": command.to_string() });
+            let request = Request::post("/api/v1")
+                .header("content-type", "application/json")
+                .header(crate::priority::PRIORITY_HEADER, priority)
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            let router = router.clone();
+            let order_tx = order_tx.clone();
+            tokio::spawn(async move {
+                let response = router.oneshot(request).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                order_tx.send(id).unwrap();
+            });
+            // Queue each request before the next arrives
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let mut served = Vec::new();
+        for _ in 0..4 {
+            served.push(order.recv().await.unwrap());
+        }
+        assert_eq!(served, [1, 4, 3, 2]);
+
+        let request = Request::get("/api/v1/stats").body(Body::empty()).unwrap();
+        let (_, body) = send(router.clone(), request).await;
+        assert_eq!(body["queue"]["high"]["dequeued"], 1);
+        assert_eq!(body["queue"]["normal"]["dequeued"], 2);
+        assert_eq!(body["queue"]["low"]["dequeued"], 1);
+        assert_eq!(body["queue"]["low"]["queued"], 0);
+
+        let command = serde_json::json!({ "jsonrpc": "2.0", "id": 5, "method": "tools/list" });
+        let request = Request::post("/api/v1")
+            .header("content-type", "application/json")
+            .header(crate::priority::PRIORITY_HEADER, "urgent")
+            .body(Body::from(
+                serde_json::json!({ "command": command.to_string() }).to_string(),
+            ))
+            .unwrap();
+        let (status, _) = send(router, request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    /// `POST /api/v1` of `command`, returning the status and raw body
+    async fn post_notification(router: Router, command: Value) -> (StatusCode, String) {
+        let body = serde_json::json!({ "command": command.to_string() });
+        let request = Request::post("/api/v1")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(bytes.to_vec()).unwrap())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_notifications_accepted_without_waiting_for_the_server() {
+        let mut server = echo_server(Hooks::default()).await;
+        server.server_state.client_notifications =
+            Arc::new(NotificationAllowlist::new(Some(&["custom/*".to_string()])).unwrap());
+        let state = server.server_state.clone();
+        let router = server.create_router();
+        let notification = |method: &str| serde_json::json!({ "jsonrpc": "2.0", "method": method, "params": { "n": 1 } });
+
+        // Answered while a request holds the transport
+        let busy = state.transport.lock().await;
+        let started = std::time::Instant::now();
+        let (status, body) =
+            post_notification(router.clone(), notification("custom/refresh")).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert!(body.is_empty());
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        let (status, body) =
+            post_notification(router.clone(), notification("notifications/initialized")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["code"], "notification_not_allowed");
+        // Let the writer queue on the transport ahead of the next request
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        drop(busy);
+
+        // Written once the transport is free; cat echoes it back as a notification
+        let (status, _) = post_command(
+            router.clone(),
+            serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "ping" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let request = Request::get("/api/v1/notifications")
+            .body(Body::empty())
+            .unwrap();
+        let (_, page) = send(router.clone(), request).await;
+        assert_eq!(
+            page["notifications"][0]["notification"]["method"],
+            "custom/refresh"
+        );
+
+        let request = Request::get("/api/v1/stats").body(Body::empty()).unwrap();
+        let (_, stats) = send(router, request).await;
+        assert_eq!(stats["windows"]["1m"]["requests"], 1);
+        assert_eq!(stats["windows"]["1m"]["notifications"]["accepted"], 1);
+        assert_eq!(stats["windows"]["1m"]["notifications"]["rejected"], 1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_notifications_reach_the_server_in_order() {
+        let mut server = echo_server(Hooks::default()).await;
+        server.server_state.client_notifications =
+            Arc::new(NotificationAllowlist::new(Some(&["custom/*".to_string()])).unwrap());
+        let state = server.server_state.clone();
+        let router = server.create_router();
+
+        // Accepted while a request holds the transport, so they all wait
+        let busy = state.transport.lock().await;
+        for n in 0..20 {
+            let notification = serde_json::json!({ "jsonrpc": "2.0", "method": "custom/step", "params": { "n": n } });
+            let (status, _) = post_notification(router.clone(), notification).await;
+            assert_eq!(status, StatusCode::ACCEPTED);
+        }
+        drop(busy);
+
+        // cat echoes them back as notifications, collected by the next requests
+        let started = std::time::Instant::now();
+        let steps = loop {
+            let (status, _) = post_command(
+                router.clone(),
+                serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "ping" }),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            let request = Request::get("/api/v1/notifications?limit=100")
+                .body(Body::empty())
+                .unwrap();
+            let (_, page) = send(router.clone(), request).await;
+            let steps: Vec<Value> = page["notifications"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|entry| entry["notification"]["params"]["n"].clone())
+                .collect();
+            if steps.len() == 20 {
+                break steps;
+            }
+            assert!(started.elapsed() < Duration::from_secs(5), "{}", page);
+        };
+        assert_eq!(steps, (0..20).map(Value::from).collect::<Vec<_>>());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_cancelled_notification_aborts_the_request() {
+        // A server that never answers
+        let server = test_server("sh", &["-c", "cat > /dev/null"], Hooks::default()).await;
+        let inflight = Arc::clone(&server.server_state.inflight);
+        let router = server.create_router();
+
+        let pending = tokio::spawn(post_command(
+            router.clone(),
+            serde_json::json!({ "jsonrpc": "2.0", "id": "call-1", "method": "tools/call" }),
+        ));
+        while inflight
+            .snapshot()
+            .first()
+            .is_none_or(|request| request.phase != InflightPhase::AwaitingResponse)
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let cancel = serde_json::json!({
+            "jsonrpc": "2.0", "method": "notifications/cancelled",
+            "params": { "requestId": "call-1", "reason": "user gave up" }
+        });
+        let (status, _) = post_notification(router, cancel).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let (status, body) = pending.await.unwrap();
+        assert_eq!(status.as_u16(), crate::error::CLIENT_CLOSED_REQUEST);
+        assert_eq!(body["code"], "request_cancelled");
+        assert_eq!(body["error"], "Client Closed Request");
+        assert!(body["message"]
+            .as_str()
+            .unwrap()
+            .contains("cancelled by the client"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_cancelled_notification_only_reaches_its_session() {
+        use crate::error::CLIENT_CLOSED_REQUEST;
+
+        // A server that never answers
+        let server = test_server("sh", &["-c", "cat > /dev/null"], Hooks::default()).await;
+        let inflight = Arc::clone(&server.server_state.inflight);
+        let router = server.create_router();
+        let post = |session: &str, command: Value| {
+            let body = serde_json::json!({ "command": command.to_string() });
+            let request = Request::post("/api/v1")
+                .header("content-type", "application/json")
+                .header(canary::SESSION_HEADER, session)
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            let router = router.clone();
+            async move { router.oneshot(request).await.unwrap().status() }
+        };
+        let call = serde_json::json!({ "jsonrpc": "2.0", "id": "call-1", "method": "tools/call" });
+        let cancel = serde_json::json!({
+            "jsonrpc": "2.0", "method": "notifications/cancelled",
+            "params": { "requestId": "call-1" }
+        });
+
+        // Two clients of the same key that happen to pick the same id
+        let first = tokio::spawn(post("session-a", call.clone()));
+        while inflight
+            .snapshot()
+            .first()
+            .is_none_or(|request| request.phase != InflightPhase::AwaitingResponse)
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let second = tokio::spawn(post("session-b", call));
+        while inflight.len() < 2 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(
+            post("session-b", cancel.clone()).await,
+            StatusCode::ACCEPTED
+        );
+        assert_eq!(second.await.unwrap().as_u16(), CLIENT_CLOSED_REQUEST);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!first.is_finished());
+        assert_eq!(inflight.len(), 1);
+
+        assert_eq!(post("session-a", cancel).await, StatusCode::ACCEPTED);
+        assert_eq!(first.await.unwrap().as_u16(), CLIENT_CLOSED_REQUEST);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_expired_requests_leave_no_entries_behind() {
+        // A server that never answers
+        let mut server = test_server("sh", &["-c", "cat > /dev/null"], Hooks::default()).await;
+        let inflight = Arc::new(InflightRegistry::new(None, Duration::from_millis(100)));
+        inflight.spawn_sweeper();
+        server.server_state.inflight = Arc::clone(&inflight);
+        server.server_state.timeouts = Arc::new(MethodTimeouts::new(
+            &HashMap::new(),
+            Some(Duration::from_millis(100)),
+        ));
+        let queue = Arc::clone(&server.server_state.request_queue);
+        let router = server.create_router();
+
+        let mut capacity = Vec::new();
+        for round in 0..2 {
+            let requests: Vec<_> = (0..1000)
+                .map(|id| {
+                    let command = serde_json::json!({
+                        "jsonrpc": "2.0", "id": round * 1000 + id, "method": "tools/call"
+                    });
+                    let body = serde_json::json!({ "command": command.to_string() });
+                    let request = Request::post("/api/v1")
+                        .header("content-type", "application/json")
+                        .body(Body::from(body.to_string()))
+                        .unwrap();
+                    tokio::spawn(router.clone().oneshot(request))
+                })
+                .collect();
+            for response in requests {
+                let response = response.await.unwrap().unwrap();
+                assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+            }
+            assert!(inflight.is_empty());
+            assert_eq!(queue.snapshot().normal.queued, 0);
+            capacity.push(inflight.capacity());
+        }
+        assert_eq!(inflight.expired(), 2000);
+        // Storage is sized by one round's peak, not by every request ever made
+        assert!(
+            capacity.iter().all(|&capacity| capacity < 2000),
+            "{:?}",
+            capacity
+        );
+
+        let request = Request::get("/api/v1/stats").body(Body::empty()).unwrap();
+        let (_, body) = send(router, request).await;
+        assert_eq!(body["inflight"]["pending"], 0);
+        assert_eq!(body["inflight"]["expired"], 2000);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_method_timeout_fires_and_names_its_limit() {
+        // A server that never answers
+        let mut server = test_server("sh", &["-c", "cat > /dev/null"], Hooks::default()).await;
+        server.server_state.inflight.spawn_sweeper();
+        server.server_state.timeouts = Arc::new(MethodTimeouts::new(
+            &HashMap::from([("tools/call:slow".to_string(), 1)]),
+            Some(Duration::from_secs(60)),
+        ));
+        let router = server.create_router();
+        let command = serde_json::json!({
+            "jsonrpc": "2.0", "id": 1, "method": "tools/call", "params": { "name": "slow" }
+        });
+
+        let body = serde_json::json!({ "command": command.to_string() });
+        let request = Request::post("/api/v1/validate")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let (_, body) = send(router.clone(), request).await;
+        assert_eq!(body["timeout"]["secs"], 1);
+        assert_eq!(body["timeout"]["limit"], "method:tools/call:slow");
+
+        let started = std::time::Instant::now();
+        let (status, body) = post_command(router, command).await;
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(body["code"], "timeout");
+        assert_eq!(body["timeout_secs"], 1);
+        assert_eq!(body["timeout_limit"], "method:tools/call:slow");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_load_shedding_sheds_and_recovers() {
+        // A server answering each request after 50ms
+        let script = "while read line; do sleep 0.05; echo \"$line\"; done";
+        let mut server = test_server("sh", &["-c", script], Hooks::default()).await;
+        server.server_state.load_shedder = Some(Arc::new(LoadShedder::new(
+            serde_json::from_value(serde_json::json!({ "max_queue_depth": 2 })).unwrap(),
+        )));
+        let router = server.create_router();
+
+        let burst: Vec<_> = (0..12)
+            .map(|id| {
+                let command =
+                    serde_json::json!({ "jsonrpc": "2.0", "id": id, "method": "tools/list" });
+                let body = serde_json::json!({ "command": command.to_string() });
+                let request = Request::post("/api/v1")
+                    .header("content-type", "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap();
+                tokio::spawn(router.clone().oneshot(request))
+            })
+            .collect();
+        let mut shed = 0;
+        for response in burst {
+            let response = response.await.unwrap().unwrap();
+            if response.status() == StatusCode::SERVICE_UNAVAILABLE {
+                assert_eq!(response.headers()["retry-after"], "1");
+                let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                let body: Value = serde_json::from_slice(&bytes).unwrap();
+                assert_eq!(body["code"], "overloaded");
+                shed += 1;
+            } else {
+                assert_eq!(response.status(), StatusCode::OK);
+            }
+        }
+        assert!((1..12).contains(&shed), "shed {} of 12", shed);
+
+        // With the queue drained every request is admitted again
+        for id in 100..105 {
+            let (status, _) = post_command(
+                router.clone(),
+                serde_json::json!({ "jsonrpc": "2.0", "id": id, "method": "tools/list" }),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let request = Request::get("/api/v1/stats").body(Body::empty()).unwrap();
+        let (_, body) = send(router, request).await;
+        assert_eq!(body["load_shedding"]["shedding"], false);
+        assert_eq!(body["load_shedding"]["rejected"], shed);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_abort_stuck_inflight_request() {
+        // A server that reads requests but never answers
+        let server = test_server("sh", &["-c", "cat > /dev/null"], Hooks::default()).await;
+        let inflight = Arc::clone(&server.server_state.inflight);
+        let router = server.create_router();
+
+        let command = serde_json::json!({ "jsonrpc": "2.0", "id": 7, "method": "tools/call" });
+        let pending = tokio::spawn(post_command(router.clone(), command));
+
+        // Wait until the request is waiting on the child
+        let mut listing = Value::Null;
+        for _ in 0..100 {
+            let request = Request::get("/admin/servers/echo/inflight")
+                .body(Body::empty())
+                .unwrap();
+            let (status, body) = send(router.clone(), request).await;
+            assert_eq!(status, StatusCode::OK);
+            if body["requests"][0]["phase"] == "awaiting_response" {
+                listing = body;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(listing["count"], 1);
+        assert_eq!(listing["requests"][0]["method"], "tools/call");
+        assert_eq!(listing["requests"][0]["jsonrpc_id"], 7);
+        let id = listing["requests"][0]["id"].as_u64().unwrap();
+
+        let request = Request::post(format!("/admin/servers/echo/inflight/{}/abort", id))
+            .body(Body::empty())
+            .unwrap();
+        let (status, _) = send(router.clone(), request).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = pending.await.unwrap();
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert!(inflight.is_empty());
+
+        let request = Request::get("/admin/servers/other/inflight")
+            .body(Body::empty())
+            .unwrap();
+        let (status, _) = send(router, request).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_drain_lets_inflight_requests_finish() {
+        // A server that takes a while to answer each request
+        let script = r#"while read request; do
+            id=$(echo "$request" | sed -n 's/.*"id":\([0-9]*\).*/\1/p')
+            sleep 0.3
+            echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{}}"
+        done"#;
+        let server = test_server("sh", &["-c", script], Hooks::default()).await;
+        let inflight = Arc::clone(&server.server_state.inflight);
+        let router = server.create_router();
+        let call =
+            |id: u64| serde_json::json!({ "jsonrpc": "2.0", "id": id, "method": "tools/call" });
+        let ready = |router: Router| async move {
+            send(router, Request::get("/ready").body(Body::empty()).unwrap()).await
+        };
+
+        let pending = tokio::spawn(post_command(router.clone(), call(1)));
+        while inflight.is_empty() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        let request = Request::post("/admin/servers/echo/drain?message=Upstream%20outage")
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = send(router.clone(), request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["maintenance"]["message"], "Upstream outage");
+        assert_eq!(body["inflight"], 1);
+
+        let (status, body) = post_command(router.clone(), call(2)).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["code"], "maintenance");
+        assert_eq!(body["maintenance_message"], "Upstream outage");
+        let (status, body) = ready(router.clone()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["ready"], false);
+        let request = Request::get("/api/v1/stats").body(Body::empty()).unwrap();
+        let (_, body) = send(router.clone(), request).await;
+        assert_eq!(body["maintenance"]["message"], "Upstream outage");
+
+        // The request admitted before the drain still completes
+        let (status, _) = pending.await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+
+        let request = Request::post("/admin/servers/echo/resume")
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = send(router.clone(), request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["resumed"], true);
+        let (status, _) = post_command(router.clone(), call(3)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = ready(router).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["maintenance"], Value::Null);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_key_rotation_leaves_no_window_without_keys() {
+        let server = echo_server(Hooks::default()).await;
+        let auth = server.auth();
+        let config = |ci_key: &str| AuthConfig {
+            api_key: None,
+            enabled: true,
+            default_priority: RequestPriority::Normal,
+            named_keys: vec![
+                ("ci".to_string(), ci_key.to_string()),
+                ("ops".to_string(), "ops-key".to_string()),
+            ],
+        };
+        auth.replace(config("ci-key-0")).unwrap();
+        let router = server.create_router();
+        let info = |key: &str| {
+            Request::get("/api/v1/info")
+                .header("authorization", format!("Bearer {}", key))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // Requests with the key kept across rotations never fail
+        let rotating = Arc::new(AtomicBool::new(true));
+        let clients: Vec<_> = (0..4)
+            .map(|_| {
+                let router = router.clone();
+                let rotating = Arc::clone(&rotating);
+                tokio::spawn(async move {
+                    let mut sent = 0;
+                    while rotating.load(Ordering::SeqCst) {
+                        let response = router.clone().oneshot(info("ops-key")).await.unwrap();
+                        assert_eq!(response.status(), StatusCode::OK);
+                        sent += 1;
+                        tokio::task::yield_now().await;
+                    }
+                    sent
+                })
+            })
+            .collect();
+        for generation in 1..=50 {
+            let key = format!("ci-key-{}", generation);
+            let change = auth.replace(config(&key)).unwrap();
+            assert_eq!(change.rotated, ["ci"]);
+            // The new key works as soon as the replacement returns
+            let response = router.clone().oneshot(info(&key)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let previous = format!("ci-key-{}", generation - 1);
+            let response = router.clone().oneshot(info(&previous)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            tokio::task::yield_now().await;
+        }
+        rotating.store(false, Ordering::SeqCst);
+        for client in clients {
+            assert!(client.await.unwrap() > 0);
+        }
+
+        // Turning auth off on loopback is allowed and seen by the index
+        let mut disabled = config("unused");
+        disabled.enabled = false;
+        let change = auth.replace(disabled).unwrap();
+        assert!(!change.enabled);
+        let (status, body) = send(router, Request::get("/").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["auth_required"], false);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_exhausted_quota_returns_429_with_reset() {
+        let mut server = echo_server(Hooks::default()).await;
+        require_key(&server, "secret");
+        let limits = std::collections::HashMap::from([(
+            auth::DEFAULT_API_KEY_NAME.to_string(),
+            crate::quota::QuotaConfig {
+                requests_per_day: Some(2),
+                ..Default::default()
+            },
+        )]);
+        server.server_state.quotas = Arc::new(Quotas::new("echo", &limits, None));
+        let router = server.create_router();
+        let call = |id: u64| {
+            let command = serde_json::json!({ "jsonrpc": "2.0", "id": id, "method": "ping" });
+            Request::post("/api/v1")
+                .header("content-type", "application/json")
+                .header("authorization", "Bearer secret")
+                .body(Body::from(
+                    serde_json::json!({ "command": command.to_string() }).to_string(),
+                ))
+                .unwrap()
+        };
+
+        for id in 1..=2 {
+            let (status, _) = send(router.clone(), call(id)).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let response = router.clone().oneshot(call(3)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["code"], "quota_exceeded");
+        assert_eq!(body["quota"], "requests_per_day");
+        let resets_at = chrono::Utc::now().date_naive().succ_opt().unwrap();
+        assert_eq!(
+            body["resets_at"],
+            format!("{}T00:00:00Z", resets_at.format("%Y-%m-%d"))
+        );
+
+        let request = Request::get("/admin/usage")
+            .header("authorization", "Bearer secret")
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = send(router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["keys"][0]["key"], "default");
+        assert_eq!(body["keys"][0]["requests_today"], 2);
+        assert_eq!(body["keys"][0]["limits"]["requests_per_day"], 2);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_refused_requests_do_not_use_the_quota() {
+        let mut server = echo_server(Hooks::default()).await;
+        require_key(&server, "secret");
+        let limits = std::collections::HashMap::from([(
+            auth::DEFAULT_API_KEY_NAME.to_string(),
+            crate::quota::QuotaConfig {
+                requests_per_day: Some(1),
+                ..Default::default()
+            },
+        )]);
+        server.server_state.quotas = Arc::new(Quotas::new("echo", &limits, None));
+        // No room for any request in flight
+        server.server_state.inflight = Arc::new(InflightRegistry::new(
+            Some(0),
+            crate::inflight::DEFAULT_REQUEST_DEADLINE,
+        ));
+        let router = server.create_router();
+
+        for id in 1..=3 {
+            let command = serde_json::json!({ "jsonrpc": "2.0", "id": id, "method": "ping" });
+            let request = Request::post("/api/v1")
+                .header("content-type", "application/json")
+                .header("authorization", "Bearer secret")
+                .body(Body::from(
+                    serde_json::json!({ "command": command.to_string() }).to_string(),
+                ))
+                .unwrap();
+            let (status, body) = send(router.clone(), request).await;
+            assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{}", body);
+        }
+
+        let request = Request::get("/admin/usage")
+            .header("authorization", "Bearer secret")
+            .body(Body::empty())
+            .unwrap();
+        let (_, body) = send(router, request).await;
+        assert_eq!(body["keys"][0]["requests_today"], 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_rate_limit_position_relative_to_auth() {
+        let router = |middleware: Value| async move {
+            let mut server = echo_server(Hooks::default()).await;
+            require_key(&server, "secret");
+            let entries: Vec<pipeline::MiddlewareEntry> =
+                serde_json::from_value(middleware).unwrap();
+            server.server_state.pipeline = Arc::new(Pipeline::new(Some(&entries)).unwrap());
+            server.create_router()
+        };
+        let request = |key: Option<&str>| {
+            let mut request = Request::get("/admin/middleware");
+            if let Some(key) = key {
+                request = request.header("authorization", format!("Bearer {}", key));
+            }
+            request.body(Body::empty()).unwrap()
+        };
+        let rate_limit =
+            serde_json::json!({ "name": "rate_limit", "requests_per_sec": 1, "burst": 1 });
+
+        // Before auth, unauthenticated requests use up the budget too
+        let outside = router(serde_json::json!([rate_limit, { "name": "auth" }])).await;
+        let (status, _) = send(outside.clone(), request(None)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let response = outside.oneshot(request(Some("secret"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+
+        // Behind auth, only authenticated requests count
+        let inside = router(serde_json::json!([
+            { "name": "version_headers", "enabled": false },
+            { "name": "auth" },
+            rate_limit
+        ]))
+        .await;
+        let (status, _) = send(inside.clone(), request(None)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let response = inside
+            .clone()
+            .oneshot(request(Some("secret")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key("x-mcp-server-name"));
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["configured"], true);
+        assert_eq!(
+            body["layers"],
+            serde_json::json!([
+                { "name": "version_headers", "enabled": false, "scope": "all", "active": false },
+                { "name": "auth", "enabled": true, "scope": "authenticated", "active": true },
+                {
+                    "name": "rate_limit", "requests_per_sec": 1, "burst": 1,
+                    "enabled": true, "scope": "authenticated", "active": true
+                }
+            ])
+        );
+        let (status, body) = send(inside, request(Some("secret"))).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(body["code"], "rate_limited");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_capture_records_matching_bodies_until_stopped() {
+        let router = echo_server(Hooks::default()).await.create_router();
+        let admin = |method: &str, uri: &str, body: Option<Value>| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("content-type", "application/json")
+                .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+                .unwrap()
+        };
+        let call = |tool: &str| {
+            serde_json::json!({
+                "jsonrpc": "2.0", "id": 1, "method": "tools/call",
+                "params": { "name": tool, "arguments": { "q": "x", "password": "hunter22" } }
+            })
+        };
+
+        let start = serde_json::json!({
+            "server": "echo", "method": "tools/call", "tool": "search", "max_bodies": 5
+        });
+        let (status, body) = send(
+            router.clone(),
+            admin("POST", "/admin/capture", Some(start.clone())),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let id = body["id"].as_str().unwrap().to_string();
+        assert_eq!(body["ttl_secs"], 600);
+        let (status, _) = send(router.clone(), admin("POST", "/admin/capture", Some(start))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        for tool in ["search", "fetch"] {
+            let (status, _) = post_command(router.clone(), call(tool)).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let (status, stats) = send(router.clone(), admin("GET", "/api/v1/stats", None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(stats["capture"]["id"], id.as_str());
+        assert_eq!(stats["capture"]["captured"], 1);
+
+        let uri = format!("/admin/capture/{}", id);
+        let (status, report) = send(router.clone(), admin("GET", &uri, None)).await;
+        assert_eq!(status, StatusCode::OK);
+        let exchange = &report["exchanges"][0];
+        assert_eq!(exchange["request"]["params"]["name"], "search");
+        assert_eq!(
+            exchange["request"]["params"]["arguments"]["password"],
+            REDACTED
+        );
+        assert_eq!(
+            exchange["response"]["params"]["arguments"]["password"],
+            REDACTED
+        );
+        assert!(!report.to_string().contains("hunter22"));
+
+        let (status, _) = send(router.clone(), admin("DELETE", &uri, None)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(router.clone(), admin("GET", &uri, None)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (_, stats) = send(router, admin("GET", "/api/v1/stats", None)).await;
+        assert_eq!(stats["capture"], Value::Null);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_caller_context_passed_in_meta() {
+        let command = serde_json::json!({
+            "jsonrpc": "2.0", "id": 7, "method": "tools/call",
+            "params": { "name": "echo", "_meta": { "progressToken": 1, "gateway": "forged" } }
+        });
+
+        // The server sees the context merged into the client's _meta
+        let mut renaming =
+            test_server("sed", &["-u", "s/\"gateway\"/\"seen\"/"], Hooks::default()).await;
+        renaming.server_state.context_meta = Some(Arc::new(ContextMetaConfig::default()));
+        let (status, body) = post_command(renaming.create_router(), command.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        let seen: Value = serde_json::from_str(body["result"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            seen["params"]["_meta"],
+            serde_json::json!({ "progressToken": 1, "seen": { "request_id": 7 } })
+        );
+
+        // Echoes of the context are stripped from the response
+        let mut echo = echo_server(Hooks::default()).await;
+        echo.server_state.context_meta = Some(Arc::new(ContextMetaConfig::default()));
+        let (_, body) = post_command(echo.create_router(), command.clone()).await;
+        let echoed: Value = serde_json::from_str(body["result"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            echoed["params"]["_meta"],
+            serde_json::json!({ "progressToken": 1 })
+        );
+
+        // Without context_meta the command is forwarded as sent
+        let (_, body) = post_command(
+            echo_server(Hooks::default()).await.create_router(),
+            command.clone(),
+        )
+        .await;
+        let echoed: Value = serde_json::from_str(body["result"].as_str().unwrap()).unwrap();
+        assert_eq!(echoed["params"], command["params"]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_configured_response_headers_set() {
+        let mut echo = echo_server(Hooks::default()).await;
+        echo.server_state.response_headers = ResponseHeaders::new(
+            &HashMap::from([("X-Robots-Tag".to_string(), "noindex".to_string())]),
+            &HashMap::from([(
+                "X-Cache-TTL".to_string(),
+                "/params/_meta/cacheTtl".to_string(),
+            )]),
+        )
+        .map(Arc::new);
+        let router = echo.create_router();
+        let post = |command: Value| {
+            let body = serde_json::json!({ "command": command.to_string() });
+            Request::post("/api/v1")
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        // The echoed request carries the hint the header is taken from
+        let command = serde_json::json!({
+            "jsonrpc": "2.0", "id": 1, "method": "tools/call",
+            "params": { "name": "echo", "_meta": { "cacheTtl": 300 } }
+        });
+        let response = router.clone().oneshot(post(command)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-robots-tag"], "noindex");
+        assert_eq!(response.headers()["x-cache-ttl"], "300");
+
+        // Without the hint only the static header is set
+        let command = serde_json::json!({ "jsonrpc": "2.0", "id": 2, "method": "tools/list" });
+        let response = router.clone().oneshot(post(command)).await.unwrap();
+        assert_eq!(response.headers()["x-robots-tag"], "noindex");
+        assert!(response.headers().get("x-cache-ttl").is_none());
+
+        // Static headers are on every response, errors included
+        for path in ["/health", "/nope"] {
+            let request = Request::get(path).body(Body::empty()).unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.headers()["x-robots-tag"], "noindex", "{}", path);
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_raw_response_passed_through_byte_for_byte() {
+        // Valid JSON a re-serializer would reorder, reformat, and unescape
+        let line = r#"{ "result" : {"b":1.50, "a":1e2,"s":"é\/"} ,"id":3,  "jsonrpc":"2.0" }"#;
+        let error =
+            r#"{"jsonrpc":"2.0","id":3,"error":{"code":-32601,"message":"Method not found"}}"#;
+        let script = format!(
+            "read request; printf '%s\\n' '{}'; read request; printf '%s\\n' '{}'; read request",
+            line, error
+        );
+        let router = test_server("sh", &["-c", &script], Hooks::default())
+            .await
+            .create_router();
+        let command = serde_json::json!({ "jsonrpc": "2.0", "id": 3, "method": "tools/list" });
+        let raw_request = |uri: &str, raw_header: bool| {
+            let mut request = Request::post(uri).header("content-type", "application/json");
+            if raw_header {
+                request = request.header(render::RAW_HEADER, "true");
+            }
+            request
+                .body(Body::from(
+                    serde_json::json!({ "command": command.to_string() }).to_string(),
+                ))
+                .unwrap()
+        };
+
+        let response = router
+            .clone()
+            .oneshot(raw_request("/api/v1", true))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, line.as_bytes());
+
+        // JSON-RPC errors are passed through like results
+        let response = router
+            .clone()
+            .oneshot(raw_request("/api/v1?raw=true", false))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, error.as_bytes());
+
+        // Gateway errors keep their status and error body
+        let (status, body) = send(router, raw_request("/api/v1", true)).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(body["message"].as_str().unwrap().contains("EOF"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_canary_split_is_sticky_and_promotable() {
+        let script = r#"while read request; do
+            id=$(echo "$request" | sed -n 's/.*"id":\([0-9]*\).*/\1/p')
+            echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"from\":\"canary\"}}"
+        done"#;
+        let canary_transport = test_server("sh", &["-c", script], Hooks::default())
+            .await
+            .server_state
+            .transport;
+        let mut server = echo_server(Hooks::default()).await;
+        let canary = Arc::new(CanaryRouter::new(Some(&crate::canary::CanaryConfig {
+            weight: 30,
+            ..Default::default()
+        })));
+        canary.start(canary_transport);
+        server.server_state.canary = Arc::clone(&canary);
+        let router = server.create_router();
+        let call = |session: &str| {
+            let command = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "ping" });
+            Request::post("/api/v1")
+                .header("content-type", "application/json")
+                .header("mcp-session-id", session)
+                .body(Body::from(
+                    serde_json::json!({ "command": command.to_string() }).to_string(),
+                ))
+                .unwrap()
+        };
+        let variant_of = |router: Router, session: String| async move {
+            let response = router.oneshot(call(&session)).await.unwrap();
+            let variant = response.headers()["x-mcp-variant"]
+                .to_str()
+                .unwrap()
+                .to_string();
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: Value = serde_json::from_slice(&bytes).unwrap();
+            let result: Value = serde_json::from_str(body["result"].as_str().unwrap()).unwrap();
+            // The header names the child that actually answered
+            assert_eq!(result["result"]["from"] == "canary", variant == "canary");
+            variant
+        };
+
+        let mut canary_sessions = 0;
+        for i in 0..40 {
+            let session = format!("session-{}", i);
+            let first = variant_of(router.clone(), session.clone()).await;
+            assert_eq!(variant_of(router.clone(), session).await, first);
+            canary_sessions += usize::from(first == "canary");
+        }
+        assert!((3..=24).contains(&canary_sessions));
+        let snapshot = canary.snapshot().unwrap();
+        assert_eq!(snapshot.primary.requests + snapshot.canary.requests, 80);
+        assert_eq!(snapshot.canary.requests, canary_sessions as u64 * 2);
+
+        let admin = |path: &str| {
+            Request::post(format!("/admin/servers/echo/canary/{}", path))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let (status, body) = send(router.clone(), admin("weight?weight=100")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["canary"]["weight"], 100);
+        assert_eq!(
+            variant_of(router.clone(), "any".to_string()).await,
+            "canary"
+        );
+
+        // After promotion the former canary answers as the primary
+        let (status, body) = send(router.clone(), admin("promote")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["canary"]["state"], "promoted");
+        let (status, body) = post_command(
+            router.clone(),
+            serde_json::json!({ "jsonrpc": "2.0", "id": 2, "method": "ping" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["result"]
+            .as_str()
+            .unwrap()
+            .contains(r#""from":"canary""#));
+        let (status, _) = send(router, admin("abort")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    /// Child answering every request with `name` after `delay`, exiting
+    /// after `answers` of them
+    async fn replica_transport(name: &str, delay: &str, answers: u32) -> SharedTransport {
+        let script = format!(
+            r#"i=0; while [ $i -lt {} ] && read request; do
+                i=$((i + 1))
+                id=$(echo "$request" | sed -n 's/.*"id":\([0-9]*\).*/\1/p')
+                sleep {}
+                echo "{{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{{\"from\":\"{}\"}}}}"
+            done"#,
+            answers, delay, name
+        );
+        test_server("sh", &["-c", &script], Hooks::default())
+            .await
+            .server_state
+            .transport
+    }
+
+    /// Server `echo` with three replicas answering with their names
+    async fn replicated_server(
+        balance: Balance,
+        delay: &str,
+    ) -> (McpHttpServer, [SharedTransport; 3]) {
+        let transports = [
+            replica_transport("echo", delay, u32::MAX).await,
+            replica_transport("echo-replica-1", delay, u32::MAX).await,
+            replica_transport("echo-replica-2", delay, u32::MAX).await,
+        ];
+        let mut server = echo_server(Hooks::default()).await;
+        server.server_state.transport = Arc::clone(&transports[0]);
+        let pool = ReplicaPool::new(
+            "echo",
+            &ReplicaConfig { count: 3, balance },
+            &crate::priority::RequestQueueConfig::default(),
+        );
+        pool.start(1, Arc::clone(&transports[1]));
+        pool.start(2, Arc::clone(&transports[2]));
+        server.server_state.replicas = Arc::new(pool);
+        (server, transports)
+    }
+
+    /// Name of the replica answering a ping sent with `affinity`
+    async fn answered_by(router: Router, affinity: Option<&str>) -> String {
+        let command = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "ping" });
+        let mut request = Request::post("/api/v1").header("content-type", "application/json");
+        if let Some(affinity) = affinity {
+            request = request.header(balance::AFFINITY_HEADER, affinity);
+        }
+        let request = request
+            .body(Body::from(
+                serde_json::json!({ "command": command.to_string() }).to_string(),
+            ))
+            .unwrap();
+        let (status, body) = send(router, request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let result: Value = serde_json::from_str(body["result"].as_str().unwrap()).unwrap();
+        result["result"]["from"].as_str().unwrap().to_string()
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_replicas_keep_clients_and_switch_balance() {
+        let (server, _transports) = replicated_server(Balance::StickyKey, "0").await;
+        let router = server.create_router();
+
+        let mut clients_per_replica: HashMap<String, usize> = HashMap::new();
+        for i in 0..30 {
+            let client = format!("client-{}", i);
+            let first = answered_by(router.clone(), Some(&client)).await;
+            for _ in 0..3 {
+                assert_eq!(answered_by(router.clone(), Some(&client)).await, first);
+            }
+            *clients_per_replica.entry(first).or_default() += 1;
+        }
+        // Every replica gets a share of the clients
+        assert_eq!(clients_per_replica.len(), 3, "{:?}", clients_per_replica);
+        assert!(clients_per_replica.values().all(|clients| *clients >= 4));
+
+        let (status, body) = send(
+            router.clone(),
+            Request::post("/admin/servers/echo/replicas/balance?balance=round-robin")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["replicas"]["balance"], "round-robin");
+        let mut answered = Vec::new();
+        for _ in 0..6 {
+            answered.push(answered_by(router.clone(), Some("client-0")).await);
+        }
+        for name in ["echo", "echo-replica-1", "echo-replica-2"] {
+            assert_eq!(answered.iter().filter(|from| *from == name).count(), 2);
+        }
+
+        let (status, body) = send(
+            router,
+            Request::get("/admin/servers/echo/replicas")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["replicas"]["replicas"][2]["name"], "echo-replica-2");
+        assert_eq!(body["replicas"]["replicas"][2]["state"], "running");
+        assert_eq!(body["replicas"]["replicas"][2]["in_flight"], 0);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_least_busy_replicas_serve_requests_side_by_side() {
+        let (server, _transports) = replicated_server(Balance::LeastBusy, "0.3").await;
+        let router = server.create_router();
+
+        // Each request is still in flight when the next is routed
+        let started = std::time::Instant::now();
+        let (a, b, c) = tokio::join!(
+            answered_by(router.clone(), None),
+            answered_by(router.clone(), None),
+            answered_by(router.clone(), None),
+        );
+        let mut answered = vec![a, b, c];
+        answered.sort();
+        assert_eq!(answered, ["echo", "echo-replica-1", "echo-replica-2"]);
+        // Taking turns with one child would take 0.9 s
+        assert!(started.elapsed() < Duration::from_millis(900));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_exited_replica_hands_its_clients_to_the_others() {
+        let (mut server, _transports) = replicated_server(Balance::StickyKey, "0").await;
+        let exiting = replica_transport("echo-replica-1", "0", 1).await;
+        let pool = Arc::clone(&server.server_state.replicas);
+        pool.start(1, Arc::clone(&exiting));
+        let restarted = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let record = Arc::clone(&restarted);
+        pool.on_exit(move |index| record.lock().unwrap().push(index));
+        server.server_state.replicas = Arc::clone(&pool);
+        let router = server.create_router();
+
+        let clients: Vec<String> = (0..30).map(|i| format!("client-{}", i)).collect();
+        let mut before = HashMap::new();
+        for client in &clients {
+            before.insert(client, answered_by(router.clone(), Some(client)).await);
+        }
+        let moved: Vec<&String> = clients
+            .iter()
+            .filter(|client| before[client] == "echo-replica-1")
+            .collect();
+        assert!(!moved.is_empty());
+
+        // Its one answer given, the child exits
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while exiting.lock().await.is_alive() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        for client in &clients {
+            let after = answered_by(router.clone(), Some(client)).await;
+            if before[client] == "echo-replica-1" {
+                assert_ne!(after, "echo-replica-1");
+            } else {
+                assert_eq!(after, before[client], "{}", client);
+            }
+        }
+        assert_eq!(*restarted.lock().unwrap(), [1]);
+        let snapshot = pool.snapshot().unwrap();
+        assert_eq!(snapshot.replicas[1].state, balance::ReplicaState::Starting);
+
+        // Once restarted, the replica gets its clients back
+        pool.start(1, replica_transport("echo-replica-1", "0", u32::MAX).await);
+        for client in moved {
+            assert_eq!(
+                answered_by(router.clone(), Some(client)).await,
+                "echo-replica-1"
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_notification_accepted_before_setup_on_first_request() {
+        let mut server = echo_server(Hooks::default()).await;
+        server.server_state.client_notifications =
+            Arc::new(NotificationAllowlist::new(Some(&["custom/*".to_string()])).unwrap());
+        let transport: Arc<Mutex<Box<dyn McpTransport>>> =
+            Arc::new(Mutex::new(Box::new(Unprovisioned)));
+        let pipeline: ProvisionFn = Arc::new(|mut timer: PhaseTimer, _trigger: Trigger| {
+            Box::pin(async move {
+                timer
+                    .measure("clone", tokio::time::sleep(Duration::from_millis(500)))
+                    .await;
+                let mut command = tokio::process::Command::new("cat");
+                command
+                    .stdin(std::process::Stdio::piped())
+                    .stdout(std::process::Stdio::piped())
+                    .stderr(std::process::Stdio::piped());
+                let process = timer.measure("spawn", McpProcess::spawn(command)).await?;
+                let provisioned = Provisioned {
+                    protocol_version: transport::SUPPORTED_PROTOCOL_VERSIONS[0].to_string(),
+                    pid: process.pid(),
+                    stderr: process.stderr_tail(),
+                    startup: timer.finish(),
+                    audit: None,
+                    commit: None,
+                    package_version: None,
+                    artifact_cache: None,
+                    sandbox: None,
+                    egress: None,
+                };
+                let transport: Box<dyn McpTransport> = Box::new(process);
+                Ok((transport, provisioned))
+            })
+        });
+        server.server_state.transport = Arc::clone(&transport);
+        server.server_state.provisioner = Arc::new(Provisioner::deferred(
+            "echo",
+            SetupMode::OnFirstRequest,
+            transport,
+            pipeline,
+        ));
+        let router = server.create_router();
+
+        // Answered at once; the writer starts the setup and waits for it
+        let started = std::time::Instant::now();
+        let notification = serde_json::json!({ "jsonrpc": "2.0", "method": "custom/hello" });
+        let (status, _) = post_notification(router.clone(), notification).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert!(started.elapsed() < Duration::from_millis(250));
+
+        // cat echoes it back once set up, collected by a later request
+        loop {
+            let (status, _) = post_command(
+                router.clone(),
+                serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "ping" }),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            let request = Request::get("/api/v1/notifications")
+                .body(Body::empty())
+                .unwrap();
+            let (_, page) = send(router.clone(), request).await;
+            if page["notifications"][0]["notification"]["method"] == "custom/hello" {
+                break;
+            }
+            assert!(started.elapsed() < Duration::from_secs(5), "{}", page);
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_manual_setup_waits_for_provision_call() {
+        let mut server = echo_server(Hooks::default()).await;
+        let transport: Arc<Mutex<Box<dyn McpTransport>>> =
+            Arc::new(Mutex::new(Box::new(Unprovisioned)));
+        let pipeline: ProvisionFn = Arc::new(|mut timer: PhaseTimer, _trigger: Trigger| {
+            Box::pin(async move {
+                timer
+                    .measure("clone", tokio::time::sleep(Duration::from_millis(100)))
+                    .await;
+                let mut command = tokio::process::Command::new("cat");
+                command
+                    .stdin(std::process::Stdio::piped())
+                    .stdout(std::process::Stdio::piped())
+                    .stderr(std::process::Stdio::piped());
+                let process = timer.measure("spawn", McpProcess::spawn(command)).await?;
+                let provisioned = Provisioned {
+                    protocol_version: transport::SUPPORTED_PROTOCOL_VERSIONS[0].to_string(),
+                    pid: process.pid(),
+                    stderr: process.stderr_tail(),
+                    startup: timer.finish(),
+                    audit: None,
+                    commit: None,
+                    package_version: None,
+                    artifact_cache: None,
+                    sandbox: None,
+                    egress: None,
+                };
+                let transport: Box<dyn McpTransport> = Box::new(process);
+                Ok((transport, provisioned))
+            })
+        });
+        server.server_state.transport = Arc::clone(&transport);
+        server.server_state.provisioner = Arc::new(Provisioner::deferred(
+            "echo",
+            SetupMode::Manual,
+            transport,
+            pipeline,
+        ));
+        let router = server.create_router();
+        let call = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/list" });
+        let provision = |router: Router| async move {
+            let request = Request::post("/admin/servers/echo/provision")
+                .body(Body::empty())
+                .unwrap();
+            send(router, request).await
+        };
+
+        // Before provisioning, requests are refused and the server is not ready
+        let (status, body) = post_command(router.clone(), call.clone()).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["code"], "not_provisioned");
+        let request = Request::get("/ready").body(Body::empty()).unwrap();
+        let (status, body) = send(router.clone(), request).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["provisioning"], "not_provisioned");
+        let request = Request::get("/api/v1/info").body(Body::empty()).unwrap();
+        let (_, body) = send(router.clone(), request).await;
+        assert_eq!(body["provisioning"]["state"], "not_provisioned");
+        assert_eq!(body["protocol_version"], Value::Null);
+
+        // A second call while the first job runs returns the same job
+        let (status, first) = provision(router.clone()).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(first["started"], true);
+        let job = first["job"]["id"].as_str().unwrap().to_string();
+        let (status, second) = provision(router.clone()).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(second["started"], false);
+        assert_eq!(second["job"]["id"], job);
+        let (status, body) = post_command(router.clone(), call.clone()).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert!(body["message"].as_str().unwrap().contains(&job));
+
+        // Poll the job until the server is up
+        let poll = Request::get(format!("/admin/servers/echo/provision/{}", job));
+        let (_, body) = send(router.clone(), poll.body(Body::empty()).unwrap()).await;
+        assert_eq!(body["job"]["state"], "running");
+        loop {
+            let request = Request::get(format!("/admin/servers/echo/provision/{}", job))
+                .body(Body::empty())
+                .unwrap();
+            let (status, body) = send(router.clone(), request).await;
+            assert_eq!(status, StatusCode::OK);
+            if body["state"] == "provisioned" {
+                let phases: Vec<&str> = body["job"]["phases"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|phase| phase["phase"].as_str().unwrap())
+                    .collect();
+                assert_eq!(phases, ["clone", "spawn"]);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let (status, body) = post_command(router.clone(), call).await;
+        assert_eq!(status, StatusCode::OK);
+        let echoed: Value = serde_json::from_str(body["result"].as_str().unwrap()).unwrap();
+        assert_eq!(echoed["id"], 1);
+        let (status, body) = provision(router.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["job"]["id"], job);
+        let request = Request::get("/admin/servers/echo/provision/provision-9")
+            .body(Body::empty())
+            .unwrap();
+        let (status, _) = send(router, request).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    /// Setup starting a `cat` child after `delay`, as a restart would
+    #[cfg(unix)]
+    fn cat_pipeline(delay: Duration) -> ProvisionFn {
+        Arc::new(move |mut timer: PhaseTimer, _trigger: Trigger| {
+            Box::pin(async move {
+                tokio::time::sleep(delay).await;
+                let mut command = tokio::process::Command::new("cat");
+                command
+                    .stdin(std::process::Stdio::piped())
+                    .stdout(std::process::Stdio::piped())
+                    .stderr(std::process::Stdio::piped());
+                let process = timer.measure("spawn", McpProcess::spawn(command)).await?;
+                let provisioned = Provisioned {
+                    protocol_version: transport::SUPPORTED_PROTOCOL_VERSIONS[0].to_string(),
+                    pid: process.pid(),
+                    stderr: process.stderr_tail(),
+                    startup: timer.finish(),
+                    audit: None,
+                    commit: None,
+                    package_version: None,
+                    artifact_cache: None,
+                    sandbox: None,
+                    egress: None,
+                };
+                let transport: Box<dyn McpTransport> = Box::new(process);
+                Ok((transport, provisioned))
+            })
+        })
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_blue_green_restart_under_load_drops_nothing() {
+        let mut server = echo_server(Hooks::default()).await;
+        let provisioned = (*server.server_state.provisioner.provisioned().unwrap()).clone();
+        let old_pid = server.server_state.transport.lock().await.pid().unwrap();
+        // The replacement takes a while to start, as a real server would
+        let pipeline = cat_pipeline(Duration::from_millis(200));
+        server.server_state.provisioner = Arc::new(
+            Provisioner::ready(
+                "echo",
+                Arc::clone(&server.server_state.transport),
+                provisioned,
+            )
+            .with_pipeline(pipeline),
+        );
+        let router = server.create_router();
+
+        // Clients send requests back to back for the whole restart
+        let stop = Arc::new(AtomicBool::new(false));
+        let clients: Vec<_> = (0..4)
+            .map(|client| {
+                let (router, stop) = (router.clone(), Arc::clone(&stop));
+                tokio::spawn(async move {
+                    let (mut sent, mut failed) = (0, 0);
+                    while !stop.load(Ordering::Relaxed) {
+                        let call = serde_json::json!({
+                            "jsonrpc": "2.0",
+                            "id": client * 100_000 + sent,
+                            "method": "ping"
+                        });
+                        let (status, _) = post_command(router.clone(), call).await;
+                        sent += 1;
+                        if status != StatusCode::OK {
+                            failed += 1;
+                        }
+                    }
+                    (sent, failed)
+                })
+            })
+            .collect();
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let request = Request::post("/admin/servers/echo/restart?strategy=blue-green")
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = send(router.clone(), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["restart"]["strategy"], "blue-green");
+        assert!(body["restart"]["duration_ms"].as_u64().unwrap() >= 200);
+        assert!(body["restart"]["switchover_ms"].is_u64());
+        let new_pid = body["pid"].as_u64().unwrap() as u32;
+        assert_ne!(new_pid, old_pid);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        stop.store(true, Ordering::Relaxed);
+
+        for client in clients {
+            let (sent, failed) = client.await.unwrap();
+            assert!(sent > 0);
+            assert_eq!(failed, 0, "{} of {} requests failed", failed, sent);
+        }
+        let request = Request::get("/admin/servers/echo/stats")
+            .body(Body::empty())
+            .unwrap();
+        let (_, body) = send(router, request).await;
+        assert_eq!(body["restarts"]["restarts"], 1);
+        assert_eq!(body["restarts"]["failures"], 0);
+        assert_eq!(body["provisioning"]["state"], "provisioned");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_child_not_reading_stdin_fails_fast_and_is_restarted() {
+        // The child never reads its stdin, so a large write fills the pipe
+        let mut server = test_server("sleep", &["600"], Hooks::default()).await;
+        let mut command = tokio::process::Command::new("sleep");
+        command
+            .arg("600")
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped());
+        let stuck = McpProcess::spawn(command)
+            .await
+            .unwrap()
+            .with_write_timeout(Duration::from_millis(200));
+        let mut stuck: Box<dyn McpTransport> = Box::new(stuck);
+        std::mem::swap(&mut stuck, &mut *server.server_state.transport.lock().await);
+        stuck.shutdown().await.unwrap();
+        let provisioned = (*server.server_state.provisioner.provisioned().unwrap()).clone();
+        server.server_state.provisioner = Arc::new(
+            Provisioner::ready(
+                "echo",
+                Arc::clone(&server.server_state.transport),
+                provisioned,
+            )
+            .with_pipeline(cat_pipeline(Duration::from_millis(300))),
+        );
+        let breaker = Arc::clone(&server.server_state.stdin_breaker);
+        let router = server.create_router();
+        let call = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "tools/call",
+            "params": { "name": "echo", "arguments": { "text": "x".repeat(256 * 1024) } }
+        });
+
+        // The write times out long before the request would
+        let started = std::time::Instant::now();
+        let (status, body) = post_command(router.clone(), call.clone()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{}", body);
+        assert_eq!(body["code"], "child_unresponsive");
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        // While the replacement starts, requests fail without writing
+        let started = std::time::Instant::now();
+        let (status, body) = post_command(router.clone(), call.clone()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["code"], "child_unresponsive");
+        assert!(started.elapsed() < Duration::from_millis(100));
+        let snapshot = breaker.snapshot();
+        assert!(snapshot.open);
+        assert_eq!(snapshot.consecutive_write_timeouts, 1);
+
+        for _ in 0..50 {
+            if !breaker.snapshot().open {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let ping = serde_json::json!({ "jsonrpc": "2.0", "id": 2, "method": "ping" });
+        let (status, body) = post_command(router.clone(), ping).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let request = Request::get("/admin/servers/echo/stats")
+            .body(Body::empty())
+            .unwrap();
+        let (_, body) = send(router, request).await;
+        assert_eq!(body["stdin_breaker"]["open"], false);
+        assert_eq!(body["stdin_breaker"]["consecutive_write_timeouts"], 0);
+        assert_eq!(body["stdin_breaker"]["write_timeouts"], 1);
+        assert_eq!(body["restarts"]["last"]["reason"], breaker::RESTART_REASON);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_abort_cancels_running_setup_job() {
+        let mut server = echo_server(Hooks::default()).await;
+        let setup = Arc::new(SetupExecutor::new(1));
+        let transport: Arc<Mutex<Box<dyn McpTransport>>> =
+            Arc::new(Mutex::new(Box::new(Unprovisioned)));
+        let pipeline: ProvisionFn = {
+            let setup = Arc::clone(&setup);
+            Arc::new(move |mut timer: PhaseTimer, _trigger: Trigger| {
+                let setup = Arc::clone(&setup);
+                Box::pin(async move {
+                    let progress = timer.observe();
+                    setup
+                        .run("echo", progress, async {
+                            timer.measure("clone", std::future::pending()).await
+                        })
+                        .await
+                })
+            })
+        };
+        server.server_state.setup = Arc::clone(&setup);
+        server.server_state.provisioner = Arc::new(Provisioner::deferred(
+            "echo",
+            SetupMode::Manual,
+            transport,
+            pipeline,
+        ));
+        let router = server.create_router();
+
+        let request = Request::post("/admin/servers/echo/provision")
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = send(router.clone(), request).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let job = body["job"]["id"].as_str().unwrap().to_string();
+        let setup_jobs = |router: Router| async move {
+            let request = Request::get("/api/v1/stats").body(Body::empty()).unwrap();
+            send(router, request).await.1["setup"].clone()
+        };
+        for _ in 0..100 {
+            if setup_jobs(router.clone()).await["running"] == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let jobs = setup_jobs(router.clone()).await;
+        assert_eq!(jobs["jobs"][0]["current_phase"], "clone");
+
+        let request = Request::delete("/admin/servers/echo/provision")
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = send(router.clone(), request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["cancelled"], 1);
+
+        // The provisioning job fails and the slot is free again
+        loop {
+            let request = Request::get(format!("/admin/servers/echo/provision/{}", job))
+                .body(Body::empty())
+                .unwrap();
+            let (_, body) = send(router.clone(), request).await;
+            if body["job"]["state"] == "failed" {
+                assert!(body["job"]["error"]
+                    .as_str()
+                    .unwrap()
+                    .contains("was cancelled"));
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let jobs = setup_jobs(router).await;
+        assert_eq!(jobs["running"], 0);
+        assert_eq!(jobs["cancelled"], 1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_trailing_slash_accepted() {
+        let router = echo_server(Hooks::default()).await.create_router();
+
+        let body = serde_json::json!({
+            "command": r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#
+        });
+        let request = Request::post("/api/v1/")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let (status, body) = send(router, request).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["result"].as_str().unwrap().contains("ping"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_method_not_allowed_is_structured() {
+        let router = echo_server(Hooks::default()).await.create_router();
+
+        let request = Request::get("/api/v1").body(Body::empty()).unwrap();
+        let (status, body) = send(router, request).await;
+
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(body["error"], "Method Not Allowed");
+        assert!(body["allowed_methods"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|method| method == "POST"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_root_index_and_unknown_path() {
+        let router = echo_server(Hooks::default()).await.create_router();
+
+        let request = Request::get("/").body(Body::empty()).unwrap();
+        let (status, body) = send(router.clone(), request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(body["auth_required"], false);
+        assert_eq!(body["auth"], "disabled");
+        assert!(body["endpoints"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|endpoint| endpoint["path"] == "/api/v1"));
+
+        let request = Request::get("/nope").body(Body::empty()).unwrap();
+        let (status, body) = send(router, request).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body["message"].as_str().unwrap().contains("/nope"));
+    }
+
+    /// Status of `method` on `path`, with its parameters naming nothing that
+    /// exists so handlers answer without acting, and whether no route matched
+    async fn probe(
+        router: &Router,
+        method: &str,
+        path: &str,
+        key: Option<&str>,
+    ) -> (StatusCode, bool) {
+        let mut path = path.to_string();
+        for param in ["{name}", "{id}", "{job}", "{tool}"] {
+            path = path.replace(param, "nope");
+        }
+        let mut request = Request::builder()
+            .method(method)
+            .uri(format!("{}?dry_run=true", path));
+        if let Some(key) = key {
+            request = request.header("authorization", format!("Bearer {}", key));
+        }
+        let request = request.body(Body::empty()).unwrap();
+        let response =
+            tokio::time::timeout(Duration::from_secs(10), router.clone().oneshot(request))
+                .await
+                .unwrap()
+                .unwrap();
+        let status = response.status();
+        if status != StatusCode::NOT_FOUND {
+            return (status, false);
+        }
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        (
+            status,
+            body["message"].as_str().unwrap().contains("No route for"),
+        )
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_manifest_lists_exactly_the_routes_served() {
+        let cases: [(&[RouteGroup], bool, Value); 4] = [
+            (&RouteGroup::ALL, true, serde_json::json!({})),
+            (
+                &[RouteGroup::Api, RouteGroup::Health],
+                false,
+                serde_json::json!({}),
+            ),
+            (
+                &[RouteGroup::Admin, RouteGroup::Metrics, RouteGroup::Health],
+                false,
+                serde_json::json!({ "include_admin": false, "metadata": { "owner": "edge" } }),
+            ),
+            (
+                &[RouteGroup::Api, RouteGroup::Admin],
+                false,
+                serde_json::json!({}),
+            ),
+        ];
+        for (groups, simple_mode, manifest_config) in cases {
+            let mut server = echo_server(Hooks::default()).await;
+            require_key(&server, "secret");
+            server.server_state.simple_mode = simple_mode;
+            server.server_state.manifest =
+                Arc::new(serde_json::from_value(manifest_config.clone()).unwrap());
+            let router = server.router(groups, Arc::new(OnceLock::new()));
+            let listed = server.route_infos(groups);
+            let case = format!("{:?} {}", groups, manifest_config);
+
+            if !groups.contains(&RouteGroup::Health) {
+                assert!(
+                    probe(&router, "GET", MANIFEST_PATH, None).await.1,
+                    "{}",
+                    case
+                );
+                assert!(!listed.iter().any(|route| route.path == MANIFEST_PATH));
+            } else {
+                let request = Request::get(MANIFEST_PATH).body(Body::empty()).unwrap();
+                let (status, manifest) = send(router.clone(), request).await;
+                assert_eq!(status, StatusCode::OK, "{}", case);
+                assert_eq!(manifest["auth"], "enabled");
+                let manifest_routes = manifest["routes"].as_array().unwrap();
+                let include_admin = manifest_config.get("include_admin").is_none();
+                let expected: Vec<Value> = listed
+                    .iter()
+                    .filter(|route| include_admin || route.group != RouteGroup::Admin)
+                    .map(|route| serde_json::to_value(route).unwrap())
+                    .collect();
+                assert_eq!(*manifest_routes, expected, "{}", case);
+                assert_eq!(manifest.get("metadata"), manifest_config.get("metadata"));
+            }
+
+            for route in &listed {
+                let label = format!("{} {} in {}", route.method, route.path, case);
+                assert!(groups.contains(&route.group), "{}", label);
+                let (status, unrouted) = probe(&router, route.method, &route.path, None).await;
+                assert!(!unrouted, "{}", label);
+                assert_ne!(status, StatusCode::METHOD_NOT_ALLOWED, "{}", label);
+                match route.auth {
+                    RouteAuth::None => assert_ne!(status, StatusCode::UNAUTHORIZED, "{}", label),
+                    _ => assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", label),
+                }
+                // The key gets past authentication to the listed method's handler
+                let (status, unrouted) =
+                    probe(&router, route.method, &route.path, Some("secret")).await;
+                assert!(!unrouted, "{}", label);
+                assert!(
+                    ![StatusCode::METHOD_NOT_ALLOWED, StatusCode::UNAUTHORIZED].contains(&status),
+                    "{} answered {}",
+                    label,
+                    status
+                );
+            }
+
+            // Routes of groups left out are not served
+            for (group, path) in [
+                (RouteGroup::Api, "/api/v1/info"),
+                (RouteGroup::Metrics, "/api/v1/stats"),
+                (RouteGroup::Admin, "/admin/usage"),
+                (RouteGroup::Health, "/ready"),
+            ] {
+                assert_eq!(
+                    listed.iter().any(|route| route.path == path),
+                    groups.contains(&group),
+                    "{} in {}",
+                    path,
+                    case
+                );
+                if !groups.contains(&group) {
+                    assert!(
+                        probe(&router, "GET", path, Some("secret")).await.1,
+                        "{}",
+                        path
+                    );
+                }
+            }
+            let simple = listed
+                .iter()
+                .filter(|route| route.path == "/api/v1/simple/{tool}")
+                .count();
+            assert_eq!(simple, if simple_mode { 2 } else { 0 }, "{}", case);
+        }
+
+        // A disabled manifest is neither served nor listed, and routes need
+        // no key while authentication is off
+        let mut server = echo_server(Hooks::default()).await;
+        server.server_state.manifest = Arc::new(ManifestConfig {
+            enabled: false,
+            ..ManifestConfig::default()
+        });
+        assert!(!server
+            .route_infos(&RouteGroup::ALL)
+            .iter()
+            .any(|route| route.path == MANIFEST_PATH));
+        let router = server.create_router();
+        assert!(probe(&router, "GET", MANIFEST_PATH, None).await.1);
+        let server = echo_server(Hooks::default()).await;
+        let request = Request::get(MANIFEST_PATH).body(Body::empty()).unwrap();
+        let (_, manifest) = send(server.create_router(), request).await;
+        assert_eq!(manifest["auth"], "disabled");
+        assert!(manifest["routes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|route| route["auth"] == "none"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_access_log_records_requests() {
+        let path = std::env::temp_dir().join(format!(
+            "mcp-access-log-server-test-{}.log",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let mut server = echo_server(Hooks::default()).await;
+        server.server_state.access_log = Some(
+            AccessLog::start(&AccessLogConfig {
+                target: access_log::AccessLogTarget::File {
+                    path: path.clone(),
+                    max_bytes: access_log::DEFAULT_MAX_BYTES,
+                    max_files: 1,
+                },
+                format: access_log::AccessLogFormat::Json,
+                fields: None,
+            })
+            .unwrap(),
+        );
+        let router = server.create_router();
+
+        let request = Request::get("/nope")
+            .header("x-request-id", "trace-1")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.headers()["x-request-id"], "trace-1");
+        let (status, _) = post_command(
+            router,
+            serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/list" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let lines: Vec<Value> = loop {
+            let content = std::fs::read_to_string(&path).unwrap_or_default();
+            if content.lines().count() == 2 {
+                break content
+                    .lines()
+                    .map(|line| serde_json::from_str(line).unwrap())
+                    .collect();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        assert_eq!(lines[0]["request_id"], "trace-1");
+        assert_eq!(lines[0]["status"], 404);
+        assert!(lines[0]["error"].as_str().unwrap().contains("/nope"));
+        assert_eq!(lines[1]["method"], "POST");
+        assert_eq!(lines[1]["path"], "/api/v1");
+        assert_eq!(lines[1]["status"], 200);
+        assert!(lines[1]["error"].is_null());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_failed_build_log_is_served() {
+        let work_dir_base =
+            std::env::temp_dir().join(format!("mcp-build-log-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&work_dir_base);
+        // Two megabytes of output, more than is kept, then the error
+        let build_command = "head -c 2000000 /dev/zero | tr '\\0' x; echo; \
+            echo 'npm ERR! missing script: build' >&2; exit 3";
+        let mut config = McpServersConfig::from_value(
+            serde_json::json!({
+                "servers": { "broken": {
+                    "command": "cat",
+                    "build_command": build_command,
+                    "setup_mode": "manual"
+                } }
+            }),
+            "test configuration",
+        )
+        .unwrap();
+        for server in config.servers.values_mut() {
+            server.work_dir_base = Some(work_dir_base.clone());
+        }
+        let router = McpHttpServer::builder("unused.json", "broken")
+            .config(config)
+            .bind_host(Ipv4Addr::LOCALHOST.into())
+            .build()
+            .await
+            .unwrap()
+            .create_router();
+        let get = |path: &str| Request::get(path).body(Body::empty()).unwrap();
+
+        let (status, _) = send(router.clone(), get("/admin/servers/broken/build-log")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let request = Request::post("/admin/servers/broken/provision")
+            .body(Body::empty())
+            .unwrap();
+        let (status, _) = send(router.clone(), request).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let body = loop {
+            let (status, body) = send(router.clone(), get("/ready")).await;
+            assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+            if body["provisioning"] == "failed" {
+                break body;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        };
+        let message = body["message"].as_str().unwrap();
+        assert!(message.contains("exit code Some(3)"), "{}", message);
+        assert!(message.contains("GET /admin/servers/broken/build-log"));
+
+        let (status, body) = send(router.clone(), get("/admin/servers/broken/build-log")).await;
+        assert_eq!(status, StatusCode::OK);
+        let run = &body["run"];
+        assert!(run["error"]
+            .as_str()
+            .unwrap()
+            .contains("Build command failed"));
+        let step = &run["steps"][0];
+        assert_eq!(step["kind"], "build");
+        assert_eq!(step["exit_code"], 3);
+        assert_eq!(step["success"], false);
+        assert_eq!(step["truncated"], true);
+        assert_eq!(step["output_bytes"], 2_000_001 + 31);
+        let stdout = step["stdout"].as_str().unwrap();
+        assert!(stdout.len() < 100_000);
+        assert!(stdout.contains("bytes truncated ...]"));
+        assert_eq!(step["stderr"], "npm ERR! missing script: build\n");
+
+        // The file keeps more of the output than memory does
+        let file = work_dir_base.join("broken").join(build_log::LOG_FILE_NAME);
+        let saved: Value = serde_json::from_slice(&std::fs::read(file).unwrap()).unwrap();
+        let saved_stdout = saved["steps"][0]["stdout"].as_str().unwrap();
+        assert!(saved_stdout.len() > stdout.len());
+        assert!(saved_stdout.contains("bytes truncated ...]"));
+
+        let _ = std::fs::remove_dir_all(&work_dir_base);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_serve_background_on_ephemeral_port() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let handle = echo_server(Hooks::default())
+            .await
+            .serve_background(0)
+            .await
+            .unwrap();
+        let port = handle.local_addr().port();
+        assert_ne!(port, 0);
+
+        let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains(&format!("\"port\":{}", port)));
+
+        handle.shutdown(true).await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_log_stream_replays_and_follows_stderr() {
+        use futures_util::StreamExt;
+
+        let script = "echo starting >&2; read request; echo \"got $request\" >&2; sleep 5";
+        let server = test_server("sh", &["-c", script], Hooks::default()).await;
+        let stderr = server
+            .server_state
+            .provisioner
+            .provisioned()
+            .and_then(|provisioned| provisioned.stderr.clone())
+            .unwrap();
+        for _ in 0..50 {
+            if !stderr.lines().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let router = server.create_router();
+
+        let (status, body) = send(
+            router.clone(),
+            Request::get("/admin/servers/echo/logs/stream?include=access")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["message"].as_str().unwrap().contains("ACCESS_LOG"));
+
+        let response = router
+            .clone()
+            .oneshot(
+                Request::get("/admin/servers/echo/logs/stream?since=1h")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut frames = response.into_body().into_data_stream();
+        async fn next_frame(frames: &mut axum::body::BodyDataStream) -> String {
+            let frame = tokio::time::timeout(Duration::from_secs(5), frames.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            String::from_utf8(frame.to_vec()).unwrap()
+        }
+
+        let replayed = next_frame(&mut frames).await;
+        assert!(replayed.contains("event: stderr"));
+        assert!(replayed.contains(r#""line":"starting""#));
+
+        tokio::spawn(post_command(
+            router,
+            serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/list" }),
+        ));
+        let live = next_frame(&mut frames).await;
+        assert!(live.contains(r#""type":"stderr""#));
+        assert!(live.contains("got "));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_server_handle_drop_and_abort() {
+        async fn index_status(addr: std::net::SocketAddr) -> Option<String> {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+            let mut stream = tokio::net::TcpStream::connect(addr).await.ok()?;
+            stream
+                .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                .await
+                .ok()?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.ok()?;
+            response.lines().next().map(str::to_string)
+        }
+
+        // Dropping the handle leaves the server running
+        let handle = echo_server(Hooks::default())
+            .await
+            .serve_background(0)
+            .await
+            .unwrap();
+        let addr = handle.local_addr();
+        drop(handle);
+        tokio::task::yield_now().await;
+        assert_eq!(index_status(addr).await.as_deref(), Some("HTTP/1.1 200 OK"));
+
+        // A forced shutdown closes the listener
+        let handle = echo_server(Hooks::default())
+            .await
+            .serve_background(0)
+            .await
+            .unwrap();
+        let addr = handle.local_addr();
+        handle.shutdown(false).await.unwrap();
+        assert!(index_status(addr).await.is_none());
+
+        let handle = echo_server(Hooks::default())
+            .await
+            .serve_background(0)
+            .await
+            .unwrap()
+            .abort_on_drop(true);
+        let addr = handle.local_addr();
+        drop(handle);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(index_status(addr).await.is_none());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_graceful_shutdown_closes_event_streams() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let server = echo_server(Hooks::default()).await;
+        let streams = Arc::clone(&server.server_state.streams);
+        let handle = server.serve_background(0).await.unwrap();
+        let mut stream = tokio::net::TcpStream::connect(handle.local_addr())
+            .await
+            .unwrap();
+        stream
+            .write_all(b"GET /api/v1/elicitations/events HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut head = [0; 512];
+        let read = stream.read(&mut head).await.unwrap();
+        assert!(String::from_utf8_lossy(&head[..read]).starts_with("HTTP/1.1 200"));
+        assert_eq!(streams.snapshot().open, 1);
+
+        // The stream ends with a close event, and the shutdown does not wait on it
+        tokio::time::timeout(Duration::from_secs(5), handle.shutdown(true))
+            .await
+            .unwrap()
+            .unwrap();
+        let mut rest = String::new();
+        stream.read_to_string(&mut rest).await.unwrap();
+        assert!(rest.contains("event: close"));
+        assert!(rest.contains(r#""reason":"shutdown""#));
+        assert_eq!(streams.snapshot().closed["shutdown"], 1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_graceful_shutdown_runs_phases_within_budget() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // The child reads requests and never answers
+        let mut server = test_server("sleep", &["600"], Hooks::default()).await;
+        server.server_state.shutdown = ShutdownConfig {
+            grace_period_secs: 1,
+            phase_timeout_secs: 1,
+        };
+        let budget = server.server_state.shutdown.budget();
+        let state = server.server_state.clone();
+        let pid = state.transport.lock().await.pid().unwrap();
+        let handle = server.serve_background(0).await.unwrap();
+        let addr = handle.local_addr();
+
+        let mut events = tokio::net::TcpStream::connect(addr).await.unwrap();
+        events
+            .write_all(b"GET /api/v1/elicitations/events HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut head = [0; 512];
+        let read = events.read(&mut head).await.unwrap();
+        assert!(String::from_utf8_lossy(&head[..read]).starts_with("HTTP/1.1 200"));
+
+        // One request waits on the child, two more queue behind it
+        let requests: Vec<_> = (1..=3)
+            .map(|id| {
+                tokio::spawn(async move {
+                    let command = serde_json::json!({ "jsonrpc": "2.0", "id": id, "method": "ping" });
+                    let body = serde_json::json!({ "command": command.to_string() }).to_string();
+                    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+                    let request = format!(
+                        "POST /api/v1 HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    );
+                    stream.write_all(request.as_bytes()).await.unwrap();
+                    let mut response = String::new();
+                    stream.read_to_string(&mut response).await.unwrap();
+                    response
+                })
+            })
+            .collect();
+        while state.inflight.len() < 3 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let started = std::time::Instant::now();
+        tokio::time::timeout(budget, handle.shutdown(true))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(started.elapsed() < budget);
+
+        for request in requests {
+            let response = request.await.unwrap();
+            assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+            assert!(response.contains("shutting_down"));
+        }
+        let mut rest = String::new();
+        events.read_to_string(&mut rest).await.unwrap();
+        assert!(rest.contains(r#""reason":"shutdown""#));
+
+        assert!(state.inflight.is_empty());
+        assert!(state
+            .request_queue
+            .acquire(RequestPriority::High)
+            .await
+            .is_err());
+        let alive = std::process::Command::new("kill")
+            .args(["-0", &pid.to_string()])
+            .stderr(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(!alive.success(), "child {} still running", pid);
+    }
+
+    /// Status line and body of a GET over a plain connection
+    async fn http_get(addr: SocketAddr, path: &str) -> (String, String) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let status = response.lines().next().unwrap_or_default().to_string();
+        let body = response
+            .split_once("\r\n\r\n")
+            .map(|(_, body)| body.to_string())
+            .unwrap_or_default();
+        (status, body)
+    }
+
+    fn listener(name: &str, bind: &str, routes: &[RouteGroup]) -> ListenerConfig {
+        ListenerConfig {
+            name: name.to_string(),
+            bind: bind.parse().unwrap(),
+            tls: None,
+            routes: routes.to_vec(),
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_listeners_serve_selected_route_groups() {
+        let mut server = echo_server(Hooks::default()).await;
+        server.listeners = vec![
+            listener(
+                "public",
+                "127.0.0.1:0",
+                &[RouteGroup::Api, RouteGroup::Health],
+            ),
+            listener(
+                "internal",
+                "127.0.0.1:0",
+                &[RouteGroup::Admin, RouteGroup::Metrics, RouteGroup::Health],
+            ),
+        ];
+        let handle = server.serve_all().await.unwrap();
+        let public = handle.local_addr("public").unwrap();
+        let internal = handle.local_addr("internal").unwrap();
+        assert_ne!(public, internal);
+        assert_eq!(handle.listeners().len(), 2);
+
+        let admin = "/admin/servers/echo/inflight";
+        assert_eq!(http_get(internal, admin).await.0, "HTTP/1.1 200 OK");
+        assert_eq!(http_get(public, admin).await.0, "HTTP/1.1 404 Not Found");
+        assert_eq!(
+            http_get(internal, "/api/v1/stats").await.0,
+            "HTTP/1.1 200 OK"
+        );
+        assert_eq!(
+            http_get(public, "/api/v1/stats").await.0,
+            "HTTP/1.1 404 Not Found"
+        );
+        assert_eq!(http_get(public, "/api/v1/info").await.0, "HTTP/1.1 200 OK");
+        assert_eq!(
+            http_get(internal, "/api/v1/info").await.0,
+            "HTTP/1.1 404 Not Found"
+        );
+        for addr in [public, internal] {
+            assert_eq!(http_get(addr, "/ready").await.0, "HTTP/1.1 200 OK");
+        }
+
+        // Each index lists its own port and only the routes it serves
+        let (_, body) = http_get(public, "/").await;
+        let index: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(index["port"], public.port());
+        let paths: Vec<&str> = index["endpoints"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|endpoint| endpoint["path"].as_str().unwrap())
+            .collect();
+        assert!(paths.contains(&"/api/v1"));
+        assert!(!paths.iter().any(|path| path.starts_with("/admin")));
+
+        handle.shutdown(true).await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_listener_bind_failure_names_listener() {
+        let taken = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let taken = taken.local_addr().unwrap().to_string();
+        let mut server = echo_server(Hooks::default()).await;
+        server.listeners = vec![
+            listener("public", "127.0.0.1:0", &RouteGroup::ALL),
+            listener("internal", &taken, &[RouteGroup::Health]),
+        ];
+
+        let error = server.serve_all().await.err().unwrap().to_string();
+        assert!(error.contains("Listener 'internal' failed to start"));
+        assert!(error.contains("already in use"));
+    }
+
+    #[cfg(all(unix, feature = "tls", feature = "reqwest"))]
+    #[tokio::test]
+    async fn test_tls_listener() {
+        let dir = std::env::temp_dir().join(format!("mcp-tls-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        let generated = tokio::process::Command::new("openssl")
+            .args([
+                "req", "-x509", "-newkey", "rsa:2048", "-nodes", "-days", "1",
+            ])
+            .args(["-subj", "/CN=localhost", "-keyout"])
+            .arg(&key)
+            .arg("-out")
+            .arg(&cert)
+            .output()
+            .await;
+        if !generated.is_ok_and(|output| output.status.success()) {
+            eprintln!("openssl unavailable, skipping");
+            return;
+        }
+
+        let mut server = echo_server(Hooks::default()).await;
+        let mut secure = listener("secure", "127.0.0.1:0", &RouteGroup::ALL);
+        secure.tls = Some(listener::TlsConfig {
+            cert: cert.to_string_lossy().into_owned(),
+            key: key.to_string_lossy().into_owned(),
+        });
+        server.listeners = vec![secure];
+        let handle = server.serve_all().await.unwrap();
+        let addr = handle.local_addr("secure").unwrap();
+
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap();
+        let response = client
+            .get(format!("https://localhost:{}/ready", addr.port()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+        handle.shutdown(true).await.unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// Server whose `greet` tool requires `name`, and also `greeting` once
+    /// `upgrade` was called; calls are answered with the number of calls seen
+    async fn schema_server() -> McpHttpServer {
+        let script = r#"version=1; calls=0; while read request; do
+            id=$(echo "$request" | sed -n 's/.*"id":\("[^"]*"\|[0-9]*\).*/\1/p')
+            case "$request" in
+                *tools/list*)
+                    required='"name"'
+                    [ $version = 2 ] && required='"name","greeting"'
+                    printf '{"jsonrpc":"2.0","id":%s,"result":{"tools":[{"name":"greet","inputSchema":{"type":"object","properties":{"name":{"type":"string"}},"required":[%s]}},{"name":"upgrade"}]}}\n' "$id" "$required" ;;
+                *'"name":"upgrade"'*)
+                    version=2
+                    echo '{"jsonrpc":"2.0","method":"notifications/tools/list_changed"}'
+                    printf '{"jsonrpc":"2.0","id":%s,"result":{}}\n' "$id" ;;
+                *)
+                    calls=$((calls + 1))
+                    printf '{"jsonrpc":"2.0","id":%s,"result":{"calls":%s}}\n' "$id" "$calls" ;;
+            esac
+        done"#;
+        let mut server = test_server("sh", &["-c", script], Hooks::default()).await;
+        server.server_state.tool_schemas = Some(Arc::new(ToolSchemas::default()));
+        server
+    }
+
+    fn tool_call(name: &str, arguments: Value) -> Value {
+        serde_json::json!({
+            "jsonrpc": "2.0", "id": 1, "method": "tools/call",
+            "params": { "name": name, "arguments": arguments }
+        })
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_tool_arguments_validated_before_forwarding() {
+        let router = schema_server().await.create_router();
+        let result = |body: &Value| -> Value {
+            serde_json::from_str(body["result"].as_str().unwrap()).unwrap()
+        };
+
+        let (status, body) = post_command(
+            router.clone(),
+            tool_call("greet", serde_json::json!({ "name": "Ada" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(result(&body)["result"]["calls"], 1);
+
+        let (status, body) = post_command(
+            router.clone(),
+            tool_call("greet", serde_json::json!({ "name": 7 })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "invalid_arguments");
+        assert_eq!(body["errors"][0]["instance_path"], "/name");
+        assert_eq!(body["errors"][0]["keyword"], "type");
+        assert!(body["errors"][0]["message"].is_string());
+
+        let (status, body) = post_command(
+            router.clone(),
+            tool_call("gret", serde_json::json!({ "name": "Ada" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body["message"].as_str().unwrap().contains("similar: greet"));
+
+        // Rejected calls never reached the server
+        let (_, body) = post_command(
+            router.clone(),
+            tool_call("greet", serde_json::json!({ "name": "Ada" })),
+        )
+        .await;
+        assert_eq!(result(&body)["result"]["calls"], 2);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_simple_mode_calls_tools_without_json_rpc() {
+        // `search` answers with its arguments as text
+        let script = r#"while read request; do
+            id=$(echo "$request" | sed -n 's/.*"id":\("[^"]*"\|[0-9]*\).*/\1/p')
+            case "$request" in
+                *tools/list*)
+                    printf '{"jsonrpc":"2.0","id":%s,"result":{"tools":[{"name":"search","inputSchema":{"type":"object","properties":{"query":{"type":"string"},"limit":{"type":"integer"},"exact":{"type":"boolean"},"filter":{"type":"object","properties":{"tags":{"type":"array","items":{"type":"string"}},"since":{"type":"integer"}}}}}},{"name":"multi"},{"name":"fail"}]}}\n' "$id" ;;
+                *'"name":"multi"'*)
+                    printf '{"jsonrpc":"2.0","id":%s,"result":{"content":[{"type":"text","text":"first"},{"type":"text","text":"second"}]}}\n' "$id" ;;
+                *'"name":"fail"'*)
+                    printf '{"jsonrpc":"2.0","id":%s,"result":{"isError":true,"content":[{"type":"text","text":"boom"}]}}\n' "$id" ;;
+                *)
+                    arguments=$(echo "$request" | sed 's/.*"arguments":\(.*\)}}$/\1/; s/"/\\"/g')
+                    printf '{"jsonrpc":"2.0","id":%s,"result":{"content":[{"type":"text","text":"%s"}]}}\n' "$id" "$arguments" ;;
+            esac
+        done"#;
+        let mut server = test_server("sh", &["-c", script], Hooks::default()).await;
+        server.server_state.tool_schemas = Some(Arc::new(ToolSchemas::default()));
+        server.server_state.simple_mode = true;
+        let router = server.create_router();
+        let call = |request: Request<Body>| {
+            let router = router.clone();
+            async move {
+                let response = router.oneshot(request).await.unwrap();
+                let status = response.status();
+                let content_type = response.headers().get(header::CONTENT_TYPE).cloned();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (
+                    status,
+                    content_type,
+                    String::from_utf8(body.to_vec()).unwrap(),
+                )
+            }
+        };
+
+        // Query strings are nested by their dots and typed by the inputSchema
+        let uri = "/api/v1/simple/search?query=7&limit=5&exact=true&filter.tags=a&filter.tags=b&filter.since=10";
+        let (status, content_type, body) =
+            call(Request::get(uri).body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type.unwrap(), "text/plain; charset=utf-8");
+        assert_eq!(
+            serde_json::from_str::<Value>(&body).unwrap(),
+            serde_json::json!({
+                "query": "7",
+                "limit": 5,
+                "exact": true,
+                "filter": { "tags": ["a", "b"], "since": 10 }
+            })
+        );
+
+        let request = Request::post("/api/v1/simple/search")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                r#"{"query": "rust", "filter.since": "3", "limit": 2}"#,
+            ))
+            .unwrap();
+        let (_, _, body) = call(request).await;
+        assert_eq!(
+            serde_json::from_str::<Value>(&body).unwrap(),
+            serde_json::json!({ "query": "rust", "filter": { "since": 3 }, "limit": 2 })
+        );
+
+        let request = Request::post("/api/v1/simple/search?exact=false")
+            .header("content-type", "application/x-www-form-urlencoded")
+            .body(Body::from("query=a+b&limit=9"))
+            .unwrap();
+        let (_, _, body) = call(request).await;
+        assert_eq!(
+            serde_json::from_str::<Value>(&body).unwrap(),
+            serde_json::json!({ "query": "a b", "limit": 9, "exact": false })
+        );
+
+        // Text blocks are joined; failures use the structured error body
+        let request = Request::post("/api/v1/simple/multi")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(call(request).await.2, "first\nsecond");
+
+        let request = Request::post("/api/v1/simple/fail")
+            .body(Body::empty())
+            .unwrap();
+        let (status, _, body) = call(request).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["code"], "tool_error");
+        assert!(body["message"].as_str().unwrap().contains("boom"));
+
+        let request = Request::get("/api/v1/simple/search?a=1&a.b=2")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(call(request).await.0, StatusCode::BAD_REQUEST);
+
+        // Without simple_mode the route does not exist
+        let router = echo_server(Hooks::default()).await.create_router();
+        let request = Request::get("/api/v1/simple/search")
+            .body(Body::empty())
+            .unwrap();
+        let (status, _) = send(router, request).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_tool_schemas_refresh_on_list_changed() {
+        let router = schema_server().await.create_router();
+        let greet = || tool_call("greet", serde_json::json!({ "name": "Ada" }));
+
+        let (status, _) = post_command(router.clone(), greet()).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = post_command(router.clone(), tool_call("upgrade", Value::Null)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = post_command(router.clone(), greet()).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["errors"][0]["keyword"], "required");
+        let arguments = serde_json::json!({ "name": "Ada", "greeting": "Hi" });
+        let (status, _) = post_command(router, tool_call("greet", arguments)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_tool_stats_by_listed_tool() {
+        let script = r#"while read request; do
+            id=$(echo "$request" | sed -n 's/.*"id":\([0-9]*\).*/\1/p')
+            case "$request" in
+                *tools/list*) echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"tools\":[{\"name\":\"echo\"},{\"name\":\"fail\"}]}}" ;;
+                *'"name":"echo"'*) echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"content\":[]}}" ;;
+                *'"name":"fail"'*) echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"content\":[],\"isError\":true}}" ;;
+                *) echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"error\":{\"code\":-32602,\"message\":\"Unknown tool\"}}" ;;
+            esac
+        done"#;
+        let router = test_server("sh", &["-c", script], Hooks::default())
+            .await
+            .create_router();
+        let list = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/list" });
+        post_command(router.clone(), list).await;
+        for tool in ["echo", "echo", "fail", "made-up", "also-made-up"] {
+            let (status, _) = post_command(router.clone(), tool_call(tool, Value::Null)).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+
+        let request = Request::get("/api/v1/stats/tools")
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = send(router.clone(), request).await;
+        assert_eq!(status, StatusCode::OK);
+        let tools = &body["tools"];
+        assert_eq!(tools["known_tools"], 2);
+        assert_eq!(tools["tools"]["echo"]["calls"], 2);
+        assert_eq!(tools["tools"]["echo"]["errors"]["tool"], 0);
+        assert_eq!(tools["tools"]["fail"]["errors"]["tool"], 1);
+        assert_eq!(tools["tools"]["fail"]["error_rate"]["tool"], 1.0);
+        assert!(tools["tools"]["fail"]["latency_ms"]["p50"].is_number());
+
+        // Unlisted names share one entry instead of adding their own
+        assert!(tools["tools"].get("made-up").is_none());
+        assert_eq!(tools["other"]["calls"], 2);
+        assert_eq!(tools["other"]["errors"]["rpc"], 2);
+
+        let request = Request::get("/api/v1/stats").body(Body::empty()).unwrap();
+        let (_, body) = send(router, request).await;
+        assert_eq!(body["tools"]["tools"]["echo"]["calls"], 2);
+    }
+
+    #[cfg(all(unix, feature = "reqwest"))]
+    #[tokio::test]
+    async fn test_client_against_running_server() {
+        use crate::client::{ClientError, McpHttpClient, RetryPolicy};
+
+        let script = r#"while read request; do
+            id=$(echo "$request" | sed -n 's/.*"id":\([0-9]*\).*/\1/p')
+            case "$request" in
+                *tools/list*) echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"tools\":[{\"name\":\"echo\"}]}}" ;;
+                *'"name":"echo"'*) echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"content\":[{\"type\":\"text\",\"text\":\"hi\"}]}}" ;;
+                *) echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"error\":{\"code\":-32602,\"message\":\"Unknown tool\"}}" ;;
+            esac
+        done"#;
+        let server = test_server("sh", &["-c", script], Hooks::default()).await;
+        require_key(&server, "secret");
+        let handle = server
+            .serve_background(0)
+            .await
+            .unwrap()
+            .abort_on_drop(true);
+        let base_url = format!("http://{}", handle.local_addr());
+
+        let client = McpHttpClient::new(&base_url, Some("secret"))
+            .unwrap()
+            .with_retry(RetryPolicy::none());
+        let tools = client.list_tools().await.unwrap();
+        assert_eq!(tools[0]["name"], "echo");
+        let result = client
+            .call_tool("echo", serde_json::json!({ "text": "hi" }))
+            .await
+            .unwrap();
+        assert_eq!(result["content"][0]["text"], "hi");
+
+        let error = client
+            .call_tool("missing", serde_json::json!({}))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ClientError::JsonRpc { code: -32602, .. }));
+        let error = client.query("not json").await.unwrap_err();
+        assert!(
+            matches!(error, ClientError::InvalidCommand { code, .. } if code == "invalid_json")
+        );
+
+        let anonymous = McpHttpClient::new(&base_url, None).unwrap();
+        let error = anonymous.list_tools().await.unwrap_err();
+        assert!(matches!(error, ClientError::Unauthorized { .. }));
+    }
+
+    #[cfg(all(unix, feature = "reqwest"))]
+    #[tokio::test]
+    async fn test_client_follows_and_answers_elicitations() {
+        use crate::client::McpHttpClient;
+        use futures_util::StreamExt;
+
+        let handle = eliciting_server(ElicitationRegistry::default())
+            .await
+            .serve_background(0)
+            .await
+            .unwrap()
+            .abort_on_drop(true);
+        let client = McpHttpClient::new(&format!("http://{}", handle.local_addr()), None).unwrap();
+
+        let events = client.elicitations().await.unwrap();
+        let tool_call = tokio::spawn({
+            let client = client.clone();
+            async move { client.call_tool("ask", serde_json::json!({})).await }
+        });
+        futures_util::pin_mut!(events);
+        let elicitation = events.next().await.unwrap().unwrap();
+        assert_eq!(elicitation.params["message"], "Name?");
+
+        let answer = serde_json::json!({ "action": "accept", "content": { "name": "Ada" } });
+        client
+            .answer_elicitation(&elicitation.id, answer.clone())
+            .await
+            .unwrap();
+        let result = tool_call.await.unwrap().unwrap();
+        assert_eq!(result["reply"]["result"], answer);
+    }
+
+    #[cfg(all(unix, feature = "ui"))]
+    #[tokio::test]
+    async fn test_ui_page_is_public_and_bootstrap_needs_the_key() {
+        let server = echo_server(Hooks::default()).await;
+        require_key(&server, "secret");
+        let router = server.create_router();
+        let get = |path: &str, key: Option<&str>| {
+            let mut request = Request::get(path);
+            if let Some(key) = key {
+                request = request.header("authorization", format!("Bearer {}", key));
+            }
+            request.body(Body::empty()).unwrap()
+        };
+
+        for (path, content_type) in [
+            ("/ui", "text/html; charset=utf-8"),
+            ("/ui/app.js", "text/javascript; charset=utf-8"),
+            ("/ui/style.css", "text/css; charset=utf-8"),
+        ] {
+            let response = router.clone().oneshot(get(path, None)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{}", path);
+            assert_eq!(response.headers()[header::CONTENT_TYPE], content_type);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert!(!body.is_empty());
+            // Nothing is loaded from another origin
+            assert!(
+                !String::from_utf8_lossy(&body).contains("https://"),
+                "{}",
+                path
+            );
+        }
+        let response = router.clone().oneshot(get("/ui", None)).await.unwrap();
+        assert!(response.headers()[header::CONTENT_SECURITY_POLICY]
+            .to_str()
+            .unwrap()
+            .starts_with("default-src 'self'"));
+
+        let (status, _) = send(router.clone(), get("/ui/api/bootstrap", None)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, body) = send(router.clone(), get("/ui/api/bootstrap", Some("secret"))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["server"], "echo");
+        assert_eq!(body["servers"], serde_json::json!(["echo"]));
+        assert_eq!(body["provisioning"], "provisioned");
+        assert_eq!(body["capabilities"]["simple_mode"], false);
+
+        let (_, body) = send(router, get("/", None)).await;
+        assert!(body["endpoints"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|endpoint| endpoint["path"] == "/ui"));
+    }
+
+    #[cfg(all(unix, not(feature = "ui")))]
+    #[tokio::test]
+    async fn test_ui_absent_without_feature() {
+        let router = echo_server(Hooks::default()).await.create_router();
+        for path in ["/ui", "/ui/app.js", "/ui/api/bootstrap"] {
+            let request = Request::get(path).body(Body::empty()).unwrap();
+            let (status, _) = send(router.clone(), request).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{}", path);
+        }
+        let request = Request::get("/").body(Body::empty()).unwrap();
+        let (_, body) = send(router, request).await;
+        assert!(!body.to_string().contains("\"/ui\""));
+    }
+}
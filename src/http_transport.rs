@@ -0,0 +1,193 @@
+//! Streamable HTTP transport for upstream MCP servers
+//!
+//! Each message is POSTed to the server's MCP endpoint. Responses arrive
+//! either as a JSON body or as a `text/event-stream` whose `data:` events
+//! are queued and handed out by [`McpTransport::receive`].
+
+use crate::error::{McpCoreError, McpCoreResult};
+use crate::transport::{McpTransport, NotificationBuffer};
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, CONTENT_TYPE};
+use std::collections::{HashMap, VecDeque};
+
+/// Header carrying the session assigned by the server
+const SESSION_HEADER: &str = "Mcp-Session-Id";
+
+/// MCP transport over Streamable HTTP
+pub struct HttpSseTransport {
+    client: reqwest::Client,
+    url: String,
+    session_id: Option<String>,
+    pending: VecDeque<String>,
+    notifications: NotificationBuffer,
+    closed: bool,
+}
+
+impl HttpSseTransport {
+    /// Create a transport for the MCP endpoint at `url`
+    pub fn new(url: &str, headers: &HashMap<String, String>) -> McpCoreResult<Self> {
+        let mut default_headers = HeaderMap::new();
+        for (name, value) in headers {
+            let name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| {
+                McpCoreError::ConfigurationError {
+                    message: format!("Invalid transport header name '{}': {}", name, e),
+                }
+            })?;
+            let value =
+                HeaderValue::from_str(value).map_err(|e| McpCoreError::ConfigurationError {
+                    message: format!("Invalid value for transport header '{}': {}", name, e),
+                })?;
+            default_headers.insert(name, value);
+        }
+
+        let client = reqwest::Client::builder()
+            .default_headers(default_headers)
+            .build()
+            .map_err(|e| McpCoreError::ConfigurationError {
+                message: format!("Failed to build HTTP client: {}", e),
+            })?;
+
+        Ok(Self {
+            client,
+            url: url.to_string(),
+            session_id: None,
+            pending: VecDeque::new(),
+            notifications: NotificationBuffer::default(),
+            closed: false,
+        })
+    }
+}
+
+#[async_trait]
+impl McpTransport for HttpSseTransport {
+    async fn send(&mut self, message: &str) -> McpCoreResult<()> {
+        tracing::debug!(
+            "Sending {} bytes to MCP server at {}",
+            message.len(),
+            self.url
+        );
+
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header(ACCEPT, "application/json, text/event-stream")
+            .header(CONTENT_TYPE, "application/json")
+            .body(message.to_string());
+        if let Some(session_id) = &self.session_id {
+            request = request.header(SESSION_HEADER, session_id);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| McpCoreError::ProcessError {
+                message: format!("Failed to send request to {}: {}", self.url, e),
+            })?;
+
+        if let Some(session_id) = response
+            .headers()
+            .get(SESSION_HEADER)
+            .and_then(|value| value.to_str().ok())
+        {
+            self.session_id = Some(session_id.to_string());
+        }
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(McpCoreError::ProcessError {
+                message: format!("MCP server at {} returned HTTP {}", self.url, status),
+            });
+        }
+
+        // Notifications are acknowledged with 202 and no body
+        if status == reqwest::StatusCode::ACCEPTED {
+            return Ok(());
+        }
+
+        let is_event_stream = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("text/event-stream"));
+        let body = response
+            .text()
+            .await
+            .map_err(|e| McpCoreError::ProcessError {
+                message: format!("Failed to read response from {}: {}", self.url, e),
+            })?;
+
+        if is_event_stream {
+            self.pending.extend(parse_sse_events(&body));
+        } else if !body.trim().is_empty() {
+            self.pending.push_back(body.trim().to_string());
+        }
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> McpCoreResult<String> {
+        self.pending
+            .pop_front()
+            .ok_or_else(|| McpCoreError::ProcessError {
+                message: "No pending response from MCP server".to_string(),
+            })
+    }
+
+    async fn shutdown(&mut self) -> McpCoreResult<()> {
+        self.closed = true;
+        let Some(session_id) = self.session_id.take() else {
+            return Ok(());
+        };
+        self.client
+            .delete(&self.url)
+            .header(SESSION_HEADER, session_id)
+            .send()
+            .await
+            .map_err(|e| McpCoreError::ProcessError {
+                message: format!("Failed to close session at {}: {}", self.url, e),
+            })?;
+        Ok(())
+    }
+
+    fn is_alive(&mut self) -> bool {
+        !self.closed
+    }
+
+    fn buffer_notification(&mut self, notification: String) {
+        self.notifications.push(notification);
+    }
+
+    fn drain_notifications(&mut self) -> Vec<String> {
+        self.notifications.drain()
+    }
+}
+
+/// Collect the `data:` payloads of a server-sent event stream
+fn parse_sse_events(body: &str) -> Vec<String> {
+    let mut events = Vec::new();
+    let mut data: Vec<&str> = Vec::new();
+    for line in body.lines() {
+        if line.is_empty() {
+            if !data.is_empty() {
+                events.push(data.join("\n"));
+                data.clear();
+            }
+        } else if let Some(value) = line.strip_prefix("data:") {
+            data.push(value.strip_prefix(' ').unwrap_or(value));
+        }
+    }
+    if !data.is_empty() {
+        events.push(data.join("\n"));
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sse_events() {
+        let body = "event: message\ndata: {\"id\":1}\n\n: comment\ndata: {\"id\":\ndata: 2}\n";
+        assert_eq!(parse_sse_events(body), vec!["{\"id\":1}", "{\"id\":\n2}"]);
+    }
+}
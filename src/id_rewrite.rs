@@ -0,0 +1,149 @@
+//! Rewriting of JSON-RPC ids between HTTP clients and the MCP server
+//!
+//! Independent HTTP clients pick their ids without coordination, so two of
+//! them may both send `"id": 1`. A late response to one would then match the
+//! other's request. Before forwarding, each request id is replaced with a
+//! gateway-unique number, and the response's id is mapped back to exactly
+//! what the client sent: string, number, or null. Notifications carry no id
+//! and pass through untouched; each element of a batch is rewritten
+//! independently.
+
+use serde_json::Value;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Issues gateway-unique ids for forwarded requests
+#[derive(Debug, Default)]
+pub struct IdRewriter {
+    next_id: AtomicU64,
+}
+
+/// Ids replaced in one forwarded message, for restoring them in the response
+#[derive(Debug, Default)]
+pub struct RewrittenIds {
+    /// Internal id and the client's original id
+    ids: Vec<(u64, Value)>,
+}
+
+impl IdRewriter {
+    /// Replace the ids of the requests in `message` with unique internal ids
+    pub fn rewrite(&self, message: &mut Value) -> RewrittenIds {
+        let mut rewritten = RewrittenIds::default();
+        match message {
+            Value::Array(batch) => {
+                for element in batch {
+                    self.rewrite_one(element, &mut rewritten);
+                }
+            }
+            other => self.rewrite_one(other, &mut rewritten),
+        }
+        rewritten
+    }
+
+    fn rewrite_one(&self, message: &mut Value, rewritten: &mut RewrittenIds) {
+        let Some(object) = message.as_object_mut() else {
+            return;
+        };
+        if !object.contains_key("method") {
+            return;
+        }
+        let Some(id) = object.get_mut("id") else {
+            return;
+        };
+        let internal = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let original = std::mem::replace(id, Value::from(internal));
+        rewritten.ids.push((internal, original));
+    }
+}
+
+impl RewrittenIds {
+    /// Internal id of a single rewritten request, for matching its response
+    pub fn request_id(&self) -> Option<Value> {
+        match self.ids.as_slice() {
+            [(internal, _)] => Some(Value::from(*internal)),
+            _ => None,
+        }
+    }
+
+    /// Put the client's ids back into a response or batch of responses
+    pub fn restore(&self, message: &mut Value) {
+        match message {
+            Value::Array(batch) => {
+                for element in batch {
+                    self.restore_one(element);
+                }
+            }
+            other => self.restore_one(other),
+        }
+    }
+
+    fn restore_one(&self, message: &mut Value) {
+        let Some(id) = message.get_mut("id") else {
+            return;
+        };
+        let Some(internal) = id.as_u64() else {
+            return;
+        };
+        if let Some((_, original)) = self.ids.iter().find(|(known, _)| *known == internal) {
+            *id = original.clone();
+        }
+    }
+
+    /// Whether no id was rewritten
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ids_round_trip() {
+        let rewriter = IdRewriter::default();
+        for original in [
+            serde_json::json!("req-1"),
+            Value::Null,
+            serde_json::json!(1),
+        ] {
+            let mut message =
+                serde_json::json!({ "jsonrpc": "2.0", "id": original, "method": "tools/list" });
+            let rewritten = rewriter.rewrite(&mut message);
+            let internal = rewritten.request_id().unwrap();
+            assert_eq!(message["id"], internal);
+            assert_ne!(internal, original);
+
+            let mut response =
+                serde_json::json!({ "jsonrpc": "2.0", "id": internal, "result": {} });
+            rewritten.restore(&mut response);
+            assert_eq!(response["id"], original);
+        }
+    }
+
+    #[test]
+    fn test_batch_and_notifications() {
+        let rewriter = IdRewriter::default();
+        let original = serde_json::json!([
+            { "jsonrpc": "2.0", "id": 1, "method": "tools/list" },
+            { "jsonrpc": "2.0", "method": "notifications/initialized" },
+            { "jsonrpc": "2.0", "id": 1, "method": "prompts/list" },
+        ]);
+        let mut message = original.clone();
+        let rewritten = rewriter.rewrite(&mut message);
+        assert_eq!(rewritten.request_id(), None);
+        assert_ne!(message[0]["id"], message[2]["id"]);
+        assert_eq!(message[1], original[1]);
+
+        // Responses may come back in any order
+        let mut responses = serde_json::json!([
+            { "jsonrpc": "2.0", "id": message[2]["id"], "result": "prompts" },
+            { "jsonrpc": "2.0", "id": message[0]["id"], "result": "tools" },
+        ]);
+        rewritten.restore(&mut responses);
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(responses[1]["id"], 1);
+
+        let mut notification = serde_json::json!({ "jsonrpc": "2.0", "method": "ping" });
+        assert!(rewriter.rewrite(&mut notification).is_empty());
+    }
+}
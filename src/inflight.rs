@@ -0,0 +1,499 @@
+//! Registry of in-flight requests for debugging stuck servers
+//!
+//! Every request forwarded to the MCP server is registered for its whole
+//! lifetime. The returned [`InflightGuard`] removes the entry when dropped,
+//! so completed, timed-out, and cancelled requests all leave the registry.
+//! Each entry also carries a deadline: a sweeper task removes entries past
+//! it and fails their requests, and the registry refuses new entries beyond
+//! its maximum size.
+
+use crate::error::{McpCoreError, McpCoreResult};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::{oneshot, Notify};
+use tokio::task::JoinHandle;
+
+/// Default time a request may spend queued and waiting for its response
+pub const DEFAULT_REQUEST_DEADLINE: Duration = Duration::from_secs(600);
+
+/// Longest interval between sweeps for expired requests
+const MAX_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// `Retry-After` of requests refused because the registry is full
+const FULL_RETRY_AFTER_SECS: u64 = 1;
+
+/// Why an in-flight request was ended early
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbortReason {
+    /// Aborted through the admin API
+    Aborted,
+    /// Removed by the sweeper after its deadline
+    Expired,
+    /// Cancelled because the gateway is shutting down
+    Shutdown,
+    /// Cancelled by the client's `notifications/cancelled`
+    Cancelled,
+}
+
+impl AbortReason {
+    /// Reason sent to the MCP server in `notifications/cancelled`
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Aborted => "Aborted by administrator",
+            Self::Expired => "Request deadline expired",
+            Self::Shutdown => "Gateway shutting down",
+            Self::Cancelled => "Cancelled by client",
+        }
+    }
+}
+
+/// Phase of an in-flight request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InflightPhase {
+    /// Waiting for exclusive access to the MCP process
+    Queued,
+    /// Being written to the MCP process
+    Sent,
+    /// Written and waiting for the response
+    AwaitingResponse,
+}
+
+/// Serializable view of an in-flight request
+#[derive(Debug, Clone, Serialize)]
+pub struct InflightSnapshot {
+    /// Gateway-assigned request id, used to abort the request
+    pub id: u64,
+    pub jsonrpc_id: Option<Value>,
+    pub method: Option<String>,
+    pub api_key_name: Option<String>,
+    pub phase: InflightPhase,
+    pub started_at: DateTime<Utc>,
+    pub age_ms: u64,
+}
+
+struct InflightEntry {
+    jsonrpc_id: Option<Value>,
+    method: Option<String>,
+    api_key_name: Option<String>,
+
+    /// `Mcp-Session-Id` the request was sent with, scoping its cancellation
+    session: Option<String>,
+    phase: InflightPhase,
+    started: Instant,
+    started_at: DateTime<Utc>,
+    deadline: Instant,
+    abort: Option<oneshot::Sender<AbortReason>>,
+}
+
+/// Registry of requests currently being handled
+pub struct InflightRegistry {
+    next_id: AtomicU64,
+    entries: Mutex<HashMap<u64, InflightEntry>>,
+
+    /// Entries allowed at once, if limited
+    max_entries: Option<usize>,
+    deadline: Duration,
+
+    /// Entries removed by the sweeper since startup
+    expired: AtomicU64,
+
+    /// Woken when the last entry is removed
+    idle: Notify,
+}
+
+impl Default for InflightRegistry {
+    fn default() -> Self {
+        Self::new(None, DEFAULT_REQUEST_DEADLINE)
+    }
+}
+
+/// Handle for a registered request; removes the entry on drop
+pub struct InflightGuard {
+    registry: Arc<InflightRegistry>,
+    id: u64,
+}
+
+impl InflightRegistry {
+    /// Registry holding at most `max_entries` requests, each for at most `deadline`
+    pub fn new(max_entries: Option<usize>, deadline: Duration) -> Self {
+        Self {
+            next_id: AtomicU64::new(0),
+            entries: Mutex::new(HashMap::new()),
+            max_entries,
+            deadline,
+            expired: AtomicU64::new(0),
+            idle: Notify::new(),
+        }
+    }
+
+    /// Register a new request in the queued phase
+    ///
+    /// The returned receiver resolves when the request is aborted or
+    /// expires. Fails with [`McpCoreError::Overloaded`] when the registry
+    /// is full.
+    pub fn register(
+        self: &Arc<Self>,
+        jsonrpc_id: Option<Value>,
+        method: Option<String>,
+        api_key_name: Option<String>,
+        session: Option<String>,
+    ) -> McpCoreResult<(InflightGuard, oneshot::Receiver<AbortReason>)> {
+        self.register_with_deadline(jsonrpc_id, method, api_key_name, session, self.deadline)
+    }
+
+    /// Register a new request that expires after `deadline` instead of the
+    /// registry's
+    pub fn register_with_deadline(
+        self: &Arc<Self>,
+        jsonrpc_id: Option<Value>,
+        method: Option<String>,
+        api_key_name: Option<String>,
+        session: Option<String>,
+        deadline: Duration,
+    ) -> McpCoreResult<(InflightGuard, oneshot::Receiver<AbortReason>)> {
+        let (abort_tx, abort_rx) = oneshot::channel();
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(max_entries) = self.max_entries {
+            if entries.len() >= max_entries {
+                return Err(McpCoreError::Overloaded {
+                    message: format!("Too many in-flight requests (limit {})", max_entries),
+                    retry_after_secs: FULL_RETRY_AFTER_SECS,
+                });
+            }
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let started = Instant::now();
+        entries.insert(
+            id,
+            InflightEntry {
+                jsonrpc_id,
+                method,
+                api_key_name,
+                session,
+                phase: InflightPhase::Queued,
+                started,
+                started_at: Utc::now(),
+                deadline: started + deadline,
+                abort: Some(abort_tx),
+            },
+        );
+
+        let guard = InflightGuard {
+            registry: Arc::clone(self),
+            id,
+        };
+        Ok((guard, abort_rx))
+    }
+
+    /// Remove entries past their deadline and fail their requests
+    ///
+    /// Returns the number of entries removed.
+    pub fn sweep(&self) -> usize {
+        let now = Instant::now();
+        let expired: Vec<InflightEntry> = {
+            let mut entries = self.entries.lock().unwrap();
+            let ids: Vec<u64> = entries
+                .iter()
+                .filter(|(_, entry)| entry.deadline <= now)
+                .map(|(id, _)| *id)
+                .collect();
+            ids.iter().filter_map(|id| entries.remove(id)).collect()
+        };
+
+        for entry in &expired {
+            tracing::warn!(
+                "In-flight {} request expired after {}ms",
+                entry.method.as_deref().unwrap_or("unknown"),
+                now.duration_since(entry.started).as_millis()
+            );
+        }
+        let count = expired.len();
+        self.expired.fetch_add(count as u64, Ordering::Relaxed);
+        if count > 0 && self.is_empty() {
+            self.idle.notify_waiters();
+        }
+        for sender in expired.into_iter().filter_map(|entry| entry.abort) {
+            let _ = sender.send(AbortReason::Expired);
+        }
+        count
+    }
+
+    /// Sweep for expired entries in the background until the registry is dropped
+    pub fn spawn_sweeper(self: &Arc<Self>) -> JoinHandle<()> {
+        let registry: Weak<Self> = Arc::downgrade(self);
+        let interval = (self.deadline / 2).min(MAX_SWEEP_INTERVAL);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                match registry.upgrade() {
+                    Some(registry) => {
+                        registry.sweep();
+                    }
+                    None => return,
+                }
+            }
+        })
+    }
+
+    /// Entries removed by the sweeper since startup
+    pub fn expired(&self) -> u64 {
+        self.expired.load(Ordering::Relaxed)
+    }
+
+    /// Snapshot of all in-flight requests, longest-waiting first
+    pub fn snapshot(&self) -> Vec<InflightSnapshot> {
+        let entries = self.entries.lock().unwrap();
+        let mut snapshot: Vec<InflightSnapshot> = entries
+            .iter()
+            .map(|(id, entry)| InflightSnapshot {
+                id: *id,
+                jsonrpc_id: entry.jsonrpc_id.clone(),
+                method: entry.method.clone(),
+                api_key_name: entry.api_key_name.clone(),
+                phase: entry.phase,
+                started_at: entry.started_at,
+                age_ms: entry.started.elapsed().as_millis() as u64,
+            })
+            .collect();
+        snapshot.sort_by(|a, b| b.age_ms.cmp(&a.age_ms).then(a.id.cmp(&b.id)));
+        snapshot
+    }
+
+    #[cfg(all(test, feature = "http-server"))]
+    pub(crate) fn capacity(&self) -> usize {
+        self.entries.lock().unwrap().capacity()
+    }
+
+    /// Number of in-flight requests
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Number of requests waiting for the MCP process
+    pub fn queued(&self) -> usize {
+        self.entries
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|entry| entry.phase == InflightPhase::Queued)
+            .count()
+    }
+
+    /// Whether no request is in flight
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Age in milliseconds of the longest-waiting request, if any
+    pub fn longest_waiting_ms(&self) -> Option<u64> {
+        self.entries
+            .lock()
+            .unwrap()
+            .values()
+            .map(|entry| entry.started.elapsed().as_millis() as u64)
+            .max()
+    }
+
+    /// Wait until no request is in flight
+    pub async fn wait_idle(&self) {
+        loop {
+            let idle = self.idle.notified();
+            tokio::pin!(idle);
+            idle.as_mut().enable();
+            if self.is_empty() {
+                return;
+            }
+            idle.await;
+        }
+    }
+
+    /// End every in-flight request for `reason`, returning how many were
+    /// not yet aborted
+    pub fn abort_all(&self, reason: AbortReason) -> usize {
+        let senders: Vec<_> = self
+            .entries
+            .lock()
+            .unwrap()
+            .values_mut()
+            .filter_map(|entry| entry.abort.take())
+            .collect();
+        senders
+            .into_iter()
+            .filter(|sender| !sender.is_closed())
+            .map(|sender| sender.send(reason))
+            .filter(Result::is_ok)
+            .count()
+    }
+
+    /// Cancel the requests with JSON-RPC id `jsonrpc_id` sent with
+    /// `api_key_name` in `session`, returning how many were not yet aborted
+    ///
+    /// Clients sharing a key each pick their own ids, so the id alone does
+    /// not tell whose request it is; requests without a session only match
+    /// a cancellation without one.
+    pub fn cancel(
+        &self,
+        jsonrpc_id: &Value,
+        api_key_name: Option<&str>,
+        session: Option<&str>,
+    ) -> usize {
+        let senders: Vec<_> = self
+            .entries
+            .lock()
+            .unwrap()
+            .values_mut()
+            .filter(|entry| {
+                entry.jsonrpc_id.as_ref() == Some(jsonrpc_id)
+                    && entry.api_key_name.as_deref() == api_key_name
+                    && entry.session.as_deref() == session
+            })
+            .filter_map(|entry| entry.abort.take())
+            .collect();
+        senders
+            .into_iter()
+            .map(|sender| sender.send(AbortReason::Cancelled))
+            .filter(Result::is_ok)
+            .count()
+    }
+
+    /// Abort a request, returning whether it was found and not yet aborted
+    pub fn abort(&self, id: u64) -> bool {
+        let sender = self
+            .entries
+            .lock()
+            .unwrap()
+            .get_mut(&id)
+            .and_then(|entry| entry.abort.take());
+        match sender {
+            Some(sender) => sender.send(AbortReason::Aborted).is_ok(),
+            None => false,
+        }
+    }
+}
+
+impl InflightGuard {
+    /// Gateway-assigned id of this request
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Update the phase of this request
+    pub fn set_phase(&self, phase: InflightPhase) {
+        if let Some(entry) = self.registry.entries.lock().unwrap().get_mut(&self.id) {
+            entry.phase = phase;
+        }
+    }
+}
+
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        let mut entries = self.registry.entries.lock().unwrap();
+        entries.remove(&self.id);
+        if entries.is_empty() {
+            self.registry.idle.notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guard_removes_entry_on_drop() {
+        let registry = Arc::new(InflightRegistry::default());
+        let (guard, _abort) = registry
+            .register(
+                Some(serde_json::json!(1)),
+                Some("tools/call".to_string()),
+                None,
+                None,
+            )
+            .unwrap();
+        guard.set_phase(InflightPhase::AwaitingResponse);
+
+        let snapshot = registry.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].phase, InflightPhase::AwaitingResponse);
+        assert_eq!(snapshot[0].method.as_deref(), Some("tools/call"));
+        assert!(registry.longest_waiting_ms().is_some());
+
+        drop(guard);
+        assert!(registry.is_empty());
+        assert!(registry.longest_waiting_ms().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_abort_signals_request_once() {
+        let registry = Arc::new(InflightRegistry::default());
+        let (guard, abort) = registry.register(None, None, None, None).unwrap();
+
+        assert!(registry.abort(guard.id()));
+        assert!(!registry.abort(guard.id()));
+        assert_eq!(abort.await.unwrap(), AbortReason::Aborted);
+        assert!(!registry.abort(guard.id() + 1));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_matches_id_api_key_and_session() {
+        let registry = Arc::new(InflightRegistry::default());
+        let register = |id: i64, key: &str, session: Option<&str>| {
+            registry
+                .register(
+                    Some(serde_json::json!(id)),
+                    None,
+                    Some(key.to_string()),
+                    session.map(str::to_string),
+                )
+                .unwrap()
+        };
+        let (_mine, mine) = register(1, "alice", Some("a"));
+        let (_other_key, mut other_key) = register(1, "bob", Some("a"));
+        let (_other_id, mut other_id) = register(2, "alice", Some("a"));
+        let (_other_session, mut other_session) = register(1, "alice", Some("b"));
+        let (_no_session, mut no_session) = register(1, "alice", None);
+
+        let cancel = |session| registry.cancel(&serde_json::json!(1), Some("alice"), session);
+        assert_eq!(cancel(Some("a")), 1);
+        assert_eq!(cancel(Some("a")), 0);
+        assert_eq!(mine.await.unwrap(), AbortReason::Cancelled);
+        assert!(other_key.try_recv().is_err());
+        assert!(other_id.try_recv().is_err());
+        assert!(other_session.try_recv().is_err());
+        assert!(no_session.try_recv().is_err());
+
+        assert_eq!(cancel(None), 1);
+        assert_eq!(no_session.await.unwrap(), AbortReason::Cancelled);
+        assert!(other_session.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_full_registry_rejects_and_sweep_expires() {
+        let registry = Arc::new(InflightRegistry::new(Some(2), Duration::from_millis(20)));
+        let (first, first_abort) = registry.register(None, None, None, None).unwrap();
+        let (_second, second_abort) = registry.register(None, None, None, None).unwrap();
+        let error = registry.register(None, None, None, None).err().unwrap();
+        assert_eq!(error.error_code(), Some("overloaded"));
+
+        assert_eq!(registry.sweep(), 0);
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(registry.sweep(), 2);
+        assert_eq!(first_abort.await.unwrap(), AbortReason::Expired);
+        assert_eq!(second_abort.await.unwrap(), AbortReason::Expired);
+        assert!(registry.is_empty());
+        assert_eq!(registry.expired(), 2);
+
+        // Expired entries free their slots; a late guard drop is harmless
+        drop(first);
+        assert!(registry.register(None, None, None, None).is_ok());
+    }
+}
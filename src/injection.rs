@@ -0,0 +1,283 @@
+//! Header-to-params injection for outgoing MCP requests
+//!
+//! Injection rules copy values from incoming HTTP headers into the JSON-RPC
+//! message before it is forwarded to the MCP server. This allows a caller's
+//! upstream credentials to be supplied per request instead of being baked
+//! into the process environment.
+
+use crate::error::{McpCoreError, McpCoreResult};
+use http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Placeholder written in place of injected values when logging
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Rule copying an HTTP header value into the JSON-RPC message
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ParamInjectionRule {
+    /// Name of the HTTP header to read (case-insensitive)
+    pub header: String,
+
+    /// JSON pointer (RFC 6901) of the target location in the message
+    pub json_pointer: String,
+
+    /// Reject the request with 400 when the header is missing
+    #[serde(default = "default_true")]
+    pub required: bool,
+
+    /// Replace a value already present at the pointer (otherwise keep it)
+    #[serde(default = "default_true")]
+    pub overwrite: bool,
+
+    /// JSON-RPC methods this rule applies to (empty means all methods)
+    #[serde(default)]
+    pub methods: Vec<String>,
+}
+
+/// Result of applying injection rules to a command
+#[derive(Debug)]
+pub struct InjectedCommand {
+    /// Message to forward to the MCP server
+    pub command: String,
+
+    /// Same message with every injected value replaced by [`REDACTED`]
+    pub redacted: String,
+}
+
+impl ParamInjectionRule {
+    /// Whether this rule applies to the given JSON-RPC method
+    fn matches(&self, method: Option<&str>) -> bool {
+        self.methods.is_empty()
+            || method.is_some_and(|m| self.methods.iter().any(|allowed| allowed == m))
+    }
+}
+
+/// Apply injection rules to a JSON-RPC command
+///
+/// Returns `Ok(None)` when no rule applies, so the command can be forwarded
+/// unchanged without being parsed.
+pub fn apply_injection_rules(
+    rules: &[ParamInjectionRule],
+    headers: &HeaderMap,
+    command: &str,
+) -> McpCoreResult<Option<InjectedCommand>> {
+    if rules.is_empty() {
+        return Ok(None);
+    }
+
+    let mut message: Value =
+        serde_json::from_str(command).map_err(|e| McpCoreError::RequestError {
+            message: format!("Command is not valid JSON: {}", e),
+        })?;
+    let method = message
+        .get("method")
+        .and_then(Value::as_str)
+        .map(str::to_string);
+
+    let mut redacted = message.clone();
+    let mut applied = false;
+
+    for rule in rules.iter().filter(|rule| rule.matches(method.as_deref())) {
+        let value = match headers.get(rule.header.as_str()) {
+            Some(value) => value.to_str().map_err(|_| McpCoreError::RequestError {
+                message: format!("Header '{}' contains invalid characters", rule.header),
+            })?,
+            None if rule.required => {
+                tracing::debug!("Missing required injection header '{}'", rule.header);
+                return Err(McpCoreError::RequestError {
+                    message: format!("Missing required header '{}'", rule.header),
+                });
+            }
+            None => continue,
+        };
+
+        let injected = set_pointer(
+            &mut message,
+            &rule.json_pointer,
+            Value::String(value.to_string()),
+            rule.overwrite,
+        )?;
+        if injected {
+            set_pointer(
+                &mut redacted,
+                &rule.json_pointer,
+                Value::String(REDACTED.to_string()),
+                true,
+            )?;
+            tracing::debug!(
+                "Injected header '{}' at '{}'",
+                rule.header,
+                rule.json_pointer
+            );
+            applied = true;
+        }
+    }
+
+    if !applied {
+        return Ok(None);
+    }
+
+    Ok(Some(InjectedCommand {
+        command: message.to_string(),
+        redacted: redacted.to_string(),
+    }))
+}
+
+/// Set `value` at `pointer`, creating intermediate objects as needed
+///
+/// Returns whether the value was written; with `overwrite` disabled an
+/// existing value at the pointer is preserved.
+fn set_pointer(
+    target: &mut Value,
+    pointer: &str,
+    value: Value,
+    overwrite: bool,
+) -> McpCoreResult<bool> {
+    let invalid = |reason: &str| McpCoreError::RequestError {
+        message: format!("Cannot inject at '{}': {}", pointer, reason),
+    };
+
+    if pointer.is_empty() {
+        return Err(invalid("pointer must not be empty"));
+    }
+    if !pointer.starts_with('/') {
+        return Err(invalid("pointer must start with '/'"));
+    }
+
+    let tokens: Vec<String> = pointer[1..]
+        .split('/')
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .collect();
+    let (last, parents) = tokens.split_last().expect("pointer has at least one token");
+
+    let mut current = target;
+    for token in parents {
+        current = match current {
+            Value::Object(map) => map
+                .entry(token.clone())
+                .or_insert_with(|| Value::Object(Map::new())),
+            Value::Array(items) => token
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| items.get_mut(index))
+                .ok_or_else(|| invalid("array index out of range"))?,
+            _ => return Err(invalid("intermediate value is not an object")),
+        };
+        if current.is_null() {
+            *current = Value::Object(Map::new());
+        }
+    }
+
+    match current {
+        Value::Object(map) => {
+            if !overwrite && map.contains_key(last) {
+                return Ok(false);
+            }
+            map.insert(last.clone(), value);
+            Ok(true)
+        }
+        Value::Array(items) => {
+            let slot = last
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| items.get_mut(index))
+                .ok_or_else(|| invalid("array index out of range"))?;
+            if !overwrite {
+                return Ok(false);
+            }
+            *slot = value;
+            Ok(true)
+        }
+        _ => Err(invalid("parent value is not an object")),
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn rule(header: &str, pointer: &str) -> ParamInjectionRule {
+        ParamInjectionRule {
+            header: header.to_string(),
+            json_pointer: pointer.to_string(),
+            required: true,
+            overwrite: true,
+            methods: Vec::new(),
+        }
+    }
+
+    fn headers(name: &'static str, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        headers
+    }
+
+    const TOOL_CALL: &str =
+        r#"{"jsonrpc":"2.0","id":1,"method":"tools/call","params":{"name":"search"}}"#;
+
+    #[test]
+    fn test_injection_creates_intermediate_objects() {
+        let rules = vec![rule("X-Upstream-Token", "/params/arguments/token")];
+        let injected =
+            apply_injection_rules(&rules, &headers("x-upstream-token", "secret"), TOOL_CALL)
+                .unwrap()
+                .unwrap();
+
+        let message: Value = serde_json::from_str(&injected.command).unwrap();
+        assert_eq!(message["params"]["arguments"]["token"], "secret");
+        assert_eq!(message["params"]["name"], "search");
+        assert!(!injected.redacted.contains("secret"));
+        assert!(injected.redacted.contains(REDACTED));
+    }
+
+    #[test]
+    fn test_injection_overwrite_and_preserve() {
+        let command = r#"{"jsonrpc":"2.0","id":1,"method":"tools/call","params":{"arguments":{"token":"client"}}}"#;
+        let headers = headers("x-upstream-token", "secret");
+
+        let mut overwrite = rule("X-Upstream-Token", "/params/arguments/token");
+        let injected = apply_injection_rules(&[overwrite.clone()], &headers, command)
+            .unwrap()
+            .unwrap();
+        let message: Value = serde_json::from_str(&injected.command).unwrap();
+        assert_eq!(message["params"]["arguments"]["token"], "secret");
+
+        overwrite.overwrite = false;
+        let result = apply_injection_rules(&[overwrite], &headers, command).unwrap();
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn test_injection_missing_header() {
+        let mut required = rule("X-Upstream-Token", "/params/arguments/token");
+        let result = apply_injection_rules(&[required.clone()], &HeaderMap::new(), TOOL_CALL);
+        assert!(matches!(result, Err(McpCoreError::RequestError { .. })));
+
+        required.required = false;
+        let result = apply_injection_rules(&[required], &HeaderMap::new(), TOOL_CALL).unwrap();
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn test_injection_method_filter() {
+        let mut scoped = rule("X-Upstream-Token", "/params/arguments/token");
+        scoped.methods = vec!["tools/list".to_string()];
+
+        let result = apply_injection_rules(&[scoped], &HeaderMap::new(), TOOL_CALL).unwrap();
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn test_injection_rejects_non_object_parent() {
+        let rules = vec![rule("X-Upstream-Token", "/params/name/token")];
+        let result =
+            apply_injection_rules(&rules, &headers("x-upstream-token", "secret"), TOOL_CALL);
+        assert!(matches!(result, Err(McpCoreError::RequestError { .. })));
+    }
+}
@@ -0,0 +1,94 @@
+//! MCP Server as HTTP Core
+//!
+//! This crate provides the core HTTP server functionality for converting
+//! Model Context Protocol (MCP) servers to REST API endpoints. It can be used
+//! as a standalone binary or embedded through [`http_server::McpHttpServer`].
+
+#[cfg(feature = "http-server")]
+pub mod access_log;
+#[cfg(feature = "http-server")]
+pub mod admin;
+pub mod artifact_cache;
+pub mod audit;
+#[cfg(feature = "http-server")]
+pub mod auth;
+pub mod balance;
+pub mod breaker;
+pub mod build_cache;
+pub mod build_log;
+pub mod canary;
+pub mod capabilities;
+pub mod capture;
+pub mod child_env;
+#[cfg(feature = "reqwest")]
+pub mod client;
+pub mod client_notifications;
+pub mod config;
+#[cfg(feature = "http-server")]
+mod connection;
+pub mod context_meta;
+pub mod diagnostics;
+pub mod egress;
+pub mod elicitation;
+pub mod error;
+pub mod health_check;
+pub mod history;
+pub mod hooks;
+#[cfg(feature = "http-server")]
+pub mod http_server;
+#[cfg(feature = "reqwest")]
+pub mod http_transport;
+pub mod id_rewrite;
+pub mod inflight;
+pub mod injection;
+pub mod lifecycle;
+pub mod listener;
+pub mod maintenance;
+pub mod manager;
+pub mod method_timeout;
+pub mod notifications;
+pub mod peek;
+pub mod pipeline;
+pub mod platform;
+pub mod presets;
+pub mod priority;
+pub mod process;
+pub mod provision;
+pub mod proxy;
+pub mod quota;
+pub mod recycle;
+#[cfg(feature = "http-server")]
+pub mod render;
+pub mod repo;
+pub mod response_headers;
+pub mod response_schema;
+pub mod response_stream;
+pub mod restart_budget;
+pub mod rewrite;
+mod rotating_file;
+pub mod routes;
+pub mod sandbox;
+pub mod scaffold;
+pub mod server_hooks;
+pub mod server_requests;
+pub mod setup;
+pub mod shedding;
+pub mod shutdown;
+#[cfg(feature = "http-server")]
+pub mod simple;
+pub mod stats;
+pub mod stderr;
+pub mod streaming;
+pub mod strict;
+pub mod template;
+pub mod tenant;
+#[cfg(test)]
+mod test_support;
+pub mod timing;
+pub mod tool_schema;
+pub mod tool_stats;
+pub mod transport;
+#[cfg(feature = "ui")]
+pub mod ui;
+pub mod workdir;
+pub mod workdir_lock;
@@ -0,0 +1,262 @@
+//! Server lifecycle metadata persisted across gateway restarts
+//!
+//! With `persist_lifecycle` set, a small JSON file records how often the
+//! server was started, its last startup failure, the commit it ran, and the
+//! protocol version it negotiated, whether it was drained for maintenance,
+//! and the quota consumption of each API key. The file is read at startup
+//! and rewritten on each start, success, and failure. The start counter decays by half for
+//! every hour since the previous start, so a burst of restarts stands out
+//! while old history fades. A file that cannot be read, parsed, or that was
+//! written by another format version or for another server is ignored with a
+//! warning.
+
+use crate::error::{McpCoreError, McpCoreResult};
+use crate::maintenance::MaintenanceMode;
+use crate::quota::StoredUsage;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Name of the lifecycle file inside a work directory
+pub const LIFECYCLE_FILE_NAME: &str = ".mcp-lifecycle.json";
+
+/// Version of the file format; files of other versions are ignored
+pub const LIFECYCLE_FORMAT_VERSION: u32 = 1;
+
+/// Period after which the recent start count halves
+const START_DECAY_PERIOD_SECS: i64 = 3600;
+
+/// A failed startup
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LifecycleFailure {
+    pub reason: String,
+    pub at: DateTime<Utc>,
+}
+
+/// Lifecycle metadata of one server
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct LifecycleState {
+    pub format_version: u32,
+    pub server_name: String,
+
+    /// Starts, decayed by half per hour since the previous one
+    pub recent_starts: u32,
+    pub total_starts: u64,
+    pub last_start_at: Option<DateTime<Utc>>,
+    pub last_ready_at: Option<DateTime<Utc>>,
+    pub last_failure: Option<LifecycleFailure>,
+
+    /// Commit the server last ran, checked out again after a fresh clone
+    pub commit: Option<String>,
+    pub protocol_version: Option<String>,
+
+    /// Maintenance mode the server was left in
+    #[serde(default)]
+    pub maintenance: Option<MaintenanceMode>,
+
+    /// Quota consumption per API key in the current day and month
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub usage: BTreeMap<String, StoredUsage>,
+}
+
+impl LifecycleState {
+    /// State of a server that was never started
+    pub fn new(server_name: &str) -> Self {
+        Self {
+            format_version: LIFECYCLE_FORMAT_VERSION,
+            server_name: server_name.to_string(),
+            recent_starts: 0,
+            total_starts: 0,
+            last_start_at: None,
+            last_ready_at: None,
+            last_failure: None,
+            commit: None,
+            protocol_version: None,
+            maintenance: None,
+            usage: BTreeMap::new(),
+        }
+    }
+
+    /// Count a start at `now`, decaying the recent count first
+    pub fn record_start(&mut self, now: DateTime<Utc>) {
+        if let Some(last_start_at) = self.last_start_at {
+            let periods = (now - last_start_at).num_seconds() / START_DECAY_PERIOD_SECS;
+            self.recent_starts = self
+                .recent_starts
+                .checked_shr(periods.clamp(0, 32) as u32)
+                .unwrap_or(0);
+        }
+        self.recent_starts = self.recent_starts.saturating_add(1);
+        self.total_starts += 1;
+        self.last_start_at = Some(now);
+    }
+
+    /// Record a successful startup
+    pub fn record_ready(&mut self, commit: Option<String>, protocol_version: &str) {
+        self.last_ready_at = Some(Utc::now());
+        if commit.is_some() {
+            self.commit = commit;
+        }
+        self.protocol_version = Some(protocol_version.to_string());
+    }
+
+    /// Record a failed startup
+    pub fn record_failure(&mut self, error: &McpCoreError) {
+        self.last_failure = Some(LifecycleFailure {
+            reason: error.to_string(),
+            at: Utc::now(),
+        });
+    }
+}
+
+/// Location of a server's lifecycle file
+#[derive(Debug, Clone)]
+pub struct LifecycleFile {
+    path: PathBuf,
+}
+
+impl LifecycleFile {
+    /// File in `state_dir` if given, otherwise in the server's work directory
+    pub fn new(state_dir: Option<&Path>, work_dir: &Path, server_name: &str) -> Self {
+        let path = match state_dir {
+            Some(state_dir) => state_dir.join(format!("{}.lifecycle.json", server_name)),
+            None => work_dir.join(LIFECYCLE_FILE_NAME),
+        };
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read the stored state, or start afresh if it is missing or unusable
+    pub async fn load(&self, server_name: &str) -> LifecycleState {
+        let content = match tokio::fs::read_to_string(&self.path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return LifecycleState::new(server_name)
+            }
+            Err(e) => return self.discard(server_name, &e.to_string()),
+        };
+        let state: LifecycleState = match serde_json::from_str(&content) {
+            Ok(state) => state,
+            Err(e) => return self.discard(server_name, &e.to_string()),
+        };
+        if state.format_version != LIFECYCLE_FORMAT_VERSION {
+            return self.discard(
+                server_name,
+                &format!("format version {}", state.format_version),
+            );
+        }
+        if state.server_name != server_name {
+            return self.discard(
+                server_name,
+                &format!("written for server '{}'", state.server_name),
+            );
+        }
+        state
+    }
+
+    /// Write the state, replacing the file atomically
+    pub async fn save(&self, state: &LifecycleState) -> McpCoreResult<()> {
+        let write_error = |e: std::io::Error| McpCoreError::ProcessError {
+            message: format!(
+                "Failed to write lifecycle file '{}': {}",
+                self.path.display(),
+                e
+            ),
+        };
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(write_error)?;
+        }
+        let temporary = self.path.with_extension("json.tmp");
+        tokio::fs::write(&temporary, serde_json::to_string_pretty(state)?)
+            .await
+            .map_err(write_error)?;
+        tokio::fs::rename(&temporary, &self.path)
+            .await
+            .map_err(write_error)
+    }
+
+    fn discard(&self, server_name: &str, reason: &str) -> LifecycleState {
+        tracing::warn!(
+            "Ignoring lifecycle file '{}' ({})",
+            self.path.display(),
+            reason
+        );
+        LifecycleState::new(server_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("mcp-lifecycle-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_state_round_trips() {
+        let dir = temp_dir("roundtrip");
+        let file = LifecycleFile::new(Some(&dir), Path::new("/nonexistent"), "echo");
+        assert_eq!(file.load("echo").await, LifecycleState::new("echo"));
+
+        let mut state = LifecycleState::new("echo");
+        state.record_start(Utc::now());
+        state.record_failure(&McpCoreError::ProcessError {
+            message: "exited".to_string(),
+        });
+        state.record_ready(Some("abc123".to_string()), "2025-06-18");
+        file.save(&state).await.unwrap();
+
+        let restored = file.load("echo").await;
+        assert_eq!(restored, state);
+        assert!(restored.last_failure.unwrap().reason.contains("exited"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_unusable_files_are_ignored() {
+        let dir = temp_dir("corrupt");
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = LifecycleFile::new(None, &dir, "echo");
+
+        std::fs::write(file.path(), "{ not json").unwrap();
+        assert_eq!(file.load("echo").await, LifecycleState::new("echo"));
+
+        let mut future = LifecycleState::new("echo");
+        future.format_version = LIFECYCLE_FORMAT_VERSION + 1;
+        future.total_starts = 5;
+        std::fs::write(file.path(), serde_json::to_string(&future).unwrap()).unwrap();
+        assert_eq!(file.load("echo").await.total_starts, 0);
+
+        let mut other = LifecycleState::new("other");
+        other.total_starts = 5;
+        std::fs::write(file.path(), serde_json::to_string(&other).unwrap()).unwrap();
+        assert_eq!(file.load("echo").await.total_starts, 0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_recent_starts_decay() {
+        let start = Utc::now();
+        let mut state = LifecycleState::new("echo");
+        for _ in 0..8 {
+            state.record_start(start);
+        }
+        assert_eq!(state.recent_starts, 8);
+
+        state.record_start(start + chrono::Duration::hours(2));
+        assert_eq!(state.recent_starts, 3);
+        state.record_start(start + chrono::Duration::days(30));
+        assert_eq!(state.recent_starts, 1);
+        assert_eq!(state.total_starts, 10);
+    }
+}
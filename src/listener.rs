@@ -0,0 +1,510 @@
+//! Binding the HTTP listeners, with diagnostics and optional port fallback
+//!
+//! Bind failures are reported by cause: a port already in use names the
+//! process holding it where `/proc` allows, and a permission error points at
+//! privileged ports. With fallback enabled, the next free port is used.
+//!
+//! A configuration may describe several listeners, each serving a subset of
+//! the route groups, optionally over TLS (with the `tls` feature).
+//!
+//! Every listener serves HTTP/1.1, and HTTP/2 as well when
+//! [`HttpConfig::http2`] is set: with prior knowledge (h2c) on plain
+//! listeners and through ALPN on TLS ones.
+
+use crate::error::{McpCoreError, McpCoreResult};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use tokio::net::TcpListener;
+
+/// Number of ports above the configured one tried when falling back
+pub const PORT_FALLBACK_RANGE: u16 = 100;
+
+/// Time a client may take to complete the TLS handshake
+#[cfg(feature = "tls")]
+const TLS_HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Group of routes a listener can serve
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteGroup {
+    /// `/api/v1` and its endpoints, except statistics
+    Api,
+    /// `/admin`
+    Admin,
+    /// `/api/v1/stats` and `/api/v1/stats/tools`
+    Metrics,
+    /// The `/` index, `/ready`, and `/version`, without authentication
+    Health,
+}
+
+impl RouteGroup {
+    pub const ALL: [RouteGroup; 4] = [
+        RouteGroup::Api,
+        RouteGroup::Admin,
+        RouteGroup::Metrics,
+        RouteGroup::Health,
+    ];
+
+    /// Group serving `path`
+    pub fn of(path: &str) -> Self {
+        match path {
+            "/" | "/ready" | "/version" => RouteGroup::Health,
+            "/api/v1/stats" | "/api/v1/stats/tools" => RouteGroup::Metrics,
+            _ if path.starts_with("/admin") => RouteGroup::Admin,
+            _ => RouteGroup::Api,
+        }
+    }
+}
+
+/// One address the gateway listens on
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ListenerConfig {
+    /// Name used in logs, errors, and [`crate::http_server::ListenersHandle`]
+    pub name: String,
+
+    /// Address and port, e.g. `0.0.0.0:8443`; port 0 picks an ephemeral port
+    pub bind: SocketAddr,
+
+    /// Serve HTTPS with this certificate instead of plain HTTP
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+
+    /// Route groups mounted on this listener (default all)
+    #[serde(default = "all_route_groups")]
+    pub routes: Vec<RouteGroup>,
+}
+
+fn all_route_groups() -> Vec<RouteGroup> {
+    RouteGroup::ALL.to_vec()
+}
+
+impl ListenerConfig {
+    /// Whether the listener serves routes behind authentication
+    pub fn serves_authenticated_routes(&self) -> bool {
+        self.routes.iter().any(|group| *group != RouteGroup::Health)
+    }
+}
+
+/// PEM files of a TLS listener
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TlsConfig {
+    /// Certificate chain, leaf first
+    pub cert: String,
+
+    /// Private key (PKCS#8, PKCS#1, or SEC1)
+    pub key: String,
+}
+
+/// HTTP protocol options of every listener
+///
+/// Unset values keep hyper's behaviour: no idle timeout, no header read
+/// timeout, and HTTP/1.1 only.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct HttpConfig {
+    /// Accept HTTP/2 next to HTTP/1.1; `HTTP2=true` does the same
+    #[serde(default)]
+    pub http2: bool,
+
+    /// Seconds a connection may stay open with no request in flight
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_alive_idle_timeout_secs: Option<u64>,
+
+    /// Streams an HTTP/2 client may have open on one connection (default 200)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_streams: Option<u32>,
+
+    /// Seconds an HTTP/1.1 client has to send the headers of a request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header_read_timeout_secs: Option<u64>,
+}
+
+impl HttpConfig {
+    /// Check that limits are positive
+    pub fn validate(&self) -> Result<(), String> {
+        if self.keep_alive_idle_timeout_secs == Some(0) {
+            return Err("keep_alive_idle_timeout_secs must be positive".to_string());
+        }
+        if self.max_concurrent_streams == Some(0) {
+            return Err("max_concurrent_streams must be positive".to_string());
+        }
+        if self.header_read_timeout_secs == Some(0) {
+            return Err("header_read_timeout_secs must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    /// Advice on settings that do not do what they may be expected to on a
+    /// listener, plain or TLS
+    pub fn guidance(&self, tls: bool) -> Vec<&'static str> {
+        let mut guidance = Vec::new();
+        if !self.http2 && self.max_concurrent_streams.is_some() {
+            guidance.push("max_concurrent_streams only applies to HTTP/2; set http2 = true");
+        }
+        if self.http2 && !tls {
+            guidance.push(
+                "HTTP/2 without TLS needs clients with prior knowledge (h2c); clients \
+                 sending `Upgrade: h2c` and browsers stay on HTTP/1.1",
+            );
+        }
+        if self.http2 && self.header_read_timeout_secs.is_some() {
+            guidance.push(
+                "header_read_timeout_secs only limits HTTP/1.1 clients; HTTP/2 \
+                 connections are bounded by keep_alive_idle_timeout_secs",
+            );
+        }
+        guidance
+    }
+}
+
+/// Check listener names and routes
+pub fn validate_listeners(listeners: &[ListenerConfig]) -> McpCoreResult<()> {
+    let mut names = std::collections::HashSet::new();
+    for listener in listeners {
+        let error = |reason: &str| McpCoreError::ConfigurationError {
+            message: format!("Listener '{}' {}", listener.name, reason),
+        };
+        if listener.name.is_empty() {
+            return Err(McpCoreError::ConfigurationError {
+                message: format!("Listener on {} has no name", listener.bind),
+            });
+        }
+        if !names.insert(listener.name.as_str()) {
+            return Err(error("is defined more than once"));
+        }
+        if listener.routes.is_empty() {
+            return Err(error("mounts no routes"));
+        }
+    }
+    Ok(())
+}
+
+/// TLS server settings from PEM files
+///
+/// With `http2`, ALPN offers `h2` ahead of `http/1.1`.
+#[cfg(feature = "tls")]
+pub fn load_tls(
+    config: &TlsConfig,
+    http2: bool,
+) -> McpCoreResult<std::sync::Arc<tokio_rustls::rustls::ServerConfig>> {
+    use tokio_rustls::rustls::pki_types::pem::PemObject;
+    use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+    let pem_error = |path: &str, e: &dyn std::fmt::Display| McpCoreError::ConfigurationError {
+        message: format!("Failed to read '{}': {}", path, e),
+    };
+    let certs = CertificateDer::pem_file_iter(&config.cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| pem_error(&config.cert, &e))?;
+    if certs.is_empty() {
+        return Err(pem_error(&config.cert, &"no certificates found"));
+    }
+    let key = PrivateKeyDer::from_pem_file(&config.key).map_err(|e| pem_error(&config.key, &e))?;
+    let mut server_config = tokio_rustls::rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| McpCoreError::ConfigurationError {
+            message: format!("Invalid TLS certificate or key: {}", e),
+        })?;
+    if http2 {
+        server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    }
+    Ok(std::sync::Arc::new(server_config))
+}
+
+/// Listener completing TLS handshakes before handing connections to axum
+///
+/// Handshakes run in their own tasks, so a slow client does not hold up
+/// others; failed handshakes are logged and dropped.
+#[cfg(feature = "tls")]
+pub struct TlsListener {
+    local_addr: SocketAddr,
+    accepted: tokio::sync::mpsc::Receiver<(
+        tokio_rustls::server::TlsStream<tokio::net::TcpStream>,
+        SocketAddr,
+    )>,
+    accept_task: tokio::task::JoinHandle<()>,
+}
+
+#[cfg(feature = "tls")]
+impl TlsListener {
+    pub fn new(
+        listener: TcpListener,
+        config: std::sync::Arc<tokio_rustls::rustls::ServerConfig>,
+    ) -> io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let acceptor = tokio_rustls::TlsAcceptor::from(config);
+        let (sender, accepted) = tokio::sync::mpsc::channel(64);
+        let accept_task = tokio::spawn(async move {
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        tracing::warn!("Failed to accept connection: {}", e);
+                        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                        continue;
+                    }
+                };
+                let (acceptor, sender) = (acceptor.clone(), sender.clone());
+                tokio::spawn(async move {
+                    match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await
+                    {
+                        Ok(Ok(stream)) => {
+                            let _ = sender.send((stream, peer)).await;
+                        }
+                        Ok(Err(e)) => tracing::debug!("TLS handshake with {} failed: {}", peer, e),
+                        Err(_) => tracing::debug!("TLS handshake with {} timed out", peer),
+                    }
+                });
+            }
+        });
+        Ok(Self {
+            local_addr,
+            accepted,
+            accept_task,
+        })
+    }
+}
+
+#[cfg(feature = "tls")]
+impl Drop for TlsListener {
+    fn drop(&mut self) {
+        self.accept_task.abort();
+    }
+}
+
+#[cfg(feature = "tls")]
+impl axum::serve::Listener for TlsListener {
+    type Io = tokio_rustls::server::TlsStream<tokio::net::TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.accepted.recv().await {
+            Some(connection) => connection,
+            // The accept task only ends when the listener is dropped
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+/// Address of the client on the other end of a connection
+///
+/// Recorded for each request by every listener, plain or TLS, and
+/// available to handlers as `ConnectInfo<PeerAddr>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerAddr(pub SocketAddr);
+
+/// Bind `host:port`, walking up to the first free port if `fallback` is set
+pub async fn bind(host: IpAddr, port: u16, fallback: bool) -> McpCoreResult<TcpListener> {
+    let error = match TcpListener::bind((host, port)).await {
+        Ok(listener) => return Ok(listener),
+        Err(e) => e,
+    };
+
+    if fallback && error.kind() == io::ErrorKind::AddrInUse && port != 0 {
+        tracing::warn!("{}", describe_bind_error(host, port, &error));
+        let last = port.saturating_add(PORT_FALLBACK_RANGE);
+        for candidate in port + 1..=last {
+            if let Ok(listener) = TcpListener::bind((host, candidate)).await {
+                tracing::warn!(
+                    "Port {} is in use, falling back to port {}",
+                    port,
+                    candidate
+                );
+                return Ok(listener);
+            }
+        }
+        return Err(McpCoreError::HttpServerError {
+            message: format!("No free port found in range {}-{}", port, last),
+        });
+    }
+
+    Err(McpCoreError::HttpServerError {
+        message: describe_bind_error(host, port, &error),
+    })
+}
+
+/// Explain a bind failure by its cause
+pub fn describe_bind_error(host: IpAddr, port: u16, error: &io::Error) -> String {
+    match error.kind() {
+        io::ErrorKind::AddrInUse => {
+            let holder = match port_holder(port) {
+                Some((pid, name)) => format!(" by {} (pid {})", name, pid),
+                None => String::new(),
+            };
+            format!(
+                "Port {} is already in use{}; set PORT to another port or PORT_FALLBACK=true",
+                port, holder
+            )
+        }
+        io::ErrorKind::PermissionDenied => format!(
+            "Permission denied binding port {}; ports below 1024 need elevated privileges",
+            port
+        ),
+        _ => format!("Failed to bind to address {}:{}: {}", host, port, error),
+    }
+}
+
+/// Process listening on a TCP port, found through `/proc`
+#[cfg(target_os = "linux")]
+pub fn port_holder(port: u16) -> Option<(u32, String)> {
+    let inodes: Vec<String> = ["/proc/net/tcp", "/proc/net/tcp6"]
+        .iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .flat_map(|table| listening_inodes(&table, port))
+        .collect();
+    if inodes.is_empty() {
+        return None;
+    }
+
+    for entry in std::fs::read_dir("/proc").ok()?.flatten() {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|s| s.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        let Ok(fds) = std::fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            let Ok(target) = std::fs::read_link(fd.path()) else {
+                continue;
+            };
+            let target = target.to_string_lossy();
+            if inodes
+                .iter()
+                .any(|inode| target == format!("socket:[{}]", inode))
+            {
+                let name = std::fs::read_to_string(entry.path().join("comm"))
+                    .map(|name| name.trim().to_string())
+                    .unwrap_or_else(|_| "unknown".to_string());
+                return Some((pid, name));
+            }
+        }
+    }
+    None
+}
+
+/// Process listening on a TCP port; unavailable on this platform
+#[cfg(not(target_os = "linux"))]
+pub fn port_holder(_port: u16) -> Option<(u32, String)> {
+    None
+}
+
+/// Socket inodes of listening entries for `port` in a `/proc/net/tcp` table
+#[cfg(target_os = "linux")]
+fn listening_inodes(table: &str, port: u16) -> Vec<String> {
+    const TCP_LISTEN: &str = "0A";
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let local_port = fields.get(1)?.rsplit(':').next()?;
+            let listening = *fields.get(3)? == TCP_LISTEN;
+            (listening && u16::from_str_radix(local_port, 16).ok()? == port)
+                .then(|| fields.get(9).map(|inode| inode.to_string()))
+                .flatten()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[tokio::test]
+    async fn test_port_in_use_is_diagnosed_and_falls_back() {
+        let taken = TcpListener::bind("0.0.0.0:0").await.unwrap();
+        let port = taken.local_addr().unwrap().port();
+
+        let error = bind(Ipv4Addr::UNSPECIFIED.into(), port, false)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("already in use"));
+        #[cfg(target_os = "linux")]
+        assert!(error
+            .to_string()
+            .contains(&format!("pid {}", std::process::id())));
+
+        let listener = bind(Ipv4Addr::UNSPECIFIED.into(), port, true)
+            .await
+            .unwrap();
+        let fallback_port = listener.local_addr().unwrap().port();
+        assert!(fallback_port > port && fallback_port <= port + PORT_FALLBACK_RANGE);
+    }
+
+    #[test]
+    fn test_route_groups_and_listener_validation() {
+        assert_eq!(RouteGroup::of("/ready"), RouteGroup::Health);
+        assert_eq!(RouteGroup::of("/api/v1/stats"), RouteGroup::Metrics);
+        assert_eq!(RouteGroup::of("/api/v1/stats/tools"), RouteGroup::Metrics);
+        assert_eq!(
+            RouteGroup::of("/admin/servers/{name}/stats"),
+            RouteGroup::Admin
+        );
+        assert_eq!(RouteGroup::of("/api/v1/info"), RouteGroup::Api);
+
+        let listeners: Vec<ListenerConfig> = serde_json::from_value(serde_json::json!([
+            { "name": "public", "bind": "0.0.0.0:8443", "routes": ["api"] },
+            { "name": "internal", "bind": "127.0.0.1:9090" }
+        ]))
+        .unwrap();
+        assert_eq!(listeners[1].routes, RouteGroup::ALL);
+        assert!(validate_listeners(&listeners).is_ok());
+
+        let mut duplicate = listeners.clone();
+        duplicate[1].name = "public".to_string();
+        let error = validate_listeners(&duplicate).unwrap_err().to_string();
+        assert!(error.contains("Listener 'public' is defined more than once"));
+
+        let mut empty = listeners;
+        empty[0].routes.clear();
+        assert!(validate_listeners(&empty).is_err());
+    }
+
+    #[test]
+    fn test_http_options_are_checked_and_explained() {
+        let http: HttpConfig = serde_json::from_value(serde_json::json!({})).unwrap();
+        assert_eq!(http, HttpConfig::default());
+        assert!(http.validate().is_ok());
+        assert!(http.guidance(false).is_empty());
+
+        let zero = HttpConfig {
+            keep_alive_idle_timeout_secs: Some(0),
+            ..HttpConfig::default()
+        };
+        assert!(zero.validate().unwrap_err().contains("keep_alive_idle"));
+
+        let streams = HttpConfig {
+            max_concurrent_streams: Some(100),
+            ..HttpConfig::default()
+        };
+        assert!(streams.guidance(true)[0].contains("set http2 = true"));
+
+        let h2 = HttpConfig {
+            http2: true,
+            header_read_timeout_secs: Some(5),
+            ..streams
+        };
+        assert_eq!(h2.guidance(true).len(), 1);
+        let guidance = h2.guidance(false);
+        assert_eq!(guidance.len(), 2);
+        assert!(guidance[0].contains("prior knowledge (h2c)"));
+    }
+
+    #[test]
+    fn test_permission_denied_message() {
+        let error = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert!(
+            describe_bind_error(Ipv4Addr::UNSPECIFIED.into(), 80, &error).contains("below 1024")
+        );
+    }
+}
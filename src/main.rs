@@ -1,18 +1,21 @@
-//! MCP Server as HTTP Core
+//! MCP Server as HTTP Core binary
 //!
-//! This crate provides the core HTTP server functionality for converting
-//! Model Context Protocol (MCP) servers to REST API endpoints.
+//! Reads its configuration from environment variables and serves a single
+//! MCP server over HTTP.
 
-pub mod auth;
-pub mod config;
-pub mod error;
-pub mod http_server;
-pub mod process;
-
-use crate::error::McpCoreResult;
-use crate::http_server::McpHttpServer;
+use mcp_server_as_http_core::access_log::AccessLogConfig;
+use mcp_server_as_http_core::config::McpServersConfig;
+use mcp_server_as_http_core::diagnostics::{self, DiagnosticsOptions};
+use mcp_server_as_http_core::error::{McpCoreError, McpCoreResult};
+use mcp_server_as_http_core::http_server::{McpHttpServer, WORK_DIR_BASE};
+use mcp_server_as_http_core::routes;
+use mcp_server_as_http_core::scaffold::{self, Example, InitOptions, Runtime, Scaffold};
+use mcp_server_as_http_core::tenant;
+use mcp_server_as_http_core::workdir::{self, CleanupOptions};
+use mcp_server_as_http_core::workdir_lock;
 use std::env;
-use tracing_subscriber;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
 
 #[tokio::main]
 async fn main() -> McpCoreResult<()> {
@@ -23,6 +26,131 @@ async fn main() -> McpCoreResult<()> {
         tracing::debug!("No .env file found or error loading it: {}", e);
     }
 
+    // Get configuration from command line arguments and environment variables
+    let args: Vec<String> = env::args().skip(1).collect();
+    let config_file = env::var("MCP_CONFIG_FILE")
+        .or_else(|_| env::var("MCP_CONFIG_DIR"))
+        .unwrap_or_else(|_| scaffold::DEFAULT_CONFIG_FILE.to_string());
+    let config_profile =
+        cli_option(&args, "--profile").or_else(|| env::var("MCP_CONFIG_PROFILE").ok());
+    let example = cli_option(&args, "--example")
+        .map(|name| Example::find(&name))
+        .transpose()?;
+    let server_name = match example {
+        Some(example) => example.name.to_string(),
+        None => env::var("MCP_SERVER_NAME").unwrap_or_else(|_| "redmine".to_string()),
+    };
+
+    let port = env::var("PORT")
+        .unwrap_or_else(|_| "3000".to_string())
+        .parse::<u16>()
+        .unwrap_or(3000);
+
+    let offline = args.iter().any(|arg| arg == "--offline") || env_flag("MCP_OFFLINE");
+    let diagnostics_options = DiagnosticsOptions { offline };
+
+    // Write a starter configuration and exit
+    if args.iter().any(|arg| arg == "--init") {
+        let scaffold = match example {
+            Some(example) => example.scaffold()?,
+            None => Scaffold::new(&init_options(&args)?)?,
+        };
+        scaffold
+            .write(
+                Path::new(&config_file),
+                args.iter().any(|arg| arg == "--force"),
+            )
+            .await?;
+        println!(
+            "Wrote {}\n\nStart the server with:\n  {}",
+            config_file,
+            scaffold.run_command(&config_file)
+        );
+        return Ok(());
+    }
+
+    // Print the merged configuration, templates expanded, with the routes
+    // each listener serves, and exit before logging is set up
+    if args.iter().any(|arg| arg == "--print-config") {
+        let config = McpServersConfig::load_with_profile(&config_file, config_profile.as_deref())
+            .await?
+            .expand_templates(Some(port))?;
+        let effective_routes = routes::effective_routes(
+            &config,
+            &config_file,
+            config_profile.as_deref(),
+            &server_name,
+        )
+        .await?;
+        let mut printed = serde_json::to_value(&config)?;
+        printed["effective_routes"] = serde_json::to_value(effective_routes)?;
+        println!("{}", serde_json::to_string_pretty(&printed)?);
+        return Ok(());
+    }
+
+    // Check external prerequisites for every configured server and exit
+    if args.iter().any(|arg| arg == "--doctor") {
+        print_banner();
+        let config =
+            McpServersConfig::load_with_profile(&config_file, config_profile.as_deref()).await?;
+        let report = diagnostics::run(&config, &diagnostics_options).await;
+        print!("{}", report.render_table());
+        if !report.is_ok() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let cleanup_options = CleanupOptions {
+        retention: cli_option(&args, "--retention-days")
+            .or_else(|| env::var("WORK_DIR_RETENTION_DAYS").ok())
+            .and_then(|days| days.parse::<i64>().ok())
+            .map(chrono::Duration::days),
+        dry_run: args.iter().any(|arg| arg == "--dry-run"),
+        force: args.iter().any(|arg| arg == "--force"),
+    };
+
+    // Remove orphaned work directories and exit
+    if args.iter().any(|arg| arg == "--gc") {
+        let config =
+            McpServersConfig::load_with_profile(&config_file, config_profile.as_deref()).await?;
+        let mut configured = config.work_dir_names();
+        if !config.tenants.is_empty() {
+            configured.insert(tenant::TENANTS_DIR.to_string());
+        }
+        let report = workdir::cleanup(
+            Path::new(WORK_DIR_BASE),
+            &configured,
+            None,
+            &cleanup_options,
+        )
+        .await?;
+        let action = if report.dry_run {
+            "Would remove"
+        } else {
+            "Removed"
+        };
+        for entry in &report.removed {
+            println!("{} {} ({})", action, entry.path.display(), entry.reason);
+        }
+        for entry in &report.kept {
+            println!("Kept {} ({})", entry.path.display(), entry.reason);
+        }
+        return Ok(());
+    }
+
+    // Remove the work directory lock of a server and exit
+    if let Some(name) = cli_option(&args, "--force-unlock") {
+        let config =
+            McpServersConfig::load_with_profile(&config_file, config_profile.as_deref()).await?;
+        let work_dir = config.get_server(&name)?.work_dir(&name);
+        match workdir_lock::force_unlock(Path::new(&work_dir))? {
+            Some(holder) => println!("Removed the lock of {} held by {}", work_dir, holder),
+            None => println!("Removed the lock of {}, if any", work_dir),
+        }
+        return Ok(());
+    }
+
     // Initialize tracing
     tracing_subscriber::fmt()
         .with_env_filter(
@@ -31,26 +159,66 @@ async fn main() -> McpCoreResult<()> {
         )
         .init();
 
-    tracing::info!("Starting MCP HTTP Core server...");
+    tracing::info!(
+        "Starting {} v{}...",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    );
 
-    // Get configuration from environment variables
-    let config_file =
-        env::var("MCP_CONFIG_FILE").unwrap_or_else(|_| "mcp_servers.config.json".to_string());
-    let server_name = env::var("MCP_SERVER_NAME").unwrap_or_else(|_| "redmine".to_string());
-    let port = env::var("PORT")
-        .unwrap_or_else(|_| "3000".to_string())
-        .parse::<u16>()
-        .unwrap_or(3000);
+    let bind_host = match env::var("BIND_ADDRESS") {
+        Ok(value) => value
+            .parse::<IpAddr>()
+            .map_err(|e| McpCoreError::ConfigurationError {
+                message: format!("Invalid BIND_ADDRESS '{}': {}", value, e),
+            })?,
+        Err(_) => IpAddr::from(Ipv4Addr::UNSPECIFIED),
+    };
 
     tracing::info!(
-        "Configuration - Config: {}, Server: {}, Port: {}",
+        "Configuration - Config: {}, Profile: {}, Server: {}, Port: {}",
         config_file,
+        config_profile.as_deref().unwrap_or("none"),
         server_name,
         port
     );
 
+    // Load the configuration once; the builder takes it from here
+    let config = match example {
+        Some(example) => example.config()?,
+        None => {
+            McpServersConfig::load_with_profile(&config_file, config_profile.as_deref()).await?
+        }
+    };
+    // Serve one server per tenant when the configuration defines tenants
+    let tenants = !config.tenants.is_empty();
+
     // Create and start the MCP HTTP server
-    let server = McpHttpServer::new(&config_file, &server_name).await?;
+    let mut builder = McpHttpServer::builder(&config_file, &server_name).config(config);
+    if let Some(profile) = config_profile {
+        builder = builder.config_profile(profile);
+    }
+    if env_flag("STRICT_PREFLIGHT") {
+        builder = builder.strict_preflight(diagnostics_options);
+    }
+    if let Some(access_log) = AccessLogConfig::from_env()? {
+        builder = builder.access_log(access_log);
+    }
+    let builder = builder
+        .startup_cleanup(cleanup_options)
+        .bind_host(bind_host)
+        .port(port)
+        .port_fallback(env_flag("PORT_FALLBACK"))
+        .http2(env_flag("HTTP2"))
+        .allow_unauthenticated_public(env_flag("ALLOW_UNAUTHENTICATED_PUBLIC"));
+    if tenants {
+        let gateway = builder.build_tenants().await?;
+        tracing::info!(
+            "Serving tenants: {}",
+            gateway.tenant_ids().collect::<Vec<_>>().join(", ")
+        );
+        return gateway.serve(port).await;
+    }
+    let server = builder.build().await?;
 
     tracing::info!("MCP HTTP Core server ready to accept connections");
 
@@ -59,3 +227,56 @@ async fn main() -> McpCoreResult<()> {
 
     Ok(())
 }
+
+/// Get the value of a `--name value` or `--name=value` command line option
+fn cli_option(args: &[String], name: &str) -> Option<String> {
+    let prefix = format!("{}=", name);
+    args.iter().enumerate().find_map(|(index, arg)| {
+        if arg == name {
+            args.get(index + 1).cloned()
+        } else {
+            arg.strip_prefix(&prefix).map(str::to_string)
+        }
+    })
+}
+
+/// Options of `--init`, from `--runtime`, `--repo`, and `--name` if any is
+/// given and asked for interactively otherwise
+fn init_options(args: &[String]) -> McpCoreResult<InitOptions> {
+    let runtime = cli_option(args, "--runtime");
+    let repository = cli_option(args, "--repo");
+    if runtime.is_none() && repository.is_none() {
+        let mut stdout = std::io::stdout();
+        return Ok(scaffold::prompt(&mut std::io::stdin().lock(), &mut stdout)?);
+    }
+    let runtime = match runtime {
+        Some(name) => Runtime::parse(&name).ok_or_else(|| McpCoreError::ConfigurationError {
+            message: format!("Unknown runtime '{}' (expected node or python)", name),
+        })?,
+        None => Runtime::Node,
+    };
+    let repository = repository.ok_or_else(|| McpCoreError::ConfigurationError {
+        message: "--init needs --repo <url> (or --example <name>)".to_string(),
+    })?;
+    Ok(InitOptions {
+        runtime,
+        repository,
+        server_name: cli_option(args, "--name"),
+    })
+}
+
+/// Whether a boolean environment variable is set to `true`
+fn env_flag(name: &str) -> bool {
+    env::var(name)
+        .map(|value| value.parse::<bool>().unwrap_or(false))
+        .unwrap_or(false)
+}
+
+/// Print the name and version of the binary
+fn print_banner() {
+    println!(
+        "{} v{}\n",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    );
+}
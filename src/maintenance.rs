@@ -0,0 +1,142 @@
+//! Maintenance mode taking a server out of rotation
+//!
+//! While a server is drained, new requests are refused with `503` and a
+//! `maintenance` code, and the server reports not ready. Requests that were
+//! already admitted run to completion and the MCP server keeps running. The
+//! flag lives in memory; with `persist_lifecycle` it is also written to the
+//! lifecycle file, so a drained server stays drained after a gateway restart.
+
+use crate::error::{McpCoreError, McpCoreResult};
+use crate::lifecycle::LifecycleFile;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+/// Why and since when a server is drained
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MaintenanceMode {
+    pub since: DateTime<Utc>,
+
+    /// Shown to clients whose requests are refused
+    pub message: Option<String>,
+}
+
+/// Maintenance flag of a server
+#[derive(Debug, Default)]
+pub struct Maintenance {
+    server_name: String,
+    mode: Mutex<Option<MaintenanceMode>>,
+
+    /// Lifecycle file the flag is persisted in, if any
+    file: Option<LifecycleFile>,
+}
+
+impl Maintenance {
+    /// Flag of `server_name`, starting in `initial`
+    pub fn new(
+        server_name: &str,
+        initial: Option<MaintenanceMode>,
+        file: Option<LifecycleFile>,
+    ) -> Self {
+        Self {
+            server_name: server_name.to_string(),
+            mode: Mutex::new(initial),
+            file,
+        }
+    }
+
+    /// The current mode, or `None` while the server is in rotation
+    pub fn current(&self) -> Option<MaintenanceMode> {
+        self.lock().clone()
+    }
+
+    /// Refuse new requests while drained
+    pub fn check(&self) -> McpCoreResult<()> {
+        match self.current() {
+            None => Ok(()),
+            Some(mode) => Err(McpCoreError::Maintenance {
+                message: format!(
+                    "Server '{}' is in maintenance since {}",
+                    self.server_name,
+                    mode.since.to_rfc3339()
+                ),
+                maintenance_message: mode.message,
+            }),
+        }
+    }
+
+    /// Stop admitting requests; draining again replaces the message
+    pub async fn drain(&self, message: Option<String>) -> MaintenanceMode {
+        let mode = {
+            let mut current = self.lock();
+            let since = current.as_ref().map_or_else(Utc::now, |mode| mode.since);
+            let mode = MaintenanceMode { since, message };
+            *current = Some(mode.clone());
+            mode
+        };
+        tracing::warn!("Server '{}' drained for maintenance", self.server_name);
+        self.persist(Some(mode.clone())).await;
+        mode
+    }
+
+    /// Admit requests again, returning the mode that ended
+    pub async fn resume(&self) -> Option<MaintenanceMode> {
+        let ended = self.lock().take();
+        if ended.is_some() {
+            tracing::info!("Server '{}' resumed", self.server_name);
+            self.persist(None).await;
+        }
+        ended
+    }
+
+    async fn persist(&self, mode: Option<MaintenanceMode>) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let mut state = file.load(&self.server_name).await;
+        state.maintenance = mode;
+        if let Err(e) = file.save(&state).await {
+            tracing::warn!("{}", e);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<MaintenanceMode>> {
+        self.mode
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[tokio::test]
+    async fn test_drain_and_resume_persist() {
+        let dir = std::env::temp_dir().join(format!("mcp-maintenance-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let file = LifecycleFile::new(Some(&dir), Path::new("/nonexistent"), "echo");
+
+        let maintenance = Maintenance::new("echo", None, Some(file.clone()));
+        assert!(maintenance.check().is_ok());
+        let mode = maintenance.drain(Some("Upstream outage".to_string())).await;
+        let error = maintenance.check().unwrap_err();
+        assert!(matches!(
+            &error,
+            McpCoreError::Maintenance { maintenance_message: Some(message), .. }
+                if message == "Upstream outage"
+        ));
+
+        // Draining again keeps the original start time
+        let again = maintenance.drain(None).await;
+        assert_eq!(again.since, mode.since);
+        assert_eq!(file.load("echo").await.maintenance, Some(again));
+
+        assert!(maintenance.resume().await.is_some());
+        assert!(maintenance.check().is_ok());
+        assert_eq!(file.load("echo").await.maintenance, None);
+        assert!(maintenance.resume().await.is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,1953 @@
+//! Supervision of MCP servers, independent of the HTTP layer
+//!
+//! [`ProcessManager`] sets up the servers of a configuration and talks to
+//! them without serving anything: it prepares each work directory, clones
+//! and builds the repository, spawns or connects the transport, performs the
+//! handshake, and restarts the child as the server's `recycle` policy says.
+//! Hosts that are not HTTP gateways, such as desktop apps, use it directly;
+//! it builds with `default-features = false`.
+//!
+//! ```no_run
+//! # async fn run() -> mcp_server_as_http_core::error::McpCoreResult<()> {
+//! use mcp_server_as_http_core::{config::McpServersConfig, manager::ProcessManager};
+//!
+//! let config = McpServersConfig::load_from_file("mcp_servers.config.json").await?;
+//! let manager = ProcessManager::new(config);
+//! let mut events = manager.subscribe_events();
+//! manager.start("filesystem").await?;
+//! let response = manager
+//!     .query("filesystem", r#"{"jsonrpc":"2.0","id":1,"method":"tools/list"}"#)
+//!     .await?;
+//! manager.stop("filesystem").await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The HTTP gateway sets up its server through the same functions, so a
+//! server behaves the same behind either.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::Instrument;
+
+use crate::{
+    artifact_cache::ArtifactCache,
+    audit,
+    build_cache::{self, BuildStamp},
+    build_log::{self, BuildLogs, BuildRecorder, BuildRun, BuildStep, StepKind},
+    canary::SharedTransport,
+    child_env::{self, ChildEnv, ServerEnv},
+    config::{McpServerConfig, McpServersConfig},
+    diagnostics,
+    error::{McpCoreError, McpCoreResult},
+    history::{self, LifecycleHistory, LifecycleRecord, Transition, Trigger},
+    lifecycle::{LifecycleFile, LifecycleState},
+    platform::Shell,
+    process::{self, McpProcess},
+    provision::{
+        ProvisionFn, Provisioned, Provisioner, RestartRecord, RestartStats, RestartStrategy,
+        SetupMode, Unprovisioned,
+    },
+    proxy,
+    recycle::{self, RecyclePolicy, RecycleSnapshot, Recycler},
+    repo::{self, WorkDirState},
+    restart_budget::RestartBudget,
+    sandbox::Sandbox,
+    server_hooks::ServerHooks,
+    server_requests::{self, ServerRequestHandlers},
+    setup::{SetupExecutor, DEFAULT_MAX_SETUP_JOBS},
+    template::TemplateValues,
+    timing::PhaseTimer,
+    transport::{self, McpTransport, TcpTransport, TransportConfig},
+    workdir, workdir_lock,
+};
+
+/// Base directory under which each server gets its working directory
+pub const WORK_DIR_BASE: &str = "/tmp/mcp-servers";
+
+/// Events kept for subscribers that fall behind
+pub const EVENT_CAPACITY: usize = 64;
+
+/// Pause before another initialize attempt
+const INITIALIZE_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// What happened to a managed server
+#[derive(Debug, Clone, Serialize)]
+pub struct ManagerEvent {
+    pub server: String,
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub kind: ManagerEventKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ManagerEventKind {
+    /// Setup started
+    Starting,
+
+    /// The handshake succeeded and the server takes queries
+    Ready {
+        pid: Option<u32>,
+        protocol_version: String,
+    },
+
+    /// Setup failed; the server is not running
+    Failed { error: String },
+
+    /// The handshake still failed after `initialize_retries`; the server
+    /// stays down until it is started or restarted again
+    HandshakeFailed { error: String, attempts: u32 },
+
+    /// The child was replaced, or replacing it failed
+    Restarted { error: Option<String> },
+
+    /// The child stopped answering, noticed by a failed query
+    Exited { error: String },
+
+    /// The server was stopped
+    Stopped,
+}
+
+/// State of a managed server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessState {
+    Stopped,
+    Starting,
+    Running,
+
+    /// Setup failed
+    Failed,
+
+    /// The handshake failed on every attempt
+    HandshakeFailed,
+
+    /// Started, but the child is gone
+    Exited,
+}
+
+/// Point-in-time view of a managed server
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerStatus {
+    pub name: String,
+    pub state: ProcessState,
+    pub pid: Option<u32>,
+    pub protocol_version: Option<String>,
+    pub uptime_secs: Option<u64>,
+
+    /// Queries sent to the running child
+    pub requests: u64,
+    pub restarts: RestartStats,
+
+    /// Readings against the `recycle` policy, if the server has one
+    pub recycling: Option<RecycleSnapshot>,
+
+    /// Whether a restart waits for the restart budget
+    pub restart_throttled: bool,
+
+    /// Why setup failed, if it did, or the handshake a restart failed
+    pub error: Option<String>,
+}
+
+/// A server's transport and what supervises it
+pub(crate) struct ManagedServer {
+    pub(crate) transport: SharedTransport,
+    pub(crate) provisioner: Arc<Provisioner>,
+    pub(crate) build_logs: Arc<BuildLogs>,
+
+    /// `pre_start` and `post_stop` hooks, shared by every start
+    pub(crate) hooks: Arc<ServerHooks>,
+
+    /// Restarts the child when it reaches a recycling threshold
+    pub(crate) recycler: Option<Arc<Recycler>>,
+
+    /// Task running `recycler`, for whoever owns the server to stop
+    pub(crate) recycling: Option<JoinHandle<()>>,
+}
+
+/// Set up `server_name` as its `setup_mode` says and supervise it
+///
+/// An `on-start` server is set up before this returns; others are left to
+/// the provisioner. Restarts and deferred setups run the same pipeline, and
+/// wait for `budget` if there is one. Every setup, restart, and exit is
+/// recorded in `history`. The pipeline shares `config` and the environment
+/// merged from it rather than copying them for every restart.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn supervise(
+    config: Arc<McpServerConfig>,
+    server_name: &str,
+    server_requests: &ServerRequestHandlers,
+    lifecycle_file: Option<&LifecycleFile>,
+    lifecycle: Option<&mut LifecycleState>,
+    setup: &Arc<SetupExecutor>,
+    budget: Option<&Arc<RestartBudget>>,
+    history: Arc<LifecycleHistory>,
+    timer: PhaseTimer,
+) -> McpCoreResult<ManagedServer> {
+    let build_logs = Arc::new(BuildLogs::new(config.work_dir(server_name)));
+    let hooks = Arc::new(ServerHooks::new(&config, server_name));
+    let env = Arc::new(config.server_env());
+    // The setup pipeline provisions a deferred server and restarts any server
+    let pipeline: ProvisionFn = {
+        let config = Arc::clone(&config);
+        let server_name = server_name.to_string();
+        let handlers = server_requests.clone();
+        let lifecycle_file = lifecycle_file.cloned();
+        let setup = Arc::clone(setup);
+        let build_logs = Arc::clone(&build_logs);
+        let hooks = Arc::clone(&hooks);
+        let env = Arc::clone(&env);
+        let history = Arc::clone(&history);
+        Arc::new(move |timer, trigger| {
+            let (config, server_name, handlers, lifecycle_file, setup, outputs, history) = (
+                Arc::clone(&config),
+                server_name.clone(),
+                handlers.clone(),
+                lifecycle_file.clone(),
+                Arc::clone(&setup),
+                (
+                    Arc::clone(&build_logs),
+                    Arc::clone(&hooks),
+                    Arc::clone(&env),
+                ),
+                Arc::clone(&history),
+            );
+            Box::pin(async move {
+                let mut lifecycle = match &lifecycle_file {
+                    Some(file) => Some(file.load(&server_name).await),
+                    None => None,
+                };
+                provision_server(
+                    &config,
+                    &server_name,
+                    &handlers,
+                    lifecycle_file.as_ref().zip(lifecycle.as_mut()),
+                    &setup,
+                    (&outputs.0, &outputs.1, &outputs.2),
+                    (&history, trigger),
+                    timer,
+                )
+                .await
+            })
+        })
+    };
+    let transport: SharedTransport;
+    let provisioner = match config.setup_mode {
+        SetupMode::OnStart => {
+            let (started, provisioned) = provision_server(
+                &config,
+                server_name,
+                server_requests,
+                lifecycle_file.zip(lifecycle),
+                setup,
+                (&build_logs, &hooks, &env),
+                (&history, Trigger::Start),
+                timer,
+            )
+            .await?;
+            transport = Arc::new(tokio::sync::Mutex::new(started));
+            Provisioner::ready(server_name, Arc::clone(&transport), provisioned)
+                .with_pipeline(pipeline)
+                .with_history(history)
+        }
+        mode => {
+            tracing::info!(
+                "Deferring setup of '{}' (setup_mode {:?})",
+                server_name,
+                mode
+            );
+            transport = Arc::new(tokio::sync::Mutex::new(Box::new(Unprovisioned)));
+            Provisioner::deferred(server_name, mode, Arc::clone(&transport), pipeline)
+                .with_history(history)
+        }
+    };
+
+    let provisioner = Arc::new(match budget {
+        Some(budget) => provisioner.with_restart_budget(Arc::clone(budget)),
+        None => provisioner,
+    });
+    let (recycler, recycling) = match &config.recycle {
+        Some(recycle) => {
+            let policy = RecyclePolicy::new(recycle)
+                .map_err(|message| McpCoreError::ConfigurationError { message })?;
+            let recycler = Arc::new(Recycler::new(policy, Arc::clone(&provisioner)));
+            let recycling = Arc::clone(&recycler).spawn(recycle::CHECK_INTERVAL);
+            (Some(recycler), Some(recycling))
+        }
+        None => (None, None),
+    };
+    Ok(ManagedServer {
+        transport,
+        provisioner,
+        build_logs,
+        hooks,
+        recycler,
+        recycling,
+    })
+}
+
+/// Lifecycle file of `server_name`, if it persists one, and its state with
+/// this start counted
+pub(crate) async fn restore_lifecycle(
+    config: &McpServerConfig,
+    server_name: &str,
+) -> (Option<LifecycleFile>, Option<LifecycleState>) {
+    let Some(file) = config.persist_lifecycle.then(|| {
+        LifecycleFile::new(
+            config.state_dir.as_deref().map(std::path::Path::new),
+            std::path::Path::new(&config.work_dir(server_name)),
+            server_name,
+        )
+    }) else {
+        return (None, None);
+    };
+    let mut state = file.load(server_name).await;
+    state.record_start(Utc::now());
+    save_lifecycle(&file, &state).await;
+    (Some(file), Some(state))
+}
+
+/// `handlers` with the sampling webhook and roots of `config` filling in
+/// those not registered; registered handlers take precedence
+pub(crate) fn with_configured_handlers(
+    mut handlers: ServerRequestHandlers,
+    config: &McpServerConfig,
+) -> McpCoreResult<ServerRequestHandlers> {
+    if handlers.sampling.is_none() {
+        if let Some(url) = &config.sampling_webhook {
+            handlers.sampling = Some(server_requests::webhook_handler(url)?);
+        }
+    }
+    if handlers.roots.is_none() && !config.roots.is_empty() {
+        handlers.roots = Some(server_requests::static_roots(&config.roots));
+    }
+    Ok(handlers)
+}
+
+/// Where a managed server stands
+enum Entry {
+    Starting,
+    Running(Arc<ManagedServer>),
+    Failed { state: ProcessState, error: String },
+}
+
+/// Starts, stops, and queries the servers of a configuration
+pub struct ProcessManager {
+    config: McpServersConfig,
+    server_requests: ServerRequestHandlers,
+    setup: Arc<SetupExecutor>,
+    restart_budget: Option<Arc<RestartBudget>>,
+    servers: Mutex<HashMap<String, Entry>>,
+
+    /// Lifecycle history of each server started, kept across its stops
+    histories: Mutex<HashMap<String, Arc<LifecycleHistory>>>,
+    events: broadcast::Sender<ManagerEvent>,
+}
+
+impl std::fmt::Debug for ProcessManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let servers: Vec<String> = self.lock().keys().cloned().collect();
+        f.debug_struct("ProcessManager")
+            .field("servers", &servers)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ProcessManager {
+    /// Manager of the servers in `config`, none of them started
+    pub fn new(config: McpServersConfig) -> Self {
+        let setup = SetupExecutor::shared(
+            config
+                .max_concurrent_setup_jobs
+                .unwrap_or(DEFAULT_MAX_SETUP_JOBS),
+        );
+        let restart_budget = config.restart_budget.map(RestartBudget::shared);
+        Self {
+            config,
+            server_requests: ServerRequestHandlers::default(),
+            setup,
+            restart_budget,
+            servers: Mutex::new(HashMap::new()),
+            histories: Mutex::new(HashMap::new()),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+
+    /// Answer requests the servers send, such as `sampling/createMessage`
+    ///
+    /// Applies to servers started afterwards.
+    pub fn with_server_requests(mut self, handlers: ServerRequestHandlers) -> Self {
+        self.server_requests = handlers;
+        self
+    }
+
+    /// Budget the restarts of every server take from, if one is configured
+    ///
+    /// [`RestartBudget::raise`] lifts it for a while.
+    pub fn restart_budget(&self) -> Option<&Arc<RestartBudget>> {
+        self.restart_budget.as_ref()
+    }
+
+    /// Events of every server from now on
+    pub fn subscribe_events(&self) -> broadcast::Receiver<ManagerEvent> {
+        self.events.subscribe()
+    }
+
+    /// Set up and start `name`, whatever its `setup_mode`
+    ///
+    /// Returns the status once the handshake succeeded. Starting a running
+    /// server returns its status; starting one that is starting fails.
+    pub async fn start(&self, name: &str) -> McpCoreResult<ServerStatus> {
+        let server_config = self.config.get_server(name)?;
+        let running = {
+            let mut servers = self.lock();
+            match servers.get(name) {
+                Some(Entry::Running(_)) => true,
+                Some(Entry::Starting) => {
+                    return Err(McpCoreError::RequestError {
+                        message: format!("Server '{}' is already starting", name),
+                    })
+                }
+                _ => {
+                    servers.insert(name.to_string(), Entry::Starting);
+                    false
+                }
+            }
+        };
+        if running {
+            return self.status(name).await;
+        }
+        self.emit(name, ManagerEventKind::Starting);
+
+        let history = self.server_history(name, server_config.history_size);
+        let started = async {
+            let mut config = server_config.expand_templates(&TemplateValues::new(
+                name,
+                &server_config.work_dir(name),
+                None,
+            ))?;
+            config.setup_mode = SetupMode::OnStart;
+            let handlers = with_configured_handlers(self.server_requests.clone(), &config)?;
+            let (lifecycle_file, mut lifecycle) = restore_lifecycle(&config, name).await;
+            supervise(
+                Arc::new(config),
+                name,
+                &handlers,
+                lifecycle_file.as_ref(),
+                lifecycle.as_mut(),
+                &self.setup,
+                self.restart_budget.as_ref(),
+                history,
+                PhaseTimer::default(),
+            )
+            .await
+        }
+        .await;
+
+        match started {
+            Ok(server) => {
+                let provisioned = server.provisioner.provisioned();
+                self.lock()
+                    .insert(name.to_string(), Entry::Running(Arc::new(server)));
+                self.emit(
+                    name,
+                    ManagerEventKind::Ready {
+                        pid: provisioned.as_ref().and_then(|p| p.pid),
+                        protocol_version: provisioned
+                            .map(|p| p.protocol_version.clone())
+                            .unwrap_or_default(),
+                    },
+                );
+                self.status(name).await
+            }
+            Err(e) => {
+                let (state, kind) = match &e {
+                    McpCoreError::HandshakeFailed { attempts, .. } => (
+                        ProcessState::HandshakeFailed,
+                        ManagerEventKind::HandshakeFailed {
+                            error: e.to_string(),
+                            attempts: *attempts,
+                        },
+                    ),
+                    _ => (
+                        ProcessState::Failed,
+                        ManagerEventKind::Failed {
+                            error: e.to_string(),
+                        },
+                    ),
+                };
+                self.lock().insert(
+                    name.to_string(),
+                    Entry::Failed {
+                        state,
+                        error: e.to_string(),
+                    },
+                );
+                self.emit(name, kind);
+                Err(e)
+            }
+        }
+    }
+
+    /// Stop `name`, shutting its child down
+    ///
+    /// Stopping a server that is not running does nothing.
+    pub async fn stop(&self, name: &str) -> McpCoreResult<()> {
+        self.config.get_server(name)?;
+        let server = {
+            let mut servers = self.lock();
+            match servers.remove(name) {
+                Some(Entry::Running(server)) => server,
+                Some(Entry::Starting) => {
+                    servers.insert(name.to_string(), Entry::Starting);
+                    return Err(McpCoreError::RequestError {
+                        message: format!("Server '{}' is starting", name),
+                    });
+                }
+                _ => return Ok(()),
+            }
+        };
+        if let Some(recycling) = &server.recycling {
+            recycling.abort();
+        }
+        let started = server.provisioner.provisioned().is_some();
+        let stopped = server.provisioner.shut_down(Trigger::Manager).await;
+        if started {
+            server.hooks.post_stop().await;
+        }
+        self.emit(name, ManagerEventKind::Stopped);
+        stopped
+    }
+
+    /// Replace the child of the running server `name`
+    ///
+    /// A server whose handshake failed on a restart is set up again; one
+    /// whose handshake failed on start is started again with
+    /// [`ProcessManager::start`].
+    pub async fn restart(
+        &self,
+        name: &str,
+        strategy: RestartStrategy,
+    ) -> McpCoreResult<RestartRecord> {
+        let server = self.running(name)?;
+        let restarted = server
+            .provisioner
+            .restart(strategy, Trigger::Manager, "manager")
+            .await;
+        self.emit(
+            name,
+            ManagerEventKind::Restarted {
+                error: restarted.as_ref().err().map(ToString::to_string),
+            },
+        );
+        if let Some(handshake) = server.provisioner.failed_handshake() {
+            self.emit(
+                name,
+                ManagerEventKind::HandshakeFailed {
+                    error: handshake.error,
+                    attempts: handshake.attempts,
+                },
+            );
+        }
+        restarted
+    }
+
+    /// Status of `name`
+    pub async fn status(&self, name: &str) -> McpCoreResult<ServerStatus> {
+        self.config.get_server(name)?;
+        let mut status = ServerStatus {
+            name: name.to_string(),
+            state: ProcessState::Stopped,
+            pid: None,
+            protocol_version: None,
+            uptime_secs: None,
+            requests: 0,
+            restarts: RestartStats::default(),
+            recycling: None,
+            restart_throttled: false,
+            error: None,
+        };
+        let server = match self.lock().get(name) {
+            None => return Ok(status),
+            Some(Entry::Starting) => {
+                status.state = ProcessState::Starting;
+                return Ok(status);
+            }
+            Some(Entry::Failed { state, error }) => {
+                status.state = *state;
+                status.error = Some(error.clone());
+                return Ok(status);
+            }
+            Some(Entry::Running(server)) => Arc::clone(server),
+        };
+        let alive = server.transport.lock().await.is_alive();
+        let provisioned = server.provisioner.provisioned();
+        let handshake = server.provisioner.failed_handshake();
+        status.state = match (alive, &handshake) {
+            (true, _) => ProcessState::Running,
+            (false, Some(_)) => ProcessState::HandshakeFailed,
+            (false, None) => ProcessState::Exited,
+        };
+        status.error = handshake.map(|handshake| handshake.error);
+        status.pid = provisioned.as_ref().and_then(|p| p.pid);
+        status.protocol_version = provisioned.map(|p| p.protocol_version.clone());
+        status.uptime_secs = server.provisioner.uptime().map(|uptime| uptime.as_secs());
+        status.requests = server.provisioner.served();
+        status.restarts = server.provisioner.restarts();
+        status.recycling = server.recycler.as_ref().map(|recycler| recycler.snapshot());
+        status.restart_throttled = server.provisioner.status().restart_throttled;
+        Ok(status)
+    }
+
+    /// Recent lifecycle transitions of `name`, oldest first
+    ///
+    /// Kept across stops and restarts; empty for a server never started.
+    pub fn history(&self, name: &str) -> McpCoreResult<Vec<LifecycleRecord>> {
+        self.config.get_server(name)?;
+        Ok(self
+            .lock_histories()
+            .get(name)
+            .map(|history| history.records())
+            .unwrap_or_default())
+    }
+
+    /// Clone and build output of `name`, `previous` runs before the latest
+    pub async fn build_log(&self, name: &str, previous: usize) -> McpCoreResult<Option<BuildRun>> {
+        let server = self.running(name)?;
+        Ok(server.build_logs.get(previous).await)
+    }
+
+    /// Send the JSON-RPC request `command` to `name` and return its response
+    ///
+    /// Requests the server sends meanwhile are answered by the handlers of
+    /// [`ProcessManager::with_server_requests`]. The wait is bounded by the
+    /// server's `request_deadline_secs`.
+    pub async fn query(&self, name: &str, command: &str) -> McpCoreResult<String> {
+        let server = self.running(name)?;
+        let request: serde_json::Value = serde_json::from_str(command)?;
+        let Some(id) = request.get("id") else {
+            return Err(McpCoreError::RequestError {
+                message: "A query needs a request with an id".to_string(),
+            });
+        };
+        let timeout = self
+            .config
+            .get_server(name)?
+            .request_deadline_secs
+            .map_or(transport::RESPONSE_TIMEOUT, std::time::Duration::from_secs);
+
+        let mut transport = server.transport.lock().await;
+        server.provisioner.record_request();
+        let response = match transport.send(command).await {
+            Ok(()) => {
+                transport::receive_response(
+                    transport.as_mut(),
+                    &self.server_requests,
+                    Some(id),
+                    timeout,
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
+        drop(transport);
+        if let Err(e) = &response {
+            if server.provisioner.check_exited().await {
+                self.emit(
+                    name,
+                    ManagerEventKind::Exited {
+                        error: e.to_string(),
+                    },
+                );
+            }
+        }
+        response
+    }
+
+    fn running(&self, name: &str) -> McpCoreResult<Arc<ManagedServer>> {
+        self.config.get_server(name)?;
+        match self.lock().get(name) {
+            Some(Entry::Running(server)) => Ok(Arc::clone(server)),
+            _ => Err(McpCoreError::NotProvisioned {
+                message: format!("Server '{}' is not running", name),
+            }),
+        }
+    }
+
+    fn emit(&self, name: &str, kind: ManagerEventKind) {
+        // Nobody listening is fine
+        let _ = self.events.send(ManagerEvent {
+            server: name.to_string(),
+            at: Utc::now(),
+            kind,
+        });
+    }
+
+    /// History of `name`, created with `size` records the first time
+    fn server_history(&self, name: &str, size: Option<usize>) -> Arc<LifecycleHistory> {
+        let mut histories = self.lock_histories();
+        let history = histories.entry(name.to_string()).or_insert_with(|| {
+            Arc::new(LifecycleHistory::new(
+                size.unwrap_or(history::DEFAULT_HISTORY_SIZE),
+            ))
+        });
+        Arc::clone(history)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Entry>> {
+        self.servers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn lock_histories(&self) -> std::sync::MutexGuard<'_, HashMap<String, Arc<LifecycleHistory>>> {
+        self.histories
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Set up the MCP server and record the outcome in its lifecycle state and
+/// history
+///
+/// The clone, build, `pre_start` hooks, spawn, and handshake run as a job of
+/// `setup`.
+#[allow(clippy::too_many_arguments)]
+async fn provision_server(
+    config: &McpServerConfig,
+    server_name: &str,
+    server_requests: &ServerRequestHandlers,
+    lifecycle: Option<(&LifecycleFile, &mut LifecycleState)>,
+    setup: &SetupExecutor,
+    (build_logs, hooks, env): (&BuildLogs, &ServerHooks, &ServerEnv),
+    (history, trigger): (&LifecycleHistory, Trigger),
+    mut timer: PhaseTimer,
+) -> McpCoreResult<(Box<dyn McpTransport>, Provisioned)> {
+    // Clone and build logs carry the server name
+    let work_dir = config.work_dir(server_name);
+    let pinned_commit = lifecycle
+        .as_ref()
+        .and_then(|(_, state)| state.commit.clone());
+    let artifact_cache = config
+        .artifact_cache
+        .as_ref()
+        .filter(|cache| cache.enabled && config.repository.is_some())
+        .map(ArtifactCache::new)
+        .transpose();
+    // Requested sandboxing that cannot work here fails the start
+    let sandbox = match &config.sandbox {
+        Some(sandbox) => sandbox.resolve(server_name).await.map(Some),
+        None => Ok(None),
+    };
+    let progress = timer.observe();
+    let recorder = BuildRecorder::default();
+    let started_at = chrono::Utc::now();
+    let started = match (artifact_cache, sandbox) {
+        (Ok(artifact_cache), Ok(sandbox)) => setup
+            .run(
+                server_name,
+                progress,
+                recorder
+                    .scope(start_transport(
+                        config,
+                        server_name,
+                        server_requests,
+                        pinned_commit.as_deref(),
+                        artifact_cache.as_ref(),
+                        sandbox.as_ref(),
+                        (hooks, env),
+                        Some((history, trigger)),
+                        &mut timer,
+                    ))
+                    .instrument(tracing::info_span!("mcp_server", server = %server_name)),
+            )
+            .await
+            .map(|(transport, protocol_version)| {
+                (transport, protocol_version, artifact_cache, sandbox)
+            }),
+        (Err(e), _) | (_, Err(e)) => Err(e),
+    };
+    build_logs
+        .save(BuildRun {
+            started_at,
+            finished_at: chrono::Utc::now(),
+            steps: recorder.steps(),
+            error: started.as_ref().err().map(ToString::to_string),
+        })
+        .await;
+    let commit = match &started {
+        Ok(_) => workdir::current_commit(std::path::Path::new(&work_dir)).await,
+        Err(_) => None,
+    };
+    if let Some((file, state)) = lifecycle {
+        match &started {
+            Ok((_, protocol_version, _, _)) => state.record_ready(commit.clone(), protocol_version),
+            Err(e) => state.record_failure(e),
+        }
+        save_lifecycle(file, state).await;
+    }
+    if let Err(e) = &started {
+        history
+            .record(LifecycleRecord::new(Transition::Failed, trigger).with_detail(e.to_string()));
+    }
+    let (transport, protocol_version, artifact_cache, sandbox) = started?;
+    let package_version = match &config.repository {
+        Some(_) => workdir::package_version(std::path::Path::new(&work_dir)).await,
+        None => None,
+    };
+    let audit = match &config.audit {
+        Some(audit) if audit.enabled => audit::read_report(std::path::Path::new(&work_dir)).await,
+        _ => None,
+    };
+    let startup = timer.finish();
+    tracing::info!(
+        "Startup timings for '{}':\n{}",
+        server_name,
+        startup.table()
+    );
+    let provisioned = Provisioned {
+        protocol_version,
+        pid: transport.pid(),
+        stderr: transport.stderr_tail(),
+        startup,
+        audit,
+        commit,
+        package_version,
+        artifact_cache: artifact_cache.map(|cache| cache.stats()),
+        sandbox: sandbox.as_ref().map(Sandbox::backend),
+        egress: sandbox.as_ref().and_then(Sandbox::egress_proxy),
+    };
+    history
+        .record(LifecycleRecord::new(Transition::Initialized, trigger).with_pid(provisioned.pid));
+    Ok((transport, provisioned))
+}
+
+/// Open the configured transport and perform the MCP handshake
+///
+/// A fresh clone checks out `pinned_commit`, the commit of the previous run.
+/// The `pre_start` hooks run once the work directory is ready. A failed
+/// handshake is retried up to `initialize_retries` times, with a new
+/// child or connection when its [`transport::HandshakeFailure`] says the old
+/// one cannot be trusted; after that the setup fails with `handshake_failed`.
+/// Children spawned and shut down along the way are recorded in `history`.
+#[allow(clippy::too_many_arguments)]
+async fn start_transport(
+    config: &McpServerConfig,
+    server_name: &str,
+    server_requests: &ServerRequestHandlers,
+    pinned_commit: Option<&str>,
+    artifact_cache: Option<&ArtifactCache>,
+    sandbox: Option<&Sandbox>,
+    (hooks, env): (&ServerHooks, &ServerEnv),
+    history: Option<(&LifecycleHistory, Trigger)>,
+    timer: &mut PhaseTimer,
+) -> McpCoreResult<(Box<dyn McpTransport>, String)> {
+    if matches!(config.transport, TransportConfig::Stdio) {
+        prepare_mcp_process(
+            config,
+            server_name,
+            &env.build,
+            pinned_commit,
+            artifact_cache,
+            timer,
+        )
+        .await?;
+    }
+    if hooks.has_pre_start() {
+        timer.measure("pre_start", hooks.pre_start()).await?;
+    }
+    open_and_initialize(
+        config,
+        server_name,
+        server_requests,
+        sandbox,
+        &env.runtime,
+        history,
+        timer,
+    )
+    .await
+}
+
+/// Start or connect to an MCP server whose work directory is ready, and
+/// initialize the connection, retrying failed handshakes
+async fn open_and_initialize(
+    config: &McpServerConfig,
+    server_name: &str,
+    server_requests: &ServerRequestHandlers,
+    sandbox: Option<&Sandbox>,
+    env: &ChildEnv,
+    history: Option<(&LifecycleHistory, Trigger)>,
+    timer: &mut PhaseTimer,
+) -> McpCoreResult<(Box<dyn McpTransport>, String)> {
+    let mut transport = open_transport(config, server_name, env, sandbox, history, timer).await?;
+
+    // Initialize MCP connection
+    let options = config.initialize_options(server_requests.capabilities());
+    let retries = config
+        .initialize_retries
+        .unwrap_or(transport::DEFAULT_INITIALIZE_RETRIES);
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let initialized = timer
+            .measure(
+                "initialize",
+                transport::initialize(transport.as_mut(), &options),
+            )
+            .await;
+        let (message, failure) = match initialized {
+            Ok(protocol_version) => return Ok((transport, protocol_version)),
+            Err(McpCoreError::HandshakeFailed {
+                message, failure, ..
+            }) => (message, failure),
+            Err(e) => return Err(e),
+        };
+
+        if !failure.is_retryable() || attempts > retries {
+            shut_down_child(transport.as_mut(), history).await;
+            return Err(McpCoreError::HandshakeFailed {
+                message: match attempts {
+                    1 => message,
+                    _ => format!("{} attempts failed, the last with: {}", attempts, message),
+                },
+                failure,
+                attempts,
+            });
+        }
+        tracing::warn!(
+            "Handshake with '{}' failed ({}), retrying ({} of {}): {}",
+            server_name,
+            failure,
+            attempts,
+            retries,
+            message
+        );
+        tokio::time::sleep(INITIALIZE_RETRY_DELAY).await;
+        if failure.needs_restart() {
+            shut_down_child(transport.as_mut(), history).await;
+            transport = open_transport(config, server_name, env, sandbox, history, timer).await?;
+        }
+    }
+}
+
+/// Shut down a child whose handshake failed, recording its exit
+async fn shut_down_child(
+    transport: &mut dyn McpTransport,
+    history: Option<(&LifecycleHistory, Trigger)>,
+) {
+    let pid = transport.pid();
+    if let Err(e) = transport.shutdown().await {
+        tracing::warn!("Failed to shut down the child: {}", e);
+    }
+    if let Some((history, trigger)) = history {
+        history.record(
+            LifecycleRecord::new(Transition::Exited, trigger)
+                .with_pid(pid)
+                .with_exit(transport.exit_status())
+                .with_detail("handshake failed"),
+        );
+    }
+}
+
+/// Spawn the server process, or connect to the server
+async fn open_transport(
+    config: &McpServerConfig,
+    server_name: &str,
+    env: &ChildEnv,
+    sandbox: Option<&Sandbox>,
+    history: Option<(&LifecycleHistory, Trigger)>,
+    timer: &mut PhaseTimer,
+) -> McpCoreResult<Box<dyn McpTransport>> {
+    Ok(match &config.transport {
+        TransportConfig::Stdio => {
+            let process = spawn_mcp_process(config, server_name, env, sandbox, timer).await?;
+            if let Some((history, trigger)) = history {
+                history.record(
+                    LifecycleRecord::new(Transition::Spawned, trigger).with_pid(process.pid()),
+                );
+            }
+            Box::new(process)
+        }
+        TransportConfig::Tcp {
+            address,
+            reconnect_attempts,
+            reconnect_backoff_ms,
+        } => {
+            tracing::info!("Connecting to MCP server '{}' at {}", server_name, address);
+            Box::new(
+                timer
+                    .measure(
+                        "connect",
+                        TcpTransport::connect(
+                            address,
+                            *reconnect_attempts,
+                            std::time::Duration::from_millis(*reconnect_backoff_ms),
+                        ),
+                    )
+                    .await?,
+            )
+        }
+        #[cfg(feature = "reqwest")]
+        TransportConfig::Http { url, headers } => {
+            tracing::info!("Using MCP server '{}' at {}", server_name, url);
+            Box::new(crate::http_transport::HttpSseTransport::new(url, headers)?)
+        }
+        #[cfg(not(feature = "reqwest"))]
+        TransportConfig::Http { .. } => {
+            return Err(McpCoreError::ConfigurationError {
+                message: "The http transport requires the 'reqwest' feature".to_string(),
+            })
+        }
+    })
+}
+
+/// Set up the canary of a server and start routing requests to it
+///
+/// A canary with a `ref` is cloned afresh and checked out at it on every
+/// start. If setup fails, every request keeps going to the primary.
+#[cfg(feature = "http-server")]
+pub(crate) async fn start_canary(
+    config: McpServerConfig,
+    name: String,
+    git_ref: Option<String>,
+    server_requests: ServerRequestHandlers,
+    setup: Arc<SetupExecutor>,
+    canary: Arc<crate::canary::CanaryRouter>,
+) {
+    let mut timer = PhaseTimer::default();
+    let progress = timer.observe();
+    let env = config.server_env();
+    let job = async {
+        if let (Some(git_ref), Some(repository_url)) = (&git_ref, &config.repository) {
+            let work_dir = config.work_dir_base().join(&name);
+            match tokio::fs::remove_dir_all(&work_dir).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(McpCoreError::ProcessError {
+                        message: format!(
+                            "Failed to remove canary work directory '{}': {}",
+                            work_dir.display(),
+                            e
+                        ),
+                    })
+                }
+                _ => {}
+            }
+            timer
+                .measure(
+                    "clone",
+                    repo::prepare(
+                        repository_url,
+                        &work_dir,
+                        None,
+                        &env.build,
+                        config.existing_work_dir,
+                    ),
+                )
+                .await?;
+            repo::checkout_ref(git_ref, &work_dir, &env.build).await?;
+        }
+        let sandbox = match &config.sandbox {
+            Some(sandbox) => Some(sandbox.resolve(&name).await?),
+            None => None,
+        };
+        start_transport(
+            &config,
+            &name,
+            &server_requests,
+            None,
+            None,
+            sandbox.as_ref(),
+            (&ServerHooks::new(&config, &name), &env),
+            None,
+            &mut timer,
+        )
+        .await
+    };
+    let started = setup
+        .run(
+            &name,
+            progress,
+            job.instrument(tracing::info_span!("mcp_server", server = %name)),
+        )
+        .await;
+    match started {
+        Ok((transport, _)) => {
+            tracing::info!("Canary '{}' is ready", name);
+            canary.start(Arc::new(tokio::sync::Mutex::new(transport)));
+        }
+        Err(e) => {
+            tracing::error!("Canary '{}' failed to start: {}", name, e);
+            canary.fail(e.to_string());
+        }
+    }
+}
+
+/// Start replica `index` of a server and send its share of requests to it
+///
+/// The replica runs the server's configuration in the primary's work
+/// directory, which is already set up, so nothing is cloned or built. If it
+/// cannot be started, its clients keep going to the other replicas.
+#[cfg(feature = "http-server")]
+pub(crate) async fn start_replica(
+    config: Arc<McpServerConfig>,
+    server_name: String,
+    index: usize,
+    server_requests: ServerRequestHandlers,
+    setup: Arc<SetupExecutor>,
+    pool: Arc<crate::balance::ReplicaPool>,
+) {
+    let name = crate::balance::replica_name(&server_name, index);
+    let mut timer = PhaseTimer::default();
+    let progress = timer.observe();
+    let env = config.server_env();
+    let job = async {
+        let sandbox = match &config.sandbox {
+            Some(sandbox) => Some(sandbox.resolve(&server_name).await?),
+            None => None,
+        };
+        open_and_initialize(
+            &config,
+            &server_name,
+            &server_requests,
+            sandbox.as_ref(),
+            &env.runtime,
+            None,
+            &mut timer,
+        )
+        .await
+    };
+    let started = setup
+        .run(
+            &name,
+            progress,
+            job.instrument(tracing::info_span!("mcp_server", server = %name)),
+        )
+        .await;
+    match started {
+        Ok((transport, _)) => {
+            tracing::info!("Replica '{}' is ready", name);
+            pool.start(index, Arc::new(tokio::sync::Mutex::new(transport)));
+        }
+        Err(e) => {
+            tracing::error!("Replica '{}' failed to start: {}", name, e);
+            pool.fail(index, e.to_string());
+        }
+    }
+}
+
+/// Prepare the work directory of an MCP server process, cloning the
+/// repository and running the build command if configured
+///
+/// With an artifact cache, a missing clone is restored from the cache if
+/// possible, and a fresh clone or build is uploaded to it.
+async fn prepare_mcp_process(
+    config: &McpServerConfig,
+    server_name: &str,
+    env: &ChildEnv,
+    pinned_commit: Option<&str>,
+    artifact_cache: Option<&ArtifactCache>,
+    timer: &mut PhaseTimer,
+) -> McpCoreResult<()> {
+    if config.command.is_empty() {
+        return Err(McpCoreError::ConfigurationError {
+            message: format!(
+                "Server '{}' uses the stdio transport but has no command",
+                server_name
+            ),
+        });
+    }
+
+    tracing::info!(
+        "Starting MCP server '{}': {} {:?}",
+        server_name,
+        config.command,
+        config.args
+    );
+
+    if let Some(proxy) = &config.proxy {
+        tracing::info!(
+            "Proxy for clone, build, and server: http_proxy={}, https_proxy={}, no_proxy={}",
+            proxy::redact(proxy.http_proxy.as_deref().unwrap_or("unset")),
+            proxy::redact(proxy.https_proxy.as_deref().unwrap_or("unset")),
+            proxy.no_proxy.as_deref().unwrap_or("unset")
+        );
+    }
+
+    // Get server-specific working directory
+    let work_dir = config.work_dir(server_name);
+
+    // Catch a mistyped command before spending minutes on clone and build;
+    // relative paths usually come from the repository and are checked later
+    if !config.skip_command_check && !diagnostics::is_relative_path(&config.command) {
+        diagnostics::check_command(&config.command, std::path::Path::new(&work_dir))?;
+    }
+    timer
+        .measure("work_dir", tokio::fs::create_dir_all(&work_dir))
+        .await
+        .map_err(|e| McpCoreError::ProcessError {
+            message: format!("Failed to create work directory '{}': {}", work_dir, e),
+        })?;
+    // Another gateway sharing the work dir base may be setting it up too
+    let work_dir_lock = workdir_lock::acquire(
+        std::path::Path::new(&work_dir),
+        config.work_dir_lock_timeout_secs.map_or(
+            workdir_lock::DEFAULT_LOCK_TIMEOUT,
+            std::time::Duration::from_secs,
+        ),
+    )
+    .await?;
+
+    // Restore a shared artifact instead of cloning, if one matches
+    if let (Some(cache), Some(repository_url)) = (artifact_cache, &config.repository) {
+        let work_path = std::path::Path::new(&work_dir);
+        if matches!(repo::inspect(work_path).await, Ok(WorkDirState::Empty)) {
+            timer
+                .measure(
+                    "artifact_fetch",
+                    cache.restore(
+                        repository_url,
+                        pinned_commit,
+                        config.build_command.as_deref(),
+                        work_path,
+                        env,
+                    ),
+                )
+                .await;
+        }
+    }
+
+    // Clone repository if specified and not already exists
+    let mut changed = false;
+    if let Some(repository_url) = &config.repository {
+        changed |= timer
+            .measure(
+                "clone",
+                repo::prepare(
+                    repository_url,
+                    std::path::Path::new(&work_dir),
+                    pinned_commit,
+                    env,
+                    config.existing_work_dir,
+                ),
+            )
+            .await?;
+    }
+
+    // Execute build command if present and the cached build is stale
+    if let Some(build_cmd) = &config.build_command {
+        changed |= timer
+            .measure("build", build_if_stale(config, env, build_cmd, &work_dir))
+            .await?;
+    }
+
+    // Share a fresh clone or build with other instances
+    if let (Some(cache), Some(repository_url)) = (artifact_cache, &config.repository) {
+        if changed {
+            timer
+                .measure(
+                    "artifact_upload",
+                    cache.save(
+                        repository_url,
+                        config.build_command.as_deref(),
+                        std::path::Path::new(&work_dir),
+                    ),
+                )
+                .await;
+        }
+    }
+
+    // Audit the dependencies before any of the cloned code runs
+    if let Some(audit_config) = config.audit.as_ref().filter(|audit| audit.enabled) {
+        timer
+            .measure(
+                "audit",
+                audit::run(
+                    audit_config,
+                    std::path::Path::new(&work_dir),
+                    config.build_command.as_deref(),
+                    &config.runtime_config,
+                    env,
+                ),
+            )
+            .await?;
+    }
+
+    if !config.skip_command_check {
+        let work_dir = std::path::Path::new(&work_dir);
+        if diagnostics::is_relative_path(&config.command) {
+            diagnostics::check_command(&config.command, work_dir)?;
+        }
+        diagnostics::check_script_arg(&config.command, &config.args, work_dir)?;
+    }
+
+    // Record ownership and last use so cleanup can recognize this directory
+    if let Err(e) = workdir::touch_metadata(
+        std::path::Path::new(&work_dir),
+        server_name,
+        config.repository.as_deref(),
+    )
+    .await
+    {
+        tracing::warn!("Failed to update work dir metadata: {}", e);
+    }
+    drop(work_dir_lock);
+    Ok(())
+}
+
+/// Spawn the MCP server process in its prepared work directory
+///
+/// With a `sandbox`, only the server process runs inside it.
+async fn spawn_mcp_process(
+    config: &McpServerConfig,
+    server_name: &str,
+    env: &ChildEnv,
+    sandbox: Option<&Sandbox>,
+    timer: &mut PhaseTimer,
+) -> McpCoreResult<McpProcess> {
+    let work_dir = config.work_dir(server_name);
+    let mut command_builder = match sandbox {
+        Some(sandbox) => sandbox.command(
+            &config.command,
+            &config.args,
+            std::path::Path::new(&work_dir),
+        )?,
+        None => {
+            let mut command_builder = tokio::process::Command::new(&config.command);
+            command_builder.args(&config.args);
+            command_builder.current_dir(&work_dir);
+            command_builder
+        }
+    };
+    env.apply(&mut command_builder);
+    if let Some(sandbox) = sandbox {
+        sandbox.apply_env(&mut command_builder);
+    }
+
+    command_builder
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+
+    Ok(timer
+        .measure(
+            "spawn",
+            McpProcess::spawn_for_server(
+                command_builder,
+                server_name,
+                config.stderr_policy(server_name),
+            ),
+        )
+        .await?
+        .with_noise_policy(config.noise_policy())
+        .with_write_timeout(config.stdin_write_timeout_secs.map_or(
+            process::DEFAULT_STDIN_WRITE_TIMEOUT,
+            std::time::Duration::from_secs,
+        ))
+        .with_early_exit_window(config.early_exit_window_secs.map_or(
+            process::DEFAULT_EARLY_EXIT_WINDOW,
+            std::time::Duration::from_secs,
+        ))
+        .with_egress_proxy(sandbox.and_then(Sandbox::egress_proxy)))
+}
+
+/// Run the build command unless the cached build is still current,
+/// returning whether it ran
+async fn build_if_stale(
+    config: &McpServerConfig,
+    env: &ChildEnv,
+    build_cmd: &str,
+    work_dir: &str,
+) -> McpCoreResult<bool> {
+    let work_path = std::path::Path::new(work_dir);
+    let build_program = build_cmd.split_whitespace().next().unwrap_or_default();
+    let stamp = BuildStamp::compute(work_path, build_cmd, &[&config.command, build_program]).await;
+    let stale_reason = if config.force_build {
+        Some("force_build is set".to_string())
+    } else {
+        stamp.stale_reason(build_cache::read_stamp(work_path).await.as_ref())
+    };
+
+    match stale_reason {
+        Some(reason) => {
+            tracing::info!(
+                "Executing build command ({}): {}",
+                reason,
+                child_env::redact_command_line(build_cmd)
+            );
+            execute_build_command(build_cmd, config.shell, work_dir, env).await?;
+            if let Err(e) = build_cache::write_stamp(work_path, &stamp).await {
+                tracing::warn!("Failed to record build stamp: {}", e);
+            }
+            Ok(true)
+        }
+        None => {
+            tracing::info!("Skipping build, stamp matches the previous build");
+            Ok(false)
+        }
+    }
+}
+
+/// Get server-specific working directory path
+pub(crate) fn get_server_work_dir(server_name: &str) -> String {
+    repo::work_dir(server_name).to_string_lossy().into_owned()
+}
+
+/// Execute build command in the specified working directory
+///
+/// The command line and its output are logged with secrets redacted.
+async fn execute_build_command(
+    build_cmd: &str,
+    shell: Shell,
+    work_dir: &str,
+    env: &ChildEnv,
+) -> McpCoreResult<()> {
+    let logged_cmd = env.redact(build_cmd);
+    tracing::info!("Starting build process: {}", logged_cmd);
+
+    let start_time = std::time::Instant::now();
+    let output = execute_command(
+        StepKind::Build,
+        "Build",
+        build_cmd,
+        shell,
+        work_dir,
+        env,
+        None,
+    )
+    .await?;
+    let duration = start_time.elapsed();
+
+    // Check if the command was successful
+    if output.status.success() {
+        tracing::info!(
+            "Build command completed successfully in {:?}: {}",
+            duration,
+            logged_cmd
+        );
+        Ok(())
+    } else {
+        let error_msg = format!(
+            "Build command failed with exit code {:?}: {}",
+            output.status.code(),
+            logged_cmd
+        );
+        tracing::error!("{}", error_msg);
+        Err(McpCoreError::ProcessError { message: error_msg })
+    }
+}
+
+/// Run a setup command in `work_dir` through `shell` and record it as a
+/// step of `kind`, returning its output however it exited
+///
+/// The command runs with stdin closed so interactive prompts fail at once,
+/// and is killed once `timeout` passes. Its output is logged line by line
+/// as it comes, prefixed with `label`; the command line and the output are
+/// redacted.
+pub(crate) async fn execute_command(
+    kind: StepKind,
+    label: &str,
+    cmd: &str,
+    shell: Shell,
+    work_dir: &str,
+    env: &ChildEnv,
+    timeout: Option<std::time::Duration>,
+) -> McpCoreResult<std::process::Output> {
+    let logged_cmd = env.redact(cmd);
+
+    // Run the command through the configured shell, or split it without one
+    let mut command_builder = shell
+        .command(cmd)
+        .map_err(|reason| McpCoreError::ProcessError {
+            message: format!(
+                "{} has an invalid command '{}': {}",
+                label, logged_cmd, reason
+            ),
+        })?;
+
+    env.apply(&mut command_builder);
+
+    // Set working directory
+    command_builder.current_dir(work_dir);
+
+    // Capture output for logging
+    // A cancelled setup job kills the command
+    command_builder
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true);
+
+    tracing::debug!("Executing command in directory: {}", work_dir);
+
+    let started_at = chrono::Utc::now();
+    let failed = |e: std::io::Error| McpCoreError::ProcessError {
+        message: format!("Failed to execute {} '{}': {}", label, logged_cmd, e),
+    };
+    let mut child = command_builder.spawn().map_err(failed)?;
+    let stdout = log_lines(child.stdout.take(), label, "stdout", env);
+    let stderr = log_lines(child.stderr.take(), label, "stderr", env);
+    let run = async {
+        let (stdout, stderr, status) = tokio::join!(stdout, stderr, child.wait());
+        status.map(|status| std::process::Output {
+            status,
+            stdout,
+            stderr,
+        })
+    };
+    let output =
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, run).await.map_err(|_| {
+                McpCoreError::ProcessError {
+                    message: format!(
+                        "{} timed out after {} seconds: {}",
+                        label,
+                        timeout.as_secs(),
+                        logged_cmd
+                    ),
+                }
+            })?,
+            None => run.await,
+        }
+        .map_err(failed)?;
+
+    build_log::record_step(BuildStep::new(kind, cmd, started_at, &output, env));
+    Ok(output)
+}
+
+/// Log each line of a command's `stream` as it comes, redacted, and return
+/// the whole of it
+async fn log_lines<R: tokio::io::AsyncRead + Unpin>(
+    stream: Option<R>,
+    label: &str,
+    name: &str,
+    env: &ChildEnv,
+) -> Vec<u8> {
+    use tokio::io::AsyncBufReadExt;
+
+    let mut output = Vec::new();
+    let Some(stream) = stream else {
+        return output;
+    };
+    let mut reader = tokio::io::BufReader::new(stream);
+    loop {
+        let start = output.len();
+        match reader.read_until(b'\n', &mut output).await {
+            Ok(0) | Err(_) => return output,
+            Ok(_) => {
+                let line = env.redact(String::from_utf8_lossy(&output[start..]).trim_end());
+                if !line.is_empty() {
+                    tracing::info!("{} {}: {}", label, name, line);
+                }
+            }
+        }
+    }
+}
+
+/// Write lifecycle metadata; failing to persist it never fails startup
+async fn save_lifecycle(file: &LifecycleFile, state: &LifecycleState) {
+    if let Err(e) = file.save(state).await {
+        tracing::warn!("{}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::injection::REDACTED;
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// MCP server on a TCP socket answering each request with its method
+    async fn mock_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (reader, mut writer) = stream.into_split();
+                    let mut lines = BufReader::new(reader).lines();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let message: serde_json::Value = serde_json::from_str(&line).unwrap();
+                        let Some(id) = message.get("id") else {
+                            continue;
+                        };
+                        let response = serde_json::json!({
+                            "jsonrpc": "2.0",
+                            "id": id,
+                            "result": { "method": message["method"] }
+                        });
+                        let _ = writer.write_all(format!("{}\n", response).as_bytes()).await;
+                    }
+                });
+            }
+        });
+        address
+    }
+
+    fn manager(servers: serde_json::Value) -> ProcessManager {
+        let work_dir_base =
+            std::env::temp_dir().join(format!("mcp-manager-test-{}", std::process::id()));
+        let mut config = McpServersConfig::from_value(
+            serde_json::json!({ "servers": servers }),
+            "test configuration",
+        )
+        .unwrap();
+        for server in config.servers.values_mut() {
+            server.work_dir_base = Some(work_dir_base.clone());
+        }
+        ProcessManager::new(config)
+    }
+
+    #[tokio::test]
+    async fn test_start_query_and_stop() {
+        let address = mock_server().await;
+        let manager = manager(serde_json::json!({
+            "remote": { "transport": { "kind": "tcp", "address": address } }
+        }));
+        let mut events = manager.subscribe_events();
+
+        let status = manager.status("remote").await.unwrap();
+        assert_eq!(status.state, ProcessState::Stopped);
+        assert!(matches!(
+            manager
+                .query("remote", r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#)
+                .await,
+            Err(McpCoreError::NotProvisioned { .. })
+        ));
+
+        let status = manager.start("remote").await.unwrap();
+        assert_eq!(status.state, ProcessState::Running);
+        assert!(status.protocol_version.is_some());
+        // Starting again returns the running server
+        assert_eq!(
+            manager.start("remote").await.unwrap().state,
+            ProcessState::Running
+        );
+
+        let response = manager
+            .query(
+                "remote",
+                r#"{"jsonrpc":"2.0","id":7,"method":"tools/list"}"#,
+            )
+            .await
+            .unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["id"], 7);
+        assert_eq!(response["result"]["method"], "tools/list");
+        assert_eq!(manager.status("remote").await.unwrap().requests, 1);
+        assert!(manager
+            .query("remote", r#"{"jsonrpc":"2.0","method":"notifications/x"}"#)
+            .await
+            .is_err());
+
+        manager.stop("remote").await.unwrap();
+        assert_eq!(
+            manager.status("remote").await.unwrap().state,
+            ProcessState::Stopped
+        );
+        manager.stop("remote").await.unwrap();
+
+        let kinds: Vec<ManagerEventKind> = std::iter::from_fn(|| events.try_recv().ok())
+            .map(|event| event.kind)
+            .collect();
+        assert_eq!(kinds.len(), 3, "{:?}", kinds);
+        assert_eq!(kinds[0], ManagerEventKind::Starting);
+        assert!(matches!(kinds[1], ManagerEventKind::Ready { .. }));
+        assert_eq!(kinds[2], ManagerEventKind::Stopped);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_server_is_refused() {
+        let manager = manager(serde_json::json!({}));
+        assert!(manager.start("missing").await.is_err());
+        assert!(manager.status("missing").await.is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_failed_setup_is_reported() {
+        let manager = manager(serde_json::json!({
+            "broken": { "command": "cat", "build_command": "echo failing >&2; exit 4" }
+        }));
+        let mut events = manager.subscribe_events();
+
+        let error = manager.start("broken").await.unwrap_err();
+        assert!(
+            error.to_string().contains("Build command failed"),
+            "{}",
+            error
+        );
+        let status = manager.status("broken").await.unwrap();
+        assert_eq!(status.state, ProcessState::Failed);
+        assert!(status.error.unwrap().contains("exit code Some(4)"));
+        assert_eq!(events.try_recv().unwrap().kind, ManagerEventKind::Starting);
+        assert!(matches!(
+            events.try_recv().unwrap().kind,
+            ManagerEventKind::Failed { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_failed_handshake_is_retried_on_new_connections() {
+        // Answers initialize with neither a result nor an error
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let connections = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let accepted = Arc::clone(&connections);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                accepted.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                tokio::spawn(async move {
+                    let (reader, mut writer) = stream.into_split();
+                    let mut lines = BufReader::new(reader).lines();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let message: serde_json::Value = serde_json::from_str(&line).unwrap();
+                        let response = serde_json::json!({ "jsonrpc": "2.0", "id": message["id"] });
+                        let _ = writer.write_all(format!("{}\n", response).as_bytes()).await;
+                    }
+                });
+            }
+        });
+        let manager = manager(serde_json::json!({
+            "garbled": {
+                "command": "unused",
+                "transport": { "kind": "tcp", "address": address },
+                "initialize_retries": 2
+            }
+        }));
+        let mut events = manager.subscribe_events();
+
+        let error = manager.start("garbled").await.unwrap_err();
+        assert_eq!(error.error_code(), Some("handshake_failed"));
+        assert!(error.to_string().contains("3 attempts failed"), "{}", error);
+        assert_eq!(connections.load(std::sync::atomic::Ordering::Relaxed), 3);
+        let status = manager.status("garbled").await.unwrap();
+        assert_eq!(status.state, ProcessState::HandshakeFailed);
+        assert_eq!(events.try_recv().unwrap().kind, ManagerEventKind::Starting);
+        assert!(matches!(
+            events.try_recv().unwrap().kind,
+            ManagerEventKind::HandshakeFailed { attempts: 3, .. }
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_history_follows_crash_restart_and_stop() {
+        // Completes the handshake, then exits with code 3 on any request
+        let script = r#"while read request; do
+            id=$(echo "$request" | sed -n 's/.*"id":\("[^"]*"\|[0-9]*\).*/\1/p')
+            case "$request" in
+                *'"method":"initialize"'*)
+                    printf '{"jsonrpc":"2.0","id":%s,"result":{"protocolVersion":"2025-06-18","capabilities":{}}}\n' "$id" ;;
+                *'"id"'*) exit 3 ;;
+            esac
+        done"#;
+        let manager = manager(serde_json::json!({
+            "crashing": { "command": "sh", "args": ["-c", script], "history_size": 6 }
+        }));
+        assert!(manager.history("crashing").unwrap().is_empty());
+
+        manager.start("crashing").await.unwrap();
+        // The exit is noticed by a failing request once the child is reaped
+        loop {
+            assert!(manager
+                .query("crashing", r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#)
+                .await
+                .is_err());
+            if manager
+                .history("crashing")
+                .unwrap()
+                .iter()
+                .any(|record| record.transition == Transition::Exited)
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        manager
+            .restart("crashing", RestartStrategy::InPlace)
+            .await
+            .unwrap();
+        manager.stop("crashing").await.unwrap();
+
+        // The oldest of the seven records was dropped
+        let history = manager.history("crashing").unwrap();
+        let transitions: Vec<_> = history
+            .iter()
+            .map(|record| (record.transition, record.trigger))
+            .collect();
+        assert_eq!(
+            transitions,
+            [
+                (Transition::Initialized, Trigger::Start),
+                (Transition::Exited, Trigger::Crash),
+                (Transition::Restarting, Trigger::Manager),
+                (Transition::Spawned, Trigger::Manager),
+                (Transition::Initialized, Trigger::Manager),
+                (Transition::Exited, Trigger::Manager),
+            ]
+        );
+        assert_eq!(history[1].exit_code, Some(3));
+        assert_eq!(history[1].pid, history[0].pid);
+        assert_ne!(history[5].pid, history[0].pid);
+        assert_eq!(history[5].pid, history[4].pid);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_hooks_run_in_order_and_run_once_hooks_only_once() {
+        // Completes the handshake and ignores everything else
+        let script = r#"while read request; do
+            id=$(echo "$request" | sed -n 's/.*"id":\("[^"]*"\|[0-9]*\).*/\1/p')
+            case "$request" in
+                *'"method":"initialize"'*)
+                    printf '{"jsonrpc":"2.0","id":%s,"result":{"protocolVersion":"2025-06-18","capabilities":{}}}\n' "$id" ;;
+            esac
+        done"#;
+        let manager = manager(serde_json::json!({
+            "hooked": {
+                "command": "sh",
+                "args": ["-c", script],
+                "pre_start": [
+                    { "command": "echo once >> hooks.log", "run_once": true },
+                    { "command": "echo \"every $STAGE\" >> hooks.log", "env": { "STAGE": "start" } }
+                ],
+                "post_stop": [{ "command": "echo stopped >> hooks.log" }]
+            }
+        }));
+        let log = std::path::PathBuf::from(
+            manager
+                .config
+                .get_server("hooked")
+                .unwrap()
+                .work_dir("hooked"),
+        )
+        .join("hooks.log");
+        let _ = std::fs::remove_file(&log);
+
+        manager.start("hooked").await.unwrap();
+        manager
+            .restart("hooked", RestartStrategy::InPlace)
+            .await
+            .unwrap();
+        manager.stop("hooked").await.unwrap();
+
+        let lines = std::fs::read_to_string(&log).unwrap();
+        assert_eq!(
+            lines.lines().collect::<Vec<_>>(),
+            ["once", "every start", "every start", "stopped"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_failing_pre_start_hook_aborts_the_start() {
+        let manager = manager(serde_json::json!({
+            "failing": {
+                "command": "cat",
+                "pre_start": [
+                    { "command": "echo first >> hooks.log" },
+                    { "command": "echo broken >&2; exit 3" },
+                    { "command": "echo never >> hooks.log" }
+                ]
+            },
+            "slow": {
+                "command": "cat",
+                "pre_start": [{ "command": "sleep 30", "timeout_secs": 1 }]
+            }
+        }));
+        let work_dir = manager
+            .config
+            .get_server("failing")
+            .unwrap()
+            .work_dir("failing");
+        let log = std::path::Path::new(&work_dir).join("hooks.log");
+        let _ = std::fs::remove_file(&log);
+
+        let error = manager.start("failing").await.unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("pre_start[1] failed with exit code 3"),
+            "{}",
+            error
+        );
+        assert_eq!(
+            manager.status("failing").await.unwrap().state,
+            ProcessState::Failed
+        );
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "first\n");
+
+        let started = std::time::Instant::now();
+        let error = manager.start("slow").await.unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("pre_start[0] timed out after 1 seconds"),
+            "{}",
+            error
+        );
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_build_logging_is_redacted() {
+        let (logs, _default) = crate::test_support::CapturedLogs::install();
+        let env = ChildEnv {
+            vars: vec![("NPM_TOKEN".to_string(), "hunter2-secret".to_string())],
+            ..ChildEnv::default()
+        };
+        let build_cmd =
+            "API_TOKEN=inline-secret echo \"$NPM_TOKEN\"; echo 'Authorization: Bearer header-secret'";
+
+        execute_build_command(build_cmd, Shell::Sh, "/tmp", &env)
+            .await
+            .unwrap();
+
+        let output = logs.contents();
+        assert!(output.contains("Build stdout"), "{}", output);
+        assert!(output.contains(REDACTED));
+        for secret in ["hunter2-secret", "inline-secret", "header-secret"] {
+            assert!(!output.contains(secret), "{} leaked:\n{}", secret, output);
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_build_without_shell_keeps_quoted_arguments() {
+        let env = ChildEnv::default();
+        // Quotes still group words, but `$HOME` is not expanded
+        let build_cmd = r#"test "a b" = 'a b' -a $HOME = '$HOME'"#;
+        execute_build_command(build_cmd, Shell::None, "/tmp", &env)
+            .await
+            .unwrap();
+        let error = execute_build_command(build_cmd, Shell::Sh, "/tmp", &env)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, McpCoreError::ProcessError { .. }));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_interactive_build_fails_promptly() {
+        let env = ChildEnv::default();
+        let build = execute_build_command(
+            "read answer && test -n \"$answer\"",
+            Shell::Sh,
+            "/tmp",
+            &env,
+        );
+        let result = tokio::time::timeout(Duration::from_secs(5), build)
+            .await
+            .expect("build waited for input");
+        assert!(matches!(result, Err(McpCoreError::ProcessError { .. })));
+    }
+}
@@ -0,0 +1,381 @@
+//! Timeouts per JSON-RPC method
+//!
+//! A request's timeout is the first of:
+//!
+//! 1. the `X-MCP-Timeout` header, in seconds, capped at the timeout that
+//!    would apply without it, so clients can only shorten it
+//! 2. the most specific match in the server's `method_timeouts`
+//! 3. the server's `request_deadline_secs`
+//! 4. [`DEFAULT_REQUEST_DEADLINE`]
+//!
+//! `method_timeouts` keys are a method (`tools/list`), a method prefix
+//! ending in `*` (`resources/*`), or a tool of `tools/call`
+//! (`tools/call:generate_report`, `tools/call:report_*`). Tool keys beat
+//! method keys, exact keys beat prefixes, and longer prefixes beat shorter
+//! ones. Requests running past their timeout fail with `504` naming the
+//! limit that fired.
+
+use crate::error::{McpCoreError, McpCoreResult};
+use crate::inflight::DEFAULT_REQUEST_DEADLINE;
+use crate::transport::RESPONSE_TIMEOUT;
+use http::HeaderMap;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+/// Header shortening a request's timeout
+pub const TIMEOUT_HEADER: &str = "x-mcp-timeout";
+
+/// Method whose keys may name a tool
+const TOOLS_CALL: &str = "tools/call";
+
+/// A name, or a prefix if the key ended in `*`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct NamePattern {
+    name: String,
+    prefix: bool,
+}
+
+impl NamePattern {
+    pub(crate) fn parse(text: &str) -> Result<Self, String> {
+        let (name, prefix) = match text.strip_suffix('*') {
+            Some(name) => (name, true),
+            None => (text, false),
+        };
+        if !prefix && name.is_empty() {
+            return Err("is empty".to_string());
+        }
+        match name
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '/')))
+        {
+            Some(c) => Err(format!("has unexpected '{}'", c)),
+            None => Ok(Self {
+                name: name.to_string(),
+                prefix,
+            }),
+        }
+    }
+
+    pub(crate) fn matches(&self, value: &str) -> bool {
+        match self.prefix {
+            true => value.starts_with(&self.name),
+            false => value == self.name,
+        }
+    }
+
+    /// Higher for patterns matching fewer names
+    fn specificity(&self) -> (bool, usize) {
+        (!self.prefix, self.name.len())
+    }
+}
+
+/// A `method_timeouts` key
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum MethodPattern {
+    Method(NamePattern),
+    Tool(NamePattern),
+}
+
+impl MethodPattern {
+    fn parse(key: &str) -> Result<Self, String> {
+        match key.split_once(':') {
+            Some((TOOLS_CALL, tool)) => NamePattern::parse(tool)
+                .map(Self::Tool)
+                .map_err(|reason| format!("tool {}", reason)),
+            Some(_) => Err(format!("may only name a tool of {}", TOOLS_CALL)),
+            None => NamePattern::parse(key).map(Self::Method),
+        }
+    }
+
+    fn matches(&self, method: &str, tool: Option<&str>) -> bool {
+        match self {
+            Self::Method(pattern) => pattern.matches(method),
+            Self::Tool(pattern) => method == TOOLS_CALL && tool.is_some_and(|t| pattern.matches(t)),
+        }
+    }
+
+    fn specificity(&self) -> (bool, bool, usize) {
+        let (is_tool, pattern) = match self {
+            Self::Method(pattern) => (false, pattern),
+            Self::Tool(pattern) => (true, pattern),
+        };
+        let (exact, length) = pattern.specificity();
+        (is_tool, exact, length)
+    }
+}
+
+/// Check the keys and values of a server's `method_timeouts`
+pub fn validate(method_timeouts: &HashMap<String, u64>) -> Result<(), String> {
+    for (key, secs) in method_timeouts {
+        MethodPattern::parse(key)
+            .map_err(|reason| format!("method_timeouts key '{}' {}", key, reason))?;
+        if *secs == 0 {
+            return Err(format!("method_timeouts '{}' must be positive", key));
+        }
+    }
+    Ok(())
+}
+
+/// Which limit set a request's timeout
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimeoutLimit {
+    /// The `X-MCP-Timeout` header
+    Header,
+    /// The `method_timeouts` entry with this key
+    Method(String),
+    /// The server's `request_deadline_secs`
+    Server,
+    /// [`DEFAULT_REQUEST_DEADLINE`]
+    Default,
+}
+
+impl fmt::Display for TimeoutLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Header => f.write_str("header"),
+            Self::Method(key) => write!(f, "method:{}", key),
+            Self::Server => f.write_str("server"),
+            Self::Default => f.write_str("default"),
+        }
+    }
+}
+
+/// A request's timeout and where it came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedTimeout {
+    pub duration: Duration,
+    pub limit: TimeoutLimit,
+}
+
+impl ResolvedTimeout {
+    /// Longest wait for a single message from the server
+    ///
+    /// A timeout set for the request's method or by the client may exceed
+    /// [`RESPONSE_TIMEOUT`]; the server's and the default deadline leave it
+    /// alone.
+    pub fn message_timeout(&self) -> Duration {
+        match self.limit {
+            TimeoutLimit::Header | TimeoutLimit::Method(_) => self.duration.max(RESPONSE_TIMEOUT),
+            TimeoutLimit::Server | TimeoutLimit::Default => RESPONSE_TIMEOUT,
+        }
+    }
+
+    /// Error of a request that ran past this timeout
+    pub fn exceeded(&self, description: &str) -> McpCoreError {
+        McpCoreError::RequestTimeout {
+            message: format!(
+                "{} exceeded its {}s timeout ({})",
+                description,
+                self.duration.as_secs(),
+                self.limit
+            ),
+            timeout_secs: self.duration.as_secs(),
+            limit: self.limit.to_string(),
+        }
+    }
+}
+
+/// A server's timeouts, most specific first
+#[derive(Debug, Clone, Default)]
+pub struct MethodTimeouts {
+    rules: Vec<(String, MethodPattern, Duration)>,
+    server: Option<Duration>,
+}
+
+impl MethodTimeouts {
+    /// Timeouts of a validated `method_timeouts` over the server's deadline
+    pub fn new(method_timeouts: &HashMap<String, u64>, server: Option<Duration>) -> Self {
+        let mut rules: Vec<(String, MethodPattern, Duration)> = method_timeouts
+            .iter()
+            .filter_map(|(key, secs)| {
+                let pattern = MethodPattern::parse(key).ok()?;
+                Some((key.clone(), pattern, Duration::from_secs(*secs)))
+            })
+            .collect();
+        rules.sort_by(|(a_key, a, _), (b_key, b, _)| {
+            b.specificity()
+                .cmp(&a.specificity())
+                .then_with(|| a_key.cmp(b_key))
+        });
+        Self { rules, server }
+    }
+
+    /// Timeout of `message` from the configuration and `headers`
+    pub fn resolve(&self, message: &Value, headers: &HeaderMap) -> McpCoreResult<ResolvedTimeout> {
+        let configured = self.configured(message);
+        let Some(value) = headers.get(TIMEOUT_HEADER) else {
+            return Ok(configured);
+        };
+        let secs = value
+            .to_str()
+            .ok()
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .filter(|secs| *secs > 0)
+            .ok_or_else(|| McpCoreError::RequestError {
+                message: "X-MCP-Timeout must be a positive number of seconds".to_string(),
+            })?;
+        let requested = Duration::from_secs(secs);
+        if requested >= configured.duration {
+            return Ok(configured);
+        }
+        Ok(ResolvedTimeout {
+            duration: requested,
+            limit: TimeoutLimit::Header,
+        })
+    }
+
+    /// Timeout of `message` from the configuration alone
+    fn configured(&self, message: &Value) -> ResolvedTimeout {
+        let method = message.get("method").and_then(Value::as_str);
+        let tool = message
+            .pointer("/params/name")
+            .and_then(Value::as_str)
+            .filter(|_| method == Some(TOOLS_CALL));
+        let rule = method.and_then(|method| {
+            self.rules
+                .iter()
+                .find(|(_, pattern, _)| pattern.matches(method, tool))
+        });
+        match (rule, self.server) {
+            (Some((key, _, duration)), _) => ResolvedTimeout {
+                duration: *duration,
+                limit: TimeoutLimit::Method(key.clone()),
+            },
+            (None, Some(duration)) => ResolvedTimeout {
+                duration,
+                limit: TimeoutLimit::Server,
+            },
+            (None, None) => ResolvedTimeout {
+                duration: DEFAULT_REQUEST_DEADLINE,
+                limit: TimeoutLimit::Default,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn timeouts(entries: &[(&str, u64)], server: Option<u64>) -> MethodTimeouts {
+        let config = entries
+            .iter()
+            .map(|(key, secs)| (key.to_string(), *secs))
+            .collect();
+        MethodTimeouts::new(&config, server.map(Duration::from_secs))
+    }
+
+    fn call(tool: &str) -> Value {
+        serde_json::json!({
+            "jsonrpc": "2.0", "id": 1, "method": "tools/call", "params": { "name": tool }
+        })
+    }
+
+    fn resolve(timeouts: &MethodTimeouts, message: &Value) -> (u64, String) {
+        let resolved = timeouts.resolve(message, &HeaderMap::new()).unwrap();
+        (resolved.duration.as_secs(), resolved.limit.to_string())
+    }
+
+    #[test]
+    fn test_patterns_validated() {
+        let config = |key: &str, secs: u64| HashMap::from([(key.to_string(), secs)]);
+        for key in [
+            "tools/list",
+            "resources/*",
+            "*",
+            "tools/call:generate_report",
+            "tools/call:report_*",
+        ] {
+            assert!(validate(&config(key, 5)).is_ok(), "{}", key);
+        }
+        for (key, reason) in [
+            ("", "is empty"),
+            ("tools/*/list", "unexpected '*'"),
+            ("tools list", "unexpected ' '"),
+            ("prompts/get:greeting", "may only name a tool"),
+            ("tools/call:", "tool is empty"),
+        ] {
+            let error = validate(&config(key, 5)).unwrap_err();
+            assert!(error.contains(reason), "{}: {}", key, error);
+        }
+        let error = validate(&config("tools/list", 0)).unwrap_err();
+        assert!(error.contains("must be positive"));
+    }
+
+    #[test]
+    fn test_most_specific_timeout_wins() {
+        let timeouts = timeouts(
+            &[
+                ("*", 60),
+                ("tools/*", 20),
+                ("tools/list", 2),
+                ("tools/call", 30),
+                ("tools/call:report_*", 120),
+                ("tools/call:report_yearly", 300),
+            ],
+            Some(45),
+        );
+        let list = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/list" });
+        assert_eq!(
+            resolve(&timeouts, &list),
+            (2, "method:tools/list".to_string())
+        );
+        assert_eq!(
+            resolve(&timeouts, &call("report_yearly")),
+            (300, "method:tools/call:report_yearly".to_string())
+        );
+        assert_eq!(
+            resolve(&timeouts, &call("report_daily")),
+            (120, "method:tools/call:report_*".to_string())
+        );
+        assert_eq!(
+            resolve(&timeouts, &call("echo")),
+            (30, "method:tools/call".to_string())
+        );
+        let prompts = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "prompts/list" });
+        assert_eq!(resolve(&timeouts, &prompts), (60, "method:*".to_string()));
+
+        // Batches and unmatched methods fall back to the server, then the default
+        let batch = serde_json::json!([list]);
+        assert_eq!(resolve(&timeouts, &batch), (45, "server".to_string()));
+        let unconfigured = self::timeouts(&[("tools/list", 2)], None);
+        assert_eq!(
+            resolve(&unconfigured, &prompts),
+            (DEFAULT_REQUEST_DEADLINE.as_secs(), "default".to_string())
+        );
+    }
+
+    #[test]
+    fn test_header_only_shortens_timeout() {
+        let timeouts = timeouts(&[("tools/call:slow", 300)], Some(45));
+        let with_header = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(TIMEOUT_HEADER, HeaderValue::from_str(value).unwrap());
+            timeouts.resolve(&call("slow"), &headers)
+        };
+
+        let resolved = with_header("10").unwrap();
+        assert_eq!(resolved.duration, Duration::from_secs(10));
+        assert_eq!(resolved.limit, TimeoutLimit::Header);
+        assert_eq!(resolved.message_timeout(), RESPONSE_TIMEOUT);
+
+        // Longer than configured is capped at the configured timeout
+        let resolved = with_header("900").unwrap();
+        assert_eq!(resolved.duration, Duration::from_secs(300));
+        assert_eq!(resolved.limit.to_string(), "method:tools/call:slow");
+        assert_eq!(resolved.message_timeout(), Duration::from_secs(300));
+
+        for invalid in ["0", "-1", "soon"] {
+            assert!(with_header(invalid).is_err(), "{}", invalid);
+        }
+
+        let error = with_header("10").unwrap().exceeded("Request 1");
+        assert_eq!(
+            error.to_string(),
+            "Request timed out: Request 1 exceeded its 10s timeout (header)"
+        );
+    }
+}
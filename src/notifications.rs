@@ -0,0 +1,189 @@
+//! Notifications from the MCP server, kept for long-polling clients
+//!
+//! Notifications the server sends while the gateway exchanges messages with
+//! it are numbered and kept in a bounded ring. `GET /api/v1/notifications`
+//! returns those after a client's cursor, waiting for the next one when there
+//! are none yet. A cursor older than the ring's oldest notification is
+//! reported as a gap, so clients know they missed some.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Default number of notifications kept
+pub const DEFAULT_NOTIFICATION_BUFFER: usize = 256;
+
+/// Default cap on how long a long-poll may wait
+pub const DEFAULT_MAX_NOTIFICATION_WAIT: Duration = Duration::from_secs(30);
+
+/// A notification with its sequence number
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SequencedNotification {
+    pub seq: u64,
+    pub received_at: chrono::DateTime<chrono::Utc>,
+    pub notification: Value,
+}
+
+/// Notifications after a cursor
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct NotificationPage {
+    pub notifications: Vec<SequencedNotification>,
+
+    /// Cursor to pass as `since` for the next page
+    pub cursor: u64,
+
+    /// Whether notifications after the given cursor were already dropped
+    pub gap: bool,
+}
+
+/// Bounded ring of the most recent notifications
+pub struct NotificationRing {
+    capacity: usize,
+    ring: Mutex<VecDeque<SequencedNotification>>,
+
+    /// Sequence number of the latest notification, 0 before the first
+    latest: watch::Sender<u64>,
+}
+
+impl Default for NotificationRing {
+    fn default() -> Self {
+        Self::new(DEFAULT_NOTIFICATION_BUFFER)
+    }
+}
+
+impl NotificationRing {
+    /// Create a ring keeping the last `capacity` notifications
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            ring: Mutex::new(VecDeque::new()),
+            latest: watch::channel(0).0,
+        }
+    }
+
+    /// Add a notification, dropping the oldest when full
+    pub fn push(&self, notification: &str) {
+        let notification = serde_json::from_str(notification)
+            .unwrap_or_else(|_| Value::String(notification.to_string()));
+        let mut ring = self.lock();
+        if ring.len() >= self.capacity {
+            ring.pop_front();
+        }
+        let seq = *self.latest.borrow() + 1;
+        ring.push_back(SequencedNotification {
+            seq,
+            received_at: chrono::Utc::now(),
+            notification,
+        });
+        self.latest.send_replace(seq);
+    }
+
+    /// Sequence number of the latest notification
+    pub fn cursor(&self) -> u64 {
+        *self.latest.borrow()
+    }
+
+    /// Notifications after `cursor`, without waiting
+    ///
+    /// A cursor ahead of the latest notification (say, from before a restart)
+    /// is reported as a gap and answered with the current cursor.
+    pub fn since(&self, cursor: u64) -> NotificationPage {
+        let ring = self.lock();
+        let latest = *self.latest.borrow();
+        if cursor > latest {
+            return NotificationPage {
+                notifications: Vec::new(),
+                cursor: latest,
+                gap: true,
+            };
+        }
+        let gap = ring.front().is_some_and(|oldest| oldest.seq > cursor + 1);
+        NotificationPage {
+            notifications: ring.iter().filter(|n| n.seq > cursor).cloned().collect(),
+            cursor: latest,
+            gap,
+        }
+    }
+
+    /// Notifications after `cursor`, waiting up to `wait` for one to arrive
+    pub async fn wait_since(&self, cursor: u64, wait: Duration) -> NotificationPage {
+        let mut latest = self.latest.subscribe();
+        let page = self.since(cursor);
+        if !page.notifications.is_empty() || page.gap || wait.is_zero() {
+            return page;
+        }
+        // The sender lives as long as `self`, so this only ends on timeout
+        let _ = tokio::time::timeout(wait, latest.wait_for(|&seq| seq > cursor)).await;
+        self.since(cursor)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<SequencedNotification>> {
+        self.ring.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification(n: u64) -> String {
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/progress",
+            "params": { "progress": n }
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_since_returns_notifications_after_cursor() {
+        let ring = NotificationRing::new(4);
+        for n in 1..=3 {
+            ring.push(&notification(n));
+        }
+
+        let page = ring.since(1);
+        assert_eq!(page.cursor, 3);
+        assert!(!page.gap);
+        let seqs: Vec<u64> = page.notifications.iter().map(|n| n.seq).collect();
+        assert_eq!(seqs, vec![2, 3]);
+        assert_eq!(page.notifications[0].notification["params"]["progress"], 2);
+
+        assert!(ring.since(3).notifications.is_empty());
+    }
+
+    #[test]
+    fn test_cursor_behind_the_ring_is_a_gap() {
+        let ring = NotificationRing::new(2);
+        for n in 1..=5 {
+            ring.push(&notification(n));
+        }
+
+        let page = ring.since(1);
+        assert!(page.gap);
+        let seqs: Vec<u64> = page.notifications.iter().map(|n| n.seq).collect();
+        assert_eq!(seqs, vec![4, 5]);
+
+        assert!(!ring.since(3).gap);
+        assert!(ring.since(9).gap);
+        assert_eq!(ring.since(9).cursor, 5);
+    }
+
+    #[tokio::test]
+    async fn test_wait_since_wakes_on_push() {
+        let ring = std::sync::Arc::new(NotificationRing::default());
+        let waiter = {
+            let ring = ring.clone();
+            tokio::spawn(async move { ring.wait_since(0, Duration::from_secs(5)).await })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        ring.push(&notification(1));
+
+        let page = waiter.await.unwrap();
+        assert_eq!(page.notifications.len(), 1);
+        assert_eq!(page.cursor, 1);
+    }
+}
@@ -0,0 +1,152 @@
+//! The JSON-RPC `method` and `id` of a command, read without parsing it whole
+//!
+//! Requests carry their JSON-RPC message as an opaque string, so logs and
+//! dashboards would otherwise only see the whole command or nothing. The
+//! peek walks the top-level members of the first [`PEEK_BYTES`] of the
+//! command and stops once it has both fields, so a huge command costs no
+//! more than a small one. It never fails: a command that is not a JSON
+//! object, or whose `method` lies past the inspected bytes, is labelled
+//! [`UNPARSED`].
+
+use serde::de::{DeserializeSeed, IgnoredAny, MapAccess, Visitor};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::fmt;
+
+/// Opening bytes of a command inspected for its `method` and `id`
+pub const PEEK_BYTES: usize = 16 * 1024;
+
+/// Label of a command whose `method` could not be read
+pub const UNPARSED: &str = "unparsed";
+
+/// What the opening of a command says about it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandPeek {
+    pub method: Option<String>,
+    pub id: Option<Value>,
+}
+
+impl CommandPeek {
+    /// `method` and `id` of `command`, as far as its first [`PEEK_BYTES`]
+    /// show them
+    pub fn of(command: &str) -> Self {
+        let head = &command.as_bytes()[..command.len().min(PEEK_BYTES)];
+        let mut peek = Self::default();
+        // Truncated or invalid input still leaves the members read before it
+        let _ = Members(&mut peek).deserialize(&mut serde_json::Deserializer::from_slice(head));
+        peek
+    }
+
+    /// The method, or [`UNPARSED`]
+    pub fn method_label(&self) -> &str {
+        self.method.as_deref().unwrap_or(UNPARSED)
+    }
+
+    /// The id as compact JSON, if there is one
+    pub fn id_label(&self) -> Option<String> {
+        self.id.as_ref().map(Value::to_string)
+    }
+}
+
+/// Reads the top-level members into a [`CommandPeek`] as they come
+struct Members<'a>(&'a mut CommandPeek);
+
+impl<'de> DeserializeSeed<'de> for Members<'_> {
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Members<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a JSON-RPC message")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while self.0.method.is_none() || self.0.id.is_none() {
+            let Some(key) = map.next_key::<Cow<str>>()? else {
+                break;
+            };
+            match key.as_ref() {
+                "method" => self.0.method = map.next_value::<Option<String>>()?,
+                "id" => self.0.id = Some(map.next_value()?),
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        // The rest of the message is left unread
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_method_and_id_of_valid_commands() {
+        let peek = CommandPeek::of(r#"{"jsonrpc":"2.0","id":7,"method":"tools/call","params":{}}"#);
+        assert_eq!(peek.method_label(), "tools/call");
+        assert_eq!(peek.id_label().as_deref(), Some("7"));
+
+        let peek = CommandPeek::of(r#"{"method":"notifications/cancelled","params":{"id":1}}"#);
+        assert_eq!(peek.method_label(), "notifications/cancelled");
+        assert_eq!(peek.id, None);
+
+        let peek = CommandPeek::of(r#"{"id":"a\"b","method":"ping"}"#);
+        assert_eq!(peek.id, Some(Value::from("a\"b")));
+        assert_eq!(peek.id_label().as_deref(), Some(r#""a\"b""#));
+    }
+
+    #[test]
+    fn test_truncated_and_non_json_commands_are_unparsed_or_partial() {
+        // Members before the cut are kept
+        let peek =
+            CommandPeek::of(r#"{"jsonrpc":"2.0","id":3,"method":"tools/call","params":{"na"#);
+        assert_eq!(peek.method_label(), "tools/call");
+        assert_eq!(peek.id_label().as_deref(), Some("3"));
+        let peek = CommandPeek::of(r#"{"jsonrpc":"2.0","id":3,"meth"#);
+        assert_eq!(peek.method_label(), UNPARSED);
+        assert_eq!(peek.id_label().as_deref(), Some("3"));
+
+        for command in [
+            "",
+            "not json",
+            "[1,2]",
+            "42",
+            r#"{"method":7}"#,
+            r#"{"method""#,
+        ] {
+            let peek = CommandPeek::of(command);
+            assert_eq!(peek.method_label(), UNPARSED, "{}", command);
+        }
+    }
+
+    #[test]
+    fn test_huge_commands_are_only_inspected_at_the_start() {
+        let filler = "x".repeat(10 * PEEK_BYTES);
+        let leading = format!(
+            r#"{{"jsonrpc":"2.0","id":1,"method":"tools/call","params":{{"text":"{}"}}}}"#,
+            filler
+        );
+        let peek = CommandPeek::of(&leading);
+        assert_eq!(peek.method_label(), "tools/call");
+        assert_eq!(peek.id_label().as_deref(), Some("1"));
+
+        // A method after the inspected bytes is not found
+        let trailing = format!(
+            r#"{{"params":{{"text":"{}"}},"jsonrpc":"2.0","id":1,"method":"tools/call"}}"#,
+            filler
+        );
+        assert_eq!(CommandPeek::of(&trailing), CommandPeek::default());
+
+        // Cutting inside a multi-byte character is harmless
+        let multibyte = format!(r#"{{"params":"{}"}}"#, "é".repeat(PEEK_BYTES));
+        assert_eq!(CommandPeek::of(&multibyte).method_label(), UNPARSED);
+    }
+}
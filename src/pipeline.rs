@@ -0,0 +1,439 @@
+//! Order and selection of the HTTP middleware
+//!
+//! `middleware` in the configuration lists the layers a request passes
+//! through, outermost first, each with its settings inline:
+//!
+//! ```json
+//! "middleware": [
+//!   { "name": "access_log" },
+//!   { "name": "rate_limit", "requests_per_sec": 50, "burst": 100 },
+//!   { "name": "auth" },
+//!   { "name": "version_headers", "enabled": false }
+//! ]
+//! ```
+//!
+//! Layers before `auth` see every request, including health checks and
+//! unauthenticated ones; layers after it only see requests to authenticated
+//! routes that passed auth. `auth` must be listed. A layer left out, or
+//! listed with `"enabled": false`, is not applied. Without the section the
+//! pipeline is [`DEFAULT_PIPELINE`].
+
+#[cfg(feature = "http-server")]
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+#[cfg(feature = "http-server")]
+use crate::error::McpCoreError;
+
+/// Layers known to the pipeline
+pub const LAYER_NAMES: &[&str] = &[
+    "access_log",
+    "version_headers",
+    "response_headers",
+    "auth",
+    "rate_limit",
+];
+
+/// Pipeline used when the configuration has no `middleware`
+pub const DEFAULT_PIPELINE: &[&str] =
+    &["access_log", "version_headers", "response_headers", "auth"];
+
+/// One entry of the `middleware` list
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MiddlewareEntry {
+    pub name: String,
+
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+
+    /// Settings of the layer, e.g. `requests_per_sec` of `rate_limit`
+    #[serde(flatten)]
+    pub settings: Map<String, Value>,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+/// A layer with its settings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "name", rename_all = "snake_case")]
+pub enum Layer {
+    /// Log each request, see [`crate::access_log`]
+    AccessLog,
+    /// `Server` and `X-MCP-*` version headers
+    VersionHeaders,
+    /// The server's configured `response_headers`
+    ResponseHeaders,
+    /// Bearer API key check
+    Auth,
+    /// Gateway-wide token bucket; excess requests receive `429`
+    RateLimit { requests_per_sec: u32, burst: u32 },
+}
+
+impl Layer {
+    fn parse(entry: &MiddlewareEntry) -> Result<Self, String> {
+        let settings = &entry.settings;
+        let allowed: &[&str] = match entry.name.as_str() {
+            "rate_limit" => &["requests_per_sec", "burst"],
+            name if LAYER_NAMES.contains(&name) => &[],
+            name => {
+                return Err(format!(
+                    "middleware '{}' is unknown; expected one of {}",
+                    name,
+                    LAYER_NAMES.join(", ")
+                ))
+            }
+        };
+        if let Some(key) = settings.keys().find(|key| !allowed.contains(&key.as_str())) {
+            return Err(format!(
+                "middleware '{}' has no setting '{}'",
+                entry.name, key
+            ));
+        }
+
+        Ok(match entry.name.as_str() {
+            "access_log" => Layer::AccessLog,
+            "version_headers" => Layer::VersionHeaders,
+            "response_headers" => Layer::ResponseHeaders,
+            "auth" => Layer::Auth,
+            _ => {
+                let positive = |key: &str| -> Result<Option<u32>, String> {
+                    match settings.get(key) {
+                        None => Ok(None),
+                        Some(value) => value
+                            .as_u64()
+                            .and_then(|value| u32::try_from(value).ok())
+                            .filter(|value| *value > 0)
+                            .map(Some)
+                            .ok_or_else(|| {
+                                format!(
+                                    "middleware 'rate_limit' {} must be a positive integer",
+                                    key
+                                )
+                            }),
+                    }
+                };
+                let requests_per_sec = positive("requests_per_sec")?.ok_or_else(|| {
+                    "middleware 'rate_limit' requires requests_per_sec".to_string()
+                })?;
+                Layer::RateLimit {
+                    requests_per_sec,
+                    burst: positive("burst")?.unwrap_or(requests_per_sec),
+                }
+            }
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Layer::AccessLog => "access_log",
+            Layer::VersionHeaders => "version_headers",
+            Layer::ResponseHeaders => "response_headers",
+            Layer::Auth => "auth",
+            Layer::RateLimit { .. } => "rate_limit",
+        }
+    }
+}
+
+/// Which requests a layer sees
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// Every request
+    All,
+    /// Requests to authenticated routes that passed auth
+    Authenticated,
+}
+
+/// A listed layer, as shown by `GET /admin/middleware`
+#[derive(Debug, Clone, Serialize)]
+pub struct LayerInfo {
+    #[serde(flatten)]
+    pub layer: Layer,
+    pub enabled: bool,
+    pub scope: Scope,
+
+    /// Whether the layer is in the router; a layer whose feature is not
+    /// configured, such as `access_log` without an access log, is left out
+    pub active: bool,
+}
+
+/// The validated middleware list
+#[derive(Debug)]
+pub struct Pipeline {
+    /// Listed layers, outermost first, with whether each is enabled
+    layers: Vec<(Layer, bool)>,
+    configured: bool,
+    rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self::new(None).expect("default pipeline is valid")
+    }
+}
+
+impl Pipeline {
+    /// Parse the configured list, or take [`DEFAULT_PIPELINE`]
+    pub fn new(entries: Option<&[MiddlewareEntry]>) -> Result<Self, String> {
+        let Some(entries) = entries else {
+            return Ok(Self {
+                layers: DEFAULT_PIPELINE
+                    .iter()
+                    .map(|name| {
+                        let entry = MiddlewareEntry {
+                            name: name.to_string(),
+                            enabled: true,
+                            settings: Map::new(),
+                        };
+                        Layer::parse(&entry).map(|layer| (layer, true))
+                    })
+                    .collect::<Result<_, _>>()?,
+                configured: false,
+                rate_limiter: None,
+            });
+        };
+
+        let mut names = HashSet::new();
+        let mut layers = Vec::new();
+        for entry in entries {
+            let layer = Layer::parse(entry)?;
+            if !names.insert(layer.name()) {
+                return Err(format!(
+                    "middleware '{}' is listed more than once",
+                    entry.name
+                ));
+            }
+            if layer == Layer::Auth && !entry.enabled {
+                return Err(
+                    "middleware 'auth' cannot be disabled; use DISABLE_AUTH instead".to_string(),
+                );
+            }
+            layers.push((layer, entry.enabled));
+        }
+        if !names.contains("auth") {
+            return Err("middleware must list 'auth'".to_string());
+        }
+
+        let rate_limiter = layers.iter().find_map(|(layer, enabled)| match layer {
+            Layer::RateLimit {
+                requests_per_sec,
+                burst,
+            } if *enabled => Some(Arc::new(RateLimiter::new(*requests_per_sec, *burst))),
+            _ => None,
+        });
+        Ok(Self {
+            layers,
+            configured: true,
+            rate_limiter,
+        })
+    }
+
+    /// Whether the list comes from the configuration
+    pub fn is_configured(&self) -> bool {
+        self.configured
+    }
+
+    fn auth_position(&self) -> usize {
+        self.layers
+            .iter()
+            .position(|(layer, _)| *layer == Layer::Auth)
+            .unwrap_or(self.layers.len())
+    }
+
+    /// Enabled layers seeing every request, outermost first
+    pub fn outer(&self) -> impl DoubleEndedIterator<Item = &Layer> {
+        self.layers[..self.auth_position()]
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(layer, _)| layer)
+    }
+
+    /// Auth and the enabled layers behind it, outermost first
+    pub fn authenticated(&self) -> impl DoubleEndedIterator<Item = &Layer> {
+        self.layers[self.auth_position()..]
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(layer, _)| layer)
+    }
+
+    pub fn rate_limiter(&self) -> Option<Arc<RateLimiter>> {
+        self.rate_limiter.clone()
+    }
+
+    /// Every listed layer, outermost first, with `applies` telling whether
+    /// an enabled layer is in the router
+    pub fn describe(&self, applies: impl Fn(&Layer) -> bool) -> Vec<LayerInfo> {
+        let auth = self.auth_position();
+        self.layers
+            .iter()
+            .enumerate()
+            .map(|(position, (layer, enabled))| LayerInfo {
+                layer: layer.clone(),
+                enabled: *enabled,
+                scope: if position < auth {
+                    Scope::All
+                } else {
+                    Scope::Authenticated
+                },
+                active: *enabled && applies(layer),
+            })
+            .collect()
+    }
+}
+
+/// Token bucket shared by every request to the gateway
+#[derive(Debug)]
+pub struct RateLimiter {
+    requests_per_sec: f64,
+    burst: f64,
+
+    /// Tokens left and when they were counted
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_sec: u32, burst: u32) -> Self {
+        Self {
+            requests_per_sec: requests_per_sec.into(),
+            burst: burst.into(),
+            bucket: Mutex::new((burst.into(), Instant::now())),
+        }
+    }
+
+    /// Take a token, or return the seconds until one is available
+    pub fn acquire(&self) -> Result<(), u64> {
+        let mut bucket = self
+            .bucket
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (tokens, counted) = &mut *bucket;
+        let now = Instant::now();
+        *tokens = (*tokens + now.duration_since(*counted).as_secs_f64() * self.requests_per_sec)
+            .min(self.burst);
+        *counted = now;
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - *tokens) / self.requests_per_sec).ceil() as u64)
+        }
+    }
+}
+
+/// Refuse requests beyond the rate limit with `429`
+#[cfg(feature = "http-server")]
+pub async fn rate_limit_middleware(
+    State(limiter): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match limiter.acquire() {
+        Ok(()) => next.run(request).await,
+        Err(retry_after_secs) => McpCoreError::RateLimited {
+            message: "Too many requests to the gateway".to_string(),
+            retry_after_secs,
+        }
+        .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipeline(json: Value) -> Result<Pipeline, String> {
+        let entries: Vec<MiddlewareEntry> = serde_json::from_value(json).unwrap();
+        Pipeline::new(Some(&entries))
+    }
+
+    fn names<'a>(layers: impl Iterator<Item = &'a Layer>) -> Vec<&'static str> {
+        layers.map(Layer::name).collect()
+    }
+
+    #[test]
+    fn test_validation() {
+        let error = |json| pipeline(json).unwrap_err();
+        assert!(
+            error(serde_json::json!([{ "name": "auth" }, { "name": "cors" }]))
+                .contains("'cors' is unknown")
+        );
+        assert!(
+            error(serde_json::json!([{ "name": "auth" }, { "name": "access_log" }, { "name": "access_log", "enabled": false }]))
+                .contains("listed more than once")
+        );
+        assert!(error(serde_json::json!([{ "name": "access_log" }])).contains("must list 'auth'"));
+        assert!(
+            error(serde_json::json!([{ "name": "auth", "enabled": false }]))
+                .contains("cannot be disabled")
+        );
+        assert!(error(serde_json::json!([{ "name": "auth", "realm": "x" }]))
+            .contains("has no setting 'realm'"));
+        assert!(
+            error(serde_json::json!([{ "name": "auth" }, { "name": "rate_limit" }]))
+                .contains("requires requests_per_sec")
+        );
+        assert!(error(
+            serde_json::json!([{ "name": "auth" }, { "name": "rate_limit", "requests_per_sec": 0 }])
+        )
+        .contains("positive integer"));
+    }
+
+    #[test]
+    fn test_layers_split_at_auth() {
+        let default = Pipeline::default();
+        assert!(!default.is_configured());
+        assert_eq!(
+            names(default.outer()),
+            ["access_log", "version_headers", "response_headers"]
+        );
+        assert_eq!(names(default.authenticated()), ["auth"]);
+        assert!(default.rate_limiter().is_none());
+
+        let configured = pipeline(serde_json::json!([
+            { "name": "version_headers", "enabled": false },
+            { "name": "auth" },
+            { "name": "rate_limit", "requests_per_sec": 5 },
+            { "name": "access_log" }
+        ]))
+        .unwrap();
+        assert!(configured.is_configured());
+        assert!(names(configured.outer()).is_empty());
+        assert_eq!(
+            names(configured.authenticated()),
+            ["auth", "rate_limit", "access_log"]
+        );
+        let described = configured.describe(|layer| *layer != Layer::AccessLog);
+        assert_eq!(described[0].scope, Scope::All);
+        assert!(!described[0].active);
+        assert_eq!(described[2].scope, Scope::Authenticated);
+        assert_eq!(
+            described[2].layer,
+            Layer::RateLimit {
+                requests_per_sec: 5,
+                burst: 5
+            }
+        );
+        assert!(described[2].active);
+        assert!(!described[3].active);
+    }
+
+    #[test]
+    fn test_rate_limiter_refills() {
+        let limiter = RateLimiter::new(10, 2);
+        assert!(limiter.acquire().is_ok());
+        assert!(limiter.acquire().is_ok());
+        assert_eq!(limiter.acquire(), Err(1));
+        std::thread::sleep(std::time::Duration::from_millis(120));
+        assert!(limiter.acquire().is_ok());
+    }
+}
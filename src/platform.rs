@@ -0,0 +1,236 @@
+//! Per-platform server settings and the shell running build commands
+//!
+//! `command`, `args`, `build_command`, and `env` of a server may each be an
+//! object keyed by platform instead of a plain value, so one configuration
+//! file serves Windows and Unix hosts:
+//!
+//! ```json
+//! { "command": { "windows": "node.exe", "unix": "node" } }
+//! ```
+//!
+//! The value for the current platform is picked when the configuration is
+//! loaded; `linux` and `macos` take precedence over `unix`. A value that
+//! leaves the current platform out is a configuration error.
+//!
+//! With `shell` set to `none`, a build command runs without any shell: it is
+//! split into the program and its arguments by POSIX shell quoting rules:
+//! single and double quotes group words, and outside quotes a backslash
+//! escapes the next character, so Windows paths belong in quotes. Pipes,
+//! `&&`, and variables have no special meaning.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Platform keys a per-platform value may use
+pub const PLATFORMS: &[&str] = &["windows", "unix", "linux", "macos"];
+
+/// Server fields that may be given per platform
+pub const PLATFORM_FIELDS: &[&str] = &["command", "args", "build_command", "env"];
+
+/// Keys naming the platform the gateway runs on, most specific first
+pub fn current() -> &'static [&'static str] {
+    if cfg!(windows) {
+        &["windows"]
+    } else if cfg!(target_os = "linux") {
+        &["linux", "unix"]
+    } else if cfg!(target_os = "macos") {
+        &["macos", "unix"]
+    } else {
+        &["unix"]
+    }
+}
+
+/// Replace the per-platform values of the server config `server` with those
+/// for `platform`, as returned by [`current`]
+pub fn resolve_server(server: &mut Value, platform: &[&str]) -> Result<(), String> {
+    let Value::Object(server) = server else {
+        return Ok(());
+    };
+    for field in PLATFORM_FIELDS {
+        let Some(value) = server.get_mut(*field) else {
+            continue;
+        };
+        if !is_per_platform(field, value) {
+            continue;
+        }
+        *value = resolve(value, platform).map_err(|reason| format!("{} {}", field, reason))?;
+    }
+    Ok(())
+}
+
+/// Whether `value` of `field` is keyed by platform rather than plain
+///
+/// Only `env` is an object when plain; its values are then strings.
+fn is_per_platform(field: &str, value: &Value) -> bool {
+    match value {
+        Value::Object(object) if field == "env" => object.values().any(Value::is_object),
+        Value::Object(_) => true,
+        _ => false,
+    }
+}
+
+/// Value for the first of the `platform` keys present in `value`
+fn resolve(value: &Value, platform: &[&str]) -> Result<Value, String> {
+    let Value::Object(object) = value else {
+        return Ok(value.clone());
+    };
+    if let Some(unknown) = object.keys().find(|key| !PLATFORMS.contains(&key.as_str())) {
+        return Err(format!(
+            "has unknown platform '{}' (expected one of {})",
+            unknown,
+            PLATFORMS.join(", ")
+        ));
+    }
+    platform
+        .iter()
+        .find_map(|key| object.get(*key).cloned())
+        .ok_or_else(|| {
+            format!(
+                "has no value for this platform; add '{}'",
+                platform.join("' or '")
+            )
+        })
+}
+
+/// Shell running a server's `build_command` and hooks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+    /// `sh -c`, the default on Unix
+    Sh,
+
+    /// `cmd /C`, the default on Windows
+    Cmd,
+
+    /// `powershell -Command`, or `pwsh` outside Windows
+    Powershell,
+
+    /// No shell; the command is split by [`split`] and run directly
+    None,
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        if cfg!(windows) {
+            Shell::Cmd
+        } else {
+            Shell::Sh
+        }
+    }
+}
+
+impl Shell {
+    /// Program of the shell, or `None` for [`Shell::None`]
+    pub fn program(self) -> Option<&'static str> {
+        match self {
+            Shell::Sh => Some("sh"),
+            Shell::Cmd => Some("cmd"),
+            Shell::Powershell => Some(if cfg!(windows) { "powershell" } else { "pwsh" }),
+            Shell::None => None,
+        }
+    }
+
+    /// Command running `line` through this shell
+    pub fn command(self, line: &str) -> Result<tokio::process::Command, String> {
+        let shell = self.program().unwrap_or_default().to_string();
+        let (program, args) = match self {
+            Shell::Sh => (shell, vec!["-c".to_string(), line.to_string()]),
+            Shell::Cmd => (shell, vec!["/C".to_string(), line.to_string()]),
+            Shell::Powershell => (
+                shell,
+                vec![
+                    "-NoProfile".to_string(),
+                    "-NonInteractive".to_string(),
+                    "-Command".to_string(),
+                    line.to_string(),
+                ],
+            ),
+            Shell::None => {
+                let mut words = split(line)?.into_iter();
+                let program = words.next().ok_or("build command is empty")?;
+                (program, words.collect())
+            }
+        };
+        let mut command = tokio::process::Command::new(program);
+        command.args(args);
+        Ok(command)
+    }
+}
+
+/// Program and arguments of `line` by POSIX shell quoting rules
+pub fn split(line: &str) -> Result<Vec<String>, String> {
+    shell_words::split(line).map_err(|_| format!("'{}' has an unterminated quote", line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const LINUX: &[&str] = &["linux", "unix"];
+    const WINDOWS: &[&str] = &["windows"];
+
+    #[test]
+    fn test_per_platform_values_are_resolved() {
+        let server = json!({
+            "command": { "windows": "node.exe", "unix": "node" },
+            "args": { "windows": [".\\dist\\index.js"], "linux": ["./dist/index.js"] },
+            "build_command": "npm run build",
+            "env": {
+                "windows": { "HOME": "C:\\Users\\mcp" },
+                "unix": { "HOME": "/home/mcp" }
+            }
+        });
+
+        let mut linux = server.clone();
+        resolve_server(&mut linux, LINUX).unwrap();
+        assert_eq!(
+            linux,
+            json!({
+                "command": "node",
+                "args": ["./dist/index.js"],
+                "build_command": "npm run build",
+                "env": { "HOME": "/home/mcp" }
+            })
+        );
+
+        let mut windows = server;
+        resolve_server(&mut windows, WINDOWS).unwrap();
+        assert_eq!(windows["command"], "node.exe");
+        assert_eq!(windows["args"], json!([".\\dist\\index.js"]));
+        assert_eq!(windows["env"], json!({ "HOME": "C:\\Users\\mcp" }));
+
+        // A plain env may name a variable after a platform
+        let mut plain = json!({ "env": { "unix": "1" } });
+        resolve_server(&mut plain, WINDOWS).unwrap();
+        assert_eq!(plain["env"]["unix"], "1");
+    }
+
+    #[test]
+    fn test_current_platform_must_be_covered() {
+        let mut server = json!({ "command": { "windows": "node.exe" } });
+        let error = resolve_server(&mut server, LINUX).unwrap_err();
+        assert_eq!(
+            error,
+            "command has no value for this platform; add 'linux' or 'unix'"
+        );
+
+        let mut server = json!({ "build_command": { "win": "build.cmd", "unix": "make" } });
+        let error = resolve_server(&mut server, LINUX).unwrap_err();
+        assert!(error.contains("unknown platform 'win'"), "{}", error);
+    }
+
+    #[test]
+    fn test_split_without_shell() {
+        assert_eq!(
+            split(r#"node "C:\Program Files\tool.js" --name 'a b' c\ d"#).unwrap(),
+            ["node", r"C:\Program Files\tool.js", "--name", "a b", "c d"]
+        );
+        assert_eq!(
+            split(r#"echo "it's" 'say "hi"'"#).unwrap(),
+            ["echo", "it's", r#"say "hi""#]
+        );
+        assert!(split("echo 'unterminated").is_err());
+        assert!(Shell::None.command("  ").is_err());
+    }
+}
@@ -0,0 +1,253 @@
+//! Named request presets
+//!
+//! A server's `presets` name JSON-RPC requests operators send often, e.g.
+//! `{"list-open-issues": {"method": "tools/call", "params": {"name":
+//! "search_issues", "arguments": {"status": "open"}}}}`.
+//! `POST /api/v1/presets/{name}` sends one with a fresh id; an optional JSON
+//! object in the body is merged over the preset's params, key by key, with
+//! the body winning and `null` removing a key. The request then goes through
+//! the same pipeline as one sent to `/api/v1`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::config;
+use crate::error::{McpCoreError, McpCoreResult};
+
+/// A named request
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Preset {
+    pub method: String,
+
+    /// Params object, the base the request body is merged over
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<Value>,
+}
+
+/// Check preset names, methods, and params
+pub fn validate(presets: &HashMap<String, Preset>) -> Result<(), String> {
+    for (name, preset) in presets {
+        let valid_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid_name {
+            return Err(format!(
+                "preset name '{}' may only contain letters, digits, '-', '_', and '.'",
+                name
+            ));
+        }
+        if preset.method.is_empty() || preset.method.starts_with("notifications/") {
+            return Err(format!(
+                "preset '{}' needs a request method, not '{}'",
+                name, preset.method
+            ));
+        }
+        if preset
+            .params
+            .as_ref()
+            .is_some_and(|params| !params.is_object())
+        {
+            return Err(format!("preset '{}' params must be an object", name));
+        }
+    }
+    Ok(())
+}
+
+/// A preset as listed by `GET /api/v1/presets`
+#[derive(Debug, Clone, Serialize)]
+pub struct PresetInfo {
+    pub name: String,
+    pub method: String,
+    pub params: Value,
+
+    /// JSON pointers of the values in params a request body can replace
+    pub slots: Vec<String>,
+}
+
+/// The presets of a server
+#[derive(Debug, Default)]
+pub struct Presets {
+    presets: BTreeMap<String, Preset>,
+    next_id: AtomicU64,
+}
+
+impl Presets {
+    pub fn new(presets: &HashMap<String, Preset>) -> Self {
+        Self {
+            presets: presets
+                .iter()
+                .map(|(name, preset)| (name.clone(), preset.clone()))
+                .collect(),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Every preset, by name
+    pub fn list(&self) -> Vec<PresetInfo> {
+        self.presets
+            .iter()
+            .map(|(name, preset)| {
+                let params = params_of(preset);
+                let mut slots = Vec::new();
+                collect_slots(&params, String::new(), &mut slots);
+                PresetInfo {
+                    name: name.clone(),
+                    method: preset.method.clone(),
+                    params,
+                    slots,
+                }
+            })
+            .collect()
+    }
+
+    /// The JSON-RPC request of preset `name`, with `body` merged over its params
+    ///
+    /// An empty body sends the preset as it is; anything else must be a JSON
+    /// object.
+    pub fn command(&self, name: &str, body: &[u8]) -> McpCoreResult<String> {
+        let preset = self
+            .presets
+            .get(name)
+            .ok_or_else(|| McpCoreError::NotFound {
+                message: format!("No preset '{}'", name),
+            })?;
+        let mut params = params_of(preset);
+        if !body.iter().all(u8::is_ascii_whitespace) {
+            let overlay: Value =
+                serde_json::from_slice(body).map_err(|e| McpCoreError::RequestError {
+                    message: format!("Preset body must be a JSON object: {}", e),
+                })?;
+            if !overlay.is_object() {
+                return Err(McpCoreError::RequestError {
+                    message: "Preset body must be a JSON object".to_string(),
+                });
+            }
+            config::merge_values(&mut params, overlay);
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        Ok(serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": format!("preset-{}", id),
+            "method": preset.method,
+            "params": params,
+        })
+        .to_string())
+    }
+}
+
+fn params_of(preset: &Preset) -> Value {
+    preset
+        .params
+        .clone()
+        .unwrap_or_else(|| Value::Object(Map::new()))
+}
+
+/// Pointers of the non-object values under `value`
+fn collect_slots(value: &Value, pointer: String, slots: &mut Vec<String>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let key = key.replace('~', "~0").replace('/', "~1");
+                collect_slots(value, format!("{}/{}", pointer, key), slots);
+            }
+        }
+        _ => slots.push(pointer),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn presets() -> HashMap<String, Preset> {
+        serde_json::from_value(serde_json::json!({
+            "list-open-issues": {
+                "method": "tools/call",
+                "params": {
+                    "name": "search_issues",
+                    "arguments": { "status": "open", "limit": 20, "labels": ["bug"] }
+                }
+            },
+            "list-tools": { "method": "tools/list" }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(validate(&presets()).is_ok());
+        let invalid = |json: Value| validate(&serde_json::from_value(json).unwrap()).unwrap_err();
+        assert!(invalid(serde_json::json!({ "a b": { "method": "ping" } })).contains("may only"));
+        assert!(invalid(serde_json::json!({ "x": { "method": "" } })).contains("request method"));
+        assert!(
+            invalid(serde_json::json!({ "x": { "method": "notifications/initialized" } }))
+                .contains("request method")
+        );
+        assert!(
+            invalid(serde_json::json!({ "x": { "method": "ping", "params": [1] } }))
+                .contains("must be an object")
+        );
+    }
+
+    #[test]
+    fn test_body_merged_over_params() {
+        let presets = Presets::new(&presets());
+        let command = |body: &str| -> Value {
+            serde_json::from_str(
+                &presets
+                    .command("list-open-issues", body.as_bytes())
+                    .unwrap(),
+            )
+            .unwrap()
+        };
+
+        let sent = command("");
+        assert_eq!(sent["method"], "tools/call");
+        assert_eq!(sent["params"]["arguments"]["status"], "open");
+        assert_eq!(sent["id"], "preset-1");
+
+        // Objects merge key by key, other values replace, null removes
+        let sent = command(
+            r#"{"arguments": {"status": "closed", "labels": ["ui"], "limit": null, "page": 2}}"#,
+        );
+        assert_eq!(sent["id"], "preset-2");
+        assert_eq!(sent["params"]["name"], "search_issues");
+        assert_eq!(
+            sent["params"]["arguments"],
+            serde_json::json!({ "status": "closed", "labels": ["ui"], "page": 2 })
+        );
+
+        // The preset itself is unchanged
+        assert_eq!(command(" \n")["params"]["arguments"]["limit"], 20);
+
+        let error = presets.command("list-open-issues", b"[1]").unwrap_err();
+        assert!(matches!(error, McpCoreError::RequestError { .. }));
+        let error = presets.command("list-open-issues", b"{").unwrap_err();
+        assert!(matches!(error, McpCoreError::RequestError { .. }));
+        let error = presets.command("nope", b"").unwrap_err();
+        assert!(matches!(error, McpCoreError::NotFound { .. }));
+    }
+
+    #[test]
+    fn test_listing_names_slots() {
+        let listed = Presets::new(&presets()).list();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].name, "list-open-issues");
+        assert_eq!(
+            listed[0].slots,
+            [
+                "/name",
+                "/arguments/status",
+                "/arguments/limit",
+                "/arguments/labels"
+            ]
+        );
+        assert_eq!(listed[1].name, "list-tools");
+        assert_eq!(listed[1].params, serde_json::json!({}));
+        assert!(listed[1].slots.is_empty());
+    }
+}
@@ -0,0 +1,449 @@
+//! Priority queue in front of the MCP server
+//!
+//! Requests take turns using the server's transport. While one is being
+//! handled the others wait in one of three classes, chosen with the
+//! `X-MCP-Priority` header or the API key's default. The next turn goes to
+//! the highest class with a waiting request, oldest first. A request that has
+//! waited `promote_after_ms` is treated as one class higher, and again for
+//! each further period, so low-priority traffic is delayed but never starved.
+
+use crate::error::{McpCoreError, McpCoreResult};
+use http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+/// Header selecting a request's priority class
+pub const PRIORITY_HEADER: &str = "x-mcp-priority";
+
+/// Default wait after which a queued request is promoted one class
+pub const DEFAULT_PROMOTE_AFTER_MS: u64 = 5000;
+
+/// `Retry-After` of requests rejected because their class is full
+const QUEUE_FULL_RETRY_AFTER_SECS: u64 = 1;
+
+fn shutting_down() -> McpCoreError {
+    McpCoreError::ShuttingDown {
+        message: "Server is shutting down".to_string(),
+    }
+}
+
+/// Priority class of a request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestPriority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl RequestPriority {
+    /// All classes, highest first
+    pub const ALL: [RequestPriority; 3] = [Self::High, Self::Normal, Self::Low];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::High => "high",
+            Self::Normal => "normal",
+            Self::Low => "low",
+        }
+    }
+
+    /// Parse a class name, ignoring case
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|priority| value.trim().eq_ignore_ascii_case(priority.as_str()))
+    }
+
+    /// Priority requested with the `X-MCP-Priority` header, if any
+    pub fn from_headers(headers: &HeaderMap) -> McpCoreResult<Option<Self>> {
+        let Some(value) = headers.get(PRIORITY_HEADER) else {
+            return Ok(None);
+        };
+        value
+            .to_str()
+            .ok()
+            .and_then(Self::parse)
+            .map(Some)
+            .ok_or_else(|| McpCoreError::RequestError {
+                message: "X-MCP-Priority must be high, normal, or low".to_string(),
+            })
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Limits of a server's request queue
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RequestQueueConfig {
+    /// Requests that may wait in the high and normal classes each (unbounded by default)
+    #[serde(default)]
+    pub max_queued: Option<usize>,
+
+    /// Requests that may wait in the low class (a quarter of `max_queued` by default)
+    #[serde(default)]
+    pub max_queued_low: Option<usize>,
+
+    /// Milliseconds of waiting after which a request is promoted one class
+    #[serde(default = "default_promote_after_ms")]
+    pub promote_after_ms: u64,
+}
+
+fn default_promote_after_ms() -> u64 {
+    DEFAULT_PROMOTE_AFTER_MS
+}
+
+impl Default for RequestQueueConfig {
+    fn default() -> Self {
+        Self {
+            max_queued: None,
+            max_queued_low: None,
+            promote_after_ms: DEFAULT_PROMOTE_AFTER_MS,
+        }
+    }
+}
+
+impl RequestQueueConfig {
+    /// Check that the limits are usable
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_queued == Some(0) || self.max_queued_low == Some(0) {
+            return Err("queue limits must be positive".to_string());
+        }
+        if self.promote_after_ms == 0 {
+            return Err("promote_after_ms must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    /// Requests that may wait in `priority`'s class
+    fn limit(&self, priority: RequestPriority) -> Option<usize> {
+        match priority {
+            RequestPriority::High | RequestPriority::Normal => self.max_queued,
+            RequestPriority::Low => self
+                .max_queued_low
+                .or(self.max_queued.map(|max| (max / 4).max(1))),
+        }
+    }
+}
+
+/// Queue metrics of one priority class
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct QueueClassSnapshot {
+    /// Requests waiting now
+    pub queued: usize,
+
+    /// Age in milliseconds of the oldest waiting request
+    pub oldest_wait_ms: Option<u64>,
+
+    /// Turns granted since startup
+    pub dequeued: u64,
+
+    /// Requests rejected because the class was full
+    pub rejected: u64,
+
+    /// Turns granted ahead of their class after a long wait
+    pub promoted: u64,
+}
+
+/// Queue metrics by priority class, served by the stats endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct RequestQueueSnapshot {
+    pub high: QueueClassSnapshot,
+    pub normal: QueueClassSnapshot,
+    pub low: QueueClassSnapshot,
+}
+
+struct Waiter {
+    id: u64,
+    enqueued: Instant,
+    grant: oneshot::Sender<QueueTurn>,
+}
+
+#[derive(Default)]
+struct QueueState {
+    /// Whether a turn is currently held
+    busy: bool,
+
+    /// Whether the queue refuses requests because the server is shutting down
+    closed: bool,
+    next_id: u64,
+    waiting: [VecDeque<Waiter>; 3],
+    counters: [QueueClassSnapshot; 3],
+}
+
+/// Grants requests their turn with the MCP server by priority
+pub struct RequestQueue {
+    config: RequestQueueConfig,
+    state: Mutex<QueueState>,
+}
+
+/// Exclusive turn with the MCP server; the next request's turn starts on drop
+pub struct QueueTurn {
+    queue: Arc<RequestQueue>,
+}
+
+impl Drop for QueueTurn {
+    fn drop(&mut self) {
+        self.queue.release();
+    }
+}
+
+/// Removes a waiter whose request was cancelled before its turn
+struct Waiting<'a> {
+    queue: &'a RequestQueue,
+    priority: RequestPriority,
+    id: u64,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.queue.lock().waiting[self.priority.index()].retain(|waiter| waiter.id != self.id);
+    }
+}
+
+impl Default for RequestQueue {
+    fn default() -> Self {
+        Self::new(RequestQueueConfig::default())
+    }
+}
+
+impl RequestQueue {
+    pub fn new(config: RequestQueueConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(QueueState::default()),
+        }
+    }
+
+    /// Wait for a turn in `priority`'s class, or fail with
+    /// [`McpCoreError::Overloaded`] if the class is full
+    pub async fn acquire(self: &Arc<Self>, priority: RequestPriority) -> McpCoreResult<QueueTurn> {
+        let class = priority.index();
+        let (id, granted) = {
+            let mut state = self.lock();
+            if state.closed {
+                return Err(shutting_down());
+            }
+            if !state.busy && state.waiting.iter().all(VecDeque::is_empty) {
+                state.busy = true;
+                state.counters[class].dequeued += 1;
+                return Ok(QueueTurn {
+                    queue: Arc::clone(self),
+                });
+            }
+            if let Some(limit) = self.config.limit(priority) {
+                if state.waiting[class].len() >= limit {
+                    state.counters[class].rejected += 1;
+                    return Err(McpCoreError::Overloaded {
+                        message: format!(
+                            "Request queue is full for {} priority ({} waiting)",
+                            priority.as_str(),
+                            limit
+                        ),
+                        retry_after_secs: QUEUE_FULL_RETRY_AFTER_SECS,
+                    });
+                }
+            }
+
+            state.next_id += 1;
+            let id = state.next_id;
+            let (grant, granted) = oneshot::channel();
+            state.waiting[class].push_back(Waiter {
+                id,
+                enqueued: Instant::now(),
+                grant,
+            });
+            (id, granted)
+        };
+
+        let _waiting = Waiting {
+            queue: self,
+            priority,
+            id,
+        };
+        granted.await.map_err(|_| shutting_down())
+    }
+
+    /// Fail every waiting request and refuse new ones, returning how many
+    /// were waiting
+    ///
+    /// A turn already held is kept until its request finishes.
+    pub fn close(&self) -> usize {
+        let waiting: Vec<Waiter> = {
+            let mut state = self.lock();
+            state.closed = true;
+            state
+                .waiting
+                .iter_mut()
+                .flat_map(|class| class.drain(..))
+                .collect()
+        };
+        // Dropping the grants fails the waiting requests
+        waiting.len()
+    }
+
+    /// Metrics of each priority class
+    pub fn snapshot(&self) -> RequestQueueSnapshot {
+        let state = self.lock();
+        let class = |priority: RequestPriority| {
+            let waiting = &state.waiting[priority.index()];
+            QueueClassSnapshot {
+                queued: waiting.len(),
+                oldest_wait_ms: waiting
+                    .front()
+                    .map(|waiter| waiter.enqueued.elapsed().as_millis() as u64),
+                ..state.counters[priority.index()].clone()
+            }
+        };
+        RequestQueueSnapshot {
+            high: class(RequestPriority::High),
+            normal: class(RequestPriority::Normal),
+            low: class(RequestPriority::Low),
+        }
+    }
+
+    /// Hand the turn to the next waiter, or mark the queue idle
+    fn release(self: &Arc<Self>) {
+        let waiter = {
+            let mut state = self.lock();
+            match self.next_waiter(&mut state) {
+                Some(waiter) => waiter,
+                None => {
+                    state.busy = false;
+                    return;
+                }
+            }
+        };
+        // A waiter cancelled meanwhile drops the turn, which passes it on again
+        let _ = waiter.grant.send(QueueTurn {
+            queue: Arc::clone(self),
+        });
+    }
+
+    /// Remove the waiter with the highest effective class, oldest first
+    fn next_waiter(&self, state: &mut QueueState) -> Option<Waiter> {
+        let promote_after = Duration::from_millis(self.config.promote_after_ms);
+        let now = Instant::now();
+        let (class, effective, _) = RequestPriority::ALL
+            .into_iter()
+            .filter_map(|priority| {
+                let waiter = state.waiting[priority.index()].front()?;
+                let waited = now.duration_since(waiter.enqueued);
+                let promotions = (waited.as_millis() / promote_after.as_millis()) as usize;
+                let effective = priority.index().saturating_sub(promotions);
+                Some((priority.index(), effective, waiter.enqueued))
+            })
+            .min_by_key(|&(_, effective, enqueued)| (effective, enqueued))?;
+
+        let counters = &mut state.counters[class];
+        counters.dequeued += 1;
+        if effective < class {
+            counters.promoted += 1;
+        }
+        state.waiting[class].pop_front()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, QueueState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(config: RequestQueueConfig) -> Arc<RequestQueue> {
+        Arc::new(RequestQueue::new(config))
+    }
+
+    /// Spawn a waiter that reports `label` when its turn starts
+    async fn enqueue(
+        queue: &Arc<RequestQueue>,
+        priority: RequestPriority,
+        label: &'static str,
+        order: &tokio::sync::mpsc::UnboundedSender<&'static str>,
+    ) {
+        let queue = Arc::clone(queue);
+        let order = order.clone();
+        tokio::spawn(async move {
+            let _turn = queue.acquire(priority).await.unwrap();
+            order.send(label).unwrap();
+        });
+        // Let the waiter reach the queue before the next one
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    #[tokio::test]
+    async fn test_long_waiting_low_priority_is_promoted() {
+        let queue = queue(RequestQueueConfig {
+            promote_after_ms: 50,
+            ..RequestQueueConfig::default()
+        });
+        let (order_tx, mut order) = tokio::sync::mpsc::unbounded_channel();
+
+        let turn = queue.acquire(RequestPriority::Normal).await.unwrap();
+        enqueue(&queue, RequestPriority::Low, "low", &order_tx).await;
+        // Two promotion periods lift the low request level with high ones
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        enqueue(&queue, RequestPriority::High, "high", &order_tx).await;
+        enqueue(&queue, RequestPriority::Normal, "normal", &order_tx).await;
+        drop(turn);
+
+        let mut granted = Vec::new();
+        for _ in 0..3 {
+            granted.push(order.recv().await.unwrap());
+        }
+        assert_eq!(granted, ["low", "high", "normal"]);
+        let snapshot = queue.snapshot();
+        assert_eq!(snapshot.low.promoted, 1);
+        assert_eq!(snapshot.high.promoted, 0);
+        assert_eq!(snapshot.normal.dequeued, 2);
+    }
+
+    #[tokio::test]
+    async fn test_low_priority_has_smaller_quota_and_cancelled_waiters_leave() {
+        let queue = queue(RequestQueueConfig {
+            max_queued: Some(4),
+            ..RequestQueueConfig::default()
+        });
+        let turn = queue.acquire(RequestPriority::High).await.unwrap();
+
+        let waiting = tokio::spawn({
+            let queue = Arc::clone(&queue);
+            async move { queue.acquire(RequestPriority::Low).await.map(drop) }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let error = queue.acquire(RequestPriority::Low).await.err().unwrap();
+        assert_eq!(error.error_code(), Some("overloaded"));
+        assert_eq!(queue.snapshot().low.rejected, 1);
+
+        waiting.abort();
+        let _ = waiting.await;
+        assert_eq!(queue.snapshot().low.queued, 0);
+
+        // The cancelled waiter does not hold up the queue
+        drop(turn);
+        assert!(queue.acquire(RequestPriority::Low).await.is_ok());
+    }
+
+    #[test]
+    fn test_priority_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(RequestPriority::from_headers(&headers).unwrap(), None);
+        headers.insert(PRIORITY_HEADER, "HIGH".parse().unwrap());
+        assert_eq!(
+            RequestPriority::from_headers(&headers).unwrap(),
+            Some(RequestPriority::High)
+        );
+        headers.insert(PRIORITY_HEADER, "urgent".parse().unwrap());
+        assert!(RequestPriority::from_headers(&headers).is_err());
+    }
+}
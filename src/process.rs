@@ -1,18 +1,47 @@
 // This is the MCP server process wrapper
+use crate::egress::EgressProxy;
 use crate::error::{McpCoreError, McpCoreResult};
+use crate::history::ChildExit;
+use crate::response_stream::{self, Head, Received, ResponseSink};
+use crate::stderr::{self, StderrPolicy, StderrTail};
+use crate::transport::{self, McpTransport, NotificationBuffer};
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
-use serde_json;
-use std::time::Instant;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
-    process::{ChildStdin, ChildStdout, Command},
-    time::{timeout, Duration},
+    process::{Child, ChildStdin, ChildStdout, Command},
+    task::JoinHandle,
 };
 
-/// MCP server process wrapper
+/// MCP server process wrapper speaking JSON-RPC over stdio
 pub struct McpProcess {
+    child: Child,
     stdin: ChildStdin,
     stdout: BufReader<ChildStdout>,
+    noise_policy: NoisePolicy,
+    notifications: NotificationBuffer,
+    stderr_tail: StderrTail,
+    line_tolerance: LineTolerance,
+
+    /// Longest a write to stdin may block
+    write_timeout: Duration,
+
+    /// Proxy the child's traffic goes through, kept running while it lives
+    egress: Option<Arc<EgressProxy>>,
+
+    /// When the child was spawned, and how soon after that exiting without
+    /// a message counts as exiting early
+    spawned_at: Instant,
+    early_exit_window: Duration,
+
+    /// Whether the child has written a JSON message
+    answered: bool,
+
+    /// First stdout lines skipped as noise, quoted if the child exits early
+    stdout_head: Vec<String>,
+    stderr_reader: Option<JoinHandle<()>>,
 }
 
 /// MCP request structure
@@ -27,10 +56,195 @@ pub struct McpResponse {
     pub result: String,
 }
 
+/// Default time a write to the MCP server's stdin may block
+pub const DEFAULT_STDIN_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default time after spawning within which a child exiting without a
+/// message is reported as [`McpCoreError::ChildExitedEarly`]
+pub const DEFAULT_EARLY_EXIT_WINDOW: Duration = Duration::from_secs(3);
+
+/// Lines of output quoted when a child exits early
+pub const EARLY_EXIT_OUTPUT_LINES: usize = 20;
+
+/// Longest wait for an early exit's status and the rest of its stderr
+const EARLY_EXIT_WAIT: Duration = Duration::from_millis(500);
+
+/// Default maximum size of a command written to the MCP server
+pub const DEFAULT_MAX_COMMAND_BYTES: usize = 1024 * 1024;
+
+/// Limits applied to commands before they are written to the MCP server
+#[derive(Debug, Clone)]
+pub struct CommandPolicy {
+    /// Maximum command size in bytes
+    pub max_bytes: usize,
+
+    /// Accept JSON values without a `jsonrpc: "2.0"` field
+    pub allow_non_jsonrpc: bool,
+}
+
+impl Default for CommandPolicy {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_MAX_COMMAND_BYTES,
+            allow_non_jsonrpc: false,
+        }
+    }
+}
+
+impl CommandPolicy {
+    /// Validate a command and re-serialize it as a single compact line
+    ///
+    /// The command must be one JSON value no larger than `max_bytes`. Raw
+    /// newlines would make the MCP server see several messages, so the
+    /// re-serialized form is what gets written to stdin.
+    pub fn sanitize(&self, command: &str) -> McpCoreResult<String> {
+        self.validate(command).map(|message| message.to_string())
+    }
+
+    /// Validate a command and return the parsed message
+    pub fn validate(&self, command: &str) -> McpCoreResult<serde_json::Value> {
+        if command.len() > self.max_bytes {
+            return Err(McpCoreError::InvalidCommand {
+                code: "command_too_large",
+                message: format!(
+                    "Command is {} bytes, exceeding the limit of {} bytes",
+                    command.len(),
+                    self.max_bytes
+                ),
+            });
+        }
+
+        let message: serde_json::Value =
+            serde_json::from_str(command).map_err(|e| McpCoreError::InvalidCommand {
+                code: "invalid_json",
+                message: format!("Command must be a single JSON value: {}", e),
+            })?;
+
+        if !self.allow_non_jsonrpc && !is_jsonrpc(&message) {
+            return Err(McpCoreError::InvalidCommand {
+                code: "not_jsonrpc",
+                message: "Command must be a JSON-RPC 2.0 message with \"jsonrpc\": \"2.0\""
+                    .to_string(),
+            });
+        }
+
+        Ok(message)
+    }
+}
+
+/// Handling of stdout lines that are not JSON messages
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StdoutNoise {
+    /// Skip banners and warnings, within the limits of [`NoisePolicy`]
+    #[default]
+    Skip,
+    /// Fail on the first line that is not a JSON message
+    Error,
+}
+
+/// Default maximum number of noise lines skipped before one message
+pub const DEFAULT_MAX_NOISE_LINES: usize = 100;
+
+/// Default maximum number of noise bytes skipped before one message
+pub const DEFAULT_MAX_NOISE_BYTES: usize = 64 * 1024;
+
+/// Tolerance for noise servers print to stdout besides JSON-RPC messages
+#[derive(Debug, Clone, Copy)]
+pub struct NoisePolicy {
+    pub mode: StdoutNoise,
+
+    /// Maximum lines skipped while waiting for a single message
+    pub max_lines: usize,
+
+    /// Maximum bytes skipped while waiting for a single message
+    pub max_bytes: usize,
+}
+
+impl Default for NoisePolicy {
+    fn default() -> Self {
+        Self {
+            mode: StdoutNoise::Skip,
+            max_lines: DEFAULT_MAX_NOISE_LINES,
+            max_bytes: DEFAULT_MAX_NOISE_BYTES,
+        }
+    }
+}
+
+/// Whether a stdout line is a JSON message rather than noise
+fn is_json_message(line: &str) -> bool {
+    matches!(
+        serde_json::from_str::<serde_json::Value>(line),
+        Ok(serde_json::Value::Object(_) | serde_json::Value::Array(_))
+    )
+}
+
+/// Whether a value is a JSON-RPC 2.0 message or a non-empty batch of them
+fn is_jsonrpc(message: &serde_json::Value) -> bool {
+    let is_message = |value: &serde_json::Value| {
+        value.get("jsonrpc").and_then(serde_json::Value::as_str) == Some("2.0")
+    };
+    match message {
+        serde_json::Value::Array(batch) => !batch.is_empty() && batch.iter().all(is_message),
+        other => is_message(other),
+    }
+}
+
+/// Noise skipped while waiting for one message
+#[derive(Debug, Default)]
+struct Skipped {
+    lines: usize,
+    bytes: usize,
+}
+
+/// Line quirks tolerated in a server's output, each logged once
+///
+/// Servers on Windows end lines with CRLF, and some write blank lines
+/// between messages; neither should reach clients or fail a request.
+#[derive(Debug, Default)]
+pub(crate) struct LineTolerance {
+    crlf_seen: bool,
+    blank_seen: bool,
+}
+
+impl LineTolerance {
+    /// `line` without its line ending and surrounding whitespace, or `None`
+    /// for a blank line to skip
+    pub(crate) fn clean(&mut self, line: &str) -> Option<String> {
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        if line.ends_with('\r') && !self.crlf_seen {
+            self.crlf_seen = true;
+            tracing::debug!("MCP server ends lines with CRLF; removing the carriage returns");
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            if !self.blank_seen {
+                self.blank_seen = true;
+                tracing::debug!("Skipping blank lines between MCP server messages");
+            }
+            return None;
+        }
+        Some(line.to_string())
+    }
+}
+
 impl McpProcess {
     /// Spawn a new MCP process from a command builder
-    pub async fn spawn(mut command_builder: Command) -> McpCoreResult<Self> {
-        tracing::debug!("Spawning MCP process...");
+    pub async fn spawn(command_builder: Command) -> McpCoreResult<Self> {
+        Self::spawn_for_server(command_builder, "mcp", StderrPolicy::default()).await
+    }
+
+    /// Spawn the process of `server_name`, logging its stderr according to
+    /// `stderr_policy`
+    ///
+    /// Stderr lines are logged in an `mcp_server` span carrying the server
+    /// name and the child's PID.
+    pub async fn spawn_for_server(
+        mut command_builder: Command,
+        server_name: &str,
+        stderr_policy: StderrPolicy,
+    ) -> McpCoreResult<Self> {
+        tracing::debug!("Spawning MCP process for '{}'...", server_name);
 
         let mut child = command_builder
             .spawn()
@@ -59,228 +273,437 @@ impl McpProcess {
                 message: "Failed to open stderr for MCP process".to_string(),
             })?;
 
-        // Spawn stderr monitoring task
-        tokio::spawn(async move {
-            let mut reader = BufReader::new(stderr);
-            let mut line = String::new();
-            loop {
-                match reader.read_line(&mut line).await {
-                    Ok(0) => {
-                        tracing::debug!("MCP server stderr: EOF, task finishing");
-                        break;
-                    }
-                    Ok(_) => {
-                        tracing::debug!("MCP server stderr: {}", line.trim());
-                        line.clear();
-                    }
-                    Err(e) => {
-                        tracing::error!("MCP server stderr read error: {}", e);
-                        break;
-                    }
-                }
-            }
-        });
+        let pid = child.id();
+        let span = tracing::info_span!("mcp_server", server = %server_name, pid);
+        let stderr_tail = StderrTail::default();
+        let stderr_reader = stderr::spawn_reader(stderr, stderr_policy, stderr_tail.clone(), span);
 
-        tracing::debug!("MCP process spawned successfully");
+        tracing::debug!("MCP process spawned successfully with PID {:?}", pid);
 
         Ok(Self {
+            child,
             stdin,
             stdout: BufReader::new(stdout),
+            noise_policy: NoisePolicy::default(),
+            notifications: NotificationBuffer::default(),
+            stderr_tail,
+            line_tolerance: LineTolerance::default(),
+            write_timeout: DEFAULT_STDIN_WRITE_TIMEOUT,
+            egress: None,
+            spawned_at: Instant::now(),
+            early_exit_window: DEFAULT_EARLY_EXIT_WINDOW,
+            answered: false,
+            stdout_head: Vec::new(),
+            stderr_reader: Some(stderr_reader),
         })
     }
 
-    /// Initialize MCP connection with handshake according to official specification
-    pub async fn initialize(&mut self) -> McpCoreResult<()> {
-        tracing::info!("Initializing MCP connection...");
-        
-        // Send initialize request with proper capabilities structure per MCP specification
-        let init_request = serde_json::json!({
-            "jsonrpc": "2.0",
-            "id": "init",
-            "method": "initialize",
-            "params": {
-                "protocolVersion": "2024-11-05",
-                "capabilities": {
-                    // Client capabilities - properly structured
-                    "roots": {
-                        "listChanged": false
-                    },
-                    "sampling": {}
-                },
-                "clientInfo": {
-                    "name": "mcp-http-core",
-                    "title": "MCP HTTP Core",
-                    "version": "0.1.0"
-                }
+    /// Set how non-JSON lines on stdout are handled
+    pub fn with_noise_policy(mut self, noise_policy: NoisePolicy) -> Self {
+        self.noise_policy = noise_policy;
+        self
+    }
+
+    /// Fail writes to stdin that block for longer than `write_timeout`
+    pub fn with_write_timeout(mut self, write_timeout: Duration) -> Self {
+        self.write_timeout = write_timeout;
+        self
+    }
+
+    /// Report a child exiting within `window` of being spawned, before its
+    /// first message, as [`McpCoreError::ChildExitedEarly`]
+    ///
+    /// A zero window turns the check off.
+    pub fn with_early_exit_window(mut self, window: Duration) -> Self {
+        self.early_exit_window = window;
+        self
+    }
+
+    /// Keep `egress` running for as long as the process
+    pub fn with_egress_proxy(mut self, egress: Option<Arc<EgressProxy>>) -> Self {
+        self.egress = egress;
+        self
+    }
+
+    /// Read the next JSON message, skipping noise according to the policy
+    async fn read_message(&mut self) -> McpCoreResult<String> {
+        let mut skipped = Skipped::default();
+        loop {
+            let line = self.read_line().await?;
+            if let Some(message) = self.accept_line(line, &mut skipped)? {
+                return Ok(message);
             }
-        });
-        
-        let init_message = init_request.to_string();
-        tracing::debug!("Sending initialize request: {}", init_message);
-        
-        // Send initialize
-        self.stdin
-            .write_all((init_message + "\n").as_bytes())
-            .await
-            .map_err(|e| McpCoreError::ProcessError {
-                message: format!("Failed to write initialize request: {}", e),
-            })?;
-            
-        self.stdin
-            .flush()
-            .await
-            .map_err(|e| McpCoreError::ProcessError {
-                message: format!("Failed to flush initialize request: {}", e),
-            })?;
-            
-        // Wait for initialize response
-        let init_response = self.read_response_with_timeout(Duration::from_secs(30)).await?;
-        tracing::debug!("Initialize response: {}", init_response);
-        
-        // Parse and validate the response
-        match serde_json::from_str::<serde_json::Value>(&init_response) {
-            Ok(response) => {
-                if let Some(error) = response.get("error") {
-                    return Err(McpCoreError::ProcessError {
-                        message: format!("MCP initialization error: {}", error),
-                    });
-                }
-                
-                if let Some(result) = response.get("result") {
-                    if let Some(protocol_version) = result.get("protocolVersion") {
-                        tracing::info!("Server protocol version: {}", protocol_version);
-                    }
-                    if let Some(capabilities) = result.get("capabilities") {
-                        tracing::info!("Server capabilities: {}", capabilities);
-                    }
-                    if let Some(server_info) = result.get("serverInfo") {
-                        tracing::info!("Server info: {}", server_info);
-                    }
-                } else {
-                    tracing::warn!("Initialize response missing 'result' field");
-                }
+        }
+    }
+
+    /// `line` if it is a JSON message; noise is counted in `skipped` and
+    /// fails the read once the policy says so
+    fn accept_line(
+        &mut self,
+        line: String,
+        skipped: &mut Skipped,
+    ) -> McpCoreResult<Option<String>> {
+        if is_json_message(&line) {
+            self.answered = true;
+            return Ok(Some(line));
+        }
+        if !self.answered && self.stdout_head.len() < EARLY_EXIT_OUTPUT_LINES {
+            self.stdout_head.push(truncate_for_log(&line));
+        }
+
+        if self.noise_policy.mode == StdoutNoise::Error {
+            return Err(McpCoreError::ProcessError {
+                message: format!(
+                    "MCP server wrote non-JSON output to stdout: '{}'",
+                    truncate_for_log(&line)
+                ),
+            });
+        }
+
+        skipped.lines += 1;
+        skipped.bytes += line.len();
+        if skipped.lines > self.noise_policy.max_lines
+            || skipped.bytes > self.noise_policy.max_bytes
+        {
+            return Err(McpCoreError::ProcessError {
+                message: format!(
+                    "MCP server wrote {} lines ({} bytes) of non-JSON output without a message",
+                    skipped.lines, skipped.bytes
+                ),
+            });
+        }
+        tracing::debug!("Child stdout noise: {}", line);
+        Ok(None)
+    }
+
+    /// Read stdout up to the end of the next line, or until its opening
+    /// shows it is the response to `pending_id`
+    ///
+    /// Returns the bytes read and whether the line should be streamed; an
+    /// empty read means EOF. A line growing past `max_bytes` is read to its
+    /// end and fails.
+    async fn read_head(
+        &mut self,
+        pending_id: &serde_json::Value,
+        max_bytes: Option<usize>,
+        timeout_duration: Duration,
+    ) -> McpCoreResult<(Vec<u8>, Head)> {
+        let mut line = Vec::new();
+        let mut head = Head::Incomplete;
+        loop {
+            let buffer = self.fill(timeout_duration).await?;
+            if buffer.is_empty() {
+                return Ok((line, Head::Buffer));
+            }
+            let (length, ended) = line_end(buffer);
+            line.extend_from_slice(&buffer[..length]);
+            self.stdout.consume(length);
+            if ended {
+                return Ok((line, Head::Buffer));
+            }
+            if let Some(max_bytes) = max_bytes.filter(|max_bytes| line.len() > *max_bytes) {
+                self.skip_line(timeout_duration).await?;
+                return Err(response_stream::too_large(max_bytes));
             }
-            Err(e) => {
-                tracing::warn!("Failed to parse initialize response as JSON: {}", e);
-                // Continue anyway - some servers might send non-JSON responses
+            if head == Head::Incomplete {
+                head = response_stream::scan_head(&line, pending_id);
+            }
+            if head == Head::Stream {
+                return Ok((line, head));
             }
         }
-        
-        // Send initialized notification per MCP specification
-        let initialized_notification = serde_json::json!({
-            "jsonrpc": "2.0",
-            "method": "notifications/initialized",
-            "params": {}
-        });
-        
-        let notification_message = initialized_notification.to_string();
-        tracing::debug!("Sending initialized notification: {}", notification_message);
-        
-        self.stdin
-            .write_all((notification_message + "\n").as_bytes())
-            .await
-            .map_err(|e| McpCoreError::ProcessError {
-                message: format!("Failed to write initialized notification: {}", e),
-            })?;
-            
-        self.stdin
-            .flush()
-            .await
-            .map_err(|e| McpCoreError::ProcessError {
-                message: format!("Failed to flush initialized notification: {}", e),
-            })?;
-            
-        tracing::info!("MCP connection initialized successfully");
-        Ok(())
-    }
-    
-    /// Read a single response from MCP server with timeout
-    async fn read_response_with_timeout(&mut self, timeout_duration: Duration) -> McpCoreResult<String> {
-        let response_result = timeout(timeout_duration, async {
+    }
+
+    /// Write the rest of the current line to `sink`, reading all of it even
+    /// if the sink fails
+    async fn stream_line(
+        &mut self,
+        sink: &mut ResponseSink,
+        timeout_duration: Duration,
+    ) -> McpCoreResult<()> {
+        let mut written = Ok(());
+        loop {
+            let buffer = self.fill(timeout_duration).await?;
+            if buffer.is_empty() {
+                return Err(McpCoreError::ProcessError {
+                    message: format!(
+                        "MCP server closed the connection in the middle of a response{}",
+                        self.stderr_tail.context()
+                    ),
+                });
+            }
+            let (length, ended) = line_end(buffer);
+            if written.is_ok() {
+                written = sink.write(&buffer[..length - usize::from(ended)]).await;
+            }
+            self.stdout.consume(length);
+            if ended {
+                return written;
+            }
+        }
+    }
+
+    /// Read and drop the rest of the current line
+    async fn skip_line(&mut self, timeout_duration: Duration) -> McpCoreResult<()> {
+        loop {
+            let buffer = self.fill(timeout_duration).await?;
+            if buffer.is_empty() {
+                return Ok(());
+            }
+            let (length, ended) = line_end(buffer);
+            self.stdout.consume(length);
+            if ended {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Bytes buffered from stdout, reading more if there are none; empty at
+    /// EOF
+    async fn fill(&mut self, timeout_duration: Duration) -> McpCoreResult<&[u8]> {
+        match tokio::time::timeout(timeout_duration, self.stdout.fill_buf()).await {
+            Ok(Ok(buffer)) => Ok(buffer),
+            Ok(Err(e)) => Err(McpCoreError::ProcessError {
+                message: format!("Failed to read from MCP stdout: {}", e),
+            }),
+            Err(_) => Err(transport::response_timeout(timeout_duration)),
+        }
+    }
+
+    /// Read the next line that is not blank from the MCP server's stdout
+    async fn read_line(&mut self) -> McpCoreResult<String> {
+        loop {
             let mut response_line = String::new();
             match self.stdout.read_line(&mut response_line).await {
                 Ok(0) => {
                     tracing::warn!("MCP server closed connection (EOF)");
-                    Err(McpCoreError::ProcessError {
-                        message: "MCP server closed the connection (EOF)".to_string(),
-                    })
+                    if let Some(early) = self.exited_early().await {
+                        return Err(early);
+                    }
+                    return Err(McpCoreError::ProcessError {
+                        message: format!(
+                            "MCP server closed the connection (EOF){}",
+                            self.stderr_tail.context()
+                        ),
+                    });
                 }
                 Ok(bytes_read) => {
                     tracing::debug!("Read {} bytes from MCP server", bytes_read);
-                    tracing::debug!("Raw response: '{}'", response_line.trim());
-
-                    if response_line.trim().is_empty() {
-                        return Err(McpCoreError::ProcessError {
-                            message: "MCP server returned an empty line".to_string(),
-                        });
+                    if let Some(line) = self.line_tolerance.clean(&response_line) {
+                        tracing::debug!("Raw response: '{}'", line);
+                        return Ok(line);
                     }
-
-                    Ok(response_line.trim().to_string())
                 }
                 Err(e) => {
                     tracing::error!("Error reading from MCP stdout: {}", e);
-                    Err(McpCoreError::ProcessError {
+                    return Err(McpCoreError::ProcessError {
                         message: format!("Failed to read from MCP stdout: {}", e),
-                    })
+                    });
                 }
             }
-        })
-        .await;
-
-        match response_result {
-            Ok(result) => result,
-            Err(_) => {
-                let timeout_secs = timeout_duration.as_secs();
-                tracing::error!("MCP server response timeout after {} seconds", timeout_secs);
-                Err(McpCoreError::ProcessError {
-                    message: format!("MCP server response timeout ({} seconds)", timeout_secs),
-                })
+        }
+    }
+
+    /// Keep the stdout lines before EOF that fit in the quoted output
+    async fn read_rest(&mut self) {
+        let mut line = String::new();
+        while matches!(self.stdout.read_line(&mut line).await, Ok(length) if length > 0) {
+            if let Some(line) = self.line_tolerance.clean(&line) {
+                if self.stdout_head.len() < EARLY_EXIT_OUTPUT_LINES {
+                    self.stdout_head.push(truncate_for_log(&line));
+                }
             }
+            line.clear();
         }
     }
 
-    /// Send a query to the MCP server and wait for response
-    pub async fn query(&mut self, request: &McpRequest) -> McpCoreResult<McpResponse> {
-        let start_time = Instant::now();
-        tracing::debug!("Starting MCP query");
-        tracing::debug!("Request: {:?}", request);
+    /// The error for a child that exited within the early exit window
+    /// without writing a message, quoting what it wrote instead
+    ///
+    /// Such a child was most likely given the wrong command or arguments and
+    /// printed its usage. Returns `None` if the child is still running.
+    async fn exited_early(&mut self) -> Option<McpCoreError> {
+        let elapsed = self.spawned_at.elapsed();
+        if self.answered || elapsed > self.early_exit_window {
+            return None;
+        }
+        let status = tokio::time::timeout(EARLY_EXIT_WAIT, self.child.wait())
+            .await
+            .ok()?
+            .ok()?;
+        if let Some(reader) = self.stderr_reader.take() {
+            let _ = tokio::time::timeout(EARLY_EXIT_WAIT, reader).await;
+        }
+        // Output not read yet, when the exit was noticed by a failed write
+        let _ = tokio::time::timeout(EARLY_EXIT_WAIT, self.read_rest()).await;
 
-        // Send the command to MCP server (the command field contains the JSON-RPC message)
-        let mcp_message = &request.command;
-        tracing::debug!("Sending to MCP server: {}", mcp_message);
+        let mut output = self.stdout_head.clone();
+        output.extend(self.stderr_tail.lines());
+        output.truncate(EARLY_EXIT_OUTPUT_LINES);
+        let exit_code = status.code();
+        let exited = match exit_code {
+            Some(code) => format!("exit code {}", code),
+            None => status.to_string(),
+        };
+        let quoted = match output.is_empty() {
+            true => "it wrote no output".to_string(),
+            false => format!("its output: {}", output.join(" | ")),
+        };
+        Some(McpCoreError::ChildExitedEarly {
+            message: format!(
+                "{} {}ms after it was spawned, before sending a message; \
+                 check its command and args; {}",
+                exited,
+                elapsed.as_millis(),
+                quoted
+            ),
+            exit_code,
+            output,
+        })
+    }
+}
 
-        // Write to MCP server stdin
-        self.stdin
-            .write_all((mcp_message.to_string() + "\n").as_bytes())
+#[async_trait]
+impl McpTransport for McpProcess {
+    async fn send(&mut self, message: &str) -> McpCoreResult<()> {
+        // The message is not logged here since it may carry injected secrets
+        tracing::debug!("Sending {} bytes to MCP server", message.len());
+
+        // Write to MCP server stdin; a full pipe means the server stopped reading
+        let write_timeout = self.write_timeout;
+        let write = async {
+            self.stdin
+                .write_all((message.to_string() + "\n").as_bytes())
+                .await
+                .map_err(|e| McpCoreError::ProcessError {
+                    message: format!("Failed to write to MCP stdin: {}", e),
+                })?;
+
+            self.stdin
+                .flush()
+                .await
+                .map_err(|e| McpCoreError::ProcessError {
+                    message: format!("Failed to flush MCP stdin: {}", e),
+                })
+        };
+        let written = tokio::time::timeout(write_timeout, write)
             .await
-            .map_err(|e| McpCoreError::ProcessError {
-                message: format!("Failed to write to MCP stdin: {}", e),
-            })?;
+            .unwrap_or_else(|_| {
+                Err(McpCoreError::ChildUnresponsive {
+                    message: format!(
+                        "writing to its stdin blocked for {}ms",
+                        write_timeout.as_millis()
+                    ),
+                })
+            });
+        // A child that exited already closed its stdin
+        if let Err(McpCoreError::ProcessError { .. }) = written {
+            if let Some(early) = self.exited_early().await {
+                return Err(early);
+            }
+        }
+        written
+    }
+
+    async fn receive(&mut self) -> McpCoreResult<String> {
+        self.read_message().await
+    }
 
-        self.stdin
-            .flush()
+    async fn shutdown(&mut self) -> McpCoreResult<()> {
+        tracing::info!("Stopping MCP process");
+        self.child
+            .kill()
             .await
             .map_err(|e| McpCoreError::ProcessError {
-                message: format!("Failed to flush MCP stdin: {}", e),
+                message: format!("Failed to stop MCP process: {}", e),
+            })
+    }
+
+    fn is_alive(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    fn exit_status(&mut self) -> Option<ChildExit> {
+        self.child.try_wait().ok().flatten().map(ChildExit::from)
+    }
+
+    fn buffer_notification(&mut self, notification: String) {
+        self.notifications.push(notification);
+    }
+
+    fn drain_notifications(&mut self) -> Vec<String> {
+        self.notifications.drain()
+    }
+
+    async fn receive_streamed(
+        &mut self,
+        pending_id: &serde_json::Value,
+        sink: &mut ResponseSink,
+        timeout_duration: Duration,
+    ) -> McpCoreResult<Received> {
+        let mut skipped = Skipped::default();
+        loop {
+            let (line, head) = self
+                .read_head(pending_id, sink.max_bytes(), timeout_duration)
+                .await?;
+            if line.is_empty() {
+                // EOF, reported as a normal read reports it
+                return self.read_line().await.map(Received::Message);
+            }
+            if head == Head::Stream {
+                self.answered = true;
+                let opening = line.trim_ascii_start();
+                tracing::debug!(
+                    "Streaming the response after its first {} bytes",
+                    opening.len()
+                );
+                let written = sink.write(opening).await;
+                let streamed = self.stream_line(sink, timeout_duration).await;
+                return written.and(streamed).map(|()| Received::Streamed);
+            }
+            let line = String::from_utf8(line).map_err(|e| McpCoreError::ProcessError {
+                message: format!("Failed to read from MCP stdout: {}", e),
             })?;
+            let Some(line) = self.line_tolerance.clean(&line) else {
+                continue;
+            };
+            if let Some(message) = self.accept_line(line, &mut skipped)? {
+                return Ok(Received::Message(message));
+            }
+        }
+    }
 
-        tracing::debug!("Data sent to MCP server, waiting for response...");
+    fn pid(&self) -> Option<u32> {
+        self.child.id()
+    }
 
-        // Read response with shorter timeout for regular queries
-        let response_line = self.read_response_with_timeout(Duration::from_secs(30)).await?;
-        
-        let elapsed = start_time.elapsed();
-        tracing::debug!("MCP query completed in {:?}", elapsed);
-        
-        Ok(McpResponse {
-            result: response_line,
-        })
+    fn stderr_tail(&self) -> Option<StderrTail> {
+        Some(self.stderr_tail.clone())
+    }
+}
+
+/// Bytes of `buffer` up to and including the end of the line, and whether
+/// the line ends there
+fn line_end(buffer: &[u8]) -> (usize, bool) {
+    match buffer.iter().position(|byte| *byte == b'\n') {
+        Some(newline) => (newline + 1, true),
+        None => (buffer.len(), false),
+    }
+}
+
+/// Shorten a line for error messages
+fn truncate_for_log(line: &str) -> String {
+    const MAX_CHARS: usize = 200;
+    match line.char_indices().nth(MAX_CHARS) {
+        Some((index, _)) => format!("{}...", &line[..index]),
+        None => line.to_string(),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::CapturedLogs;
 
     #[test]
     fn test_mcp_request_serialization() {
@@ -294,6 +717,257 @@ mod tests {
         assert!(json.contains("tools/list"));
     }
 
+    #[test]
+    fn test_command_policy_compacts_to_one_line() {
+        let policy = CommandPolicy::default();
+        let command = "{\n  \"jsonrpc\": \"2.0\",\n  \"id\": 1,\n  \"method\": \"tools/list\"\n}";
+
+        let sanitized = policy.sanitize(command).unwrap();
+        assert!(!sanitized.contains('\n'));
+        assert_eq!(
+            sanitized,
+            r#"{"jsonrpc":"2.0","id":1,"method":"tools/list"}"#
+        );
+    }
+
+    #[test]
+    fn test_command_policy_rejects_two_messages() {
+        let policy = CommandPolicy::default();
+        let command = "{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"a\"}\n{\"jsonrpc\":\"2.0\",\"id\":2,\"method\":\"b\"}";
+
+        let error = policy.sanitize(command).unwrap_err();
+        assert!(matches!(
+            error,
+            McpCoreError::InvalidCommand {
+                code: "invalid_json",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_command_policy_limits() {
+        let policy = CommandPolicy {
+            max_bytes: 16,
+            allow_non_jsonrpc: false,
+        };
+        let error = policy
+            .sanitize(r#"{"jsonrpc":"2.0","id":1,"method":"tools/list"}"#)
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            McpCoreError::InvalidCommand {
+                code: "command_too_large",
+                ..
+            }
+        ));
+
+        let policy = CommandPolicy::default();
+        let error = policy.sanitize(r#"{"id":1}"#).unwrap_err();
+        assert!(matches!(
+            error,
+            McpCoreError::InvalidCommand {
+                code: "not_jsonrpc",
+                ..
+            }
+        ));
+
+        let policy = CommandPolicy {
+            allow_non_jsonrpc: true,
+            ..CommandPolicy::default()
+        };
+        assert!(policy.sanitize(r#"{"id":1}"#).is_ok());
+        assert!(policy.sanitize("not json").is_err());
+    }
+
+    #[cfg(unix)]
+    async fn spawn_script(script: &str, noise_policy: NoisePolicy) -> McpProcess {
+        let mut command = Command::new("sh");
+        command
+            .args(["-c", script])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped());
+        McpProcess::spawn(command)
+            .await
+            .unwrap()
+            .with_noise_policy(noise_policy)
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stderr_logged_with_server_name_and_pid() {
+        let (logs, _default) = CapturedLogs::install();
+
+        let mut command = Command::new("sh");
+        command
+            .args(["-c", "echo 'listening on stdio' >&2; cat"])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped());
+        let mut process = McpProcess::spawn_for_server(command, "alpha", StderrPolicy::default())
+            .await
+            .unwrap();
+        let pid = process.pid().unwrap();
+
+        let mut line = None;
+        for _ in 0..100 {
+            line = logs
+                .contents()
+                .lines()
+                .find(|line| line.contains("listening on stdio"))
+                .map(str::to_string);
+            if line.is_some() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let line = line.expect("stderr line was not logged");
+        assert!(line.contains("server=alpha"), "{}", line);
+        assert!(line.contains(&format!("pid={}", pid)), "{}", line);
+
+        process.shutdown().await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_write_to_child_not_reading_stdin_times_out() {
+        let mut process = spawn_script("sleep 600", NoisePolicy::default())
+            .await
+            .with_write_timeout(Duration::from_millis(100));
+        assert!(process
+            .send(r#"{"jsonrpc":"2.0","method":"ping"}"#)
+            .await
+            .is_ok());
+
+        // Enough to fill the pipe buffer
+        let message = format!(r#"{{"text":"{}"}}"#, "x".repeat(1024 * 1024));
+        let started = std::time::Instant::now();
+        let error = process.send(&message).await.unwrap_err();
+        assert!(matches!(error, McpCoreError::ChildUnresponsive { .. }));
+        assert_eq!(
+            error.to_string(),
+            "MCP server unresponsive: writing to its stdin blocked for 100ms"
+        );
+        assert!(started.elapsed() < Duration::from_secs(1));
+        process.shutdown().await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_child_printing_usage_fails_fast() {
+        let script =
+            "echo 'usage: server [--stdio]'; echo 'error: unknown option --sse' >&2; exit 2";
+        let started = Instant::now();
+        let mut process = spawn_script(script, NoisePolicy::default()).await;
+        let _ = process
+            .send(r#"{"jsonrpc":"2.0","id":1,"method":"initialize"}"#)
+            .await;
+        let error = process.receive().await.unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let McpCoreError::ChildExitedEarly {
+            exit_code, output, ..
+        } = &error
+        else {
+            panic!("unexpected error: {}", error);
+        };
+        assert_eq!(*exit_code, Some(2));
+        assert_eq!(
+            output,
+            &["usage: server [--stdio]", "error: unknown option --sse"]
+        );
+        assert_eq!(error.error_code(), Some("child_exited_early"));
+        assert!(error.to_string().contains("exit code 2"), "{}", error);
+
+        // Noticed by a write after the exit, the output is still quoted
+        let mut process = spawn_script(script, NoisePolicy::default()).await;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let error = process
+            .send(r#"{"jsonrpc":"2.0","id":1,"method":"initialize"}"#)
+            .await
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("usage: server [--stdio]"),
+            "{}",
+            error
+        );
+
+        // Without a window the exit is an ordinary closed connection
+        let mut process = spawn_script(script, NoisePolicy::default())
+            .await
+            .with_early_exit_window(Duration::ZERO);
+        let error = process.receive().await.unwrap_err();
+        assert!(matches!(error, McpCoreError::ProcessError { .. }));
+        assert!(error.to_string().contains("EOF"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stdout_noise_skipped_before_and_between_messages() {
+        let script = r#"echo 'Server v1.2 starting'; echo '[dotenv] injecting env'; echo '{"jsonrpc":"2.0","id":1,"result":{}}'; echo 'npm fund notice'; echo '{"jsonrpc":"2.0","id":2,"result":{}}'"#;
+        let mut process = spawn_script(script, NoisePolicy::default()).await;
+
+        let first: serde_json::Value =
+            serde_json::from_str(&process.receive().await.unwrap()).unwrap();
+        assert_eq!(first["id"], 1);
+        let second: serde_json::Value =
+            serde_json::from_str(&process.receive().await.unwrap()).unwrap();
+        assert_eq!(second["id"], 2);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stdout_noise_limits_and_strict_mode() {
+        let script =
+            r#"echo one; echo two; echo three; echo '{"jsonrpc":"2.0","id":1,"result":{}}'"#;
+
+        let policy = NoisePolicy {
+            max_lines: 2,
+            ..NoisePolicy::default()
+        };
+        let mut process = spawn_script(script, policy).await;
+        let error = process.receive().await.unwrap_err();
+        assert!(error.to_string().contains("3 lines"));
+
+        let policy = NoisePolicy {
+            mode: StdoutNoise::Error,
+            ..NoisePolicy::default()
+        };
+        let mut process = spawn_script(script, policy).await;
+        let error = process.receive().await.unwrap_err();
+        assert!(error.to_string().contains("'one'"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_crlf_and_blank_lines_are_tolerated() {
+        let script = r#"printf '{"jsonrpc":"2.0","id":1,"result":{}}\r\n\r\n\n  \n{"jsonrpc":"2.0","id":2,"result":{}}\r\n'"#;
+        // Neither counts as noise, even when noise is an error
+        let policy = NoisePolicy {
+            mode: StdoutNoise::Error,
+            ..NoisePolicy::default()
+        };
+        let mut process = spawn_script(script, policy).await;
+
+        for id in [1, 2] {
+            let response = process.receive().await.unwrap();
+            assert!(!response.contains('\r'), "{:?}", response);
+            let message: serde_json::Value = serde_json::from_str(&response).unwrap();
+            assert_eq!(message["id"], id);
+        }
+    }
+
+    #[test]
+    fn test_line_tolerance() {
+        let mut tolerance = LineTolerance::default();
+        assert_eq!(tolerance.clean("{}\r\n").as_deref(), Some("{}"));
+        assert_eq!(tolerance.clean("{}\n").as_deref(), Some("{}"));
+        assert_eq!(tolerance.clean("\r\n"), None);
+        assert_eq!(tolerance.clean(" \t\n"), None);
+        assert!(tolerance.crlf_seen && tolerance.blank_seen);
+    }
+
     #[test]
     fn test_mcp_response_serialization() {
         let response = McpResponse {
@@ -0,0 +1,1179 @@
+//! Deferred setup of the MCP server
+//!
+//! By default a server is cloned, built, and spawned while the gateway
+//! starts (`setup_mode: "on-start"`). With `"on-first-request"` the gateway
+//! starts without it and the first `/api/v1` request runs the setup while
+//! it waits. With `"manual"` requests are refused with `409 not_provisioned`
+//! until an operator calls `POST /admin/servers/{name}/provision`.
+//!
+//! Each setup attempt is a job whose phases are reported as they run. Asking
+//! for provisioning while a job runs or after one succeeded returns that job;
+//! after a failure a new job starts.
+//!
+//! A provisioned server can be restarted by running the setup again, which
+//! reuses the clone and build. `in-place` stops the child first and holds
+//! requests until the new one is up. `blue-green` starts and initializes the
+//! replacement while the old child keeps serving, then switches to it once
+//! the old child's current request is answered; if the replacement fails to
+//! start, the old child is left alone.
+//!
+//! Restarts, and provisioning attempts after a failed one, wait for the
+//! [`RestartBudget`] shared across servers when one is configured; the old
+//! child keeps serving meanwhile.
+//!
+//! A child exiting right after it was spawned was most likely given the
+//! wrong command or arguments, and will do so again. After
+//! [`MAX_EARLY_EXITS`] setups in a row fail with `child_exited_early`, the
+//! server is marked failed: restarts are refused and requests no longer
+//! start provisioning. An operator can still provision it through the admin
+//! API.
+//!
+//! A setup whose handshake still fails after `initialize_retries` leaves the
+//! server `handshake_failed`: requests fail at once with that error instead
+//! of waiting on another setup, until an operator restarts or provisions the
+//! server, which clears the state and tries again.
+//!
+//! Setups and restarts are recorded in the server's [`LifecycleHistory`]
+//! with the [`Trigger`] that asked for them.
+
+use crate::artifact_cache::ArtifactCacheStats;
+use crate::audit::AuditReport;
+use crate::egress::EgressProxy;
+use crate::error::{McpCoreError, McpCoreResult};
+use crate::history::{ChildExit, LifecycleHistory, LifecycleRecord, Transition, Trigger};
+use crate::restart_budget::RestartBudget;
+use crate::sandbox::SandboxBackend;
+use crate::stderr::StderrTail;
+use crate::timing::{PhaseProgress, PhaseTimer, PhaseTimings, ProgressSnapshot};
+use crate::transport::{HandshakeFailure, McpTransport};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+use tokio::sync::watch;
+
+/// Setups in a row failing with `child_exited_early` before the server is
+/// marked failed
+pub const MAX_EARLY_EXITS: u32 = 3;
+
+/// When the clone, build, and spawn of a server happen
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SetupMode {
+    /// While the gateway starts
+    #[default]
+    OnStart,
+    /// When the first request arrives
+    OnFirstRequest,
+    /// When requested through the admin API
+    Manual,
+}
+
+/// What a successful setup produced, besides the transport
+#[derive(Debug, Clone)]
+pub struct Provisioned {
+    pub protocol_version: String,
+    pub pid: Option<u32>,
+    pub stderr: Option<StderrTail>,
+    pub startup: PhaseTimings,
+    pub audit: Option<AuditReport>,
+
+    /// HEAD of the cloned repository, captured after clone or update
+    pub commit: Option<String>,
+
+    /// Version declared by the cloned repository's package manifest
+    pub package_version: Option<String>,
+
+    /// Artifact cache hits, misses, and bytes transferred during setup
+    pub artifact_cache: Option<ArtifactCacheStats>,
+
+    /// Backend isolating the server process, if it is sandboxed
+    pub sandbox: Option<SandboxBackend>,
+
+    /// Proxy enforcing the sandbox's host allowlist
+    pub egress: Option<Arc<EgressProxy>>,
+}
+
+/// How a restart replaces the server's child
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartStrategy {
+    /// Stop the child, then start a new one while requests wait
+    #[default]
+    InPlace,
+    /// Start a replacement next to the child and switch to it once it is ready
+    BlueGreen,
+}
+
+/// Outcome of a restart
+#[derive(Debug, Clone, Serialize)]
+pub struct RestartRecord {
+    pub strategy: RestartStrategy,
+
+    /// What asked for the restart, e.g. `admin` or a recycling threshold
+    pub reason: String,
+    pub started_at: DateTime<Utc>,
+
+    /// Time from the request to the replacement serving, or to the failure
+    pub duration_ms: u64,
+
+    /// Time requests were held while the children were switched
+    pub switchover_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// Restarts since the gateway started
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RestartStats {
+    pub restarts: u64,
+    pub failures: u64,
+    pub last: Option<RestartRecord>,
+}
+
+/// Setup pipeline run by a provisioning job, recording its transitions
+/// with the trigger it is given
+pub type ProvisionFn = Arc<
+    dyn Fn(
+            PhaseTimer,
+            Trigger,
+        ) -> Pin<
+            Box<dyn Future<Output = McpCoreResult<(Box<dyn McpTransport>, Provisioned)>> + Send>,
+        > + Send
+        + Sync,
+>;
+
+/// State of a provisioning job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+/// Point-in-time view of a provisioning job
+#[derive(Debug, Clone, Serialize)]
+pub struct ProvisionJob {
+    pub id: String,
+    pub state: JobState,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+    #[serde(flatten)]
+    pub progress: ProgressSnapshot,
+}
+
+/// Setup that failed its handshake and left the server unprovisioned
+#[derive(Debug, Clone, Serialize)]
+pub struct FailedHandshake {
+    pub failure: HandshakeFailure,
+    pub attempts: u32,
+    pub error: String,
+    pub at: DateTime<Utc>,
+}
+
+/// Provisioning status of the server as reported by the status endpoints
+#[derive(Debug, Clone, Serialize)]
+pub struct ProvisionStatus {
+    pub setup_mode: SetupMode,
+
+    /// `not_provisioned`, `provisioning`, `restart_throttled`,
+    /// `provisioned`, `handshake_failed` or `failed`
+    pub state: &'static str,
+    pub job: Option<ProvisionJob>,
+
+    /// Whether a restart waits for the restart budget; a provisioned server
+    /// keeps serving meanwhile
+    pub restart_throttled: bool,
+
+    /// Failed handshake the server is stuck on, while `handshake_failed`
+    pub handshake: Option<FailedHandshake>,
+
+    /// Setups that failed their handshake since the gateway started
+    pub handshake_failures: u64,
+}
+
+struct Job {
+    id: String,
+    started_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+    progress: Arc<PhaseProgress>,
+    state: JobState,
+    error: Option<String>,
+}
+
+impl Job {
+    fn snapshot(&self) -> ProvisionJob {
+        ProvisionJob {
+            id: self.id.clone(),
+            state: self.state,
+            started_at: self.started_at,
+            finished_at: self.finished_at,
+            error: self.error.clone(),
+            progress: self.progress.snapshot(),
+        }
+    }
+}
+
+/// Provisioning of the server behind this gateway
+pub struct Provisioner {
+    server_name: String,
+    mode: SetupMode,
+    provisioned: RwLock<Option<Arc<Provisioned>>>,
+
+    /// When the serving child started, and the requests sent to it since
+    serving_since: Mutex<Option<Instant>>,
+    served: AtomicU64,
+
+    /// Transport the setup result is swapped into
+    transport: Arc<tokio::sync::Mutex<Box<dyn McpTransport>>>,
+    pipeline: Option<ProvisionFn>,
+    job: Mutex<Option<Job>>,
+    next_job: AtomicU64,
+
+    /// Bumped whenever a job finishes
+    finished: watch::Sender<u64>,
+
+    /// Held while a restart runs
+    restarting: tokio::sync::Mutex<()>,
+    restarts: Mutex<RestartStats>,
+
+    /// Budget restarts take from, and whether one waits for it
+    budget: Option<Arc<RestartBudget>>,
+    throttled: AtomicBool,
+
+    /// Setups in a row whose child exited early
+    early_exits: AtomicU32,
+
+    /// Failed handshake of the latest setup, while it left no child serving
+    failed_handshake: Mutex<Option<FailedHandshake>>,
+    handshake_failures: AtomicU64,
+
+    /// Recent transitions of the server's children
+    history: Arc<LifecycleHistory>,
+}
+
+impl std::fmt::Debug for Provisioner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Provisioner")
+            .field("server_name", &self.server_name)
+            .field("mode", &self.mode)
+            .field("provisioned", &self.provisioned().is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Provisioner {
+    /// Provisioner of a server that was set up while the gateway started
+    pub fn ready(
+        server_name: &str,
+        transport: Arc<tokio::sync::Mutex<Box<dyn McpTransport>>>,
+        provisioned: Provisioned,
+    ) -> Self {
+        let provisioner = Self::new(server_name, SetupMode::OnStart, transport, None);
+        provisioner.set_provisioned(Some(provisioned));
+        provisioner
+    }
+
+    /// Run `pipeline` to restart the server, and to provision it again
+    /// should a restart fail
+    pub fn with_pipeline(mut self, pipeline: ProvisionFn) -> Self {
+        self.pipeline = Some(pipeline);
+        self
+    }
+
+    /// Take a token of `budget` before each restart
+    pub fn with_restart_budget(mut self, budget: Arc<RestartBudget>) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Record transitions in `history`, which the pipeline should share
+    pub fn with_history(mut self, history: Arc<LifecycleHistory>) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Recent transitions of the server's children
+    pub fn history(&self) -> &Arc<LifecycleHistory> {
+        &self.history
+    }
+
+    /// Budget restarts of this server take from
+    pub fn restart_budget(&self) -> Option<&Arc<RestartBudget>> {
+        self.budget.as_ref()
+    }
+
+    /// Provisioner that runs `pipeline` later, as `mode` says
+    ///
+    /// Until then, `transport` should hold an [`Unprovisioned`] placeholder.
+    pub fn deferred(
+        server_name: &str,
+        mode: SetupMode,
+        transport: Arc<tokio::sync::Mutex<Box<dyn McpTransport>>>,
+        pipeline: ProvisionFn,
+    ) -> Self {
+        Self::new(server_name, mode, transport, Some(pipeline))
+    }
+
+    fn new(
+        server_name: &str,
+        mode: SetupMode,
+        transport: Arc<tokio::sync::Mutex<Box<dyn McpTransport>>>,
+        pipeline: Option<ProvisionFn>,
+    ) -> Self {
+        Self {
+            server_name: server_name.to_string(),
+            mode,
+            provisioned: RwLock::new(None),
+            serving_since: Mutex::new(None),
+            served: AtomicU64::new(0),
+            transport,
+            pipeline,
+            job: Mutex::new(None),
+            next_job: AtomicU64::new(1),
+            finished: watch::channel(0).0,
+            restarting: tokio::sync::Mutex::new(()),
+            restarts: Mutex::new(RestartStats::default()),
+            budget: None,
+            throttled: AtomicBool::new(false),
+            early_exits: AtomicU32::new(0),
+            failed_handshake: Mutex::new(None),
+            handshake_failures: AtomicU64::new(0),
+            history: Arc::default(),
+        }
+    }
+
+    /// Result of the latest setup, while it is serving
+    pub fn provisioned(&self) -> Option<Arc<Provisioned>> {
+        self.provisioned
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    fn set_provisioned(&self, provisioned: Option<Provisioned>) {
+        *self
+            .serving_since
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) =
+            provisioned.as_ref().map(|_| Instant::now());
+        self.served.store(0, Ordering::Relaxed);
+        *self
+            .provisioned
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = provisioned.map(Arc::new);
+    }
+
+    /// Count a request sent to the serving child
+    pub fn record_request(&self) {
+        self.served.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Requests sent to the serving child
+    pub fn served(&self) -> u64 {
+        self.served.load(Ordering::Relaxed)
+    }
+
+    /// How long the serving child has been up
+    pub fn uptime(&self) -> Option<std::time::Duration> {
+        self.serving_since
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .map(|since| since.elapsed())
+    }
+
+    pub fn status(&self) -> ProvisionStatus {
+        let job = self.lock().as_ref().map(Job::snapshot);
+        let restart_throttled = self.throttled.load(Ordering::Relaxed);
+        let handshake = self.failed_handshake();
+        let state = match (&self.provisioned(), job.as_ref().map(|job| job.state)) {
+            (Some(_), _) => "provisioned",
+            (None, Some(JobState::Running)) if restart_throttled => "restart_throttled",
+            (None, Some(JobState::Running)) => "provisioning",
+            (None, _) if handshake.is_some() => "handshake_failed",
+            (None, _) if self.gave_up() => "failed",
+            (None, Some(JobState::Failed)) => "failed",
+            (None, _) => "not_provisioned",
+        };
+        ProvisionStatus {
+            setup_mode: self.mode,
+            state,
+            job,
+            restart_throttled,
+            handshake,
+            handshake_failures: self.handshake_failures.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The job with id `id`, if it is the latest one
+    pub fn job(&self, id: &str) -> Option<ProvisionJob> {
+        self.lock()
+            .as_ref()
+            .filter(|job| job.id == id)
+            .map(Job::snapshot)
+    }
+
+    /// Start provisioning for `trigger` unless a job is running or succeeded
+    ///
+    /// Returns the job that provisions the server and whether it was started
+    /// by this call. A server set up on start has no job until a failed
+    /// restart leaves it unprovisioned.
+    pub fn provision(self: &Arc<Self>, trigger: Trigger) -> (Option<ProvisionJob>, bool) {
+        let mut current = self.lock();
+        let Some(pipeline) = &self.pipeline else {
+            return (None, false);
+        };
+        let running = current
+            .as_ref()
+            .is_some_and(|job| job.state == JobState::Running);
+        if running || self.provisioned().is_some() {
+            return (current.as_ref().map(Job::snapshot), false);
+        }
+        // Only the first setup of a deferred server is not a respawn
+        let respawn = current.is_some() || self.mode == SetupMode::OnStart;
+
+        let id = format!(
+            "provision-{}",
+            self.next_job.fetch_add(1, Ordering::Relaxed)
+        );
+        let progress = Arc::new(PhaseProgress::default());
+        let job = Job {
+            id: id.clone(),
+            started_at: Utc::now(),
+            finished_at: None,
+            progress: Arc::clone(&progress),
+            state: JobState::Running,
+            error: None,
+        };
+        let snapshot = job.snapshot();
+        *current = Some(job);
+        tracing::info!("Provisioning server '{}' (job {})", self.server_name, id);
+
+        let setup = pipeline(PhaseTimer::observed(progress), trigger);
+        let provisioner = Arc::clone(self);
+        tokio::spawn(async move {
+            if respawn {
+                provisioner.take_budget().await;
+            }
+            let result = match setup.await {
+                Ok((transport, provisioned)) => {
+                    *provisioner.transport.lock().await = transport;
+                    provisioner.set_provisioned(Some(provisioned));
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            };
+            provisioner.note_setup(result.as_ref().err());
+            let result = result.map_err(|e| e.to_string());
+            provisioner.finish(&id, result);
+        });
+        (Some(snapshot), true)
+    }
+
+    fn finish(&self, id: &str, result: Result<(), String>) {
+        if let Some(job) = self.lock().as_mut().filter(|job| job.id == id) {
+            job.finished_at = Some(Utc::now());
+            match result {
+                Ok(()) => {
+                    tracing::info!("Server '{}' provisioned (job {})", self.server_name, id);
+                    job.state = JobState::Succeeded;
+                }
+                Err(e) => {
+                    tracing::error!(
+                        "Provisioning server '{}' failed (job {}): {}",
+                        self.server_name,
+                        id,
+                        e
+                    );
+                    job.state = JobState::Failed;
+                    job.error = Some(e);
+                }
+            }
+        }
+        self.finished.send_modify(|generation| *generation += 1);
+    }
+
+    /// Refuse a request the server cannot take, without waiting
+    ///
+    /// Passes once the server is provisioned, and with `on-first-request`
+    /// while it can still be provisioned on demand.
+    pub fn check_ready(&self) -> McpCoreResult<()> {
+        if self.provisioned().is_some() {
+            return Ok(());
+        }
+        if let Some(handshake) = self.failed_handshake() {
+            return Err(self.handshake_error(handshake));
+        }
+        if self.mode != SetupMode::OnFirstRequest {
+            let message = match self.lock().as_ref() {
+                Some(job) if job.state == JobState::Running => format!(
+                    "Server '{}' is being provisioned (job {})",
+                    self.server_name, job.id
+                ),
+                _ => format!(
+                    "Server '{}' is not provisioned; call POST /admin/servers/{}/provision",
+                    self.server_name, self.server_name
+                ),
+            };
+            return Err(McpCoreError::NotProvisioned { message });
+        }
+
+        if self.gave_up() {
+            return Err(self.given_up());
+        }
+        Ok(())
+    }
+
+    /// Admit a request once the server is provisioned
+    ///
+    /// With `on-first-request` the request starts provisioning if needed and
+    /// waits for it; with `manual` it is refused until then.
+    pub async fn ensure_ready(self: &Arc<Self>) -> McpCoreResult<()> {
+        self.check_ready()?;
+        if self.provisioned().is_some() {
+            return Ok(());
+        }
+        let mut finished = self.finished.subscribe();
+        let (job, _) = self.provision(Trigger::Start);
+        let Some(id) = job.map(|job| job.id) else {
+            return Ok(());
+        };
+        loop {
+            if self.provisioned().is_some() {
+                return Ok(());
+            }
+            match self.job(&id) {
+                Some(job) if job.state == JobState::Failed => {
+                    if let Some(handshake) = self.failed_handshake() {
+                        return Err(self.handshake_error(handshake));
+                    }
+                    return Err(McpCoreError::NotProvisioned {
+                        message: format!(
+                            "Provisioning server '{}' failed: {}",
+                            self.server_name,
+                            job.error.unwrap_or_default()
+                        ),
+                    });
+                }
+                Some(_) => {}
+                // A later job replaced it; wait for that one instead
+                None => return Box::pin(self.ensure_ready()).await,
+            }
+            if finished.changed().await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Replace the running child with a freshly set up one
+    ///
+    /// `reason` says what `trigger` asked for, such as the recycling
+    /// threshold reached. Fails without touching the child if the server is
+    /// not provisioned, cannot be restarted, or is already restarting; a
+    /// server left `handshake_failed` is set up again. A failed `blue-green`
+    /// restart leaves the old child serving; a failed `in-place` restart
+    /// leaves the server unprovisioned.
+    pub async fn restart(
+        &self,
+        strategy: RestartStrategy,
+        trigger: Trigger,
+        reason: &str,
+    ) -> McpCoreResult<RestartRecord> {
+        let Some(pipeline) = &self.pipeline else {
+            return Err(McpCoreError::RequestError {
+                message: format!("Server '{}' cannot be restarted", self.server_name),
+            });
+        };
+        let Ok(_restarting) = self.restarting.try_lock() else {
+            return Err(McpCoreError::RequestError {
+                message: format!("Server '{}' is already restarting", self.server_name),
+            });
+        };
+        if self.provisioned().is_none() && self.failed_handshake().is_none() {
+            return Err(McpCoreError::NotProvisioned {
+                message: format!("Server '{}' is not provisioned", self.server_name),
+            });
+        }
+        if self.gave_up() {
+            return Err(self.given_up());
+        }
+        self.take_budget().await;
+
+        tracing::info!(
+            "Restarting server '{}' ({}, {})",
+            self.server_name,
+            serde_json::to_value(strategy).unwrap_or_default(),
+            reason
+        );
+        let started_at = Utc::now();
+        let started = Instant::now();
+        let pid = self.provisioned().and_then(|provisioned| provisioned.pid);
+        self.history.record(
+            LifecycleRecord::new(Transition::Restarting, trigger)
+                .with_pid(pid)
+                .with_detail(reason),
+        );
+        let switched = match strategy {
+            RestartStrategy::BlueGreen => match pipeline(PhaseTimer::default(), trigger).await {
+                Ok((replacement, provisioned)) => {
+                    let switching = Instant::now();
+                    let mut transport = self.transport.lock().await;
+                    let mut retired = std::mem::replace(&mut *transport, replacement);
+                    self.set_provisioned(Some(provisioned));
+                    drop(transport);
+                    let switchover = switching.elapsed();
+                    if let Err(e) = retired.shutdown().await {
+                        tracing::warn!("Failed to shut down the retired child: {}", e);
+                    }
+                    self.record_exit(pid, retired.exit_status(), trigger);
+                    Ok(switchover)
+                }
+                Err(e) => Err(e),
+            },
+            RestartStrategy::InPlace => {
+                let mut transport = self.transport.lock().await;
+                let switching = Instant::now();
+                if let Err(e) = transport.shutdown().await {
+                    tracing::warn!("Failed to shut down the child: {}", e);
+                }
+                self.record_exit(pid, transport.exit_status(), trigger);
+                match pipeline(PhaseTimer::default(), trigger).await {
+                    Ok((replacement, provisioned)) => {
+                        *transport = replacement;
+                        self.set_provisioned(Some(provisioned));
+                        Ok(switching.elapsed())
+                    }
+                    Err(e) => {
+                        *transport = Box::new(Unprovisioned);
+                        self.set_provisioned(None);
+                        Err(e)
+                    }
+                }
+            }
+        };
+
+        self.note_setup(switched.as_ref().err());
+
+        let record = RestartRecord {
+            strategy,
+            reason: reason.to_string(),
+            started_at,
+            duration_ms: started.elapsed().as_millis() as u64,
+            switchover_ms: switched
+                .as_ref()
+                .ok()
+                .map(|switchover| switchover.as_millis() as u64),
+            error: switched.as_ref().err().map(ToString::to_string),
+        };
+        let mut restarts = self
+            .restarts
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        restarts.restarts += 1;
+        restarts.last = Some(record.clone());
+        match switched {
+            Ok(_) => {
+                tracing::info!(
+                    "Server '{}' restarted in {}ms",
+                    self.server_name,
+                    record.duration_ms
+                );
+                Ok(record)
+            }
+            Err(e) => {
+                restarts.failures += 1;
+                tracing::error!("Restarting server '{}' failed: {}", self.server_name, e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Shut the serving child down for `trigger` and record its exit, if
+    /// the server is provisioned
+    pub async fn shut_down(&self, trigger: Trigger) -> McpCoreResult<()> {
+        let provisioned = self.provisioned();
+        let mut transport = self.transport.lock().await;
+        let stopped = transport.shutdown().await;
+        if let Some(provisioned) = provisioned {
+            self.record_exit(provisioned.pid, transport.exit_status(), trigger);
+        }
+        stopped
+    }
+
+    /// Whether the serving child is gone, checked after a request to it
+    /// failed; its exit is recorded as a crash the first time
+    pub async fn check_exited(&self) -> bool {
+        let Some(provisioned) = self.provisioned() else {
+            return false;
+        };
+        let mut transport = self.transport.lock().await;
+        if transport.is_alive() {
+            return false;
+        }
+        self.record_exit(provisioned.pid, transport.exit_status(), Trigger::Crash);
+        true
+    }
+
+    fn record_exit(&self, pid: Option<u32>, exit: Option<ChildExit>, trigger: Trigger) {
+        self.history.record(
+            LifecycleRecord::new(Transition::Exited, trigger)
+                .with_pid(pid)
+                .with_exit(exit),
+        );
+    }
+
+    /// Wait for a token of the restart budget, if there is one
+    async fn take_budget(&self) {
+        let Some(budget) = &self.budget else {
+            return;
+        };
+        // Taking an available token does not yield, so the flag is only
+        // seen while the restart waits
+        struct Throttled<'a>(&'a AtomicBool);
+        impl Drop for Throttled<'_> {
+            fn drop(&mut self) {
+                self.0.store(false, Ordering::Relaxed);
+            }
+        }
+        self.throttled.store(true, Ordering::Relaxed);
+        let _throttled = Throttled(&self.throttled);
+        budget.acquire(&self.server_name).await;
+    }
+
+    /// Count a setup failing with an early exit, or forget them on success,
+    /// and record a failed handshake that left no child serving
+    fn note_setup(&self, error: Option<&McpCoreError>) {
+        let handshake = match error {
+            Some(McpCoreError::HandshakeFailed {
+                message,
+                failure,
+                attempts,
+            }) => {
+                self.handshake_failures.fetch_add(1, Ordering::Relaxed);
+                self.provisioned().is_none().then(|| FailedHandshake {
+                    failure: *failure,
+                    attempts: *attempts,
+                    error: message.clone(),
+                    at: Utc::now(),
+                })
+            }
+            _ => None,
+        };
+        if handshake.is_some() {
+            tracing::error!(
+                "Server '{}' failed its handshake; requests fail until it is restarted",
+                self.server_name
+            );
+        }
+        *self
+            .failed_handshake
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = handshake;
+
+        match error {
+            None => self.early_exits.store(0, Ordering::Relaxed),
+            Some(McpCoreError::ChildExitedEarly { .. }) => {
+                let early_exits = self.early_exits.fetch_add(1, Ordering::Relaxed) + 1;
+                if early_exits == MAX_EARLY_EXITS {
+                    tracing::error!(
+                        "Server '{}' exited early on {} setups in a row; marking it failed",
+                        self.server_name,
+                        early_exits
+                    );
+                }
+            }
+            Some(_) => {}
+        }
+    }
+
+    /// Whether the server exited early too often to be set up again
+    fn gave_up(&self) -> bool {
+        self.early_exits.load(Ordering::Relaxed) >= MAX_EARLY_EXITS
+    }
+
+    fn given_up(&self) -> McpCoreError {
+        McpCoreError::ConfigurationError {
+            message: format!(
+                "Server '{}' exited right after starting on {} setups in a row; \
+                 fix its command or args",
+                self.server_name,
+                self.early_exits.load(Ordering::Relaxed)
+            ),
+        }
+    }
+
+    /// Failed handshake the server is stuck on, if any
+    pub fn failed_handshake(&self) -> Option<FailedHandshake> {
+        self.failed_handshake
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    fn handshake_error(&self, handshake: FailedHandshake) -> McpCoreError {
+        McpCoreError::HandshakeFailed {
+            message: format!(
+                "Server '{}' is not retried until restarted: {}",
+                self.server_name, handshake.error
+            ),
+            failure: handshake.failure,
+            attempts: handshake.attempts,
+        }
+    }
+
+    /// Restarts since the gateway started
+    pub fn restarts(&self) -> RestartStats {
+        self.restarts
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<Job>> {
+        self.job
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Transport standing in for a server that is not provisioned yet
+#[derive(Debug, Default)]
+pub struct Unprovisioned;
+
+#[async_trait]
+impl McpTransport for Unprovisioned {
+    async fn send(&mut self, _message: &str) -> McpCoreResult<()> {
+        Err(not_provisioned())
+    }
+
+    async fn receive(&mut self) -> McpCoreResult<String> {
+        Err(not_provisioned())
+    }
+
+    async fn shutdown(&mut self) -> McpCoreResult<()> {
+        Ok(())
+    }
+
+    fn is_alive(&mut self) -> bool {
+        false
+    }
+
+    fn buffer_notification(&mut self, _notification: String) {}
+
+    fn drain_notifications(&mut self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+fn not_provisioned() -> McpCoreError {
+    McpCoreError::NotProvisioned {
+        message: "The server is not provisioned".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    fn provisioned() -> Provisioned {
+        Provisioned {
+            protocol_version: "2025-06-18".to_string(),
+            pid: None,
+            stderr: None,
+            startup: PhaseTimings::default(),
+            audit: None,
+            commit: None,
+            package_version: None,
+            artifact_cache: None,
+            sandbox: None,
+            egress: None,
+        }
+    }
+
+    /// Provisioner whose setup takes 50ms and fails the first `failures` times
+    fn provisioner(mode: SetupMode, failures: usize) -> (Arc<Provisioner>, Arc<AtomicUsize>) {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&runs);
+        let pipeline: ProvisionFn = Arc::new(move |mut timer: PhaseTimer, _trigger: Trigger| {
+            let run = counter.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                timer
+                    .measure("clone", tokio::time::sleep(Duration::from_millis(50)))
+                    .await;
+                if run < failures {
+                    return Err(McpCoreError::ProcessError {
+                        message: "clone failed".to_string(),
+                    });
+                }
+                let transport: Box<dyn McpTransport> = Box::new(Unprovisioned);
+                Ok((transport, provisioned()))
+            })
+        });
+        let transport: Box<dyn McpTransport> = Box::new(Unprovisioned);
+        let provisioner = Provisioner::deferred(
+            "echo",
+            mode,
+            Arc::new(tokio::sync::Mutex::new(transport)),
+            pipeline,
+        );
+        (Arc::new(provisioner), runs)
+    }
+
+    #[tokio::test]
+    async fn test_manual_provisioning_is_idempotent() {
+        let (provisioner, runs) = provisioner(SetupMode::Manual, 1);
+        assert_eq!(provisioner.status().state, "not_provisioned");
+        assert!(matches!(
+            provisioner.ensure_ready().await,
+            Err(McpCoreError::NotProvisioned { .. })
+        ));
+
+        // The first job fails; asking again while it runs returns it
+        let (first, started) = provisioner.provision(Trigger::Admin);
+        let first = first.unwrap();
+        assert!(started);
+        let (again, started) = provisioner.provision(Trigger::Admin);
+        assert!(!started);
+        assert_eq!(again.unwrap().id, first.id);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(
+            provisioner.job(&first.id).unwrap().progress.current_phase,
+            Some("clone")
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let status = provisioner.status();
+        assert_eq!(status.state, "failed");
+        assert_eq!(
+            status.job.unwrap().error.as_deref(),
+            Some("Process communication error: clone failed")
+        );
+
+        // After a failure a new job starts and succeeds
+        let (second, started) = provisioner.provision(Trigger::Admin);
+        assert!(started);
+        let second = second.unwrap();
+        assert_ne!(second.id, first.id);
+        assert!(provisioner.job(&first.id).is_none());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(provisioner.status().state, "provisioned");
+        assert!(provisioner.ensure_ready().await.is_ok());
+        let (done, started) = provisioner.provision(Trigger::Admin);
+        assert!(!started);
+        assert_eq!(done.unwrap().state, JobState::Succeeded);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_repeated_early_exits_mark_server_failed() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&runs);
+        let pipeline: ProvisionFn = Arc::new(move |_timer: PhaseTimer, _trigger: Trigger| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async {
+                Err(McpCoreError::ChildExitedEarly {
+                    message: "exit code 1 after 5ms".to_string(),
+                    exit_code: Some(1),
+                    output: vec!["usage: server --stdio".to_string()],
+                })
+            })
+        });
+        let transport: Box<dyn McpTransport> = Box::new(Unprovisioned);
+        let provisioner = Arc::new(Provisioner::deferred(
+            "echo",
+            SetupMode::OnFirstRequest,
+            Arc::new(tokio::sync::Mutex::new(transport)),
+            pipeline,
+        ));
+
+        // Each request provisions again until the limit is reached
+        for _ in 0..MAX_EARLY_EXITS {
+            let error = provisioner.ensure_ready().await.unwrap_err();
+            assert!(error.to_string().contains("exited early"), "{}", error);
+        }
+        let error = provisioner.ensure_ready().await.unwrap_err();
+        assert!(matches!(error, McpCoreError::ConfigurationError { .. }));
+        assert!(error.to_string().contains("3 setups in a row"), "{}", error);
+        assert_eq!(runs.load(Ordering::SeqCst), MAX_EARLY_EXITS as usize);
+        assert_eq!(provisioner.status().state, "failed");
+
+        // A running server is not restarted into the same failure
+        let transport: Box<dyn McpTransport> = Box::new(Unprovisioned);
+        let pipeline = Arc::clone(provisioner.pipeline.as_ref().unwrap());
+        let serving = Provisioner::ready(
+            "echo",
+            Arc::new(tokio::sync::Mutex::new(transport)),
+            provisioned(),
+        )
+        .with_pipeline(pipeline);
+        for _ in 0..MAX_EARLY_EXITS {
+            let error = serving
+                .restart(RestartStrategy::BlueGreen, Trigger::Manager, "manager")
+                .await
+                .unwrap_err();
+            assert!(matches!(error, McpCoreError::ChildExitedEarly { .. }));
+        }
+        assert!(matches!(
+            serving
+                .restart(RestartStrategy::BlueGreen, Trigger::Manager, "manager")
+                .await,
+            Err(McpCoreError::ConfigurationError { .. })
+        ));
+        assert_eq!(runs.load(Ordering::SeqCst), 2 * MAX_EARLY_EXITS as usize);
+        assert_eq!(serving.status().state, "provisioned");
+    }
+
+    #[tokio::test]
+    async fn test_first_requests_share_one_job() {
+        let (provisioner, runs) = provisioner(SetupMode::OnFirstRequest, 0);
+        let waiters: Vec<_> = (0..3)
+            .map(|_| {
+                let provisioner = Arc::clone(&provisioner);
+                tokio::spawn(async move { provisioner.ensure_ready().await })
+            })
+            .collect();
+        for waiter in waiters {
+            assert!(waiter.await.unwrap().is_ok());
+        }
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(provisioner.status().state, "provisioned");
+        let phases = provisioner.status().job.unwrap().progress.phases;
+        assert_eq!(phases[0].phase, "clone");
+    }
+
+    #[tokio::test]
+    async fn test_failed_blue_green_restart_keeps_old_child() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&runs);
+        let pipeline: ProvisionFn = Arc::new(move |_timer: PhaseTimer, _trigger: Trigger| {
+            let run = counter.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                if run == 0 {
+                    return Err(McpCoreError::ProcessError {
+                        message: "spawn failed".to_string(),
+                    });
+                }
+                let transport: Box<dyn McpTransport> = Box::new(Unprovisioned);
+                Ok((
+                    transport,
+                    Provisioned {
+                        pid: Some(2),
+                        ..provisioned()
+                    },
+                ))
+            })
+        });
+        let transport: Box<dyn McpTransport> = Box::new(Unprovisioned);
+        let provisioner = Provisioner::ready(
+            "echo",
+            Arc::new(tokio::sync::Mutex::new(transport)),
+            Provisioned {
+                pid: Some(1),
+                ..provisioned()
+            },
+        );
+        assert!(provisioner
+            .restart(RestartStrategy::BlueGreen, Trigger::Admin, "test")
+            .await
+            .is_err());
+        let provisioner = provisioner.with_pipeline(pipeline);
+
+        let error = provisioner
+            .restart(RestartStrategy::BlueGreen, Trigger::Admin, "test")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Process communication error: spawn failed"
+        );
+        assert_eq!(provisioner.provisioned().unwrap().pid, Some(1));
+        assert_eq!(provisioner.status().state, "provisioned");
+
+        let record = provisioner
+            .restart(RestartStrategy::BlueGreen, Trigger::Admin, "test")
+            .await
+            .unwrap();
+        assert!(record.switchover_ms.is_some());
+        assert_eq!(provisioner.provisioned().unwrap().pid, Some(2));
+        let stats = provisioner.restarts();
+        assert_eq!((stats.restarts, stats.failures), (2, 1));
+        assert_eq!(stats.last.unwrap().strategy, RestartStrategy::BlueGreen);
+    }
+
+    #[tokio::test]
+    async fn test_crash_looping_servers_share_restart_budget() {
+        use crate::restart_budget::RestartBudgetConfig;
+
+        // Ten restarts a second after a burst of three
+        let budget = Arc::new(RestartBudget::new(RestartBudgetConfig {
+            per_minute: 600,
+            burst: 3,
+        }));
+        let spawns = Arc::new(AtomicUsize::new(0));
+        let provisioners: Vec<_> = ["a", "b", "c"]
+            .into_iter()
+            .map(|name| {
+                let counter = Arc::clone(&spawns);
+                let pipeline: ProvisionFn =
+                    Arc::new(move |_timer: PhaseTimer, _trigger: Trigger| {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        Box::pin(async {
+                            let transport: Box<dyn McpTransport> = Box::new(Unprovisioned);
+                            Ok((transport, provisioned()))
+                        })
+                    });
+                let transport: Box<dyn McpTransport> = Box::new(Unprovisioned);
+                let provisioner = Provisioner::ready(
+                    name,
+                    Arc::new(tokio::sync::Mutex::new(transport)),
+                    provisioned(),
+                )
+                .with_pipeline(pipeline)
+                .with_restart_budget(Arc::clone(&budget));
+                Arc::new(provisioner)
+            })
+            .collect();
+
+        // Each child dies as soon as it is up
+        let started = Instant::now();
+        let loops: Vec<_> = provisioners
+            .iter()
+            .map(|provisioner| {
+                let provisioner = Arc::clone(provisioner);
+                tokio::spawn(async move {
+                    loop {
+                        let _ = provisioner
+                            .restart(RestartStrategy::InPlace, Trigger::Admin, "crashed")
+                            .await;
+                    }
+                })
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        let throttled = provisioners
+            .iter()
+            .filter(|provisioner| provisioner.status().restart_throttled)
+            .count();
+        assert!(throttled >= 2, "{} servers throttled", throttled);
+        assert_eq!(provisioners[0].status().state, "provisioned");
+        tokio::time::sleep(Duration::from_millis(350)).await;
+        let spawned = spawns.load(Ordering::SeqCst);
+        let elapsed = started.elapsed();
+        for running in loops {
+            running.abort();
+        }
+
+        let cap = 3 + (elapsed.as_secs_f64() * 10.0) as usize + 1;
+        assert!(spawned <= cap, "{} spawns in {:?}", spawned, elapsed);
+        assert!(spawned >= 6, "{} spawns in {:?}", spawned, elapsed);
+        let snapshot = budget.snapshot();
+        assert!(snapshot.episodes >= 1);
+        assert!(snapshot.throttled > 0);
+    }
+}
@@ -0,0 +1,640 @@
+//! Per-API-key usage quotas
+//!
+//! Requests are counted per API key name against the key's
+//! `requests_per_day`, `requests_per_month`, and `tool_calls_per_day`. Days
+//! and months are UTC calendar periods: counts start from zero at midnight
+//! UTC and on the first of each month. A request over a quota is refused with
+//! `429` naming the quota and when it resets, and crossing 80% of a quota
+//! logs a warning once per period. With `persist_lifecycle` the counts are
+//! saved to the lifecycle file every 30 seconds and at shutdown, so a restart
+//! does not start the day afresh.
+
+use crate::error::{McpCoreError, McpCoreResult};
+use crate::lifecycle::LifecycleFile;
+use chrono::{DateTime, Datelike, Months, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// Share of a quota after which a warning is logged
+pub const WARN_PERCENT: u64 = 80;
+
+/// Interval at which changed counts are written to the lifecycle file
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Limits of one API key; unset limits are not enforced
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct QuotaConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requests_per_day: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requests_per_month: Option<u64>,
+
+    /// `tools/call` requests per day, also counted as requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls_per_day: Option<u64>,
+}
+
+impl QuotaConfig {
+    /// Check that every set limit is positive
+    pub fn validate(&self) -> Result<(), String> {
+        if Quota::ALL.iter().any(|quota| self.limit(*quota) == Some(0)) {
+            return Err("limits must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    fn limit(&self, quota: Quota) -> Option<u64> {
+        match quota {
+            Quota::RequestsPerDay => self.requests_per_day,
+            Quota::RequestsPerMonth => self.requests_per_month,
+            Quota::ToolCallsPerDay => self.tool_calls_per_day,
+        }
+    }
+}
+
+/// A limited count
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Quota {
+    RequestsPerDay,
+    RequestsPerMonth,
+    ToolCallsPerDay,
+}
+
+impl Quota {
+    const ALL: [Quota; 3] = [
+        Quota::RequestsPerDay,
+        Quota::RequestsPerMonth,
+        Quota::ToolCallsPerDay,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Quota::RequestsPerDay => "requests_per_day",
+            Quota::RequestsPerMonth => "requests_per_month",
+            Quota::ToolCallsPerDay => "tool_calls_per_day",
+        }
+    }
+
+    fn period(self) -> Period {
+        match self {
+            Quota::RequestsPerMonth => Period::Month,
+            _ => Period::Day,
+        }
+    }
+}
+
+impl fmt::Display for Quota {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// UTC calendar period a count covers
+#[derive(Debug, Clone, Copy)]
+enum Period {
+    Day,
+    Month,
+}
+
+impl Period {
+    /// Number of the period containing `now`, increasing over time
+    fn index(self, now: DateTime<Utc>) -> u32 {
+        let date = now.date_naive();
+        match self {
+            Period::Day => date.num_days_from_ce() as u32,
+            Period::Month => date.year() as u32 * 12 + date.month0(),
+        }
+    }
+
+    /// First day of the period containing `now`
+    fn start(self, now: DateTime<Utc>) -> NaiveDate {
+        let date = now.date_naive();
+        match self {
+            Period::Day => date,
+            Period::Month => date.with_day(1).expect("every month has a first day"),
+        }
+    }
+
+    /// Start of the period after the one containing `now`
+    fn resets_at(self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let start = self.start(now);
+        let next = match self {
+            Period::Day => start.succ_opt(),
+            Period::Month => start.checked_add_months(Months::new(1)),
+        };
+        next.expect("date within chrono's range")
+            .and_hms_opt(0, 0, 0)
+            .expect("midnight exists")
+            .and_utc()
+    }
+}
+
+/// Count within one period, reset when a later period starts
+///
+/// Without a lock, a request racing the reset at a period boundary may be
+/// counted in the period before.
+#[derive(Debug, Default)]
+struct Counter {
+    period: AtomicU32,
+    count: AtomicU64,
+}
+
+impl Counter {
+    fn current(&self, period: u32) -> u64 {
+        if self.period.load(Ordering::Acquire) == period {
+            self.count.load(Ordering::Acquire)
+        } else {
+            0
+        }
+    }
+
+    /// Count one in `period` unless the count already reached `limit`,
+    /// returning the new count
+    ///
+    /// Checking and counting are one step, so concurrent requests cannot
+    /// all see room for one more.
+    fn try_add(&self, period: u32, limit: Option<u64>) -> Option<u64> {
+        let seen = self.period.load(Ordering::Acquire);
+        if seen != period
+            && self
+                .period
+                .compare_exchange(seen, period, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        {
+            self.count.store(0, Ordering::Release);
+        }
+        self.count
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| match limit {
+                Some(limit) if count >= limit => None,
+                _ => Some(count + 1),
+            })
+            .ok()
+            .map(|count| count + 1)
+    }
+
+    /// Take back one counted in `period`, unless a later period started
+    fn undo(&self, period: u32) {
+        if self.period.load(Ordering::Acquire) == period {
+            let _ = self
+                .count
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                    count.checked_sub(1)
+                });
+        }
+    }
+
+    fn set(&self, period: u32, count: u64) {
+        self.period.store(period, Ordering::Release);
+        self.count.store(count, Ordering::Release);
+    }
+}
+
+/// Counts and limits of one API key
+#[derive(Debug, Default)]
+struct KeyUsage {
+    limits: QuotaConfig,
+    counters: [Counter; 3],
+
+    /// Period for which each quota's warning was logged
+    warned: [AtomicU32; 3],
+}
+
+/// Consumption of one API key, served by `GET /admin/usage`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KeyUsageSnapshot {
+    pub key: String,
+    pub requests_today: u64,
+    pub requests_this_month: u64,
+    pub tool_calls_today: u64,
+    pub limits: QuotaConfig,
+    pub day_resets_at: DateTime<Utc>,
+    pub month_resets_at: DateTime<Utc>,
+}
+
+/// Counts of one API key as saved in the lifecycle file
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct StoredUsage {
+    pub day: NaiveDate,
+    pub requests_today: u64,
+    pub tool_calls_today: u64,
+
+    /// First day of the month counted
+    pub month: NaiveDate,
+    pub requests_this_month: u64,
+}
+
+/// A request counted against its key's quotas, taken back when dropped
+/// unless kept
+///
+/// A request only uses its quotas once it reaches the server: one refused
+/// before that, by the in-flight limit or the request queue, drops its charge.
+#[must_use]
+pub struct QuotaCharge {
+    usage: Arc<KeyUsage>,
+
+    /// Period each counter counted the request in
+    counted: [Option<u32>; 3],
+}
+
+impl QuotaCharge {
+    /// Keep the counts of a request that reached the server
+    pub fn keep(mut self) {
+        self.counted = [None; Quota::ALL.len()];
+    }
+}
+
+impl Drop for QuotaCharge {
+    fn drop(&mut self) {
+        for (counter, counted) in self.usage.counters.iter().zip(self.counted) {
+            if let Some(period) = counted {
+                counter.undo(period);
+            }
+        }
+    }
+}
+
+/// Usage of every API key that sent requests or has limits
+#[derive(Debug, Default)]
+pub struct Quotas {
+    server_name: String,
+    keys: RwLock<HashMap<String, Arc<KeyUsage>>>,
+
+    /// Lifecycle file the counts are saved in, if any
+    file: Option<LifecycleFile>,
+
+    /// Whether counts changed since they were last saved
+    dirty: AtomicBool,
+}
+
+impl Quotas {
+    /// Quotas of `limits`, keyed by API key name
+    pub fn new(
+        server_name: &str,
+        limits: &HashMap<String, QuotaConfig>,
+        file: Option<LifecycleFile>,
+    ) -> Self {
+        let keys = limits
+            .iter()
+            .map(|(key, limits)| {
+                let usage = KeyUsage {
+                    limits: limits.clone(),
+                    ..KeyUsage::default()
+                };
+                (key.clone(), Arc::new(usage))
+            })
+            .collect();
+        Self {
+            server_name: server_name.to_string(),
+            keys: RwLock::new(keys),
+            file,
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// Count a request of `key` at `now`, or refuse it if a quota is used up
+    ///
+    /// `tools/call` requests also count against `tool_calls_per_day`.
+    pub fn admit(&self, key: &str, method: Option<&str>, now: DateTime<Utc>) -> McpCoreResult<()> {
+        self.charge(key, method, now).map(QuotaCharge::keep)
+    }
+
+    /// Count a request of `key` at `now` until the returned charge is
+    /// dropped, or refuse it if a quota is used up
+    pub fn charge(
+        &self,
+        key: &str,
+        method: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> McpCoreResult<QuotaCharge> {
+        let usage = self.usage(key);
+        let tool_call = method == Some("tools/call");
+        let applies = |quota: &Quota| *quota != Quota::ToolCallsPerDay || tool_call;
+
+        // Count against each quota while below its limit; a refused request
+        // drops its charge, taking back what it counted
+        let mut charge = QuotaCharge {
+            usage: Arc::clone(&usage),
+            counted: [None; Quota::ALL.len()],
+        };
+        let mut counts = [0; Quota::ALL.len()];
+        for (index, quota) in Quota::ALL.iter().enumerate().filter(|(_, q)| applies(q)) {
+            let limit = usage.limits.limit(*quota);
+            let period = quota.period().index(now);
+            match usage.counters[index].try_add(period, limit) {
+                Some(count) => {
+                    charge.counted[index] = Some(period);
+                    counts[index] = count;
+                }
+                None => {
+                    let limit = limit.unwrap_or_default();
+                    let resets_at = quota.period().resets_at(now);
+                    return Err(McpCoreError::QuotaExceeded {
+                        message: format!(
+                            "API key '{}' used its {} quota of {}; it resets at {}",
+                            key,
+                            quota,
+                            limit,
+                            resets_at.to_rfc3339()
+                        ),
+                        quota: quota.as_str(),
+                        resets_at,
+                        retry_after_secs: (resets_at - now).num_seconds().max(1) as u64,
+                    });
+                }
+            }
+        }
+
+        for (index, quota) in Quota::ALL.iter().enumerate() {
+            let (Some(period), Some(limit)) = (charge.counted[index], usage.limits.limit(*quota))
+            else {
+                continue;
+            };
+            let count = counts[index];
+            if count * 100 >= limit * WARN_PERCENT
+                && usage.warned[index].swap(period, Ordering::AcqRel) != period
+            {
+                tracing::warn!(
+                    "API key '{}' used {} of its {} quota of {} on server '{}'",
+                    key,
+                    count,
+                    quota,
+                    limit,
+                    self.server_name
+                );
+            }
+        }
+        self.dirty.store(true, Ordering::Release);
+        Ok(charge)
+    }
+
+    /// Consumption of every key as of `now`, by key name
+    pub fn snapshot(&self, now: DateTime<Utc>) -> Vec<KeyUsageSnapshot> {
+        let (day, month) = (Period::Day.index(now), Period::Month.index(now));
+        let mut keys: Vec<KeyUsageSnapshot> = self
+            .read()
+            .iter()
+            .map(|(key, usage)| KeyUsageSnapshot {
+                key: key.clone(),
+                requests_today: usage.counters[0].current(day),
+                requests_this_month: usage.counters[1].current(month),
+                tool_calls_today: usage.counters[2].current(day),
+                limits: usage.limits.clone(),
+                day_resets_at: Period::Day.resets_at(now),
+                month_resets_at: Period::Month.resets_at(now),
+            })
+            .collect();
+        keys.sort_by(|a, b| a.key.cmp(&b.key));
+        keys
+    }
+
+    /// Counts to save, as of `now`
+    pub fn stored(&self, now: DateTime<Utc>) -> BTreeMap<String, StoredUsage> {
+        self.snapshot(now)
+            .into_iter()
+            .map(|usage| {
+                let stored = StoredUsage {
+                    day: Period::Day.start(now),
+                    requests_today: usage.requests_today,
+                    tool_calls_today: usage.tool_calls_today,
+                    month: Period::Month.start(now),
+                    requests_this_month: usage.requests_this_month,
+                };
+                (usage.key, stored)
+            })
+            .collect()
+    }
+
+    /// Resume counting from saved counts; counts of past periods are dropped
+    pub fn restore(&self, stored: &BTreeMap<String, StoredUsage>, now: DateTime<Utc>) {
+        for (key, stored) in stored {
+            let usage = self.usage(key);
+            if stored.day == Period::Day.start(now) {
+                let day = Period::Day.index(now);
+                usage.counters[0].set(day, stored.requests_today);
+                usage.counters[2].set(day, stored.tool_calls_today);
+            }
+            if stored.month == Period::Month.start(now) {
+                usage.counters[1].set(Period::Month.index(now), stored.requests_this_month);
+            }
+        }
+    }
+
+    /// Save the counts to the lifecycle file if they changed
+    pub async fn flush(&self) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return;
+        }
+        let mut state = file.load(&self.server_name).await;
+        state.usage = self.stored(Utc::now());
+        if let Err(e) = file.save(&state).await {
+            tracing::warn!("{}", e);
+        }
+    }
+
+    /// Save the counts every [`FLUSH_INTERVAL`] until the quotas are dropped
+    pub fn spawn_flusher(self: &Arc<Self>) -> JoinHandle<()> {
+        let quotas = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let Some(quotas) = quotas.upgrade() else {
+                    return;
+                };
+                quotas.flush().await;
+            }
+        })
+    }
+
+    fn usage(&self, key: &str) -> Arc<KeyUsage> {
+        if let Some(usage) = self.read().get(key) {
+            return Arc::clone(usage);
+        }
+        let mut keys = self.keys.write().unwrap_or_else(|e| e.into_inner());
+        Arc::clone(keys.entry(key.to_string()).or_default())
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HashMap<String, Arc<KeyUsage>>> {
+        self.keys.read().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::CapturedLogs;
+    use chrono::TimeZone;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, min: u32, sec: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, min, sec)
+            .unwrap()
+    }
+
+    fn quotas(limits: QuotaConfig) -> Quotas {
+        Quotas::new("echo", &HashMap::from([("team".to_string(), limits)]), None)
+    }
+
+    #[test]
+    fn test_daily_quota_resets_at_utc_midnight() {
+        let quotas = quotas(QuotaConfig {
+            requests_per_day: Some(2),
+            requests_per_month: Some(3),
+            ..QuotaConfig::default()
+        });
+        let before_midnight = at(2025, 1, 31, 23, 59, 59);
+        quotas.admit("team", None, before_midnight).unwrap();
+        quotas.admit("team", None, before_midnight).unwrap();
+
+        let error = quotas.admit("team", None, before_midnight).unwrap_err();
+        let McpCoreError::QuotaExceeded {
+            quota,
+            resets_at,
+            retry_after_secs,
+            ..
+        } = error
+        else {
+            panic!("unexpected error: {}", error);
+        };
+        assert_eq!(quota, "requests_per_day");
+        assert_eq!(resets_at, at(2025, 2, 1, 0, 0, 0));
+        assert_eq!(retry_after_secs, 1);
+
+        // Midnight starts a new day, and here also a new month
+        let midnight = at(2025, 2, 1, 0, 0, 0);
+        quotas.admit("team", None, midnight).unwrap();
+        let usage = &quotas.snapshot(midnight)[0];
+        assert_eq!((usage.requests_today, usage.requests_this_month), (1, 1));
+
+        // The monthly quota carries over days within the month
+        let next_day = at(2025, 2, 2, 0, 0, 0);
+        quotas.admit("team", None, next_day).unwrap();
+        quotas.admit("team", None, next_day).unwrap();
+        let later = at(2025, 2, 3, 12, 0, 0);
+        let error = quotas.admit("team", None, later).unwrap_err();
+        assert!(error.to_string().contains("requests_per_month quota of 3"));
+        assert!(error.to_string().contains("2025-03-01T00:00:00+00:00"));
+    }
+
+    #[test]
+    fn test_tool_calls_counted_and_warned_once() {
+        let (logs, _guard) = CapturedLogs::install();
+        let quotas = quotas(QuotaConfig {
+            tool_calls_per_day: Some(5),
+            ..QuotaConfig::default()
+        });
+        let now = at(2025, 6, 1, 12, 0, 0);
+        for _ in 0..10 {
+            quotas.admit("team", Some("tools/list"), now).unwrap();
+        }
+        for _ in 0..5 {
+            quotas.admit("team", Some("tools/call"), now).unwrap();
+        }
+        assert!(quotas.admit("team", Some("tools/call"), now).is_err());
+        quotas.admit("team", Some("ping"), now).unwrap();
+
+        // Keys without limits are counted too
+        quotas.admit("other", None, now).unwrap();
+        let usage = quotas.snapshot(now);
+        assert_eq!(usage[0].key, "other");
+        assert_eq!(usage[1].requests_today, 16);
+        assert_eq!(usage[1].tool_calls_today, 5);
+
+        let contents = logs.contents();
+        assert_eq!(contents.matches("tool_calls_per_day quota").count(), 1);
+        assert!(contents.contains("used 4 of its tool_calls_per_day quota of 5"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_requests_never_exceed_the_quota() {
+        let quotas = Arc::new(quotas(QuotaConfig {
+            requests_per_day: Some(100),
+            tool_calls_per_day: Some(50),
+            ..QuotaConfig::default()
+        }));
+        let now = at(2025, 6, 1, 12, 0, 0);
+        let barrier = Arc::new(tokio::sync::Barrier::new(100));
+        let tasks: Vec<_> = (0..100)
+            .map(|_| {
+                let (quotas, barrier) = (Arc::clone(&quotas), Arc::clone(&barrier));
+                tokio::spawn(async move {
+                    barrier.wait().await;
+                    quotas.admit("team", Some("tools/call"), now).is_ok()
+                })
+            })
+            .collect();
+        let mut admitted = 0;
+        for task in tasks {
+            admitted += usize::from(task.await.unwrap());
+        }
+        assert_eq!(admitted, 50);
+
+        // Refused tool calls took back their request counts
+        let usage = &quotas.snapshot(now)[0];
+        assert_eq!((usage.requests_today, usage.tool_calls_today), (50, 50));
+    }
+
+    #[test]
+    fn test_dropped_charge_is_taken_back() {
+        let quotas = quotas(QuotaConfig {
+            requests_per_day: Some(1),
+            ..QuotaConfig::default()
+        });
+        let now = at(2025, 6, 1, 12, 0, 0);
+        let charge = quotas.charge("team", Some("tools/call"), now).unwrap();
+        assert!(quotas.charge("team", None, now).is_err());
+        drop(charge);
+        let usage = &quotas.snapshot(now)[0];
+        assert_eq!((usage.requests_today, usage.tool_calls_today), (0, 0));
+
+        quotas.charge("team", None, now).unwrap().keep();
+        assert!(quotas.admit("team", None, now).is_err());
+        assert_eq!(quotas.snapshot(now)[0].requests_today, 1);
+    }
+
+    #[tokio::test]
+    async fn test_usage_survives_restart_within_period() {
+        let dir = std::env::temp_dir().join(format!("mcp-quota-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let file = LifecycleFile::new(Some(&dir), std::path::Path::new("/nonexistent"), "echo");
+        let limits = HashMap::from([(
+            "team".to_string(),
+            QuotaConfig {
+                requests_per_day: Some(10),
+                ..QuotaConfig::default()
+            },
+        )]);
+
+        let quotas = Quotas::new("echo", &limits, Some(file.clone()));
+        quotas.admit("team", None, Utc::now()).unwrap();
+        quotas.admit("team", None, Utc::now()).unwrap();
+        quotas.flush().await;
+
+        let stored = file.load("echo").await.usage;
+        assert_eq!(stored["team"].requests_today, 2);
+        let restarted = Quotas::new("echo", &limits, None);
+        restarted.restore(&stored, Utc::now());
+        assert_eq!(restarted.snapshot(Utc::now())[0].requests_today, 2);
+
+        // Counts of an earlier day start over; the month carries on
+        let mut yesterday = stored.clone();
+        let entry = yesterday.get_mut("team").unwrap();
+        entry.day = entry.day.pred_opt().unwrap();
+        entry.month = Period::Month.start(Utc::now());
+        let restarted = Quotas::new("echo", &limits, None);
+        restarted.restore(&yesterday, Utc::now());
+        let usage = &restarted.snapshot(Utc::now())[0];
+        assert_eq!((usage.requests_today, usage.requests_this_month), (0, 2));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,508 @@
+//! Recycling a server's child before it wears out
+//!
+//! A server's `recycle` policy restarts its child blue-green when the child
+//! uses more than `max_rss_mb` of resident memory, has been sent
+//! `max_requests`, has run for `max_uptime`, or when the cron `schedule`
+//! fires in `timezone` (UTC unless set). The thresholds are checked every few
+//! seconds. A recycle less than `min_interval` after the previous one is
+//! skipped, so a child that comes back over a threshold, or a replacement
+//! that keeps failing to start, cannot cause a storm of restarts. Memory is
+//! read from `/proc` and only checked on Linux.
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use croner::Cron;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+use crate::history::Trigger;
+use crate::provision::{Provisioner, RestartStrategy};
+
+/// How often the thresholds are checked
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Least time between recycles unless `min_interval` is set
+pub const DEFAULT_MIN_INTERVAL: Duration = Duration::from_secs(600);
+
+/// When a server's child is recycled
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RecycleConfig {
+    /// Resident memory of the child, in MiB
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_rss_mb: Option<u64>,
+
+    /// Requests sent to the child
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_requests: Option<u64>,
+
+    /// Time the child has run, e.g. `24h`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_uptime: Option<String>,
+
+    /// Cron expression, e.g. `0 4 * * *`; a leading seconds field is allowed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<String>,
+
+    /// IANA time zone the schedule is read in, e.g. `Europe/Berlin`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+
+    /// Least time between recycles (default `10m`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_interval: Option<String>,
+}
+
+impl RecycleConfig {
+    /// Check the thresholds, schedule, and time zone
+    pub fn validate(&self) -> Result<(), String> {
+        RecyclePolicy::new(self).map(|_| ())
+    }
+}
+
+/// Parse a duration such as `30s`, `15m`, `24h`, or `7d`
+fn parse_duration(field: &str, text: &str) -> Result<Duration, String> {
+    let invalid = || {
+        format!(
+            "recycle {} '{}' is not a duration like 30s, 15m, 24h, 7d",
+            field, text
+        )
+    };
+    let split = text.len().checked_sub(1).ok_or_else(invalid)?;
+    let (amount, unit) = text.split_at(split);
+    let amount: u64 = amount.parse().map_err(|_| invalid())?;
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    if amount == 0 {
+        return Err(format!("recycle {} must be positive", field));
+    }
+    Ok(Duration::from_secs(amount.saturating_mul(unit)))
+}
+
+/// A validated recycling policy
+#[derive(Debug, Clone)]
+pub struct RecyclePolicy {
+    max_rss_mb: Option<u64>,
+    max_requests: Option<u64>,
+    max_uptime: Option<Duration>,
+    schedule: Option<(Cron, Tz)>,
+    min_interval: Duration,
+}
+
+impl RecyclePolicy {
+    pub fn new(config: &RecycleConfig) -> Result<Self, String> {
+        if config.max_rss_mb == Some(0) || config.max_requests == Some(0) {
+            return Err("recycle max_rss_mb and max_requests must be positive".to_string());
+        }
+        let max_uptime = config
+            .max_uptime
+            .as_deref()
+            .map(|text| parse_duration("max_uptime", text))
+            .transpose()?;
+        let min_interval = match &config.min_interval {
+            Some(text) => parse_duration("min_interval", text)?,
+            None => DEFAULT_MIN_INTERVAL,
+        };
+        let timezone = match &config.timezone {
+            Some(name) => name
+                .parse::<Tz>()
+                .map_err(|_| format!("recycle timezone '{}' is not an IANA time zone", name))?,
+            None => Tz::UTC,
+        };
+        let schedule = match &config.schedule {
+            Some(expression) => {
+                let cron = Cron::new(expression)
+                    .with_seconds_optional()
+                    .parse()
+                    .map_err(|e| format!("recycle schedule '{}' is invalid: {}", expression, e))?;
+                Some((cron, timezone))
+            }
+            None if config.timezone.is_some() => {
+                return Err("recycle timezone requires a schedule".to_string())
+            }
+            None => None,
+        };
+        if config.max_rss_mb.is_none()
+            && config.max_requests.is_none()
+            && max_uptime.is_none()
+            && schedule.is_none()
+        {
+            return Err(
+                "recycle sets none of max_rss_mb, max_requests, max_uptime, schedule".to_string(),
+            );
+        }
+        Ok(Self {
+            max_rss_mb: config.max_rss_mb,
+            max_requests: config.max_requests,
+            max_uptime,
+            schedule,
+            min_interval,
+        })
+    }
+
+    /// The first time the schedule fires after `after`
+    fn next_scheduled(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let (cron, timezone) = self.schedule.as_ref()?;
+        cron.find_next_occurrence(&after.with_timezone(timezone), false)
+            .ok()
+            .map(|next| next.with_timezone(&Utc))
+    }
+}
+
+/// Threshold that asked for a recycle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecycleReason {
+    MaxRss { rss_mb: u64, limit: u64 },
+    MaxRequests { requests: u64, limit: u64 },
+    MaxUptime { uptime_secs: u64, limit_secs: u64 },
+    Schedule { at: DateTime<Utc> },
+}
+
+impl fmt::Display for RecycleReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MaxRss { rss_mb, limit } => {
+                write!(f, "recycle: rss {}MB reached max_rss_mb {}", rss_mb, limit)
+            }
+            Self::MaxRequests { requests, limit } => {
+                write!(
+                    f,
+                    "recycle: {} requests reached max_requests {}",
+                    requests, limit
+                )
+            }
+            Self::MaxUptime {
+                uptime_secs,
+                limit_secs,
+            } => write!(
+                f,
+                "recycle: uptime {}s reached max_uptime {}s",
+                uptime_secs, limit_secs
+            ),
+            Self::Schedule { at } => write!(f, "recycle: scheduled at {}", at.to_rfc3339()),
+        }
+    }
+}
+
+/// Recycles since the gateway started
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RecycleStats {
+    pub recycles: u64,
+
+    /// Recycles refused because the previous one was too recent
+    pub skipped: u64,
+    pub last_reason: Option<String>,
+    pub last_at: Option<DateTime<Utc>>,
+}
+
+/// Current readings against the policy, for the stats endpoints
+#[derive(Debug, Clone, Serialize)]
+pub struct RecycleSnapshot {
+    #[serde(flatten)]
+    pub stats: RecycleStats,
+    pub rss_mb: Option<u64>,
+    pub requests: u64,
+    pub uptime_secs: Option<u64>,
+    pub next_scheduled: Option<DateTime<Utc>>,
+}
+
+/// Applies a recycling policy to the server's child
+#[derive(Debug)]
+pub struct Recycler {
+    policy: RecyclePolicy,
+    provisioner: Arc<Provisioner>,
+    next_scheduled: Mutex<Option<DateTime<Utc>>>,
+    last_recycle: Mutex<Option<Instant>>,
+    stats: Mutex<RecycleStats>,
+}
+
+impl Recycler {
+    pub fn new(policy: RecyclePolicy, provisioner: Arc<Provisioner>) -> Self {
+        let next_scheduled = policy.next_scheduled(Utc::now());
+        Self {
+            policy,
+            provisioner,
+            next_scheduled: Mutex::new(next_scheduled),
+            last_recycle: Mutex::new(None),
+            stats: Mutex::new(RecycleStats::default()),
+        }
+    }
+
+    /// Resident memory of the serving child
+    fn rss_mb(&self) -> Option<u64> {
+        rss_mb(self.provisioner.provisioned()?.pid?)
+    }
+
+    /// The first threshold the serving child reached at `now`, if any
+    ///
+    /// A schedule that fired moves on to its next time.
+    pub fn check(&self, now: DateTime<Utc>) -> Option<RecycleReason> {
+        self.provisioner.provisioned()?;
+        let mut next_scheduled = lock(&self.next_scheduled);
+        if let Some(at) = next_scheduled.filter(|at| *at <= now) {
+            *next_scheduled = self.policy.next_scheduled(now);
+            return Some(RecycleReason::Schedule { at });
+        }
+        drop(next_scheduled);
+
+        if let Some(limit) = self.policy.max_rss_mb {
+            if let Some(rss_mb) = self.rss_mb().filter(|rss_mb| *rss_mb >= limit) {
+                return Some(RecycleReason::MaxRss { rss_mb, limit });
+            }
+        }
+        if let Some(limit) = self.policy.max_requests {
+            let requests = self.provisioner.served();
+            if requests >= limit {
+                return Some(RecycleReason::MaxRequests { requests, limit });
+            }
+        }
+        if let Some(limit) = self.policy.max_uptime {
+            let uptime = self.provisioner.uptime().unwrap_or_default();
+            if uptime >= limit {
+                return Some(RecycleReason::MaxUptime {
+                    uptime_secs: uptime.as_secs(),
+                    limit_secs: limit.as_secs(),
+                });
+            }
+        }
+        None
+    }
+
+    /// Check the thresholds and recycle the child if one was reached
+    ///
+    /// Returns the reason of a recycle that was attempted.
+    pub async fn run_once(&self) -> Option<RecycleReason> {
+        let reason = self.check(Utc::now())?;
+        {
+            let mut last_recycle = lock(&self.last_recycle);
+            if last_recycle.is_some_and(|last| last.elapsed() < self.policy.min_interval) {
+                tracing::debug!("Skipping {}: the previous recycle was too recent", reason);
+                lock(&self.stats).skipped += 1;
+                return None;
+            }
+            *last_recycle = Some(Instant::now());
+        }
+
+        tracing::warn!("Server child reached a threshold ({})", reason);
+        match self
+            .provisioner
+            .restart(
+                RestartStrategy::BlueGreen,
+                Trigger::Recycle,
+                &reason.to_string(),
+            )
+            .await
+        {
+            Ok(_) => {
+                let mut stats = lock(&self.stats);
+                stats.recycles += 1;
+                stats.last_reason = Some(reason.to_string());
+                stats.last_at = Some(Utc::now());
+            }
+            Err(e) => tracing::error!("Recycling failed, keeping the current child: {}", e),
+        }
+        Some(reason)
+    }
+
+    /// Check the thresholds every `interval` until the task is aborted
+    pub fn spawn(self: Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                self.run_once().await;
+            }
+        })
+    }
+
+    pub fn snapshot(&self) -> RecycleSnapshot {
+        RecycleSnapshot {
+            stats: lock(&self.stats).clone(),
+            rss_mb: self.rss_mb(),
+            requests: self.provisioner.served(),
+            uptime_secs: self.provisioner.uptime().map(|uptime| uptime.as_secs()),
+            next_scheduled: *lock(&self.next_scheduled),
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Resident memory of process `pid` in MiB
+#[cfg(target_os = "linux")]
+fn rss_mb(pid: u32) -> Option<u64> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let kib: u64 = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kib / 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn rss_mb(_pid: u32) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::McpCoreError;
+    use crate::provision::{ProvisionFn, Provisioned, Unprovisioned};
+    use crate::timing::{PhaseTimer, PhaseTimings};
+    use crate::transport::McpTransport;
+
+    fn config(json: serde_json::Value) -> RecycleConfig {
+        serde_json::from_value(json).unwrap()
+    }
+
+    /// Recycler over a server whose restarts always succeed
+    fn recycler(json: serde_json::Value) -> Recycler {
+        let provisioned = || Provisioned {
+            protocol_version: "2025-06-18".to_string(),
+            pid: Some(std::process::id()),
+            stderr: None,
+            startup: PhaseTimings::default(),
+            audit: None,
+            commit: None,
+            package_version: None,
+            artifact_cache: None,
+            sandbox: None,
+            egress: None,
+        };
+        let pipeline: ProvisionFn = Arc::new(move |_timer: PhaseTimer, _trigger: Trigger| {
+            Box::pin(async move {
+                let transport: Box<dyn McpTransport> = Box::new(Unprovisioned);
+                Ok::<_, McpCoreError>((transport, provisioned()))
+            })
+        });
+        let transport: Box<dyn McpTransport> = Box::new(Unprovisioned);
+        let provisioner = Provisioner::ready(
+            "echo",
+            Arc::new(tokio::sync::Mutex::new(transport)),
+            provisioned(),
+        )
+        .with_pipeline(pipeline);
+        let policy = RecyclePolicy::new(&config(json)).unwrap();
+        Recycler::new(policy, Arc::new(provisioner))
+    }
+
+    #[test]
+    fn test_policy_validation() {
+        let error = |json| RecyclePolicy::new(&config(json)).unwrap_err();
+        assert!(error(serde_json::json!({})).contains("sets none of"));
+        assert!(error(serde_json::json!({ "max_requests": 0 })).contains("positive"));
+        assert!(error(serde_json::json!({ "max_uptime": "1w" })).contains("not a duration"));
+        assert!(error(serde_json::json!({ "max_uptime": "0h" })).contains("positive"));
+        assert!(error(serde_json::json!({ "schedule": "0 25 * * *" })).contains("invalid"));
+        assert!(
+            error(serde_json::json!({ "schedule": "0 4 * * *", "timezone": "Mars/Olympus" }))
+                .contains("IANA")
+        );
+        assert!(
+            error(serde_json::json!({ "max_requests": 1, "timezone": "UTC" }))
+                .contains("requires a schedule")
+        );
+
+        let policy = RecyclePolicy::new(&config(serde_json::json!({
+            "schedule": "0 4 * * *",
+            "timezone": "Asia/Tokyo",
+            "min_interval": "1h",
+        })))
+        .unwrap();
+        assert_eq!(policy.min_interval, Duration::from_secs(3600));
+        // 04:00 in Tokyo is 19:00 UTC the day before
+        let after = DateTime::parse_from_rfc3339("2026-03-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            policy.next_scheduled(after).unwrap().to_rfc3339(),
+            "2026-03-01T19:00:00+00:00"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_each_threshold_triggers() {
+        let requests = recycler(serde_json::json!({ "max_requests": 2 }));
+        assert_eq!(requests.check(Utc::now()), None);
+        requests.provisioner.record_request();
+        requests.provisioner.record_request();
+        assert_eq!(
+            requests.check(Utc::now()),
+            Some(RecycleReason::MaxRequests {
+                requests: 2,
+                limit: 2
+            })
+        );
+
+        let uptime = recycler(serde_json::json!({ "max_uptime": "1s" }));
+        assert_eq!(uptime.check(Utc::now()), None);
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert!(matches!(
+            uptime.check(Utc::now()),
+            Some(RecycleReason::MaxUptime { limit_secs: 1, .. })
+        ));
+
+        // Every second; the schedule moves on once it fired
+        let schedule = recycler(serde_json::json!({ "schedule": "* * * * * *" }));
+        let at = schedule.snapshot().next_scheduled.unwrap();
+        assert_eq!(schedule.check(at - chrono::Duration::milliseconds(1)), None);
+        assert_eq!(schedule.check(at), Some(RecycleReason::Schedule { at }));
+        assert!(schedule.snapshot().next_scheduled.unwrap() > at);
+        assert_eq!(schedule.check(at), None);
+
+        if cfg!(target_os = "linux") {
+            let memory = recycler(serde_json::json!({ "max_rss_mb": 1 }));
+            assert!(memory.snapshot().rss_mb.unwrap() >= 1);
+            assert!(matches!(
+                memory.check(Utc::now()),
+                Some(RecycleReason::MaxRss { limit: 1, .. })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_min_interval_prevents_recycle_storm() {
+        let recycler = recycler(serde_json::json!({ "max_requests": 1 }));
+        assert_eq!(recycler.run_once().await, None);
+
+        recycler.provisioner.record_request();
+        let reason = recycler.run_once().await.unwrap();
+        assert_eq!(
+            reason.to_string(),
+            "recycle: 1 requests reached max_requests 1"
+        );
+        let restarts = recycler.provisioner.restarts();
+        assert_eq!(restarts.restarts, 1);
+        let last = restarts.last.unwrap();
+        assert_eq!(last.strategy, RestartStrategy::BlueGreen);
+        assert_eq!(last.reason, reason.to_string());
+        // The new child starts counting from zero
+        assert_eq!(recycler.provisioner.served(), 0);
+
+        // The threshold is reached again at once, but too soon to recycle
+        recycler.provisioner.record_request();
+        assert_eq!(recycler.run_once().await, None);
+        let snapshot = recycler.snapshot();
+        assert_eq!((snapshot.stats.recycles, snapshot.stats.skipped), (1, 1));
+        assert_eq!(snapshot.requests, 1);
+        assert_eq!(recycler.provisioner.restarts().restarts, 1);
+    }
+}
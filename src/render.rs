@@ -0,0 +1,311 @@
+//! Alternative renderings of MCP responses selected by the `Accept` header
+//!
+//! JSON stays the default. `text/plain` returns the concatenated text of a
+//! result made only of text content blocks, and `application/x-ndjson` emits
+//! one line per content block or list item. Responses that cannot be
+//! rendered in the requested format fall back to JSON, and the
+//! `Content-Type` header always names the format actually returned.
+//!
+//! Clients that need the server's exact bytes ask for raw output with the
+//! `X-MCP-Raw` header or `?raw=true`; the response line is then returned
+//! verbatim instead of being parsed, transformed, and re-serialized. With
+//! `X-MCP-Stream-Response` the raw line is also passed on while it is still
+//! being read, see [`crate::response_stream`].
+
+use crate::error::{McpCoreError, McpCoreResult};
+use crate::process::McpResponse;
+use crate::response_stream::ResponseChunks;
+use axum::{
+    http::{header, HeaderMap, HeaderValue},
+    response::{IntoResponse, Json, Response},
+};
+use serde_json::Value;
+
+/// Content type for newline-delimited JSON
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// Header asking for the MCP server's response line verbatim
+pub const RAW_HEADER: &str = "x-mcp-raw";
+
+/// Header asking for the raw response line as it is read
+pub const STREAM_HEADER: &str = "x-mcp-stream-response";
+
+/// Result fields holding the items of MCP list methods
+const LIST_FIELDS: &[&str] = &[
+    "tools",
+    "resources",
+    "resourceTemplates",
+    "prompts",
+    "roots",
+];
+
+/// Rendering requested by the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseFormat {
+    Json,
+    Text,
+    Ndjson,
+}
+
+impl ResponseFormat {
+    /// Pick the format from the `Accept` header
+    ///
+    /// Media ranges are tried in order of their `q` value; unknown or missing
+    /// values select JSON.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let Some(accept) = headers
+            .get(header::ACCEPT)
+            .and_then(|value| value.to_str().ok())
+        else {
+            return Self::Json;
+        };
+
+        let mut ranges: Vec<(&str, f32)> = accept
+            .split(',')
+            .map(|range| {
+                let mut parts = range.split(';').map(str::trim);
+                let media_type = parts.next().unwrap_or_default();
+                let quality = parts
+                    .find_map(|param| param.strip_prefix("q="))
+                    .and_then(|q| q.parse().ok())
+                    .unwrap_or(1.0);
+                (media_type, quality)
+            })
+            .filter(|(_, quality)| *quality > 0.0)
+            .collect();
+        // Stable sort keeps header order among equal qualities
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        ranges
+            .iter()
+            .find_map(
+                |(media_type, _)| match media_type.to_ascii_lowercase().as_str() {
+                    "application/json" => Some(Self::Json),
+                    "text/plain" => Some(Self::Text),
+                    NDJSON_CONTENT_TYPE => Some(Self::Ndjson),
+                    _ => None,
+                },
+            )
+            .unwrap_or(Self::Json)
+    }
+}
+
+/// Render a response in the requested format
+pub fn render(format: ResponseFormat, response: McpResponse) -> Response {
+    let message = serde_json::from_str::<Value>(&response.result).ok();
+    let rendered = match (format, &message) {
+        (ResponseFormat::Text, Some(message)) => render_text(message).map(|text| {
+            (
+                [(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static("text/plain; charset=utf-8"),
+                )],
+                text,
+            )
+                .into_response()
+        }),
+        (ResponseFormat::Ndjson, Some(message)) => Some(
+            (
+                [(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static(NDJSON_CONTENT_TYPE),
+                )],
+                render_ndjson(message),
+            )
+                .into_response(),
+        ),
+        _ => None,
+    };
+
+    rendered.unwrap_or_else(|| Json(response).into_response())
+}
+
+/// Whether the client asked for raw output, by header or query parameter
+pub fn raw_requested(headers: &HeaderMap, query: &[(String, String)]) -> bool {
+    let header = headers
+        .get(RAW_HEADER)
+        .and_then(|value| value.to_str().ok());
+    let param = query
+        .iter()
+        .find(|(name, _)| name == "raw")
+        .map(|(_, value)| value.as_str());
+    header
+        .or(param)
+        .is_some_and(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+}
+
+/// Whether the client asked for the response to be streamed
+pub fn stream_requested(headers: &HeaderMap) -> bool {
+    headers
+        .get(STREAM_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+}
+
+/// Body passing on the chunks of a streamed response line
+///
+/// Nothing is checked, as the line is not complete when the response
+/// starts; a chunk failing ends the body with an error.
+pub fn render_streamed(chunks: ResponseChunks) -> Response {
+    let body = futures_util::stream::unfold(chunks, |mut chunks| async move {
+        let chunk = chunks.recv().await?;
+        Some((chunk, chunks))
+    });
+    (
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        )],
+        axum::body::Body::from_stream(body),
+    )
+        .into_response()
+}
+
+/// The response line exactly as the MCP server wrote it
+///
+/// The line is parsed only to check that it is JSON, so the body can be
+/// labelled `application/json`; it is never re-serialized.
+pub fn render_raw(response: McpResponse) -> McpCoreResult<Response> {
+    if let Err(e) = serde_json::from_str::<serde::de::IgnoredAny>(&response.result) {
+        return Err(McpCoreError::ProcessError {
+            message: format!("MCP server response is not valid JSON: {}", e),
+        });
+    }
+    Ok((
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        )],
+        response.result,
+    )
+        .into_response())
+}
+
+/// Concatenated text of a result made only of text content blocks
+pub(crate) fn render_text(message: &Value) -> Option<String> {
+    let blocks = message.pointer("/result/content")?.as_array()?;
+    let texts: Option<Vec<&str>> = blocks
+        .iter()
+        .map(|block| match block.get("type").and_then(Value::as_str) {
+            Some("text") => block.get("text").and_then(Value::as_str),
+            _ => None,
+        })
+        .collect();
+    Some(texts?.join("\n"))
+}
+
+/// One JSON line per content block or list item, else the whole message
+fn render_ndjson(message: &Value) -> String {
+    let result = message.get("result");
+    let items = result
+        .and_then(|result| result.get("content"))
+        .or_else(|| {
+            LIST_FIELDS
+                .iter()
+                .find_map(|field| result.and_then(|result| result.get(*field)))
+        })
+        .and_then(Value::as_array);
+
+    match items {
+        Some(items) => items.iter().map(|item| format!("{}\n", item)).collect(),
+        None => format!("{}\n", message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept(value: &str) -> ResponseFormat {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_str(value).unwrap());
+        ResponseFormat::from_headers(&headers)
+    }
+
+    fn tool_result(content: Value) -> Value {
+        serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": { "content": content } })
+    }
+
+    #[test]
+    fn test_format_from_accept() {
+        assert_eq!(
+            ResponseFormat::from_headers(&HeaderMap::new()),
+            ResponseFormat::Json
+        );
+        assert_eq!(accept("text/plain"), ResponseFormat::Text);
+        assert_eq!(accept("application/x-ndjson"), ResponseFormat::Ndjson);
+        assert_eq!(accept("text/html, */*"), ResponseFormat::Json);
+        assert_eq!(
+            accept("application/json;q=0.5, text/plain"),
+            ResponseFormat::Text
+        );
+        assert_eq!(accept("text/plain;q=0, image/png"), ResponseFormat::Json);
+    }
+
+    #[test]
+    fn test_render_text_joins_text_blocks() {
+        let message = tool_result(serde_json::json!([
+            { "type": "text", "text": "first" },
+            { "type": "text", "text": "second" }
+        ]));
+        assert_eq!(render_text(&message).as_deref(), Some("first\nsecond"));
+
+        // Non-text content and non-tool results cannot be rendered as text
+        let message = tool_result(serde_json::json!([
+            { "type": "text", "text": "caption" },
+            { "type": "image", "data": "AAAA", "mimeType": "image/png" }
+        ]));
+        assert!(render_text(&message).is_none());
+        assert!(render_text(&serde_json::json!({ "result": { "tools": [] } })).is_none());
+    }
+
+    #[test]
+    fn test_render_ndjson_lines() {
+        let message = tool_result(serde_json::json!([
+            { "type": "text", "text": "a" },
+            { "type": "image", "data": "AAAA", "mimeType": "image/png" }
+        ]));
+        let lines: Vec<Value> = render_ndjson(&message)
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["type"], "image");
+
+        let message = serde_json::json!({
+            "result": { "tools": [{ "name": "a" }, { "name": "b" }, { "name": "c" }] }
+        });
+        assert_eq!(render_ndjson(&message).lines().count(), 3);
+
+        let message = serde_json::json!({ "error": { "code": -32601, "message": "nope" } });
+        assert_eq!(render_ndjson(&message), format!("{}\n", message));
+    }
+
+    #[test]
+    fn test_raw_requested_by_header_or_query() {
+        let mut headers = HeaderMap::new();
+        assert!(!raw_requested(&headers, &[]));
+        assert!(raw_requested(
+            &headers,
+            &[("raw".to_string(), "true".to_string())]
+        ));
+        headers.insert(RAW_HEADER, HeaderValue::from_static("TRUE"));
+        assert!(raw_requested(&headers, &[]));
+
+        // The header wins over the query
+        headers.insert(RAW_HEADER, HeaderValue::from_static("false"));
+        assert!(!raw_requested(
+            &headers,
+            &[("raw".to_string(), "1".to_string())]
+        ));
+    }
+
+    #[test]
+    fn test_render_falls_back_to_json() {
+        let response = McpResponse {
+            result: "not json".to_string(),
+        };
+        let rendered = render(ResponseFormat::Text, response);
+        assert_eq!(rendered.headers()[header::CONTENT_TYPE], "application/json");
+    }
+}
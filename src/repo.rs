@@ -0,0 +1,449 @@
+//! Repository setup in a server's work directory
+//!
+//! A server's repository is always cloned into the root of its work
+//! directory, `WORK_DIR_BASE/<server>`, which is also where the build and
+//! the server run. Files the gateway keeps there (`.mcp-*`) do not count as
+//! content. A directory with other content but no clone, or with a clone of
+//! another repository, is refused or adopted as is, per `existing_work_dir`,
+//! instead of letting git fail on it.
+
+use crate::build_log::{self, BuildStep, StepKind};
+use crate::child_env::ChildEnv;
+use crate::error::{McpCoreError, McpCoreResult};
+use crate::manager::WORK_DIR_BASE;
+use crate::workdir;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Prefix of the files the gateway itself keeps in a work directory
+pub const GATEWAY_FILE_PREFIX: &str = ".mcp-";
+
+/// Directory inside the work directory a clone is made in before it is moved up
+const CLONE_DIR_NAME: &str = ".mcp-clone";
+
+/// What to do with a work directory that already holds something else
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExistingWorkDir {
+    /// Refuse to start the server
+    #[default]
+    Error,
+    /// Use the directory's contents without cloning
+    Adopt,
+}
+
+/// Contents of a work directory before setup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WorkDirState {
+    /// Missing, or holding only the gateway's own files
+    Empty,
+    /// A git clone, with the repository recorded in its metadata, if any
+    Clone { repository: Option<String> },
+    /// Content that is not a git clone
+    Foreign,
+}
+
+/// Work directory of `server_name`
+pub fn work_dir(server_name: &str) -> PathBuf {
+    Path::new(WORK_DIR_BASE).join(server_name)
+}
+
+/// Look at what `work_dir` holds
+pub async fn inspect(work_dir: &Path) -> McpCoreResult<WorkDirState> {
+    let mut entries = match tokio::fs::read_dir(work_dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(WorkDirState::Empty),
+        Err(e) => {
+            return Err(McpCoreError::ProcessError {
+                message: format!(
+                    "Failed to read work directory '{}': {}",
+                    work_dir.display(),
+                    e
+                ),
+            })
+        }
+    };
+    if tokio::fs::metadata(work_dir.join(".git")).await.is_ok() {
+        let repository = workdir::read_metadata(work_dir)
+            .await
+            .and_then(|metadata| metadata.repository);
+        return Ok(WorkDirState::Clone { repository });
+    }
+    while let Some(entry) = entries.next_entry().await? {
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(GATEWAY_FILE_PREFIX)
+        {
+            return Ok(WorkDirState::Foreign);
+        }
+    }
+    Ok(WorkDirState::Empty)
+}
+
+/// Clone `repository_url` into `work_dir` unless it is already there,
+/// returning whether it was cloned
+///
+/// Git runs with stdin closed and terminal prompts disabled, so a
+/// repository needing credentials fails instead of waiting for input.
+/// A fresh clone then checks out `pinned_commit` if given.
+pub async fn prepare(
+    repository_url: &str,
+    work_dir: &Path,
+    pinned_commit: Option<&str>,
+    env: &ChildEnv,
+    existing: ExistingWorkDir,
+) -> McpCoreResult<bool> {
+    let logged_url = env.redact(repository_url);
+    tracing::info!("Checking repository: {}", logged_url);
+
+    match inspect(work_dir).await? {
+        WorkDirState::Clone { repository } => {
+            if let Some(recorded) = repository.filter(|recorded| recorded != repository_url) {
+                let message = format!(
+                    "Work directory '{}' holds a clone of '{}', not '{}'",
+                    work_dir.display(),
+                    env.redact(&recorded),
+                    logged_url
+                );
+                refuse_or_adopt(existing, message)?;
+            }
+            tracing::info!(
+                "Repository already exists in '{}', skipping clone",
+                work_dir.display()
+            );
+            Ok(false)
+        }
+        WorkDirState::Foreign => {
+            let message = format!(
+                "Work directory '{}' is not empty and holds no git clone of '{}'",
+                work_dir.display(),
+                logged_url
+            );
+            refuse_or_adopt(existing, message)?;
+            Ok(false)
+        }
+        WorkDirState::Empty => {
+            clone(repository_url, work_dir, env).await?;
+            if let Some(commit) = pinned_commit {
+                checkout_pinned_commit(commit, work_dir, env).await;
+            }
+            Ok(true)
+        }
+    }
+}
+
+/// Fail with `message`, or log it and carry on with the directory as it is
+fn refuse_or_adopt(existing: ExistingWorkDir, message: String) -> McpCoreResult<()> {
+    match existing {
+        ExistingWorkDir::Error => Err(McpCoreError::ConfigurationError {
+            message: format!(
+                "{}; remove it, or set existing_work_dir to \"adopt\" to use its contents",
+                message
+            ),
+        }),
+        ExistingWorkDir::Adopt => {
+            tracing::warn!("{}; adopting its contents", message);
+            Ok(())
+        }
+    }
+}
+
+/// Clone next to the gateway's files, then move the clone to the root of `work_dir`
+async fn clone(repository_url: &str, work_dir: &Path, env: &ChildEnv) -> McpCoreResult<()> {
+    let logged_url = env.redact(repository_url);
+    let clone_dir = work_dir.join(CLONE_DIR_NAME);
+    tokio::fs::create_dir_all(work_dir)
+        .await
+        .map_err(|e| McpCoreError::ProcessError {
+            message: format!(
+                "Failed to create work directory '{}': {}",
+                work_dir.display(),
+                e
+            ),
+        })?;
+    // Left behind by an interrupted clone
+    let _ = tokio::fs::remove_dir_all(&clone_dir).await;
+
+    tracing::info!(
+        "Cloning repository '{}' to '{}'",
+        logged_url,
+        work_dir.display()
+    );
+    let start_time = std::time::Instant::now();
+
+    let mut command_builder = tokio::process::Command::new("git");
+    command_builder
+        .arg("clone")
+        .arg(repository_url)
+        .arg(CLONE_DIR_NAME);
+    env.apply(&mut command_builder);
+    command_builder.env("GIT_TERMINAL_PROMPT", "0");
+    command_builder.current_dir(work_dir);
+
+    // Capture output for logging; a cancelled setup job kills the clone
+    command_builder
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true);
+
+    tracing::debug!("Executing: git clone {} {}", logged_url, CLONE_DIR_NAME);
+
+    let started_at = chrono::Utc::now();
+    let output = command_builder
+        .output()
+        .await
+        .map_err(|e| McpCoreError::ProcessError {
+            message: format!("Failed to execute git clone: {}", e),
+        })?;
+
+    let duration = start_time.elapsed();
+    build_log::record_step(BuildStep::new(
+        StepKind::Clone,
+        &format!("git clone {} {}", repository_url, CLONE_DIR_NAME),
+        started_at,
+        &output,
+        env,
+    ));
+
+    // Log the output
+    if !output.stdout.is_empty() {
+        let stdout_str = env.redact(&String::from_utf8_lossy(&output.stdout));
+        tracing::debug!("Git clone stdout: {}", stdout_str.trim());
+    }
+
+    if !output.stderr.is_empty() {
+        let stderr_str = env.redact(&String::from_utf8_lossy(&output.stderr));
+        if output.status.success() {
+            tracing::debug!("Git clone stderr: {}", stderr_str.trim());
+        } else {
+            tracing::error!("Git clone stderr: {}", stderr_str.trim());
+        }
+    }
+
+    if !output.status.success() {
+        let _ = tokio::fs::remove_dir_all(&clone_dir).await;
+        let error_msg = format!(
+            "Git clone failed with exit code {:?}: {}",
+            output.status.code(),
+            logged_url
+        );
+        tracing::error!("{}", error_msg);
+        return Err(McpCoreError::ProcessError { message: error_msg });
+    }
+
+    move_contents(&clone_dir, work_dir).await?;
+    tracing::info!(
+        "Repository cloned successfully in {:?}: {}",
+        duration,
+        logged_url
+    );
+    Ok(())
+}
+
+/// Move every entry of `from` into `to` and remove `from`
+async fn move_contents(from: &Path, to: &Path) -> McpCoreResult<()> {
+    let failed = |e: std::io::Error| McpCoreError::ProcessError {
+        message: format!("Failed to move the clone into '{}': {}", to.display(), e),
+    };
+    let mut entries = tokio::fs::read_dir(from).await.map_err(failed)?;
+    while let Some(entry) = entries.next_entry().await.map_err(failed)? {
+        tokio::fs::rename(entry.path(), to.join(entry.file_name()))
+            .await
+            .map_err(failed)?;
+    }
+    tokio::fs::remove_dir(from).await.map_err(failed)
+}
+
+/// Check out `git_ref`, a branch, tag, or commit, in the clone in `work_dir`
+pub async fn checkout_ref(git_ref: &str, work_dir: &Path, env: &ChildEnv) -> McpCoreResult<()> {
+    let mut command_builder = tokio::process::Command::new("git");
+    command_builder.args(["checkout", "--quiet", git_ref]);
+    env.apply(&mut command_builder);
+    command_builder
+        .current_dir(work_dir)
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true);
+
+    let output = command_builder
+        .output()
+        .await
+        .map_err(|e| McpCoreError::ProcessError {
+            message: format!("Failed to execute git checkout: {}", e),
+        })?;
+    if !output.status.success() {
+        return Err(McpCoreError::ProcessError {
+            message: format!(
+                "Failed to check out '{}': {}",
+                git_ref,
+                env.redact(String::from_utf8_lossy(&output.stderr).trim())
+            ),
+        });
+    }
+    tracing::info!("Checked out '{}' in '{}'", git_ref, work_dir.display());
+    Ok(())
+}
+
+/// Check out the commit of the previous run, keeping the clone's HEAD on failure
+async fn checkout_pinned_commit(commit: &str, work_dir: &Path, env: &ChildEnv) {
+    let mut command_builder = tokio::process::Command::new("git");
+    command_builder.args(["checkout", "--quiet", "--detach", commit]);
+    env.apply(&mut command_builder);
+    command_builder
+        .current_dir(work_dir)
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true);
+
+    match command_builder.output().await {
+        Ok(output) if output.status.success() => {
+            tracing::info!("Checked out commit {} of the previous run", commit)
+        }
+        Ok(output) => tracing::warn!(
+            "Failed to check out commit {} of the previous run, using the default branch: {}",
+            commit,
+            env.redact(String::from_utf8_lossy(&output.stderr).trim())
+        ),
+        Err(e) => tracing::warn!("Failed to execute git checkout: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mcp-repo-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn git(dir: &Path, args: &[&str]) {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=t", "-c", "user.email=t@t"])
+            .args(args)
+            .current_dir(dir)
+            .stdout(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    /// Repository with one commit adding `index.js`
+    fn source_repo(root: &Path) -> String {
+        let source = root.join("source");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("index.js"), "server").unwrap();
+        git(&source, &["init", "--quiet"]);
+        git(&source, &["add", "."]);
+        git(&source, &["commit", "--quiet", "-m", "init"]);
+        source.to_string_lossy().into_owned()
+    }
+
+    #[tokio::test]
+    async fn test_clone_lands_at_work_dir_root() {
+        let root = test_dir("layout");
+        let url = source_repo(&root);
+        let env = ChildEnv::default();
+
+        // A missing directory and one holding only gateway files are cloned alike
+        let missing = root.join("missing");
+        let with_meta = root.join("with-meta");
+        std::fs::create_dir_all(&with_meta).unwrap();
+        workdir::touch_metadata(&with_meta, "srv", Some(&url))
+            .await
+            .unwrap();
+        for dir in [&missing, &with_meta] {
+            assert_eq!(inspect(dir).await.unwrap(), WorkDirState::Empty);
+            assert!(prepare(&url, dir, None, &env, ExistingWorkDir::Error)
+                .await
+                .unwrap());
+            assert_eq!(
+                std::fs::read_to_string(dir.join("index.js")).unwrap(),
+                "server"
+            );
+            assert!(dir.join(".git").is_dir());
+            assert!(!dir.join(CLONE_DIR_NAME).exists());
+        }
+        assert!(with_meta.join(workdir::META_FILE_NAME).exists());
+        assert_eq!(
+            work_dir("srv"),
+            Path::new(&crate::manager::get_server_work_dir("srv"))
+        );
+
+        // A second run finds the clone and leaves it alone
+        assert!(
+            !prepare(&url, &with_meta, None, &env, ExistingWorkDir::Error)
+                .await
+                .unwrap()
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn test_existing_content_refused_or_adopted() {
+        let root = test_dir("existing");
+        let url = source_repo(&root);
+        let env = ChildEnv::default();
+
+        let foreign = root.join("foreign");
+        std::fs::create_dir_all(&foreign).unwrap();
+        std::fs::write(foreign.join("notes.txt"), "mine").unwrap();
+        assert_eq!(inspect(&foreign).await.unwrap(), WorkDirState::Foreign);
+        let error = prepare(&url, &foreign, None, &env, ExistingWorkDir::Error)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, McpCoreError::ConfigurationError { .. }));
+        assert!(error.to_string().contains("holds no git clone"));
+        assert!(error.to_string().contains("existing_work_dir"));
+
+        assert!(!prepare(&url, &foreign, None, &env, ExistingWorkDir::Adopt)
+            .await
+            .unwrap());
+        assert_eq!(
+            std::fs::read_to_string(foreign.join("notes.txt")).unwrap(),
+            "mine"
+        );
+        assert!(!foreign.join(".git").exists());
+
+        // A clone recorded for another repository
+        let other = root.join("other");
+        prepare(&url, &other, None, &env, ExistingWorkDir::Error)
+            .await
+            .unwrap();
+        workdir::touch_metadata(&other, "srv", Some("https://example.com/other.git"))
+            .await
+            .unwrap();
+        let error = prepare(&url, &other, None, &env, ExistingWorkDir::Error)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("holds a clone of"));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn test_checkout_ref_switches_to_remote_branch() {
+        let root = test_dir("checkout");
+        let url = source_repo(&root);
+        let source = root.join("source");
+        git(&source, &["checkout", "--quiet", "-b", "next"]);
+        std::fs::write(source.join("index.js"), "next").unwrap();
+        git(&source, &["commit", "--quiet", "-am", "next"]);
+        git(&source, &["checkout", "--quiet", "-"]);
+        let env = ChildEnv::default();
+
+        let clone = root.join("clone");
+        prepare(&url, &clone, None, &env, ExistingWorkDir::Error)
+            .await
+            .unwrap();
+        checkout_ref("next", &clone, &env).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(clone.join("index.js")).unwrap(),
+            "next"
+        );
+        let error = checkout_ref("missing", &clone, &env).await.unwrap_err();
+        assert!(error.to_string().contains("Failed to check out 'missing'"));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,204 @@
+//! Headers added to the gateway's responses
+//!
+//! A server's `response_headers` are set on every response the gateway sends
+//! for it, errors and health checks included. Its `header_from_meta` maps
+//! header names to JSON pointers into the server's JSON-RPC responses, e.g.
+//! `{"X-Cache-TTL": "/result/_meta/cacheTtl"}`; when the pointer finds a
+//! string, number, or boolean, the header is set on that response, taking
+//! precedence over a static header of the same name. Headers that describe
+//! the message framing or connection are the gateway's own and cannot be
+//! configured.
+
+use http::{HeaderMap, HeaderName, HeaderValue};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Headers whose values the gateway owns
+pub const FORBIDDEN_HEADERS: &[&str] = &[
+    "connection",
+    "content-encoding",
+    "content-length",
+    "content-type",
+    "host",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Check the configured header names, values, and pointers
+pub fn validate(
+    response_headers: &HashMap<String, String>,
+    header_from_meta: &HashMap<String, String>,
+) -> Result<(), String> {
+    for name in response_headers.keys().chain(header_from_meta.keys()) {
+        header_name(name)?;
+    }
+    for (name, value) in response_headers {
+        HeaderValue::from_str(value)
+            .map_err(|_| format!("response header '{}' has an invalid value", name))?;
+    }
+    for (name, pointer) in header_from_meta {
+        if !pointer.starts_with('/') {
+            return Err(format!(
+                "header_from_meta '{}' pointer '{}' must start with '/'",
+                name, pointer
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Parse a configured header name, refusing the gateway's own
+fn header_name(name: &str) -> Result<HeaderName, String> {
+    let parsed = HeaderName::from_bytes(name.as_bytes())
+        .map_err(|_| format!("'{}' is not a valid header name", name))?;
+    if FORBIDDEN_HEADERS.contains(&parsed.as_str()) {
+        return Err(format!("header '{}' cannot be configured", name));
+    }
+    Ok(parsed)
+}
+
+/// A server's configured response headers, parsed
+#[derive(Debug, Clone, Default)]
+pub struct ResponseHeaders {
+    fixed: HeaderMap,
+    from_meta: Vec<(HeaderName, String)>,
+}
+
+impl ResponseHeaders {
+    /// Parse a validated configuration, or `None` if it sets no headers
+    pub fn new(
+        response_headers: &HashMap<String, String>,
+        header_from_meta: &HashMap<String, String>,
+    ) -> Option<Self> {
+        if response_headers.is_empty() && header_from_meta.is_empty() {
+            return None;
+        }
+        let fixed = response_headers
+            .iter()
+            .filter_map(|(name, value)| {
+                Some((header_name(name).ok()?, HeaderValue::from_str(value).ok()?))
+            })
+            .collect();
+        let mut from_meta: Vec<(HeaderName, String)> = header_from_meta
+            .iter()
+            .filter_map(|(name, pointer)| Some((header_name(name).ok()?, pointer.clone())))
+            .collect();
+        from_meta.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+        Some(Self { fixed, from_meta })
+    }
+
+    /// Set the static headers a response does not already have
+    pub fn apply_fixed(&self, headers: &mut HeaderMap) {
+        for (name, value) in &self.fixed {
+            headers.entry(name).or_insert_with(|| value.clone());
+        }
+    }
+
+    /// Whether any header is taken from the server's responses
+    pub fn reads_meta(&self) -> bool {
+        !self.from_meta.is_empty()
+    }
+
+    /// Headers found in `message` by the configured pointers
+    ///
+    /// Pointers finding nothing, or an object, array, or null, set no header.
+    pub fn from_message(&self, message: &Value) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, pointer) in &self.from_meta {
+            let text = match message.pointer(pointer) {
+                Some(Value::String(text)) => text.clone(),
+                Some(value @ (Value::Number(_) | Value::Bool(_))) => value.to_string(),
+                _ => continue,
+            };
+            match HeaderValue::from_str(&text) {
+                Ok(value) => {
+                    headers.insert(name, value);
+                }
+                Err(_) => tracing::debug!("Skipping header '{}' with an invalid value", name),
+            }
+        }
+        headers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_forbidden_and_invalid_headers_rejected() {
+        for name in ["Content-Length", "transfer-encoding", "Connection"] {
+            let error = validate(&map(&[(name, "1")]), &HashMap::new()).unwrap_err();
+            assert!(error.contains("cannot be configured"), "{}", error);
+            let error = validate(&HashMap::new(), &map(&[(name, "/result")])).unwrap_err();
+            assert!(error.contains("cannot be configured"), "{}", error);
+        }
+        assert!(validate(&map(&[("bad header", "1")]), &HashMap::new())
+            .unwrap_err()
+            .contains("not a valid header name"));
+        assert!(validate(&map(&[("X-Robots-Tag", "a\nb")]), &HashMap::new())
+            .unwrap_err()
+            .contains("invalid value"));
+        assert!(
+            validate(&HashMap::new(), &map(&[("X-Cache-TTL", "result/ttl")]))
+                .unwrap_err()
+                .contains("must start with '/'")
+        );
+        assert!(validate(
+            &map(&[("X-Robots-Tag", "noindex")]),
+            &map(&[("X-Cache-TTL", "/result/_meta/cacheTtl")])
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_headers_extracted_from_message() {
+        assert!(ResponseHeaders::new(&HashMap::new(), &HashMap::new()).is_none());
+        let headers = ResponseHeaders::new(
+            &map(&[("X-Robots-Tag", "noindex")]),
+            &map(&[
+                ("X-Cache-TTL", "/result/_meta/cacheTtl"),
+                ("X-Deprecated", "/result/_meta/deprecated"),
+                ("X-Hint", "/result/_meta/hint"),
+                ("X-Nested", "/result/_meta"),
+            ]),
+        )
+        .unwrap();
+
+        let mut fixed = HeaderMap::new();
+        headers.apply_fixed(&mut fixed);
+        assert_eq!(fixed["x-robots-tag"], "noindex");
+        let mut existing = HeaderMap::from_iter([(
+            HeaderName::from_static("x-robots-tag"),
+            HeaderValue::from_static("all"),
+        )]);
+        headers.apply_fixed(&mut existing);
+        assert_eq!(existing["x-robots-tag"], "all");
+
+        let message = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": { "_meta": { "cacheTtl": 60, "deprecated": true, "hint": "use v2" } }
+        });
+        let found = headers.from_message(&message);
+        assert_eq!(found["x-cache-ttl"], "60");
+        assert_eq!(found["x-deprecated"], "true");
+        assert_eq!(found["x-hint"], "use v2");
+        assert!(found.get("x-nested").is_none());
+
+        // Absent values set no header
+        let found = headers.from_message(&serde_json::json!({ "id": 1, "result": {} }));
+        assert!(found.is_empty());
+    }
+}
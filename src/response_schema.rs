@@ -0,0 +1,281 @@
+//! Validation of server results against the MCP specification
+//!
+//! With `validate_responses` set, the `result` of a response to a known
+//! method is checked against the result type the specification gives it,
+//! from the schemas bundled in `schemas/mcp-results.json`. Violations are
+//! logged with the JSON pointer of the offending value; in strict mode the
+//! response is refused with `502 invalid_upstream_response` listing them.
+//! Error responses, results of other methods, and methods matching the
+//! server's `skip_response_validation` are passed through unchecked.
+//!
+//! The schemas are compiled once per process and stop at free-form values
+//! such as `inputSchema` properties, `structuredContent`, and `_meta`, so
+//! the cost of a check does not grow with how deeply a result nests.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use serde_json::Value;
+
+use crate::error::{McpCoreError, McpCoreResult};
+use crate::method_timeout::NamePattern;
+use crate::tool_schema::SchemaViolation;
+
+/// Bundled result types of the MCP specification
+const RESULT_SCHEMAS: &str = include_str!("../schemas/mcp-results.json");
+
+/// Methods whose results are checked, with their result type
+pub const RESULT_TYPES: &[(&str, &str)] = &[
+    ("initialize", "InitializeResult"),
+    ("ping", "EmptyResult"),
+    ("logging/setLevel", "EmptyResult"),
+    ("tools/list", "ListToolsResult"),
+    ("tools/call", "CallToolResult"),
+    ("resources/list", "ListResourcesResult"),
+    ("resources/templates/list", "ListResourceTemplatesResult"),
+    ("resources/read", "ReadResourceResult"),
+    ("prompts/list", "ListPromptsResult"),
+    ("prompts/get", "GetPromptResult"),
+    ("completion/complete", "CompleteResult"),
+];
+
+/// Violations reported per result
+pub const MAX_VIOLATIONS: usize = 20;
+
+/// Validators of the result types by method, compiled on first use
+fn validators() -> &'static HashMap<&'static str, jsonschema::Validator> {
+    static VALIDATORS: OnceLock<HashMap<&'static str, jsonschema::Validator>> = OnceLock::new();
+    VALIDATORS.get_or_init(|| {
+        let bundle: Value = serde_json::from_str(RESULT_SCHEMAS).expect("bundled schemas are JSON");
+        RESULT_TYPES
+            .iter()
+            .map(|(method, result_type)| {
+                let schema = serde_json::json!({
+                    "$defs": bundle["$defs"],
+                    "$ref": format!("#/$defs/{}", result_type),
+                });
+                let validator =
+                    jsonschema::validator_for(&schema).expect("bundled schemas compile");
+                (*method, validator)
+            })
+            .collect()
+    })
+}
+
+/// Checks the results of a server
+#[derive(Debug)]
+pub struct ResponseValidator {
+    skip: Vec<NamePattern>,
+
+    /// Whether violations fail the request rather than being logged
+    strict: bool,
+}
+
+impl ResponseValidator {
+    /// Validator skipping the methods, or prefixes ending in `*`, in `skip`
+    pub fn new(skip: &[String], strict: bool) -> Result<Self, String> {
+        let skip = skip
+            .iter()
+            .map(|method| {
+                NamePattern::parse(method).map_err(|reason| {
+                    format!("skip_response_validation entry '{}' {}", method, reason)
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { skip, strict })
+    }
+
+    /// Check the results in `response` to the requests in `command`
+    ///
+    /// Responses of a batch are matched to their requests by id.
+    pub fn check(&self, command: &str, response: &str) -> McpCoreResult<()> {
+        let (Ok(request), Ok(response)) = (
+            serde_json::from_str::<Value>(command),
+            serde_json::from_str::<Value>(response),
+        ) else {
+            return Ok(());
+        };
+        let methods: HashMap<String, &str> = match &request {
+            Value::Array(batch) => batch.iter().filter_map(id_and_method).collect(),
+            single => id_and_method(single).into_iter().collect(),
+        };
+        let responses = match &response {
+            Value::Array(batch) => batch.iter().collect(),
+            single => vec![single],
+        };
+        for response in responses {
+            let (Some(id), Some(result)) = (response.get("id"), response.get("result")) else {
+                continue;
+            };
+            let Some(method) = methods.get(&id.to_string()) else {
+                continue;
+            };
+            self.check_result(method, id, result)?;
+        }
+        Ok(())
+    }
+
+    fn check_result(&self, method: &str, id: &Value, result: &Value) -> McpCoreResult<()> {
+        if self.skip.iter().any(|pattern| pattern.matches(method)) {
+            return Ok(());
+        }
+        let Some(validator) = validators().get(method) else {
+            return Ok(());
+        };
+        let errors: Vec<SchemaViolation> = validator
+            .iter_errors(result)
+            .take(MAX_VIOLATIONS)
+            .map(|error| SchemaViolation::from_error(&error))
+            .collect();
+        if errors.is_empty() {
+            return Ok(());
+        }
+        for error in &errors {
+            tracing::warn!(
+                "Invalid {} result (id {}) at '{}': {}",
+                method,
+                id,
+                error.instance_path,
+                error.message
+            );
+        }
+        if !self.strict {
+            return Ok(());
+        }
+        Err(McpCoreError::InvalidUpstreamResponse {
+            message: format!(
+                "Result of '{}' does not match the MCP specification: {}",
+                method,
+                errors
+                    .iter()
+                    .map(|error| format!("{} at '{}'", error.message, error.instance_path))
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            ),
+            errors,
+        })
+    }
+}
+
+/// Id, as JSON text, and method of a request
+fn id_and_method(message: &Value) -> Option<(String, &str)> {
+    let id = message.get("id")?;
+    let method = message.get("method")?.as_str()?;
+    Some((id.to_string(), method))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request(method: &str) -> String {
+        json!({ "jsonrpc": "2.0", "id": 1, "method": method }).to_string()
+    }
+
+    fn response(result: Value) -> String {
+        json!({ "jsonrpc": "2.0", "id": 1, "result": result }).to_string()
+    }
+
+    #[test]
+    fn test_bundled_schemas_compile() {
+        assert_eq!(validators().len(), RESULT_TYPES.len());
+    }
+
+    #[test]
+    fn test_valid_results_pass() {
+        let validator = ResponseValidator::new(&[], true).unwrap();
+        let tools = json!({
+            "tools": [{
+                "name": "echo",
+                "inputSchema": {
+                    "type": "object",
+                    "properties": { "deep": { "items": { "items": { "type": "string" } } } }
+                }
+            }]
+        });
+        let call = json!({
+            "content": [
+                { "type": "text", "text": "hello" },
+                { "type": "resource", "resource": { "uri": "file:///a", "text": "a" } }
+            ],
+            "structuredContent": { "nested": [[[{ "free": "form" }]]] },
+            "isError": false
+        });
+        for (method, result) in [
+            ("tools/list", tools),
+            ("tools/call", call),
+            ("ping", json!({})),
+            (
+                "completion/complete",
+                json!({ "completion": { "values": ["a"] } }),
+            ),
+        ] {
+            validator
+                .check(&request(method), &response(result))
+                .unwrap_or_else(|e| panic!("{}: {}", method, e));
+        }
+    }
+
+    #[test]
+    fn test_invalid_result_lists_violations_in_strict_mode() {
+        let result = json!({
+            "tools": [
+                { "name": "echo", "inputSchema": { "type": "object" } },
+                { "name": "broken" }
+            ]
+        });
+        let error = ResponseValidator::new(&[], true)
+            .unwrap()
+            .check(&request("tools/list"), &response(result.clone()))
+            .unwrap_err();
+        let McpCoreError::InvalidUpstreamResponse { errors, .. } = &error else {
+            panic!("unexpected error: {}", error);
+        };
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].instance_path, "/tools/1");
+        assert_eq!(errors[0].keyword, "required");
+        assert_eq!(error.error_code(), Some("invalid_upstream_response"));
+        assert_eq!(error.status_code(), http::StatusCode::BAD_GATEWAY);
+
+        // Outside strict mode violations are only logged
+        ResponseValidator::new(&[], false)
+            .unwrap()
+            .check(&request("tools/list"), &response(result))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_unknown_methods_errors_and_skipped_methods_pass() {
+        let skipping = ResponseValidator::new(&["tools/*".to_string()], true).unwrap();
+        let validator = ResponseValidator::new(&[], true).unwrap();
+        let malformed = response(json!({ "tools": "none" }));
+
+        skipping.check(&request("tools/list"), &malformed).unwrap();
+        validator
+            .check(&request("vendor/custom"), &malformed)
+            .unwrap();
+        let error = json!({ "jsonrpc": "2.0", "id": 1, "error": { "code": -1, "message": "x" } });
+        validator
+            .check(&request("tools/list"), &error.to_string())
+            .unwrap();
+        assert!(ResponseValidator::new(&["*tools".to_string()], true).is_err());
+    }
+
+    #[test]
+    fn test_batch_responses_are_matched_by_id() {
+        let command = json!([
+            { "jsonrpc": "2.0", "id": "a", "method": "ping" },
+            { "jsonrpc": "2.0", "id": "b", "method": "prompts/list" }
+        ]);
+        let responses = json!([
+            { "jsonrpc": "2.0", "id": "b", "result": { "prompts": [{ "title": "untitled" }] } },
+            { "jsonrpc": "2.0", "id": "a", "result": {} }
+        ]);
+        let error = ResponseValidator::new(&[], true)
+            .unwrap()
+            .check(&command.to_string(), &responses.to_string())
+            .unwrap_err();
+        assert!(error.to_string().contains("prompts/list"), "{}", error);
+    }
+}
@@ -0,0 +1,342 @@
+//! Responses passed to the client while they are still being read
+//!
+//! A transport that reads messages line by line can start passing a
+//! response on as soon as the opening of its line shows it is the awaited
+//! one: a top-level `id` matching the request, followed by the start of its
+//! `result` or `error`. [`scan_head`] decides that from the first bytes
+//! without parsing the rest, and the transport writes the line to a
+//! [`ResponseSink`] chunk by chunk. A response whose `id` comes after its
+//! `result` cannot be told apart early and is read whole, like any other
+//! message.
+//!
+//! The sink enforces the response size limit. A response growing past it,
+//! or a client that goes away, stops the forwarding, but the transport still
+//! reads the rest of the line so the next message starts where it should.
+
+use crate::error::{McpCoreError, McpCoreResult};
+use serde_json::Value;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+/// Opening bytes of a line searched for its `id` before reading it whole
+pub const MAX_HEAD_BYTES: usize = 64 * 1024;
+
+/// Chunks buffered between the transport and a slow client
+const CHANNEL_CHUNKS: usize = 8;
+
+/// Chunks of a streamed response, ending with an error if it was cut short
+pub type ResponseChunks = mpsc::Receiver<std::io::Result<Vec<u8>>>;
+
+/// What a transport read instead of the awaited response, or that it
+/// streamed it
+#[derive(Debug)]
+pub enum Received {
+    /// A whole message, handled as if it had been received normally
+    Message(String),
+
+    /// The awaited response, written to the sink as it was read
+    Streamed,
+}
+
+/// What the opening of a line says about streaming it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Head {
+    /// The response to the pending request; its body starts here
+    Stream,
+
+    /// Something else, or a response that cannot be recognized early
+    Buffer,
+
+    /// Not enough bytes to tell yet
+    Incomplete,
+}
+
+/// Where a streamed response goes, with the limits it must stay within
+pub struct ResponseSink {
+    chunks: mpsc::Sender<std::io::Result<Vec<u8>>>,
+    started: Option<oneshot::Sender<()>>,
+    max_bytes: Option<usize>,
+    sent: usize,
+
+    /// Longest a write may wait for the client to take earlier chunks
+    stall_timeout: Duration,
+}
+
+impl ResponseSink {
+    /// Sink allowing responses of up to `max_bytes`, the receiver of its
+    /// chunks, and a signal sent with the first chunk
+    pub fn channel(
+        max_bytes: Option<usize>,
+        stall_timeout: Duration,
+    ) -> (Self, ResponseChunks, oneshot::Receiver<()>) {
+        let (chunks, receiver) = mpsc::channel(CHANNEL_CHUNKS);
+        let (started, started_signal) = oneshot::channel();
+        let sink = Self {
+            chunks,
+            started: Some(started),
+            max_bytes,
+            sent: 0,
+            stall_timeout,
+        };
+        (sink, receiver, started_signal)
+    }
+
+    /// Whether part of a response was written
+    pub fn is_started(&self) -> bool {
+        self.started.is_none()
+    }
+
+    /// Largest response allowed
+    pub fn max_bytes(&self) -> Option<usize> {
+        self.max_bytes
+    }
+
+    /// Bytes written so far
+    pub fn sent(&self) -> usize {
+        self.sent
+    }
+
+    /// Pass `chunk` to the client
+    ///
+    /// Fails with `response_too_large` once the response would exceed the
+    /// limit, and with `request_aborted` if the client went away or stopped
+    /// reading for the stall timeout.
+    pub async fn write(&mut self, chunk: &[u8]) -> McpCoreResult<()> {
+        if chunk.is_empty() {
+            return Ok(());
+        }
+        self.sent += chunk.len();
+        if let Some(max_bytes) = self.max_bytes.filter(|max_bytes| self.sent > *max_bytes) {
+            return Err(too_large(max_bytes));
+        }
+        if let Some(started) = self.started.take() {
+            let _ = started.send(());
+        }
+        let gone = || McpCoreError::RequestAborted {
+            message: "The client stopped reading the streamed response".to_string(),
+        };
+        match tokio::time::timeout(self.stall_timeout, self.chunks.send(Ok(chunk.to_vec()))).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(_)) | Err(_) => Err(gone()),
+        }
+    }
+
+    /// Cut a started response short because of `error`
+    ///
+    /// The client sees the body end in an error, which closes an HTTP/1.1
+    /// connection or resets an HTTP/2 stream.
+    pub async fn abort(&mut self, error: &McpCoreError) {
+        if !self.is_started() {
+            return;
+        }
+        tracing::warn!(
+            "Cutting a streamed response short after {} bytes: {}",
+            self.sent,
+            error
+        );
+        let error = std::io::Error::other(error.to_string());
+        let _ = tokio::time::timeout(self.stall_timeout, self.chunks.send(Err(error))).await;
+    }
+}
+
+/// Error for a response larger than `max_bytes`
+pub fn too_large(max_bytes: usize) -> McpCoreError {
+    McpCoreError::ResponseTooLarge {
+        message: format!("MCP server response exceeds the {} byte limit", max_bytes),
+    }
+}
+
+/// Whether the line starting with `head` answers `pending_id`, judged from
+/// its top-level keys up to `result` or `error`
+///
+/// A line opening with a `method`, without an `id` before its `result`, or
+/// with another `id` is read whole.
+pub fn scan_head(head: &[u8], pending_id: &Value) -> Head {
+    match scan_object(head, pending_id) {
+        Ok(head) => head,
+        Err(Scan::Incomplete) if head.len() < MAX_HEAD_BYTES => Head::Incomplete,
+        Err(_) => Head::Buffer,
+    }
+}
+
+/// Why a value could not be skipped
+enum Scan {
+    Incomplete,
+    Invalid,
+}
+
+fn scan_object(head: &[u8], pending_id: &Value) -> Result<Head, Scan> {
+    let mut at = skip_whitespace(head, 0)?;
+    if head[at] != b'{' {
+        return Ok(Head::Buffer);
+    }
+    at += 1;
+    let mut id = None;
+    loop {
+        at = skip_whitespace(head, at)?;
+        match head[at] {
+            b',' => {
+                at += 1;
+                continue;
+            }
+            b'"' => {}
+            _ => return Ok(Head::Buffer),
+        }
+        let key_end = skip_string(head, at)?;
+        let key = &head[at + 1..key_end - 1];
+        at = skip_whitespace(head, key_end)?;
+        if head[at] != b':' {
+            return Err(Scan::Invalid);
+        }
+        at = skip_whitespace(head, at + 1)?;
+        match key {
+            b"result" | b"error" => {
+                return Ok(match id {
+                    Some(id) if &id == pending_id => Head::Stream,
+                    _ => Head::Buffer,
+                });
+            }
+            b"method" => return Ok(Head::Buffer),
+            _ => {}
+        }
+        let value_end = skip_value(head, at)?;
+        if key == b"id" {
+            id = Some(
+                serde_json::from_slice::<Value>(&head[at..value_end]).map_err(|_| Scan::Invalid)?,
+            );
+        }
+        at = value_end;
+    }
+}
+
+fn skip_whitespace(head: &[u8], mut at: usize) -> Result<usize, Scan> {
+    while at < head.len() && head[at].is_ascii_whitespace() {
+        at += 1;
+    }
+    match at < head.len() {
+        true => Ok(at),
+        false => Err(Scan::Incomplete),
+    }
+}
+
+/// End of the string starting at `at`, past its closing quote
+fn skip_string(head: &[u8], at: usize) -> Result<usize, Scan> {
+    let mut escaped = false;
+    for (offset, byte) in head[at + 1..].iter().enumerate() {
+        match (escaped, byte) {
+            (true, _) => escaped = false,
+            (false, b'\\') => escaped = true,
+            (false, b'"') => return Ok(at + offset + 2),
+            _ => {}
+        }
+    }
+    Err(Scan::Incomplete)
+}
+
+/// End of the value starting at `at`
+fn skip_value(head: &[u8], at: usize) -> Result<usize, Scan> {
+    match head[at] {
+        b'"' => skip_string(head, at),
+        b'{' | b'[' => {
+            let mut depth = 0usize;
+            let mut position = at;
+            while position < head.len() {
+                match head[position] {
+                    b'"' => {
+                        position = skip_string(head, position)?;
+                        continue;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Ok(position + 1);
+                        }
+                    }
+                    _ => {}
+                }
+                position += 1;
+            }
+            Err(Scan::Incomplete)
+        }
+        b'}' | b']' | b',' | b':' => Err(Scan::Invalid),
+        _ => head[at..]
+            .iter()
+            .position(|byte| matches!(byte, b',' | b'}' | b']') || byte.is_ascii_whitespace())
+            .map(|length| at + length)
+            .ok_or(Scan::Incomplete),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_head_is_streamed_only_for_the_pending_response() {
+        let id = serde_json::json!(7);
+        let scan = |head: &str| scan_head(head.as_bytes(), &id);
+
+        assert_eq!(
+            scan(r#" {"jsonrpc":"2.0", "id" : 7, "result":{"content":[{"type":"text","text":"aaa"#),
+            Head::Stream
+        );
+        assert_eq!(scan(r#"{"jsonrpc":"2.0","id":7,"error":{"#), Head::Stream);
+        // Nested values and escaped quotes before the id are skipped
+        assert_eq!(
+            scan(r#"{"_meta":{"a":["}",{"b":"\"x"}]},"id":7,"result":{"#),
+            Head::Stream
+        );
+
+        assert_eq!(scan(r#"{"jsonrpc":"2.0","id":8,"result":{"#), Head::Buffer);
+        assert_eq!(
+            scan(r#"{"jsonrpc":"2.0","id":"7","result":{"#),
+            Head::Buffer
+        );
+        assert_eq!(
+            scan(r#"{"jsonrpc":"2.0","result":{},"id":7}"#),
+            Head::Buffer
+        );
+        assert_eq!(
+            scan(r#"{"jsonrpc":"2.0","id":7,"method":"roots/list"}"#),
+            Head::Buffer
+        );
+        assert_eq!(
+            scan(r#"{"jsonrpc":"2.0","method":"notifications/x"#),
+            Head::Buffer
+        );
+        assert_eq!(scan("server ready"), Head::Buffer);
+        assert_eq!(
+            scan(r#"[{"jsonrpc":"2.0","id":7,"result":{}}]"#),
+            Head::Buffer
+        );
+
+        assert_eq!(scan(""), Head::Incomplete);
+        assert_eq!(scan(r#"{"jsonrpc":"2.0","id":7"#), Head::Incomplete);
+        assert_eq!(scan(r#"{"jsonrpc":"2.0","id":7,"res"#), Head::Incomplete);
+        let long = format!(r#"{{"_meta":"{}"#, "a".repeat(MAX_HEAD_BYTES));
+        assert_eq!(scan(&long), Head::Buffer);
+    }
+
+    #[tokio::test]
+    async fn test_sink_enforces_the_limit_and_notices_the_client_leaving() {
+        let (mut sink, mut chunks, started) =
+            ResponseSink::channel(Some(5), Duration::from_secs(1));
+        sink.write(b"").await.unwrap();
+        assert!(!sink.is_started());
+        sink.write(b"abc").await.unwrap();
+        started.await.unwrap();
+        assert_eq!(chunks.recv().await.unwrap().unwrap(), b"abc");
+        let error = sink.write(b"def").await.unwrap_err();
+        assert!(matches!(error, McpCoreError::ResponseTooLarge { .. }));
+        sink.abort(&error).await;
+        assert!(chunks.recv().await.unwrap().is_err());
+
+        let (mut sink, chunks, _) = ResponseSink::channel(None, Duration::from_secs(1));
+        drop(chunks);
+        assert!(matches!(
+            sink.write(b"abc").await,
+            Err(McpCoreError::RequestAborted { .. })
+        ));
+    }
+}
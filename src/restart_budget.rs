@@ -0,0 +1,381 @@
+//! Restart budget shared by every server of the process
+//!
+//! Servers crash-looping at once, say during an outage of an upstream they
+//! share, each stay within their own limits yet together keep the host busy
+//! spawning and building. With `restart_budget` configured, every restart
+//! and every provisioning attempt after a failed one first takes a token
+//! from a bucket shared by all servers, refilled at `per_minute` up to
+//! `burst`. Limits of the server itself, such as the recycler's minimum
+//! interval, apply before the budget is asked.
+//!
+//! When the bucket is empty the restart waits for a token rather than
+//! failing, and the server reports `restart_throttled` meanwhile. A period
+//! during which restarts wait is a throttling episode; each is logged when
+//! it starts and ends, and counted in the stats. An operator can raise the
+//! budget for a while with `POST /admin/restart-budget`.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Longest a raise of the budget lasts
+pub const MAX_RAISE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Rate and burst of the restart budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RestartBudgetConfig {
+    /// Tokens added per minute
+    pub per_minute: u32,
+
+    /// Tokens the bucket holds when full
+    pub burst: u32,
+}
+
+impl RestartBudgetConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.per_minute == 0 {
+            return Err("per_minute must be positive".to_string());
+        }
+        if self.burst == 0 {
+            return Err("burst must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Point-in-time view of the restart budget
+#[derive(Debug, Clone, Serialize)]
+pub struct RestartBudgetSnapshot {
+    /// Limits in effect, raised or configured
+    pub per_minute: u32,
+    pub burst: u32,
+
+    /// Whole tokens in the bucket
+    pub available: u32,
+
+    /// End of the raise in effect, if any
+    pub raised_until: Option<DateTime<Utc>>,
+
+    /// Restarts waiting for a token
+    pub waiting: usize,
+
+    /// Start of the current throttling episode, if restarts are waiting
+    pub throttled_since: Option<DateTime<Utc>>,
+
+    /// Throttling episodes since the process started
+    pub episodes: u64,
+
+    /// Restarts that had to wait, and those granted a token
+    pub throttled: u64,
+    pub granted: u64,
+}
+
+#[derive(Debug)]
+struct Raise {
+    limits: RestartBudgetConfig,
+    until: Instant,
+    until_utc: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Tokens left and when they were counted
+    tokens: f64,
+    counted: Instant,
+    raise: Option<Raise>,
+    waiting: usize,
+
+    /// When the current episode started, and restarts delayed during it
+    episode: Option<(Instant, DateTime<Utc>, u64)>,
+    episodes: u64,
+    throttled: u64,
+    granted: u64,
+}
+
+/// Token bucket every restart of the process takes from
+#[derive(Debug)]
+pub struct RestartBudget {
+    configured: RestartBudgetConfig,
+    bucket: Mutex<Bucket>,
+
+    /// Woken when the budget is raised
+    raised: Notify,
+}
+
+impl RestartBudget {
+    pub fn new(configured: RestartBudgetConfig) -> Self {
+        Self {
+            configured,
+            bucket: Mutex::new(Bucket {
+                tokens: configured.burst.into(),
+                counted: Instant::now(),
+                raise: None,
+                waiting: 0,
+                episode: None,
+                episodes: 0,
+                throttled: 0,
+                granted: 0,
+            }),
+            raised: Notify::new(),
+        }
+    }
+
+    /// The budget shared by every server in the process
+    ///
+    /// The first call sets the limits; later calls asking for others keep
+    /// them and log a warning.
+    pub fn shared(configured: RestartBudgetConfig) -> Arc<Self> {
+        static SHARED: OnceLock<Arc<RestartBudget>> = OnceLock::new();
+        let budget = SHARED.get_or_init(|| Arc::new(Self::new(configured)));
+        if budget.configured != configured {
+            tracing::warn!(
+                "Restarts are already limited to {} per minute (burst {}); ignoring restart_budget {} per minute (burst {})",
+                budget.configured.per_minute,
+                budget.configured.burst,
+                configured.per_minute,
+                configured.burst
+            );
+        }
+        Arc::clone(budget)
+    }
+
+    /// Take a token for a restart of `server`, waiting until one is available
+    pub async fn acquire(&self, server: &str) {
+        let mut waiting: Option<Waiting<'_>> = None;
+        loop {
+            let raised = self.raised.notified();
+            tokio::pin!(raised);
+            raised.as_mut().enable();
+
+            let wait = {
+                let mut bucket = self.lock();
+                let limits = self.refill(&mut bucket);
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    bucket.granted += 1;
+                    return;
+                }
+                if waiting.is_none() {
+                    waiting = Some(self.start_waiting(&mut bucket, server));
+                }
+                Duration::from_secs_f64((1.0 - bucket.tokens) * 60.0 / f64::from(limits.per_minute))
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = raised => {}
+            }
+        }
+    }
+
+    /// Limits the budget was configured with
+    pub fn configured(&self) -> RestartBudgetConfig {
+        self.configured
+    }
+
+    /// Use `limits` for `duration`, up to [`MAX_RAISE`], instead of the
+    /// configured ones
+    ///
+    /// Neither limit may be below the configured one. Tokens added to the
+    /// burst are available at once.
+    pub fn raise(
+        &self,
+        limits: RestartBudgetConfig,
+        duration: Duration,
+    ) -> Result<RestartBudgetSnapshot, String> {
+        limits.validate()?;
+        if duration.is_zero() || duration > MAX_RAISE {
+            return Err(format!(
+                "raise must last between 1 and {} seconds",
+                MAX_RAISE.as_secs()
+            ));
+        }
+        if limits.per_minute < self.configured.per_minute || limits.burst < self.configured.burst {
+            return Err(format!(
+                "cannot lower the budget below {} per minute (burst {})",
+                self.configured.per_minute, self.configured.burst
+            ));
+        }
+        let mut bucket = self.lock();
+        let current = self.refill(&mut bucket);
+        bucket.tokens += f64::from(limits.burst.saturating_sub(current.burst));
+        bucket.raise = Some(Raise {
+            limits,
+            until: Instant::now() + duration,
+            until_utc: Utc::now()
+                + chrono::Duration::from_std(duration).expect("raises are at most a day"),
+        });
+        tracing::info!(
+            "Restart budget raised to {} per minute (burst {}) for {}s",
+            limits.per_minute,
+            limits.burst,
+            duration.as_secs()
+        );
+        let snapshot = self.snapshot_of(&mut bucket);
+        drop(bucket);
+        self.raised.notify_waiters();
+        Ok(snapshot)
+    }
+
+    pub fn snapshot(&self) -> RestartBudgetSnapshot {
+        let mut bucket = self.lock();
+        self.snapshot_of(&mut bucket)
+    }
+
+    fn snapshot_of(&self, bucket: &mut Bucket) -> RestartBudgetSnapshot {
+        let limits = self.refill(bucket);
+        RestartBudgetSnapshot {
+            per_minute: limits.per_minute,
+            burst: limits.burst,
+            available: bucket.tokens as u32,
+            raised_until: bucket.raise.as_ref().map(|raise| raise.until_utc),
+            waiting: bucket.waiting,
+            throttled_since: bucket.episode.map(|(_, since, _)| since),
+            episodes: bucket.episodes,
+            throttled: bucket.throttled,
+            granted: bucket.granted,
+        }
+    }
+
+    /// Add the tokens earned since they were last counted, returning the
+    /// limits in effect
+    fn refill(&self, bucket: &mut Bucket) -> RestartBudgetConfig {
+        let now = Instant::now();
+        let raise_until = bucket.raise.as_ref().map(|raise| raise.until);
+        let earned_until = raise_until.map_or(now, |until| until.min(now));
+        let limits = self.limits(bucket);
+        bucket.tokens += earned_until
+            .saturating_duration_since(bucket.counted)
+            .as_secs_f64()
+            * f64::from(limits.per_minute)
+            / 60.0;
+        bucket.counted = earned_until;
+        if raise_until.is_some_and(|until| until <= now) {
+            tracing::info!("Restart budget raise ended");
+            bucket.raise = None;
+            return self.refill(bucket);
+        }
+        bucket.tokens = bucket.tokens.min(limits.burst.into());
+        limits
+    }
+
+    fn limits(&self, bucket: &Bucket) -> RestartBudgetConfig {
+        bucket
+            .raise
+            .as_ref()
+            .map_or(self.configured, |raise| raise.limits)
+    }
+
+    fn start_waiting<'a>(&'a self, bucket: &mut Bucket, server: &str) -> Waiting<'a> {
+        bucket.waiting += 1;
+        bucket.throttled += 1;
+        match &mut bucket.episode {
+            Some((_, _, delayed)) => *delayed += 1,
+            None => {
+                bucket.episodes += 1;
+                bucket.episode = Some((Instant::now(), Utc::now(), 1));
+                tracing::warn!(
+                    "Restart budget exhausted; restart of '{}' waits for a token",
+                    server
+                );
+            }
+        }
+        Waiting { budget: self }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Bucket> {
+        self.bucket
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// A restart waiting for a token, ending the episode with the last one
+struct Waiting<'a> {
+    budget: &'a RestartBudget,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        let mut bucket = self.budget.lock();
+        bucket.waiting -= 1;
+        if bucket.waiting > 0 {
+            return;
+        }
+        if let Some((started, _, delayed)) = bucket.episode.take() {
+            tracing::info!(
+                "Restart throttling ended after {}ms; {} restarts were delayed",
+                started.elapsed().as_millis(),
+                delayed
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(per_minute: u32, burst: u32) -> Arc<RestartBudget> {
+        Arc::new(RestartBudget::new(RestartBudgetConfig {
+            per_minute,
+            burst,
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_restarts_wait_for_tokens() {
+        // One token per 100ms
+        let budget = budget(600, 2);
+        let started = Instant::now();
+        for _ in 0..4 {
+            budget.acquire("echo").await;
+        }
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(180), "{:?}", elapsed);
+
+        let snapshot = budget.snapshot();
+        assert_eq!((snapshot.granted, snapshot.throttled), (4, 2));
+        assert_eq!((snapshot.episodes, snapshot.waiting), (2, 0));
+        assert!(snapshot.throttled_since.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_raise_releases_waiting_restarts() {
+        let budget = budget(1, 1);
+        budget.acquire("echo").await;
+        let waiter = {
+            let budget = Arc::clone(&budget);
+            tokio::spawn(async move { budget.acquire("echo").await })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let snapshot = budget.snapshot();
+        assert_eq!(snapshot.waiting, 1);
+        assert!(snapshot.throttled_since.is_some());
+
+        let lower = RestartBudgetConfig {
+            per_minute: 1,
+            burst: 0,
+        };
+        assert!(budget.raise(lower, Duration::from_secs(60)).is_err());
+        let raised = RestartBudgetConfig {
+            per_minute: 60,
+            burst: 3,
+        };
+        let snapshot = budget.raise(raised, Duration::from_millis(200)).unwrap();
+        assert_eq!((snapshot.per_minute, snapshot.burst), (60, 3));
+        assert!(snapshot.raised_until.is_some());
+        tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .expect("raise releases the waiting restart")
+            .unwrap();
+
+        // The raise ends, and the extra tokens with it
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        let snapshot = budget.snapshot();
+        assert_eq!((snapshot.per_minute, snapshot.burst), (1, 1));
+        assert!(snapshot.raised_until.is_none());
+        assert!(snapshot.available <= 1);
+    }
+}
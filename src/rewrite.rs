@@ -0,0 +1,362 @@
+//! Declarative rewriting of client requests
+//!
+//! Clients written for another protocol can keep their method names and
+//! params when a server has `rewrite` rules. Every request is rewritten
+//! right after it is parsed, so timeouts, quotas, allowlists, argument
+//! validation, and hooks all see the final form. Rules apply in a fixed
+//! order:
+//!
+//! 1. `aliases` replace the method and set fixed params over the client's,
+//!    such as a tool `name`
+//! 2. `moves` move a value from one JSON pointer to another, such as
+//!    `/params/q` to `/params/arguments/query`
+//! 3. `defaults` are merged under the params, filling what is still missing
+//!
+//! `moves` and `defaults` apply to the methods they list, matched against
+//! the method the client sent or the one it was aliased to; an empty list
+//! matches every method. Responses are not rewritten.
+
+use crate::error::{McpCoreError, McpCoreResult};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+
+/// Rewrite rules of a server
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct RewriteRules {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<MethodAlias>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub moves: Vec<ParamMove>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub defaults: Vec<ParamDefaults>,
+}
+
+/// A method clients may send in place of one the server knows
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct MethodAlias {
+    /// Method the client sends
+    pub from: String,
+
+    /// Method forwarded to the server
+    pub to: String,
+
+    /// Params set over the client's, e.g. `{"name": "search"}`
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub params: Map<String, Value>,
+}
+
+/// A value moved to another place in the message
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ParamMove {
+    /// JSON pointer (RFC 6901) of the value to move; nothing happens if absent
+    pub from: String,
+
+    /// JSON pointer it is moved to, replacing any value there
+    pub to: String,
+
+    /// Methods this rule applies to (empty means all methods)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub methods: Vec<String>,
+}
+
+/// Params used where the client gave none
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ParamDefaults {
+    /// Merged under the client's params, object by object
+    pub params: Map<String, Value>,
+
+    /// Methods this rule applies to (empty means all methods)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub methods: Vec<String>,
+}
+
+impl RewriteRules {
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty() && self.moves.is_empty() && self.defaults.is_empty()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let mut sources = HashSet::new();
+        for alias in &self.aliases {
+            if alias.from.is_empty() || alias.to.is_empty() {
+                return Err("rewrite alias methods must not be empty".to_string());
+            }
+            if alias.from == alias.to {
+                return Err(format!("rewrite alias '{}' points to itself", alias.from));
+            }
+            if !sources.insert(alias.from.as_str()) {
+                return Err(format!(
+                    "rewrite has several aliases for method '{}'",
+                    alias.from
+                ));
+            }
+        }
+        for alias in &self.aliases {
+            if sources.contains(alias.to.as_str()) {
+                return Err(format!(
+                    "rewrite alias '{}' points to '{}', which is itself an alias",
+                    alias.from, alias.to
+                ));
+            }
+        }
+        for rule in &self.moves {
+            for pointer in [&rule.from, &rule.to] {
+                if !pointer.starts_with("/params/") {
+                    return Err(format!(
+                        "rewrite move pointer '{}' must start with '/params/'",
+                        pointer
+                    ));
+                }
+            }
+            if rule.to == rule.from || rule.to.starts_with(&format!("{}/", rule.from)) {
+                return Err(format!(
+                    "rewrite cannot move '{}' into '{}'",
+                    rule.from, rule.to
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Rewrite `message`, or each message of a batch
+    ///
+    /// Returns the methods the client sent that were aliased.
+    pub fn apply(&self, message: &mut Value) -> McpCoreResult<Vec<String>> {
+        let mut aliased = Vec::new();
+        match message {
+            Value::Array(batch) => {
+                for message in batch {
+                    aliased.extend(self.apply_one(message)?);
+                }
+            }
+            single => aliased.extend(self.apply_one(single)?),
+        }
+        Ok(aliased)
+    }
+
+    fn apply_one(&self, message: &mut Value) -> McpCoreResult<Option<String>> {
+        let Some(sent) = message.get("method").and_then(Value::as_str) else {
+            return Ok(None);
+        };
+        let sent = sent.to_string();
+        let alias = self.aliases.iter().find(|alias| alias.from == sent);
+        if let Some(alias) = alias {
+            message["method"] = Value::from(alias.to.as_str());
+            if !alias.params.is_empty() {
+                merge(params_of(message)?, &alias.params, true);
+            }
+        }
+        let method = alias.map_or(sent.as_str(), |alias| alias.to.as_str());
+        let applies = |methods: &[String]| {
+            methods.is_empty() || methods.iter().any(|m| *m == sent || m == method)
+        };
+
+        for rule in self.moves.iter().filter(|rule| applies(&rule.methods)) {
+            move_value(message, &rule.from, &rule.to)?;
+        }
+        for rule in self.defaults.iter().filter(|rule| applies(&rule.methods)) {
+            merge(params_of(message)?, &rule.params, false);
+        }
+        Ok(alias.map(|_| sent))
+    }
+}
+
+/// The params of `message`, created if absent
+fn params_of(message: &mut Value) -> McpCoreResult<&mut Map<String, Value>> {
+    let params = message
+        .as_object_mut()
+        .expect("messages with a method are objects")
+        .entry("params")
+        .or_insert_with(|| Value::Object(Map::new()));
+    if params.is_null() {
+        *params = Value::Object(Map::new());
+    }
+    params
+        .as_object_mut()
+        .ok_or_else(|| McpCoreError::RequestError {
+            message: "Cannot rewrite params that are not an object".to_string(),
+        })
+}
+
+/// Merge `source` into `target`, descending into objects present in both
+///
+/// Other values in `source` replace those of `target` with `overwrite`, and
+/// only fill in missing ones without it.
+fn merge(target: &mut Map<String, Value>, source: &Map<String, Value>, overwrite: bool) {
+    for (key, value) in source {
+        match (target.get_mut(key), value) {
+            (Some(Value::Object(existing)), Value::Object(value)) => {
+                merge(existing, value, overwrite)
+            }
+            (Some(_), _) if !overwrite => {}
+            _ => {
+                target.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Move the value at `from` to `to`, creating the objects on the way
+fn move_value(message: &mut Value, from: &str, to: &str) -> McpCoreResult<()> {
+    let (parent, key) = split_pointer(from);
+    let Some(value) = message
+        .pointer_mut(parent)
+        .and_then(Value::as_object_mut)
+        .and_then(|parent| parent.shift_remove(&key))
+    else {
+        return Ok(());
+    };
+
+    let (parent, key) = split_pointer(to);
+    let mut current = message;
+    for token in parent.split('/').skip(1).map(unescape) {
+        let object = current
+            .as_object_mut()
+            .ok_or_else(|| McpCoreError::RequestError {
+                message: format!("Cannot move '{}' to '{}': not an object", from, to),
+            })?;
+        current = object
+            .entry(token)
+            .or_insert_with(|| Value::Object(Map::new()));
+        if current.is_null() {
+            *current = Value::Object(Map::new());
+        }
+    }
+    current
+        .as_object_mut()
+        .ok_or_else(|| McpCoreError::RequestError {
+            message: format!("Cannot move '{}' to '{}': not an object", from, to),
+        })?
+        .insert(key, value);
+    Ok(())
+}
+
+/// Pointer of the parent, and the unescaped last token
+fn split_pointer(pointer: &str) -> (&str, String) {
+    let (parent, last) = pointer.rsplit_once('/').unwrap_or(("", pointer));
+    (parent, unescape(last))
+}
+
+fn unescape(token: &str) -> String {
+    token.replace("~1", "/").replace("~0", "~")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rules(value: Value) -> RewriteRules {
+        let rules: RewriteRules = serde_json::from_value(value).unwrap();
+        rules.validate().unwrap();
+        rules
+    }
+
+    #[test]
+    fn test_each_rule_type() {
+        let alias = rules(json!({
+            "aliases": [{ "from": "search", "to": "tools/call", "params": { "name": "search" } }]
+        }));
+        let mut message = json!({ "jsonrpc": "2.0", "id": 1, "method": "search", "params": { "name": "x", "q": "rust" } });
+        assert_eq!(alias.apply(&mut message).unwrap(), vec!["search"]);
+        assert_eq!(message["method"], "tools/call");
+        assert_eq!(message["params"], json!({ "name": "search", "q": "rust" }));
+
+        let moves = rules(json!({
+            "moves": [{ "from": "/params/q", "to": "/params/arguments/query" }]
+        }));
+        let mut message = json!({ "method": "tools/call", "params": { "q": "rust", "arguments": { "limit": 2 } } });
+        assert!(moves.apply(&mut message).unwrap().is_empty());
+        assert_eq!(
+            message["params"],
+            json!({ "arguments": { "limit": 2, "query": "rust" } })
+        );
+
+        // A missing source leaves the message as it is
+        let mut message = json!({ "method": "tools/call", "params": {} });
+        moves.apply(&mut message).unwrap();
+        assert_eq!(message["params"], json!({}));
+
+        let defaults = rules(json!({
+            "defaults": [{
+                "methods": ["tools/call"],
+                "params": { "arguments": { "limit": 10, "lang": "en" } }
+            }]
+        }));
+        let mut message =
+            json!({ "method": "tools/call", "params": { "arguments": { "limit": 2 } } });
+        defaults.apply(&mut message).unwrap();
+        assert_eq!(
+            message["params"],
+            json!({ "arguments": { "limit": 2, "lang": "en" } })
+        );
+        let mut message = json!({ "method": "tools/list" });
+        defaults.apply(&mut message).unwrap();
+        assert!(message.get("params").is_none());
+    }
+
+    #[test]
+    fn test_rules_compose_in_order() {
+        let rules = rules(json!({
+            "aliases": [{ "from": "search", "to": "tools/call", "params": { "name": "search" } }],
+            "moves": [{ "methods": ["search"], "from": "/params/q", "to": "/params/arguments/query" }],
+            "defaults": [
+                { "methods": ["tools/call"], "params": { "arguments": { "query": "*", "limit": 10 } } }
+            ]
+        }));
+
+        // Aliased, then moved, then filled in; defaults do not replace moved values
+        let mut batch = json!([
+            { "jsonrpc": "2.0", "id": 1, "method": "search", "params": { "q": "rust" } },
+            { "jsonrpc": "2.0", "id": 2, "method": "search" },
+            { "jsonrpc": "2.0", "id": 3, "method": "tools/call", "params": { "name": "other", "q": "kept" } }
+        ]);
+        assert_eq!(rules.apply(&mut batch).unwrap(), vec!["search", "search"]);
+        assert_eq!(
+            batch[0]["params"],
+            json!({ "name": "search", "arguments": { "query": "rust", "limit": 10 } })
+        );
+        assert_eq!(
+            batch[1]["params"],
+            json!({ "name": "search", "arguments": { "query": "*", "limit": 10 } })
+        );
+        // Moves listed for the alias leave direct calls alone
+        assert_eq!(batch[2]["params"]["q"], "kept");
+        assert_eq!(batch[2]["params"]["arguments"]["query"], "*");
+    }
+
+    #[test]
+    fn test_conflicting_rules_fail_validation() {
+        let invalid = |value: Value| {
+            serde_json::from_value::<RewriteRules>(value)
+                .unwrap()
+                .validate()
+                .unwrap_err()
+        };
+        let error = invalid(json!({
+            "aliases": [
+                { "from": "search", "to": "tools/call" },
+                { "from": "search", "to": "resources/read" }
+            ]
+        }));
+        assert!(error.contains("several aliases"), "{}", error);
+        assert!(invalid(json!({
+            "aliases": [
+                { "from": "find", "to": "search" },
+                { "from": "search", "to": "tools/call" }
+            ]
+        }))
+        .contains("itself an alias"));
+        assert!(
+            invalid(json!({ "moves": [{ "from": "/id", "to": "/params/id" }] }))
+                .contains("must start with")
+        );
+        assert!(
+            invalid(json!({ "moves": [{ "from": "/params/a", "to": "/params/a/b" }] }))
+                .contains("cannot move")
+        );
+    }
+}
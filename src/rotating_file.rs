@@ -0,0 +1,147 @@
+//! Log file rotated by size, shared by the access log and stderr logs
+//!
+//! When a write would take the file past `max_bytes`, it is renamed to
+//! `<path>.1`, older generations move up by one, and the oldest beyond
+//! `max_files` is overwritten. Generations left beyond `max_files` by an
+//! earlier run with a higher limit are removed when the file is opened.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// File rotated by size, keeping `max_files` older generations
+pub(crate) struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    pub(crate) fn open(path: &Path, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        let rotating = Self {
+            path: path.to_path_buf(),
+            max_bytes,
+            max_files,
+            file,
+            size,
+        };
+        rotating.prune()?;
+        Ok(rotating)
+    }
+
+    fn rotated_path(&self, generation: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", generation));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            for generation in (1..self.max_files).rev() {
+                let from = self.rotated_path(generation);
+                if from.exists() {
+                    std::fs::rename(&from, self.rotated_path(generation + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated_path(1))?;
+            self.file = File::create(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Remove the generations beyond `max_files`
+    fn prune(&self) -> io::Result<()> {
+        let (Some(dir), Some(name)) = (self.path.parent(), self.path.file_name()) else {
+            return Ok(());
+        };
+        let dir = match dir.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => dir,
+        };
+        let prefix = format!("{}.", name.to_string_lossy());
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let generation = entry
+                .file_name()
+                .to_string_lossy()
+                .strip_prefix(&prefix)
+                .and_then(|generation| generation.parse::<usize>().ok());
+            if generation.is_some_and(|generation| generation > self.max_files) {
+                std::fs::remove_file(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_rotates_at_size_threshold() {
+        let dir = std::env::temp_dir().join(format!("mcp-access-log-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+
+        let mut file = RotatingFile::open(&path, 64, 2).unwrap();
+        for index in 0..10 {
+            file.write_all(format!("{:030}\n", index).as_bytes())
+                .unwrap();
+        }
+
+        // 31-byte lines, two per 64-byte file: 8 lines rotated, 2 kept
+        let current = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(current.lines().count(), 2);
+        assert!(current.ends_with(&format!("{:030}\n", 9)));
+        let newest = std::fs::read_to_string(dir.join("access.log.1")).unwrap();
+        assert!(newest.starts_with(&format!("{:030}\n", 6)));
+        assert!(dir.join("access.log.2").exists());
+        assert!(!dir.join("access.log.3").exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_generations_beyond_limit_are_pruned() {
+        let dir = std::env::temp_dir().join(format!("mcp-rotating-prune-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("server.log");
+        for generation in [1, 2, 3, 5] {
+            std::fs::write(dir.join(format!("server.log.{}", generation)), "old\n").unwrap();
+        }
+        std::fs::write(dir.join("server.log.old"), "kept\n").unwrap();
+
+        let _file = RotatingFile::open(&path, 64, 2).unwrap();
+        assert!(dir.join("server.log.2").exists());
+        assert!(!dir.join("server.log.3").exists());
+        assert!(!dir.join("server.log.5").exists());
+        assert!(dir.join("server.log.old").exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
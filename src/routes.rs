@@ -0,0 +1,359 @@
+//! The routes the gateway serves, and the manifest published from them
+//!
+//! Every route is registered as a [`Route`] holding its method, path,
+//! description, and handler. The router is built from these registrations,
+//! and so are the index at `/` and the manifest at [`MANIFEST_PATH`], so
+//! neither can list a route that is not served or leave out one that is.
+//!
+//! The manifest is meant for edge routers provisioned from service
+//! metadata. It is served without authentication and lists, for the
+//! listener it is fetched from, each route's method and path pattern, its
+//! route group, whether it needs an API key (`bearer`, or `admin` for the
+//! admin routes), and whether its response may be streamed. The top-level
+//! `manifest` configuration can leave the admin routes out, add free-form
+//! `metadata`, or turn the manifest off. `--print-config` shows the same
+//! routes for every listener as `effective_routes`.
+
+use crate::listener::RouteGroup;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+#[cfg(feature = "http-server")]
+use crate::{
+    config::{AuthConfig, McpServersConfig},
+    error::McpCoreResult,
+    http_server::{self, ServerState},
+    listener::ListenerConfig,
+};
+#[cfg(feature = "http-server")]
+use axum::{
+    handler::Handler,
+    routing::{self, MethodRouter},
+    Router,
+};
+#[cfg(feature = "http-server")]
+use std::collections::BTreeMap;
+
+/// Path of the manifest, relative to the root of the listener
+pub const MANIFEST_PATH: &str = "/.well-known/mcp-gateway.json";
+
+/// What the manifest shows
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ManifestConfig {
+    /// Serve the manifest (default true)
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// List the admin routes (default true)
+    #[serde(default = "default_true")]
+    pub include_admin: bool,
+
+    /// Published as the manifest's `metadata`, e.g. an owner or upstream name
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub metadata: Map<String, Value>,
+}
+
+impl Default for ManifestConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            include_admin: true,
+            metadata: Map::new(),
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Credentials a route needs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteAuth {
+    None,
+
+    /// An API key as a Bearer token
+    Bearer,
+
+    /// An API key as a Bearer token, on an admin route
+    Admin,
+}
+
+/// A served route, as the index and manifest list it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RouteInfo {
+    pub method: &'static str,
+
+    /// Path pattern, with `{param}` segments
+    pub path: String,
+    pub group: RouteGroup,
+    pub auth: RouteAuth,
+
+    /// Whether the response may be streamed, as server-sent events or in
+    /// chunks passed on as they are read
+    pub streaming: bool,
+    pub description: &'static str,
+}
+
+/// `routes` as served while authentication is `enabled` or off
+pub fn effective(routes: &[RouteInfo], enabled: bool) -> Vec<RouteInfo> {
+    routes
+        .iter()
+        .map(|route| RouteInfo {
+            auth: match enabled {
+                true => route.auth,
+                false => RouteAuth::None,
+            },
+            ..route.clone()
+        })
+        .collect()
+}
+
+/// The manifest document of `routes`
+pub fn manifest(routes: &[RouteInfo], auth_status: &str, config: &ManifestConfig) -> Value {
+    let routes: Vec<&RouteInfo> = routes
+        .iter()
+        .filter(|route| config.include_admin || route.group != RouteGroup::Admin)
+        .collect();
+    let mut manifest = serde_json::json!({
+        "service": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "auth": auth_status,
+        "routes": routes,
+    });
+    if !config.metadata.is_empty() {
+        manifest["metadata"] = Value::Object(config.metadata.clone());
+    }
+    manifest
+}
+
+/// A route and the handler serving it
+#[cfg(feature = "http-server")]
+pub(crate) struct Route<S = ServerState> {
+    method: &'static str,
+    path: &'static str,
+    description: &'static str,
+    streaming: bool,
+    public: bool,
+    handler: MethodRouter<S>,
+}
+
+#[cfg(feature = "http-server")]
+impl<S: Clone + Send + Sync + 'static> Route<S> {
+    pub fn get<H, T>(path: &'static str, description: &'static str, handler: H) -> Self
+    where
+        H: Handler<T, S>,
+        T: 'static,
+    {
+        Self::new("GET", path, description, routing::get(handler))
+    }
+
+    pub fn post<H, T>(path: &'static str, description: &'static str, handler: H) -> Self
+    where
+        H: Handler<T, S>,
+        T: 'static,
+    {
+        Self::new("POST", path, description, routing::post(handler))
+    }
+
+    pub fn delete<H, T>(path: &'static str, description: &'static str, handler: H) -> Self
+    where
+        H: Handler<T, S>,
+        T: 'static,
+    {
+        Self::new("DELETE", path, description, routing::delete(handler))
+    }
+
+    fn new(
+        method: &'static str,
+        path: &'static str,
+        description: &'static str,
+        handler: MethodRouter<S>,
+    ) -> Self {
+        Self {
+            method,
+            path,
+            description,
+            streaming: false,
+            public: false,
+            handler,
+        }
+    }
+
+    /// Mark the response as possibly streamed
+    pub fn streaming(mut self) -> Self {
+        self.streaming = true;
+        self
+    }
+
+    /// Serve the route without authentication
+    pub fn public(mut self) -> Self {
+        self.public = true;
+        self
+    }
+}
+
+/// Routes of the route groups a router mounts
+#[cfg(feature = "http-server")]
+pub(crate) struct RouteTable<S = ServerState> {
+    routes: Vec<(RouteGroup, Route<S>)>,
+}
+
+#[cfg(feature = "http-server")]
+impl<S> Default for RouteTable<S> {
+    fn default() -> Self {
+        Self { routes: Vec::new() }
+    }
+}
+
+#[cfg(feature = "http-server")]
+impl<S: Clone + Send + Sync + 'static> RouteTable<S> {
+    pub fn add(&mut self, group: RouteGroup, routes: Vec<Route<S>>) {
+        self.routes
+            .extend(routes.into_iter().map(|route| (group, route)));
+    }
+
+    /// The routes as listed, with their paths under `prefix`
+    pub fn infos(&self, prefix: &str) -> Vec<RouteInfo> {
+        self.routes
+            .iter()
+            .map(|(group, route)| RouteInfo {
+                method: route.method,
+                path: format!("{}{}", prefix, route.path),
+                group: *group,
+                auth: match (route.public, group) {
+                    (true, _) => RouteAuth::None,
+                    (false, RouteGroup::Admin) => RouteAuth::Admin,
+                    (false, _) => RouteAuth::Bearer,
+                },
+                streaming: route.streaming,
+                description: route.description,
+            })
+            .collect()
+    }
+
+    /// Router of the public routes, and router of those needing a key,
+    /// which the caller puts behind authentication
+    pub fn into_routers(self) -> (Router<S>, Router<S>) {
+        let (mut public, mut authenticated) = (Router::new(), Router::new());
+        for (_, route) in self.routes {
+            if route.public {
+                public = public.route(route.path, route.handler);
+            } else {
+                authenticated = authenticated.route(route.path, route.handler);
+            }
+        }
+        (public, authenticated)
+    }
+}
+
+/// Routes each listener would serve with `config`, by listener name
+///
+/// Without `listeners`, the single listener serving every route group is
+/// named `default`. Authentication is judged from the environment as at
+/// startup; tenant configurations are loaded to find their routes.
+#[cfg(feature = "http-server")]
+pub async fn effective_routes(
+    config: &McpServersConfig,
+    config_file: &str,
+    profile: Option<&str>,
+    server_name: &str,
+) -> McpCoreResult<BTreeMap<String, Vec<RouteInfo>>> {
+    let listeners = match config.listeners.is_empty() {
+        true => vec![("default".to_string(), RouteGroup::ALL.to_vec())],
+        false => config
+            .listeners
+            .iter()
+            .map(|ListenerConfig { name, routes, .. }| (name.clone(), routes.clone()))
+            .collect(),
+    };
+    let auth_config = AuthConfig::from_env();
+    let simple_mode = |config: &McpServersConfig| {
+        config
+            .servers
+            .get(server_name)
+            .is_some_and(|server| server.simple_mode)
+    };
+
+    let mut tenants = Vec::new();
+    let mut ids: Vec<&String> = config.tenants.keys().collect();
+    ids.sort();
+    for id in ids {
+        let tenant = &config.tenants[id];
+        let tenant_config = tenant.resolve(id, config_file, profile).await?;
+        let names = tenant
+            .api_keys
+            .keys()
+            .map(|name| (name.clone(), String::new()));
+        let enabled = auth_config.with_named_keys(names.collect()).enabled;
+        tenants.push((id.clone(), tenant_config, enabled));
+    }
+
+    let mut by_listener = BTreeMap::new();
+    for (name, groups) in listeners {
+        let table = |config: &McpServersConfig| {
+            http_server::route_table(&groups, simple_mode(config), config.manifest.enabled)
+        };
+        let mut routes = match tenants.is_empty() {
+            true => table(config).infos(""),
+            false => crate::tenant::root_routes(&groups, config.manifest.enabled).infos(""),
+        };
+        routes = effective(&routes, auth_config.enabled);
+        for (id, tenant_config, enabled) in &tenants {
+            let prefixed = table(tenant_config).infos(&crate::tenant::path_prefix(id));
+            routes.extend(effective(&prefixed, *enabled));
+        }
+        by_listener.insert(name, routes);
+    }
+    Ok(by_listener)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(path: &str, group: RouteGroup) -> RouteInfo {
+        RouteInfo {
+            method: "GET",
+            path: path.to_string(),
+            group,
+            auth: RouteAuth::Bearer,
+            streaming: false,
+            description: "",
+        }
+    }
+
+    #[test]
+    fn test_manifest_content_follows_the_config() {
+        let routes = [
+            route("/api/v1/info", RouteGroup::Api),
+            route("/admin/usage", RouteGroup::Admin),
+        ];
+        let paths = |manifest: &Value| -> Vec<String> {
+            manifest["routes"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|route| route["path"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        let manifest = manifest(&routes, "enabled", &ManifestConfig::default());
+        assert_eq!(paths(&manifest), ["/api/v1/info", "/admin/usage"]);
+        assert_eq!(manifest["routes"][1]["group"], "admin");
+        assert_eq!(manifest["routes"][1]["auth"], "bearer");
+        assert!(manifest.get("metadata").is_none());
+
+        let config: ManifestConfig = serde_json::from_value(serde_json::json!({
+            "include_admin": false,
+            "metadata": { "owner": "platform" }
+        }))
+        .unwrap();
+        assert!(config.enabled);
+        let manifest = super::manifest(&routes, "disabled", &config);
+        assert_eq!(paths(&manifest), ["/api/v1/info"]);
+        assert_eq!(manifest["metadata"]["owner"], "platform");
+        assert_eq!(manifest["auth"], "disabled");
+    }
+}
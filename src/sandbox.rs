@@ -0,0 +1,582 @@
+//! Filesystem sandbox for MCP server processes
+//!
+//! A server with a `sandbox` section runs, on Linux, in its own mount and PID
+//! namespaces where the filesystem holds only its work directory (writable),
+//! the system paths interpreters need, the directory of its executable, and
+//! the configured `allow_paths` (read-only). Two backends build the
+//! namespaces: `bwrap` (bubblewrap) when installed, or `unshare` from
+//! util-linux with unprivileged user namespaces. When sandboxing is
+//! requested and no backend works, the server fails to start; it never runs
+//! unsandboxed. Clone and build commands are not sandboxed.
+//!
+//! Network access is governed separately by [`NetworkPolicy`]: `none` adds a
+//! network namespace holding only loopback, and `allowlist` routes the
+//! server's HTTP(S) traffic through an [`EgressProxy`].
+
+use crate::diagnostics;
+use crate::egress::{EgressProxy, HostPattern};
+use crate::error::{McpCoreError, McpCoreResult};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// System paths mounted read-only so interpreters, TLS, and DNS keep working
+const RUNTIME_PATHS: &[&str] = &[
+    "/usr",
+    "/bin",
+    "/sbin",
+    "/lib",
+    "/lib32",
+    "/lib64",
+    "/etc/alternatives",
+    "/etc/ssl",
+    "/etc/pki",
+    "/etc/ca-certificates",
+    "/etc/resolv.conf",
+    "/etc/hosts",
+    "/etc/nsswitch.conf",
+    "/etc/passwd",
+    "/etc/group",
+    "/etc/localtime",
+    "/etc/ld.so.cache",
+    "/etc/ld.so.conf",
+    "/etc/ld.so.conf.d",
+];
+
+/// Directories searched for util-linux tools missing from PATH
+const SYSTEM_BIN_DIRS: &[&str] = &["/usr/sbin", "/sbin", "/usr/bin", "/bin"];
+
+/// What a sandboxed server may see of the filesystem
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilesystemScope {
+    /// The work directory, runtime paths, and `allow_paths` only
+    #[default]
+    WorkdirOnly,
+}
+
+/// What network a sandboxed server may reach
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkPolicy {
+    /// The gateway's network, unrestricted
+    #[default]
+    All,
+    /// Loopback of its own network namespace only
+    None,
+    /// `allow_hosts`, through the egress proxy; traffic ignoring the proxy
+    /// variables is not restricted
+    Allowlist,
+}
+
+/// Backend requested in the configuration
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendChoice {
+    /// `bwrap` if installed, else `unshare`
+    #[default]
+    Auto,
+    Bwrap,
+    Unshare,
+}
+
+/// Backend a sandbox actually runs with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SandboxBackend {
+    Bwrap,
+    Unshare,
+}
+
+/// Sandbox settings of a server
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SandboxConfig {
+    #[serde(default)]
+    pub filesystem: FilesystemScope,
+
+    /// Absolute paths mounted read-only in addition to the defaults
+    #[serde(default)]
+    pub allow_paths: Vec<String>,
+
+    #[serde(default)]
+    pub network: NetworkPolicy,
+
+    /// `host:port` patterns reachable under [`NetworkPolicy::Allowlist`]
+    #[serde(default)]
+    pub allow_hosts: Vec<String>,
+
+    #[serde(default)]
+    pub backend: BackendChoice,
+}
+
+impl SandboxConfig {
+    /// Check that allowed paths are absolute and allowed hosts parse
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(path) = self
+            .allow_paths
+            .iter()
+            .find(|path| !Path::new(path).is_absolute())
+        {
+            return Err(format!("allow_paths entry '{}' must be absolute", path));
+        }
+        if !self.allow_hosts.is_empty() && self.network != NetworkPolicy::Allowlist {
+            return Err("allow_hosts requires network \"allowlist\"".to_string());
+        }
+        self.host_patterns()
+            .map(|_| ())
+            .map_err(|reason| format!("allow_hosts entry {}", reason))
+    }
+
+    fn host_patterns(&self) -> Result<Vec<HostPattern>, String> {
+        self.allow_hosts
+            .iter()
+            .map(|pattern| HostPattern::parse(pattern))
+            .collect()
+    }
+
+    /// Find a working backend, failing if sandboxing is not possible here
+    ///
+    /// Under [`NetworkPolicy::Allowlist`] this also starts the egress proxy,
+    /// which runs as long as the returned sandbox or a process started in it.
+    pub async fn resolve(&self, server_name: &str) -> McpCoreResult<Sandbox> {
+        if !cfg!(target_os = "linux") {
+            return Err(unavailable("sandboxing requires Linux".to_string()));
+        }
+        let isolate_network = self.network == NetworkPolicy::None;
+        let tools = match self.backend {
+            BackendChoice::Bwrap => bwrap()?,
+            BackendChoice::Unshare => unshare(isolate_network).await?,
+            BackendChoice::Auto => match bwrap() {
+                Ok(tools) => tools,
+                Err(bwrap_error) => unshare(isolate_network).await.map_err(|unshare_error| {
+                    unavailable(format!(
+                        "no backend works ({}; {})",
+                        bwrap_error, unshare_error
+                    ))
+                })?,
+            },
+        };
+        let egress = match self.network {
+            NetworkPolicy::Allowlist => {
+                let patterns =
+                    self.host_patterns()
+                        .map_err(|reason| McpCoreError::ConfigurationError {
+                            message: format!("allow_hosts entry {}", reason),
+                        })?;
+                Some(Arc::new(EgressProxy::start(server_name, patterns).await?))
+            }
+            NetworkPolicy::All | NetworkPolicy::None => None,
+        };
+        let sandbox = Sandbox {
+            tools,
+            allow_paths: self.allow_paths.iter().map(PathBuf::from).collect(),
+            network: self.network,
+            egress,
+        };
+        tracing::info!("Sandboxing the server with {}", sandbox.backend().as_str());
+        Ok(sandbox)
+    }
+}
+
+/// Executables a backend runs, resolved when the sandbox is set up
+#[derive(Debug, Clone)]
+enum Tools {
+    Bwrap {
+        bwrap: PathBuf,
+    },
+    Unshare {
+        unshare: PathBuf,
+        mount: PathBuf,
+        umount: PathBuf,
+        pivot_root: PathBuf,
+        setpriv: PathBuf,
+
+        /// `ip`, to bring up loopback in a new network namespace
+        ip: Option<PathBuf>,
+    },
+}
+
+/// A backend known to work on this host
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    tools: Tools,
+    allow_paths: Vec<PathBuf>,
+    network: NetworkPolicy,
+    egress: Option<Arc<EgressProxy>>,
+}
+
+impl Sandbox {
+    pub fn backend(&self) -> SandboxBackend {
+        match self.tools {
+            Tools::Bwrap { .. } => SandboxBackend::Bwrap,
+            Tools::Unshare { .. } => SandboxBackend::Unshare,
+        }
+    }
+
+    pub fn network(&self) -> NetworkPolicy {
+        self.network
+    }
+
+    /// Proxy the server's traffic goes through under [`NetworkPolicy::Allowlist`]
+    pub fn egress_proxy(&self) -> Option<Arc<EgressProxy>> {
+        self.egress.clone()
+    }
+
+    /// Point the proxy variables of `command` at the egress proxy, if any
+    ///
+    /// Applied after the server's own environment so it cannot opt out.
+    pub fn apply_env(&self, command: &mut tokio::process::Command) {
+        if let Some(egress) = &self.egress {
+            command.envs(egress.env_vars());
+            command.env_remove("NO_PROXY").env_remove("no_proxy");
+        }
+    }
+
+    /// Command running `program` with `args` inside the sandbox, in `work_dir`
+    pub fn command(
+        &self,
+        program: &str,
+        args: &[String],
+        work_dir: &Path,
+    ) -> McpCoreResult<tokio::process::Command> {
+        let work_dir = std::fs::canonicalize(work_dir).map_err(|e| McpCoreError::ProcessError {
+            message: format!(
+                "Failed to resolve work dir '{}' for the sandbox: {}",
+                work_dir.display(),
+                e
+            ),
+        })?;
+        let read_only = self.read_only_paths(program);
+
+        let mut command = match &self.tools {
+            Tools::Bwrap { bwrap } => {
+                let mut command = tokio::process::Command::new(bwrap);
+                command.args(["--die-with-parent", "--unshare-pid"]);
+                if self.network == NetworkPolicy::None {
+                    command.arg("--unshare-net");
+                }
+                for path in &read_only {
+                    command.arg("--ro-bind-try").arg(path).arg(path);
+                }
+                command.args(["--proc", "/proc", "--dev", "/dev", "--tmpfs", "/tmp"]);
+                command.arg("--bind").arg(&work_dir).arg(&work_dir);
+                command.arg("--chdir").arg(&work_dir).arg("--");
+                command
+            }
+            Tools::Unshare { unshare, .. } => {
+                let mut command = tokio::process::Command::new(unshare);
+                command.args(["--user", "--map-root-user", "--mount", "--pid"]);
+                if self.network == NetworkPolicy::None {
+                    command.arg("--net");
+                }
+                command
+                    .args(["--fork", "--kill-child", "/bin/sh", "-c"])
+                    .arg(self.unshare_script(&read_only, &work_dir))
+                    .arg("mcp-sandbox");
+                command
+            }
+        };
+        command.arg(program).args(args);
+        command.current_dir(&work_dir);
+        Ok(command)
+    }
+
+    /// Paths mounted read-only: runtime paths, the executable's directory,
+    /// and the allowed paths
+    fn read_only_paths(&self, program: &str) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = RUNTIME_PATHS.iter().map(PathBuf::from).collect();
+        let executable_dir = diagnostics::find_executable(program)
+            .filter(|path| path.is_absolute())
+            .and_then(|path| path.parent().map(Path::to_path_buf));
+        if let Some(dir) = executable_dir {
+            if !paths.iter().any(|path| dir.starts_with(path)) {
+                paths.push(dir);
+            }
+        }
+        paths.extend(self.allow_paths.iter().cloned());
+        paths
+    }
+
+    /// Shell script building the root in the new namespaces, then running
+    /// its arguments without capabilities
+    ///
+    /// The root is a tmpfs on a fixed, empty directory, mounted only in the
+    /// sandbox's mount namespace. Relative symlinks such as `/bin -> usr/bin`
+    /// are recreated rather than mounted.
+    fn unshare_script(&self, read_only: &[PathBuf], work_dir: &Path) -> String {
+        let Tools::Unshare {
+            mount,
+            umount,
+            pivot_root,
+            setpriv,
+            ip,
+            ..
+        } = &self.tools
+        else {
+            unreachable!("script is only built for unshare")
+        };
+        let root = std::env::temp_dir().join("mcp-sandbox-root");
+        let mut script = format!(
+            r#"set -e
+mount={mount}
+root={root}
+bind() {{
+  if [ -L "$1" ] && [ "$(readlink "$1" | cut -c1)" != / ]; then
+    mkdir -p "$root$(dirname "$1")"
+    ln -s "$(readlink "$1")" "$root$1"
+    return
+  fi
+  if [ -d "$1" ]; then mkdir -p "$root$1"; else mkdir -p "$root$(dirname "$1")"; touch "$root$1"; fi
+  "$mount" --rbind "$1" "$root$1"
+  if [ "$2" = ro ]; then "$mount" -o remount,bind,ro "$root$1"; fi
+}}
+mkdir -p "$root"
+"$mount" -t tmpfs -o mode=755 tmpfs "$root"
+mkdir -p "$root/proc" "$root/dev" "$root/tmp"
+"$mount" -t proc proc "$root/proc"
+"$mount" --rbind /dev "$root/dev"
+"$mount" -t tmpfs tmpfs "$root/tmp"
+"#,
+            mount = quote(mount),
+            root = quote(&root),
+        );
+        if let Some(ip) = ip.as_ref().filter(|_| self.network == NetworkPolicy::None) {
+            script.push_str(&format!("{} link set lo up\n", quote(ip)));
+        }
+        for path in read_only {
+            script.push_str(&format!(
+                "if [ -e {path} ]; then bind {path} ro; fi\n",
+                path = quote(path)
+            ));
+        }
+        script.push_str(&format!(
+            r#"bind {work_dir} rw
+cd "$root"
+mkdir .oldroot
+{pivot_root} . .oldroot
+{umount} -l /.oldroot
+rmdir /.oldroot
+cd {work_dir}
+exec {setpriv} --bounding-set=-all --inh-caps=-all --no-new-privs -- "$@"
+"#,
+            work_dir = quote(work_dir),
+            pivot_root = quote(pivot_root),
+            umount = quote(umount),
+            setpriv = quote(setpriv),
+        ));
+        script
+    }
+}
+
+impl SandboxBackend {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SandboxBackend::Bwrap => "bwrap",
+            SandboxBackend::Unshare => "unshare",
+        }
+    }
+}
+
+fn unavailable(reason: String) -> McpCoreError {
+    McpCoreError::ConfigurationError {
+        message: format!("Sandbox unavailable: {}", reason),
+    }
+}
+
+fn bwrap() -> McpCoreResult<Tools> {
+    find_tool("bwrap").map(|bwrap| Tools::Bwrap { bwrap })
+}
+
+/// The util-linux tools, after checking that unprivileged user namespaces
+/// work, with a network namespace too if `isolate_network`
+async fn unshare(isolate_network: bool) -> McpCoreResult<Tools> {
+    let unshare = find_tool("unshare")?;
+    let tools = Tools::Unshare {
+        mount: find_tool("mount")?,
+        umount: find_tool("umount")?,
+        pivot_root: find_tool("pivot_root")?,
+        setpriv: find_tool("setpriv")?,
+        ip: match isolate_network {
+            true => Some(find_tool("ip")?),
+            false => None,
+        },
+        unshare: unshare.clone(),
+    };
+    let mut probe = tokio::process::Command::new(&unshare);
+    probe.args(["--user", "--map-root-user", "--mount", "--pid"]);
+    if isolate_network {
+        probe.arg("--net");
+    }
+    let output = probe
+        .args(["--fork", "true"])
+        .stdin(std::process::Stdio::null())
+        .output()
+        .await
+        .map_err(|e| unavailable(format!("failed to run unshare: {}", e)))?;
+    if !output.status.success() {
+        return Err(unavailable(format!(
+            "unshare cannot create user namespaces ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(tools)
+}
+
+/// Resolve a tool on PATH or in the usual system directories
+fn find_tool(name: &str) -> McpCoreResult<PathBuf> {
+    diagnostics::find_executable(name)
+        .or_else(|| {
+            SYSTEM_BIN_DIRS
+                .iter()
+                .map(|dir| Path::new(dir).join(name))
+                .find(|path| path.is_file())
+        })
+        .ok_or_else(|| unavailable(format!("{} not found", name)))
+}
+
+/// Single-quote a path for `sh`
+fn quote(path: &Path) -> String {
+    format!("'{}'", path.to_string_lossy().replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_parsed_and_validated() {
+        let config: SandboxConfig = serde_json::from_value(serde_json::json!({
+            "filesystem": "workdir-only",
+            "allow_paths": ["/opt/data"],
+            "backend": "unshare"
+        }))
+        .unwrap();
+        assert_eq!(config.backend, BackendChoice::Unshare);
+        assert!(config.validate().is_ok());
+
+        let relative = SandboxConfig {
+            allow_paths: vec!["data".to_string()],
+            ..Default::default()
+        };
+        assert!(relative.validate().unwrap_err().contains("'data'"));
+
+        let config: SandboxConfig = serde_json::from_value(serde_json::json!({
+            "network": "allowlist",
+            "allow_hosts": ["api.github.com:443", "*.corp.example:*"]
+        }))
+        .unwrap();
+        assert_eq!(config.network, NetworkPolicy::Allowlist);
+        assert!(config.validate().is_ok());
+        let bad_host = SandboxConfig {
+            allow_hosts: vec!["api.github.com".to_string()],
+            ..config.clone()
+        };
+        assert!(bad_host.validate().unwrap_err().contains("host:port"));
+        let without_allowlist = SandboxConfig {
+            network: NetworkPolicy::None,
+            ..config
+        };
+        assert!(without_allowlist
+            .validate()
+            .unwrap_err()
+            .contains("requires network"));
+        assert_eq!(quote(Path::new("/a'b")), r"'/a'\''b'");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_child_sees_only_allowed_paths() {
+        let dir = std::env::temp_dir().join(format!("mcp-sandbox-{}", std::process::id()));
+        let work_dir = dir.join("work");
+        let allowed = dir.join("allowed");
+        std::fs::create_dir_all(&work_dir).unwrap();
+        std::fs::create_dir_all(&allowed).unwrap();
+        std::fs::write(dir.join("secret"), "secret").unwrap();
+        std::fs::write(allowed.join("shared"), "shared\n").unwrap();
+
+        let config = SandboxConfig {
+            allow_paths: vec![allowed.to_string_lossy().into_owned()],
+            backend: BackendChoice::Unshare,
+            ..Default::default()
+        };
+        let sandbox = match config.resolve("test").await {
+            Ok(sandbox) => sandbox,
+            Err(e) => {
+                // Requested sandboxing fails loudly; nothing to run here
+                assert!(e.to_string().contains("Sandbox unavailable"));
+                eprintln!("Skipping sandbox test: {}", e);
+                let _ = std::fs::remove_dir_all(&dir);
+                return;
+            }
+        };
+        assert_eq!(sandbox.backend(), SandboxBackend::Unshare);
+
+        let script = format!(
+            "cat {secret} || echo hidden; cat {shared}; echo ok > written; echo x > {shared} || echo read-only",
+            secret = quote(&dir.join("secret")),
+            shared = quote(&allowed.join("shared")),
+        );
+        let output = sandbox
+            .command("sh", &["-c".to_string(), script], &work_dir)
+            .unwrap()
+            .output()
+            .await
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(stdout, "hidden\nshared\nread-only\n");
+        assert_eq!(
+            std::fs::read_to_string(work_dir.join("written")).unwrap(),
+            "ok\n"
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_network_none_leaves_only_loopback() {
+        let work_dir = std::env::temp_dir().join(format!("mcp-sandbox-net-{}", std::process::id()));
+        std::fs::create_dir_all(&work_dir).unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let config = SandboxConfig {
+            network: NetworkPolicy::None,
+            backend: BackendChoice::Unshare,
+            ..Default::default()
+        };
+        let sandbox = match config.resolve("test").await {
+            Ok(sandbox) => sandbox,
+            Err(e) => {
+                assert!(e.to_string().contains("Sandbox unavailable"));
+                eprintln!("Skipping sandbox test: {}", e);
+                let _ = std::fs::remove_dir_all(&work_dir);
+                return;
+            }
+        };
+
+        // The gateway's listener is out of reach; the sandbox's own loopback works
+        let script = format!(
+            "(echo > /dev/tcp/127.0.0.1/{port}) 2>/dev/null && echo reached || echo isolated",
+            port = port
+        );
+        let output = sandbox
+            .command("bash", &["-c".to_string(), script], &work_dir)
+            .unwrap()
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "isolated\n");
+        let output = sandbox
+            .command("ip", &["-o".to_string(), "link".to_string()], &work_dir)
+            .unwrap()
+            .output()
+            .await
+            .unwrap();
+        let links = String::from_utf8_lossy(&output.stdout);
+        assert!(links.contains("lo:") && links.contains("UP"), "{}", links);
+        assert_eq!(links.lines().count(), 1, "{}", links);
+        let _ = std::fs::remove_dir_all(&work_dir);
+    }
+}
@@ -0,0 +1,374 @@
+//! Starter configurations for `--init` and `--example`
+//!
+//! `--init` writes a configuration for a server in a Git repository, built and
+//! started with the usual commands of its runtime. A few known-good
+//! configurations are compiled into the binary; `--init --example <name>`
+//! writes one, and `--example <name>` alone serves it without a config file.
+//! Everything generated goes through the same parsing and validation as a
+//! config file, so the starters cannot drift from what the gateway accepts.
+
+use crate::config::McpServersConfig;
+use crate::error::{McpCoreError, McpCoreResult};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+/// Config file the binary reads when `MCP_CONFIG_FILE` is not set
+pub const DEFAULT_CONFIG_FILE: &str = "mcp_servers.config.json";
+
+/// Server name used when none can be derived from the repository
+const FALLBACK_SERVER_NAME: &str = "mcp-server";
+
+/// A configuration compiled into the binary, serving one server of the same name
+#[derive(Debug, Clone, Copy)]
+pub struct Example {
+    pub name: &'static str,
+    pub description: &'static str,
+    source: &'static str,
+}
+
+/// Configurations available to `--example`
+pub const EXAMPLES: &[Example] = &[
+    Example {
+        name: "echo",
+        description: "MCP reference server with an echo tool, run through npx",
+        source: include_str!("../examples/configs/echo.json"),
+    },
+    Example {
+        name: "filesystem",
+        description: "MCP filesystem server confined to its work directory",
+        source: include_str!("../examples/configs/filesystem.json"),
+    },
+];
+
+impl Example {
+    /// Example with the given name
+    pub fn find(name: &str) -> McpCoreResult<&'static Example> {
+        EXAMPLES
+            .iter()
+            .find(|example| example.name == name)
+            .ok_or_else(|| McpCoreError::ConfigurationError {
+                message: format!(
+                    "Unknown example '{}' (available: {})",
+                    name,
+                    EXAMPLES
+                        .iter()
+                        .map(|example| example.name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            })
+    }
+
+    /// The example as a starter configuration
+    pub fn scaffold(&self) -> McpCoreResult<Scaffold> {
+        Scaffold::checked(self.name.to_string(), self.source.to_string())
+    }
+
+    /// The example, parsed and validated for serving
+    pub fn config(&self) -> McpCoreResult<McpServersConfig> {
+        self.scaffold()?.parse()
+    }
+}
+
+/// Runtime of a server in a repository
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Runtime {
+    Node,
+    Python,
+}
+
+impl Runtime {
+    /// Parse a runtime name as given on the command line
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "node" | "nodejs" | "node.js" => Some(Runtime::Node),
+            "python" | "python3" => Some(Runtime::Python),
+            _ => None,
+        }
+    }
+
+    fn build_command(self) -> &'static str {
+        match self {
+            Runtime::Node => "npm install && npm run build",
+            Runtime::Python => "python3 -m venv .venv && .venv/bin/pip install -r requirements.txt",
+        }
+    }
+
+    fn command(self) -> &'static str {
+        match self {
+            Runtime::Node => "node",
+            Runtime::Python => ".venv/bin/python",
+        }
+    }
+
+    fn args(self) -> &'static [&'static str] {
+        match self {
+            Runtime::Node => &["dist/index.js"],
+            Runtime::Python => &["server.py"],
+        }
+    }
+}
+
+/// What `--init` scaffolds for a repository
+#[derive(Debug, Clone)]
+pub struct InitOptions {
+    pub runtime: Runtime,
+    pub repository: String,
+
+    /// Name of the server; derived from the repository if unset
+    pub server_name: Option<String>,
+}
+
+/// A validated starter configuration
+#[derive(Debug, Clone)]
+pub struct Scaffold {
+    /// The server to pass as `MCP_SERVER_NAME`
+    pub server_name: String,
+
+    /// The configuration as written, fields in the order users read them
+    pub content: String,
+}
+
+#[derive(Serialize)]
+struct StarterConfig<'a> {
+    version: &'a str,
+    servers: BTreeMap<&'a str, StarterServer<'a>>,
+}
+
+#[derive(Serialize)]
+struct StarterServer<'a> {
+    repository: &'a str,
+    build_command: &'a str,
+    force_build: bool,
+    setup_mode: &'a str,
+    command: &'a str,
+    args: &'a [&'a str],
+    env: BTreeMap<&'a str, &'a str>,
+}
+
+impl Scaffold {
+    /// Starter configuration for a server in a repository
+    ///
+    /// Fields most users end up changing are written out with their defaults.
+    pub fn new(options: &InitOptions) -> McpCoreResult<Self> {
+        let server_name = options
+            .server_name
+            .clone()
+            .unwrap_or_else(|| server_name_from_repository(&options.repository));
+        let runtime = options.runtime;
+        let server = StarterServer {
+            repository: &options.repository,
+            build_command: runtime.build_command(),
+            force_build: false,
+            setup_mode: "on-start",
+            command: runtime.command(),
+            args: runtime.args(),
+            env: BTreeMap::new(),
+        };
+        let content = serde_json::to_string_pretty(&StarterConfig {
+            version: "1.0",
+            servers: BTreeMap::from([(server_name.as_str(), server)]),
+        })?;
+        Self::checked(server_name, format!("{}\n", content))
+    }
+
+    /// Scaffold of `content`, after checking it serves `server_name` and
+    /// passes validation
+    fn checked(server_name: String, content: String) -> McpCoreResult<Self> {
+        let scaffold = Self {
+            server_name,
+            content,
+        };
+        scaffold.parse()?.get_server(&scaffold.server_name)?;
+        Ok(scaffold)
+    }
+
+    /// Parse and validate the configuration like a config file
+    pub fn parse(&self) -> McpCoreResult<McpServersConfig> {
+        McpServersConfig::from_value(
+            serde_json::from_str(&self.content)?,
+            &format!("starter config for '{}'", self.server_name),
+        )
+    }
+
+    /// Write the configuration to `path`, refusing to replace a file unless
+    /// `force` is set
+    pub async fn write(&self, path: &Path, force: bool) -> McpCoreResult<()> {
+        if !force && tokio::fs::try_exists(path).await.unwrap_or(true) {
+            return Err(McpCoreError::ConfigurationError {
+                message: format!(
+                    "'{}' already exists; pass --force to overwrite it",
+                    path.display()
+                ),
+            });
+        }
+        tokio::fs::write(path, &self.content)
+            .await
+            .map_err(|e| McpCoreError::ConfigurationError {
+                message: format!("Failed to write '{}': {}", path.display(), e),
+            })
+    }
+
+    /// Shell command starting the gateway with the configuration at `path`
+    pub fn run_command(&self, path: &str) -> String {
+        let config_file = if path == DEFAULT_CONFIG_FILE {
+            String::new()
+        } else {
+            format!("MCP_CONFIG_FILE={} ", path)
+        };
+        format!(
+            "{}MCP_SERVER_NAME={} {}",
+            config_file,
+            self.server_name,
+            env!("CARGO_PKG_NAME")
+        )
+    }
+}
+
+/// Server name from the last segment of a repository URL
+///
+/// Characters other than ASCII letters, digits, `-`, and `_` are replaced.
+pub fn server_name_from_repository(repository: &str) -> String {
+    let segment = repository
+        .trim_end_matches('/')
+        .rsplit(['/', ':'])
+        .next()
+        .unwrap_or_default();
+    let segment = segment.strip_suffix(".git").unwrap_or(segment);
+    let name: String = segment
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    let name = name.trim_matches('-');
+    if name.is_empty() {
+        FALLBACK_SERVER_NAME.to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Ask for the options of `--init` on `output`, reading answers from `input`
+///
+/// Invalid answers are asked again; running out of input is an error.
+pub fn prompt(input: &mut impl BufRead, output: &mut impl Write) -> io::Result<InitOptions> {
+    let runtime = loop {
+        let answer = ask(input, output, "Runtime (node, python) [node]: ")?;
+        if answer.is_empty() {
+            break Runtime::Node;
+        }
+        match Runtime::parse(&answer) {
+            Some(runtime) => break runtime,
+            None => writeln!(output, "Unknown runtime '{}'", answer)?,
+        }
+    };
+    let repository = loop {
+        let answer = ask(input, output, "Git repository URL: ")?;
+        if !answer.is_empty() {
+            break answer;
+        }
+    };
+    let derived = server_name_from_repository(&repository);
+    let answer = ask(input, output, &format!("Server name [{}]: ", derived))?;
+    Ok(InitOptions {
+        runtime,
+        repository,
+        server_name: (!answer.is_empty()).then_some(answer),
+    })
+}
+
+fn ask(input: &mut impl BufRead, output: &mut impl Write, question: &str) -> io::Result<String> {
+    write!(output, "{}", question)?;
+    output.flush()?;
+    let mut answer = String::new();
+    if input.read_line(&mut answer)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "input ended before --init was answered",
+        ));
+    }
+    Ok(answer.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_examples_pass_validation() {
+        for example in EXAMPLES {
+            let config = example.config().unwrap();
+            assert_eq!(config.servers.len(), 1, "example '{}'", example.name);
+            assert!(config.get_server(example.name).is_ok());
+        }
+        let error = Example::find("nope").unwrap_err().to_string();
+        assert!(error.contains("available: echo, filesystem"));
+    }
+
+    #[test]
+    fn test_starter_configs_pass_validation() {
+        for runtime in [Runtime::Node, Runtime::Python] {
+            let scaffold = Scaffold::new(&InitOptions {
+                runtime,
+                repository: "https://github.com/example/My.Server.git".to_string(),
+                server_name: None,
+            })
+            .unwrap();
+            assert_eq!(scaffold.server_name, "my-server");
+            let config = scaffold.parse().unwrap();
+            let server = config.get_server("my-server").unwrap();
+            assert_eq!(server.command, runtime.command());
+        }
+
+        assert_eq!(
+            server_name_from_repository("git@github.com:yonaka15/mcp-server-redmine.git"),
+            "mcp-server-redmine"
+        );
+        assert_eq!(
+            server_name_from_repository("https://"),
+            FALLBACK_SERVER_NAME
+        );
+        assert_eq!(Runtime::parse("Node.js"), Some(Runtime::Node));
+        assert_eq!(Runtime::parse("ruby"), None);
+    }
+
+    #[tokio::test]
+    async fn test_prompted_config_written_once() {
+        let mut input =
+            io::Cursor::new("ruby\npython\n\nhttps://github.com/example/weather\nforecast\n");
+        let mut output = Vec::new();
+        let options = prompt(&mut input, &mut output).unwrap();
+        assert_eq!(options.runtime, Runtime::Python);
+        assert_eq!(options.server_name.as_deref(), Some("forecast"));
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("Unknown runtime 'ruby'"));
+        assert!(prompt(&mut io::Cursor::new("node\n"), &mut Vec::new()).is_err());
+
+        let scaffold = Scaffold::new(&options).unwrap();
+        let dir = std::env::temp_dir().join(format!("mcp-scaffold-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(DEFAULT_CONFIG_FILE);
+        let _ = std::fs::remove_file(&path);
+        scaffold.write(&path, false).await.unwrap();
+        assert!(scaffold.write(&path, false).await.is_err());
+        scaffold.write(&path, true).await.unwrap();
+
+        let written = McpServersConfig::load_from_file(path.to_str().unwrap())
+            .await
+            .unwrap();
+        assert!(written.get_server("forecast").is_ok());
+        assert_eq!(
+            scaffold.run_command(DEFAULT_CONFIG_FILE),
+            "MCP_SERVER_NAME=forecast mcp-server-as-http-core"
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
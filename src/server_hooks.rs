@@ -0,0 +1,192 @@
+//! Commands run before a server starts and after it stops
+//!
+//! Like init containers, `pre_start` hooks prepare what a server needs
+//! beyond its build, such as a database migration or a data snapshot. They
+//! run in order after the clone and build and before the server is spawned
+//! or connected to, in its work directory, through its `shell`, and with
+//! the build environment plus their own `env`. Their output is logged as it
+//! comes, secrets redacted, and kept in the build log. A hook failing or
+//! running past its timeout fails the start, naming the hook by its index.
+//!
+//! A `run_once` hook is skipped once it has succeeded, so restarting the
+//! child does not repeat it; other hooks run before every start. `post_stop`
+//! hooks run in order after the child exited on a graceful shutdown, or
+//! when the process manager stops the server; a failing one is logged and
+//! the rest still run.
+
+use crate::build_log::StepKind;
+use crate::child_env::ChildEnv;
+use crate::config::McpServerConfig;
+use crate::error::{McpCoreError, McpCoreResult};
+use crate::manager;
+use crate::platform::{self, Shell};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Time a hook may run when it sets no `timeout_secs`
+pub const DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// One command of `pre_start` or `post_stop`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ServerHook {
+    /// Command line, run through the server's `shell`
+    pub command: String,
+
+    /// Seconds before the hook is killed and counted as failed (default 300)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+
+    /// Environment variables on top of the build environment
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+
+    /// Skip the hook once it has succeeded, instead of running it before
+    /// every start
+    #[serde(default)]
+    pub run_once: bool,
+}
+
+impl ServerHook {
+    /// Check the hook can run with `shell`
+    pub fn validate(&self, shell: Shell) -> Result<(), String> {
+        if self.timeout_secs == Some(0) {
+            return Err("has a timeout_secs of 0".to_string());
+        }
+        if shell == Shell::None {
+            platform::split(&self.command)?;
+        }
+        Ok(())
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout_secs
+            .map_or(DEFAULT_HOOK_TIMEOUT, Duration::from_secs)
+    }
+}
+
+/// Which list a hook belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Phase {
+    PreStart,
+    PostStop,
+}
+
+impl Phase {
+    fn name(self) -> &'static str {
+        match self {
+            Self::PreStart => "pre_start",
+            Self::PostStop => "post_stop",
+        }
+    }
+
+    fn step_kind(self) -> StepKind {
+        match self {
+            Self::PreStart => StepKind::PreStart,
+            Self::PostStop => StepKind::PostStop,
+        }
+    }
+}
+
+/// The hooks of a server, and which `run_once` hooks already succeeded
+///
+/// One is kept for as long as the server is supervised, so the restarts it
+/// goes through share what ran.
+#[derive(Debug, Default)]
+pub struct ServerHooks {
+    pre_start: Vec<ServerHook>,
+    post_stop: Vec<ServerHook>,
+    shell: Shell,
+    work_dir: String,
+    env: ChildEnv,
+    succeeded: Mutex<HashSet<(Phase, usize)>>,
+}
+
+impl ServerHooks {
+    /// Hooks of `server_name`, running in its work directory
+    pub fn new(config: &McpServerConfig, server_name: &str) -> Self {
+        Self {
+            pre_start: config.pre_start.clone(),
+            post_stop: config.post_stop.clone(),
+            shell: config.shell,
+            work_dir: config.work_dir(server_name),
+            env: config.build_child_env(),
+            succeeded: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Whether there are `pre_start` hooks
+    pub fn has_pre_start(&self) -> bool {
+        !self.pre_start.is_empty()
+    }
+
+    /// Run the `pre_start` hooks in order, failing at the first that fails
+    pub async fn pre_start(&self) -> McpCoreResult<()> {
+        for (index, hook) in self.pre_start.iter().enumerate() {
+            self.run(Phase::PreStart, index, hook).await?;
+        }
+        Ok(())
+    }
+
+    /// Run every `post_stop` hook in order, logging failures
+    pub async fn post_stop(&self) {
+        for (index, hook) in self.post_stop.iter().enumerate() {
+            if let Err(e) = self.run(Phase::PostStop, index, hook).await {
+                tracing::warn!("{}", e);
+            }
+        }
+    }
+
+    async fn run(&self, phase: Phase, index: usize, hook: &ServerHook) -> McpCoreResult<()> {
+        let label = format!("{}[{}]", phase.name(), index);
+        if hook.run_once && self.lock().contains(&(phase, index)) {
+            tracing::debug!("Skipping {}, it already succeeded", label);
+            return Ok(());
+        }
+        let mut env = self.env.clone();
+        env.vars.extend(
+            hook.env
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone())),
+        );
+        let logged_cmd = env.redact(&hook.command);
+        tracing::info!("Running {}: {}", label, logged_cmd);
+        // Servers reached over the network have no work directory of their own yet
+        tokio::fs::create_dir_all(&self.work_dir)
+            .await
+            .map_err(|e| McpCoreError::ProcessError {
+                message: format!("Failed to create work directory '{}': {}", self.work_dir, e),
+            })?;
+
+        let output = manager::execute_command(
+            phase.step_kind(),
+            &label,
+            &hook.command,
+            self.shell,
+            &self.work_dir,
+            &env,
+            Some(hook.timeout()),
+        )
+        .await?;
+        if !output.status.success() {
+            let exit = match output.status.code() {
+                Some(code) => format!("exit code {}", code),
+                None => "a signal".to_string(),
+            };
+            return Err(McpCoreError::ProcessError {
+                message: format!("{} failed with {}: {}", label, exit, logged_cmd),
+            });
+        }
+        if hook.run_once {
+            self.lock().insert((phase, index));
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashSet<(Phase, usize)>> {
+        self.succeeded
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
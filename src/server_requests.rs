@@ -0,0 +1,290 @@
+//! Handling of requests sent by the MCP server to the client
+//!
+//! MCP servers may call back into the client with `sampling/createMessage`,
+//! `roots/list`, `elicitation/create`, or `ping`. These arrive interleaved with responses and are
+//! answered by the gateway so the server never waits on a reply that will
+//! not come. Embedders supply real implementations through
+//! [`crate::http_server::McpHttpServerBuilder`].
+
+use crate::config::RootConfig;
+use crate::error::McpCoreResult;
+use serde_json::Value;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// JSON-RPC error code for unsupported methods
+pub const METHOD_NOT_FOUND: i64 = -32601;
+
+/// JSON-RPC error code for handler failures
+pub const INTERNAL_ERROR: i64 = -32603;
+
+/// Error returned by a server request handler
+#[derive(Debug, Clone)]
+pub struct ServerRequestError {
+    /// JSON-RPC error code
+    pub code: i64,
+
+    /// Error message sent to the MCP server
+    pub message: String,
+}
+
+impl ServerRequestError {
+    /// Create an error with the given JSON-RPC code and message
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Future returned by a server request handler
+pub type ServerRequestFuture =
+    Pin<Box<dyn Future<Output = Result<Value, ServerRequestError>> + Send>>;
+
+/// Async handler receiving the request `params` and returning its `result`
+pub type ServerRequestHandler = Arc<dyn Fn(Value) -> ServerRequestFuture + Send + Sync>;
+
+/// Handlers for server-initiated requests
+#[derive(Clone, Default)]
+pub struct ServerRequestHandlers {
+    /// Handler for `sampling/createMessage`; unsupported when absent
+    pub sampling: Option<ServerRequestHandler>,
+
+    /// Handler for `roots/list`; an empty list is returned when absent
+    pub roots: Option<ServerRequestHandler>,
+
+    /// Handler for `elicitation/create`; unsupported when absent
+    pub elicitation: Option<ServerRequestHandler>,
+}
+
+impl std::fmt::Debug for ServerRequestHandlers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServerRequestHandlers")
+            .field("sampling", &self.sampling.is_some())
+            .field("roots", &self.roots.is_some())
+            .field("elicitation", &self.elicitation.is_some())
+            .finish()
+    }
+}
+
+impl ServerRequestHandlers {
+    /// Client capabilities to advertise in `initialize`
+    ///
+    /// Each capability is only advertised when its handler is registered.
+    pub fn capabilities(&self) -> Value {
+        let mut capabilities = serde_json::json!({});
+        if self.roots.is_some() {
+            // Roots are fixed for the lifetime of the process
+            capabilities["roots"] = serde_json::json!({ "listChanged": false });
+        }
+        if self.sampling.is_some() {
+            capabilities["sampling"] = serde_json::json!({});
+        }
+        if self.elicitation.is_some() {
+            capabilities["elicitation"] = serde_json::json!({});
+        }
+        capabilities
+    }
+
+    /// Answer a server-initiated request, returning the JSON-RPC response
+    pub async fn handle(&self, request: &Value) -> Value {
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let method = request
+            .get("method")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+        tracing::debug!("Handling server-initiated request '{}'", method);
+
+        let result = match (method, &self.sampling, &self.roots) {
+            ("ping", _, _) => Ok(serde_json::json!({})),
+            ("sampling/createMessage", Some(handler), _) => handler(params).await,
+            ("sampling/createMessage", None, _) => Err(ServerRequestError::new(
+                METHOD_NOT_FOUND,
+                "Sampling is not supported by this client",
+            )),
+            ("roots/list", _, Some(handler)) => handler(params).await,
+            ("roots/list", _, None) => Ok(serde_json::json!({ "roots": [] })),
+            ("elicitation/create", _, _) => match &self.elicitation {
+                Some(handler) => handler(params).await,
+                None => Err(ServerRequestError::new(
+                    METHOD_NOT_FOUND,
+                    "Elicitation is not supported by this client",
+                )),
+            },
+            (method, _, _) => Err(ServerRequestError::new(
+                METHOD_NOT_FOUND,
+                format!("Method '{}' is not supported by this client", method),
+            )),
+        };
+
+        match result {
+            Ok(result) => serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "result": result
+            }),
+            Err(error) => {
+                tracing::warn!(
+                    "Server-initiated request '{}' failed: {}",
+                    method,
+                    error.message
+                );
+                serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": {
+                        "code": error.code,
+                        "message": error.message
+                    }
+                })
+            }
+        }
+    }
+}
+
+/// Whether a message is a request from the server rather than a response
+///
+/// A message carrying the id of the pending client request is treated as its
+/// response, since the server cannot expect a reply under that id.
+pub fn is_server_request(message: &Value, pending_id: Option<&Value>) -> bool {
+    message.get("method").is_some() && message.get("id").is_some_and(|id| Some(id) != pending_id)
+}
+
+/// Handler answering `roots/list` with the configured roots
+pub fn static_roots(roots: &[RootConfig]) -> ServerRequestHandler {
+    let result = serde_json::json!({ "roots": roots });
+    Arc::new(move |_params: Value| {
+        let result = result.clone();
+        Box::pin(async move { Ok(result) })
+    })
+}
+
+/// Handler forwarding sampling requests to an HTTP endpoint
+///
+/// The request `params` are POSTed as JSON and the response body is used as
+/// the `result`.
+#[cfg(feature = "reqwest")]
+pub fn webhook_handler(url: &str) -> McpCoreResult<ServerRequestHandler> {
+    let client = reqwest::Client::new();
+    let url = url.to_string();
+    Ok(Arc::new(move |params: Value| {
+        let client = client.clone();
+        let url = url.clone();
+        Box::pin(async move {
+            let internal = |message: String| ServerRequestError::new(INTERNAL_ERROR, message);
+            let response = client
+                .post(&url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(params.to_string())
+                .send()
+                .await
+                .map_err(|e| internal(format!("Sampling webhook request failed: {}", e)))?;
+            if !response.status().is_success() {
+                return Err(internal(format!(
+                    "Sampling webhook returned HTTP {}",
+                    response.status()
+                )));
+            }
+            let body = response.text().await.map_err(|e| {
+                internal(format!("Failed to read sampling webhook response: {}", e))
+            })?;
+            serde_json::from_str(&body)
+                .map_err(|e| internal(format!("Sampling webhook returned invalid JSON: {}", e)))
+        })
+    }))
+}
+
+/// Handler forwarding sampling requests to an HTTP endpoint
+///
+/// Unavailable without the `reqwest` feature.
+#[cfg(not(feature = "reqwest"))]
+pub fn webhook_handler(url: &str) -> McpCoreResult<ServerRequestHandler> {
+    Err(crate::error::McpCoreError::ConfigurationError {
+        message: format!("sampling_webhook '{}' requires the 'reqwest' feature", url),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_default_handlers() {
+        let handlers = ServerRequestHandlers::default();
+        assert_eq!(handlers.capabilities(), serde_json::json!({}));
+
+        let request = serde_json::json!({ "jsonrpc": "2.0", "id": 7, "method": "roots/list" });
+        let response = handlers.handle(&request).await;
+        assert_eq!(response["id"], 7);
+        assert_eq!(response["result"]["roots"], serde_json::json!([]));
+
+        let request =
+            serde_json::json!({ "jsonrpc": "2.0", "id": 8, "method": "sampling/createMessage" });
+        let response = handlers.handle(&request).await;
+        assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+
+        let request =
+            serde_json::json!({ "jsonrpc": "2.0", "id": 9, "method": "elicitation/create" });
+        let response = handlers.handle(&request).await;
+        assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+        assert!(handlers.capabilities().get("elicitation").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_registered_sampling_handler() {
+        let handlers = ServerRequestHandlers {
+            sampling: Some(Arc::new(|params: Value| {
+                Box::pin(async move { Ok(serde_json::json!({ "echo": params["prompt"] })) })
+                    as ServerRequestFuture
+            })),
+            roots: None,
+            elicitation: None,
+        };
+        assert!(handlers.capabilities().get("sampling").is_some());
+
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": "s1",
+            "method": "sampling/createMessage",
+            "params": { "prompt": "hi" }
+        });
+        let response = handlers.handle(&request).await;
+        assert_eq!(response["result"]["echo"], "hi");
+    }
+
+    #[tokio::test]
+    async fn test_static_roots() {
+        let roots = vec![RootConfig {
+            uri: "file:///data/projects".to_string(),
+            name: Some("Projects".to_string()),
+        }];
+        let handlers = ServerRequestHandlers {
+            sampling: None,
+            roots: Some(static_roots(&roots)),
+            elicitation: None,
+        };
+        assert_eq!(handlers.capabilities()["roots"]["listChanged"], false);
+
+        let request = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "roots/list" });
+        let response = handlers.handle(&request).await;
+        assert_eq!(
+            response["result"]["roots"],
+            serde_json::json!([{ "uri": "file:///data/projects", "name": "Projects" }])
+        );
+    }
+
+    #[test]
+    fn test_is_server_request() {
+        let request = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "roots/list" });
+        assert!(is_server_request(&request, None));
+        assert!(is_server_request(&request, Some(&serde_json::json!(2))));
+        assert!(!is_server_request(&request, Some(&serde_json::json!(1))));
+
+        let notification =
+            serde_json::json!({ "jsonrpc": "2.0", "method": "notifications/progress" });
+        assert!(!is_server_request(&notification, None));
+    }
+}
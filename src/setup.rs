@@ -0,0 +1,319 @@
+//! Shared executor for clone, build, and provisioning work
+//!
+//! Setting up a server clones, builds, hashes lockfiles, and packs archives,
+//! which saturates a machine when several servers set up at once. Every setup
+//! runs as a job of the process-wide [`SetupExecutor`], which runs at most
+//! `max_concurrent_setup_jobs` jobs at a time and queues the rest. CPU-bound
+//! steps go to the blocking thread pool through [`blocking`] so they do not
+//! stall the runtime serving requests. A job reports its phases while it runs
+//! and can be cancelled, which drops its work and kills the child processes
+//! it started.
+
+use crate::error::{McpCoreError, McpCoreResult};
+use crate::timing::{PhaseProgress, ProgressSnapshot};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::sync::{watch, Semaphore};
+
+/// Setup jobs run at once unless configured otherwise
+pub const DEFAULT_MAX_SETUP_JOBS: usize = 2;
+
+/// Whether a setup job waits for a slot or holds one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SetupJobState {
+    Queued,
+    Running,
+}
+
+/// Point-in-time view of a setup job
+#[derive(Debug, Clone, Serialize)]
+pub struct SetupJobSnapshot {
+    pub id: String,
+    pub server: String,
+    pub state: SetupJobState,
+    pub queued_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(flatten)]
+    pub progress: ProgressSnapshot,
+}
+
+/// Setup jobs of the process, served by the stats endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct SetupSnapshot {
+    pub max_concurrent: usize,
+    pub queued: usize,
+    pub running: usize,
+
+    /// Jobs cancelled since the process started
+    pub cancelled: u64,
+    pub jobs: Vec<SetupJobSnapshot>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    server: String,
+    state: SetupJobState,
+    queued_at: DateTime<Utc>,
+    started_at: Option<DateTime<Utc>>,
+    progress: Arc<PhaseProgress>,
+    cancel: watch::Sender<bool>,
+}
+
+/// Runs setup jobs, at most `max_concurrent` at a time
+#[derive(Debug)]
+pub struct SetupExecutor {
+    max_concurrent: usize,
+    slots: Semaphore,
+    jobs: Mutex<BTreeMap<u64, Entry>>,
+    next_job: AtomicU64,
+    cancelled: AtomicU64,
+}
+
+impl Default for SetupExecutor {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_SETUP_JOBS)
+    }
+}
+
+impl SetupExecutor {
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            max_concurrent,
+            slots: Semaphore::new(max_concurrent),
+            jobs: Mutex::new(BTreeMap::new()),
+            next_job: AtomicU64::new(1),
+            cancelled: AtomicU64::new(0),
+        }
+    }
+
+    /// The executor shared by every server in the process
+    ///
+    /// The first call sets the limit; later calls asking for another one
+    /// keep it and log a warning.
+    pub fn shared(max_concurrent: usize) -> Arc<Self> {
+        static SHARED: OnceLock<Arc<SetupExecutor>> = OnceLock::new();
+        let executor = SHARED.get_or_init(|| Arc::new(Self::new(max_concurrent)));
+        if executor.max_concurrent != max_concurrent {
+            tracing::warn!(
+                "Setup jobs are already limited to {}; ignoring max_concurrent_setup_jobs {}",
+                executor.max_concurrent,
+                max_concurrent
+            );
+        }
+        Arc::clone(executor)
+    }
+
+    /// Run `job` for `server` once a slot is free
+    ///
+    /// `progress` is reported as the job's phases. A cancelled job is
+    /// dropped where it is, queued or running.
+    pub async fn run<T>(
+        &self,
+        server: &str,
+        progress: Arc<PhaseProgress>,
+        job: impl Future<Output = McpCoreResult<T>>,
+    ) -> McpCoreResult<T> {
+        let id = self.next_job.fetch_add(1, Ordering::Relaxed);
+        let (cancel, mut cancelled) = watch::channel(false);
+        self.lock().insert(
+            id,
+            Entry {
+                server: server.to_string(),
+                state: SetupJobState::Queued,
+                queued_at: Utc::now(),
+                started_at: None,
+                progress,
+                cancel,
+            },
+        );
+        let _registered = Registered { executor: self, id };
+        let cancelled_error = || McpCoreError::ProcessError {
+            message: format!("Setup of server '{}' was cancelled", server),
+        };
+
+        let _slot = tokio::select! {
+            slot = self.slots.acquire() => slot.expect("setup slots are never closed"),
+            _ = cancelled.wait_for(|cancelled| *cancelled) => return Err(cancelled_error()),
+        };
+        if let Some(entry) = self.lock().get_mut(&id) {
+            entry.state = SetupJobState::Running;
+            entry.started_at = Some(Utc::now());
+        }
+        tokio::select! {
+            result = job => result,
+            _ = cancelled.wait_for(|cancelled| *cancelled) => Err(cancelled_error()),
+        }
+    }
+
+    /// Cancel the queued and running jobs of `server`, returning how many
+    pub fn cancel(&self, server: &str) -> usize {
+        let jobs = self.lock();
+        let mut count = 0;
+        for entry in jobs.values().filter(|entry| entry.server == server) {
+            if !entry.cancel.send_replace(true) {
+                count += 1;
+            }
+        }
+        if count > 0 {
+            tracing::info!("Cancelled {} setup job(s) of server '{}'", count, server);
+            self.cancelled.fetch_add(count as u64, Ordering::Relaxed);
+        }
+        count
+    }
+
+    pub fn snapshot(&self) -> SetupSnapshot {
+        let jobs: Vec<SetupJobSnapshot> = self
+            .lock()
+            .iter()
+            .map(|(id, entry)| SetupJobSnapshot {
+                id: format!("setup-{}", id),
+                server: entry.server.clone(),
+                state: entry.state,
+                queued_at: entry.queued_at,
+                started_at: entry.started_at,
+                progress: entry.progress.snapshot(),
+            })
+            .collect();
+        let running = jobs
+            .iter()
+            .filter(|job| job.state == SetupJobState::Running)
+            .count();
+        SetupSnapshot {
+            max_concurrent: self.max_concurrent,
+            queued: jobs.len() - running,
+            running,
+            cancelled: self.cancelled.load(Ordering::Relaxed),
+            jobs,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<u64, Entry>> {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Removes a job from the executor when it ends or is dropped
+struct Registered<'a> {
+    executor: &'a SetupExecutor,
+    id: u64,
+}
+
+impl Drop for Registered<'_> {
+    fn drop(&mut self) {
+        self.executor.lock().remove(&self.id);
+    }
+}
+
+/// Run CPU-bound setup work on the blocking thread pool
+pub async fn blocking<T: Send + 'static>(
+    task: impl FnOnce() -> McpCoreResult<T> + Send + 'static,
+) -> McpCoreResult<T> {
+    tokio::task::spawn_blocking(task)
+        .await
+        .map_err(|e| McpCoreError::RuntimeError {
+            message: format!("Setup task failed: {}", e),
+        })?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::sync::oneshot;
+
+    async fn wait_until(executor: &SetupExecutor, done: impl Fn(&SetupSnapshot) -> bool) {
+        for _ in 0..100 {
+            if done(&executor.snapshot()) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!(
+            "executor never reached the state: {:?}",
+            executor.snapshot()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_jobs_beyond_limit_wait_for_a_slot() {
+        let executor = Arc::new(SetupExecutor::new(1));
+        let (release, released) = oneshot::channel::<()>();
+        let first = tokio::spawn({
+            let executor = Arc::clone(&executor);
+            async move {
+                executor
+                    .run("first", Arc::default(), async {
+                        let _ = released.await;
+                        Ok("first")
+                    })
+                    .await
+            }
+        });
+        wait_until(&executor, |snapshot| snapshot.running == 1).await;
+
+        let second = tokio::spawn({
+            let executor = Arc::clone(&executor);
+            async move {
+                executor
+                    .run("second", Arc::default(), async { Ok("second") })
+                    .await
+            }
+        });
+        wait_until(&executor, |snapshot| snapshot.queued == 1).await;
+        let snapshot = executor.snapshot();
+        assert_eq!(snapshot.jobs[0].server, "first");
+        assert_eq!(snapshot.jobs[1].state, SetupJobState::Queued);
+        assert!(!second.is_finished());
+
+        release.send(()).unwrap();
+        assert_eq!(first.await.unwrap().unwrap(), "first");
+        assert_eq!(second.await.unwrap().unwrap(), "second");
+        assert!(executor.snapshot().jobs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_stops_running_and_queued_jobs() {
+        let executor = Arc::new(SetupExecutor::new(1));
+        let progress = Arc::new(PhaseProgress::default());
+        let spawn = |server: &'static str, progress: Arc<PhaseProgress>| {
+            let executor = Arc::clone(&executor);
+            tokio::spawn(async move {
+                let mut timer = crate::timing::PhaseTimer::observed(progress);
+                executor
+                    .run(server, timer.observe(), async {
+                        timer.measure("build", std::future::pending::<()>()).await;
+                        Ok(())
+                    })
+                    .await
+            })
+        };
+        let running = spawn("srv", Arc::clone(&progress));
+        wait_until(&executor, |snapshot| snapshot.running == 1).await;
+        let queued = spawn("srv", Arc::default());
+        let other = spawn("other", Arc::default());
+        wait_until(&executor, |snapshot| snapshot.queued == 2).await;
+        assert_eq!(
+            executor.snapshot().jobs[0].progress.current_phase,
+            Some("build")
+        );
+
+        assert_eq!(executor.cancel("srv"), 2);
+        for job in [running, queued] {
+            let error = job.await.unwrap().unwrap_err();
+            assert!(error.to_string().contains("was cancelled"));
+        }
+
+        // The freed slot goes to the next job
+        wait_until(&executor, |snapshot| snapshot.running == 1).await;
+        let snapshot = executor.snapshot();
+        assert_eq!(snapshot.jobs[0].server, "other");
+        assert_eq!(snapshot.cancelled, 2);
+        assert_eq!(executor.cancel("other"), 1);
+        assert!(other.await.unwrap().is_err());
+    }
+}
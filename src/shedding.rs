@@ -0,0 +1,314 @@
+//! Adaptive load shedding in front of the MCP server
+//!
+//! The shedder watches the latency of recent responses from the server and
+//! the number of requests queued for its transport. Once either exceeds its
+//! configured limit, a fraction of new requests is rejected straight away
+//! with `503 overloaded` instead of queueing behind the backlog. The fraction
+//! grows with the overshoot, and methods on the sheddable list are rejected
+//! before anything else. Rejections are spread evenly rather than drawn at
+//! random: each class accumulates its rejection rate as credit and rejects a
+//! request whenever the credit reaches one.
+
+use crate::error::{McpCoreError, McpCoreResult};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Methods shed first unless configured otherwise
+pub const DEFAULT_SHEDDABLE_METHODS: &[&str] = &[
+    "tools/list",
+    "resources/list",
+    "resources/templates/list",
+    "prompts/list",
+];
+
+/// Default `Retry-After` of rejected requests in seconds
+pub const DEFAULT_RETRY_AFTER_SECS: u64 = 1;
+
+/// Number of recent response latencies the p95 is computed from
+const LATENCY_SAMPLES: usize = 100;
+
+/// Age after which a latency sample no longer counts
+const SAMPLE_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// Highest fraction of any class of requests that is rejected, so some
+/// requests always get through and report whether the server recovered
+const MAX_REJECTION_RATE: f64 = 0.9;
+
+/// Per-server load shedding limits; shedding starts above either limit
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LoadSheddingConfig {
+    /// p95 of recent response latencies, in milliseconds
+    #[serde(default)]
+    pub p95_threshold_ms: Option<f64>,
+
+    /// Requests waiting for the transport
+    #[serde(default)]
+    pub max_queue_depth: Option<usize>,
+
+    /// Methods rejected before all others
+    #[serde(default = "default_sheddable_methods")]
+    pub sheddable_methods: Vec<String>,
+
+    /// `Retry-After` sent with rejections, in seconds
+    #[serde(default = "default_retry_after_secs")]
+    pub retry_after_secs: u64,
+}
+
+fn default_sheddable_methods() -> Vec<String> {
+    DEFAULT_SHEDDABLE_METHODS
+        .iter()
+        .map(|method| method.to_string())
+        .collect()
+}
+
+fn default_retry_after_secs() -> u64 {
+    DEFAULT_RETRY_AFTER_SECS
+}
+
+impl LoadSheddingConfig {
+    /// Check that at least one usable limit is set
+    pub fn validate(&self) -> Result<(), String> {
+        if self.p95_threshold_ms.is_none() && self.max_queue_depth.is_none() {
+            return Err("needs p95_threshold_ms or max_queue_depth".to_string());
+        }
+        if self
+            .p95_threshold_ms
+            .is_some_and(|ms| ms.is_nan() || ms <= 0.0)
+        {
+            return Err("p95_threshold_ms must be positive".to_string());
+        }
+        if self.max_queue_depth == Some(0) {
+            return Err("max_queue_depth must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Current shedding state, served by the stats endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct LoadSheddingSnapshot {
+    pub shedding: bool,
+
+    /// Fraction of requests currently rejected, before method preference
+    pub rejection_rate: f64,
+    pub p95_ms: Option<f64>,
+    pub queue_depth: usize,
+
+    /// Requests rejected since startup
+    pub rejected: u64,
+}
+
+#[derive(Default)]
+struct ShedState {
+    latencies: VecDeque<(Instant, f64)>,
+    /// Accumulated rejection rates in thousandths, kept as integers so
+    /// rejections stay evenly spaced
+    sheddable_credit: u32,
+    other_credit: u32,
+}
+
+/// Decides which requests to reject while the server is overloaded
+pub struct LoadShedder {
+    config: LoadSheddingConfig,
+    state: Mutex<ShedState>,
+    rejected: AtomicU64,
+}
+
+impl LoadShedder {
+    pub fn new(config: LoadSheddingConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(ShedState::default()),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Record how long the server took to answer a request
+    pub fn record_latency(&self, latency: Duration) {
+        let mut state = self.lock();
+        if state.latencies.len() == LATENCY_SAMPLES {
+            state.latencies.pop_front();
+        }
+        state
+            .latencies
+            .push_back((Instant::now(), latency.as_secs_f64() * 1000.0));
+    }
+
+    /// Admit a request for `method`, or reject it with
+    /// [`McpCoreError::Overloaded`]
+    pub fn admit(&self, method: Option<&str>, queue_depth: usize) -> McpCoreResult<()> {
+        let mut state = self.lock();
+        let rate = self.rejection_rate(&mut state, queue_depth);
+        if rate == 0.0 {
+            state.sheddable_credit = 0;
+            state.other_credit = 0;
+            return Ok(());
+        }
+
+        let sheddable = method.is_some_and(|method| {
+            self.config
+                .sheddable_methods
+                .iter()
+                .any(|sheddable| sheddable == method)
+        });
+        // Sheddable methods absorb the first half of the rate on their own
+        let (credit, class_rate) = if sheddable {
+            (&mut state.sheddable_credit, (rate * 2.0).min(1.0))
+        } else {
+            (&mut state.other_credit, (rate * 2.0 - 1.0).max(0.0))
+        };
+        *credit += (class_rate.min(MAX_REJECTION_RATE) * 1000.0).round() as u32;
+        if *credit < 1000 {
+            return Ok(());
+        }
+        *credit -= 1000;
+        drop(state);
+
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+        tracing::warn!(
+            "Shedding {} request at rejection rate {:.2}",
+            method.unwrap_or("unknown"),
+            rate
+        );
+        Err(McpCoreError::Overloaded {
+            message: format!(
+                "MCP server is overloaded; retry in {} seconds",
+                self.config.retry_after_secs
+            ),
+            retry_after_secs: self.config.retry_after_secs,
+        })
+    }
+
+    /// Current state with `queue_depth` requests waiting
+    pub fn snapshot(&self, queue_depth: usize) -> LoadSheddingSnapshot {
+        let mut state = self.lock();
+        let rejection_rate = self.rejection_rate(&mut state, queue_depth);
+        LoadSheddingSnapshot {
+            shedding: rejection_rate > 0.0,
+            rejection_rate: (rejection_rate * 100.0).round() / 100.0,
+            p95_ms: p95(&state.latencies).map(|ms| (ms * 100.0).round() / 100.0),
+            queue_depth,
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Fraction of requests to reject, growing with the overshoot of the
+    /// worse limit and capped at [`MAX_REJECTION_RATE`]
+    fn rejection_rate(&self, state: &mut ShedState, queue_depth: usize) -> f64 {
+        let now = Instant::now();
+        while state
+            .latencies
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) > SAMPLE_MAX_AGE)
+        {
+            state.latencies.pop_front();
+        }
+
+        let latency_pressure = self
+            .config
+            .p95_threshold_ms
+            .zip(p95(&state.latencies))
+            .map(|(threshold, p95)| p95 / threshold);
+        let queue_pressure = self
+            .config
+            .max_queue_depth
+            .map(|max| queue_depth as f64 / max as f64);
+        let pressure = latency_pressure
+            .into_iter()
+            .chain(queue_pressure)
+            .fold(0.0, f64::max);
+        (pressure - 1.0).clamp(0.0, MAX_REJECTION_RATE)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ShedState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn p95(latencies: &VecDeque<(Instant, f64)>) -> Option<f64> {
+    if latencies.is_empty() {
+        return None;
+    }
+    let mut sorted: Vec<f64> = latencies.iter().map(|(_, ms)| *ms).collect();
+    sorted.sort_by(f64::total_cmp);
+    let rank = ((sorted.len() as f64) * 0.95).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shedder(p95_threshold_ms: Option<f64>, max_queue_depth: Option<usize>) -> LoadShedder {
+        LoadShedder::new(LoadSheddingConfig {
+            p95_threshold_ms,
+            max_queue_depth,
+            sheddable_methods: default_sheddable_methods(),
+            retry_after_secs: DEFAULT_RETRY_AFTER_SECS,
+        })
+    }
+
+    fn rejections(shedder: &LoadShedder, method: &str, queue_depth: usize) -> usize {
+        (0..100)
+            .filter(|_| shedder.admit(Some(method), queue_depth).is_err())
+            .count()
+    }
+
+    #[test]
+    fn test_slow_responses_shed_list_operations_first() {
+        let shedder = shedder(Some(100.0), None);
+        assert_eq!(rejections(&shedder, "tools/list", 0), 0);
+
+        // p95 at 1.25x the threshold: only sheddable methods are rejected
+        for _ in 0..LATENCY_SAMPLES {
+            shedder.record_latency(Duration::from_millis(125));
+        }
+        assert_eq!(rejections(&shedder, "tools/list", 0), 50);
+        assert_eq!(rejections(&shedder, "tools/call", 0), 0);
+
+        // Far above the threshold both are shed, never completely
+        for _ in 0..LATENCY_SAMPLES {
+            shedder.record_latency(Duration::from_millis(1000));
+        }
+        assert_eq!(rejections(&shedder, "tools/list", 0), 90);
+        assert_eq!(rejections(&shedder, "tools/call", 0), 80);
+        let snapshot = shedder.snapshot(0);
+        assert!(snapshot.shedding);
+        assert_eq!(snapshot.rejected, 220);
+
+        // Fast responses bring the p95 back down
+        for _ in 0..LATENCY_SAMPLES {
+            shedder.record_latency(Duration::from_millis(10));
+        }
+        assert_eq!(rejections(&shedder, "tools/call", 0), 0);
+        assert!(!shedder.snapshot(0).shedding);
+    }
+
+    #[test]
+    fn test_queue_depth_above_high_water_mark_sheds() {
+        let shedder = shedder(None, Some(4));
+        assert!(shedder.admit(Some("tools/list"), 4).is_ok());
+        let error = (0..10)
+            .find_map(|_| shedder.admit(Some("tools/list"), 6).err())
+            .unwrap();
+        assert_eq!(error.error_code(), Some("overloaded"));
+        assert_eq!(error.status_code(), http::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(shedder.snapshot(6).rejection_rate, 0.5);
+    }
+
+    #[test]
+    fn test_config_needs_a_limit() {
+        let config: LoadSheddingConfig = serde_json::from_str("{}").unwrap();
+        assert!(config.validate().is_err());
+        assert_eq!(config.sheddable_methods, DEFAULT_SHEDDABLE_METHODS);
+
+        let config: LoadSheddingConfig =
+            serde_json::from_str(r#"{ "max_queue_depth": 8 }"#).unwrap();
+        assert!(config.validate().is_ok());
+    }
+}
@@ -0,0 +1,306 @@
+//! Ordered shutdown of a server
+//!
+//! A graceful shutdown, whether asked for through a handle or by a signal,
+//! runs the same phases in order: stop accepting connections, close event
+//! streams, fail queued requests with `503`, wait for in-flight requests up
+//! to the grace period, cancel the rest, wait for connections to finish,
+//! stop background tasks, terminate the MCP processes, and flush what is
+//! still buffered. Each phase has a timeout so a stuck one cannot hold up
+//! the rest, and its duration is traced.
+
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// Default time in-flight requests get to finish, in seconds
+pub const DEFAULT_GRACE_PERIOD_SECS: u64 = 10;
+
+/// Default time every other phase may take, in seconds
+pub const DEFAULT_PHASE_TIMEOUT_SECS: u64 = 5;
+
+/// Time budget of a graceful shutdown
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ShutdownConfig {
+    /// Seconds in-flight requests get to finish before they are cancelled
+    #[serde(default = "default_grace_period_secs")]
+    pub grace_period_secs: u64,
+
+    /// Seconds each of the other phases may take
+    #[serde(default = "default_phase_timeout_secs")]
+    pub phase_timeout_secs: u64,
+}
+
+fn default_grace_period_secs() -> u64 {
+    DEFAULT_GRACE_PERIOD_SECS
+}
+
+fn default_phase_timeout_secs() -> u64 {
+    DEFAULT_PHASE_TIMEOUT_SECS
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            grace_period_secs: DEFAULT_GRACE_PERIOD_SECS,
+            phase_timeout_secs: DEFAULT_PHASE_TIMEOUT_SECS,
+        }
+    }
+}
+
+impl ShutdownConfig {
+    /// Check that phases get some time
+    pub fn validate(&self) -> Result<(), String> {
+        if self.phase_timeout_secs == 0 {
+            return Err("phase_timeout_secs must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    /// Longest a graceful shutdown can take
+    pub fn budget(&self) -> Duration {
+        let phases = ShutdownPhase::ALL.len() as u64 - 1;
+        Duration::from_secs(self.grace_period_secs + phases * self.phase_timeout_secs)
+    }
+}
+
+/// Step of a graceful shutdown, in the order they run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShutdownPhase {
+    StopAccepting,
+    CloseStreams,
+    DrainQueue,
+    WaitInflight,
+    CancelInflight,
+    CloseConnections,
+    StopBackground,
+    TerminateChildren,
+    Flush,
+}
+
+impl ShutdownPhase {
+    pub const ALL: [ShutdownPhase; 9] = [
+        ShutdownPhase::StopAccepting,
+        ShutdownPhase::CloseStreams,
+        ShutdownPhase::DrainQueue,
+        ShutdownPhase::WaitInflight,
+        ShutdownPhase::CancelInflight,
+        ShutdownPhase::CloseConnections,
+        ShutdownPhase::StopBackground,
+        ShutdownPhase::TerminateChildren,
+        ShutdownPhase::Flush,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ShutdownPhase::StopAccepting => "stop_accepting",
+            ShutdownPhase::CloseStreams => "close_streams",
+            ShutdownPhase::DrainQueue => "drain_queue",
+            ShutdownPhase::WaitInflight => "wait_inflight",
+            ShutdownPhase::CancelInflight => "cancel_inflight",
+            ShutdownPhase::CloseConnections => "close_connections",
+            ShutdownPhase::StopBackground => "stop_background",
+            ShutdownPhase::TerminateChildren => "terminate_children",
+            ShutdownPhase::Flush => "flush",
+        }
+    }
+}
+
+/// How one phase went
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PhaseReport {
+    pub phase: ShutdownPhase,
+    pub duration_ms: u64,
+
+    /// Whether the phase finished before its timeout
+    pub completed: bool,
+}
+
+/// Runs the phases of one graceful shutdown, in order
+#[derive(Debug)]
+pub struct ShutdownCoordinator {
+    config: ShutdownConfig,
+    started: Instant,
+    next: usize,
+    phases: Vec<PhaseReport>,
+}
+
+impl ShutdownCoordinator {
+    pub fn new(config: &ShutdownConfig) -> Self {
+        tracing::info!("Shutting down");
+        Self {
+            config: config.clone(),
+            started: Instant::now(),
+            next: 0,
+            phases: Vec::new(),
+        }
+    }
+
+    /// Run `phase`, giving up on it after its timeout
+    ///
+    /// Returns the output of `work`, or `None` if it timed out. Phases must
+    /// run in the order of [`ShutdownPhase::ALL`]; phases skipped over are
+    /// recorded as taking no time.
+    pub async fn phase<F: Future>(&mut self, phase: ShutdownPhase, work: F) -> Option<F::Output> {
+        let index = ShutdownPhase::ALL
+            .iter()
+            .position(|candidate| *candidate == phase)
+            .expect("phase is listed");
+        debug_assert!(
+            index >= self.next,
+            "shutdown phase {:?} out of order",
+            phase
+        );
+        for skipped in &ShutdownPhase::ALL[self.next..index] {
+            self.record(*skipped, Duration::ZERO, true);
+        }
+        self.next = index + 1;
+
+        let timeout = match phase {
+            ShutdownPhase::WaitInflight => Duration::from_secs(self.config.grace_period_secs),
+            _ => Duration::from_secs(self.config.phase_timeout_secs),
+        };
+        let started = Instant::now();
+        let output = tokio::time::timeout(timeout, work).await.ok();
+        self.record(phase, started.elapsed(), output.is_some());
+        output
+    }
+
+    /// Finish the shutdown, returning how each phase went
+    pub fn finish(self) -> Vec<PhaseReport> {
+        tracing::info!(
+            "Shutdown finished in {}ms",
+            self.started.elapsed().as_millis()
+        );
+        self.phases
+    }
+
+    fn record(&mut self, phase: ShutdownPhase, elapsed: Duration, completed: bool) {
+        let duration_ms = elapsed.as_millis() as u64;
+        if completed {
+            tracing::info!("Shutdown phase {} took {}ms", phase.as_str(), duration_ms);
+        } else {
+            tracing::warn!(
+                "Shutdown phase {} timed out after {}ms",
+                phase.as_str(),
+                duration_ms
+            );
+        }
+        self.phases.push(PhaseReport {
+            phase,
+            duration_ms,
+            completed,
+        });
+    }
+}
+
+/// Background tasks of a server, stopped during shutdown
+#[derive(Debug, Default)]
+pub struct BackgroundTasks {
+    tasks: Mutex<Vec<(&'static str, JoinHandle<()>)>>,
+}
+
+impl BackgroundTasks {
+    /// Stop `task` when the server shuts down
+    pub fn register(&self, name: &'static str, task: JoinHandle<()>) {
+        self.lock().push((name, task));
+    }
+
+    /// Abort every task and wait for them to end
+    pub async fn stop(&self) {
+        let tasks = std::mem::take(&mut *self.lock());
+        for (name, task) in tasks {
+            task.abort();
+            let _ = task.await;
+            tracing::debug!("Stopped background task {}", name);
+        }
+    }
+
+    /// Number of tasks still registered
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<(&'static str, JoinHandle<()>)>> {
+        self.tasks.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Wait for SIGINT or, on Unix, SIGTERM
+pub async fn signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::warn!("Failed to listen for SIGINT: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => tracing::info!("Received SIGINT"),
+        _ = terminate => tracing::info!("Received SIGTERM"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_phases_time_out_and_skipped_ones_are_recorded() {
+        let config = ShutdownConfig {
+            grace_period_secs: 0,
+            phase_timeout_secs: 1,
+        };
+        assert_eq!(config.budget(), Duration::from_secs(8));
+        let mut coordinator = ShutdownCoordinator::new(&config);
+
+        assert_eq!(
+            coordinator
+                .phase(ShutdownPhase::CloseStreams, async { 3 })
+                .await,
+            Some(3)
+        );
+        let waited = coordinator
+            .phase(ShutdownPhase::WaitInflight, std::future::pending::<()>())
+            .await;
+        assert!(waited.is_none());
+
+        let phases = coordinator.finish();
+        let names: Vec<_> = phases.iter().map(|report| report.phase).collect();
+        assert_eq!(names, ShutdownPhase::ALL[..4]);
+        assert!(phases[..3].iter().all(|report| report.completed));
+        assert!(!phases[3].completed);
+    }
+
+    #[tokio::test]
+    async fn test_background_tasks_stopped() {
+        let tasks = BackgroundTasks::default();
+        tasks.register("forever", tokio::spawn(std::future::pending()));
+        assert_eq!(tasks.len(), 1);
+        tokio::time::timeout(Duration::from_secs(1), tasks.stop())
+            .await
+            .unwrap();
+        assert!(tasks.is_empty());
+    }
+}
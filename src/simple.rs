@@ -0,0 +1,287 @@
+//! Tool calls without JSON-RPC for `POST /api/v1/simple/{tool}`
+//!
+//! Integrations that can only send flat JSON, forms, or query parameters
+//! call a tool by name. Dotted keys (`filter.status`) become nested objects,
+//! repeated keys become arrays, and string values are converted to the types
+//! the tool's `inputSchema` asks for when it is known. The result's text
+//! content is returned as the response body; JSON-RPC errors and results
+//! flagged `isError` become structured error responses.
+
+use crate::error::{McpCoreError, McpCoreResult};
+use crate::render;
+use axum::{
+    http::{header, HeaderValue},
+    response::{IntoResponse, Json, Response},
+};
+use serde_json::{Map, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Source of the JSON-RPC ids of simple tool calls
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Nest `pairs` under their dotted keys
+///
+/// A key given more than once collects its values into an array. A key used
+/// both as a value and as the parent of another key is rejected.
+pub fn expand(pairs: Vec<(String, Value)>) -> McpCoreResult<Map<String, Value>> {
+    let mut arguments = Map::new();
+    for (key, value) in pairs {
+        let conflict = || McpCoreError::RequestError {
+            message: format!("Parameter '{}' conflicts with another parameter", key),
+        };
+        let mut segments = key.split('.').peekable();
+        let mut object = &mut arguments;
+        while let Some(segment) = segments.next() {
+            if segment.is_empty() {
+                return Err(McpCoreError::RequestError {
+                    message: format!("Parameter '{}' has an empty key segment", key),
+                });
+            }
+            if segments.peek().is_none() {
+                match object.get_mut(segment) {
+                    None => {
+                        object.insert(segment.to_string(), value);
+                    }
+                    Some(Value::Object(_)) => return Err(conflict()),
+                    Some(Value::Array(values)) => values.push(value),
+                    Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+                }
+                break;
+            }
+            object = match object
+                .entry(segment)
+                .or_insert_with(|| Value::Object(Map::new()))
+            {
+                Value::Object(child) => child,
+                _ => return Err(conflict()),
+            };
+        }
+    }
+    Ok(arguments)
+}
+
+/// Convert the strings in `value` to the types `schema` allows
+///
+/// Strings stay strings wherever the schema accepts one or says nothing;
+/// values that do not parse are left for schema validation to report.
+pub fn coerce(value: &mut Value, schema: &Value) {
+    match value {
+        Value::Object(object) => {
+            for (key, child) in object.iter_mut() {
+                let child_schema = schema
+                    .pointer(&format!(
+                        "/properties/{}",
+                        key.replace('~', "~0").replace('/', "~1")
+                    ))
+                    .or_else(|| schema.get("additionalProperties"));
+                if let Some(child_schema) = child_schema {
+                    coerce(child, child_schema);
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                items.iter_mut().for_each(|item| coerce(item, item_schema));
+            }
+        }
+        Value::String(text) => {
+            let types = schema_types(schema);
+            if types.is_empty() || types.contains(&"string") {
+                return;
+            }
+            if let Some(coerced) = types.iter().find_map(|ty| parse_as(text, ty, schema)) {
+                *value = coerced;
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Types named by `type`, directly or in `anyOf`/`oneOf` branches
+fn schema_types(schema: &Value) -> Vec<&str> {
+    let mut types: Vec<&str> = match schema.get("type") {
+        Some(Value::String(ty)) => vec![ty.as_str()],
+        Some(Value::Array(types)) => types.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    for branches in ["anyOf", "oneOf"] {
+        if let Some(Value::Array(branches)) = schema.get(branches) {
+            types.extend(branches.iter().flat_map(schema_types));
+        }
+    }
+    types
+}
+
+/// `text` as a value of JSON type `ty`, if it parses as one
+fn parse_as(text: &str, ty: &str, schema: &Value) -> Option<Value> {
+    match ty {
+        "integer" => text.trim().parse::<i64>().ok().map(Value::from),
+        "number" => text
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|number| number.is_finite())
+            .map(Value::from),
+        "boolean" => match text.trim() {
+            "true" | "1" => Some(Value::Bool(true)),
+            "false" | "0" => Some(Value::Bool(false)),
+            _ => None,
+        },
+        "null" => (text.is_empty() || text == "null").then_some(Value::Null),
+        "object" => serde_json::from_str(text).ok().filter(Value::is_object),
+        "array" => {
+            // A JSON array, or comma-separated items
+            let mut items = serde_json::from_str(text)
+                .ok()
+                .filter(Value::is_array)
+                .unwrap_or_else(|| {
+                    Value::Array(
+                        text.split(',')
+                            .map(|item| Value::String(item.trim().to_string()))
+                            .collect(),
+                    )
+                });
+            coerce(&mut items, schema);
+            Some(items)
+        }
+        _ => None,
+    }
+}
+
+/// `tools/call` request for `tool` with `arguments`, with an id of its own
+pub fn tool_call(tool: &str, arguments: Map<String, Value>) -> Value {
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": format!("simple-{}", NEXT_ID.fetch_add(1, Ordering::Relaxed)),
+        "method": "tools/call",
+        "params": {
+            "name": tool,
+            "arguments": arguments,
+        }
+    })
+}
+
+/// Response body for the server's answer to a simple tool call
+///
+/// Text content is returned as `text/plain`, blocks joined by newlines;
+/// results with other content return the content blocks as JSON.
+pub fn render(response: &str) -> McpCoreResult<Response> {
+    let message: Value = serde_json::from_str(response)?;
+    if let Some(error) = message.get("error") {
+        return Err(McpCoreError::ToolError {
+            message: error
+                .get("message")
+                .and_then(Value::as_str)
+                .map_or_else(|| error.to_string(), str::to_string),
+        });
+    }
+    let text = render::render_text(&message);
+    if message.pointer("/result/isError") == Some(&Value::Bool(true)) {
+        return Err(McpCoreError::ToolError {
+            message: text.unwrap_or_else(|| message["result"]["content"].to_string()),
+        });
+    }
+    Ok(match text {
+        Some(text) => (
+            [(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("text/plain; charset=utf-8"),
+            )],
+            text,
+        )
+            .into_response(),
+        None => Json(
+            message
+                .pointer("/result/content")
+                .cloned()
+                .unwrap_or(Value::Array(Vec::new())),
+        )
+        .into_response(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn strings(pairs: &[(&str, &str)]) -> Vec<(String, Value)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), Value::from(*value)))
+            .collect()
+    }
+
+    #[test]
+    fn test_dotted_and_repeated_keys_expand() {
+        let arguments = expand(strings(&[
+            ("query", "rust"),
+            ("filter.status", "open"),
+            ("filter.labels", "bug"),
+            ("filter.labels", "ui"),
+            ("page.size", "20"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            Value::Object(arguments),
+            json!({
+                "query": "rust",
+                "filter": { "status": "open", "labels": ["bug", "ui"] },
+                "page": { "size": "20" }
+            })
+        );
+
+        let error = expand(strings(&[("a", "1"), ("a.b", "2")])).unwrap_err();
+        assert!(error.to_string().contains("conflicts"));
+        assert!(expand(strings(&[("a..b", "1")])).is_err());
+    }
+
+    #[test]
+    fn test_strings_coerced_to_schema_types() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "limit": { "type": "integer" },
+                "ratio": { "type": "number" },
+                "verbose": { "type": "boolean" },
+                "ids": { "type": "array", "items": { "type": "integer" } },
+                "filter": {
+                    "type": "object",
+                    "properties": { "since": { "type": ["integer", "null"] } }
+                },
+                "untyped": {}
+            }
+        });
+        let mut arguments = Value::Object(
+            expand(strings(&[
+                ("name", "42"),
+                ("limit", "10"),
+                ("ratio", "0.5"),
+                ("verbose", "true"),
+                ("ids", "1,2,3"),
+                ("filter.since", "1700000000"),
+                ("untyped", "7"),
+            ]))
+            .unwrap(),
+        );
+        coerce(&mut arguments, &schema);
+        assert_eq!(
+            arguments,
+            json!({
+                "name": "42",
+                "limit": 10,
+                "ratio": 0.5,
+                "verbose": true,
+                "ids": [1, 2, 3],
+                "filter": { "since": 1700000000 },
+                "untyped": "7"
+            })
+        );
+
+        // Values that do not parse are left for validation to report
+        let mut arguments = json!({ "limit": "ten" });
+        coerce(&mut arguments, &schema);
+        assert_eq!(arguments, json!({ "limit": "ten" }));
+    }
+}
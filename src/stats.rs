@@ -0,0 +1,373 @@
+//! Rolling request statistics served by `GET /api/v1/stats`
+//!
+//! Requests are recorded into a ring of fixed-width time buckets covering the
+//! longest window. Every bucket field is an atomic, so recording never takes
+//! a lock; a bucket is lazily reset when the ring wraps around to it. Latency
+//! is kept in a log-linear histogram per bucket, which bounds memory and
+//! gives percentiles within one histogram step (about 19%). Notifications,
+//! which get no response, are counted apart from requests.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Width of a single bucket in seconds
+const BUCKET_SECS: u64 = 5;
+
+/// Number of buckets, covering the longest window
+const BUCKET_COUNT: usize = 720;
+
+/// Histogram sub-buckets per power of two
+const SUB_BUCKETS: u32 = 4;
+
+/// Histogram buckets; the last one absorbs latencies above ~2^24 µs
+pub(crate) const LATENCY_BUCKETS: usize = 96;
+
+/// Windows reported by [`RequestStats::snapshot`]
+pub const WINDOWS: &[(&str, u64)] = &[("1m", 60), ("5m", 300), ("1h", 3600)];
+
+/// Class of a failed request, derived from its HTTP status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// 4xx: rejected before or by validation
+    Client,
+    /// 504: timed out or aborted
+    Timeout,
+    /// Other 5xx: the MCP server or gateway failed
+    Server,
+}
+
+impl ErrorClass {
+    /// Classify an HTTP status, returning `None` for successes
+    pub fn from_status(status: u16) -> Option<Self> {
+        match status {
+            504 => Some(Self::Timeout),
+            400..=499 => Some(Self::Client),
+            500..=599 => Some(Self::Server),
+            _ => None,
+        }
+    }
+}
+
+struct Bucket {
+    /// Index of the time slot this bucket currently holds
+    epoch: AtomicU64,
+    requests: AtomicU64,
+    client_errors: AtomicU64,
+    timeout_errors: AtomicU64,
+    server_errors: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    latency: [AtomicU64; LATENCY_BUCKETS],
+    notifications: AtomicU64,
+    rejected_notifications: AtomicU64,
+}
+
+impl Bucket {
+    fn new() -> Self {
+        Self {
+            epoch: AtomicU64::new(u64::MAX),
+            requests: AtomicU64::new(0),
+            client_errors: AtomicU64::new(0),
+            timeout_errors: AtomicU64::new(0),
+            server_errors: AtomicU64::new(0),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            latency: std::array::from_fn(|_| AtomicU64::new(0)),
+            notifications: AtomicU64::new(0),
+            rejected_notifications: AtomicU64::new(0),
+        }
+    }
+
+    fn reset(&self) {
+        for counter in [
+            &self.requests,
+            &self.client_errors,
+            &self.timeout_errors,
+            &self.server_errors,
+            &self.bytes_in,
+            &self.bytes_out,
+            &self.notifications,
+            &self.rejected_notifications,
+        ]
+        .into_iter()
+        .chain(self.latency.iter())
+        {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Statistics for one window
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WindowStats {
+    pub requests: u64,
+    pub errors: ErrorCounts,
+    pub latency_ms: LatencyPercentiles,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub notifications: NotificationCounts,
+}
+
+/// Notifications forwarded to the MCP server and refused
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct NotificationCounts {
+    pub accepted: u64,
+    pub rejected: u64,
+}
+
+/// Error counts by class
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ErrorCounts {
+    pub client: u64,
+    pub timeout: u64,
+    pub server: u64,
+}
+
+/// Latency percentiles in milliseconds; `None` without requests
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LatencyPercentiles {
+    pub p50: Option<f64>,
+    pub p95: Option<f64>,
+    pub p99: Option<f64>,
+}
+
+impl LatencyPercentiles {
+    /// Percentiles of a latency histogram
+    pub(crate) fn of(histogram: &[u64]) -> Self {
+        Self {
+            p50: percentile(histogram, 0.50),
+            p95: percentile(histogram, 0.95),
+            p99: percentile(histogram, 0.99),
+        }
+    }
+}
+
+/// Rolling request statistics for one server
+pub struct RequestStats {
+    started: Instant,
+    buckets: Box<[Bucket]>,
+}
+
+impl Default for RequestStats {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            buckets: (0..BUCKET_COUNT).map(|_| Bucket::new()).collect(),
+        }
+    }
+}
+
+impl RequestStats {
+    /// Record a finished request
+    pub fn record(
+        &self,
+        latency: Duration,
+        error: Option<ErrorClass>,
+        bytes_in: usize,
+        bytes_out: usize,
+    ) {
+        let now = self.started.elapsed().as_secs();
+        self.record_at(now, latency, error, bytes_in, bytes_out);
+    }
+
+    /// Record a notification from a client, accepted or refused
+    pub fn record_notification(&self, accepted: bool) {
+        let now = self.started.elapsed().as_secs();
+        self.record_notification_at(now, accepted);
+    }
+
+    /// Statistics for each of [`WINDOWS`]
+    pub fn snapshot(&self) -> Vec<(&'static str, WindowStats)> {
+        let now = self.started.elapsed().as_secs();
+        WINDOWS
+            .iter()
+            .map(|(name, secs)| (*name, self.window_at(now, *secs)))
+            .collect()
+    }
+
+    fn record_at(
+        &self,
+        now_secs: u64,
+        latency: Duration,
+        error: Option<ErrorClass>,
+        bytes_in: usize,
+        bytes_out: usize,
+    ) {
+        let bucket = self.bucket_for(now_secs / BUCKET_SECS);
+        bucket.requests.fetch_add(1, Ordering::Relaxed);
+        let error_counter = match error {
+            Some(ErrorClass::Client) => Some(&bucket.client_errors),
+            Some(ErrorClass::Timeout) => Some(&bucket.timeout_errors),
+            Some(ErrorClass::Server) => Some(&bucket.server_errors),
+            None => None,
+        };
+        if let Some(counter) = error_counter {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        bucket
+            .bytes_in
+            .fetch_add(bytes_in as u64, Ordering::Relaxed);
+        bucket
+            .bytes_out
+            .fetch_add(bytes_out as u64, Ordering::Relaxed);
+        bucket.latency[latency_bucket(latency)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_notification_at(&self, now_secs: u64, accepted: bool) {
+        let bucket = self.bucket_for(now_secs / BUCKET_SECS);
+        match accepted {
+            true => &bucket.notifications,
+            false => &bucket.rejected_notifications,
+        }
+        .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Bucket for `epoch`, resetting it if it still holds an older slot
+    fn bucket_for(&self, epoch: u64) -> &Bucket {
+        let bucket = &self.buckets[(epoch % BUCKET_COUNT as u64) as usize];
+        let current = bucket.epoch.load(Ordering::Acquire);
+        if current != epoch
+            && bucket
+                .epoch
+                .compare_exchange(current, epoch, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        {
+            // Records racing with the reset may be lost; acceptable for stats
+            bucket.reset();
+        }
+        bucket
+    }
+
+    fn window_at(&self, now_secs: u64, window_secs: u64) -> WindowStats {
+        let newest = now_secs / BUCKET_SECS;
+        let count = window_secs.div_ceil(BUCKET_SECS).min(BUCKET_COUNT as u64);
+        let oldest = (newest + 1).saturating_sub(count);
+
+        let mut stats = WindowStats::default();
+        let mut latency = [0u64; LATENCY_BUCKETS];
+        for bucket in self.buckets.iter() {
+            let epoch = bucket.epoch.load(Ordering::Acquire);
+            if epoch == u64::MAX || epoch < oldest || epoch > newest {
+                continue;
+            }
+            stats.requests += bucket.requests.load(Ordering::Relaxed);
+            stats.errors.client += bucket.client_errors.load(Ordering::Relaxed);
+            stats.errors.timeout += bucket.timeout_errors.load(Ordering::Relaxed);
+            stats.errors.server += bucket.server_errors.load(Ordering::Relaxed);
+            stats.bytes_in += bucket.bytes_in.load(Ordering::Relaxed);
+            stats.bytes_out += bucket.bytes_out.load(Ordering::Relaxed);
+            stats.notifications.accepted += bucket.notifications.load(Ordering::Relaxed);
+            stats.notifications.rejected += bucket.rejected_notifications.load(Ordering::Relaxed);
+            for (total, counter) in latency.iter_mut().zip(bucket.latency.iter()) {
+                *total += counter.load(Ordering::Relaxed);
+            }
+        }
+
+        stats.latency_ms = LatencyPercentiles::of(&latency);
+        stats
+    }
+}
+
+/// Histogram bucket for a latency, log-linear in microseconds
+pub(crate) fn latency_bucket(latency: Duration) -> usize {
+    let micros = latency.as_micros().max(1) as f64;
+    let index = (micros.log2() * f64::from(SUB_BUCKETS)).floor() as usize;
+    index.min(LATENCY_BUCKETS - 1)
+}
+
+/// Representative latency in milliseconds of a histogram bucket
+fn bucket_value_ms(index: usize) -> f64 {
+    // Geometric midpoint of [2^(i/4), 2^((i+1)/4)) microseconds
+    let micros = 2f64.powf((index as f64 + 0.5) / f64::from(SUB_BUCKETS));
+    micros / 1000.0
+}
+
+fn percentile(histogram: &[u64], quantile: f64) -> Option<f64> {
+    let total: u64 = histogram.iter().sum();
+    if total == 0 {
+        return None;
+    }
+    let rank = ((total as f64) * quantile).ceil().max(1.0) as u64;
+    let mut seen = 0;
+    for (index, count) in histogram.iter().enumerate() {
+        seen += count;
+        if seen >= rank {
+            return Some((bucket_value_ms(index) * 100.0).round() / 100.0);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_windows_roll_over() {
+        let stats = RequestStats::default();
+        let latency = Duration::from_millis(10);
+        stats.record_at(0, latency, None, 100, 200);
+        stats.record_at(100, latency, Some(ErrorClass::Server), 10, 0);
+        stats.record_at(290, latency, Some(ErrorClass::Client), 10, 0);
+
+        let one_minute = stats.window_at(300, 60);
+        assert_eq!(one_minute.requests, 1);
+        assert_eq!(one_minute.errors.client, 1);
+
+        let five_minutes = stats.window_at(300, 300);
+        assert_eq!(five_minutes.requests, 2);
+        assert_eq!(five_minutes.errors.server, 1);
+
+        let hour = stats.window_at(300, 3600);
+        assert_eq!(hour.requests, 3);
+        assert_eq!(hour.bytes_in, 120);
+        assert_eq!(hour.bytes_out, 200);
+
+        // Notifications are counted apart from requests
+        stats.record_notification_at(290, true);
+        stats.record_notification_at(290, false);
+        let one_minute = stats.window_at(300, 60);
+        assert_eq!(one_minute.requests, 1);
+        assert_eq!(one_minute.notifications.accepted, 1);
+        assert_eq!(one_minute.notifications.rejected, 1);
+
+        // After an hour the ring wraps and the oldest slot is reused
+        stats.record_at(3600, latency, None, 1, 1);
+        let hour = stats.window_at(3600, 3600);
+        assert_eq!(hour.requests, 3);
+        assert_eq!(hour.bytes_in, 21);
+        assert_eq!(stats.window_at(7300, 3600).requests, 0);
+    }
+
+    #[test]
+    fn test_percentiles_on_synthetic_latencies() {
+        let stats = RequestStats::default();
+        for ms in 1..=1000 {
+            stats.record_at(0, Duration::from_millis(ms), None, 0, 0);
+        }
+
+        let window = stats.window_at(0, 60);
+        let p50 = window.latency_ms.p50.unwrap();
+        let p95 = window.latency_ms.p95.unwrap();
+        let p99 = window.latency_ms.p99.unwrap();
+        assert!((400.0..=600.0).contains(&p50), "p50 = {}", p50);
+        assert!((800.0..=1150.0).contains(&p95), "p95 = {}", p95);
+        assert!((850.0..=1200.0).contains(&p99), "p99 = {}", p99);
+        assert!(p50 <= p95 && p95 <= p99);
+
+        assert!(RequestStats::default()
+            .window_at(0, 60)
+            .latency_ms
+            .p50
+            .is_none());
+    }
+
+    #[test]
+    fn test_error_class_from_status() {
+        assert_eq!(ErrorClass::from_status(200), None);
+        assert_eq!(ErrorClass::from_status(401), Some(ErrorClass::Client));
+        assert_eq!(ErrorClass::from_status(504), Some(ErrorClass::Timeout));
+        assert_eq!(ErrorClass::from_status(500), Some(ErrorClass::Server));
+    }
+}
@@ -0,0 +1,607 @@
+//! Rate-limited logging of MCP server stderr
+//!
+//! Each stderr line is captured in a [`StderrTail`] for error context, then
+//! logged at the server's [`StderrLevel`]. Lines beyond
+//! `max_lines_per_sec` are counted and dropped, with a summary of how many
+//! were suppressed once the second is over. Lines longer than
+//! `max_line_bytes` are truncated while being read, so a server that never
+//! writes a newline cannot grow the reader's buffer without bound.
+//!
+//! With `stderr_log`, every line is also written with its timestamp to a
+//! file of the server's own, rotated by size, by a writer thread shared by
+//! the server's children, so a slow disk never holds up the reader. Lines
+//! then go to `tracing` only with `also_trace`, or once writing the file
+//! fails.
+
+use crate::rotating_file::RotatingFile;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::sync::broadcast;
+use tracing::Instrument;
+
+/// Default maximum number of stderr lines logged per second
+pub const DEFAULT_MAX_STDERR_LINES_PER_SEC: u32 = 100;
+
+/// Default maximum length of a logged stderr line
+pub const DEFAULT_MAX_STDERR_LINE_BYTES: usize = 4096;
+
+/// Number of recent stderr lines kept for error messages
+pub const STDERR_TAIL_LINES: usize = 20;
+
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Level at which a server's stderr is logged
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StderrLevel {
+    #[default]
+    Debug,
+    Info,
+    /// Not logged; still captured for error messages
+    Off,
+}
+
+/// Limits on logging a server's stderr
+#[derive(Debug, Clone)]
+pub struct StderrPolicy {
+    pub level: StderrLevel,
+
+    /// Lines logged per second before the rest are suppressed
+    pub max_lines_per_sec: u32,
+
+    /// Bytes of a line kept before it is truncated
+    pub max_line_bytes: usize,
+
+    /// File every line is also written to
+    pub file: Option<StderrLog>,
+}
+
+impl Default for StderrPolicy {
+    fn default() -> Self {
+        Self {
+            level: StderrLevel::Debug,
+            max_lines_per_sec: DEFAULT_MAX_STDERR_LINES_PER_SEC,
+            max_line_bytes: DEFAULT_MAX_STDERR_LINE_BYTES,
+            file: None,
+        }
+    }
+}
+
+/// Default size at which a stderr log file is rotated, in MiB
+pub const DEFAULT_STDERR_LOG_MAX_SIZE_MB: u64 = 10;
+
+/// Default number of rotated stderr log files kept besides the current one
+pub const DEFAULT_STDERR_LOG_MAX_FILES: usize = 5;
+
+/// Lines buffered for a stderr log writer before new ones are dropped
+const STDERR_LOG_CAPACITY: usize = 4096;
+
+/// Per-server file the server's stderr is written to
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct StderrLogConfig {
+    /// File written, `logs/<server>.stderr.log` by default
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+
+    /// Size at which the file is rotated, in MiB
+    #[serde(default = "default_max_size_mb")]
+    pub max_size_mb: u64,
+
+    /// Rotated files kept besides the current one
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+
+    /// Whether lines are still logged through `tracing` at `stderr_level`
+    #[serde(default)]
+    pub also_trace: bool,
+}
+
+fn default_max_size_mb() -> u64 {
+    DEFAULT_STDERR_LOG_MAX_SIZE_MB
+}
+
+fn default_max_files() -> usize {
+    DEFAULT_STDERR_LOG_MAX_FILES
+}
+
+impl StderrLogConfig {
+    /// File the stderr of `server_name` is written to
+    pub fn path(&self, server_name: &str) -> PathBuf {
+        self.path
+            .clone()
+            .unwrap_or_else(|| Path::new("logs").join(format!("{}.stderr.log", server_name)))
+    }
+}
+
+/// Handle for writing lines to a stderr log file
+#[derive(Debug, Clone)]
+pub struct StderrLog {
+    sender: SyncSender<String>,
+    also_trace: bool,
+
+    /// Set once the writer failed, after which lines go to `tracing`
+    failed: Arc<AtomicBool>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl StderrLog {
+    /// Open `path` and start its writer thread
+    pub fn open(
+        path: &Path,
+        max_bytes: u64,
+        max_files: usize,
+        also_trace: bool,
+    ) -> std::io::Result<Self> {
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = RotatingFile::open(path, max_bytes, max_files)?;
+        let (sender, receiver) = mpsc::sync_channel::<String>(STDERR_LOG_CAPACITY);
+        let failed = Arc::new(AtomicBool::new(false));
+        let writer_failed = Arc::clone(&failed);
+        let log_path = path.display().to_string();
+        std::thread::Builder::new()
+            .name("stderr-log".to_string())
+            .spawn(move || {
+                for line in receiver {
+                    // One write per line so rotation never splits a line
+                    if let Err(e) = file.write_all(line.as_bytes()).and_then(|()| file.flush()) {
+                        writer_failed.store(true, Ordering::Relaxed);
+                        tracing::warn!(
+                            "Failed to write stderr log '{}': {}; logging stderr through tracing instead",
+                            log_path,
+                            e
+                        );
+                        return;
+                    }
+                }
+            })?;
+        Ok(Self {
+            sender,
+            also_trace,
+            failed,
+            dropped: Arc::default(),
+        })
+    }
+
+    /// Log of `server_name` as `config` says, shared by every child writing
+    /// to the same file
+    pub fn shared(server_name: &str, config: &StderrLogConfig) -> std::io::Result<Self> {
+        static LOGS: OnceLock<Mutex<HashMap<PathBuf, StderrLog>>> = OnceLock::new();
+        let path = config.path(server_name);
+        let mut logs = LOGS
+            .get_or_init(Mutex::default)
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(log) = logs.get(&path).filter(|log| !log.failed()) {
+            return Ok(log.clone());
+        }
+        let log = Self::open(
+            &path,
+            config.max_size_mb.saturating_mul(1024 * 1024),
+            config.max_files,
+            config.also_trace,
+        )?;
+        logs.insert(path, log.clone());
+        Ok(log)
+    }
+
+    /// Queue `line` with its timestamp; dropped if the writer is behind
+    fn write(&self, line: &str) {
+        let record = format!(
+            "{} {}\n",
+            chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            line
+        );
+        match self.sender.try_send(record) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                if dropped.is_power_of_two() {
+                    tracing::warn!("Stderr log writer is behind, {} lines dropped", dropped);
+                }
+            }
+            Err(TrySendError::Disconnected(_)) => self.failed.store(true, Ordering::Relaxed),
+        }
+    }
+
+    /// Whether writing the file failed
+    pub fn failed(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    /// Whether lines should also be logged through `tracing`
+    fn traces(&self) -> bool {
+        self.also_trace || self.failed()
+    }
+}
+
+/// Capacity of the live stderr channel; slower subscribers lag
+const STDERR_EVENT_CAPACITY: usize = 256;
+
+/// A captured stderr line
+#[derive(Debug, Clone, Serialize)]
+pub struct StderrLine {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub line: String,
+}
+
+/// The most recent stderr lines of a server, and a feed of new ones
+#[derive(Debug, Clone)]
+pub struct StderrTail {
+    lines: Arc<Mutex<VecDeque<StderrLine>>>,
+    events: broadcast::Sender<StderrLine>,
+}
+
+impl Default for StderrTail {
+    fn default() -> Self {
+        Self {
+            lines: Arc::default(),
+            events: broadcast::channel(STDERR_EVENT_CAPACITY).0,
+        }
+    }
+}
+
+impl StderrTail {
+    fn push(&self, line: String) {
+        let line = StderrLine {
+            timestamp: chrono::Utc::now(),
+            line,
+        };
+        // Sent under the lock so `subscribe_since` sees each line exactly once
+        let mut lines = self.lock();
+        if self.events.receiver_count() > 0 {
+            let _ = self.events.send(line.clone());
+        }
+        if lines.len() == STDERR_TAIL_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    /// Captured lines, oldest first
+    pub fn lines(&self) -> Vec<String> {
+        self.lock().iter().map(|line| line.line.clone()).collect()
+    }
+
+    /// Receive every line captured from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<StderrLine> {
+        self.events.subscribe()
+    }
+
+    /// Captured lines written at or after `since`, oldest first, and a
+    /// receiver for the lines after them
+    pub fn subscribe_since(
+        &self,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> (Vec<StderrLine>, broadcast::Receiver<StderrLine>) {
+        let lines = self.lock();
+        let replay = lines
+            .iter()
+            .filter(|line| line.timestamp >= since)
+            .cloned()
+            .collect();
+        (replay, self.events.subscribe())
+    }
+
+    /// Suffix for error messages quoting the captured lines, if any
+    pub fn context(&self) -> String {
+        let lines = self.lines();
+        if lines.is_empty() {
+            String::new()
+        } else {
+            format!("; last stderr output: {}", lines.join(" | "))
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<StderrLine>> {
+        self.lines
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Output of the stderr reader
+#[derive(Debug, PartialEq, Eq)]
+pub enum StderrEvent<'a> {
+    Line(&'a str),
+    /// Number of lines dropped by the rate limit
+    Suppressed(u64),
+}
+
+/// Fixed one-second window limiting emitted lines
+#[derive(Debug)]
+struct RateLimit {
+    max_per_window: u32,
+    window_start: Instant,
+    in_window: u32,
+    suppressed: u64,
+}
+
+impl RateLimit {
+    fn new(max_per_window: u32, now: Instant) -> Self {
+        Self {
+            max_per_window,
+            window_start: now,
+            in_window: 0,
+            suppressed: 0,
+        }
+    }
+
+    /// Whether a line arriving at `now` may be emitted, and the number of
+    /// lines suppressed in the window it closed
+    fn admit(&mut self, now: Instant) -> (bool, Option<u64>) {
+        let mut closed = None;
+        if now.duration_since(self.window_start) >= RATE_WINDOW {
+            closed = self.take_suppressed();
+            self.window_start = now;
+            self.in_window = 0;
+        }
+        if self.in_window < self.max_per_window {
+            self.in_window += 1;
+            (true, closed)
+        } else {
+            self.suppressed += 1;
+            (false, closed)
+        }
+    }
+
+    fn take_suppressed(&mut self) -> Option<u64> {
+        (self.suppressed > 0).then(|| std::mem::take(&mut self.suppressed))
+    }
+}
+
+/// Log a server's stderr in `span` in the background until it closes
+pub(crate) fn spawn_reader<R>(
+    stderr: R,
+    policy: StderrPolicy,
+    tail: StderrTail,
+    span: tracing::Span,
+) -> tokio::task::JoinHandle<()>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    tokio::spawn(
+        async move {
+            let level = policy.level;
+            let file = policy.file.clone();
+            let result = pump(BufReader::new(stderr), policy, &tail, |event| {
+                if file.as_ref().is_some_and(|file| !file.traces()) {
+                    return;
+                }
+                match (level, event) {
+                    (StderrLevel::Off, _) => {}
+                    (StderrLevel::Debug, StderrEvent::Line(line)) => {
+                        tracing::debug!("MCP server stderr: {}", line)
+                    }
+                    (StderrLevel::Info, StderrEvent::Line(line)) => {
+                        tracing::info!("MCP server stderr: {}", line)
+                    }
+                    (StderrLevel::Debug, StderrEvent::Suppressed(count)) => {
+                        tracing::debug!("MCP server stderr: suppressed {} lines", count)
+                    }
+                    (StderrLevel::Info, StderrEvent::Suppressed(count)) => {
+                        tracing::info!("MCP server stderr: suppressed {} lines", count)
+                    }
+                }
+            })
+            .await;
+            match result {
+                Ok(()) => tracing::debug!("MCP server stderr: EOF, task finishing"),
+                Err(e) => tracing::error!("MCP server stderr read error: {}", e),
+            }
+        }
+        .instrument(span),
+    )
+}
+
+/// Read lines until EOF, capturing each and passing the admitted ones to `emit`
+async fn pump<R, F>(
+    mut reader: R,
+    policy: StderrPolicy,
+    tail: &StderrTail,
+    mut emit: F,
+) -> std::io::Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    F: FnMut(StderrEvent<'_>),
+{
+    let mut limit = RateLimit::new(policy.max_lines_per_sec, Instant::now());
+    let mut buffer = Vec::new();
+    while let Some(length) =
+        read_bounded_line(&mut reader, &mut buffer, policy.max_line_bytes).await?
+    {
+        let mut line = String::from_utf8_lossy(&buffer).trim_end().to_string();
+        if length > buffer.len() {
+            line.push_str(&format!("... ({} bytes truncated)", length - buffer.len()));
+        }
+        if let Some(file) = &policy.file {
+            file.write(&line);
+        }
+
+        if policy.level != StderrLevel::Off {
+            let (admitted, suppressed) = limit.admit(Instant::now());
+            if let Some(count) = suppressed {
+                emit(StderrEvent::Suppressed(count));
+            }
+            if admitted {
+                emit(StderrEvent::Line(&line));
+            }
+        }
+        tail.push(line);
+    }
+    if let Some(count) = limit.take_suppressed() {
+        emit(StderrEvent::Suppressed(count));
+    }
+    Ok(())
+}
+
+/// Read one line into `buffer`, keeping at most `max_bytes` of it
+///
+/// Returns the full length of the line without its newline, or `None` at EOF.
+async fn read_bounded_line<R>(
+    reader: &mut R,
+    buffer: &mut Vec<u8>,
+    max_bytes: usize,
+) -> std::io::Result<Option<usize>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    buffer.clear();
+    let mut length = 0;
+    let mut read_any = false;
+    loop {
+        let available = reader.fill_buf().await?;
+        if available.is_empty() {
+            return Ok(read_any.then_some(length));
+        }
+        read_any = true;
+        let newline = available.iter().position(|&byte| byte == b'\n');
+        let chunk = &available[..newline.unwrap_or(available.len())];
+        let room = max_bytes.saturating_sub(buffer.len());
+        buffer.extend_from_slice(&chunk[..chunk.len().min(room)]);
+        length += chunk.len();
+        let consumed = chunk.len() + usize::from(newline.is_some());
+        reader.consume(consumed);
+        if newline.is_some() {
+            return Ok(Some(length));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn collect(input: Vec<u8>, policy: StderrPolicy, tail: &StderrTail) -> Vec<String> {
+        let mut events = Vec::new();
+        pump(std::io::Cursor::new(input), policy, tail, |event| {
+            events.push(match event {
+                StderrEvent::Line(line) => line.to_string(),
+                StderrEvent::Suppressed(count) => format!("suppressed {}", count),
+            })
+        })
+        .await
+        .unwrap();
+        events
+    }
+
+    #[tokio::test]
+    async fn test_firehose_is_suppressed_and_summarized() {
+        let firehose: String = (0..1000)
+            .map(|i| format!("GET /upstream/{}\n", i))
+            .collect();
+        let policy = StderrPolicy {
+            max_lines_per_sec: 10,
+            ..StderrPolicy::default()
+        };
+        let tail = StderrTail::default();
+
+        let events = collect(firehose.clone().into_bytes(), policy.clone(), &tail).await;
+        assert_eq!(events.len(), 11);
+        assert_eq!(events[0], "GET /upstream/0");
+        assert_eq!(events[9], "GET /upstream/9");
+        assert_eq!(events[10], "suppressed 990");
+
+        // Suppressed lines are still captured
+        let lines = tail.lines();
+        assert_eq!(lines.len(), STDERR_TAIL_LINES);
+        assert_eq!(lines.last().unwrap(), "GET /upstream/999");
+
+        let silenced = StderrPolicy {
+            level: StderrLevel::Off,
+            ..policy
+        };
+        let tail = StderrTail::default();
+        assert!(collect(firehose.into_bytes(), silenced, &tail)
+            .await
+            .is_empty());
+        assert!(tail.context().contains("GET /upstream/999"));
+    }
+
+    #[test]
+    fn test_rate_limit_summarizes_each_window() {
+        let start = Instant::now();
+        let mut limit = RateLimit::new(2, start);
+        assert_eq!(limit.admit(start), (true, None));
+        assert_eq!(limit.admit(start), (true, None));
+        assert_eq!(limit.admit(start), (false, None));
+        assert_eq!(limit.admit(start), (false, None));
+
+        let next = start + RATE_WINDOW;
+        assert_eq!(limit.admit(next), (true, Some(2)));
+        assert_eq!(limit.admit(next + Duration::from_millis(1)), (true, None));
+        assert_eq!(limit.take_suppressed(), None);
+    }
+
+    #[tokio::test]
+    async fn test_log_file_rotates_and_prunes() {
+        let dir = std::env::temp_dir().join(format!("mcp-stderr-log-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("logs").join("alpha.stderr.log");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        // Left by an earlier run keeping more files
+        std::fs::write(dir.join("logs/alpha.stderr.log.4"), "old\n").unwrap();
+
+        let policy = StderrPolicy {
+            file: Some(StderrLog::open(&path, 256, 2, false).unwrap()),
+            ..StderrPolicy::default()
+        };
+        let input: String = (0..20).map(|i| format!("line {:02}\n", i)).collect();
+        let tail = StderrTail::default();
+        let reader = spawn_reader(
+            std::io::Cursor::new(input.into_bytes()),
+            policy,
+            tail,
+            tracing::Span::none(),
+        );
+        reader.await.unwrap();
+        let mut current = String::new();
+        for _ in 0..200 {
+            current = std::fs::read_to_string(&path).unwrap();
+            if current.ends_with("line 19\n") {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // 33-byte lines, seven per 256-byte file
+        let lines: Vec<&str> = current.lines().collect();
+        assert_eq!(lines.len(), 6, "{}", current);
+        let (timestamp, line) = lines[0].split_once(' ').unwrap();
+        assert!(chrono::DateTime::parse_from_rfc3339(timestamp).is_ok());
+        assert_eq!(line, "line 14");
+        let newest = std::fs::read_to_string(dir.join("logs/alpha.stderr.log.1")).unwrap();
+        assert!(newest.lines().next().unwrap().ends_with(" line 07"));
+        assert!(dir.join("logs/alpha.stderr.log.2").exists());
+        assert!(!dir.join("logs/alpha.stderr.log.3").exists());
+        assert!(!dir.join("logs/alpha.stderr.log.4").exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_long_lines_are_truncated() {
+        let mut input = vec![b'x'; 100];
+        input.extend_from_slice(b"\nshort\nno newline");
+        let policy = StderrPolicy {
+            max_line_bytes: 10,
+            ..StderrPolicy::default()
+        };
+        let events = collect(input, policy, &StderrTail::default()).await;
+        assert_eq!(
+            events,
+            ["xxxxxxxxxx... (90 bytes truncated)", "short", "no newline"]
+        );
+    }
+}
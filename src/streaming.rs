@@ -0,0 +1,341 @@
+//! Keep-alives and orderly closing of server-sent event streams
+//!
+//! Load balancers drop connections that stay silent for too long, so every
+//! event stream sends a `: ping` comment after `keepalive_secs` without
+//! events. A stream without events for `idle_timeout_secs` is closed, and a
+//! graceful shutdown closes every open stream before waiting for connections
+//! to finish. Streams closed by the gateway end with a `close` event naming
+//! the reason; open streams and close reasons are counted for `/api/v1/stats`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+#[cfg(feature = "http-server")]
+use {
+    axum::response::sse::{Event, KeepAlive, Sse},
+    futures_util::{Stream, StreamExt},
+    std::convert::Infallible,
+    std::pin::Pin,
+    std::sync::atomic::{AtomicU64, Ordering},
+    std::sync::Arc,
+    std::time::Duration,
+    tokio::sync::watch,
+};
+
+/// Default interval of keep-alive comments in seconds
+pub const DEFAULT_KEEPALIVE_SECS: u64 = 15;
+
+/// Keep-alive and idle limits of event streams
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StreamingConfig {
+    /// Seconds without events after which a `: ping` comment is sent
+    #[serde(default = "default_keepalive_secs")]
+    pub keepalive_secs: u64,
+
+    /// Seconds without events after which a stream is closed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_secs: Option<u64>,
+}
+
+fn default_keepalive_secs() -> u64 {
+    DEFAULT_KEEPALIVE_SECS
+}
+
+impl Default for StreamingConfig {
+    fn default() -> Self {
+        Self {
+            keepalive_secs: DEFAULT_KEEPALIVE_SECS,
+            idle_timeout_secs: None,
+        }
+    }
+}
+
+impl StreamingConfig {
+    /// Check that the intervals are positive
+    pub fn validate(&self) -> Result<(), String> {
+        if self.keepalive_secs == 0 || self.idle_timeout_secs == Some(0) {
+            return Err("keepalive_secs and idle_timeout_secs must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Why an event stream ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseReason {
+    /// No events for the idle timeout
+    Idle,
+
+    /// The gateway is shutting down
+    Shutdown,
+
+    /// The client fell too far behind
+    SlowConsumer,
+
+    /// The client went away
+    ClientGone,
+
+    /// The source of the events ended
+    Ended,
+}
+
+#[cfg(feature = "http-server")]
+impl CloseReason {
+    const ALL: [CloseReason; 5] = [
+        CloseReason::Idle,
+        CloseReason::Shutdown,
+        CloseReason::SlowConsumer,
+        CloseReason::ClientGone,
+        CloseReason::Ended,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            CloseReason::Idle => "idle",
+            CloseReason::Shutdown => "shutdown",
+            CloseReason::SlowConsumer => "slow_consumer",
+            CloseReason::ClientGone => "client_gone",
+            CloseReason::Ended => "ended",
+        }
+    }
+}
+
+/// Open streams and how closed ones ended, served by the stats endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamSnapshot {
+    pub open: u64,
+    pub closed: BTreeMap<&'static str, u64>,
+}
+
+/// The event streams of a server
+#[cfg(feature = "http-server")]
+#[derive(Debug)]
+pub struct Streams {
+    config: StreamingConfig,
+    shutdown: watch::Sender<bool>,
+    open: AtomicU64,
+    closed: [AtomicU64; CloseReason::ALL.len()],
+}
+
+#[cfg(feature = "http-server")]
+impl Default for Streams {
+    fn default() -> Self {
+        Self::new(StreamingConfig::default())
+    }
+}
+
+#[cfg(feature = "http-server")]
+impl Streams {
+    pub fn new(config: StreamingConfig) -> Self {
+        Self {
+            config,
+            shutdown: watch::Sender::new(false),
+            open: AtomicU64::new(0),
+            closed: Default::default(),
+        }
+    }
+
+    /// Serve `events` with keep-alives, the idle timeout, and shutdown
+    ///
+    /// `events` ends a stream early by yielding the reason; it sends its own
+    /// final event for it, if any.
+    pub fn sse<S>(
+        self: &Arc<Self>,
+        events: S,
+    ) -> Sse<impl Stream<Item = Result<Event, Infallible>> + Send + 'static>
+    where
+        S: Stream<Item = Result<Event, CloseReason>> + Send + 'static,
+    {
+        let interval = Duration::from_secs(self.config.keepalive_secs);
+        Sse::new(self.track(events)).keep_alive(KeepAlive::new().interval(interval).text("ping"))
+    }
+
+    /// Close every open stream, and streams opened from now on, with a
+    /// `shutdown` event
+    pub fn close_all(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    pub fn snapshot(&self) -> StreamSnapshot {
+        StreamSnapshot {
+            open: self.open.load(Ordering::Relaxed),
+            closed: CloseReason::ALL
+                .iter()
+                .map(|reason| {
+                    (
+                        reason.as_str(),
+                        self.closed[*reason as usize].load(Ordering::Relaxed),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    fn track<S>(
+        self: &Arc<Self>,
+        events: S,
+    ) -> impl Stream<Item = Result<Event, Infallible>> + Send + 'static
+    where
+        S: Stream<Item = Result<Event, CloseReason>> + Send + 'static,
+    {
+        self.open.fetch_add(1, Ordering::Relaxed);
+        let tracked = Tracked {
+            events: Box::pin(events),
+            shutdown: self.shutdown.subscribe(),
+            idle_timeout: self.config.idle_timeout_secs.map(Duration::from_secs),
+            open: OpenStream {
+                streams: Arc::clone(self),
+                reason: None,
+            },
+        };
+        futures_util::stream::unfold(tracked, |mut tracked| async move {
+            if tracked.open.reason.is_some() {
+                return None;
+            }
+            let idle_timeout = tracked.idle_timeout;
+            let idle = async move {
+                match idle_timeout {
+                    Some(timeout) => tokio::time::sleep(timeout).await,
+                    None => std::future::pending().await,
+                }
+            };
+            let next = tokio::select! {
+                biased;
+                _ = tracked.shutdown.wait_for(|stop| *stop) => Err(CloseReason::Shutdown),
+                item = tracked.events.next() => Ok(item),
+                _ = idle => Err(CloseReason::Idle),
+            };
+            let reason = match next {
+                Ok(Some(Ok(event))) => return Some((Ok(event), tracked)),
+                // The source ended the stream and sent any final event itself
+                Ok(Some(Err(reason))) => {
+                    tracked.open.close(reason);
+                    return None;
+                }
+                Ok(None) => {
+                    tracked.open.close(CloseReason::Ended);
+                    return None;
+                }
+                Err(reason) => reason,
+            };
+            tracked.open.close(reason);
+            Some((Ok(close_event(reason)), tracked))
+        })
+    }
+}
+
+/// State of one tracked stream
+#[cfg(feature = "http-server")]
+struct Tracked {
+    events: Pin<Box<dyn Stream<Item = Result<Event, CloseReason>> + Send>>,
+    shutdown: watch::Receiver<bool>,
+    idle_timeout: Option<Duration>,
+    open: OpenStream,
+}
+
+/// Counts a stream as open until dropped, then as closed for its reason
+#[cfg(feature = "http-server")]
+struct OpenStream {
+    streams: Arc<Streams>,
+
+    /// Why the stream ended; a stream dropped without one lost its client
+    reason: Option<CloseReason>,
+}
+
+#[cfg(feature = "http-server")]
+impl OpenStream {
+    fn close(&mut self, reason: CloseReason) {
+        self.reason = Some(reason);
+    }
+}
+
+#[cfg(feature = "http-server")]
+impl Drop for OpenStream {
+    fn drop(&mut self) {
+        let reason = self.reason.unwrap_or(CloseReason::ClientGone);
+        self.streams.open.fetch_sub(1, Ordering::Relaxed);
+        self.streams.closed[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Final event of a stream the gateway closes
+#[cfg(feature = "http-server")]
+fn close_event(reason: CloseReason) -> Event {
+    Event::default().event("close").data(
+        serde_json::json!({
+            "type": "close",
+            "timestamp": chrono::Utc::now(),
+            "reason": reason,
+        })
+        .to_string(),
+    )
+}
+
+#[cfg(all(test, feature = "http-server"))]
+mod tests {
+    use super::*;
+    use axum::response::IntoResponse;
+
+    async fn frames(
+        sse: Sse<impl Stream<Item = Result<Event, Infallible>> + Send + 'static>,
+    ) -> String {
+        let body = axum::body::to_bytes(sse.into_response().into_body(), usize::MAX);
+        let body = tokio::time::timeout(Duration::from_secs(5), body)
+            .await
+            .unwrap()
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_idle_and_shutdown_close_streams_with_reason() {
+        let streams = Arc::new(Streams::new(StreamingConfig {
+            keepalive_secs: 60,
+            idle_timeout_secs: Some(1),
+        }));
+        let silent = futures_util::stream::pending();
+        let first = futures_util::stream::once(async { Ok(Event::default().data("first")) });
+        let body = frames(streams.sse(first.chain(silent))).await;
+        assert!(body.starts_with("data: first\n\n"));
+        assert!(body.contains("event: close\n"));
+        assert!(body.contains(r#""reason":"idle""#));
+
+        let open = streams.sse(futures_util::stream::pending());
+        assert_eq!(streams.snapshot().open, 1);
+        streams.close_all();
+        assert!(frames(open).await.contains(r#""reason":"shutdown""#));
+
+        let snapshot = streams.snapshot();
+        assert_eq!(snapshot.open, 0);
+        assert_eq!(snapshot.closed["idle"], 1);
+        assert_eq!(snapshot.closed["shutdown"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_stream_close_reasons_counted() {
+        let streams = Arc::new(Streams::default());
+
+        drop(streams.sse(futures_util::stream::pending()));
+        let ended = frames(streams.sse(futures_util::stream::empty())).await;
+        assert!(ended.is_empty());
+        let lagging = futures_util::stream::iter([
+            Ok(Event::default().event("disconnected")),
+            Err(CloseReason::SlowConsumer),
+        ]);
+        let lagged = frames(streams.sse(lagging)).await;
+        assert!(lagged.starts_with("event: disconnected\n"));
+        assert!(!lagged.contains("event: close"));
+
+        let snapshot = streams.snapshot();
+        assert_eq!(snapshot.open, 0);
+        assert_eq!(snapshot.closed["client_gone"], 1);
+        assert_eq!(snapshot.closed["ended"], 1);
+        assert_eq!(snapshot.closed["slow_consumer"], 1);
+        assert!(StreamingConfig {
+            keepalive_secs: 0,
+            idle_timeout_secs: None,
+        }
+        .validate()
+        .is_err());
+    }
+}
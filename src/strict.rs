@@ -0,0 +1,205 @@
+//! Detection of unknown keys in request bodies and configuration
+//!
+//! Serde skips keys a struct does not declare, so a typo such as `comand`
+//! passes silently. The keys of a request body or config object are compared
+//! with the fields of the struct it deserializes into, and the closest field
+//! is suggested for each unknown one. In strict mode (`"strict": true` in the
+//! configuration, or `MCP_STRICT=1`) unknown keys are rejected; otherwise each
+//! is logged once.
+
+use crate::tool_schema::edit_distance;
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+
+/// Environment variable enabling strict mode
+pub const STRICT_ENV: &str = "MCP_STRICT";
+
+/// Whether `MCP_STRICT` enables strict mode
+pub fn strict_from_env() -> bool {
+    matches!(
+        std::env::var(STRICT_ENV).as_deref(),
+        Ok("1") | Ok("true") | Ok("yes")
+    )
+}
+
+/// A key the target struct does not declare
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownKey {
+    pub key: String,
+
+    /// Closest declared field, if any is close
+    pub suggestion: Option<&'static str>,
+}
+
+impl fmt::Display for UnknownKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.suggestion {
+            Some(suggestion) => write!(f, "'{}' (did you mean '{}'?)", self.key, suggestion),
+            None => write!(f, "'{}'", self.key),
+        }
+    }
+}
+
+/// Keys of `value` that `T` does not declare, in the object's order
+///
+/// Values other than objects have no unknown keys.
+pub fn unknown_keys<'de, T: Deserialize<'de>>(value: &Value) -> Vec<UnknownKey> {
+    let Value::Object(object) = value else {
+        return Vec::new();
+    };
+    let fields = fields_of::<T>();
+    object
+        .keys()
+        .filter(|key| !fields.contains(&key.as_str()))
+        .map(|key| UnknownKey {
+            key: key.clone(),
+            suggestion: closest(key, fields),
+        })
+        .collect()
+}
+
+/// Log each of `unknown` found in `context`, once per process
+pub fn warn_once(context: &str, unknown: &[UnknownKey]) {
+    static WARNED: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+    let mut warned = WARNED
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    for key in unknown {
+        if warned.insert(format!("{}\0{}", context, key.key)) {
+            tracing::warn!("Ignoring unknown key {} in {}", key, context);
+        }
+    }
+}
+
+/// List of unknown keys for an error message
+pub fn describe(unknown: &[UnknownKey]) -> String {
+    unknown
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Field closest to `key`, if within half the length of the longer name
+fn closest(key: &str, fields: &[&'static str]) -> Option<&'static str> {
+    fields
+        .iter()
+        .map(|field| (edit_distance(key, field), *field))
+        .filter(|(distance, field)| *distance <= key.len().max(field.len()) / 2)
+        .min()
+        .map(|(_, field)| field)
+}
+
+/// Field names `T` declares, empty unless it deserializes from a struct
+pub fn fields_of<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    match T::deserialize(FieldCapture) {
+        Err(Captured(fields)) => fields,
+        Ok(_) => &[],
+    }
+}
+
+/// Deserializer that fails with the field names passed to `deserialize_struct`
+struct FieldCapture;
+
+#[derive(Debug)]
+struct Captured(&'static [&'static str]);
+
+impl fmt::Display for Captured {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fields: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for Captured {}
+
+impl de::Error for Captured {
+    fn custom<T: fmt::Display>(_: T) -> Self {
+        Captured(&[])
+    }
+}
+
+impl<'de> Deserializer<'de> for FieldCapture {
+    type Error = Captured;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Captured> {
+        Err(Captured(&[]))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        fields: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, Captured> {
+        Err(Captured(fields))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::McpServerConfig;
+    use crate::process::McpRequest;
+    use crate::test_support::CapturedLogs;
+    use serde_json::json;
+
+    #[test]
+    fn test_unknown_keys_suggest_closest_field() {
+        assert_eq!(fields_of::<McpRequest>(), &["command"]);
+
+        let unknown = unknown_keys::<McpRequest>(&json!({ "comand": "{}", "zzz": 1 }));
+        assert_eq!(
+            unknown,
+            vec![
+                UnknownKey {
+                    key: "comand".to_string(),
+                    suggestion: Some("command"),
+                },
+                UnknownKey {
+                    key: "zzz".to_string(),
+                    suggestion: None,
+                },
+            ]
+        );
+        assert_eq!(
+            describe(&unknown),
+            "'comand' (did you mean 'command'?), 'zzz'"
+        );
+
+        let server = json!({ "command": "node", "arg": ["a"], "build_comand": "make" });
+        let suggestions: Vec<_> = unknown_keys::<McpServerConfig>(&server)
+            .into_iter()
+            .map(|unknown| unknown.suggestion)
+            .collect();
+        assert_eq!(suggestions, vec![Some("args"), Some("build_command")]);
+        assert!(unknown_keys::<McpRequest>(&json!({ "command": "{}" })).is_empty());
+    }
+
+    #[test]
+    fn test_unknown_keys_warned_once() {
+        let (logs, _guard) = CapturedLogs::install();
+        let unknown = unknown_keys::<McpRequest>(&json!({ "warn_once_probe": 1 }));
+        warn_once("request body", &unknown);
+        warn_once("request body", &unknown);
+        warn_once("server 'other'", &unknown);
+
+        let contents = logs.contents();
+        assert_eq!(
+            contents
+                .matches("unknown key 'warn_once_probe' in request body")
+                .count(),
+            1
+        );
+        assert_eq!(contents.matches("in server 'other'").count(), 1);
+    }
+}
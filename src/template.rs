@@ -0,0 +1,219 @@
+//! Template variables in server arguments, environment, and build command
+//!
+//! `args`, `env` values, and `build_command` may refer to values only the
+//! gateway knows, such as `{{work_dir}}` or `{{port}}`. They are expanded
+//! once the work directory is resolved, before the build and the spawn.
+//! `{{{{` stands for a literal `{{`. Unknown names are rejected when the
+//! configuration is validated.
+
+use crate::error::{McpCoreError, McpCoreResult};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// Names accepted inside `{{...}}`
+pub const TEMPLATE_VARIABLES: &[&str] = &[
+    "work_dir",
+    "server_name",
+    "repo_dir",
+    "port",
+    "hostname",
+    "uuid",
+];
+
+/// Values substituted for template variables in one spawn
+#[derive(Debug, Clone)]
+pub struct TemplateValues {
+    pub work_dir: String,
+    pub server_name: String,
+
+    /// Checkout of the repository; the work directory, which it is cloned into
+    pub repo_dir: String,
+
+    /// Port the gateway was asked to listen on, if known
+    pub port: Option<u16>,
+    pub hostname: String,
+
+    /// Random identifier, fresh for every spawn
+    pub uuid: String,
+}
+
+impl TemplateValues {
+    /// Values for a server whose work directory is `work_dir`
+    pub fn new(server_name: &str, work_dir: &str, port: Option<u16>) -> Self {
+        Self {
+            work_dir: work_dir.to_string(),
+            server_name: server_name.to_string(),
+            repo_dir: work_dir.to_string(),
+            port,
+            hostname: hostname(),
+            uuid: random_uuid(),
+        }
+    }
+
+    /// Copy of `text` with every template variable replaced
+    pub fn expand(&self, text: &str) -> McpCoreResult<String> {
+        let mut expanded = String::with_capacity(text.len());
+        let segments =
+            parse(text).map_err(|message| McpCoreError::ConfigurationError { message })?;
+        for segment in segments {
+            match segment {
+                Segment::Text(text) => expanded.push_str(text),
+                Segment::Variable(name) => expanded.push_str(&self.value(name)?),
+            }
+        }
+        Ok(expanded)
+    }
+
+    fn value(&self, name: &str) -> McpCoreResult<String> {
+        Ok(match name {
+            "work_dir" => self.work_dir.clone(),
+            "server_name" => self.server_name.clone(),
+            "repo_dir" => self.repo_dir.clone(),
+            "port" => match self.port {
+                Some(port) => port.to_string(),
+                None => {
+                    return Err(McpCoreError::ConfigurationError {
+                        message: "{{port}} is used but the gateway's port is not known".to_string(),
+                    })
+                }
+            },
+            "hostname" => self.hostname.clone(),
+            "uuid" => self.uuid.clone(),
+            _ => {
+                return Err(McpCoreError::ConfigurationError {
+                    message: unknown_variable(name),
+                })
+            }
+        })
+    }
+}
+
+/// Check that `text` only refers to known template variables
+pub fn validate(text: &str) -> Result<(), String> {
+    parse(text).map(|_| ())
+}
+
+enum Segment<'a> {
+    Text(&'a str),
+    Variable(&'a str),
+}
+
+fn parse(text: &str) -> Result<Vec<Segment<'_>>, String> {
+    let mut segments = Vec::new();
+    let mut rest = text;
+    while let Some(open) = rest.find("{{") {
+        segments.push(Segment::Text(&rest[..open]));
+        rest = &rest[open..];
+        if let Some(after) = rest.strip_prefix("{{{{") {
+            segments.push(Segment::Text("{{"));
+            rest = after;
+            continue;
+        }
+        let Some(close) = rest.find("}}") else {
+            return Err(format!("Unterminated template variable in '{}'", text));
+        };
+        let name = rest[2..close].trim();
+        if !TEMPLATE_VARIABLES.contains(&name) {
+            return Err(unknown_variable(name));
+        }
+        segments.push(Segment::Variable(name));
+        rest = &rest[close + 2..];
+    }
+    segments.push(Segment::Text(rest));
+    Ok(segments)
+}
+
+fn unknown_variable(name: &str) -> String {
+    format!(
+        "Unknown template variable '{{{{{}}}}}' (valid: {})",
+        name,
+        TEMPLATE_VARIABLES.join(", ")
+    )
+}
+
+/// Name of this host, or `localhost` if it cannot be determined
+pub(crate) fn hostname() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/proc/sys/kernel/hostname").ok())
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+/// Random version 4 UUID in its hyphenated form
+fn random_uuid() -> String {
+    let mut bytes = [0u8; 16];
+    for half in bytes.chunks_mut(8) {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_nanos())
+                .unwrap_or_default(),
+        );
+        half.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values() -> TemplateValues {
+        TemplateValues::new("echo", "/tmp/mcp-servers/echo", Some(3000))
+    }
+
+    #[test]
+    fn test_each_variable_expands() {
+        let values = values();
+        let expand = |text: &str| values.expand(text).unwrap();
+        assert_eq!(expand("{{work_dir}}/dist"), "/tmp/mcp-servers/echo/dist");
+        assert_eq!(expand("--name={{server_name}}"), "--name=echo");
+        assert_eq!(expand("{{repo_dir}}"), "/tmp/mcp-servers/echo");
+        assert_eq!(
+            expand("http://localhost:{{ port }}"),
+            "http://localhost:3000"
+        );
+        assert_eq!(expand("{{hostname}}"), values.hostname);
+        assert!(!values.hostname.is_empty());
+        assert_eq!(expand("no variables"), "no variables");
+
+        let uuid = expand("{{uuid}}");
+        assert_eq!(uuid.len(), 36);
+        assert_eq!(&uuid[14..15], "4");
+        assert_ne!(TemplateValues::new("echo", "/w", None).uuid, uuid);
+    }
+
+    #[test]
+    fn test_escape_yields_literal_braces() {
+        let values = values();
+        assert_eq!(values.expand("{{{{port}}").unwrap(), "{{port}}");
+        assert_eq!(values.expand("{{{{{{port}}").unwrap(), "{{3000");
+        assert_eq!(values.expand("a}}b").unwrap(), "a}}b");
+    }
+
+    #[test]
+    fn test_unknown_and_unresolvable_variables_fail() {
+        let error = validate("--token={{secret}}").unwrap_err();
+        assert!(error.contains("{{secret}}"));
+        assert!(error.contains("work_dir, server_name, repo_dir, port, hostname, uuid"));
+        assert!(validate("{{work_dir").is_err());
+        assert!(validate("{{{{secret}}").is_ok());
+
+        let no_port = TemplateValues::new("echo", "/w", None);
+        assert!(no_port.expand("{{port}}").is_err());
+    }
+}
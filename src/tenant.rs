@@ -0,0 +1,543 @@
+//! Tenants sharing one gateway
+//!
+//! With a top-level `tenants` section, the gateway serves the configured
+//! server once per tenant, each built from the tenant's own configuration and
+//! mounted under `/t/{tenant}`. A tenant has its own API keys, its work
+//! directories under `WORK_DIR_BASE/tenants/<tenant>`, and its own setup
+//! executor, so one tenant's failing clones and builds never hold up
+//! another's. A path naming an unknown tenant gets `404` before any
+//! authentication. `HTTP_API_KEY` authenticates the super-admin view across
+//! tenants at `/admin/tenants`. The tenant list is fixed at startup.
+
+use crate::config::McpServersConfig;
+use crate::error::{McpCoreError, McpCoreResult};
+use crate::manager::WORK_DIR_BASE;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "http-server")]
+use crate::{
+    access_log::{self, AccessLog},
+    auth::SharedAuth,
+    config::AuthConfig,
+    http_server::{self, ListenersHandle, McpHttpServer, ServerHandle, ServerState, ShutdownScope},
+    listener::{HttpConfig, ListenerConfig, RouteGroup},
+    routes::{self, ManifestConfig, Route, RouteInfo, RouteTable, MANIFEST_PATH},
+    shutdown::ShutdownConfig,
+};
+#[cfg(feature = "http-server")]
+use axum::{extract::State, middleware, response::Json, Router};
+#[cfg(feature = "http-server")]
+use std::net::{IpAddr, SocketAddr};
+#[cfg(feature = "http-server")]
+use std::sync::{Arc, OnceLock};
+
+/// Directory under `WORK_DIR_BASE` holding one subtree per tenant
+pub const TENANTS_DIR: &str = "tenants";
+
+/// Where a tenant's configuration comes from, and its API keys
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct TenantConfig {
+    /// Configuration file of the tenant, relative to the gateway's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config_file: Option<String>,
+
+    /// Inline configuration, in place of `config_file`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<Value>,
+
+    /// Environment variables holding the tenant's API keys, by key name
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub api_keys: HashMap<String, String>,
+}
+
+impl TenantConfig {
+    /// Check the tenant id and that exactly one configuration source is set
+    pub fn validate(&self, id: &str) -> Result<(), String> {
+        if id.is_empty()
+            || !id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err("id must consist of letters, digits, '-' and '_'".to_string());
+        }
+        match (&self.config_file, &self.config) {
+            (Some(_), Some(_)) => return Err("sets both config_file and config".to_string()),
+            (None, None) => return Err("needs config_file or config".to_string()),
+            _ => {}
+        }
+        match self
+            .api_keys
+            .iter()
+            .find(|(_, variable)| variable.is_empty())
+        {
+            Some((name, _)) => Err(format!("API key '{}' names no variable", name)),
+            None => Ok(()),
+        }
+    }
+
+    /// Load the tenant's configuration, with its work directories placed in
+    /// the tenant's subtree
+    ///
+    /// A `config_file` is resolved against the directory of `gateway_config`
+    /// and loaded with the gateway's `profile`.
+    pub async fn resolve(
+        &self,
+        id: &str,
+        gateway_config: &str,
+        profile: Option<&str>,
+    ) -> McpCoreResult<McpServersConfig> {
+        let mut config = match (&self.config_file, &self.config) {
+            (Some(file), _) => {
+                let path = config_dir(Path::new(gateway_config)).await.join(file);
+                McpServersConfig::load_with_profile(&path.to_string_lossy(), profile).await?
+            }
+            (None, Some(value)) => McpServersConfig::from_value(
+                value.clone(),
+                &format!("configuration of tenant '{}'", id),
+            )?,
+            (None, None) => {
+                return Err(McpCoreError::ConfigurationError {
+                    message: format!("Tenant '{}' needs config_file or config", id),
+                })
+            }
+        };
+        if !config.tenants.is_empty() {
+            return Err(McpCoreError::ConfigurationError {
+                message: format!("Configuration of tenant '{}' must not define tenants", id),
+            });
+        }
+        let base = work_dir_base(id);
+        for server in config.servers.values_mut() {
+            server.work_dir_base = Some(base.clone());
+        }
+        Ok(config)
+    }
+
+    /// The tenant's API keys read from the environment, by key name
+    pub fn api_keys(&self, id: &str) -> McpCoreResult<Vec<(String, String)>> {
+        let mut keys = self
+            .api_keys
+            .iter()
+            .map(|(name, variable)| match std::env::var(variable) {
+                Ok(key) if !key.is_empty() => Ok((name.clone(), key)),
+                _ => Err(McpCoreError::ConfigurationError {
+                    message: format!(
+                        "API key '{}' of tenant '{}': {} is not set",
+                        name, id, variable
+                    ),
+                }),
+            })
+            .collect::<McpCoreResult<Vec<_>>>()?;
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+/// Path the routes of tenant `id` are mounted under
+pub fn path_prefix(id: &str) -> String {
+    format!("/t/{}", id)
+}
+
+/// Directory holding the work directories of tenant `id`
+pub fn work_dir_base(id: &str) -> PathBuf {
+    Path::new(WORK_DIR_BASE).join(TENANTS_DIR).join(id)
+}
+
+/// Directory relative config paths are resolved against
+async fn config_dir(gateway_config: &Path) -> PathBuf {
+    let is_dir = tokio::fs::metadata(gateway_config)
+        .await
+        .map(|metadata| metadata.is_dir())
+        .unwrap_or(false);
+    match gateway_config.parent() {
+        _ if is_dir => gateway_config.to_path_buf(),
+        Some(parent) => parent.to_path_buf(),
+        None => PathBuf::new(),
+    }
+}
+
+/// Tenant a server is built for
+#[cfg(feature = "http-server")]
+#[derive(Debug, Clone)]
+pub(crate) struct TenantScope {
+    pub id: String,
+    pub auth_config: AuthConfig,
+}
+
+/// Gateway serving one server per tenant, built by
+/// [`McpHttpServerBuilder::build_tenants`](crate::http_server::McpHttpServerBuilder::build_tenants)
+#[cfg(feature = "http-server")]
+pub struct TenantGateway {
+    /// Authenticates the super-admin routes
+    pub(crate) auth: Arc<SharedAuth>,
+
+    /// Servers by tenant id, in id order
+    pub(crate) tenants: Vec<(String, McpHttpServer)>,
+    pub(crate) access_log: Option<AccessLog>,
+    pub(crate) shutdown: ShutdownConfig,
+    pub(crate) bind_host: IpAddr,
+    pub(crate) port_fallback: bool,
+    pub(crate) local_addr: Arc<OnceLock<SocketAddr>>,
+    pub(crate) listeners: Vec<ListenerConfig>,
+    pub(crate) http: HttpConfig,
+
+    /// What the manifest at the root of the gateway shows
+    pub(crate) manifest: Arc<ManifestConfig>,
+}
+
+#[cfg(feature = "http-server")]
+impl TenantGateway {
+    /// Authentication of the super-admin routes, replaceable while serving
+    pub fn auth(&self) -> Arc<SharedAuth> {
+        Arc::clone(&self.auth)
+    }
+
+    /// Ids of the tenants, in order
+    pub fn tenant_ids(&self) -> impl Iterator<Item = &str> {
+        self.tenants.iter().map(|(id, _)| id.as_str())
+    }
+
+    /// Create the router serving every tenant and the super-admin routes
+    pub fn create_router(self) -> Router {
+        let local_addr = Arc::clone(&self.local_addr);
+        self.router(&RouteGroup::ALL, local_addr)
+    }
+
+    /// Router mounting each tenant's `groups` under `/t/{tenant}`
+    ///
+    /// Unknown paths, including those of unknown tenants, fall through to
+    /// the unauthenticated `404`.
+    fn router(&self, groups: &[RouteGroup], local_addr: Arc<OnceLock<SocketAddr>>) -> Router {
+        let mut router = Router::new();
+        for (id, server) in &self.tenants {
+            router = router.nest_service(
+                &path_prefix(id),
+                server.router(groups, Arc::clone(&local_addr)),
+            );
+        }
+        let table = root_routes(groups, self.manifest.enabled);
+        let mut routes = vec![(table.infos(""), Arc::clone(&self.auth))];
+        routes.extend(
+            self.tenants
+                .iter()
+                .map(|(_, server)| (server.route_infos(groups), server.auth())),
+        );
+        let root = Arc::new(GatewayRoot {
+            tenants: self
+                .tenants
+                .iter()
+                .map(|(id, server)| (id.clone(), server.server_state().clone()))
+                .collect(),
+            manifest: Arc::clone(&self.manifest),
+            routes,
+        });
+        let (public, authenticated) = table.into_routers();
+        let authenticated = authenticated.layer(middleware::from_fn_with_state(
+            Arc::clone(&self.auth),
+            crate::auth::bearer_auth_middleware,
+        ));
+        router = router.merge(public.merge(authenticated).with_state(root));
+        let router = router
+            .fallback(http_server::not_found)
+            .layer(middleware::map_response(
+                http_server::json_method_not_allowed,
+            ));
+        match &self.access_log {
+            Some(log) => router.layer(middleware::from_fn_with_state(
+                log.clone(),
+                access_log::access_log_middleware,
+            )),
+            None => router,
+        }
+    }
+
+    fn scope(&self) -> ShutdownScope {
+        ShutdownScope {
+            config: self.shutdown.clone(),
+            states: self
+                .tenants
+                .iter()
+                .map(|(_, server)| server.server_state().clone())
+                .collect(),
+            access_log: self.access_log.clone(),
+        }
+    }
+
+    /// Serve every tenant until the gateway stops, as
+    /// [`McpHttpServer::serve`] does for a single server
+    pub async fn serve(self, port: u16) -> McpCoreResult<()> {
+        if !self.listeners.is_empty() {
+            return self.serve_all().await?.await_signal().await;
+        }
+        self.serve_background(port).await?.await_signal().await
+    }
+
+    /// Bind every configured listener and serve each one's route groups
+    pub async fn serve_all(self) -> McpCoreResult<ListenersHandle> {
+        http_server::serve_listeners(
+            &self.listeners,
+            &self.http,
+            |groups, local_addr| self.router(groups, local_addr),
+            self.scope(),
+        )
+        .await
+    }
+
+    /// Serve every tenant in a background task
+    pub async fn serve_background(self, port: u16) -> McpCoreResult<ServerHandle> {
+        let scope = self.scope();
+        let (bind_host, port_fallback) = (self.bind_host, self.port_fallback);
+        let local_addr = Arc::clone(&self.local_addr);
+        let http = self.http.clone();
+        let app = self.create_router();
+        http_server::serve_in_background(
+            bind_host,
+            port,
+            port_fallback,
+            &local_addr,
+            &http,
+            app,
+            scope,
+        )
+        .await
+    }
+}
+
+/// State of the routes at the root of a tenant gateway
+#[cfg(feature = "http-server")]
+pub(crate) struct GatewayRoot {
+    /// Each tenant's server state, by tenant id
+    tenants: Vec<(String, ServerState)>,
+    manifest: Arc<ManifestConfig>,
+
+    /// Routes at the root and then each tenant's, with the authentication
+    /// deciding whether they need a key
+    routes: Vec<(Vec<RouteInfo>, Arc<SharedAuth>)>,
+}
+
+/// Routes of `groups` at the root of a tenant gateway, with the route
+/// manifest if `manifest` is set
+#[cfg(feature = "http-server")]
+pub(crate) fn root_routes(groups: &[RouteGroup], manifest: bool) -> RouteTable<Arc<GatewayRoot>> {
+    let mut table = RouteTable::default();
+    if groups.contains(&RouteGroup::Admin) {
+        table.add(
+            RouteGroup::Admin,
+            vec![Route::get(
+                "/admin/tenants",
+                "Show the statistics of every tenant",
+                list_tenants,
+            )],
+        );
+    }
+    if groups.contains(&RouteGroup::Health) {
+        let mut routes = vec![Route::get(
+            "/health",
+            "Report that the gateway is up",
+            http_server::health_check,
+        )
+        .public()];
+        if manifest {
+            routes.push(
+                Route::get(
+                    MANIFEST_PATH,
+                    "List the routes of the gateway and every tenant, for edge routers",
+                    gateway_manifest,
+                )
+                .public(),
+            );
+        }
+        table.add(RouteGroup::Health, routes);
+    }
+    table
+}
+
+/// Request statistics, provisioning, and maintenance of every tenant
+#[cfg(feature = "http-server")]
+async fn list_tenants(State(root): State<Arc<GatewayRoot>>) -> Json<Value> {
+    let tenants: serde_json::Map<String, Value> = root
+        .tenants
+        .iter()
+        .map(|(id, server_state)| (id.clone(), http_server::stats_body(server_state)))
+        .collect();
+    Json(serde_json::json!({ "tenants": tenants }))
+}
+
+/// Routes of the gateway and of every tenant, see [`crate::routes`]
+#[cfg(feature = "http-server")]
+async fn gateway_manifest(State(root): State<Arc<GatewayRoot>>) -> Json<Value> {
+    let routes: Vec<RouteInfo> = root
+        .routes
+        .iter()
+        .flat_map(|(routes, auth)| routes::effective(routes, auth.load().enabled))
+        .collect();
+    let auth_config = root.routes[0].1.load();
+    Json(routes::manifest(
+        &routes,
+        crate::auth::auth_status(&auth_config),
+        &root.manifest,
+    ))
+}
+
+#[cfg(all(test, feature = "http-server"))]
+mod tests {
+    use super::*;
+    use crate::http_server::McpHttpServer;
+    use crate::priority::RequestPriority;
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_tenant_config_validated() {
+        let tenant = TenantConfig {
+            config_file: Some("acme.json".to_string()),
+            ..Default::default()
+        };
+        assert!(tenant.validate("acme-1").is_ok());
+        assert!(tenant.validate("acme/1").unwrap_err().contains("id must"));
+        assert!(TenantConfig::default()
+            .validate("acme")
+            .unwrap_err()
+            .contains("needs config_file"));
+        let both = TenantConfig {
+            config: Some(serde_json::json!({})),
+            ..tenant.clone()
+        };
+        assert!(both.validate("acme").unwrap_err().contains("both"));
+        let unnamed = TenantConfig {
+            api_keys: HashMap::from([("ci".to_string(), String::new())]),
+            ..tenant
+        };
+        assert!(unnamed.validate("acme").unwrap_err().contains("'ci'"));
+
+        let missing = TenantConfig {
+            api_keys: HashMap::from([("ci".to_string(), "MCP_TEST_TENANT_KEY_UNSET".to_string())]),
+            ..Default::default()
+        };
+        let error = missing.api_keys("acme").unwrap_err().to_string();
+        assert!(error.contains("MCP_TEST_TENANT_KEY_UNSET is not set"));
+    }
+
+    async fn get(router: &Router, path: &str, key: Option<&str>) -> (StatusCode, Value) {
+        let mut request = Request::get(path);
+        if let Some(key) = key {
+            request = request.header("authorization", format!("Bearer {}", key));
+        }
+        let response = router
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    #[tokio::test]
+    async fn test_tenants_with_same_named_servers_are_isolated() {
+        std::env::set_var("MCP_TEST_TENANT_ACME_KEY", "acme-secret");
+        std::env::set_var("MCP_TEST_TENANT_GLOBEX_KEY", "globex-secret");
+        let server = serde_json::json!({
+            "servers": { "echo": { "command": "cat", "setup_mode": "manual" } }
+        });
+        let config = McpServersConfig::from_value(
+            serde_json::json!({
+                "tenants": {
+                    "acme": {
+                        "config": server,
+                        "api_keys": { "acme-ci": "MCP_TEST_TENANT_ACME_KEY" }
+                    },
+                    "globex": {
+                        "config": server,
+                        "api_keys": { "globex-ci": "MCP_TEST_TENANT_GLOBEX_KEY" }
+                    }
+                }
+            }),
+            "test configuration",
+        )
+        .unwrap();
+        let gateway = McpHttpServer::builder("unused.json", "echo")
+            .config(config)
+            .bind_host(std::net::Ipv4Addr::LOCALHOST.into())
+            .build_tenants()
+            .await
+            .unwrap();
+        gateway
+            .auth()
+            .replace(AuthConfig {
+                api_key: Some("root-secret".to_string()),
+                enabled: true,
+                default_priority: RequestPriority::Normal,
+                named_keys: Vec::new(),
+            })
+            .unwrap();
+        assert_eq!(gateway.tenant_ids().collect::<Vec<_>>(), ["acme", "globex"]);
+
+        let acme = gateway.tenants[0].1.server_state().clone();
+        let globex = gateway.tenants[1].1.server_state().clone();
+        assert_eq!(acme.work_dir_base, work_dir_base("acme"));
+        assert_eq!(globex.work_dir_base, work_dir_base("globex"));
+        assert!(!Arc::ptr_eq(&acme.setup, &globex.setup));
+        let router = gateway.create_router();
+
+        // Unknown tenants are not found, whatever the credentials
+        let (status, body) = get(&router, "/t/initech/api/v1/info", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(body["message"].as_str().unwrap().contains("/t/initech"));
+
+        // Each tenant accepts only its own keys
+        let (status, _) = get(&router, "/t/acme/api/v1/info", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = get(&router, "/t/acme/api/v1/info", Some("globex-secret")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, body) = get(&router, "/t/acme/api/v1/info", Some("acme-secret")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["server_name"], "echo");
+
+        // Draining one tenant's server leaves the other's alone
+        let request = Request::post("/t/acme/admin/servers/echo/drain")
+            .header("authorization", "Bearer acme-secret")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let (_, body) = get(&router, "/t/acme/api/v1/info", Some("acme-secret")).await;
+        assert!(!body["maintenance"].is_null());
+        let (_, body) = get(&router, "/t/globex/api/v1/info", Some("globex-secret")).await;
+        assert!(body["maintenance"].is_null());
+
+        // The super-admin view needs the gateway key and covers every tenant
+        let (status, _) = get(&router, "/admin/tenants", Some("acme-secret")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, body) = get(&router, "/admin/tenants", Some("root-secret")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!body["tenants"]["acme"]["maintenance"].is_null());
+        assert!(body["tenants"]["globex"]["maintenance"].is_null());
+        assert_eq!(body["tenants"]["globex"]["server"], "echo");
+
+        // The root manifest lists its own routes and each tenant's, prefixed
+        let (status, manifest) = get(&router, MANIFEST_PATH, None).await;
+        assert_eq!(status, StatusCode::OK);
+        let routes = manifest["routes"].as_array().unwrap();
+        let find = |path: &str| {
+            routes
+                .iter()
+                .find(|route| route["path"] == path)
+                .unwrap_or_else(|| panic!("{} not listed", path))
+        };
+        assert_eq!(find("/admin/tenants")["auth"], "admin");
+        assert_eq!(find("/health")["auth"], "none");
+        assert_eq!(find("/t/acme/api/v1/info")["auth"], "bearer");
+        assert_eq!(find("/t/globex/ready")["auth"], "none");
+        assert!(!routes.iter().any(|route| route["path"] == "/api/v1/info"));
+        for path in ["/health", "/t/acme/version", "/t/globex/version"] {
+            assert_eq!(get(&router, path, None).await.0, StatusCode::OK, "{}", path);
+        }
+    }
+}
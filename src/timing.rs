@@ -0,0 +1,214 @@
+//! Wall-clock timing of startup phases
+//!
+//! [`PhaseTimer`] records how long each phase of bringing up an MCP server
+//! took (work directory preparation, clone, build, spawn or connect,
+//! initialize). The finished [`PhaseTimings`] are logged as a table and
+//! served by the info and stats endpoints. While phases are still running, a
+//! [`PhaseProgress`] attached to the timer shows how far startup got.
+
+use serde::Serialize;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Duration of one phase
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PhaseTiming {
+    pub phase: &'static str,
+    pub duration_ms: f64,
+}
+
+/// Durations of all recorded phases
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PhaseTimings {
+    pub phases: Vec<PhaseTiming>,
+    pub total_ms: f64,
+}
+
+/// Phases finished so far and the one running, shared with a running timer
+#[derive(Debug, Default)]
+pub struct PhaseProgress {
+    state: Mutex<ProgressSnapshot>,
+}
+
+/// Point-in-time view of a [`PhaseProgress`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ProgressSnapshot {
+    pub phases: Vec<PhaseTiming>,
+    pub current_phase: Option<&'static str>,
+}
+
+impl PhaseProgress {
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ProgressSnapshot> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Records the duration of consecutive phases
+#[derive(Debug)]
+pub struct PhaseTimer {
+    started: Instant,
+    phases: Vec<PhaseTiming>,
+    progress: Option<Arc<PhaseProgress>>,
+}
+
+impl Default for PhaseTimer {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            phases: Vec::new(),
+            progress: None,
+        }
+    }
+}
+
+impl PhaseTimer {
+    /// Timer that also reports each phase to `progress` as it starts and ends
+    pub fn observed(progress: Arc<PhaseProgress>) -> Self {
+        Self {
+            progress: Some(progress),
+            ..Self::default()
+        }
+    }
+
+    /// Progress reported by the timer, attaching one if it has none
+    pub fn observe(&mut self) -> Arc<PhaseProgress> {
+        Arc::clone(self.progress.get_or_insert_with(Arc::default))
+    }
+
+    /// Run `future` and record its duration under `phase`
+    ///
+    /// The duration is recorded even when the future resolves to an error.
+    pub async fn measure<F: Future>(&mut self, phase: &'static str, future: F) -> F::Output {
+        if let Some(progress) = &self.progress {
+            progress.lock().current_phase = Some(phase);
+        }
+        let started = Instant::now();
+        let output = future.await;
+        self.record(phase, started.elapsed());
+        output
+    }
+
+    /// Record a phase timed by the caller
+    pub fn record(&mut self, phase: &'static str, duration: Duration) {
+        let timing = PhaseTiming {
+            phase,
+            duration_ms: millis(duration),
+        };
+        if let Some(progress) = &self.progress {
+            let mut state = progress.lock();
+            state.phases.push(timing.clone());
+            state.current_phase = None;
+        }
+        self.phases.push(timing);
+    }
+
+    /// Stop timing, with the total measured from the timer's creation
+    pub fn finish(self) -> PhaseTimings {
+        PhaseTimings {
+            phases: self.phases,
+            total_ms: millis(self.started.elapsed()),
+        }
+    }
+}
+
+impl PhaseTimings {
+    /// Plain-text table of the phases and the total, for logging
+    pub fn table(&self) -> String {
+        let width = self
+            .phases
+            .iter()
+            .map(|timing| timing.phase.len())
+            .chain(["total".len()])
+            .max()
+            .unwrap_or_default();
+        self.phases
+            .iter()
+            .map(|timing| (timing.phase, timing.duration_ms))
+            .chain([("total", self.total_ms)])
+            .map(|(phase, ms)| format!("{:<width$}  {:>10.1} ms", phase, ms, width = width))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Milliseconds rounded to a tenth
+fn millis(duration: Duration) -> f64 {
+    (duration.as_secs_f64() * 10_000.0).round() / 10.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_measure_records_phases_in_order() {
+        let mut timer = PhaseTimer::default();
+        let value = timer
+            .measure("clone", async {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                7
+            })
+            .await;
+        assert_eq!(value, 7);
+
+        let failed: Result<(), &str> = timer.measure("build", async { Err("failed") }).await;
+        assert!(failed.is_err());
+        timer.record("spawn", Duration::from_micros(1500));
+
+        let timings = timer.finish();
+        let phases: Vec<&str> = timings.phases.iter().map(|timing| timing.phase).collect();
+        assert_eq!(phases, ["clone", "build", "spawn"]);
+        assert!(timings.phases[0].duration_ms >= 20.0);
+        assert_eq!(timings.phases[2].duration_ms, 1.5);
+        assert!(timings.total_ms >= timings.phases[0].duration_ms);
+    }
+
+    #[tokio::test]
+    async fn test_progress_shows_running_phase() {
+        let progress = Arc::new(PhaseProgress::default());
+        let mut timer = PhaseTimer::observed(Arc::clone(&progress));
+        timer.record("work_dir", Duration::from_millis(2));
+        let observer = Arc::clone(&progress);
+        timer
+            .measure("clone", async move {
+                let snapshot = observer.snapshot();
+                assert_eq!(snapshot.current_phase, Some("clone"));
+                assert_eq!(snapshot.phases.len(), 1);
+            })
+            .await;
+
+        let snapshot = progress.snapshot();
+        assert_eq!(snapshot.current_phase, None);
+        assert_eq!(snapshot.phases, timer.finish().phases);
+    }
+
+    #[test]
+    fn test_table_lists_phases_and_total() {
+        let timings = PhaseTimings {
+            phases: vec![
+                PhaseTiming {
+                    phase: "clone",
+                    duration_ms: 1200.0,
+                },
+                PhaseTiming {
+                    phase: "initialize",
+                    duration_ms: 35.5,
+                },
+            ],
+            total_ms: 1240.0,
+        };
+        let table = timings.table();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("clone "));
+        assert!(lines[1].ends_with("35.5 ms"));
+        assert!(lines[2].starts_with("total"));
+    }
+}
@@ -0,0 +1,440 @@
+//! Validation of `tools/call` arguments against the tools' input schemas
+//!
+//! With `validate_tool_arguments` set, the gateway keeps the tools the server
+//! listed, with a compiled validator for each `inputSchema`, and checks the
+//! `arguments` of every `tools/call` before it reaches the server. The list
+//! is fetched with a `tools/list` of the gateway's own when none is known,
+//! replaced whenever a client's complete `tools/list` passes through, and
+//! dropped when the server sends `notifications/tools/list_changed`.
+
+use crate::error::{McpCoreError, McpCoreResult};
+use crate::server_requests::ServerRequestHandlers;
+use crate::transport::{self, McpTransport};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Notification announcing that the server's tools changed
+const LIST_CHANGED: &str = "notifications/tools/list_changed";
+
+/// Maximum similar tool names suggested for an unknown one
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Pages of `tools/list` fetched before giving up on a cursor loop
+const MAX_PAGES: usize = 100;
+
+/// One way in which a value violates a schema
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SchemaViolation {
+    /// JSON pointer to the offending value inside the checked `arguments`
+    /// or `result`
+    pub instance_path: String,
+
+    /// Schema keyword that failed, such as `required` or `type`
+    pub keyword: String,
+    pub message: String,
+}
+
+impl SchemaViolation {
+    pub(crate) fn from_error(error: &jsonschema::ValidationError<'_>) -> Self {
+        Self {
+            instance_path: error.instance_path.to_string(),
+            keyword: error
+                .schema_path
+                .as_str()
+                .rsplit('/')
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            message: error.to_string(),
+        }
+    }
+}
+
+/// Validators of the tools the server listed
+#[derive(Default)]
+pub struct ToolSchemas {
+    /// Known tools by name; `None` until the list is known
+    tools: Mutex<Option<HashMap<String, KnownTool>>>,
+    next_id: AtomicU64,
+}
+
+/// Input schema of a listed tool
+struct KnownTool {
+    schema: Option<Value>,
+
+    /// `None` for tools without a usable schema
+    validator: Option<Arc<jsonschema::Validator>>,
+}
+
+impl std::fmt::Debug for ToolSchemas {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ToolSchemas")
+            .field("tools", &self.tool_names())
+            .finish()
+    }
+}
+
+impl ToolSchemas {
+    /// Check the `tools/call` requests in `command` before it is sent
+    ///
+    /// Fetches the tool list over `transport` first if it is not known.
+    pub async fn check(
+        &self,
+        transport: &mut dyn McpTransport,
+        handlers: &ServerRequestHandlers,
+        command: &str,
+    ) -> McpCoreResult<()> {
+        self.observe_buffered(transport);
+        let Ok(message) = serde_json::from_str::<Value>(command) else {
+            return Ok(());
+        };
+        let calls: Vec<&Value> = match &message {
+            Value::Array(batch) => batch.iter().filter(|m| is_tool_call(m)).collect(),
+            single if is_tool_call(single) => vec![single],
+            _ => Vec::new(),
+        };
+        if calls.is_empty() {
+            return Ok(());
+        }
+        if self.lock().is_none() {
+            self.fetch(transport, handlers).await?;
+        }
+        calls.into_iter().try_for_each(|call| self.check_call(call))
+    }
+
+    /// Input schema of `tool`, if it is listed with one
+    ///
+    /// Fetches the tool list over `transport` first if it is not known.
+    pub async fn input_schema(
+        &self,
+        transport: &mut dyn McpTransport,
+        handlers: &ServerRequestHandlers,
+        tool: &str,
+    ) -> McpCoreResult<Option<Value>> {
+        self.observe_buffered(transport);
+        if self.lock().is_none() {
+            self.fetch(transport, handlers).await?;
+        }
+        Ok(self
+            .lock()
+            .as_ref()
+            .and_then(|tools| tools.get(tool))
+            .and_then(|tool| tool.schema.clone()))
+    }
+
+    /// Keep the tools of a client's `tools/list` response
+    ///
+    /// Only a first page that is also the last describes every tool.
+    pub fn observe_response(&self, command: &str, response: &str) {
+        let Ok(request) = serde_json::from_str::<Value>(command) else {
+            return;
+        };
+        if request.get("method").and_then(Value::as_str) != Some("tools/list")
+            || request.pointer("/params/cursor").is_some()
+        {
+            return;
+        }
+        let Ok(response) = serde_json::from_str::<Value>(response) else {
+            return;
+        };
+        match response.get("result") {
+            Some(result) if result.get("nextCursor").is_none() => {
+                if let Some(Value::Array(tools)) = result.get("tools") {
+                    self.load(tools);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Forget the tool list if one of `notifications` announced a change
+    pub fn observe_notifications(&self, notifications: &[String]) {
+        if notifications.iter().any(|n| n.contains(LIST_CHANGED)) {
+            tracing::debug!("Tool list changed, dropping cached schemas");
+            *self.lock() = None;
+        }
+    }
+
+    /// Observe the notifications buffered on `transport`, leaving them there
+    fn observe_buffered(&self, transport: &mut dyn McpTransport) {
+        let notifications = transport.drain_notifications();
+        self.observe_notifications(&notifications);
+        for notification in notifications {
+            transport.buffer_notification(notification);
+        }
+    }
+
+    /// Request every page of the tool list from the server
+    async fn fetch(
+        &self,
+        transport: &mut dyn McpTransport,
+        handlers: &ServerRequestHandlers,
+    ) -> McpCoreResult<()> {
+        let mut tools = Vec::new();
+        let mut cursor: Option<Value> = None;
+        for _ in 0..MAX_PAGES {
+            let id = Value::from(format!(
+                "gateway-tools-{}",
+                self.next_id.fetch_add(1, Ordering::Relaxed) + 1
+            ));
+            let mut request =
+                serde_json::json!({ "jsonrpc": "2.0", "id": id, "method": "tools/list" });
+            if let Some(cursor) = cursor.take() {
+                request["params"] = serde_json::json!({ "cursor": cursor });
+            }
+            transport.send(&request.to_string()).await?;
+            let response = transport::receive_response(
+                transport,
+                handlers,
+                Some(&id),
+                transport::RESPONSE_TIMEOUT,
+            )
+            .await?;
+            let mut response: Value = serde_json::from_str(&response)?;
+            let Some(result) = response.get_mut("result") else {
+                return Err(McpCoreError::ProcessError {
+                    message: format!("tools/list failed: {}", response["error"]),
+                });
+            };
+            if let Some(Value::Array(page)) = result.get_mut("tools") {
+                tools.append(page);
+            }
+            match result.get("nextCursor") {
+                Some(next) if !next.is_null() => cursor = Some(next.clone()),
+                _ => {
+                    self.load(&tools);
+                    return Ok(());
+                }
+            }
+        }
+        Err(McpCoreError::ProcessError {
+            message: format!("tools/list returned more than {} pages", MAX_PAGES),
+        })
+    }
+
+    /// Replace the known tools, compiling each input schema
+    fn load(&self, tools: &[Value]) {
+        let schemas = tools
+            .iter()
+            .filter_map(|tool| {
+                let name = tool.get("name")?.as_str()?.to_string();
+                let schema = tool.get("inputSchema").cloned();
+                let validator = schema.as_ref().and_then(|schema| {
+                    jsonschema::validator_for(schema)
+                        .map_err(|e| {
+                            tracing::warn!(
+                                "Not validating tool '{}': invalid inputSchema: {}",
+                                name,
+                                e
+                            )
+                        })
+                        .ok()
+                });
+                Some((
+                    name,
+                    KnownTool {
+                        schema,
+                        validator: validator.map(Arc::new),
+                    },
+                ))
+            })
+            .collect();
+        *self.lock() = Some(schemas);
+    }
+
+    fn check_call(&self, call: &Value) -> McpCoreResult<()> {
+        let name = call
+            .pointer("/params/name")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let validator = {
+            let tools = self.lock();
+            let Some(tools) = tools.as_ref() else {
+                return Ok(());
+            };
+            match tools.get(name) {
+                Some(tool) => tool.validator.clone(),
+                None => return Err(unknown_tool(name, tools.keys())),
+            }
+        };
+        let Some(validator) = validator else {
+            return Ok(());
+        };
+
+        let empty = Value::Object(Default::default());
+        let arguments = call.pointer("/params/arguments").unwrap_or(&empty);
+        let errors: Vec<SchemaViolation> = validator
+            .iter_errors(arguments)
+            .map(|error| SchemaViolation::from_error(&error))
+            .collect();
+        if errors.is_empty() {
+            return Ok(());
+        }
+        Err(McpCoreError::InvalidToolArguments {
+            message: format!(
+                "Arguments of tool '{}' do not match its inputSchema: {}",
+                name,
+                errors
+                    .iter()
+                    .map(|error| error.message.as_str())
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            ),
+            errors,
+        })
+    }
+
+    /// Whether `tool` is in the known list
+    pub fn lists(&self, tool: &str) -> bool {
+        self.lock()
+            .as_ref()
+            .is_some_and(|tools| tools.contains_key(tool))
+    }
+
+    /// Names of the known tools, sorted, or `None` if the list is not known
+    pub fn tool_names(&self) -> Option<Vec<String>> {
+        self.lock().as_ref().map(|tools| {
+            let mut names: Vec<String> = tools.keys().cloned().collect();
+            names.sort();
+            names
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<HashMap<String, KnownTool>>> {
+        self.tools
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn is_tool_call(message: &Value) -> bool {
+    message.get("method").and_then(Value::as_str) == Some("tools/call")
+}
+
+/// 404 naming the tools whose names are closest to `name`
+fn unknown_tool<'a>(name: &str, known: impl Iterator<Item = &'a String>) -> McpCoreError {
+    let mut similar: Vec<(usize, &String)> = known
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, candidate)| {
+            *distance <= name.len().max(candidate.len()) / 2
+                || candidate.contains(name)
+                || name.contains(candidate.as_str())
+        })
+        .collect();
+    similar.sort();
+    let similar: Vec<&str> = similar
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, candidate)| candidate.as_str())
+        .collect();
+    let message = if similar.is_empty() {
+        format!("Unknown tool '{}'", name)
+    } else {
+        format!("Unknown tool '{}' (similar: {})", name, similar.join(", "))
+    };
+    McpCoreError::NotFound { message }
+}
+
+/// Levenshtein distance between two names
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schemas() -> ToolSchemas {
+        let schemas = ToolSchemas::default();
+        schemas.load(&[
+            serde_json::json!({
+                "name": "create_issue",
+                "inputSchema": {
+                    "type": "object",
+                    "properties": { "title": { "type": "string" }, "priority": { "type": "integer" } },
+                    "required": ["title"]
+                }
+            }),
+            serde_json::json!({ "name": "list_issues" }),
+        ]);
+        schemas
+    }
+
+    fn call(name: &str, arguments: Value) -> Value {
+        serde_json::json!({
+            "jsonrpc": "2.0", "id": 1, "method": "tools/call",
+            "params": { "name": name, "arguments": arguments }
+        })
+    }
+
+    #[test]
+    fn test_arguments_checked_against_schema() {
+        let schemas = schemas();
+        assert!(schemas
+            .check_call(&call("create_issue", serde_json::json!({ "title": "Bug" })))
+            .is_ok());
+        assert!(schemas
+            .check_call(&call("list_issues", serde_json::json!({ "anything": 1 })))
+            .is_ok());
+
+        let error = schemas
+            .check_call(&call(
+                "create_issue",
+                serde_json::json!({ "priority": "high" }),
+            ))
+            .unwrap_err();
+        let McpCoreError::InvalidToolArguments { errors, .. } = error else {
+            panic!("unexpected error: {}", error);
+        };
+        let mut found: Vec<(&str, &str)> = errors
+            .iter()
+            .map(|e| (e.instance_path.as_str(), e.keyword.as_str()))
+            .collect();
+        found.sort();
+        assert_eq!(found, [("", "required"), ("/priority", "type")]);
+    }
+
+    #[test]
+    fn test_unknown_tools_suggest_similar_names() {
+        let error = schemas()
+            .check_call(&call("create_isue", serde_json::json!({})))
+            .unwrap_err();
+        assert!(matches!(&error, McpCoreError::NotFound { .. }));
+        assert!(error.to_string().contains("similar: create_issue"));
+
+        let error = schemas()
+            .check_call(&call("weather", serde_json::json!({})))
+            .unwrap_err();
+        assert!(!error.to_string().contains("similar"));
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn test_observed_list_replaces_tools() {
+        let schemas = schemas();
+        let list = r#"{"jsonrpc":"2.0","id":1,"method":"tools/list"}"#;
+        schemas.observe_response(
+            list,
+            r#"{"jsonrpc":"2.0","id":1,"result":{"tools":[{"name":"a"}],"nextCursor":"2"}}"#,
+        );
+        assert_eq!(schemas.tool_names().unwrap().len(), 2);
+        schemas.observe_response(
+            list,
+            r#"{"jsonrpc":"2.0","id":1,"result":{"tools":[{"name":"a"}]}}"#,
+        );
+        assert_eq!(schemas.tool_names().unwrap(), ["a"]);
+    }
+}
@@ -0,0 +1,386 @@
+//! Per-tool call statistics served by `GET /api/v1/stats/tools`
+//!
+//! Every `tools/call` is counted under the name in its `params.name`, with
+//! a latency histogram and its outcome: a result, a result with `isError`
+//! set, a JSON-RPC error, a failure of the gateway to get an answer, or a
+//! refusal by the gateway before it was sent. Clients choose the names, so
+//! only tools the server listed get an entry of their own; calls to any
+//! other name are counted together under `other`. Names are learned from
+//! `tools/list` responses passing through the gateway, and from the tool
+//! list kept for argument validation, up to [`MAX_TOOLS`]. Counts cover the
+//! life of the process.
+
+use crate::error::McpCoreError;
+use crate::stats::{self, LatencyPercentiles, LATENCY_BUCKETS};
+use crate::tool_schema::ToolSchemas;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Most tool names given an entry of their own
+pub const MAX_TOOLS: usize = 512;
+
+/// How a tool call ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolOutcome {
+    /// A result without `isError`
+    Ok,
+    /// A result with `isError: true`: the tool ran and reported a failure
+    ToolError,
+    /// A JSON-RPC error from the server
+    RpcError,
+    /// No answer: the server failed, timed out, or the call was aborted
+    TransportError,
+    /// Refused by the gateway (4xx) before reaching the server
+    Rejected,
+}
+
+impl ToolOutcome {
+    /// Outcome of a call given the gateway's error
+    fn of_error(error: &McpCoreError) -> Self {
+        match error.status_code().is_client_error() {
+            true => Self::Rejected,
+            false => Self::TransportError,
+        }
+    }
+
+    /// Outcome of a call given the server's answer to it
+    fn of_answer(answer: Option<&Value>) -> Self {
+        let Some(answer) = answer else {
+            return Self::TransportError;
+        };
+        if answer.get("error").is_some() {
+            return Self::RpcError;
+        }
+        match answer.pointer("/result/isError").and_then(Value::as_bool) {
+            Some(true) => Self::ToolError,
+            _ => Self::Ok,
+        }
+    }
+}
+
+/// Counts by outcome
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ToolErrorCounts {
+    pub tool: u64,
+    pub rpc: u64,
+    pub transport: u64,
+    pub rejected: u64,
+}
+
+/// Share of calls ending in each kind of error
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ToolErrorRates {
+    pub tool: f64,
+    pub rpc: f64,
+    pub transport: f64,
+    pub rejected: f64,
+}
+
+/// Statistics of one tool, or of every unlisted one
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ToolCallStats {
+    pub calls: u64,
+    pub errors: ToolErrorCounts,
+    pub error_rate: ToolErrorRates,
+    pub latency_ms: LatencyPercentiles,
+}
+
+/// Every tool's statistics
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ToolStatsSnapshot {
+    /// Listed tools that were called, by name
+    pub tools: BTreeMap<String, ToolCallStats>,
+
+    /// Calls to tools the server did not list
+    pub other: ToolCallStats,
+
+    /// Tool names learned so far
+    pub known_tools: usize,
+}
+
+struct Counters {
+    calls: u64,
+    errors: ToolErrorCounts,
+    latency: [u64; LATENCY_BUCKETS],
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Self {
+            calls: 0,
+            errors: ToolErrorCounts::default(),
+            latency: [0; LATENCY_BUCKETS],
+        }
+    }
+}
+
+impl Counters {
+    fn record(&mut self, outcome: ToolOutcome, latency: Duration) {
+        self.calls += 1;
+        match outcome {
+            ToolOutcome::Ok => {}
+            ToolOutcome::ToolError => self.errors.tool += 1,
+            ToolOutcome::RpcError => self.errors.rpc += 1,
+            ToolOutcome::TransportError => self.errors.transport += 1,
+            ToolOutcome::Rejected => self.errors.rejected += 1,
+        }
+        self.latency[stats::latency_bucket(latency)] += 1;
+    }
+
+    fn stats(&self) -> ToolCallStats {
+        let rate = |count: u64| match self.calls {
+            0 => 0.0,
+            calls => (count as f64 / calls as f64 * 10_000.0).round() / 10_000.0,
+        };
+        ToolCallStats {
+            calls: self.calls,
+            error_rate: ToolErrorRates {
+                tool: rate(self.errors.tool),
+                rpc: rate(self.errors.rpc),
+                transport: rate(self.errors.transport),
+                rejected: rate(self.errors.rejected),
+            },
+            errors: self.errors.clone(),
+            latency_ms: LatencyPercentiles::of(&self.latency),
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    /// Names learned from `tools/list` responses
+    known: HashSet<String>,
+    tools: HashMap<String, Counters>,
+    other: Counters,
+}
+
+/// Per-tool call statistics for one server
+#[derive(Default)]
+pub struct ToolStats {
+    state: Mutex<State>,
+
+    /// Tool list kept for argument validation, if any
+    schemas: Option<Arc<ToolSchemas>>,
+}
+
+impl std::fmt::Debug for ToolStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ToolStats")
+            .field("known_tools", &self.lock().known.len())
+            .finish()
+    }
+}
+
+impl ToolStats {
+    /// Statistics also taking tool names from `schemas`
+    pub fn new(schemas: Option<Arc<ToolSchemas>>) -> Self {
+        Self {
+            state: Mutex::default(),
+            schemas,
+        }
+    }
+
+    /// Record the `tools/call` requests in `command` and their outcomes,
+    /// and learn the tools of a `tools/list` response
+    pub fn record(&self, command: &str, response: Result<&str, &McpCoreError>, latency: Duration) {
+        if !command.contains("tools/") {
+            return;
+        }
+        let Ok(request) = serde_json::from_str::<Value>(command) else {
+            return;
+        };
+        let requests: Vec<&Value> = match &request {
+            Value::Array(batch) => batch.iter().collect(),
+            single => vec![single],
+        };
+        let answers: Option<Value> = response
+            .ok()
+            .and_then(|response| serde_json::from_str(response).ok());
+
+        let mut state = self.lock();
+        for request in requests {
+            let method = request.get("method").and_then(Value::as_str);
+            let answer = answers
+                .as_ref()
+                .and_then(|answers| answer_to(answers, request.get("id")));
+            match method {
+                Some("tools/list") => {
+                    if let Some(Value::Array(tools)) =
+                        answer.and_then(|a| a.pointer("/result/tools"))
+                    {
+                        learn(&mut state, tools);
+                    }
+                }
+                Some("tools/call") => {
+                    let outcome = match response {
+                        Err(error) => ToolOutcome::of_error(error),
+                        Ok(_) => ToolOutcome::of_answer(answer),
+                    };
+                    let name = request.pointer("/params/name").and_then(Value::as_str);
+                    let counters = match name {
+                        Some(name) if self.is_known(&state, name) => {
+                            state.tools.entry(name.to_string()).or_default()
+                        }
+                        _ => &mut state.other,
+                    };
+                    counters.record(outcome, latency);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> ToolStatsSnapshot {
+        let state = self.lock();
+        ToolStatsSnapshot {
+            tools: state
+                .tools
+                .iter()
+                .map(|(name, counters)| (name.clone(), counters.stats()))
+                .collect(),
+            other: state.other.stats(),
+            known_tools: state.known.len(),
+        }
+    }
+
+    fn is_known(&self, state: &State, name: &str) -> bool {
+        state.known.contains(name)
+            || state.tools.contains_key(name)
+            || (state.tools.len() < MAX_TOOLS
+                && self
+                    .schemas
+                    .as_ref()
+                    .is_some_and(|schemas| schemas.lists(name)))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Add the names of listed `tools`, up to [`MAX_TOOLS`]
+fn learn(state: &mut State, tools: &[Value]) {
+    for name in tools
+        .iter()
+        .filter_map(|tool| tool.get("name").and_then(Value::as_str))
+    {
+        if state.known.len() >= MAX_TOOLS {
+            tracing::debug!("Tool statistics already track {} tools", MAX_TOOLS);
+            return;
+        }
+        if !state.known.contains(name) {
+            state.known.insert(name.to_string());
+        }
+    }
+}
+
+/// The answer in `answers` to the request with `id`
+fn answer_to<'a>(answers: &'a Value, id: Option<&Value>) -> Option<&'a Value> {
+    match answers {
+        Value::Array(batch) => batch
+            .iter()
+            .find(|answer| id.is_some() && answer.get("id") == id),
+        single => Some(single),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIST: &str = r#"{"jsonrpc":"2.0","id":1,"method":"tools/list"}"#;
+    const TOOLS: &str =
+        r#"{"jsonrpc":"2.0","id":1,"result":{"tools":[{"name":"echo"},{"name":"search"}]}}"#;
+
+    fn call(tool: &str) -> String {
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "tools/call",
+            "params": { "name": tool, "arguments": {} }
+        })
+        .to_string()
+    }
+
+    fn result(is_error: bool) -> String {
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "result": { "content": [], "isError": is_error }
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_listed_tools_are_counted_apart() {
+        let stats = ToolStats::default();
+        let latency = Duration::from_millis(5);
+
+        // Nothing is listed yet, so the call counts as other
+        stats.record(&call("echo"), Ok(&result(false)), latency);
+        stats.record(LIST, Ok(TOOLS), latency);
+        stats.record(&call("echo"), Ok(&result(false)), latency);
+        stats.record(&call("echo"), Ok(&result(true)), latency);
+        stats.record(&call("made-up"), Ok(&result(false)), latency);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.known_tools, 2);
+        assert_eq!(snapshot.tools.keys().collect::<Vec<_>>(), vec!["echo"]);
+        let echo = &snapshot.tools["echo"];
+        assert_eq!(echo.calls, 2);
+        assert_eq!(echo.errors.tool, 1);
+        assert_eq!(echo.error_rate.tool, 0.5);
+        assert!(echo.latency_ms.p50.is_some());
+        assert_eq!(snapshot.other.calls, 2);
+        assert_eq!(snapshot.other.errors.tool, 0);
+    }
+
+    #[test]
+    fn test_errors_are_told_apart() {
+        let stats = ToolStats::default();
+        let latency = Duration::from_millis(1);
+        stats.record(LIST, Ok(TOOLS), latency);
+
+        let rpc_error = r#"{"jsonrpc":"2.0","id":2,"error":{"code":-32602,"message":"bad"}}"#;
+        stats.record(&call("search"), Ok(rpc_error), latency);
+        let timeout = McpCoreError::RequestTimeout {
+            message: "timed out".to_string(),
+            timeout_secs: 30,
+            limit: "default".to_string(),
+        };
+        stats.record(&call("search"), Err(&timeout), latency);
+        let refused = McpCoreError::RequestError {
+            message: "quota".to_string(),
+        };
+        stats.record(&call("search"), Err(&refused), latency);
+        stats.record(&call("search"), Ok(&result(true)), latency);
+
+        // A batch is matched to its answers by id
+        let batch = format!(
+            "[{},{}]",
+            call("echo"),
+            LIST.replace("\"id\":1", "\"id\":3")
+        );
+        let answers = format!("[{}]", result(true));
+        stats.record(&batch, Ok(&answers), latency);
+
+        let search = &stats.snapshot().tools["search"];
+        assert_eq!(search.calls, 4);
+        assert_eq!(
+            (
+                search.errors.rpc,
+                search.errors.transport,
+                search.errors.rejected,
+                search.errors.tool
+            ),
+            (1, 1, 1, 1)
+        );
+        assert_eq!(search.error_rate.transport, 0.25);
+        assert_eq!(stats.snapshot().tools["echo"].errors.tool, 1);
+    }
+}
@@ -0,0 +1,1378 @@
+//! Transports carrying JSON-RPC messages to and from MCP servers
+//!
+//! [`McpTransport`] abstracts over how messages reach a server: a local
+//! stdio process ([`crate::process::McpProcess`]), a raw TCP socket
+//! ([`TcpTransport`]), or an upstream Streamable HTTP server (behind the
+//! `reqwest` feature). The handshake and request handling are written
+//! against the trait so they behave identically on every transport.
+
+use crate::child_env::is_secret_key;
+use crate::error::{McpCoreError, McpCoreResult};
+use crate::history::ChildExit;
+use crate::injection::REDACTED;
+use crate::process::{LineTolerance, McpRequest, McpResponse};
+use crate::response_stream::{Received, ResponseSink};
+use crate::server_requests::{is_server_request, ServerRequestHandlers};
+use crate::stderr::StderrTail;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    time::{timeout, Duration},
+};
+
+/// MCP protocol versions this crate supports, newest first
+pub const SUPPORTED_PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// Timeout for a single response from the MCP server
+pub const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Initialize attempts after a failed first one, unless configured
+pub const DEFAULT_INITIALIZE_RETRIES: u32 = 2;
+
+/// `clientInfo.name` sent unless overridden
+pub const DEFAULT_CLIENT_NAME: &str = "mcp-http-core";
+
+/// `clientInfo.version` sent unless overridden
+pub const DEFAULT_CLIENT_VERSION: &str = "0.1.0";
+
+/// Maximum notifications kept by a [`NotificationBuffer`]
+pub const MAX_BUFFERED_NOTIFICATIONS: usize = 1000;
+
+/// Bidirectional JSON-RPC message channel to an MCP server
+#[async_trait]
+pub trait McpTransport: Send {
+    /// Send a single JSON-RPC message
+    async fn send(&mut self, message: &str) -> McpCoreResult<()>;
+
+    /// Receive the next message (response or notification) from the server
+    async fn receive(&mut self) -> McpCoreResult<String>;
+
+    /// Close the transport and release its resources
+    async fn shutdown(&mut self) -> McpCoreResult<()>;
+
+    /// Whether the transport can still exchange messages
+    fn is_alive(&mut self) -> bool;
+
+    /// Keep a notification that arrived while a response was awaited
+    fn buffer_notification(&mut self, notification: String);
+
+    /// Take the buffered notifications, oldest first
+    fn drain_notifications(&mut self) -> Vec<String>;
+
+    /// PID of the server process, for transports that own one
+    fn pid(&self) -> Option<u32> {
+        None
+    }
+
+    /// Recent and live stderr of the server process, for transports that own one
+    fn stderr_tail(&self) -> Option<StderrTail> {
+        None
+    }
+
+    /// How the server process exited, once it has, for transports that own one
+    fn exit_status(&mut self) -> Option<ChildExit> {
+        None
+    }
+
+    /// Receive the next message, writing it to `sink` as it is read if it
+    /// is the response to `pending_id`
+    ///
+    /// Fails if no bytes arrive for `timeout_duration`. Transports that
+    /// cannot stream return every message whole.
+    async fn receive_streamed(
+        &mut self,
+        _pending_id: &serde_json::Value,
+        _sink: &mut ResponseSink,
+        timeout_duration: Duration,
+    ) -> McpCoreResult<Received> {
+        match timeout(timeout_duration, self.receive()).await {
+            Ok(message) => message.map(Received::Message),
+            Err(_) => Err(response_timeout(timeout_duration)),
+        }
+    }
+}
+
+/// Notifications received while waiting for a response
+///
+/// Bounded by [`MAX_BUFFERED_NOTIFICATIONS`]; the oldest are dropped first.
+#[derive(Debug, Default)]
+pub struct NotificationBuffer {
+    notifications: VecDeque<String>,
+}
+
+impl NotificationBuffer {
+    /// Add a notification, dropping the oldest when full
+    pub fn push(&mut self, notification: String) {
+        if self.notifications.len() >= MAX_BUFFERED_NOTIFICATIONS {
+            tracing::warn!("Notification buffer is full, dropping the oldest notification");
+            self.notifications.pop_front();
+        }
+        self.notifications.push_back(notification);
+    }
+
+    /// Take all buffered notifications, oldest first
+    pub fn drain(&mut self) -> Vec<String> {
+        self.notifications.drain(..).collect()
+    }
+}
+
+/// Why an initialize handshake failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HandshakeFailure {
+    /// No response arrived within the initialize timeout
+    Timeout,
+
+    /// The response was not JSON, or had neither `result` nor `error`
+    Malformed,
+
+    /// The server answered with a JSON-RPC error
+    ErrorResponse,
+
+    /// The transport failed or closed during the handshake
+    Closed,
+
+    /// The server requires a protocol version this crate does not speak
+    Incompatible,
+}
+
+impl HandshakeFailure {
+    /// Whether another attempt could succeed
+    pub fn is_retryable(self) -> bool {
+        self != HandshakeFailure::Incompatible
+    }
+
+    /// Whether another attempt needs a new child or connection; a server
+    /// answering with an error is asked again as it is
+    pub fn needs_restart(self) -> bool {
+        matches!(
+            self,
+            HandshakeFailure::Timeout | HandshakeFailure::Malformed | HandshakeFailure::Closed
+        )
+    }
+}
+
+impl std::fmt::Display for HandshakeFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            HandshakeFailure::Timeout => "timeout",
+            HandshakeFailure::Malformed => "malformed response",
+            HandshakeFailure::ErrorResponse => "error response",
+            HandshakeFailure::Closed => "transport closed",
+            HandshakeFailure::Incompatible => "incompatible protocol version",
+        })
+    }
+}
+
+/// Handshake error of class `failure`
+fn handshake_failed(failure: HandshakeFailure, message: String) -> McpCoreError {
+    McpCoreError::HandshakeFailed {
+        message,
+        failure,
+        attempts: 1,
+    }
+}
+
+/// Transport selection for a server
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TransportConfig {
+    /// Spawn `command` and speak JSON-RPC over its stdin/stdout
+    #[default]
+    Stdio,
+
+    /// Connect to a newline-delimited JSON-RPC socket
+    Tcp {
+        /// Address in `host:port` form
+        address: String,
+
+        /// Connection attempts before giving up
+        #[serde(default = "default_reconnect_attempts")]
+        reconnect_attempts: u32,
+
+        /// Initial delay between attempts, doubled after each failure
+        #[serde(default = "default_reconnect_backoff_ms")]
+        reconnect_backoff_ms: u64,
+    },
+
+    /// Send messages to an upstream Streamable HTTP MCP server
+    Http {
+        /// MCP endpoint URL
+        url: String,
+
+        /// Extra headers sent with every request
+        #[serde(default)]
+        headers: HashMap<String, String>,
+    },
+}
+
+/// Receive a message, failing if none arrives within `timeout_duration`
+pub async fn receive_with_timeout(
+    transport: &mut dyn McpTransport,
+    timeout_duration: Duration,
+) -> McpCoreResult<String> {
+    match timeout(timeout_duration, transport.receive()).await {
+        Ok(result) => result,
+        Err(_) => Err(response_timeout(timeout_duration)),
+    }
+}
+
+/// Error for a server that sent nothing for `timeout_duration`
+pub(crate) fn response_timeout(timeout_duration: Duration) -> McpCoreError {
+    let timeout_secs = timeout_duration.as_secs();
+    tracing::error!("MCP server response timeout after {} seconds", timeout_secs);
+    McpCoreError::ProcessError {
+        message: format!("MCP server response timeout ({} seconds)", timeout_secs),
+    }
+}
+
+/// Receive the response to a pending request, answering server requests
+///
+/// Requests the server sends in the meantime (`sampling/createMessage`,
+/// `roots/list`, ...) are handled by `handlers` and replied to on the same
+/// transport. Notifications are buffered on the transport and responses to
+/// other ids are discarded, so only the response matching `pending_id` is
+/// returned. Each message must arrive within `timeout_duration`.
+pub async fn receive_response(
+    transport: &mut dyn McpTransport,
+    handlers: &ServerRequestHandlers,
+    pending_id: Option<&serde_json::Value>,
+    timeout_duration: Duration,
+) -> McpCoreResult<String> {
+    loop {
+        let message = receive_with_timeout(transport, timeout_duration).await?;
+        if let Some(response) = take_response(transport, handlers, pending_id, message).await? {
+            return Ok(response);
+        }
+    }
+}
+
+/// Receive the response to a pending request like [`receive_response`],
+/// writing it to `sink` as it is read
+///
+/// A response the transport read whole is written at once. A failure after
+/// part of the response was written cuts the sink's response short.
+pub async fn stream_response(
+    transport: &mut dyn McpTransport,
+    handlers: &ServerRequestHandlers,
+    pending_id: &serde_json::Value,
+    timeout_duration: Duration,
+    sink: &mut ResponseSink,
+) -> McpCoreResult<()> {
+    let streamed = async {
+        loop {
+            let message = match transport
+                .receive_streamed(pending_id, sink, timeout_duration)
+                .await?
+            {
+                Received::Streamed => return Ok(()),
+                Received::Message(message) => message,
+            };
+            if let Some(response) =
+                take_response(transport, handlers, Some(pending_id), message).await?
+            {
+                return sink.write(response.as_bytes()).await;
+            }
+        }
+    }
+    .await;
+    if let Err(e) = &streamed {
+        sink.abort(e).await;
+    }
+    streamed
+}
+
+/// `message` if it is the response awaited; server requests are answered,
+/// notifications buffered, and responses to other ids discarded
+async fn take_response(
+    transport: &mut dyn McpTransport,
+    handlers: &ServerRequestHandlers,
+    pending_id: Option<&serde_json::Value>,
+    message: String,
+) -> McpCoreResult<Option<String>> {
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(&message) else {
+        return Ok(Some(message));
+    };
+
+    if is_server_request(&value, pending_id) {
+        let reply = handlers.handle(&value).await;
+        transport.send(&reply.to_string()).await?;
+        return Ok(None);
+    }
+
+    let (Some(pending_id), Some(object)) = (pending_id, value.as_object()) else {
+        return Ok(Some(message));
+    };
+    match object.get("id") {
+        None => {
+            tracing::debug!("Buffering notification received while awaiting a response");
+            transport.buffer_notification(message);
+        }
+        Some(id) if id == pending_id => return Ok(Some(message)),
+        // Parse errors carry a null id and answer whatever was just sent
+        Some(serde_json::Value::Null) if object.contains_key("error") => return Ok(Some(message)),
+        Some(id) => tracing::warn!(
+            "Discarding response with id {} while awaiting {}",
+            id,
+            pending_id
+        ),
+    }
+    Ok(None)
+}
+
+/// Parameters of the initialize handshake
+#[derive(Debug, Clone)]
+pub struct InitializeOptions {
+    /// Client capabilities advertised to the server
+    pub capabilities: serde_json::Value,
+
+    /// Version to offer instead of the newest supported one
+    pub protocol_version: Option<String>,
+
+    /// Time allowed for the initialize response
+    pub timeout: Duration,
+
+    /// `clientInfo.name` sent to the server
+    pub client_name: String,
+
+    /// `clientInfo.version` sent to the server
+    pub client_version: String,
+}
+
+impl Default for InitializeOptions {
+    fn default() -> Self {
+        Self {
+            capabilities: serde_json::json!({}),
+            protocol_version: None,
+            timeout: RESPONSE_TIMEOUT,
+            client_name: DEFAULT_CLIENT_NAME.to_string(),
+            client_version: DEFAULT_CLIENT_VERSION.to_string(),
+        }
+    }
+}
+
+/// Initialize MCP connection with handshake according to official specification
+///
+/// Offers `options.protocol_version`, or the newest supported version if
+/// `None`, and returns the version negotiated with the server. If the server
+/// rejects an offered default version and lists the versions it supports, the
+/// handshake is retried once with the newest version both sides support.
+///
+/// Fails with [`McpCoreError::HandshakeFailed`] classed by
+/// [`HandshakeFailure`], or with [`McpCoreError::ChildExitedEarly`].
+pub async fn initialize(
+    transport: &mut dyn McpTransport,
+    options: &InitializeOptions,
+) -> McpCoreResult<String> {
+    tracing::info!("Initializing MCP connection...");
+
+    let protocol_version = options.protocol_version.as_deref();
+    let mut offered = protocol_version
+        .unwrap_or(SUPPORTED_PROTOCOL_VERSIONS[0])
+        .to_string();
+    let mut retried = false;
+
+    let negotiated = loop {
+        let init_response = send_initialize(transport, options, &offered).await?;
+
+        // Parse and validate the response
+        let response = match serde_json::from_str::<serde_json::Value>(&init_response) {
+            Ok(response) => response,
+            Err(e) => {
+                return Err(handshake_failed(
+                    HandshakeFailure::Malformed,
+                    format!("Initialize response is not JSON: {}", e),
+                ))
+            }
+        };
+
+        if let Some(error) = response.get("error") {
+            let fallback = error
+                .pointer("/data/supported")
+                .and_then(serde_json::Value::as_array)
+                .and_then(|supported| {
+                    SUPPORTED_PROTOCOL_VERSIONS
+                        .iter()
+                        .find(|version| supported.iter().any(|v| v == **version))
+                });
+            match fallback {
+                Some(version) if !retried && protocol_version.is_none() && *version != offered => {
+                    tracing::warn!(
+                        "MCP server rejected protocol version {}, retrying with {}",
+                        offered,
+                        version
+                    );
+                    offered = version.to_string();
+                    retried = true;
+                    continue;
+                }
+                _ => {
+                    return Err(handshake_failed(
+                        HandshakeFailure::ErrorResponse,
+                        format!("MCP initialization error: {}", error),
+                    ))
+                }
+            }
+        }
+
+        let Some(result) = response.get("result") else {
+            return Err(handshake_failed(
+                HandshakeFailure::Malformed,
+                "Initialize response has neither 'result' nor 'error'".to_string(),
+            ));
+        };
+        if let Some(capabilities) = result.get("capabilities") {
+            tracing::info!("Server capabilities: {}", capabilities);
+        }
+        if let Some(server_info) = result.get("serverInfo") {
+            tracing::info!("Server info: {}", server_info);
+        }
+
+        match result
+            .get("protocolVersion")
+            .and_then(serde_json::Value::as_str)
+        {
+            Some(version) if is_supported_protocol_version(version) => {
+                if version != offered {
+                    tracing::info!(
+                        "MCP server answered protocol version {} to offered {}, adopting it",
+                        version,
+                        offered
+                    );
+                }
+                break version.to_string();
+            }
+            Some(version) => {
+                return Err(handshake_failed(
+                    HandshakeFailure::Incompatible,
+                    format!(
+                        "MCP server requires unsupported protocol version {} (supported: {})",
+                        version,
+                        SUPPORTED_PROTOCOL_VERSIONS.join(", ")
+                    ),
+                ))
+            }
+            None => {
+                tracing::warn!("Initialize response missing 'protocolVersion'");
+                break offered;
+            }
+        }
+    };
+    tracing::info!("Negotiated protocol version: {}", negotiated);
+
+    // Send initialized notification per MCP specification
+    let initialized_notification = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "notifications/initialized",
+        "params": {}
+    });
+
+    let notification_message = initialized_notification.to_string();
+    tracing::debug!("Sending initialized notification: {}", notification_message);
+
+    transport.send(&notification_message).await.map_err(|e| {
+        handshake_failed(
+            HandshakeFailure::Closed,
+            format!("Failed to write initialized notification: {}", e),
+        )
+    })?;
+
+    tracing::info!("MCP connection initialized successfully");
+    Ok(negotiated)
+}
+
+/// Send an initialize request offering `protocol_version` and read the reply
+async fn send_initialize(
+    transport: &mut dyn McpTransport,
+    options: &InitializeOptions,
+    protocol_version: &str,
+) -> McpCoreResult<String> {
+    let mut client_info = serde_json::json!({
+        "name": options.client_name,
+        "version": options.client_version
+    });
+    // `title` was added to Implementation in 2025-06-18, and names this crate
+    if protocol_version >= "2025-06-18" && options.client_name == DEFAULT_CLIENT_NAME {
+        client_info["title"] = serde_json::json!("MCP HTTP Core");
+    }
+
+    // Send initialize request with proper capabilities structure per MCP specification
+    let init_request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": "init",
+        "method": "initialize",
+        "params": {
+            "protocolVersion": protocol_version,
+            "capabilities": options.capabilities,
+            "clientInfo": client_info
+        }
+    });
+
+    let init_message = init_request.to_string();
+    tracing::debug!(
+        "Sending initialize request: {}",
+        redact_secrets(&init_request)
+    );
+
+    // Send initialize; a child that exited early already says why
+    transport.send(&init_message).await.map_err(|e| match e {
+        McpCoreError::ChildExitedEarly { .. } => e,
+        e => handshake_failed(
+            HandshakeFailure::Closed,
+            format!("Failed to write initialize request: {}", e),
+        ),
+    })?;
+
+    // Wait for initialize response, however many messages come before it;
+    // the timeout around the wait bounds each message too
+    let init_response = timeout(
+        options.timeout,
+        receive_response(
+            transport,
+            &ServerRequestHandlers::default(),
+            init_request.get("id"),
+            Duration::MAX,
+        ),
+    )
+    .await
+    .map_err(|_| {
+        handshake_failed(
+            HandshakeFailure::Timeout,
+            format!(
+                "No initialize response within {} seconds",
+                options.timeout.as_secs()
+            ),
+        )
+    })?
+    .map_err(|e| match e {
+        McpCoreError::ChildExitedEarly { .. } => e,
+        e => handshake_failed(
+            HandshakeFailure::Closed,
+            format!("Failed to read initialize response: {}", e),
+        ),
+    })?;
+    tracing::debug!("Initialize response: {}", init_response);
+    Ok(init_response)
+}
+
+/// Copy of `value` with the values of secret-looking keys replaced
+fn redact_secrets(value: &serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => map
+            .iter()
+            .map(|(key, value)| {
+                let value = if is_secret_key(key) {
+                    serde_json::Value::String(REDACTED.to_string())
+                } else {
+                    redact_secrets(value)
+                };
+                (key.clone(), value)
+            })
+            .collect(),
+        serde_json::Value::Array(items) => items.iter().map(redact_secrets).collect(),
+        other => other.clone(),
+    }
+}
+
+/// Whether this crate can speak the given MCP protocol version
+pub fn is_supported_protocol_version(version: &str) -> bool {
+    SUPPORTED_PROTOCOL_VERSIONS.contains(&version)
+}
+
+/// Send a query to the MCP server and wait for response
+pub async fn query(
+    transport: &mut dyn McpTransport,
+    request: &McpRequest,
+) -> McpCoreResult<McpResponse> {
+    let start_time = Instant::now();
+    tracing::debug!("Starting MCP query");
+
+    transport.send(&request.command).await?;
+
+    tracing::debug!("Data sent to MCP server, waiting for response...");
+
+    let pending_id = serde_json::from_str::<serde_json::Value>(&request.command)
+        .ok()
+        .and_then(|command| command.get("id").cloned());
+    let response_line = receive_response(
+        transport,
+        &ServerRequestHandlers::default(),
+        pending_id.as_ref(),
+        RESPONSE_TIMEOUT,
+    )
+    .await?;
+
+    let elapsed = start_time.elapsed();
+    tracing::debug!("MCP query completed in {:?}", elapsed);
+
+    Ok(McpResponse {
+        result: response_line,
+    })
+}
+
+/// Newline-delimited JSON-RPC over a TCP connection
+///
+/// Lost connections are re-established with exponential backoff on the next
+/// send. The handshake messages seen so far are replayed on reconnect, since
+/// a new connection is a new MCP session. A message is only sent again on a
+/// new connection when none of it was written to the old one.
+pub struct TcpTransport {
+    address: String,
+    reconnect_attempts: u32,
+    reconnect_backoff: Duration,
+    reader: Option<BufReader<OwnedReadHalf>>,
+    writer: Option<OwnedWriteHalf>,
+    handshake: Vec<String>,
+    notifications: NotificationBuffer,
+    line_tolerance: LineTolerance,
+}
+
+/// Upper bound for the delay between reconnect attempts
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+impl TcpTransport {
+    /// Connect to `address`, retrying with backoff
+    pub async fn connect(
+        address: &str,
+        reconnect_attempts: u32,
+        reconnect_backoff: Duration,
+    ) -> McpCoreResult<Self> {
+        let mut transport = Self {
+            address: address.to_string(),
+            reconnect_attempts: reconnect_attempts.max(1),
+            reconnect_backoff,
+            reader: None,
+            writer: None,
+            handshake: Vec::new(),
+            notifications: NotificationBuffer::default(),
+            line_tolerance: LineTolerance::default(),
+        };
+        transport.connect_with_backoff().await?;
+        Ok(transport)
+    }
+
+    async fn connect_with_backoff(&mut self) -> McpCoreResult<()> {
+        let mut backoff = self.reconnect_backoff;
+        let mut attempt = 1;
+        loop {
+            match TcpStream::connect(&self.address).await {
+                Ok(stream) => {
+                    tracing::info!("Connected to MCP server at {}", self.address);
+                    let (reader, writer) = stream.into_split();
+                    self.reader = Some(BufReader::new(reader));
+                    self.writer = Some(writer);
+                    return Ok(());
+                }
+                Err(e) if attempt >= self.reconnect_attempts => {
+                    return Err(McpCoreError::ProcessError {
+                        message: format!(
+                            "Failed to connect to MCP server at {} after {} attempts: {}",
+                            self.address, attempt, e
+                        ),
+                    });
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "Connection to {} failed (attempt {}): {}, retrying in {:?}",
+                        self.address,
+                        attempt,
+                        e,
+                        backoff
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    /// Reconnect and replay the handshake of the previous session
+    async fn reconnect(&mut self) -> McpCoreResult<()> {
+        self.disconnect();
+        self.connect_with_backoff().await?;
+
+        for message in self.handshake.clone() {
+            self.write_line(&message).await?;
+            if let Some(id) = jsonrpc_id(&message) {
+                let response = timeout(RESPONSE_TIMEOUT, self.read_response(&id))
+                    .await
+                    .map_err(|_| McpCoreError::ProcessError {
+                        message: "Timed out replaying handshake after reconnect".to_string(),
+                    })??;
+                tracing::debug!("Handshake replay response: {}", response);
+            }
+        }
+        Ok(())
+    }
+
+    /// Read until the response to request `id` arrives
+    ///
+    /// Notifications sent before it are buffered; other messages are skipped.
+    async fn read_response(&mut self, id: &serde_json::Value) -> McpCoreResult<String> {
+        loop {
+            let line = self.read_line().await?;
+            match jsonrpc_id(&line) {
+                Some(line_id) if line_id == *id => return Ok(line),
+                Some(line_id) => {
+                    tracing::debug!("Skipping message {} during handshake replay", line_id)
+                }
+                None => self.notifications.push(line),
+            }
+        }
+    }
+
+    fn disconnect(&mut self) {
+        self.reader = None;
+        self.writer = None;
+    }
+
+    async fn write_line(&mut self, message: &str) -> Result<(), WriteFailure> {
+        let Some(writer) = self.writer.as_mut() else {
+            return Err(WriteFailure {
+                error: McpCoreError::ProcessError {
+                    message: format!("Not connected to MCP server at {}", self.address),
+                },
+                partial: false,
+            });
+        };
+
+        let line = format!("{}\n", message);
+        let mut written = 0;
+        let result = async {
+            while written < line.len() {
+                match writer.write(&line.as_bytes()[written..]).await? {
+                    0 => return Err(std::io::ErrorKind::WriteZero.into()),
+                    n => written += n,
+                }
+            }
+            writer.flush().await
+        }
+        .await;
+        result.map_err(|e| WriteFailure {
+            error: McpCoreError::ProcessError {
+                message: format!("Failed to write to MCP server at {}: {}", self.address, e),
+            },
+            partial: written > 0,
+        })
+    }
+
+    async fn read_line(&mut self) -> McpCoreResult<String> {
+        let reader = self
+            .reader
+            .as_mut()
+            .ok_or_else(|| McpCoreError::ProcessError {
+                message: format!("Not connected to MCP server at {}", self.address),
+            })?;
+
+        loop {
+            let mut line = String::new();
+            match reader.read_line(&mut line).await {
+                Ok(0) => {
+                    tracing::warn!("MCP server at {} closed the connection", self.address);
+                    self.disconnect();
+                    return Err(McpCoreError::ProcessError {
+                        message: "MCP server closed the connection (EOF)".to_string(),
+                    });
+                }
+                Ok(_) => {
+                    if let Some(line) = self.line_tolerance.clean(&line) {
+                        return Ok(line);
+                    }
+                }
+                Err(e) => {
+                    self.disconnect();
+                    return Err(McpCoreError::ProcessError {
+                        message: format!(
+                            "Failed to read from MCP server at {}: {}",
+                            self.address, e
+                        ),
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl McpTransport for TcpTransport {
+    async fn send(&mut self, message: &str) -> McpCoreResult<()> {
+        if self.writer.is_none() {
+            self.reconnect().await?;
+        }
+
+        // Remember the handshake so it can be replayed on a new connection
+        match jsonrpc_method(message).as_deref() {
+            Some("initialize") => self.handshake = vec![message.to_string()],
+            Some("notifications/initialized") => self.handshake.push(message.to_string()),
+            _ => {}
+        }
+
+        tracing::debug!("Sending {} bytes to MCP server", message.len());
+        match self.write_line(message).await {
+            Ok(()) => Ok(()),
+            // Part of the message may have reached the server, so sending it
+            // again could run the request twice
+            Err(failure) if failure.partial => {
+                self.disconnect();
+                Err(failure.error)
+            }
+            Err(failure) => {
+                tracing::warn!("{}, reconnecting", failure.error);
+                self.reconnect().await?;
+                Ok(self.write_line(message).await?)
+            }
+        }
+    }
+
+    async fn receive(&mut self) -> McpCoreResult<String> {
+        self.read_line().await
+    }
+
+    async fn shutdown(&mut self) -> McpCoreResult<()> {
+        if let Some(mut writer) = self.writer.take() {
+            let _ = writer.shutdown().await;
+        }
+        self.reader = None;
+        self.handshake.clear();
+        Ok(())
+    }
+
+    fn is_alive(&mut self) -> bool {
+        self.writer.is_some()
+    }
+
+    fn buffer_notification(&mut self, notification: String) {
+        self.notifications.push(notification);
+    }
+
+    fn drain_notifications(&mut self) -> Vec<String> {
+        self.notifications.drain()
+    }
+}
+
+/// A failed write to a TCP connection
+struct WriteFailure {
+    error: McpCoreError,
+    /// Whether part of the message was written before the failure
+    partial: bool,
+}
+
+impl From<WriteFailure> for McpCoreError {
+    fn from(failure: WriteFailure) -> Self {
+        failure.error
+    }
+}
+
+/// JSON-RPC method of a message, if it has one
+fn jsonrpc_method(message: &str) -> Option<String> {
+    serde_json::from_str::<serde_json::Value>(message)
+        .ok()?
+        .get("method")?
+        .as_str()
+        .map(str::to_string)
+}
+
+/// JSON-RPC id of a message, if it is a request or a response
+fn jsonrpc_id(message: &str) -> Option<serde_json::Value> {
+    serde_json::from_str::<serde_json::Value>(message)
+        .ok()?
+        .get("id")
+        .cloned()
+}
+
+fn default_reconnect_attempts() -> u32 {
+    5
+}
+
+fn default_reconnect_backoff_ms() -> u64 {
+    200
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    /// Mock MCP server answering each request with its id; the connection is
+    /// closed after `close_after` messages. Received methods are reported.
+    async fn mock_server(close_after: usize) -> (String, mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = listener.accept().await else {
+                    return;
+                };
+                let (reader, mut writer) = stream.into_split();
+                let mut lines = BufReader::new(reader).lines();
+                let mut seen = 0;
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let message: serde_json::Value = serde_json::from_str(&line).unwrap();
+                    let _ = tx.send(message["method"].as_str().unwrap_or("").to_string());
+                    if let Some(id) = message.get("id") {
+                        let response = serde_json::json!({
+                            "jsonrpc": "2.0",
+                            "id": id,
+                            "result": { "method": message["method"] }
+                        });
+                        let _ = writer.write_all(format!("{}\n", response).as_bytes()).await;
+                    }
+                    seen += 1;
+                    if seen >= close_after {
+                        break;
+                    }
+                }
+            }
+        });
+
+        (address, rx)
+    }
+
+    #[tokio::test]
+    async fn test_tcp_transport_handshake_and_query() {
+        let (address, mut methods) = mock_server(usize::MAX).await;
+        let mut transport = TcpTransport::connect(&address, 1, Duration::from_millis(10))
+            .await
+            .unwrap();
+
+        initialize(&mut transport, &InitializeOptions::default())
+            .await
+            .unwrap();
+        let request = McpRequest {
+            command: r#"{"jsonrpc":"2.0","id":1,"method":"tools/list"}"#.to_string(),
+        };
+        let response = query(&mut transport, &request).await.unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&response.result).unwrap();
+        assert_eq!(response["id"], 1);
+        assert_eq!(methods.recv().await.unwrap(), "initialize");
+        assert_eq!(methods.recv().await.unwrap(), "notifications/initialized");
+        assert_eq!(methods.recv().await.unwrap(), "tools/list");
+        assert!(transport.is_alive());
+
+        transport.shutdown().await.unwrap();
+        assert!(!transport.is_alive());
+    }
+
+    #[tokio::test]
+    async fn test_tcp_transport_reconnects_and_replays_handshake() {
+        // Connection drops after the handshake and one request
+        let (address, mut methods) = mock_server(3).await;
+        let mut transport = TcpTransport::connect(&address, 3, Duration::from_millis(10))
+            .await
+            .unwrap();
+        initialize(&mut transport, &InitializeOptions::default())
+            .await
+            .unwrap();
+
+        let request = McpRequest {
+            command: r#"{"jsonrpc":"2.0","id":1,"method":"tools/list"}"#.to_string(),
+        };
+        query(&mut transport, &request).await.unwrap();
+        // The server closed the connection; reading observes EOF
+        assert!(transport.receive().await.is_err());
+        assert!(!transport.is_alive());
+
+        let request = McpRequest {
+            command: r#"{"jsonrpc":"2.0","id":2,"method":"tools/call"}"#.to_string(),
+        };
+        let response = query(&mut transport, &request).await.unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response.result).unwrap();
+        assert_eq!(response["id"], 2);
+
+        let mut seen = Vec::new();
+        while let Ok(method) = methods.try_recv() {
+            seen.push(method);
+        }
+        assert_eq!(
+            seen,
+            vec![
+                "initialize",
+                "notifications/initialized",
+                "tools/list",
+                "initialize",
+                "notifications/initialized",
+                "tools/call"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tcp_transport_handshake_replay_waits_for_its_response() {
+        // Each connection announces itself and asks the client something
+        // before answering `initialize`; the first one drops after the handshake
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut connection = 0;
+            while let Ok((stream, _)) = listener.accept().await {
+                connection += 1;
+                let (reader, mut writer) = stream.into_split();
+                let mut lines = BufReader::new(reader).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let message: serde_json::Value = serde_json::from_str(&line).unwrap();
+                    let replies = match message["method"].as_str() {
+                        Some("initialize") => vec![
+                            serde_json::json!({
+                                "jsonrpc": "2.0",
+                                "method": "notifications/message",
+                                "params": { "connection": connection }
+                            }),
+                            serde_json::json!({
+                                "jsonrpc": "2.0",
+                                "id": "server-1",
+                                "method": "roots/list"
+                            }),
+                            serde_json::json!({
+                                "jsonrpc": "2.0",
+                                "id": message["id"],
+                                "result": { "connection": connection }
+                            }),
+                        ],
+                        Some("notifications/initialized") if connection == 1 => break,
+                        Some(_) if message.get("id").is_some() => vec![serde_json::json!({
+                            "jsonrpc": "2.0",
+                            "id": message["id"],
+                            "result": { "connection": connection }
+                        })],
+                        _ => Vec::new(),
+                    };
+                    for reply in replies {
+                        let _ = writer.write_all(format!("{}\n", reply).as_bytes()).await;
+                    }
+                }
+            }
+        });
+
+        let mut transport = TcpTransport::connect(&address, 3, Duration::from_millis(10))
+            .await
+            .unwrap();
+        initialize(&mut transport, &InitializeOptions::default())
+            .await
+            .unwrap();
+        assert!(transport.receive().await.is_err());
+        transport.drain_notifications();
+
+        let request = McpRequest {
+            command: r#"{"jsonrpc":"2.0","id":7,"method":"tools/list"}"#.to_string(),
+        };
+        let response = query(&mut transport, &request).await.unwrap();
+
+        // The reply to `tools/list` is not mistaken for the replayed handshake's
+        let response: serde_json::Value = serde_json::from_str(&response.result).unwrap();
+        assert_eq!(response["id"], 7);
+        assert_eq!(response["result"]["connection"], 2);
+        let notifications = transport.drain_notifications();
+        assert_eq!(notifications.len(), 1);
+        assert!(notifications[0].contains(r#""connection":2"#));
+    }
+
+    #[tokio::test]
+    async fn test_tcp_transport_connect_failure() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let result = TcpTransport::connect(&address, 2, Duration::from_millis(1)).await;
+        assert!(result.is_err());
+    }
+
+    /// Transport replaying canned replies and recording sent messages
+    #[derive(Default)]
+    struct ScriptedTransport {
+        replies: std::collections::VecDeque<serde_json::Value>,
+        sent: Vec<serde_json::Value>,
+        notifications: NotificationBuffer,
+    }
+
+    #[async_trait]
+    impl McpTransport for ScriptedTransport {
+        async fn send(&mut self, message: &str) -> McpCoreResult<()> {
+            self.sent.push(serde_json::from_str(message).unwrap());
+            Ok(())
+        }
+
+        async fn receive(&mut self) -> McpCoreResult<String> {
+            Ok(self.replies.pop_front().unwrap().to_string())
+        }
+
+        async fn shutdown(&mut self) -> McpCoreResult<()> {
+            Ok(())
+        }
+
+        fn is_alive(&mut self) -> bool {
+            true
+        }
+
+        fn buffer_notification(&mut self, notification: String) {
+            self.notifications.push(notification);
+        }
+
+        fn drain_notifications(&mut self) -> Vec<String> {
+            self.notifications.drain()
+        }
+    }
+
+    fn init_result(version: &str) -> serde_json::Value {
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": "init",
+            "result": { "protocolVersion": version, "capabilities": {} }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_initialize_adopts_server_version() {
+        let mut transport = ScriptedTransport {
+            replies: [init_result("2024-11-05")].into(),
+            ..ScriptedTransport::default()
+        };
+
+        let version = initialize(&mut transport, &InitializeOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(version, "2024-11-05");
+        assert_eq!(
+            transport.sent[0]["params"]["protocolVersion"],
+            SUPPORTED_PROTOCOL_VERSIONS[0]
+        );
+        assert_eq!(
+            transport.sent[0]["params"]["clientInfo"]["title"],
+            "MCP HTTP Core"
+        );
+        assert_eq!(transport.sent[1]["method"], "notifications/initialized");
+    }
+
+    #[tokio::test]
+    async fn test_initialize_retries_after_version_mismatch() {
+        let mismatch = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": "init",
+            "error": {
+                "code": -32602,
+                "message": "Unsupported protocol version",
+                "data": { "supported": ["2024-11-05"], "requested": "2025-06-18" }
+            }
+        });
+        let mut transport = ScriptedTransport {
+            replies: [mismatch.clone(), init_result("2024-11-05")].into(),
+            ..ScriptedTransport::default()
+        };
+
+        let version = initialize(&mut transport, &InitializeOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(version, "2024-11-05");
+        assert_eq!(transport.sent[1]["params"]["protocolVersion"], "2024-11-05");
+        // Older versions have no `title` in clientInfo
+        assert!(transport.sent[1]["params"]["clientInfo"]
+            .get("title")
+            .is_none());
+
+        // A forced version is not renegotiated
+        let mut transport = ScriptedTransport {
+            replies: [mismatch].into(),
+            ..ScriptedTransport::default()
+        };
+        let options = InitializeOptions {
+            protocol_version: Some("2025-03-26".to_string()),
+            ..InitializeOptions::default()
+        };
+        let result = initialize(&mut transport, &options).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_initialize_sends_client_overrides() {
+        let mut transport = ScriptedTransport {
+            replies: [init_result(SUPPORTED_PROTOCOL_VERSIONS[0])].into(),
+            ..ScriptedTransport::default()
+        };
+        let options = InitializeOptions {
+            capabilities: serde_json::json!({ "elicitation": {} }),
+            client_name: "acme-gateway".to_string(),
+            client_version: "2.1.0".to_string(),
+            ..InitializeOptions::default()
+        };
+
+        initialize(&mut transport, &options).await.unwrap();
+        let params = &transport.sent[0]["params"];
+        assert_eq!(
+            params["capabilities"],
+            serde_json::json!({ "elicitation": {} })
+        );
+        assert_eq!(
+            params["clientInfo"],
+            serde_json::json!({ "name": "acme-gateway", "version": "2.1.0" })
+        );
+    }
+
+    #[test]
+    fn test_redact_secrets() {
+        let redacted = redact_secrets(&serde_json::json!({
+            "capabilities": { "experimental": { "apiToken": "abc", "region": "eu" } },
+            "items": [{ "password": "hunter2" }]
+        }));
+        assert_eq!(
+            redacted["capabilities"]["experimental"],
+            serde_json::json!({ "apiToken": REDACTED, "region": "eu" })
+        );
+        assert_eq!(redacted["items"][0]["password"], REDACTED);
+    }
+
+    #[tokio::test]
+    async fn test_initialize_rejects_unsupported_server_version() {
+        let mut transport = ScriptedTransport {
+            replies: [init_result("1999-01-01")].into(),
+            ..ScriptedTransport::default()
+        };
+
+        let error = initialize(&mut transport, &InitializeOptions::default())
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("unsupported protocol version"));
+        assert!(matches!(
+            error,
+            McpCoreError::HandshakeFailed {
+                failure: HandshakeFailure::Incompatible,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_initialize_failures_are_classified() {
+        let failure = |reply: serde_json::Value| async move {
+            let mut transport = ScriptedTransport {
+                replies: [reply].into(),
+                ..ScriptedTransport::default()
+            };
+            match initialize(&mut transport, &InitializeOptions::default()).await {
+                Err(McpCoreError::HandshakeFailed { failure, .. }) => failure,
+                other => panic!("unexpected outcome: {:?}", other),
+            }
+        };
+
+        let malformed = serde_json::json!({ "jsonrpc": "2.0", "id": "init" });
+        assert_eq!(failure(malformed).await, HandshakeFailure::Malformed);
+        assert_eq!(
+            failure(serde_json::json!("not an object")).await,
+            HandshakeFailure::Malformed
+        );
+        let error = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": "init",
+            "error": { "code": -32603, "message": "not ready" }
+        });
+        assert_eq!(failure(error).await, HandshakeFailure::ErrorResponse);
+        assert!(HandshakeFailure::ErrorResponse.is_retryable());
+        assert!(!HandshakeFailure::ErrorResponse.needs_restart());
+        assert!(!HandshakeFailure::Incompatible.is_retryable());
+
+        // A server that never answers times out
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let connection = listener.accept().await;
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            drop(connection);
+        });
+        let mut transport = TcpTransport::connect(&address, 1, Duration::from_millis(10))
+            .await
+            .unwrap();
+        let options = InitializeOptions {
+            timeout: Duration::from_millis(200),
+            ..InitializeOptions::default()
+        };
+        let error = initialize(&mut transport, &options).await.unwrap_err();
+        assert!(
+            matches!(
+                error,
+                McpCoreError::HandshakeFailed {
+                    failure: HandshakeFailure::Timeout,
+                    ..
+                }
+            ),
+            "{}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn test_receive_response_skips_notifications_and_stale_ids() {
+        let notification = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/progress",
+            "params": { "progress": 1 }
+        });
+        let stale = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": {} });
+        let response = serde_json::json!({ "jsonrpc": "2.0", "id": 2, "result": { "ok": true } });
+        let mut transport = ScriptedTransport {
+            replies: [notification.clone(), stale, response.clone()].into(),
+            ..ScriptedTransport::default()
+        };
+
+        let received = receive_response(
+            &mut transport,
+            &ServerRequestHandlers::default(),
+            Some(&serde_json::json!(2)),
+            RESPONSE_TIMEOUT,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&received).unwrap(),
+            response
+        );
+        assert_eq!(
+            transport.drain_notifications(),
+            vec![notification.to_string()]
+        );
+        assert!(transport.drain_notifications().is_empty());
+
+        // A parse error carries a null id but still answers the request
+        let parse_error = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": null,
+            "error": { "code": -32700, "message": "Parse error" }
+        });
+        let mut transport = ScriptedTransport {
+            replies: [parse_error.clone()].into(),
+            ..ScriptedTransport::default()
+        };
+        let received = receive_response(
+            &mut transport,
+            &ServerRequestHandlers::default(),
+            Some(&serde_json::json!(3)),
+            RESPONSE_TIMEOUT,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&received).unwrap(),
+            parse_error
+        );
+    }
+
+    #[test]
+    fn test_notification_buffer_is_bounded() {
+        let mut buffer = NotificationBuffer::default();
+        for index in 0..MAX_BUFFERED_NOTIFICATIONS + 5 {
+            buffer.push(index.to_string());
+        }
+        let drained = buffer.drain();
+        assert_eq!(drained.len(), MAX_BUFFERED_NOTIFICATIONS);
+        assert_eq!(drained[0], "5");
+    }
+}
@@ -0,0 +1,93 @@
+//! Test page for trying the gateway from a browser
+//!
+//! With the `ui` feature, `GET /ui` serves a page embedded in the binary,
+//! loading nothing from elsewhere, that lists the server's tools, builds a
+//! form from a tool's `inputSchema`, calls it through `POST /api/v1` showing
+//! the request and response, and follows `/api/v1/notifications`. The page
+//! and its assets are public; everything it shows comes from API calls made
+//! with the key entered on the page, starting with `GET /ui/api/bootstrap`.
+
+use axum::{
+    extract::State,
+    http::header,
+    response::{IntoResponse, Json, Response},
+};
+use serde_json::Value;
+
+use crate::http_server::ServerState;
+use crate::routes::Route;
+
+const INDEX_HTML: &str = include_str!("../ui/index.html");
+const APP_JS: &str = include_str!("../ui/app.js");
+const STYLE_CSS: &str = include_str!("../ui/style.css");
+
+/// Policy of the page: its own scripts, styles, and API only
+const CONTENT_SECURITY_POLICY: &str =
+    "default-src 'self'; base-uri 'none'; form-action 'none'; frame-ancestors 'none'";
+
+/// The page and its assets, served without authentication, and the
+/// endpoints the page calls with the key it was given
+pub(crate) fn routes() -> Vec<Route> {
+    vec![
+        Route::get(
+            "/ui",
+            "Open a page for listing and calling the server's tools in a browser",
+            index,
+        )
+        .public(),
+        Route::get("/ui/", "Same page as /ui", index).public(),
+        Route::get("/ui/app.js", "Script of the page", || {
+            asset("text/javascript; charset=utf-8", APP_JS)
+        })
+        .public(),
+        Route::get("/ui/style.css", "Stylesheet of the page", || {
+            asset("text/css; charset=utf-8", STYLE_CSS)
+        })
+        .public(),
+        Route::get(
+            "/ui/api/bootstrap",
+            "List the servers and capabilities the page starts from",
+            bootstrap,
+        ),
+    ]
+}
+
+async fn index() -> Response {
+    let mut response = asset("text/html; charset=utf-8", INDEX_HTML).await;
+    response.headers_mut().insert(
+        header::CONTENT_SECURITY_POLICY,
+        header::HeaderValue::from_static(CONTENT_SECURITY_POLICY),
+    );
+    response
+}
+
+async fn asset(content_type: &'static str, body: &'static str) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CACHE_CONTROL, "no-cache"),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// What the page needs to start: the servers and what the gateway offers
+async fn bootstrap(State(server_state): State<ServerState>) -> Json<Value> {
+    let mut servers: Vec<&String> = server_state.configured_servers.iter().collect();
+    servers.sort();
+    let provisioned = server_state.provisioner.provisioned();
+    Json(serde_json::json!({
+        "server": server_state.server_name,
+        "servers": servers,
+        "version": env!("CARGO_PKG_VERSION"),
+        "protocol_version": provisioned.map(|provisioned| provisioned.protocol_version.clone()),
+        "provisioning": server_state.provisioner.status().state,
+        "capabilities": {
+            "notifications": true,
+            "presets": !server_state.presets.list().is_empty(),
+            "simple_mode": server_state.simple_mode,
+        },
+    }))
+}
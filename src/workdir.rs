@@ -0,0 +1,356 @@
+//! Work directory metadata and cleanup of orphaned server directories
+//!
+//! Each server's work directory holds a `.mcp-meta.json` file recording which
+//! server and repository it belongs to and when it was last used. Cleanup
+//! only removes directories that carry this file, unless forced.
+
+use crate::error::{McpCoreError, McpCoreResult};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// Name of the metadata file written into each work directory
+pub const META_FILE_NAME: &str = ".mcp-meta.json";
+
+/// Metadata describing a server work directory
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WorkDirMetadata {
+    pub server_name: String,
+    pub repository: Option<String>,
+    pub commit: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>,
+}
+
+/// Options for [`cleanup`]
+#[derive(Debug, Clone, Default)]
+pub struct CleanupOptions {
+    /// Remove directories not used for longer than this
+    pub retention: Option<chrono::Duration>,
+
+    /// Only report what would be removed
+    pub dry_run: bool,
+
+    /// Also consider directories without a metadata file
+    pub force: bool,
+}
+
+/// Outcome for a single directory
+#[derive(Debug, Clone, Serialize)]
+pub struct CleanupEntry {
+    pub path: PathBuf,
+    pub server_name: String,
+    pub reason: String,
+}
+
+/// Result of a cleanup run
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CleanupReport {
+    pub dry_run: bool,
+
+    /// Directories removed (or that would be removed in dry-run mode)
+    pub removed: Vec<CleanupEntry>,
+
+    /// Directories left alone, with the reason
+    pub kept: Vec<CleanupEntry>,
+}
+
+/// Read the metadata file of a work directory, if present and valid
+pub async fn read_metadata(work_dir: &Path) -> Option<WorkDirMetadata> {
+    let content = tokio::fs::read_to_string(work_dir.join(META_FILE_NAME))
+        .await
+        .ok()?;
+    match serde_json::from_str(&content) {
+        Ok(metadata) => Some(metadata),
+        Err(e) => {
+            tracing::warn!(
+                "Ignoring invalid metadata in '{}': {}",
+                work_dir.display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Create or refresh the metadata file of a work directory
+///
+/// Keeps the original creation time and records the checked-out commit.
+pub async fn touch_metadata(
+    work_dir: &Path,
+    server_name: &str,
+    repository: Option<&str>,
+) -> McpCoreResult<()> {
+    let now = Utc::now();
+    let created_at = read_metadata(work_dir)
+        .await
+        .map(|metadata| metadata.created_at)
+        .unwrap_or(now);
+
+    let metadata = WorkDirMetadata {
+        server_name: server_name.to_string(),
+        repository: repository.map(str::to_string),
+        commit: current_commit(work_dir).await,
+        created_at,
+        last_used_at: now,
+    };
+
+    let path = work_dir.join(META_FILE_NAME);
+    tokio::fs::write(&path, serde_json::to_string_pretty(&metadata)?)
+        .await
+        .map_err(|e| McpCoreError::ProcessError {
+            message: format!("Failed to write metadata '{}': {}", path.display(), e),
+        })
+}
+
+/// Commit checked out in `work_dir`, if it is a git repository
+pub(crate) async fn current_commit(work_dir: &Path) -> Option<String> {
+    if tokio::fs::metadata(work_dir.join(".git")).await.is_err() {
+        return None;
+    }
+
+    let output = tokio::process::Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(work_dir)
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let commit = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!commit.is_empty()).then_some(commit)
+}
+
+/// Version declared by the package manifest in `work_dir`
+///
+/// Looks at `package.json`, `Cargo.toml`, and `pyproject.toml`, in that order.
+pub(crate) async fn package_version(work_dir: &Path) -> Option<String> {
+    if let Ok(manifest) = tokio::fs::read_to_string(work_dir.join("package.json")).await {
+        let manifest: serde_json::Value = serde_json::from_str(&manifest).ok()?;
+        return manifest["version"].as_str().map(str::to_string);
+    }
+    let (manifest, pointers): (_, &[&[&str]]) =
+        match tokio::fs::read_to_string(work_dir.join("Cargo.toml")).await {
+            Ok(manifest) => (manifest, &[&["package", "version"]]),
+            Err(_) => (
+                tokio::fs::read_to_string(work_dir.join("pyproject.toml"))
+                    .await
+                    .ok()?,
+                &[&["project", "version"], &["tool", "poetry", "version"]],
+            ),
+        };
+    let manifest: toml::Value = toml::from_str(&manifest).ok()?;
+    pointers.iter().find_map(|keys| {
+        keys.iter()
+            .try_fold(&manifest, |value, key| value.get(key))?
+            .as_str()
+            .map(str::to_string)
+    })
+}
+
+/// Remove work directories of unconfigured or expired servers under `base`
+///
+/// The directory of `active_server` is never removed.
+pub async fn cleanup(
+    base: &Path,
+    configured: &HashSet<String>,
+    active_server: Option<&str>,
+    options: &CleanupOptions,
+) -> McpCoreResult<CleanupReport> {
+    let mut report = CleanupReport {
+        dry_run: options.dry_run,
+        ..CleanupReport::default()
+    };
+
+    let mut entries = match tokio::fs::read_dir(base).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(report),
+        Err(e) => {
+            return Err(McpCoreError::ProcessError {
+                message: format!("Failed to read work dir base '{}': {}", base.display(), e),
+            })
+        }
+    };
+
+    let mut dirs = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_type().await?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+    dirs.sort();
+
+    let now = Utc::now();
+    for path in dirs {
+        let dir_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let metadata = read_metadata(&path).await;
+        let server_name = metadata
+            .as_ref()
+            .map(|metadata| metadata.server_name.clone())
+            .unwrap_or_else(|| dir_name.clone());
+
+        let removal_reason = if active_server == Some(server_name.as_str()) {
+            None
+        } else if metadata.is_none() && !options.force {
+            report.kept.push(CleanupEntry {
+                path,
+                server_name,
+                reason: format!("missing {} (use force to remove)", META_FILE_NAME),
+            });
+            continue;
+        } else if !configured.contains(&server_name) {
+            Some("server is no longer configured".to_string())
+        } else {
+            match (&metadata, options.retention) {
+                (Some(metadata), Some(retention)) if now - metadata.last_used_at > retention => {
+                    Some(format!(
+                        "last used {} exceeds retention",
+                        metadata.last_used_at.to_rfc3339()
+                    ))
+                }
+                _ => None,
+            }
+        };
+
+        let Some(reason) = removal_reason else {
+            report.kept.push(CleanupEntry {
+                path,
+                server_name,
+                reason: "in use".to_string(),
+            });
+            continue;
+        };
+
+        if options.dry_run {
+            tracing::info!("Would remove '{}': {}", path.display(), reason);
+        } else {
+            tracing::info!("Removing '{}': {}", path.display(), reason);
+            tokio::fs::remove_dir_all(&path)
+                .await
+                .map_err(|e| McpCoreError::ProcessError {
+                    message: format!("Failed to remove '{}': {}", path.display(), e),
+                })?;
+        }
+        report.removed.push(CleanupEntry {
+            path,
+            server_name,
+            reason,
+        });
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("mcp-workdir-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    async fn server_dir(base: &Path, name: &str, with_metadata: bool) -> PathBuf {
+        let dir = base.join(name);
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        if with_metadata {
+            touch_metadata(&dir, name, None).await.unwrap();
+        }
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_removes_unconfigured_and_keeps_unmarked() {
+        let base = test_dir("cleanup");
+        let configured = server_dir(&base, "configured", true).await;
+        let removed = server_dir(&base, "removed", true).await;
+        let unmarked = server_dir(&base, "unmarked", false).await;
+        let servers: HashSet<String> = ["configured".to_string()].into();
+
+        // Dry run only reports
+        let options = CleanupOptions {
+            dry_run: true,
+            ..CleanupOptions::default()
+        };
+        let report = cleanup(&base, &servers, None, &options).await.unwrap();
+        assert_eq!(report.removed.len(), 1);
+        assert!(removed.exists());
+
+        let report = cleanup(&base, &servers, None, &CleanupOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(report.removed[0].server_name, "removed");
+        assert!(!removed.exists());
+        assert!(configured.exists());
+        assert!(unmarked.exists());
+
+        let options = CleanupOptions {
+            force: true,
+            ..CleanupOptions::default()
+        };
+        cleanup(&base, &servers, None, &options).await.unwrap();
+        assert!(!unmarked.exists());
+        assert!(configured.exists());
+
+        let _ = std::fs::remove_dir_all(&base);
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_retention_spares_active_server() {
+        let base = test_dir("retention");
+        let idle = server_dir(&base, "idle", true).await;
+        let active = server_dir(&base, "active", true).await;
+        let servers: HashSet<String> = ["idle".to_string(), "active".to_string()].into();
+
+        let options = CleanupOptions {
+            retention: Some(chrono::Duration::zero()),
+            ..CleanupOptions::default()
+        };
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        cleanup(&base, &servers, Some("active"), &options)
+            .await
+            .unwrap();
+
+        assert!(!idle.exists());
+        assert!(active.exists());
+
+        let _ = std::fs::remove_dir_all(&base);
+    }
+
+    #[tokio::test]
+    async fn test_package_version_from_manifests() {
+        let dir = test_dir("package-version");
+        assert_eq!(package_version(&dir).await, None);
+
+        std::fs::write(
+            dir.join("pyproject.toml"),
+            "[tool.poetry]\nname = \"srv\"\nversion = \"0.3.1\"\n",
+        )
+        .unwrap();
+        assert_eq!(package_version(&dir).await.as_deref(), Some("0.3.1"));
+
+        std::fs::write(
+            dir.join("Cargo.toml"),
+            "[package]\nname = \"srv\"\nversion = \"1.2.0\"\n",
+        )
+        .unwrap();
+        assert_eq!(package_version(&dir).await.as_deref(), Some("1.2.0"));
+
+        std::fs::write(
+            dir.join("package.json"),
+            r#"{"name": "srv", "version": "2.0.0-beta.1"}"#,
+        )
+        .unwrap();
+        assert_eq!(package_version(&dir).await.as_deref(), Some("2.0.0-beta.1"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,251 @@
+//! Advisory lock serializing setup of a work directory across processes
+//!
+//! Two gateways pointed at the same work dir base would otherwise clone and
+//! build into the same directory at once and corrupt it. While a server's
+//! work directory is restored, cloned, updated, built, or audited, the
+//! gateway holds an exclusive lock on a `.<server>.lock` file next to it:
+//! `flock` on Unix, `LockFileEx` on Windows. A setup finding the lock taken
+//! waits up to `work_dir_lock_timeout_secs` (default 300), then fails with
+//! `work_dir_locked`, naming the PID and host recorded by the holder.
+//!
+//! The operating system releases the lock when its holder exits, so a
+//! crashed gateway never blocks the next one; the record it left behind is
+//! only reported. For locks that cannot be released that way, such as a
+//! holder hung on a network filesystem, `--force-unlock <server>` removes
+//! the lock file.
+
+use crate::error::{McpCoreError, McpCoreResult};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Default longest wait for another process to release a work directory
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Time between attempts to take a held lock
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Process holding a work directory lock, as recorded in the lock file
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LockHolder {
+    pub pid: u32,
+    pub host: String,
+    pub acquired_at: DateTime<Utc>,
+}
+
+impl std::fmt::Display for LockHolder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "PID {} on host '{}' since {}",
+            self.pid,
+            self.host,
+            self.acquired_at.to_rfc3339()
+        )
+    }
+}
+
+/// Exclusive lock of a work directory, released when dropped
+#[derive(Debug)]
+pub struct WorkDirLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl Drop for WorkDirLock {
+    fn drop(&mut self) {
+        // An empty file tells the next holder the lock was released cleanly;
+        // closing the file releases the lock itself
+        if let Err(e) = self.file.set_len(0) {
+            tracing::debug!("Failed to clear lock file '{}': {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Lock file guarding `work_dir`, kept next to it so a clone into the
+/// directory finds it empty
+pub fn lock_path(work_dir: &Path) -> PathBuf {
+    let name = work_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    work_dir.with_file_name(format!(".{}.lock", name))
+}
+
+/// Lock `work_dir`, waiting up to `timeout` for another holder to release it
+pub async fn acquire(work_dir: &Path, timeout: Duration) -> McpCoreResult<WorkDirLock> {
+    let path = lock_path(work_dir);
+    let locking_error = |e: std::io::Error| McpCoreError::ProcessError {
+        message: format!("Failed to lock '{}': {}", path.display(), e),
+    };
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .map_err(locking_error)?;
+
+    let started = Instant::now();
+    let mut waiting = false;
+    loop {
+        match file.try_lock() {
+            Ok(()) => break,
+            Err(TryLockError::WouldBlock) => {}
+            Err(TryLockError::Error(e)) => return Err(locking_error(e)),
+        }
+        let holder = read_holder(&mut file);
+        let holder = holder
+            .as_ref()
+            .map_or("another process".to_string(), ToString::to_string);
+        if started.elapsed() >= timeout {
+            return Err(McpCoreError::WorkDirLocked {
+                message: format!(
+                    "Work directory '{}' is locked by {}; gave up after {}s \
+                     (see --force-unlock)",
+                    work_dir.display(),
+                    holder,
+                    timeout.as_secs()
+                ),
+            });
+        }
+        if !waiting {
+            waiting = true;
+            tracing::info!(
+                "Work directory '{}' is locked by {}; waiting up to {}s",
+                work_dir.display(),
+                holder,
+                timeout.as_secs()
+            );
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+
+    if let Some(stale) = read_holder(&mut file) {
+        tracing::warn!(
+            "Taking over the lock of '{}' left by {}, which exited without releasing it",
+            work_dir.display(),
+            stale
+        );
+    }
+    let holder = LockHolder {
+        pid: std::process::id(),
+        host: crate::template::hostname(),
+        acquired_at: Utc::now(),
+    };
+    let record = serde_json::to_string(&holder)?;
+    file.set_len(0)
+        .and_then(|()| file.rewind())
+        .and_then(|()| file.write_all(record.as_bytes()))
+        .map_err(locking_error)?;
+    Ok(WorkDirLock { file, path })
+}
+
+/// Remove the lock file of `work_dir`, returning the holder it recorded
+///
+/// A process still holding the lock keeps it, but the next setup takes a new
+/// one instead of waiting for it.
+pub fn force_unlock(work_dir: &Path) -> McpCoreResult<Option<LockHolder>> {
+    let path = lock_path(work_dir);
+    let holder = File::open(&path)
+        .ok()
+        .and_then(|mut file| read_holder(&mut file));
+    match std::fs::remove_file(&path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(McpCoreError::ProcessError {
+            message: format!("Failed to remove '{}': {}", path.display(), e),
+        }),
+        _ => Ok(holder),
+    }
+}
+
+/// Holder recorded in a lock file, if any
+fn read_holder(file: &mut File) -> Option<LockHolder> {
+    let mut record = String::new();
+    file.rewind().ok()?;
+    file.read_to_string(&mut record).ok()?;
+    serde_json::from_str(&record).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn work_dir(name: &str) -> PathBuf {
+        let base = std::env::temp_dir().join(format!("mcp-lock-{}", std::process::id()));
+        std::fs::create_dir_all(&base).unwrap();
+        base.join(name)
+    }
+
+    #[tokio::test]
+    async fn test_contending_setups_are_serialized() {
+        let dir = work_dir("contended");
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let setups: Vec<_> = ["first", "second"]
+            .into_iter()
+            .map(|setup| {
+                let (dir, events) = (dir.clone(), Arc::clone(&events));
+                tokio::spawn(async move {
+                    let _lock = acquire(&dir, Duration::from_secs(5)).await.unwrap();
+                    events.lock().unwrap().push(format!("{} start", setup));
+                    tokio::time::sleep(Duration::from_millis(150)).await;
+                    events.lock().unwrap().push(format!("{} end", setup));
+                })
+            })
+            .collect();
+        for setup in setups {
+            setup.await.unwrap();
+        }
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 4);
+        for pair in events.chunks(2) {
+            let setup = pair[0].strip_suffix(" start").unwrap();
+            assert_eq!(pair[1], format!("{} end", setup), "{:?}", events);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_held_lock_names_its_holder() {
+        let dir = work_dir("held");
+        let held = acquire(&dir, Duration::ZERO).await.unwrap();
+
+        let error = acquire(&dir, Duration::from_millis(200)).await.unwrap_err();
+        assert_eq!(error.error_code(), Some("work_dir_locked"));
+        let message = error.to_string();
+        assert!(
+            message.contains(&format!("PID {} on host", std::process::id())),
+            "{}",
+            message
+        );
+
+        // Dropping the lock releases it and clears the record
+        drop(held);
+        let lock = acquire(&dir, Duration::ZERO).await.unwrap();
+        let holder = force_unlock(&dir).unwrap().unwrap();
+        assert_eq!(holder.pid, std::process::id());
+        assert!(!lock_path(&dir).exists());
+        assert!(acquire(&dir, Duration::ZERO).await.is_ok());
+        drop(lock);
+    }
+
+    #[tokio::test]
+    async fn test_record_of_crashed_holder_is_taken_over() {
+        let dir = work_dir("stale");
+        let stale = LockHolder {
+            pid: 4_000_000,
+            host: "crashed-host".to_string(),
+            acquired_at: Utc::now(),
+        };
+        std::fs::write(lock_path(&dir), serde_json::to_string(&stale).unwrap()).unwrap();
+
+        let lock = acquire(&dir, Duration::ZERO).await.unwrap();
+        let holder = force_unlock(&dir).unwrap().unwrap();
+        assert_eq!(holder.pid, std::process::id());
+        drop(lock);
+        assert_eq!(force_unlock(&dir).unwrap(), None);
+    }
+}
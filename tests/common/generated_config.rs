@@ -0,0 +1,31 @@
+//! Machine-generated configuration shared by `tests/large_config.rs` and
+//! `benches/config.rs`, so both measure the same file
+
+use serde_json::{json, Map, Value};
+
+/// Servers in the generated configuration, like one per tenant
+pub const SERVERS: usize = 5_000;
+
+/// Variables in each server's `env`
+pub const ENV_VARS: usize = 20;
+
+/// Configuration of [`SERVERS`] servers
+pub fn generated_config() -> Value {
+    let servers: Map<String, Value> = (0..SERVERS)
+        .map(|i| {
+            let env: Map<String, Value> = (0..ENV_VARS)
+                .map(|j| (format!("VAR_{}", j), json!(format!("value-{}-{}", i, j))))
+                .collect();
+            let server = json!({
+                "command": "node",
+                "args": ["dist/index.js", "--port", "{{port}}"],
+                "env": env,
+                "repository": format!("https://git.example.com/mcp/server-{}.git", i),
+                "build_command": "npm ci && npm run build",
+                "setup_mode": "manual"
+            });
+            (format!("server-{}", i), server)
+        })
+        .collect();
+    json!({ "servers": servers })
+}
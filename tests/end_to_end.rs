@@ -0,0 +1,646 @@
+//! End-to-end tests running the gateway in front of the `echo-mcp-fixture`
+//! server from `tests/fixtures`, so they need nothing but `cargo test`
+
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use mcp_server_as_http_core::config::{AuthConfig, McpServersConfig};
+use mcp_server_as_http_core::error::McpCoreResult;
+use mcp_server_as_http_core::http_server::McpHttpServer;
+use mcp_server_as_http_core::priority::RequestPriority;
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+const FIXTURE: &str = env!("CARGO_BIN_EXE_echo-mcp-fixture");
+
+const API_KEY: &str = "e2e-secret";
+
+/// Authentication switched off
+fn no_auth() -> AuthConfig {
+    AuthConfig {
+        api_key: None,
+        enabled: false,
+        default_priority: RequestPriority::Normal,
+        named_keys: Vec::new(),
+    }
+}
+
+/// Authentication with [`API_KEY`]
+fn key_auth() -> AuthConfig {
+    AuthConfig {
+        api_key: Some(API_KEY.to_string()),
+        enabled: true,
+        ..no_auth()
+    }
+}
+
+/// Gateway serving the fixture as `name`, configured with `server` on top of
+/// its command
+///
+/// Each test uses its own `name`, so their work directories do not collide.
+async fn gateway(name: &str, server: Value, auth: AuthConfig) -> McpCoreResult<McpHttpServer> {
+    gateway_with(name, json!({ "servers": { name: server } }), auth).await
+}
+
+/// Gateway serving the fixture as `name` from the whole `config`
+async fn gateway_with(name: &str, config: Value, auth: AuthConfig) -> McpCoreResult<McpHttpServer> {
+    let mut config = config;
+    config["servers"][name]["command"] = json!(FIXTURE);
+    let mut config = McpServersConfig::from_value(config, "test configuration")?;
+    let work_dir_base: PathBuf =
+        std::env::temp_dir().join(format!("mcp-end-to-end-{}", std::process::id()));
+    for server in config.servers.values_mut() {
+        server.work_dir_base = Some(work_dir_base.clone());
+    }
+    McpHttpServer::builder("unused.json", name)
+        .config(config)
+        .bind_host(Ipv4Addr::LOCALHOST.into())
+        .auth_config(auth)
+        .build()
+        .await
+}
+
+async fn router(name: &str, server: Value) -> Router {
+    gateway(name, server, no_auth())
+        .await
+        .unwrap()
+        .create_router()
+}
+
+fn tools_call(id: u64, name: &str, arguments: Value) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "tools/call",
+        "params": { "name": name, "arguments": arguments }
+    })
+}
+
+/// Send `command` to `POST /api/v1` with the given extra headers
+async fn post_command(
+    router: &Router,
+    command: &Value,
+    headers: &[(&str, &str)],
+) -> (StatusCode, Value) {
+    let mut request = Request::post("/api/v1").header("content-type", "application/json");
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let body = json!({ "command": command.to_string() });
+    send(router, request.body(Body::from(body.to_string())).unwrap()).await
+}
+
+/// Send `request`, returning the status and the JSON-RPC message of a
+/// successful response or the error body of a failed one
+async fn send(router: &Router, request: Request<Body>) -> (StatusCode, Value) {
+    let response = router.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: Value = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+    match body.get("result").and_then(Value::as_str) {
+        Some(message) if status.is_success() => (status, serde_json::from_str(message).unwrap()),
+        _ => (status, body),
+    }
+}
+
+#[tokio::test]
+async fn test_tools_are_listed_and_called_through_the_gateway() {
+    let router = router("e2e-request-path", json!({})).await;
+
+    let list = json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/list" });
+    let (status, message) = post_command(&router, &list, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(message["id"], 1);
+    let tools: Vec<_> = message["result"]["tools"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|tool| tool["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(tools, ["echo", "expiring", "fill", "sleep"]);
+
+    let arguments = json!({ "text": "hello", "count": 3 });
+    let (status, message) =
+        post_command(&router, &tools_call(2, "echo", arguments.clone()), &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(message["id"], 2);
+    assert_eq!(message["result"]["structuredContent"], arguments);
+
+    let unknown = json!({ "jsonrpc": "2.0", "id": 3, "method": "prompts/list" });
+    let (status, message) = post_command(&router, &unknown, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(message["error"]["code"], -32601);
+}
+
+#[tokio::test]
+async fn test_api_key_is_required_only_when_auth_is_enabled() {
+    let ping = json!({ "jsonrpc": "2.0", "id": 1, "method": "ping" });
+
+    let secured = gateway("e2e-auth-on", json!({}), key_auth())
+        .await
+        .unwrap()
+        .create_router();
+    let (status, _) = post_command(&secured, &ping, &[]).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = post_command(&secured, &ping, &[("authorization", "Bearer wrong")]).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let bearer = format!("Bearer {}", API_KEY);
+    let (status, message) = post_command(&secured, &ping, &[("authorization", &bearer)]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(message["result"], json!({}));
+
+    let open = router("e2e-auth-off", json!({})).await;
+    let (status, message) = post_command(&open, &ping, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(message["result"], json!({}));
+}
+
+#[tokio::test]
+async fn test_slow_tool_times_out_and_server_keeps_answering() {
+    let router = router("e2e-timeout", json!({})).await;
+
+    let sleep = tools_call(1, "sleep", json!({ "ms": 3000 }));
+    let (status, body) = post_command(&router, &sleep, &[("x-mcp-timeout", "1")]).await;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(body["code"], "timeout");
+
+    // The late answer to the abandoned request is not mistaken for this one
+    let echo = tools_call(2, "echo", json!({ "after": "timeout" }));
+    let (status, message) = post_command(&router, &echo, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(message["id"], 2);
+    assert_eq!(message["result"]["structuredContent"]["after"], "timeout");
+}
+
+#[tokio::test]
+async fn test_crashed_server_answers_again_after_restart() {
+    let router = router(
+        "e2e-crash",
+        json!({ "env": { "ECHO_MCP_CRASH_AFTER": "1" } }),
+    )
+    .await;
+    let echo = tools_call(1, "echo", json!({ "n": 1 }));
+
+    let (status, _) = post_command(&router, &echo, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = post_command(&router, &echo, &[]).await;
+    assert!(status.is_server_error(), "{}", status);
+
+    let restart = Request::post("/admin/servers/e2e-crash/restart")
+        .body(Body::empty())
+        .unwrap();
+    let (status, body) = send(&router, restart).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let (status, message) = post_command(&router, &echo, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(message["result"]["structuredContent"]["n"], 1);
+}
+
+#[tokio::test]
+async fn test_history_records_crash_restart_and_drain() {
+    let router = router(
+        "e2e-history",
+        json!({ "env": { "ECHO_MCP_CRASH_AFTER": "1" } }),
+    )
+    .await;
+    let echo = tools_call(1, "echo", json!({ "n": 1 }));
+
+    let (status, _) = post_command(&router, &echo, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = post_command(&router, &echo, &[]).await;
+    assert!(status.is_server_error(), "{}", status);
+    let restart = Request::post("/admin/servers/e2e-history/restart?strategy=blue-green")
+        .body(Body::empty())
+        .unwrap();
+    let (status, body) = send(&router, restart).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    // Draining stops admitting requests but leaves the child running
+    let drain = Request::post("/admin/servers/e2e-history/drain")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(send(&router, drain).await.0, StatusCode::OK);
+
+    let history = Request::get("/admin/servers/e2e-history/history")
+        .body(Body::empty())
+        .unwrap();
+    let (status, body) = send(&router, history).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let records = body["history"].as_array().unwrap();
+    let transitions: Vec<(&str, &str)> = records
+        .iter()
+        .map(|record| {
+            (
+                record["transition"].as_str().unwrap(),
+                record["trigger"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        transitions,
+        [
+            ("spawned", "start"),
+            ("initialized", "start"),
+            ("exited", "crash"),
+            ("restarting", "admin"),
+            ("spawned", "admin"),
+            ("initialized", "admin"),
+        ]
+    );
+    assert_eq!(records[2]["exit_code"], 1);
+    assert_eq!(records[2]["pid"], records[0]["pid"]);
+    assert_ne!(records[5]["pid"], records[0]["pid"]);
+}
+
+#[tokio::test]
+async fn test_concurrent_requests_get_their_own_responses() {
+    let router = router("e2e-concurrent", json!({})).await;
+
+    let requests = (0..16).map(|n| {
+        let router = router.clone();
+        tokio::spawn(async move {
+            let echo = tools_call(n, "echo", json!({ "n": n }));
+            (n, post_command(&router, &echo, &[]).await)
+        })
+    });
+    for request in requests.collect::<Vec<_>>() {
+        let (n, (status, message)) = request.await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(message["id"], n);
+        assert_eq!(message["result"]["structuredContent"]["n"], n);
+    }
+}
+
+#[tokio::test]
+async fn test_banner_and_stderr_noise_are_tolerated() {
+    let router = router(
+        "e2e-noise",
+        json!({
+            "env": {
+                "ECHO_MCP_BANNER": "echo-mcp fixture starting",
+                "ECHO_MCP_STDERR_NOISE": "1"
+            }
+        }),
+    )
+    .await;
+
+    let echo = tools_call(1, "echo", json!({ "quiet": false }));
+    let (status, message) = post_command(&router, &echo, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(message["result"]["structuredContent"]["quiet"], false);
+}
+
+#[tokio::test]
+async fn test_pretty_printed_responses_fail_when_noise_is_an_error() {
+    let error = gateway(
+        "e2e-pretty",
+        json!({ "env": { "ECHO_MCP_PRETTY": "1" }, "stdout_noise": "error" }),
+        no_auth(),
+    )
+    .await
+    .err()
+    .expect("multi-line responses are not JSON lines");
+    assert!(error.to_string().contains("non-JSON output"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_server_printing_usage_fails_startup_at_once() {
+    let started = std::time::Instant::now();
+    let error = gateway("e2e-usage", json!({ "args": ["--sse"] }), no_auth())
+        .await
+        .err()
+        .expect("the fixture exits on arguments");
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    let message = error.to_string();
+    assert!(message.contains("exit code 2"), "{}", message);
+    assert!(message.contains("usage: echo-mcp-fixture"), "{}", message);
+    assert!(
+        message.contains("unexpected argument '--sse'"),
+        "{}",
+        message
+    );
+}
+
+#[tokio::test]
+async fn test_malformed_results_are_refused_in_strict_mode() {
+    let list = json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/list" });
+    let server = json!({
+        "env": { "ECHO_MCP_NO_INPUT_SCHEMA": "1" },
+        "validate_responses": true
+    });
+
+    let (status, body) =
+        post_command(&router("e2e-lenient", server.clone()).await, &list, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["result"]["tools"][0].get("inputSchema").is_none());
+
+    let config = json!({ "servers": { "e2e-strict": server }, "strict": true });
+    let strict = gateway_with("e2e-strict", config, no_auth())
+        .await
+        .unwrap()
+        .create_router();
+    let (status, body) = post_command(&strict, &list, &[]).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(body["code"], "invalid_upstream_response");
+    assert_eq!(body["errors"][0]["instance_path"], "/tools/0");
+    assert_eq!(body["errors"][0]["keyword"], "required");
+
+    // Other methods still answer
+    let echo = tools_call(2, "echo", json!({ "strict": true }));
+    let (status, _) = post_command(&strict, &echo, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_failed_handshakes_leave_server_failed_until_restarted() {
+    let list = json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/list" });
+    for (mode, failure) in [
+        ("hang", "timeout"),
+        ("malformed", "malformed response"),
+        ("error", "error response"),
+    ] {
+        let name = format!("e2e-handshake-{}", mode);
+        let work_dir = std::env::temp_dir()
+            .join(format!("mcp-end-to-end-{}", std::process::id()))
+            .join(&name);
+        std::fs::create_dir_all(&work_dir).unwrap();
+        std::fs::write(work_dir.join("bad-handshake"), "").unwrap();
+        let router = router(
+            &name,
+            json!({
+                "env": { "ECHO_MCP_BAD_HANDSHAKE": mode },
+                "setup_mode": "on-first-request",
+                "initialize_timeout_secs": 1,
+                "initialize_retries": 1
+            }),
+        )
+        .await;
+
+        // The first request waits for the setup and its retry
+        let (status, body) = post_command(&router, &list, &[]).await;
+        assert_eq!(
+            status,
+            StatusCode::SERVICE_UNAVAILABLE,
+            "{}: {}",
+            mode,
+            body
+        );
+        assert_eq!(body["code"], "handshake_failed");
+        let message = body["message"].as_str().unwrap();
+        assert!(message.contains("2 attempts failed"), "{}", message);
+
+        // Later requests fail at once instead of setting it up again
+        let started = std::time::Instant::now();
+        let (status, body) = post_command(&router, &list, &[]).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["code"], "handshake_failed");
+        assert!(started.elapsed() < std::time::Duration::from_millis(500));
+
+        let ready = Request::get("/ready").body(Body::empty()).unwrap();
+        let (status, body) = send(&router, ready).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["provisioning"], "handshake_failed");
+        assert_eq!(body["code"], "handshake_failed");
+        let message = body["message"].as_str().unwrap();
+        assert!(message.contains(failure), "{}", message);
+
+        // A restart clears the state and tries again
+        std::fs::remove_file(work_dir.join("bad-handshake")).unwrap();
+        let restart = Request::post(format!("/admin/servers/{}/restart", name))
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = send(&router, restart).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let (status, _) = post_command(&router, &list, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        let ready = Request::get("/ready").body(Body::empty()).unwrap();
+        assert_eq!(send(&router, ready).await.0, StatusCode::OK);
+    }
+}
+
+#[tokio::test]
+async fn test_http2_is_served_and_idle_connections_are_closed() {
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    let name = "e2e-http2";
+    let config = json!({
+        "servers": { name: {} },
+        "http": { "http2": true, "keep_alive_idle_timeout_secs": 1 }
+    });
+    let handle = gateway_with(name, config, no_auth())
+        .await
+        .unwrap()
+        .serve_background(0)
+        .await
+        .unwrap()
+        .abort_on_drop(true);
+    let addr = handle.local_addr();
+
+    // HTTP/2 with prior knowledge
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (mut sender, connection) =
+        hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+            .await
+            .unwrap();
+    let connection = tokio::spawn(connection);
+    let request = Request::get(format!("http://{}/version", addr))
+        .body(Body::empty())
+        .unwrap();
+    let response = sender.send_request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.version(), axum::http::Version::HTTP_2);
+    let body = axum::body::to_bytes(Body::new(response.into_body()), usize::MAX)
+        .await
+        .unwrap();
+    assert!(!body.is_empty());
+
+    // The idle HTTP/2 connection is sent a GOAWAY and ends
+    tokio::time::timeout(std::time::Duration::from_secs(5), connection)
+        .await
+        .expect("idle HTTP/2 connection was not closed")
+        .unwrap()
+        .unwrap();
+
+    // So is an idle HTTP/1.1 connection, after its response
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET /version HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut received = Vec::new();
+    tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        stream.read_to_end(&mut received),
+    )
+    .await
+    .expect("idle HTTP/1.1 connection was not closed")
+    .unwrap();
+    assert!(received.starts_with(b"HTTP/1.1 200 OK"));
+}
+
+#[tokio::test]
+async fn test_responses_over_the_limit_are_refused_or_cut_short() {
+    let router = router(
+        "e2e-response-limit",
+        json!({ "max_response_bytes": 65_536 }),
+    )
+    .await;
+    let fill = |id, bytes| tools_call(id, "fill", json!({ "bytes": bytes }));
+
+    let (status, message) = post_command(&router, &fill(1, 1000), &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        message["result"]["content"][0]["text"]
+            .as_str()
+            .unwrap()
+            .len(),
+        1000
+    );
+
+    let (status, body) = post_command(&router, &fill(2, 100_000), &[]).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(body["code"], "response_too_large");
+
+    // A streamed response has started before it grows too large, so the
+    // body ends in an error
+    let (status, message) = post_command(
+        &router,
+        &fill(3, 1000),
+        &[("x-mcp-stream-response", "true")],
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(message["id"], 3);
+    let request = Request::post("/api/v1")
+        .header("content-type", "application/json")
+        .header("x-mcp-stream-response", "true")
+        .body(Body::from(
+            json!({ "command": fill(4, 100_000).to_string() }).to_string(),
+        ))
+        .unwrap();
+    let response = router.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .is_err());
+
+    // The rest of the long line was read, so the next answer is in step
+    let ping = json!({ "jsonrpc": "2.0", "id": 5, "method": "ping" });
+    let (status, message) = post_command(&router, &ping, &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(message["id"], 5);
+}
+
+#[tokio::test]
+async fn test_failing_health_checks_restart_the_server() {
+    let router = router(
+        "e2e-health-check",
+        json!({
+            "health_check": {
+                "method": "tools/call",
+                "params": { "name": "expiring", "arguments": { "after": 2 } },
+                "expect": { "path": "$.result.content[0].text", "equals": "valid" },
+                "interval_secs": 1,
+                "failure_threshold": 2
+            }
+        }),
+    )
+    .await;
+    let ready = || Request::get("/ready").body(Body::empty()).unwrap();
+
+    // Two checks pass, two fail, and the replacement child passes again
+    let started = std::time::Instant::now();
+    let body = loop {
+        let (status, body) = send(&router, ready()).await;
+        let health = &body["health_check"];
+        if health["restarts"] == 1 && health["last"]["passed"] == true {
+            assert_eq!(status, StatusCode::OK, "{}", body);
+            break body;
+        }
+        assert!(
+            started.elapsed() < std::time::Duration::from_secs(20),
+            "{}",
+            body
+        );
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    };
+    let health = &body["health_check"];
+    assert_eq!(health["healthy"], true);
+    assert_eq!(health["failures"], 2);
+    assert_eq!(health["consecutive_failures"], 0);
+    assert!(health["last"]["latency_ms"].is_u64());
+
+    let stats = Request::get("/api/v1/stats").body(Body::empty()).unwrap();
+    let (status, stats) = send(&router, stats).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        stats["restarts"]["last"]["reason"],
+        "health check failed 2 times in a row: result has isError set: credential expired"
+    );
+    assert_eq!(stats["health_check"]["restarts"], 1);
+    // Health checks are not client requests
+    assert_eq!(stats["tools"]["tools"]["expiring"], Value::Null);
+
+    let history = Request::get("/admin/servers/e2e-health-check/history")
+        .body(Body::empty())
+        .unwrap();
+    let (_, body) = send(&router, history).await;
+    assert!(body["history"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|record| record["transition"] == "restarting" && record["trigger"] == "health_check"));
+}
+
+#[tokio::test]
+async fn test_health_checks_skip_header_injection() {
+    let router = router(
+        "e2e-health-check-injection",
+        json!({
+            "param_injection": [
+                { "header": "x-tenant", "json_pointer": "/params/tenant" }
+            ],
+            "health_check": {
+                "method": "ping",
+                "interval_secs": 1,
+                "failure_threshold": 1
+            }
+        }),
+    )
+    .await;
+    let ready = || Request::get("/ready").body(Body::empty()).unwrap();
+
+    // Clients still have to send the header
+    let ping = json!({ "jsonrpc": "2.0", "id": 1, "method": "ping" });
+    let (status, _) = post_command(&router, &ping, &[]).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // The gateway's own checks do not, and pass without restarting the child
+    let started = std::time::Instant::now();
+    loop {
+        let (_, body) = send(&router, ready()).await;
+        if !body["health_check"]["last"].is_null() {
+            break;
+        }
+        assert!(
+            started.elapsed() < std::time::Duration::from_secs(10),
+            "{}",
+            body
+        );
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(2500)).await;
+    let (status, body) = send(&router, ready()).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let health = &body["health_check"];
+    assert_eq!(health["last"]["passed"], true, "{}", body);
+    assert_eq!(health["failures"], 0);
+    assert_eq!(health["restarts"], 0);
+}
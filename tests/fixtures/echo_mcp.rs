@@ -0,0 +1,292 @@
+//! Minimal stdio MCP server the end-to-end tests run behind the gateway
+//!
+//! Speaks newline-delimited JSON-RPC on stdin and stdout: it answers
+//! `initialize`, `ping`, `tools/list`, and `tools/call` with four tools:
+//! `echo`, returning its arguments, `expiring`, answering normally for its
+//! first `after` calls and with `isError` from then on, like a server whose
+//! credential expired, `fill`, returning a text of `bytes` bytes, and
+//! `sleep`, answering after `ms` milliseconds without holding up other
+//! requests. Notifications are
+//! ignored and other methods answered with "method not found". Given any
+//! argument, it prints its usage and exits with code 2, like a CLI started
+//! with the wrong arguments.
+//!
+//! Misbehavior is switched on through the environment:
+//!
+//! - `ECHO_MCP_CRASH_AFTER=N`: exit after answering N requests besides
+//!   `initialize`
+//! - `ECHO_MCP_STDERR_NOISE=1`: write a line to stderr for every message
+//! - `ECHO_MCP_BANNER=text`: print `text` to stdout before anything else
+//! - `ECHO_MCP_PRETTY=1`: pretty-print responses over several lines
+//! - `ECHO_MCP_NO_INPUT_SCHEMA=1`: list the tools without their `inputSchema`
+//! - `ECHO_MCP_BAD_HANDSHAKE=hang|malformed|error`: while a file named
+//!   `bad-handshake` exists in the working directory, never answer
+//!   `initialize`, answer it with neither `result` nor `error`, or answer it
+//!   with an error
+
+use std::io::{BufRead, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const PARSE_ERROR: i64 = -32700;
+const INTERNAL_ERROR: i64 = -32603;
+
+/// Protocol version answered when the client offers none
+const DEFAULT_PROTOCOL_VERSION: &str = "2025-06-18";
+
+struct Options {
+    crash_after: Option<usize>,
+    bad_handshake: Option<String>,
+    stderr_noise: bool,
+    pretty: bool,
+    no_input_schema: bool,
+}
+
+impl Options {
+    fn from_env() -> Self {
+        Self {
+            crash_after: std::env::var("ECHO_MCP_CRASH_AFTER")
+                .ok()
+                .and_then(|value| value.parse().ok()),
+            bad_handshake: std::env::var("ECHO_MCP_BAD_HANDSHAKE").ok(),
+            stderr_noise: std::env::var_os("ECHO_MCP_STDERR_NOISE").is_some(),
+            pretty: std::env::var_os("ECHO_MCP_PRETTY").is_some(),
+            no_input_schema: std::env::var_os("ECHO_MCP_NO_INPUT_SCHEMA").is_some(),
+        }
+    }
+}
+
+/// Stdout shared with the threads answering `sleep`
+#[derive(Clone)]
+struct Output {
+    stdout: Arc<Mutex<std::io::Stdout>>,
+    pretty: bool,
+}
+
+impl Output {
+    fn write(&self, message: &Value) {
+        let line = if self.pretty {
+            serde_json::to_string_pretty(message)
+        } else {
+            serde_json::to_string(message)
+        }
+        .expect("JSON values serialize");
+        let mut stdout = self.stdout.lock().unwrap_or_else(|e| e.into_inner());
+        // The client going away ends the process through stdin
+        let _ = writeln!(stdout, "{}", line);
+        let _ = stdout.flush();
+    }
+}
+
+fn main() {
+    if let Some(argument) = std::env::args().nth(1) {
+        println!("usage: echo-mcp-fixture");
+        eprintln!("error: unexpected argument '{}'", argument);
+        std::process::exit(2);
+    }
+    let options = Options::from_env();
+    let output = Output {
+        stdout: Arc::new(Mutex::new(std::io::stdout())),
+        pretty: options.pretty,
+    };
+    if let Ok(banner) = std::env::var("ECHO_MCP_BANNER") {
+        let mut stdout = output.stdout.lock().unwrap();
+        let _ = writeln!(stdout, "{}", banner);
+        let _ = stdout.flush();
+    }
+
+    let mut answered = 0;
+    let mut expiring_calls = 0;
+    for line in std::io::stdin().lock().lines() {
+        let Ok(line) = line else { break };
+        if line.trim().is_empty() {
+            continue;
+        }
+        if options.stderr_noise {
+            eprintln!("echo-mcp: received {} bytes", line.len());
+        }
+
+        let message: Value = match serde_json::from_str(&line) {
+            Ok(message) => message,
+            Err(e) => {
+                output.write(&error(Value::Null, PARSE_ERROR, &e.to_string()));
+                continue;
+            }
+        };
+        // Notifications and responses to requests of ours need no answer
+        let (Some(id), Some(method)) = (
+            message.get("id").cloned(),
+            message.get("method").and_then(Value::as_str),
+        ) else {
+            continue;
+        };
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+
+        match method {
+            "initialize" if bad_handshake(&options) == Some("hang") => continue,
+            "initialize" if bad_handshake(&options) == Some("malformed") => {
+                output.write(&json!({ "jsonrpc": "2.0", "id": id }));
+                continue;
+            }
+            "initialize" if bad_handshake(&options) == Some("error") => {
+                output.write(&error(id, INTERNAL_ERROR, "not ready to initialize"));
+                continue;
+            }
+            "initialize" => {
+                let version = params
+                    .get("protocolVersion")
+                    .and_then(Value::as_str)
+                    .unwrap_or(DEFAULT_PROTOCOL_VERSION);
+                output.write(&result(
+                    id,
+                    json!({
+                        "protocolVersion": version,
+                        "capabilities": { "tools": {} },
+                        "serverInfo": {
+                            "name": "echo-mcp-fixture",
+                            "version": env!("CARGO_PKG_VERSION")
+                        }
+                    }),
+                ));
+                continue;
+            }
+            "tools/call" if params.get("name").and_then(Value::as_str) == Some("sleep") => {
+                let ms = params.pointer("/arguments/ms").and_then(Value::as_u64);
+                let Some(ms) = ms else {
+                    output.write(&error(id, INVALID_PARAMS, "sleep needs a numeric 'ms'"));
+                    continue;
+                };
+                let output = output.clone();
+                std::thread::spawn(move || {
+                    std::thread::sleep(Duration::from_millis(ms));
+                    output.write(&result(id, text_content(&format!("slept {} ms", ms))));
+                });
+            }
+            "tools/call" if params.get("name").and_then(Value::as_str) == Some("expiring") => {
+                let Some(after) = params.pointer("/arguments/after").and_then(Value::as_u64) else {
+                    output.write(&error(
+                        id,
+                        INVALID_PARAMS,
+                        "expiring needs a numeric 'after'",
+                    ));
+                    continue;
+                };
+                expiring_calls += 1;
+                if expiring_calls <= after {
+                    output.write(&result(id, text_content("valid")));
+                } else {
+                    let mut content = text_content("credential expired");
+                    content["isError"] = json!(true);
+                    output.write(&result(id, content));
+                }
+            }
+            "tools/list" if options.no_input_schema => {
+                let mut listed = answer(id, method, &params);
+                for tool in listed["result"]["tools"]
+                    .as_array_mut()
+                    .into_iter()
+                    .flatten()
+                {
+                    tool.as_object_mut().map(|tool| tool.remove("inputSchema"));
+                }
+                output.write(&listed);
+            }
+            _ => output.write(&answer(id, method, &params)),
+        }
+
+        answered += 1;
+        if options.crash_after.is_some_and(|limit| answered >= limit) {
+            eprintln!("echo-mcp: crashing after {} requests", answered);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// How to misbehave on `initialize`, while `bad-handshake` exists
+fn bad_handshake(options: &Options) -> Option<&str> {
+    let mode = options.bad_handshake.as_deref()?;
+    std::path::Path::new("bad-handshake")
+        .exists()
+        .then_some(mode)
+}
+
+/// Response to a request answered at once
+fn answer(id: Value, method: &str, params: &Value) -> Value {
+    match method {
+        "ping" => result(id, json!({})),
+        "tools/list" => result(
+            id,
+            json!({
+                "tools": [
+                    {
+                        "name": "echo",
+                        "description": "Return the arguments it is called with",
+                        "inputSchema": { "type": "object" }
+                    },
+                    {
+                        "name": "expiring",
+                        "description": "Answer normally for the given number of calls, then fail",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": { "after": { "type": "integer", "minimum": 0 } },
+                            "required": ["after"]
+                        }
+                    },
+                    {
+                        "name": "fill",
+                        "description": "Return a text of the given number of bytes",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": { "bytes": { "type": "integer", "minimum": 0 } },
+                            "required": ["bytes"]
+                        }
+                    },
+                    {
+                        "name": "sleep",
+                        "description": "Answer after the given number of milliseconds",
+                        "inputSchema": {
+                            "type": "object",
+                            "properties": { "ms": { "type": "integer", "minimum": 0 } },
+                            "required": ["ms"]
+                        }
+                    }
+                ]
+            }),
+        ),
+        "tools/call" => match params.get("name").and_then(Value::as_str) {
+            Some("echo") => {
+                let arguments = params.get("arguments").cloned().unwrap_or(json!({}));
+                let mut content = text_content(&arguments.to_string());
+                content["structuredContent"] = arguments;
+                result(id, content)
+            }
+            Some("fill") => match params.pointer("/arguments/bytes").and_then(Value::as_u64) {
+                Some(bytes) => result(id, text_content(&"x".repeat(bytes as usize))),
+                None => error(id, INVALID_PARAMS, "fill needs a numeric 'bytes'"),
+            },
+            Some(name) => error(id, INVALID_PARAMS, &format!("Unknown tool: {}", name)),
+            None => error(id, INVALID_PARAMS, "tools/call needs a 'name'"),
+        },
+        _ => error(
+            id,
+            METHOD_NOT_FOUND,
+            &format!("Method not found: {}", method),
+        ),
+    }
+}
+
+fn text_content(text: &str) -> Value {
+    json!({ "content": [{ "type": "text", "text": text }], "isError": false })
+}
+
+fn result(id: Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+fn error(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
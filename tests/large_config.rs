@@ -0,0 +1,55 @@
+//! Loading a machine-generated configuration of 5,000 servers, with the
+//! allocations of the whole test process counted to keep startup memory
+//! bounded
+
+mod common;
+#[path = "common/generated_config.rs"]
+mod generated_config;
+
+use std::time::{Duration, Instant};
+
+use generated_config::{generated_config, SERVERS};
+use mcp_server_as_http_core::config::McpServersConfig;
+
+/// Longest the load and validation may take, generous for debug builds on
+/// a busy machine
+const MAX_LOAD_TIME: Duration = Duration::from_secs(10);
+
+/// Most the process may allocate on top of what it held before the load,
+/// about twice what the loaded configuration itself takes
+const MAX_PEAK_GROWTH: usize = 64 * 1024 * 1024;
+
+#[tokio::test]
+async fn test_large_config_loads_in_bounded_time_and_memory() {
+    let path = std::env::temp_dir().join(format!("mcp-large-config-{}.json", std::process::id()));
+    std::fs::write(&path, serde_json::to_vec(&generated_config()).unwrap()).unwrap();
+
+    let baseline = common::baseline();
+    let started = Instant::now();
+    let config = McpServersConfig::load_from_file(path.to_str().unwrap())
+        .await
+        .unwrap();
+    let elapsed = started.elapsed();
+    let peak_growth = common::peak_growth(baseline);
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(config.servers.len(), SERVERS);
+    let server = config.get_server("server-4999").unwrap();
+    assert_eq!(server.env["VAR_19"], "value-4999-19");
+    assert!(
+        elapsed < MAX_LOAD_TIME,
+        "loading {} servers took {:?}",
+        SERVERS,
+        elapsed
+    );
+    assert!(
+        peak_growth < MAX_PEAK_GROWTH,
+        "allocations grew by {} bytes while loading {} servers",
+        peak_growth,
+        SERVERS
+    );
+
+    // Expanding every server's templates copies the configuration once
+    let expanded = config.expand_templates(Some(8080)).unwrap();
+    assert_eq!(expanded.servers["server-0"].args[2], "8080");
+}
@@ -0,0 +1,116 @@
+//! A large response streamed through the gateway, with the allocations of
+//! the whole test process counted to show it is never held in memory
+
+mod common;
+
+use std::net::Ipv4Addr;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use futures_util::StreamExt;
+use mcp_server_as_http_core::config::{AuthConfig, McpServersConfig};
+use mcp_server_as_http_core::http_server::McpHttpServer;
+use mcp_server_as_http_core::priority::RequestPriority;
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+const FIXTURE: &str = env!("CARGO_BIN_EXE_echo-mcp-fixture");
+
+/// Size of the streamed text
+const RESPONSE_BYTES: usize = 50 * 1024 * 1024;
+
+/// Most the process may allocate on top of what it held before the request
+const MAX_PEAK_GROWTH: usize = 8 * 1024 * 1024;
+
+#[tokio::test]
+async fn test_large_response_is_streamed_in_bounded_memory() {
+    let name = "streamed-response";
+    let mut config = McpServersConfig::from_value(
+        json!({ "servers": { name: { "command": FIXTURE } } }),
+        "test configuration",
+    )
+    .unwrap();
+    let work_dir_base =
+        std::env::temp_dir().join(format!("mcp-streamed-response-{}", std::process::id()));
+    for server in config.servers.values_mut() {
+        server.work_dir_base = Some(work_dir_base.clone());
+    }
+    let router = McpHttpServer::builder("unused.json", name)
+        .config(config)
+        .bind_host(Ipv4Addr::LOCALHOST.into())
+        .auth_config(AuthConfig {
+            api_key: None,
+            enabled: false,
+            default_priority: RequestPriority::Normal,
+            named_keys: Vec::new(),
+        })
+        .build()
+        .await
+        .unwrap()
+        .create_router();
+
+    let command = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "tools/call",
+        "params": { "name": "fill", "arguments": { "bytes": RESPONSE_BYTES } }
+    });
+    let request = Request::post("/api/v1")
+        .header("content-type", "application/json")
+        .header("x-mcp-stream-response", "true")
+        .body(Body::from(
+            json!({ "command": command.to_string() }).to_string(),
+        ))
+        .unwrap();
+
+    let baseline = common::baseline();
+    let response = router.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Keep the opening and the end of the message to check it is whole
+    let mut received = 0;
+    let mut opening = Vec::new();
+    let mut ending = Vec::new();
+    let mut chunks = response.into_body().into_data_stream();
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.unwrap();
+        received += chunk.len();
+        if opening.len() < 128 {
+            opening.extend_from_slice(&chunk[..chunk.len().min(128)]);
+        }
+        ending = chunk[chunk.len().saturating_sub(64)..].to_vec();
+    }
+    let peak_growth = common::peak_growth(baseline);
+
+    assert!(received > RESPONSE_BYTES, "received {} bytes", received);
+    assert!(
+        opening.starts_with(
+            br#"{"jsonrpc":"2.0","id":1,"result":{"content":[{"type":"text","text":"xxx"#
+        ),
+        "opening: {}",
+        String::from_utf8_lossy(&opening)
+    );
+    assert!(ending.ends_with(br#"xxx"}],"isError":false}}"#));
+    assert!(
+        peak_growth < MAX_PEAK_GROWTH,
+        "allocations grew by {} bytes while streaming",
+        peak_growth
+    );
+
+    // The server's output is still in step for the next request
+    let ping = json!({ "jsonrpc": "2.0", "id": 2, "method": "ping" });
+    let request = Request::post("/api/v1")
+        .header("content-type", "application/json")
+        .header("x-mcp-stream-response", "true")
+        .body(Body::from(
+            json!({ "command": ping.to_string() }).to_string(),
+        ))
+        .unwrap();
+    let response = router.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let message: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(message, json!({ "jsonrpc": "2.0", "id": 2, "result": {} }));
+}
@@ -0,0 +1,251 @@
+// Test page of the gateway: lists the server's tools, calls them through
+// POST /api/v1, and follows its notifications. Every call carries the API
+// key entered on the page; it is kept in session storage only.
+"use strict";
+
+const KEY_STORAGE = "mcp-gateway-api-key";
+const NOTIFICATIONS_KEPT = 50;
+
+let apiKey = sessionStorage.getItem(KEY_STORAGE) || "";
+let nextId = 1;
+let selectedTool = null;
+
+// Bumped on every connect so an older notification poll stops
+let generation = 0;
+
+const $ = (id) => document.getElementById(id);
+
+function setStatus(text, isError) {
+  $("status").textContent = text;
+  $("status").classList.toggle("error", Boolean(isError));
+}
+
+async function call(path, options = {}) {
+  const headers = { ...(options.headers || {}) };
+  if (apiKey) {
+    headers.Authorization = "Bearer " + apiKey;
+  }
+  const response = await fetch(path, { ...options, headers });
+  const text = await response.text();
+  let body;
+  try {
+    body = JSON.parse(text);
+  } catch (_) {
+    body = text;
+  }
+  if (!response.ok) {
+    const message = body && body.message ? body.message : response.statusText;
+    throw new Error(response.status + " " + message);
+  }
+  return body;
+}
+
+// Send a JSON-RPC request; returns the message and the server's answer
+async function rpc(method, params) {
+  const message = { jsonrpc: "2.0", id: nextId++, method };
+  if (params !== undefined) {
+    message.params = params;
+  }
+  const body = await call("/api/v1", {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ command: JSON.stringify(message) }),
+  });
+  return { message, answer: JSON.parse(body.result) };
+}
+
+async function connect() {
+  generation++;
+  setStatus("Connecting…");
+  try {
+    const bootstrap = await call("/ui/api/bootstrap");
+    $("server-name").textContent = "of " + bootstrap.server;
+    await listTools();
+    const others = bootstrap.servers.filter((name) => name !== bootstrap.server);
+    setStatus(
+      "Connected to " + bootstrap.server +
+        (bootstrap.protocol_version ? " (" + bootstrap.protocol_version + ")" : "") +
+        (others.length ? "; also configured: " + others.join(", ") : "")
+    );
+    if (bootstrap.capabilities.notifications) {
+      followNotifications(generation);
+    }
+  } catch (error) {
+    setStatus(error.message, true);
+  }
+}
+
+async function listTools() {
+  const tools = [];
+  let cursor;
+  do {
+    const { answer } = await rpc("tools/list", cursor ? { cursor } : {});
+    if (answer.error) {
+      throw new Error("tools/list failed: " + answer.error.message);
+    }
+    tools.push(...(answer.result.tools || []));
+    cursor = answer.result.nextCursor;
+  } while (cursor);
+
+  const list = $("tools");
+  list.replaceChildren();
+  for (const tool of tools) {
+    const button = document.createElement("button");
+    button.type = "button";
+    button.textContent = tool.name;
+    button.title = tool.description || "";
+    button.addEventListener("click", () => {
+      for (const other of list.querySelectorAll("button")) {
+        other.classList.remove("selected");
+      }
+      button.classList.add("selected");
+      selectTool(tool);
+    });
+    const item = document.createElement("li");
+    item.append(button);
+    list.append(item);
+  }
+}
+
+function selectTool(tool) {
+  selectedTool = tool;
+  $("tool-name").textContent = tool.name;
+  $("tool-description").textContent = tool.description || "";
+  const schema = tool.inputSchema || {};
+  const required = new Set(schema.required || []);
+  const fields = $("fields");
+  fields.replaceChildren();
+  for (const [name, property] of Object.entries(schema.properties || {})) {
+    fields.append(field(name, property, required.has(name)));
+  }
+  $("call").hidden = false;
+}
+
+// Input for one property of the inputSchema
+function field(name, property, required) {
+  const label = document.createElement("label");
+  label.textContent = name + (required ? " *" : "");
+  if (property.description) {
+    label.title = property.description;
+  }
+  let input;
+  if (Array.isArray(property.enum)) {
+    input = document.createElement("select");
+    input.append(new Option("", ""));
+    for (const value of property.enum) {
+      input.append(new Option(String(value), JSON.stringify(value)));
+    }
+    input.dataset.kind = "enum";
+  } else if (property.type === "boolean") {
+    input = document.createElement("input");
+    input.type = "checkbox";
+    input.dataset.kind = "boolean";
+  } else if (property.type === "integer" || property.type === "number") {
+    input = document.createElement("input");
+    input.type = "number";
+    input.step = property.type === "integer" ? "1" : "any";
+    input.dataset.kind = "number";
+  } else if (property.type === "string") {
+    input = document.createElement("input");
+    input.type = "text";
+    input.dataset.kind = "string";
+  } else {
+    input = document.createElement("textarea");
+    input.rows = 3;
+    input.placeholder = "JSON";
+    input.dataset.kind = "json";
+  }
+  input.name = name;
+  input.required = required && property.type !== "boolean";
+  label.append(input);
+  return label;
+}
+
+// Arguments of the call from the form; empty optional fields are left out
+function readArguments() {
+  const args = {};
+  for (const input of $("fields").querySelectorAll("input, select, textarea")) {
+    const kind = input.dataset.kind;
+    if (kind === "boolean") {
+      args[input.name] = input.checked;
+      continue;
+    }
+    if (input.value === "") {
+      continue;
+    }
+    if (kind === "number") {
+      args[input.name] = Number(input.value);
+    } else if (kind === "string") {
+      args[input.name] = input.value;
+    } else {
+      try {
+        args[input.name] = JSON.parse(input.value);
+      } catch (error) {
+        throw new Error(input.name + " is not valid JSON: " + error.message);
+      }
+    }
+  }
+  return args;
+}
+
+async function callTool() {
+  if (!selectedTool) {
+    return;
+  }
+  $("response").textContent = "";
+  try {
+    const params = { name: selectedTool.name, arguments: readArguments() };
+    $("request").textContent = JSON.stringify(
+      { jsonrpc: "2.0", id: nextId, method: "tools/call", params },
+      null,
+      2
+    );
+    const { answer } = await rpc("tools/call", params);
+    $("response").textContent = JSON.stringify(answer, null, 2);
+  } catch (error) {
+    $("response").textContent = error.message;
+  }
+}
+
+// Long-poll notifications until the page connects again
+async function followNotifications(current) {
+  let cursor = 0;
+  while (current === generation) {
+    try {
+      const page = await call("/api/v1/notifications?wait=25&since=" + cursor);
+      if (current !== generation) {
+        return;
+      }
+      cursor = page.cursor;
+      for (const entry of page.notifications) {
+        showNotification(entry);
+      }
+    } catch (_) {
+      await new Promise((resolve) => setTimeout(resolve, 5000));
+    }
+  }
+}
+
+function showNotification(entry) {
+  const list = $("notifications");
+  const item = document.createElement("li");
+  item.textContent =
+    entry.received_at + " " + JSON.stringify(entry.notification);
+  list.prepend(item);
+  while (list.children.length > NOTIFICATIONS_KEPT) {
+    list.lastChild.remove();
+  }
+}
+
+$("api-key").value = apiKey;
+$("connect").addEventListener("submit", (event) => {
+  event.preventDefault();
+  apiKey = $("api-key").value.trim();
+  sessionStorage.setItem(KEY_STORAGE, apiKey);
+  connect();
+});
+$("call").addEventListener("submit", (event) => {
+  event.preventDefault();
+  callTool();
+});
+connect();